@@ -0,0 +1,186 @@
+use crate::error::Error;
+use crate::schedule::Train;
+
+use chrono::{DateTime, Utc};
+
+use rand::RngCore;
+
+use serde::{Deserialize, Serialize};
+
+use std::sync::RwLock;
+
+// Messages about known problems with the data, e.g. "NIR data missing from 12 June", put up by
+// admins and shown with whatever they're about: everywhere, or only with one namespace, one
+// operator's trains or one location's trains and boards. Where they're scoped more than one way,
+// all of them have to apply. They're kept in a file if there is one, so they survive restarts.
+
+#[derive(Clone, Default, Deserialize)]
+pub struct AlertsConfig {
+    file: Option<String>, // JSON, rewritten on every change
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum AlertSeverity {
+    #[default]
+    Info,
+    Warning,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct NewAlert {
+    pub message: String,
+    #[serde(default)]
+    pub severity: AlertSeverity,
+    pub starts: Option<DateTime<Utc>>, // straight away if not given
+    pub expires: Option<DateTime<Utc>>, // never if not given, until it's expired or deleted
+    pub namespace: Option<String>,
+    pub operator_id: Option<String>, // as in the namespace's trains, e.g. an ATOC code
+    pub location_id: Option<String>, // not a public ID; e.g. a TIPLOC
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Alert {
+    pub id: String,
+    pub created: DateTime<Utc>,
+    #[serde(flatten)]
+    pub alert: NewAlert,
+}
+
+impl Alert {
+    pub fn is_current(&self, now: DateTime<Utc>) -> bool {
+        self.alert.starts.map_or(true, |x| x <= now) && self.alert.expires.map_or(true, |x| x > now)
+    }
+
+    // whether it's about something in a namespace, with these operators and at these locations
+    pub fn applies_to<'a>(
+        &self,
+        namespace: Option<&str>,
+        operator_ids: &[&str],
+        location_ids: impl IntoIterator<Item = &'a str>,
+    ) -> bool {
+        let alert = &self.alert;
+        (alert.namespace.is_none() || alert.namespace.as_deref() == namespace)
+            && alert
+                .operator_id
+                .as_ref()
+                .map_or(true, |x| operator_ids.contains(&x.as_str()))
+            && match &alert.location_id {
+                Some(x) => location_ids.into_iter().any(|y| y == x),
+                None => true,
+            }
+    }
+}
+
+pub struct Alerts {
+    file: Option<String>,
+    alerts: RwLock<Vec<Alert>>,
+}
+
+impl Alerts {
+    pub fn new(config: AlertsConfig) -> Result<Self, Error> {
+        let alerts = match &config.file {
+            Some(x) if std::path::Path::new(x).exists() => {
+                let alerts = serde_json::from_slice::<Vec<Alert>>(&std::fs::read(x)?)?;
+                println!("Loaded {} alerts from {}", alerts.len(), x);
+                alerts
+            }
+            _ => vec![],
+        };
+        Ok(Self {
+            file: config.file,
+            alerts: RwLock::new(alerts),
+        })
+    }
+
+    fn save(&self, alerts: &[Alert]) -> Result<(), Error> {
+        match &self.file {
+            Some(x) => Ok(std::fs::write(x, serde_json::to_vec_pretty(alerts)?)?),
+            None => Ok(()),
+        }
+    }
+
+    pub fn add(&self, alert: NewAlert) -> Result<Alert, Error> {
+        let mut bytes = [0u8; 8];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let alert = Alert {
+            id: bytes.iter().map(|x| format!("{:02x}", x)).collect(),
+            created: Utc::now(),
+            alert,
+        };
+        let mut alerts = self.alerts.write().unwrap();
+        alerts.push(alert.clone());
+        self.save(&alerts)?;
+        Ok(alert)
+    }
+
+    // Takes it down now, but keeps it for the record. False if there's no such alert.
+    pub fn expire(&self, id: &str) -> Result<bool, Error> {
+        let mut alerts = self.alerts.write().unwrap();
+        match alerts.iter_mut().find(|x| x.id == id) {
+            Some(x) => {
+                let now = Utc::now();
+                if x.alert.expires.map_or(true, |y| y > now) {
+                    x.alert.expires = Some(now);
+                }
+            }
+            None => return Ok(false),
+        }
+        self.save(&alerts)?;
+        Ok(true)
+    }
+
+    pub fn delete(&self, id: &str) -> Result<bool, Error> {
+        let mut alerts = self.alerts.write().unwrap();
+        let before = alerts.len();
+        alerts.retain(|x| x.id != id);
+        if alerts.len() == before {
+            return Ok(false);
+        }
+        self.save(&alerts)?;
+        Ok(true)
+    }
+
+    // everything, including ones that have expired or haven't started
+    pub fn all(&self) -> Vec<Alert> {
+        self.alerts.read().unwrap().clone()
+    }
+
+    pub fn current<'a>(
+        &self,
+        namespace: Option<&str>,
+        operator_ids: &[&str],
+        location_ids: impl IntoIterator<Item = &'a str> + Clone,
+    ) -> Vec<Alert> {
+        let now = Utc::now();
+        self.alerts
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|x| {
+                x.is_current(now) && x.applies_to(namespace, operator_ids, location_ids.clone())
+            })
+            .cloned()
+            .collect()
+    }
+
+    // for a train: its namespace, anyone it's run by and anywhere on its route
+    pub fn for_train(&self, namespace: &str, train: &Train) -> Vec<Alert> {
+        let operator_ids = train
+            .variable_train
+            .operator
+            .iter()
+            .chain(
+                train
+                    .route
+                    .iter()
+                    .filter_map(|x| x.change_en_route.as_ref()?.operator.as_ref()),
+            )
+            .map(|x| x.id.as_str())
+            .collect::<Vec<_>>();
+        self.current(
+            Some(namespace),
+            &operator_ids,
+            train.route.iter().map(|x| x.id.as_str()),
+        )
+    }
+}
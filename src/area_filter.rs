@@ -0,0 +1,145 @@
+use crate::error::Error;
+use crate::regions::{Region, Regions, RegionsConfig};
+use crate::schedule::{AssociationNode, Schedule, Train};
+
+use serde::Deserialize;
+
+use std::collections::{HashMap, HashSet};
+
+// For deployments that only care about one area, e.g. "Scotland only": every train that doesn't
+// call anywhere in it is thrown away as it's committed, whichever feed it came from, so the
+// schedule held in memory is a fraction of the size. Trains associated with one that's kept (say
+// the other half of a train that divides outside the area) are kept too, so associations still
+// have both ends. Locations are all kept; they're small, and importers need them to make sense of
+// trains coming in.
+
+#[derive(Clone, Default, Deserialize)]
+pub struct AreaFilterConfig {
+    locations: Option<HashMap<String, Vec<String>>>, // by namespace, location IDs to keep trains at
+    regions: Option<Vec<Region>>, // e.g. [{ country = "GB", region = "Scotland" }]
+    geography: Option<RegionsConfig>, // how to tell which region each location is in
+    namespaces: Option<Vec<String>>, // the only ones filtered; by default any with an area
+}
+
+pub struct AreaFilter {
+    locations: HashMap<String, HashSet<String>>,
+    regions: Vec<Region>,
+    geography: Regions,
+    namespaces: Option<HashSet<String>>,
+}
+
+fn associations(train: &Train) -> impl Iterator<Item = &AssociationNode> {
+    train.route.iter().flat_map(|x| {
+        x.divides_to_form
+            .iter()
+            .chain(x.joins_to.iter())
+            .chain(x.becomes.iter())
+            .chain(x.divides_from.iter())
+            .chain(x.is_joined_to_by.iter())
+            .chain(x.forms_from.iter())
+    })
+}
+
+// every version of a train, including overlays
+fn versions(trains: &[Train]) -> impl Iterator<Item = &Train> {
+    trains
+        .iter()
+        .flat_map(|x| std::iter::once(x).chain(x.replacements.iter()))
+}
+
+fn calls_in(trains: &[Train], area: &HashSet<String>) -> bool {
+    versions(trains).any(|x| x.route.iter().any(|y| area.contains(y.id.as_str())))
+}
+
+impl AreaFilter {
+    pub fn new(config: AreaFilterConfig) -> Result<Self, Error> {
+        Ok(Self {
+            locations: config
+                .locations
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(namespace, ids)| (namespace, ids.into_iter().collect()))
+                .collect(),
+            regions: config.regions.unwrap_or_default(),
+            geography: Regions::new(config.geography.unwrap_or_default())?,
+            namespaces: config.namespaces.map(|x| x.into_iter().collect()),
+        })
+    }
+
+    pub fn filters(&self, namespace: &str) -> bool {
+        match &self.namespaces {
+            Some(x) if !x.contains(namespace) => false,
+            _ => !self.regions.is_empty() || self.locations.contains_key(namespace),
+        }
+    }
+
+    // the IDs of the locations trains have to call at to be kept
+    pub fn area(&self, schedule: &Schedule) -> HashSet<String> {
+        let mut area = self
+            .locations
+            .get(&schedule.namespace)
+            .cloned()
+            .unwrap_or_default();
+        if !self.regions.is_empty() {
+            for location_id in schedule.locations.keys() {
+                let region = self.geography.locate(schedule, location_id);
+                if self
+                    .regions
+                    .iter()
+                    .any(|x| region.matches(x.country.as_deref(), x.region.as_deref()))
+                {
+                    area.insert(location_id.clone());
+                }
+            }
+        }
+        area
+    }
+
+    fn keeps(&self, schedule: &Schedule, train_id: &str, area: &HashSet<String>) -> bool {
+        let trains = match schedule.trains.get(train_id) {
+            Some(x) => x,
+            None => return true,
+        };
+        // deleted trains stay in the map for a while, and cost nothing
+        trains.is_empty()
+            || calls_in(trains, area)
+            || versions(trains).flat_map(associations).any(|x| {
+                schedule
+                    .trains
+                    .get(x.other_train_id.as_str())
+                    .is_some_and(|y| calls_in(y, area))
+            })
+    }
+
+    // Drops trains outside the area, either all of them or, after a small update, just those it
+    // changed. Returns how many were dropped.
+    pub fn apply(&self, schedule: &mut Schedule, changed: Option<&HashSet<String>>) -> usize {
+        if !self.filters(&schedule.namespace) {
+            return 0;
+        }
+        let area = self.area(schedule);
+        let candidates = match changed {
+            Some(x) => x.iter().cloned().collect::<Vec<_>>(),
+            None => schedule.trains.keys().cloned().collect(),
+        };
+        let dropped = candidates
+            .into_iter()
+            .filter(|x| !self.keeps(schedule, x, &area))
+            .collect::<Vec<_>>();
+        for train_id in &dropped {
+            schedule.trains.remove(train_id);
+            schedule.realtime.remove(train_id);
+            schedule.shapes_indexed_by_train.remove(train_id);
+            schedule.reindex_train(train_id);
+        }
+        if changed.is_none() {
+            let used = schedule
+                .shapes_indexed_by_train
+                .values()
+                .cloned()
+                .collect::<HashSet<_>>();
+            schedule.shapes.retain(|x, _| used.contains(x));
+        }
+        dropped.len()
+    }
+}
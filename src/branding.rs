@@ -0,0 +1,128 @@
+use crate::error::Error;
+use crate::schedule::VariableTrain;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+// How operators and brands style themselves, so frontends can colour boards the way stations' own
+// screens do. The feeds only give an operator code and sometimes a brand name, so all of this
+// comes from config.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Branding {
+    pub name: Option<String>,
+    pub short_name: Option<String>, // for narrow columns, e.g. "GWR"
+    pub colour: Option<String>,     // as CSS has it, e.g. "#0a493e"
+    pub text_colour: Option<String>,
+    pub logo_url: Option<String>,
+}
+
+impl Branding {
+    // anything this leaves out taken from other
+    fn or(&self, other: &Branding) -> Branding {
+        Branding {
+            name: self.name.clone().or_else(|| other.name.clone()),
+            short_name: self.short_name.clone().or_else(|| other.short_name.clone()),
+            colour: self.colour.clone().or_else(|| other.colour.clone()),
+            text_colour: self
+                .text_colour
+                .clone()
+                .or_else(|| other.text_colour.clone()),
+            logo_url: self.logo_url.clone().or_else(|| other.logo_url.clone()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NamespaceBranding {
+    #[serde(default)]
+    operators: HashMap<String, Branding>, // by operator ID, e.g. an ATOC code
+    #[serde(default)]
+    brands: HashMap<String, Branding>, // by the brand trains carry, e.g. "Eurostar"
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct BrandingConfig {
+    namespaces: Option<HashMap<String, NamespaceBranding>>,
+    files: Option<Vec<String>>, // JSON of the same shape as namespaces, later ones overriding
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OperatorInfo {
+    pub namespace: String,
+    pub id: String,
+    pub is_brand: bool, // a brand run by one or more operators rather than an operator itself
+    #[serde(flatten)]
+    pub branding: Branding,
+}
+
+pub struct OperatorBranding {
+    namespaces: HashMap<String, NamespaceBranding>,
+}
+
+impl OperatorBranding {
+    pub fn new(config: BrandingConfig) -> Result<Self, Error> {
+        let mut branding = Self {
+            namespaces: HashMap::new(),
+        };
+        branding.merge(config.namespaces.unwrap_or_default());
+        for filename in config.files.unwrap_or_default() {
+            println!("Loading branding from {}", filename);
+            let contents = std::fs::read_to_string(&filename)?;
+            branding.merge(serde_json::from_str::<HashMap<String, NamespaceBranding>>(
+                &contents,
+            )?);
+        }
+        Ok(branding)
+    }
+
+    fn merge(&mut self, namespaces: HashMap<String, NamespaceBranding>) {
+        for (namespace, branding) in namespaces {
+            let existing = self.namespaces.entry(namespace).or_default();
+            existing.operators.extend(branding.operators);
+            existing.brands.extend(branding.brands);
+        }
+    }
+
+    // The brand's own styling if it has any (e.g. Eurostar, whoever runs it), with the rest from
+    // the operator. Trains can change operator en route, so this wants the variable_train in
+    // force at the location it's for.
+    pub fn for_train(&self, namespace: &str, variable_train: &VariableTrain) -> Option<Branding> {
+        let branding = self.namespaces.get(namespace)?;
+        let brand = variable_train
+            .brand
+            .as_ref()
+            .and_then(|x| branding.brands.get(x));
+        let operator = variable_train
+            .operator
+            .as_ref()
+            .and_then(|x| branding.operators.get(x.id.as_str()));
+        match (brand, operator) {
+            (Some(x), Some(y)) => Some(x.or(y)),
+            (x, y) => x.or(y).cloned(),
+        }
+    }
+
+    pub fn operators(&self, only_namespace: Option<&str>) -> Vec<OperatorInfo> {
+        let mut operators = vec![];
+        for (namespace, branding) in &self.namespaces {
+            if only_namespace.map_or(false, |x| x != namespace) {
+                continue;
+            }
+            for (is_brand, entries) in [(false, &branding.operators), (true, &branding.brands)] {
+                operators.extend(entries.iter().map(|(id, x)| OperatorInfo {
+                    namespace: namespace.clone(),
+                    id: id.clone(),
+                    is_brand,
+                    branding: x.clone(),
+                }));
+            }
+        }
+        operators.sort_by(|x, y| {
+            (&x.namespace, x.is_brand, &x.id).cmp(&(&y.namespace, y.is_brand, &y.id))
+        });
+        operators
+    }
+}
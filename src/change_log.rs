@@ -0,0 +1,105 @@
+use crate::notifications::TrainEvent;
+
+use chrono::{DateTime, Days, NaiveDate, Utc};
+
+use serde::Serialize;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+// What the realtime feeds have done to each train, in the order we applied it, so people can see
+// how its plan got where it is rather than only where it ended up: e.g. replaced by VSTP at 10:02,
+// then cancelled at 11:45. Only the last few changes to each train are kept, and nothing about
+// trains that have finished running.
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type")]
+pub enum ChangeKind {
+    VstpCreated,  // a train we didn't have
+    VstpReplaced, // an overlay on one we did
+    VstpCancelled,
+    VstpDeleted, // something VSTP sent before, taken away again
+    VstpUpdated,
+    Realtime { event: TrainEvent },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TrainChange {
+    pub time: DateTime<Utc>,   // when we applied it
+    pub first_date: NaiveDate, // the operating dates it's for
+    pub last_date: NaiveDate,
+    pub change: ChangeKind,
+}
+
+impl TrainChange {
+    pub fn new(first_date: NaiveDate, last_date: NaiveDate, change: ChangeKind) -> Self {
+        Self {
+            time: Utc::now(),
+            first_date,
+            last_date,
+            change,
+        }
+    }
+}
+
+pub struct ChangeLog {
+    max_per_train: AtomicUsize,
+    trains: RwLock<HashMap<(String, String), VecDeque<TrainChange>>>, // by namespace and train ID
+}
+
+impl Default for ChangeLog {
+    fn default() -> Self {
+        Self {
+            max_per_train: AtomicUsize::new(50),
+            trains: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+impl ChangeLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // the oldest are dropped first
+    pub fn set_max_per_train(&self, max_per_train: usize) {
+        self.max_per_train.store(max_per_train, Ordering::Relaxed);
+    }
+
+    pub fn record(&self, namespace: &str, train_id: &str, change: TrainChange) {
+        let key = (namespace.to_string(), train_id.to_string());
+        let mut trains = self.trains.write().unwrap();
+        // New trains turn up every day, so every so often forget the ones that have finished
+        // running. Realtime messages stop coming a couple of days after.
+        if !trains.contains_key(&key) {
+            let cutoff = Utc::now()
+                .date_naive()
+                .checked_sub_days(Days::new(2))
+                .unwrap();
+            trains.retain(|_, changes| changes.iter().any(|x| x.last_date >= cutoff));
+        }
+        let changes = trains.entry(key).or_default();
+        changes.push_back(change);
+        while changes.len() > self.max_per_train.load(Ordering::Relaxed) {
+            changes.pop_front();
+        }
+    }
+
+    // oldest first
+    pub fn history(&self, namespace: &str, train_id: &str, date: NaiveDate) -> Vec<TrainChange> {
+        match self
+            .trains
+            .read()
+            .unwrap()
+            .get(&(namespace.to_string(), train_id.to_string()))
+        {
+            Some(x) => x
+                .iter()
+                .filter(|x| x.first_date <= date && date <= x.last_date)
+                .cloned()
+                .collect(),
+            None => vec![],
+        }
+    }
+}
@@ -0,0 +1,470 @@
+use crate::intern::{IStr, Interner};
+use crate::schedule::{intern_route, Schedule, Train, TrainLocation};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::{Read, Write};
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+
+// Most trains in a full extract are never asked about, yet their routes are most of what a
+// schedule holds in memory. With this configured, every route is compressed as its schedule's
+// committed, and only the most recently looked at few keep a decompressed copy alongside; the rest
+// are decompressed again the next time anything looks at them. Small servers trade some CPU on the
+// first look at a train for a much smaller resident set.
+//
+// Full imports and daily updates get a full pass, as does compress_cold_routes(). Small updates
+// (VSTP, TRUST) only compress the trains they changed, which then stay decompressed until enough
+// others have been changed since.
+
+// How a namespace's routes are being looked at, kept apart for each namespace a ColdRoutes looks
+// after, so how busy one is doesn't push another's routes out
+#[derive(Default)]
+struct Usage {
+    clock: AtomicU64, // moved on by every pass, so each route knows roughly when it was last looked at
+    decompressed: AtomicUsize, // since the last pass, which is how a pass knows to come early
+    changed: Mutex<Changed>, // since the last pass
+}
+
+// The trains small updates have compressed since the last pass, least recently changed first, and
+// how many routes each has
+#[derive(Default)]
+struct Changed {
+    next: u64,
+    trains: HashMap<String, (u64, usize)>,
+    order: BTreeMap<u64, String>,
+    routes: usize,
+}
+
+impl Changed {
+    // moves it to the back, then takes whatever's over the limit off the front
+    fn touch(&mut self, train_id: &str, routes: usize, limit: usize) -> Vec<String> {
+        match self
+            .trains
+            .insert(train_id.to_string(), (self.next, routes))
+        {
+            Some((x, y)) => {
+                self.order.remove(&x);
+                self.routes -= y;
+            }
+            None => (),
+        }
+        self.order.insert(self.next, train_id.to_string());
+        self.next += 1;
+        self.routes += routes;
+        let mut evicted = vec![];
+        while self.routes > limit {
+            let (_, train_id) = self.order.pop_first().unwrap();
+            self.routes -= self.trains.remove(&train_id).unwrap().1;
+            evicted.push(train_id);
+        }
+        evicted
+    }
+
+    fn clear(&mut self) {
+        self.trains.clear();
+        self.order.clear();
+        self.routes = 0;
+    }
+}
+
+/// A train's calling points. Reads and writes go through to a plain vector, which is decompressed
+/// first if need be; writing drops the compressed copy, as it's out of date.
+pub struct Route {
+    hot: OnceLock<Arc<Vec<TrainLocation>>>, // both shared between copies of the schedule
+    cold: Option<Arc<Cold>>,
+    usage: Option<Arc<Usage>>, // that of the namespace it was compressed in
+    last_used: AtomicU64,
+}
+
+struct Cold {
+    bytes: Box<[u8]>,
+    strings: Box<[IStr]>, // the route's own, so decompressing shares them again
+}
+
+fn decompress(cold: &Cold) -> Vec<TrainLocation> {
+    let mut decoded = vec![];
+    DeflateDecoder::new(&cold.bytes[..])
+        .read_to_end(&mut decoded)
+        .expect("Compressed route should decompress");
+    let mut route =
+        bincode::deserialize::<Vec<_>>(&decoded).expect("Compressed route should deserialise");
+    intern_route(
+        &mut route,
+        &mut Interner::with_strings(cold.strings.iter().cloned()),
+    );
+    route
+}
+
+impl Route {
+    pub fn is_compressed(&self) -> bool {
+        self.cold.is_some()
+    }
+
+    pub fn is_decompressed(&self) -> bool {
+        self.hot.get().is_some()
+    }
+
+    // without keeping the decompressed copy, for things that look at every train once, e.g.
+    // snapshots
+    pub fn peek<T>(&self, f: impl FnOnce(&Vec<TrainLocation>) -> T) -> T {
+        match (self.hot.get(), &self.cold) {
+            (Some(x), _) => f(x),
+            (None, Some(x)) => f(&decompress(x)),
+            (None, None) => unreachable!(),
+        }
+    }
+
+    // how many bytes it came to
+    fn compress(&mut self, usage: &Arc<Usage>) -> usize {
+        let mut encoder = DeflateEncoder::new(vec![], Compression::fast());
+        // not through deref, which would count as looking at it
+        let route = Arc::make_mut(self.hot.get_mut().unwrap());
+        encoder
+            .write_all(&bincode::serialize(route).expect("Route should serialise"))
+            .unwrap();
+        // it's been interned already, so this only finds the strings it has
+        let mut interner = Interner::new();
+        intern_route(route, &mut interner);
+        let cold = Cold {
+            bytes: encoder.finish().unwrap().into(),
+            strings: interner.into_strings().into(),
+        };
+        let len = cold.bytes.len();
+        self.cold = Some(Arc::new(cold));
+        self.usage = Some(usage.clone());
+        len
+    }
+
+    // a copy that's only decompressed, for a copy of the train that's about to be thrown away
+    fn decompressed(&self) -> Self {
+        match self.hot.get() {
+            Some(_) => self.clone(),
+            None => decompress(self.cold.as_ref().unwrap()).into(),
+        }
+    }
+
+    fn evict(&mut self) {
+        if self.cold.is_some() {
+            self.hot.take();
+        }
+    }
+
+    // as of the pass that's now starting, `now`; one that's never been compressed is new since
+    // the last
+    fn last_used(&self, now: u64) -> u64 {
+        match &self.usage {
+            Some(_) => self.last_used.load(Ordering::Relaxed),
+            None => now,
+        }
+    }
+}
+
+impl Deref for Route {
+    type Target = Vec<TrainLocation>;
+
+    fn deref(&self) -> &Vec<TrainLocation> {
+        // only compressed routes are ever evicted, so only they need to know
+        let usage = match &self.usage {
+            Some(x) => x,
+            None => return self.hot.get().unwrap(),
+        };
+        // only written when it changes, as lots of threads read the same few routes at once
+        let now = usage.clock.load(Ordering::Relaxed);
+        if self.last_used.load(Ordering::Relaxed) != now {
+            self.last_used.store(now, Ordering::Relaxed);
+        }
+        self.hot.get_or_init(|| {
+            usage.decompressed.fetch_add(1, Ordering::Relaxed);
+            Arc::new(decompress(self.cold.as_ref().unwrap()))
+        })
+    }
+}
+
+impl DerefMut for Route {
+    fn deref_mut(&mut self) -> &mut Vec<TrainLocation> {
+        if self.hot.get().is_none() {
+            let _ = self
+                .hot
+                .set(Arc::new(decompress(self.cold.as_ref().unwrap())));
+        }
+        self.cold = None;
+        self.usage = None;
+        // copied first if another copy of the schedule still has it
+        Arc::make_mut(self.hot.get_mut().unwrap())
+    }
+}
+
+impl Clone for Route {
+    fn clone(&self) -> Self {
+        Self {
+            hot: self.hot.clone(),
+            cold: self.cold.clone(),
+            usage: self.usage.clone(),
+            last_used: AtomicU64::new(self.last_used.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Default for Route {
+    fn default() -> Self {
+        vec![].into()
+    }
+}
+
+impl From<Vec<TrainLocation>> for Route {
+    fn from(route: Vec<TrainLocation>) -> Self {
+        Self {
+            hot: OnceLock::from(Arc::new(route)),
+            cold: None,
+            usage: None,
+            last_used: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Route {
+    type Item = &'a TrainLocation;
+    type IntoIter = std::slice::Iter<'a, TrainLocation>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut Route {
+    type Item = &'a mut TrainLocation;
+    type IntoIter = std::slice::IterMut<'a, TrainLocation>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl fmt::Debug for Route {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.peek(|x| fmt::Debug::fmt(x, f))
+    }
+}
+
+impl Serialize for Route {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.peek(|x| x.serialize(serializer))
+    }
+}
+
+impl<'de> Deserialize<'de> for Route {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Vec::<TrainLocation>::deserialize(deserializer)?.into())
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct ColdRoutesConfig {
+    hot_routes: Option<usize>, // decompressed ones kept in each namespace; 10000 if not given
+    namespaces: Option<Vec<String>>, // the only ones compressed; by default all
+}
+
+pub struct ColdRoutes {
+    hot_routes: usize,
+    namespaces: Option<HashSet<String>>,
+    usage: Mutex<HashMap<String, Arc<Usage>>>, // by namespace
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ColdRouteStats {
+    pub compressed: usize, // routes that weren't already
+    pub compressed_bytes: usize,
+    pub evicted: usize, // decompressed copies dropped
+    pub hot: usize,     // and kept
+}
+
+impl fmt::Display for ColdRouteStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} routes into {} KiB, dropped {} decompressed and kept {}",
+            self.compressed,
+            self.compressed_bytes / 1024,
+            self.evicted,
+            self.hot
+        )
+    }
+}
+
+// every version of a train, including overlays
+fn routes(train: &Train) -> impl Iterator<Item = &Route> {
+    std::iter::once(&train.route).chain(train.replacements.iter().map(|x| &x.route))
+}
+
+fn routes_mut(train: &mut Train) -> impl Iterator<Item = &mut Route> {
+    std::iter::once(&mut train.route).chain(train.replacements.iter_mut().map(|x| &mut x.route))
+}
+
+/// The train with every route readable, for things that go through every train once, e.g.
+/// statistics. Routes only there compressed are decompressed into a copy rather than the train
+/// itself, so looking at them all doesn't undo the last pass.
+pub fn peek_train(train: &Train) -> Cow<'_, Train> {
+    if routes(train).all(|x| x.is_decompressed()) {
+        return Cow::Borrowed(train);
+    }
+    let mut train = train.clone();
+    for route in routes_mut(&mut train) {
+        *route = route.decompressed();
+    }
+    Cow::Owned(train)
+}
+
+impl ColdRoutes {
+    pub fn new(config: ColdRoutesConfig) -> Self {
+        Self {
+            hot_routes: config.hot_routes.unwrap_or(10000),
+            namespaces: config.namespaces.map(|x| x.into_iter().collect()),
+            usage: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn usage(&self, namespace: &str) -> Arc<Usage> {
+        self.usage
+            .lock()
+            .unwrap()
+            .entry(namespace.to_string())
+            .or_default()
+            .clone()
+    }
+
+    pub fn wants(&self, namespace: &str) -> bool {
+        match &self.namespaces {
+            Some(x) => x.contains(namespace),
+            None => true,
+        }
+    }
+
+    // whether enough routes have been decompressed in any namespace since its last pass that it's
+    // worth another before the next one's due
+    pub fn due(&self) -> bool {
+        self.usage
+            .lock()
+            .unwrap()
+            .values()
+            .any(|x| x.decompressed.load(Ordering::Relaxed) > self.hot_routes)
+    }
+
+    // whether apply() would drop anything, without needing our own copy of the schedule
+    pub fn over_limit(&self, schedule: &Schedule) -> bool {
+        schedule
+            .trains
+            .values()
+            .flatten()
+            .flat_map(|x| std::iter::once(x).chain(x.replacements.iter()))
+            .filter(|x| x.route.is_compressed() && x.route.is_decompressed())
+            .nth(self.hot_routes)
+            .is_some()
+    }
+
+    // Compresses any route that isn't, then drops the decompressed copies of all but the most
+    // recently looked at. Anything looked at from here on counts as more recent than all of them.
+    // What to change is worked out first, so only the trains that do change are written to, and a
+    // copy of a committed schedule only copies the partitions they're in.
+    pub fn apply(&self, schedule: &mut Schedule) -> ColdRouteStats {
+        let usage = self.usage(&schedule.namespace);
+        let now = usage.clock.fetch_add(1, Ordering::Relaxed);
+        usage.decompressed.store(0, Ordering::Relaxed);
+        usage.changed.lock().unwrap().clear();
+        let mut changes = HashMap::<String, Vec<usize>>::new(); // by train, the routes to evict
+        let mut hot = vec![];
+        for (train_id, trains) in &schedule.trains {
+            for (i, route) in trains.iter().flat_map(routes).enumerate() {
+                if !route.is_compressed() {
+                    changes.entry(train_id.clone()).or_default();
+                }
+                if route.is_decompressed() {
+                    hot.push((route.last_used(now), train_id, i));
+                }
+            }
+        }
+        hot.sort_unstable_by_key(|x| std::cmp::Reverse(x.0));
+        for (_, train_id, i) in hot.iter().skip(self.hot_routes) {
+            changes.entry(train_id.to_string()).or_default().push(*i);
+        }
+
+        let mut stats = ColdRouteStats {
+            hot: hot.len(),
+            ..Default::default()
+        };
+        for (train_id, evict) in changes {
+            let trains = schedule.trains.get_mut(&train_id).unwrap();
+            for (i, route) in trains.iter_mut().flat_map(routes_mut).enumerate() {
+                if !route.is_compressed() {
+                    stats.compressed += 1;
+                    stats.compressed_bytes += route.compress(&usage);
+                }
+                if evict.contains(&i) {
+                    route.evict();
+                    stats.evicted += 1;
+                }
+            }
+        }
+        stats.hot -= stats.evicted;
+        stats
+    }
+
+    // For small updates: only compresses the routes of the trains they changed, which stay
+    // decompressed, and drops the decompressed copies of those changed longest ago once there are
+    // more than hot_routes of them. Nothing else is looked at.
+    pub fn apply_changed(
+        &self,
+        schedule: &mut Schedule,
+        changed: &HashSet<String>,
+    ) -> ColdRouteStats {
+        let usage = self.usage(&schedule.namespace);
+        let mut changed_since = usage.changed.lock().unwrap();
+        let mut stats = ColdRouteStats::default();
+        let mut evict = vec![];
+        for train_id in changed {
+            let trains = match schedule.trains.get(train_id) {
+                Some(x) => x,
+                None => continue,
+            };
+            if trains.iter().flat_map(routes).all(|x| x.is_compressed()) {
+                continue;
+            }
+            let trains = schedule.trains.get_mut(train_id).unwrap();
+            let mut count = 0;
+            for route in trains.iter_mut().flat_map(routes_mut) {
+                if !route.is_compressed() {
+                    stats.compressed += 1;
+                    stats.compressed_bytes += route.compress(&usage);
+                }
+                count += 1;
+            }
+            evict.extend(changed_since.touch(train_id, count, self.hot_routes));
+        }
+        for train_id in evict {
+            // unless it was changed again later on in these
+            if changed_since.trains.contains_key(&train_id) {
+                continue;
+            }
+            let trains = match schedule.trains.get_mut(&train_id) {
+                Some(x) => x,
+                None => continue,
+            };
+            for route in trains.iter_mut().flat_map(routes_mut) {
+                if route.is_compressed() && route.is_decompressed() {
+                    route.evict();
+                    stats.evicted += 1;
+                }
+            }
+        }
+        stats.hot = changed_since.routes;
+        stats
+    }
+}
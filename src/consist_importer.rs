@@ -0,0 +1,162 @@
+use crate::error::Error;
+use crate::importer::SlowStreamingImporter;
+use crate::schedule::{Schedule, TrainAllocation, TrainVehicle, VehicleAccessibility};
+
+use async_trait::async_trait;
+
+use chrono::NaiveDate;
+
+use serde::Deserialize;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+use std::collections::HashMap;
+
+// Freight consists, wagon by wagon, for the trains and days we have them, so freight users get
+// more than a bare path. Network Rail's Schedule Freight Gateway and TOPS have this, but not as an
+// open feed, so for now this reads a JSON array of these, put together from whatever extract an
+// operator or NR will share. Each is summarised on the train as its actual allocation: one vehicle
+// per wagon type, in the order they first turn up.
+//
+// Anything else given for a wagon, e.g. its number, is ignored.
+#[derive(Deserialize)]
+struct WagonJson {
+    wagon_type: String,          // the TOPS code, e.g. "FEA"
+    description: Option<String>, // e.g. "Container flat"
+    length_m: Option<f64>,
+    max_speed_mph: Option<u16>,
+    loaded: Option<bool>,
+    gross_weight_t: Option<f64>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConsistJson {
+    train_id: String,   // the CIF UID, as the train is known in the schedule
+    date: NaiveDate,    // its operating date
+    id: Option<String>, // the consist's own, e.g. a TOPS train ID; default the train ID
+    wagons: Vec<WagonJson>,
+}
+
+fn vehicles(wagons: &[WagonJson]) -> Vec<TrainVehicle> {
+    let mut vehicles: Vec<(TrainVehicle, Vec<f64>)> = vec![];
+    for wagon in wagons {
+        let i = match vehicles.iter().position(|(x, _)| x.id == wagon.wagon_type) {
+            Some(x) => x,
+            None => {
+                vehicles.push((
+                    TrainVehicle {
+                        id: wagon.wagon_type.clone(),
+                        description: wagon
+                            .description
+                            .clone()
+                            .unwrap_or(wagon.wagon_type.clone()),
+                        cars: vec![0],
+                        car_length_m: None,
+                        max_speed_mph: None,
+                        accessibility: VehicleAccessibility::default(),
+                    },
+                    vec![],
+                ));
+                vehicles.len() - 1
+            }
+        };
+        let (vehicle, lengths) = &mut vehicles[i];
+        vehicle.cars[0] += 1;
+        match wagon.length_m {
+            Some(x) => lengths.push(x),
+            None => (),
+        }
+        // the slowest wagon of a type is what that type can do
+        vehicle.max_speed_mph = match (vehicle.max_speed_mph, wagon.max_speed_mph) {
+            (Some(x), Some(y)) => Some(x.min(y)),
+            (x, y) => x.or(y),
+        };
+    }
+    vehicles
+        .into_iter()
+        .map(|(mut vehicle, lengths)| {
+            if !lengths.is_empty() {
+                vehicle.car_length_m = Some(lengths.iter().sum::<f64>() / lengths.len() as f64);
+            }
+            vehicle
+        })
+        .collect()
+}
+
+// e.g. "24 wagons (20 loaded), 480 m, 1650 t", leaving out whatever isn't known for all of them
+fn description(wagons: &[WagonJson]) -> String {
+    let mut description = format!("{} wagons", wagons.len());
+    let loaded = wagons.iter().map(|x| x.loaded).collect::<Option<Vec<_>>>();
+    match loaded {
+        Some(x) if !x.is_empty() => {
+            description += &format!(" ({} loaded)", x.iter().filter(|x| **x).count())
+        }
+        _ => (),
+    }
+    match wagons.iter().map(|x| x.length_m).sum::<Option<f64>>() {
+        Some(x) if x > 0.0 => description += &format!(", {:.0} m", x),
+        _ => (),
+    }
+    match wagons.iter().map(|x| x.gross_weight_t).sum::<Option<f64>>() {
+        Some(x) if x > 0.0 => description += &format!(", {:.0} t", x),
+        _ => (),
+    }
+    description
+}
+
+pub struct ConsistImporter {}
+
+impl ConsistImporter {
+    pub fn new() -> ConsistImporter {
+        ConsistImporter {}
+    }
+}
+
+#[async_trait]
+impl SlowStreamingImporter for ConsistImporter {
+    async fn overlay(
+        &mut self,
+        mut reader: impl AsyncBufReadExt + Unpin + Send,
+        mut schedule: Schedule,
+    ) -> Result<Schedule, Error> {
+        println!("Importing freight consists");
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).await?;
+        let consists = serde_json::from_str::<Vec<ConsistJson>>(&contents)?;
+
+        let mut skipped = 0;
+        for consist in consists {
+            // only for freight, as passenger trains' allocations come from elsewhere
+            let freight = schedule.trains.get(&consist.train_id).is_some_and(|x| {
+                x.iter()
+                    .flat_map(|y| std::iter::once(y).chain(y.replacements.iter()))
+                    .any(|y| y.variable_train.train_type.is_freight())
+            });
+            if !freight || consist.wagons.is_empty() {
+                skipped += 1;
+                continue;
+            }
+            schedule
+                .consists
+                .entry(consist.train_id.clone())
+                .or_insert(HashMap::new())
+                .insert(
+                    consist.date,
+                    TrainAllocation {
+                        id: consist.id.unwrap_or(consist.train_id).into(),
+                        description: description(&consist.wagons).into(),
+                        vehicles: Some(vehicles(&consist.wagons)),
+                    },
+                );
+        }
+        if skipped > 0 {
+            println!(
+                "Skipped {} freight consists for trains that aren't freight, or we don't have",
+                skipped
+            );
+        }
+
+        Ok(schedule)
+    }
+}
@@ -0,0 +1,166 @@
+use crate::partitioned_map::PartitionedMap;
+use crate::schedule::{train_index_keys, Schedule, Train, TrainIndexKeys, TrainLocation};
+
+use chrono::{NaiveTime, Timelike};
+
+use serde::Serialize;
+
+use std::collections::{HashMap, HashSet};
+
+type Index = PartitionedMap<HashSet<String>>;
+
+// Things that should be true of every train whatever an importer was fed, so anything found here
+// is a bug in the importer rather than in the data. Used for validating new feeds, and by the fuzz
+// tests to check mangled input doesn't get through as nonsense.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct InvariantViolation {
+    pub train_id: String,
+    pub problem: String,
+}
+
+// seconds from midnight on the day the train starts
+fn offset_secs(day: Option<u8>, time: Option<NaiveTime>) -> Option<u32> {
+    let time = time?;
+    Some(u32::from(day.unwrap_or(0)) * 86400 + time.num_seconds_from_midnight())
+}
+
+// Working times have to go forwards along the route (or stay put) once the day offsets are added
+// on. Public times aren't checked, as their days are worked out from the working times, so a
+// public time that's nothing like the working one can come out on the wrong day.
+fn check_times(route: &[TrainLocation], problems: &mut Vec<String>) {
+    let mut last: Option<(u32, &str)> = None;
+    for location in route {
+        for time in [
+            offset_secs(location.working_arr_day, location.working_arr),
+            offset_secs(location.working_pass_day, location.working_pass),
+            offset_secs(location.working_dep_day, location.working_dep),
+        ]
+        .into_iter()
+        .flatten()
+        {
+            match last {
+                Some((last, last_id)) if time < last => problems.push(format!(
+                    "working time at {} is before the one at {}",
+                    location.id, last_id
+                )),
+                _ => (),
+            }
+            last = Some((time, location.id.as_str()));
+        }
+    }
+}
+
+pub fn check_train(train: &Train) -> Vec<String> {
+    let mut problems = vec![];
+    for validity in &train.validity {
+        if validity.valid_begin > validity.valid_end {
+            problems.push(format!(
+                "valid from {} until {}, which is earlier",
+                validity.valid_begin, validity.valid_end
+            ));
+        }
+    }
+
+    let (origin, destination) = match (train.route.first(), train.route.last()) {
+        (Some(x), Some(y)) => (x, y),
+        _ => {
+            problems.push("has no route".to_string());
+            return problems;
+        }
+    };
+    if origin.working_arr.is_some() || origin.public_arr.is_some() {
+        problems.push(format!("arrives at its origin {}", origin.id));
+    }
+    if destination.working_dep.is_some() || destination.public_dep.is_some() {
+        problems.push(format!("departs from its destination {}", destination.id));
+    }
+    check_times(&train.route, &mut problems);
+
+    for replacement in &train.replacements {
+        for problem in check_train(replacement) {
+            problems.push(format!("replacement: {}", problem));
+        }
+    }
+    problems
+}
+
+fn check_indexed(schedule: &Schedule, train_id: &str, train: &Train, problems: &mut Vec<String>) {
+    for location in &train.route {
+        if !schedule
+            .trains_indexed_by_location
+            .get(location.id.as_str())
+            .is_some_and(|x| x.contains(train_id))
+        {
+            problems.push(format!("not indexed at {}", location.id));
+        }
+    }
+    for replacement in &train.replacements {
+        check_indexed(schedule, train_id, replacement, problems);
+    }
+}
+
+// The other way round: index entries for trains that don't call there or go by that ID any more,
+// which deletes and amendments used to leave behind
+fn check_index_entries(schedule: &Schedule) -> Vec<InvariantViolation> {
+    let mut keys = HashMap::new();
+    let mut violations = vec![];
+    let indexes: [(&str, &Index, fn(&TrainIndexKeys) -> &HashSet<String>); 3] = [
+        ("location", &schedule.trains_indexed_by_location, |x| {
+            &x.locations
+        }),
+        ("public ID", &schedule.trains_indexed_by_public_id, |x| {
+            &x.public_ids
+        }),
+        ("UIC number", &schedule.trains_indexed_by_uic, |x| {
+            &x.uic_codes
+        }),
+    ];
+    for (what, index, wanted) in indexes {
+        for (key, train_ids) in index {
+            for train_id in train_ids {
+                let keys = keys.entry(train_id).or_insert_with(|| {
+                    train_index_keys(
+                        schedule
+                            .trains
+                            .get(train_id)
+                            .map(|x| x.as_slice())
+                            .unwrap_or_default(),
+                    )
+                });
+                if !wanted(keys).contains(key) {
+                    violations.push(InvariantViolation {
+                        train_id: train_id.clone(),
+                        problem: format!(
+                            "indexed under {} {} but has no schedule with it",
+                            what, key
+                        ),
+                    });
+                }
+            }
+        }
+    }
+    violations.sort_by(|x, y| (&x.train_id, &x.problem).cmp(&(&y.train_id, &y.problem)));
+    violations
+}
+
+pub fn check_schedule(schedule: &Schedule) -> Vec<InvariantViolation> {
+    let mut violations = vec![];
+    let mut train_ids = schedule.trains.keys().collect::<Vec<_>>();
+    train_ids.sort();
+    for train_id in train_ids {
+        for train in &schedule.trains[train_id] {
+            let mut problems = check_train(train);
+            if &train.id != train_id {
+                problems.push(format!("filed under {} but has ID {}", train_id, train.id));
+            }
+            check_indexed(schedule, train_id, train, &mut problems);
+            problems.dedup();
+            violations.extend(problems.into_iter().map(|problem| InvariantViolation {
+                train_id: train_id.clone(),
+                problem,
+            }));
+        }
+    }
+    violations.extend(check_index_entries(schedule));
+    violations
+}
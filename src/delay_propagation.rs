@@ -0,0 +1,140 @@
+use crate::schedule::{LocalTimes, RealtimeEventType, TrainLocation, TrainRealtime};
+
+use chrono::{DateTime, Duration};
+use chrono_tz::Tz;
+
+use serde::Serialize;
+
+// When a train will probably get to the rest of its route, going by the last report we had from
+// it. Whatever delay it had then is carried forward, less any engineering, pathing or performance
+// allowances on the way, which are slack it can make up. A train running early can't leave a stop
+// before its booked time, so that's where an early running train gets back to time. Reported times
+// are passed through as they are, and anything we've worked out ourselves says it's projected.
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub enum ExpectedSource {
+    Reported,
+    Projected,
+}
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ExpectedTime {
+    pub time: DateTime<Tz>,
+    pub source: ExpectedSource,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ExpectedTimes {
+    pub arrival: Option<ExpectedTime>,
+    pub departure: Option<ExpectedTime>,
+    pub pass: Option<ExpectedTime>,
+    pub delay_minutes: Option<i64>, // as it leaves, where it stops; negative if early
+}
+
+// working times where there are any, as that's what reports are made against
+fn planned_arrival(times: &LocalTimes) -> Option<DateTime<Tz>> {
+    times.working_arr.or(times.public_arr)
+}
+
+fn planned_departure(times: &LocalTimes) -> Option<DateTime<Tz>> {
+    times.working_dep.or(times.public_dep)
+}
+
+// Allowances are given at the location they're taken after, i.e. on the way to the next one
+fn recovery(location: &TrainLocation) -> Duration {
+    Duration::seconds(
+        [
+            location.engineering_allowance_s,
+            location.pathing_allowance_s,
+            location.performance_allowance_s,
+        ]
+        .iter()
+        .map(|x| i64::from(x.unwrap_or(0)))
+        .sum(),
+    )
+}
+
+fn projected(time: DateTime<Tz>) -> Option<ExpectedTime> {
+    Some(ExpectedTime {
+        time,
+        source: ExpectedSource::Projected,
+    })
+}
+
+// One for each location in the route, which has to be the one the realtime reports were made
+// against, with its planned times alongside (all None where they're unknown). Nothing is projected
+// for a train that's cancelled or has finished.
+pub fn expected_times(
+    realtime: &TrainRealtime,
+    route: &[TrainLocation],
+    planned: &[LocalTimes],
+) -> Vec<Option<ExpectedTimes>> {
+    let mut expected = vec![None; route.len()];
+
+    // work along the route with the reports, so a location visited twice gets the right visit's
+    let mut last = None;
+    let mut cursor = 0;
+    for event in &realtime.events {
+        let i = match route[cursor..]
+            .iter()
+            .position(|x| event.location_ids.iter().any(|y| y == x.id.as_str()))
+        {
+            Some(x) => cursor + x,
+            None => continue,
+        };
+        cursor = i;
+        let reported = Some(ExpectedTime {
+            time: event.actual,
+            source: ExpectedSource::Reported,
+        });
+        let times = expected[i].get_or_insert_with(ExpectedTimes::default);
+        match event.event_type {
+            RealtimeEventType::Arrival => times.arrival = reported,
+            RealtimeEventType::Departure if planned[i].working_pass.is_some() => {
+                times.pass = reported
+            }
+            RealtimeEventType::Departure => times.departure = reported,
+        }
+        times.delay_minutes = Some(event.delay_minutes);
+        last = Some((i, event));
+    }
+
+    let (reported_index, event) = match last {
+        Some(x) if !realtime.terminated && realtime.cancellation.is_none() => x,
+        _ => return expected,
+    };
+    let mut delay = match event.planned {
+        Some(x) => event.actual - x,
+        None => Duration::minutes(event.delay_minutes),
+    };
+    let has_departed = event.event_type == RealtimeEventType::Departure;
+
+    for i in reported_index..route.len() {
+        if i > reported_index {
+            let recovered = recovery(&route[i - 1]);
+            if delay > Duration::zero() {
+                delay = std::cmp::max(delay - recovered, Duration::zero());
+            }
+        }
+        let times = &planned[i];
+        let entry = expected[i].get_or_insert_with(ExpectedTimes::default);
+        if i > reported_index {
+            entry.arrival = planned_arrival(times).and_then(|x| projected(x + delay));
+            entry.pass = times.working_pass.and_then(|x| projected(x + delay));
+        }
+        if i > reported_index || !has_departed {
+            match planned_departure(times) {
+                Some(x) => {
+                    // early running is lost here, as it waits for its booked time
+                    delay = std::cmp::max(delay, Duration::zero());
+                    entry.departure = projected(x + delay);
+                }
+                None => (),
+            }
+        }
+        if i > reported_index {
+            entry.delay_minutes = Some(delay.num_minutes());
+        }
+    }
+    expected
+}
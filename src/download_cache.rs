@@ -0,0 +1,209 @@
+use crate::error::Error;
+use crate::fetcher::StreamingFetcher;
+
+use async_trait::async_trait;
+
+use chrono::{DateTime, Utc};
+
+use serde::{Deserialize, Serialize};
+
+use sha2::{Digest, Sha256};
+
+use tokio::fs;
+use tokio::fs::File;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Clone, Deserialize)]
+pub struct DownloadCacheConfig {
+    directory: String,
+    keep_versions: Option<usize>,
+    offline: Option<bool>, // never fetch, just import whatever is newest in the directory
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CachedVersion {
+    pub filename: String,
+    pub fetched: DateTime<Utc>,
+    pub sha256: String,
+    pub size: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Error in download cache: {what}")]
+pub struct DownloadCacheError {
+    what: String,
+}
+
+// Keeps the last few copies of everything we download, so we can roll back to an older one or
+// import without a network connection. Each artifact has a name, and an index of its versions,
+// newest first, in <name>.json.
+pub struct DownloadCache {
+    config: DownloadCacheConfig,
+}
+
+impl DownloadCache {
+    pub fn new(config: DownloadCacheConfig) -> Self {
+        Self { config }
+    }
+
+    pub fn is_offline(&self) -> bool {
+        self.config.offline.unwrap_or(false)
+    }
+
+    fn path(&self, filename: &str) -> PathBuf {
+        PathBuf::from(&self.config.directory).join(filename)
+    }
+
+    pub async fn versions(&self, name: &str) -> Result<Vec<CachedVersion>, Error> {
+        match fs::read(self.path(&format!("{}.json", name))).await {
+            Ok(x) => Ok(serde_json::from_slice(&x)?),
+            Err(x) if x.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+            Err(x) => Err(x.into()),
+        }
+    }
+
+    async fn write_versions(&self, name: &str, versions: &Vec<CachedVersion>) -> Result<(), Error> {
+        let filename = self.path(&format!("{}.json", name));
+        let tmp_filename = self.path(&format!("{}.json.bak", name));
+        fs::write(&tmp_filename, serde_json::to_string(versions)?).await?;
+        fs::rename(tmp_filename, filename).await?;
+        Ok(())
+    }
+
+    // Copies everything from the reader into the cache as the newest version of `name`, throwing
+    // away the oldest versions if there are now too many.
+    pub async fn store(
+        &self,
+        name: &str,
+        mut reader: Box<dyn AsyncBufRead + Unpin + Send>,
+    ) -> Result<CachedVersion, Error> {
+        fs::create_dir_all(&self.config.directory).await?;
+
+        let fetched = Utc::now();
+        let filename = format!("{}-{}.dat", name, fetched.format("%Y%m%dT%H%M%S%.3f"));
+        let tmp_filename = format!("{}.part", filename);
+        let mut file = File::create(self.path(&tmp_filename)).await?;
+        let mut hasher = Sha256::new();
+        let mut size = 0;
+        loop {
+            let buf = reader.fill_buf().await?;
+            if buf.is_empty() {
+                break;
+            }
+            hasher.update(buf);
+            file.write_all(buf).await?;
+            let len = buf.len();
+            size += len as u64;
+            reader.consume(len);
+        }
+        file.flush().await?;
+        fs::rename(self.path(&tmp_filename), self.path(&filename)).await?;
+
+        let version = CachedVersion {
+            filename,
+            fetched,
+            sha256: format!("{:x}", hasher.finalize()),
+            size,
+        };
+        println!(
+            "Cached {} as {} ({} bytes, sha256 {})",
+            name, version.filename, version.size, version.sha256
+        );
+
+        let mut versions = self.versions(name).await?;
+        versions.insert(0, version.clone());
+        let keep_versions = std::cmp::max(self.config.keep_versions.unwrap_or(3), 1);
+        if versions.len() > keep_versions {
+            for old in versions.split_off(keep_versions) {
+                match fs::remove_file(self.path(&old.filename)).await {
+                    Ok(()) => (),
+                    Err(x) => println!("WARNING: Failed to remove {}: {}", old.filename, x),
+                }
+            }
+        }
+        self.write_versions(name, &versions).await?;
+
+        Ok(version)
+    }
+
+    pub async fn open(
+        &self,
+        version: &CachedVersion,
+    ) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, Error> {
+        let file = File::open(self.path(&version.filename)).await?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+
+    pub async fn latest(&self, name: &str) -> Result<CachedVersion, Error> {
+        match self.versions(name).await?.into_iter().next() {
+            Some(x) => {
+                println!("Using cached {} from {}", name, x.fetched);
+                Ok(x)
+            }
+            None => Err(Error::DownloadCacheError(DownloadCacheError {
+                what: format!("No cached copy of {}", name),
+            })),
+        }
+    }
+
+    pub async fn open_latest(
+        &self,
+        name: &str,
+    ) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, Error> {
+        let version = self.latest(name).await?;
+        self.open(&version).await
+    }
+
+    pub fn path_of(&self, version: &CachedVersion) -> PathBuf {
+        self.path(&version.filename)
+    }
+}
+
+// Wraps another fetcher so everything it fetches goes through the cache. If there is no cache
+// configured this just passes straight through.
+pub struct CachingFetcher<F: StreamingFetcher> {
+    inner: F,
+    name: String,
+    cache: Option<Arc<DownloadCache>>,
+}
+
+impl<F: StreamingFetcher> CachingFetcher<F> {
+    pub fn new(inner: F, name: &str, cache: Option<Arc<DownloadCache>>) -> Self {
+        Self {
+            inner,
+            name: name.to_string(),
+            cache,
+        }
+    }
+}
+
+#[async_trait]
+impl<F: StreamingFetcher + Send + Sync> StreamingFetcher for CachingFetcher<F> {
+    async fn fetch(&self) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, Error> {
+        let cache = match &self.cache {
+            Some(x) => x,
+            None => return self.inner.fetch().await,
+        };
+
+        if cache.is_offline() {
+            return cache.open_latest(&self.name).await;
+        }
+
+        let reader = match self.inner.fetch().await {
+            Ok(x) => x,
+            Err(x) => {
+                // better old data than none at all
+                println!(
+                    "WARNING: Failed to fetch {}, trying the cache instead: {}",
+                    self.name, x
+                );
+                return cache.open_latest(&self.name).await;
+            }
+        };
+        let version = cache.store(&self.name, reader).await?;
+        cache.open(&version).await
+    }
+}
@@ -0,0 +1,100 @@
+use crate::redaction::RedactionPolicy;
+use crate::resolved_train::resolve_train;
+use crate::schedule::Schedule;
+
+use chrono::NaiveDate;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use std::io::Write;
+use std::sync::Arc;
+
+// Every train running on a date, one JSON object per line, resolved as in resolved_train, for
+// loading the lot into pandas or DuckDB in one go instead of asking for each train separately.
+// It's made a chunk at a time as it's sent, so nothing holds the whole dump.
+
+const TRAINS_PER_CHUNK: usize = 500;
+
+// The dump itself, as the chunks of bytes to send, in namespace then train ID order
+pub struct TrainDump {
+    trains: std::vec::IntoIter<(Arc<Schedule>, String)>,
+    date: NaiveDate,
+    policy: Option<RedactionPolicy>, // what the public mustn't see, unless they're let through
+    gzip: Option<GzEncoder<Vec<u8>>>, // taken once it's finished
+    gzipped: bool,
+}
+
+impl TrainDump {
+    pub fn new(
+        mut schedules: Vec<Arc<Schedule>>,
+        date: NaiveDate,
+        policy: Option<RedactionPolicy>,
+        gzipped: bool,
+    ) -> Self {
+        schedules.sort_by(|x, y| x.namespace.cmp(&y.namespace));
+        let mut trains = vec![];
+        for schedule in schedules {
+            let mut train_ids = schedule.trains.keys().cloned().collect::<Vec<_>>();
+            train_ids.sort();
+            trains.extend(train_ids.into_iter().map(|x| (schedule.clone(), x)));
+        }
+        Self {
+            trains: trains.into_iter(),
+            date,
+            policy,
+            gzip: match gzipped {
+                true => Some(GzEncoder::new(vec![], Compression::default())),
+                false => None,
+            },
+            gzipped,
+        }
+    }
+
+    fn lines(&mut self) -> Option<Vec<u8>> {
+        let mut lines = vec![];
+        let mut trains = 0;
+        for (schedule, train_id) in self.trains.by_ref() {
+            let train = match resolve_train(&schedule, &train_id, self.date) {
+                Some(x) => x,
+                None => continue,
+            };
+            let mut value = serde_json::to_value(train).unwrap();
+            match &self.policy {
+                Some(x) if !x.apply(&mut value) => continue,
+                _ => (),
+            }
+            serde_json::to_writer(&mut lines, &value).unwrap();
+            lines.push(b'\n');
+            trains += 1;
+            if trains == TRAINS_PER_CHUNK {
+                break;
+            }
+        }
+        match trains {
+            0 => None,
+            _ => Some(lines),
+        }
+    }
+}
+
+impl Iterator for TrainDump {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Vec<u8>> {
+        let lines = self.lines();
+        if !self.gzipped {
+            return lines;
+        }
+        // flushed after every chunk, so each can go as soon as it's ready
+        let gzip = self.gzip.as_mut()?;
+        match lines {
+            Some(x) => {
+                gzip.write_all(&x).unwrap();
+                gzip.flush().unwrap();
+                Some(std::mem::take(gzip.get_mut()))
+            }
+            None => Some(self.gzip.take()?.finish().unwrap()),
+        }
+    }
+}
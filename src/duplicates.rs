@@ -0,0 +1,498 @@
+use crate::cold_routes::peek_train;
+use crate::schedule::{Schedule, Train};
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+// Some trains are in more than one feed, e.g. the Enterprise is in both the NIR CIF and the Irish
+// Rail GTFS. We can't go by IDs or location codes as each feed has its own, so two trains in
+// different namespaces are the same if they have the same UIC code and share a station, or if they
+// call at two or more stations with the same name at the same time.
+#[derive(Clone, Default, Deserialize)]
+pub struct DuplicateConfig {
+    priority: Option<Vec<String>>, // namespaces, most trusted first; the rest follow in name order
+    links: Option<Vec<Vec<LinkedTrain>>>, // each one trains known to be legs of one through service
+}
+
+// Some through trains are split at a border, each feed only having its own side, and not every
+// feed has UIC codes. These say which trains go together when nothing else does, by ID or by
+// public ID (which tends to survive new GTFS extracts better).
+#[derive(Clone, Deserialize)]
+pub struct LinkedTrain {
+    namespace: String,
+    id: Option<String>,
+    public_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct TrainRef {
+    pub namespace: String,
+    pub id: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ThroughCall {
+    pub namespace: String,
+    pub location_id: String,
+    pub name: String,
+    pub time: DateTime<Utc>, // public departure, or arrival at the end
+}
+
+// One train made out of legs from more than one namespace, with the calls of all of them in order
+#[derive(Clone, Debug, Serialize)]
+pub struct ThroughService {
+    pub legs: Vec<TrainRef>, // in the order they run, the train asked about included
+    pub calls: Vec<ThroughCall>,
+}
+
+pub struct Duplicates {
+    priority: Vec<String>,
+    links: Vec<Vec<LinkedTrain>>,
+    indexes: RwLock<HashMap<String, Arc<DuplicateIndex>>>,
+}
+
+// Looking through a whole namespace for copies of every train anyone asks about would take far
+// too long, so each one's indexed as it's imported: its stations by normalised name, and its
+// trains by the calls they make. The index is always the latest, so trains added since (e.g. by
+// VSTP) aren't found in other namespaces until the next import, though they can still find others.
+#[derive(Default)]
+struct DuplicateIndex {
+    locations_by_name: HashMap<String, Vec<String>>,
+    trains_by_call: HashMap<(String, NaiveTime), HashSet<String>>,
+}
+
+impl LinkedTrain {
+    fn matches(&self, namespace: &str, train: &Train) -> bool {
+        if self.namespace != namespace || (self.id.is_none() && self.public_id.is_none()) {
+            return false;
+        }
+        self.id.as_ref().map_or(true, |x| *x == train.id)
+            && self
+                .public_id
+                .as_ref()
+                .map_or(true, |x| Some(x) == train.variable_train.public_id.as_ref())
+    }
+}
+
+// names are about the only thing feeds agree on, and not always on capitals or punctuation
+fn normalise_name(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// every public call in order, as the location, its normalised name and when it happens
+fn ordered_public_calls(
+    schedule: &Schedule,
+    train: &Train,
+    date: NaiveDate,
+) -> Vec<(String, String, DateTime<Utc>)> {
+    let mut calls = vec![];
+    for train_location in &train.route {
+        let location = match schedule.locations.get(train_location.id.as_str()) {
+            Some(x) => x,
+            None => continue,
+        };
+        let times = train_location.local_times(date, location.timezone);
+        match times.public_dep.or(times.public_arr) {
+            Some(x) => calls.push((
+                train_location.id.to_string(),
+                normalise_name(&location.name),
+                x.with_timezone(&Utc),
+            )),
+            None => (),
+        }
+    }
+    calls
+}
+
+// Every public call as a normalised station name and the local time there, on no date in
+// particular. It's taken in winter and in summer, in case a feed times trains in a zone that
+// changes its clocks on different days to the station's.
+fn call_keys(schedule: &Schedule, train: &Train) -> HashSet<(String, NaiveTime)> {
+    let dates =
+        [(1, 15), (7, 15)].map(|(month, day)| NaiveDate::from_ymd_opt(2024, month, day).unwrap());
+    let mut keys = HashSet::new();
+    for train_location in &train.route {
+        let location = match schedule.locations.get(train_location.id.as_str()) {
+            Some(x) => x,
+            None => continue,
+        };
+        let name = normalise_name(&location.name);
+        for date in dates {
+            let times = train_location.local_times(date, location.timezone);
+            match times.public_dep.or(times.public_arr) {
+                Some(x) => {
+                    keys.insert((name.clone(), x.time()));
+                }
+                None => (),
+            }
+        }
+    }
+    keys
+}
+
+fn build_index(schedule: &Schedule) -> DuplicateIndex {
+    let mut index = DuplicateIndex::default();
+    for (location_id, location) in &schedule.locations {
+        index
+            .locations_by_name
+            .entry(normalise_name(&location.name))
+            .or_default()
+            .push(location_id.clone());
+    }
+    for (train_id, trains) in &schedule.trains {
+        for train in trains {
+            let train = peek_train(train);
+            for version in std::iter::once(&*train).chain(train.replacements.iter()) {
+                for key in call_keys(schedule, version) {
+                    index
+                        .trains_by_call
+                        .entry(key)
+                        .or_default()
+                        .insert(train_id.clone());
+                }
+            }
+        }
+    }
+    index
+}
+
+// every public call as a normalised station name and when it happens
+fn public_calls(
+    schedule: &Schedule,
+    train: &Train,
+    date: NaiveDate,
+) -> HashSet<(String, DateTime<Utc>)> {
+    let mut calls = HashSet::new();
+    for train_location in &train.route {
+        let location = match schedule.locations.get(train_location.id.as_str()) {
+            Some(x) => x,
+            None => continue,
+        };
+        let times = train_location.local_times(date, location.timezone);
+        match times.public_dep.or(times.public_arr) {
+            Some(x) => {
+                calls.insert((normalise_name(&location.name), x.with_timezone(&Utc)));
+            }
+            None => (),
+        }
+    }
+    calls
+}
+
+impl Duplicates {
+    pub fn new(config: DuplicateConfig) -> Self {
+        Self {
+            priority: config.priority.unwrap_or_default(),
+            links: config.links.unwrap_or_default(),
+            indexes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub async fn refresh(self: Arc<Self>, schedule: Arc<Schedule>) {
+        let namespace = schedule.namespace.clone();
+        let index = match tokio::task::spawn_blocking(move || build_index(&schedule)).await {
+            Ok(x) => x,
+            Err(x) => {
+                println!(
+                    "WARNING: Failed to index duplicates for {}: {}",
+                    namespace, x
+                );
+                return;
+            }
+        };
+        self.indexes
+            .write()
+            .unwrap()
+            .insert(namespace, Arc::new(index));
+    }
+
+    // built here if nothing has been imported since startup, e.g. after restoring a snapshot
+    fn index(&self, schedule: &Schedule) -> Arc<DuplicateIndex> {
+        match self.indexes.read().unwrap().get(&schedule.namespace) {
+            Some(x) => return x.clone(),
+            None => (),
+        }
+        let index = Arc::new(build_index(schedule));
+        self.indexes
+            .write()
+            .unwrap()
+            .insert(schedule.namespace.clone(), index.clone());
+        index
+    }
+
+    fn linked(&self, namespace: &str, train: &Train, other_namespace: &str, other: &Train) -> bool {
+        if train.variable_train.uic_code.is_some()
+            && train.variable_train.uic_code == other.variable_train.uic_code
+        {
+            return true;
+        }
+        self.links.iter().any(|group| {
+            group.iter().any(|x| x.matches(namespace, train))
+                && group.iter().any(|x| x.matches(other_namespace, other))
+        })
+    }
+
+    // the order to look through namespaces in, when a request doesn't say which one it wants
+    pub fn namespace_order<'a>(
+        &self,
+        namespaces: impl Iterator<Item = &'a String>,
+    ) -> Vec<&'a String> {
+        let mut namespaces = namespaces.collect::<Vec<_>>();
+        namespaces.sort_by_key(|x| {
+            (
+                self.priority
+                    .iter()
+                    .position(|y| y == *x)
+                    .unwrap_or(self.priority.len()),
+                x.to_string(),
+            )
+        });
+        namespaces
+    }
+
+    // Copies of the train in other namespaces, in priority order. `resolve` picks the version of a
+    // train that runs on the date, if any.
+    pub fn find(
+        &self,
+        schedules: &HashMap<String, Arc<Schedule>>,
+        namespace: &str,
+        train: &Train,
+        date: NaiveDate,
+        resolve: impl Fn(&Vec<Train>) -> Option<Train>,
+    ) -> Vec<TrainRef> {
+        let schedule = match schedules.get(namespace) {
+            Some(x) => x,
+            None => return vec![],
+        };
+        let calls = public_calls(schedule, train, date);
+        let names = calls.iter().map(|(name, _)| name).collect::<HashSet<_>>();
+        if names.is_empty() {
+            return vec![];
+        }
+        let keys = call_keys(schedule, train);
+
+        let mut duplicates = vec![];
+        for other_namespace in self.namespace_order(schedules.keys()) {
+            if other_namespace == namespace {
+                continue;
+            }
+            let other = schedules.get(other_namespace).unwrap();
+            let index = self.index(other);
+
+            // anything with the same UIC code, or with two calls in common on some day, is a
+            // candidate
+            let mut matches = HashMap::<&String, usize>::new();
+            for key in &keys {
+                for candidate_id in index.trains_by_call.get(key).into_iter().flatten() {
+                    *matches.entry(candidate_id).or_default() += 1;
+                }
+            }
+            let mut candidates = matches
+                .into_iter()
+                .filter(|(_, x)| *x >= 2)
+                .map(|(x, _)| x)
+                .collect::<HashSet<_>>();
+            match &train.variable_train.uic_code {
+                Some(x) => {
+                    candidates.extend(other.trains_indexed_by_uic.get(x).into_iter().flatten())
+                }
+                None => (),
+            }
+
+            let mut found = vec![];
+            for candidate_id in candidates {
+                let candidate = match other.trains.get(candidate_id).and_then(|x| resolve(x)) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                // as long as they share a station
+                let same_uic = train.variable_train.uic_code.is_some()
+                    && train.variable_train.uic_code == candidate.variable_train.uic_code
+                    && candidate.route.iter().any(|x| {
+                        other
+                            .locations
+                            .get(x.id.as_str())
+                            .is_some_and(|y| names.contains(&normalise_name(&y.name)))
+                    });
+                if same_uic
+                    || public_calls(other, &candidate, date)
+                        .intersection(&calls)
+                        .count()
+                        >= 2
+                {
+                    found.push(TrainRef {
+                        namespace: other_namespace.clone(),
+                        id: candidate_id.clone(),
+                    });
+                }
+            }
+            found.sort();
+            duplicates.extend(found);
+        }
+        duplicates
+    }
+
+    // Legs of the same through train in other namespaces, i.e. linked trains that carry on from
+    // where this one starts or ends. Anything calling at a station by the same name is a
+    // candidate, as the legs meet at the border; so are trains from the links in the config.
+    // `resolve` picks the version of a train that runs on the date, if any.
+    pub fn through_service(
+        &self,
+        schedules: &HashMap<String, Arc<Schedule>>,
+        namespace: &str,
+        train: &Train,
+        date: NaiveDate,
+        resolve: impl Fn(&Vec<Train>) -> Option<Train>,
+    ) -> Option<ThroughService> {
+        let schedule = schedules.get(namespace)?;
+        let calls = ordered_public_calls(schedule, train, date);
+        let first_time = calls.first()?.2;
+        let last_time = calls.last()?.2;
+        let names = calls
+            .iter()
+            .map(|(_, name, _)| name.clone())
+            .collect::<HashSet<_>>();
+
+        let mut legs = vec![(
+            first_time,
+            TrainRef {
+                namespace: namespace.to_string(),
+                id: train.id.clone(),
+            },
+        )];
+        let mut all_calls = calls
+            .iter()
+            .map(|(location_id, _, time)| (namespace.to_string(), location_id.clone(), *time))
+            .collect::<Vec<_>>();
+
+        for other_namespace in self.namespace_order(schedules.keys()) {
+            if other_namespace == namespace {
+                continue;
+            }
+            let other = schedules.get(other_namespace).unwrap();
+            let index = self.index(other);
+
+            let mut candidates = HashSet::new();
+            for location_id in names
+                .iter()
+                .filter_map(|x| index.locations_by_name.get(x))
+                .flatten()
+            {
+                match other.trains_indexed_by_location.get(location_id) {
+                    Some(x) => candidates.extend(x.iter()),
+                    None => (),
+                }
+            }
+            for group in &self.links {
+                if !group.iter().any(|x| x.matches(namespace, train)) {
+                    continue;
+                }
+                for linked in group {
+                    if linked.namespace != *other_namespace {
+                        continue;
+                    }
+                    match (&linked.id, &linked.public_id) {
+                        (Some(x), _) => {
+                            candidates.extend(other.trains.get_key_value(x).map(|x| x.0))
+                        }
+                        (None, Some(x)) => candidates.extend(
+                            other
+                                .trains_indexed_by_public_id
+                                .get(x)
+                                .into_iter()
+                                .flatten(),
+                        ),
+                        (None, None) => (),
+                    }
+                }
+            }
+
+            let mut candidates = candidates.into_iter().collect::<Vec<_>>();
+            candidates.sort();
+            for candidate_id in candidates {
+                let candidate = match other.trains.get(candidate_id).and_then(|x| resolve(x)) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                if !self.linked(namespace, train, other_namespace, &candidate) {
+                    continue;
+                }
+                // Only what this train doesn't already cover counts, and it all has to be off
+                // one end or the other; otherwise it's a copy or a different train altogether.
+                let extra = ordered_public_calls(other, &candidate, date)
+                    .into_iter()
+                    .filter(|(_, name, _)| !names.contains(name))
+                    .collect::<Vec<_>>();
+                if extra.is_empty()
+                    || !extra
+                        .iter()
+                        .all(|(_, _, time)| *time < first_time || *time > last_time)
+                {
+                    continue;
+                }
+                legs.push((
+                    extra[0].2,
+                    TrainRef {
+                        namespace: other_namespace.clone(),
+                        id: candidate_id.clone(),
+                    },
+                ));
+                all_calls.extend(
+                    extra
+                        .into_iter()
+                        .map(|(location_id, _, time)| (other_namespace.clone(), location_id, time)),
+                );
+            }
+        }
+
+        if legs.len() < 2 {
+            return None;
+        }
+        legs.sort();
+        all_calls.sort_by_key(|(_, _, time)| *time);
+        Some(ThroughService {
+            legs: legs.into_iter().map(|(_, x)| x).collect(),
+            calls: all_calls
+                .into_iter()
+                .map(|(namespace, location_id, time)| {
+                    let name = schedules
+                        .get(&namespace)
+                        .and_then(|x| x.locations.get(&location_id))
+                        .map(|x| x.name.clone())
+                        .unwrap_or_else(|| location_id.clone());
+                    ThroughCall {
+                        namespace,
+                        location_id,
+                        name,
+                        time,
+                    }
+                })
+                .collect(),
+        })
+    }
+
+    // the copy people should be shown, if it isn't this one
+    pub fn preferred<'a>(
+        &self,
+        namespace: &str,
+        duplicates: &'a [TrainRef],
+    ) -> Option<&'a TrainRef> {
+        let rank = |x: &str| {
+            self.priority
+                .iter()
+                .position(|y| y == x)
+                .unwrap_or(self.priority.len())
+        };
+        duplicates
+            .first()
+            .filter(|x| (rank(&x.namespace), &x.namespace[..]) < (rank(namespace), namespace))
+    }
+}
@@ -0,0 +1,269 @@
+use crate::schedule::{
+    get_association, get_train_version, operating_dates_between, Schedule, Train,
+};
+use crate::train_id::GlobalTrainId;
+
+use chrono::{DateTime, Days, NaiveDate};
+use chrono_tz::Tz;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashSet;
+
+#[derive(Clone, Default, Deserialize)]
+pub struct DwellConfig {
+    bucket_secs: Option<i64>, // width of the histogram buckets; CIF times are to the half minute
+    max_days: Option<u64>,    // longest range one request can ask about
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DwellCall {
+    pub global_id: GlobalTrainId,
+    pub train_id: String,
+    pub public_id: Option<String>,
+    pub date: NaiveDate, // that the train starts on
+    pub location_id: String,
+    pub arrival: DateTime<Tz>,
+    pub departure: DateTime<Tz>,
+    pub dwell_secs: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Turnaround {
+    pub global_id: GlobalTrainId, // of the one terminating here
+    pub train_id: String,
+    pub date: NaiveDate,
+    pub forms_global_id: GlobalTrainId,
+    pub forms_train_id: String,
+    pub forms_date: NaiveDate,
+    pub location_id: String,
+    pub arrival: DateTime<Tz>,
+    pub departure: DateTime<Tz>,
+    pub turnaround_secs: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DwellBucket {
+    pub from_secs: i64, // up to but not including the next bucket's
+    pub count: usize,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DwellDistribution {
+    pub count: usize,
+    pub min_secs: Option<i64>,
+    pub median_secs: Option<i64>,
+    pub mean_secs: Option<f64>,
+    pub max_secs: Option<i64>,
+    pub buckets: Vec<DwellBucket>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DwellReport {
+    pub namespace: String,
+    pub location_ids: Vec<String>,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub bucket_secs: i64,
+    pub calls: Vec<DwellCall>, // in order of arrival
+    pub turnarounds: Vec<Turnaround>,
+    pub dwell: DwellDistribution,
+    pub turnaround: DwellDistribution,
+}
+
+pub struct Dwell {
+    bucket_secs: i64,
+    max_days: u64,
+}
+
+fn distribution(mut secs: Vec<i64>, bucket_secs: i64) -> DwellDistribution {
+    if secs.is_empty() {
+        return DwellDistribution::default();
+    }
+    secs.sort();
+    let mut buckets: Vec<DwellBucket> = vec![];
+    for x in &secs {
+        let from_secs = x.div_euclid(bucket_secs) * bucket_secs;
+        match buckets.last_mut() {
+            Some(bucket) if bucket.from_secs == from_secs => bucket.count += 1,
+            _ => buckets.push(DwellBucket {
+                from_secs,
+                count: 1,
+            }),
+        }
+    }
+    DwellDistribution {
+        count: secs.len(),
+        min_secs: secs.first().copied(),
+        median_secs: Some(secs[secs.len() / 2]),
+        mean_secs: Some(secs.iter().sum::<i64>() as f64 / secs.len() as f64),
+        max_secs: secs.last().copied(),
+        buckets,
+    }
+}
+
+// the version of a train that runs on a date, unless it doesn't or is cancelled
+fn running_train(trains: &[Train], date: NaiveDate) -> Option<&Train> {
+    match get_train_version(trains, date)? {
+        (base, Some(overlay), false) => Some(&trains[base].replacements[overlay]),
+        (base, None, false) => Some(&trains[base]),
+        _ => None,
+    }
+}
+
+impl Dwell {
+    pub fn new(config: DwellConfig) -> Self {
+        Self {
+            bucket_secs: config.bucket_secs.unwrap_or(30).max(1),
+            max_days: config.max_days.unwrap_or(31),
+        }
+    }
+
+    // How long trains stand at the locations on the dates, going by working times where there
+    // are any, and how long trains that terminate there take to form their next working. Times
+    // are worked out as real instants, so day offsets, midnight and clock changes all come out
+    // right, and half minutes are kept.
+    pub fn report(
+        &self,
+        schedule: &Schedule,
+        location_ids: &HashSet<String>,
+        from: NaiveDate,
+        days: Option<u64>,
+    ) -> DwellReport {
+        let days = days.unwrap_or(1).clamp(1, self.max_days);
+        let to = from + Days::new(days - 1);
+        let mut calls = vec![];
+        let mut turnarounds = vec![];
+
+        let mut train_ids = HashSet::new();
+        for location_id in location_ids {
+            match schedule.trains_indexed_by_location.get(location_id) {
+                Some(x) => train_ids.extend(x.iter()),
+                None => (),
+            }
+        }
+
+        for train_id in train_ids {
+            let versions = match schedule.trains.get(train_id) {
+                Some(x) if !x.is_empty() => x,
+                _ => continue,
+            };
+            // trains that started a day or two before can still be here
+            for date in operating_dates_between(versions, from, to) {
+                let train = match running_train(versions, date) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                for (i, location) in train.route.iter().enumerate() {
+                    if !location_ids.contains(location.id.as_str()) {
+                        continue;
+                    }
+                    let timezone = match schedule.locations.get(location.id.as_str()) {
+                        Some(x) => x.timezone,
+                        None => continue,
+                    };
+                    let times = location.local_times(date, timezone);
+                    let arrival = times.working_arr.or(times.public_arr);
+                    let arrival = match arrival {
+                        Some(x) if x.date_naive() >= from && x.date_naive() <= to => x,
+                        _ => continue,
+                    };
+
+                    match times.working_dep.or(times.public_dep) {
+                        Some(departure) => calls.push(DwellCall {
+                            global_id: GlobalTrainId::new(&schedule.namespace, &train.id, date),
+                            train_id: train.id.clone(),
+                            public_id: train.variable_train.public_id.clone(),
+                            date,
+                            location_id: location.id.to_string(),
+                            arrival,
+                            departure,
+                            dwell_secs: (departure - arrival).num_seconds(),
+                        }),
+                        None => (),
+                    }
+
+                    // only the end of the line counts as turning round
+                    if i + 1 != train.route.len() {
+                        continue;
+                    }
+                    let association = match location
+                        .becomes
+                        .as_ref()
+                        .and_then(|x| get_association(x, date))
+                    {
+                        Some(x) => x,
+                        None => continue,
+                    };
+                    let forms_date = match association.day_diff {
+                        x if x >= 0 => date.checked_add_days(Days::new(x as u64)),
+                        x => date.checked_sub_days(Days::new(x.unsigned_abs().into())),
+                    };
+                    let forms_date = match forms_date {
+                        Some(x) => x,
+                        None => continue,
+                    };
+                    let forms = match schedule
+                        .trains
+                        .get(association.other_train_id.as_str())
+                        .and_then(|x| running_train(x, forms_date))
+                    {
+                        Some(x) => x,
+                        None => continue,
+                    };
+                    let departure = match forms.route.first() {
+                        Some(x) if x.id == location.id => {
+                            let times = x.local_times(forms_date, timezone);
+                            times.working_dep.or(times.public_dep)
+                        }
+                        _ => None,
+                    };
+                    match departure {
+                        Some(departure) => turnarounds.push(Turnaround {
+                            global_id: GlobalTrainId::new(&schedule.namespace, &train.id, date),
+                            train_id: train.id.clone(),
+                            date,
+                            forms_global_id: GlobalTrainId::new(
+                                &schedule.namespace,
+                                &forms.id,
+                                forms_date,
+                            ),
+                            forms_train_id: forms.id.clone(),
+                            forms_date,
+                            location_id: location.id.to_string(),
+                            arrival,
+                            departure,
+                            turnaround_secs: (departure - arrival).num_seconds(),
+                        }),
+                        None => (),
+                    }
+                }
+            }
+        }
+
+        calls.sort_by(|a, b| a.arrival.cmp(&b.arrival).then(a.train_id.cmp(&b.train_id)));
+        turnarounds.sort_by(|a, b| a.arrival.cmp(&b.arrival).then(a.train_id.cmp(&b.train_id)));
+        let dwell = distribution(
+            calls.iter().map(|x| x.dwell_secs).collect(),
+            self.bucket_secs,
+        );
+        let turnaround = distribution(
+            turnarounds.iter().map(|x| x.turnaround_secs).collect(),
+            self.bucket_secs,
+        );
+
+        let mut location_ids = location_ids.iter().cloned().collect::<Vec<_>>();
+        location_ids.sort();
+        DwellReport {
+            namespace: schedule.namespace.clone(),
+            location_ids,
+            from,
+            to,
+            bucket_secs: self.bucket_secs,
+            calls,
+            turnarounds,
+            dwell,
+            turnaround,
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use crate::schedule_manager::ScheduleManager;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+// Other sets of schedules run alongside the main one, each from its own feeds, e.g. a trial feed
+// to check against production on the same instance. The web UI answers from one of them if asked
+// with an X-Environment header, or under /env/<name>/; otherwise from the main one.
+
+pub const MAIN_ENVIRONMENT: &str = "production";
+
+pub struct Environments {
+    main: Arc<ScheduleManager>,
+    others: BTreeMap<String, Arc<ScheduleManager>>,
+}
+
+impl Environments {
+    pub fn new(main: Arc<ScheduleManager>) -> Self {
+        Self {
+            main,
+            others: BTreeMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, name: String, schedule_manager: Arc<ScheduleManager>) {
+        self.others.insert(name, schedule_manager);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<ScheduleManager>> {
+        match name {
+            MAIN_ENVIRONMENT => Some(&self.main),
+            x => self.others.get(x),
+        }
+    }
+
+    pub fn main(&self) -> &Arc<ScheduleManager> {
+        &self.main
+    }
+
+    // not including the main one
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.others.keys()
+    }
+}
+
+// The environment a path is under, if it's under /env/<name>/, and the rest of it as it would be
+// at the root, e.g. /location/EUSTON for /env/test/location/EUSTON. Anything that goes by the
+// endpoint should look at the rest, so an environment's responses are treated as the main one's.
+pub fn split_path(path: &str) -> (Option<&str>, &str) {
+    match path.strip_prefix("/env/") {
+        Some(x) => match x.find('/') {
+            Some(i) => (Some(&x[..i]), &x[i..]),
+            None => (Some(x), "/"),
+        },
+        None => (None, path),
+    }
+}
+
+// The environment a request is for, from its X-Environment header and its path, or an error if
+// they name different ones
+pub fn requested<'a>(header: Option<&'a str>, path: &'a str) -> Result<&'a str, String> {
+    match (header, split_path(path).0) {
+        (Some(x), Some(y)) if x != y => Err(format!(
+            "X-Environment {} doesn't match the environment {} in the path",
+            x, y
+        )),
+        (Some(x), _) => Ok(x),
+        (None, Some(y)) => Ok(y),
+        (None, None) => Ok(MAIN_ENVIRONMENT),
+    }
+}
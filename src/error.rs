@@ -1,6 +1,9 @@
+use crate::download_cache::DownloadCacheError;
 use crate::gtfs_importer::GtfsImportError;
 use crate::nir_fetcher::{CkanError, NirFetcherError};
+use crate::nr_fetcher::NrFetcherError;
 use crate::nr_vstp_subscriber::NrVstpError;
+use crate::snapshot::SnapshotError;
 use crate::sncf_fetcher::SncfFetcherError;
 use crate::uk_importer::{CifError, NrJsonError};
 use crate::webui::WebUiError;
@@ -10,145 +13,107 @@ use rc_zip_tokio::rc_zip::error::Error as RcZipError;
 use reqwest;
 use tokio::task::JoinError;
 
-use std::fmt;
+use std::backtrace::Backtrace;
+use std::io::ErrorKind;
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
 pub enum Error {
-    ConfigFileError(ConfigFileError),
-    HttpRequestError(reqwest::Error),
-    IoError(std::io::Error),
-    CifError(CifError),
-    NrJsonError(NrJsonError),
-    AnyhowError(anyhow::Error),
-    NrVstpError(NrVstpError),
-    SerdeJsonError(serde_json::Error),
-    RocketError(rocket::Error),
-    WebUiError(WebUiError),
-    RcZipError(RcZipError),
-    GtfsError(gtfs_structures::error::Error),
-    JoinError(JoinError),
-    GtfsImportError(GtfsImportError),
-    SncfFetcherError(SncfFetcherError),
-    CkanError(CkanError),
-    NirFetcherError(NirFetcherError),
-}
-
-impl fmt::Display for Error {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    #[error("{0}")]
+    ConfigFileError(#[from] ConfigFileError),
+    #[error("{0}")]
+    HttpRequestError(#[from] reqwest::Error),
+    #[error("{0}")]
+    IoError(#[from] std::io::Error),
+    #[error("{0}")]
+    CifError(#[from] CifError),
+    #[error("{0}")]
+    NrJsonError(#[from] NrJsonError),
+    #[error(transparent)]
+    AnyhowError(#[from] anyhow::Error),
+    #[error("{0}")]
+    NrVstpError(#[from] NrVstpError),
+    #[error("{0}")]
+    SerdeJsonError(#[from] serde_json::Error),
+    #[error("{}", .0.pretty_print())]
+    RocketError(#[from] rocket::Error),
+    #[error("{0}")]
+    WebUiError(#[from] WebUiError),
+    #[error("{0}")]
+    RcZipError(#[from] RcZipError),
+    #[error("{0}")]
+    GtfsError(#[from] gtfs_structures::error::Error),
+    #[error("{0}")]
+    JoinError(#[from] JoinError),
+    #[error("{0}")]
+    GtfsImportError(#[from] GtfsImportError),
+    #[error("{0}")]
+    SncfFetcherError(#[from] SncfFetcherError),
+    #[error("{0}")]
+    CkanError(#[from] CkanError),
+    #[error("{0}")]
+    NirFetcherError(#[from] NirFetcherError),
+    #[error("{0}")]
+    NrFetcherError(#[from] NrFetcherError),
+    #[error("{0}")]
+    DownloadCacheError(#[from] DownloadCacheError),
+    #[error("{0}")]
+    BincodeError(#[from] bincode::Error),
+    #[error("{0}")]
+    SnapshotError(#[from] SnapshotError),
+    // which importer/fetcher/subscriber it came from, and where
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        source: Box<Error>,
+        backtrace: Box<Backtrace>, // boxed to keep Error small
+    },
+}
+
+impl Error {
+    // Whether it's worth starting the manager again, i.e. the network or an upstream service
+    // had a moment, as opposed to us or the data being broken
+    pub fn is_retryable(&self) -> bool {
         match self {
-            Error::ConfigFileError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::HttpRequestError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::IoError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::CifError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::NrJsonError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::AnyhowError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::NrVstpError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::SerdeJsonError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::RocketError(x) => write!(f, "WorldRailTimetables error: {}", x.pretty_print()),
-            Error::WebUiError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::RcZipError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::GtfsError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::JoinError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::GtfsImportError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::SncfFetcherError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::CkanError(x) => write!(f, "WorldRailTimetables error: {}", x),
-            Error::NirFetcherError(x) => write!(f, "WorldRailTimetables error: {}", x),
+            Error::HttpRequestError(_) => true,
+            Error::IoError(x) => matches!(
+                x.kind(),
+                ErrorKind::ConnectionRefused
+                    | ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::NotConnected
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::TimedOut
+                    | ErrorKind::Interrupted
+                    | ErrorKind::UnexpectedEof
+                    | ErrorKind::Other // how fetchers pass on errors from the HTTP stream
+            ),
+            Error::NrVstpError(_) => true,
+            Error::RcZipError(_) => true, // usually a truncated download
+            Error::CkanError(_) => true,
+            Error::Context { source, .. } => source.is_retryable(),
+            _ => false,
         }
     }
-}
-
-impl From<ConfigFileError> for Error {
-    fn from(error: ConfigFileError) -> Self {
-        Error::ConfigFileError(error)
-    }
-}
-
-impl From<reqwest::Error> for Error {
-    fn from(error: reqwest::Error) -> Self {
-        Error::HttpRequestError(error)
-    }
-}
-
-impl From<std::io::Error> for Error {
-    fn from(error: std::io::Error) -> Self {
-        Error::IoError(error)
-    }
-}
 
-impl From<CifError> for Error {
-    fn from(error: CifError) -> Self {
-        Error::CifError(error)
-    }
-}
-
-impl From<NrJsonError> for Error {
-    fn from(error: NrJsonError) -> Self {
-        Error::NrJsonError(error)
-    }
-}
-
-impl From<anyhow::Error> for Error {
-    fn from(error: anyhow::Error) -> Self {
-        Error::AnyhowError(error)
-    }
-}
-
-impl From<NrVstpError> for Error {
-    fn from(error: NrVstpError) -> Self {
-        Error::NrVstpError(error)
-    }
-}
-
-impl From<serde_json::Error> for Error {
-    fn from(error: serde_json::Error) -> Self {
-        Error::SerdeJsonError(error)
-    }
-}
-
-impl From<rocket::Error> for Error {
-    fn from(error: rocket::Error) -> Self {
-        Error::RocketError(error)
-    }
-}
-
-impl From<RcZipError> for Error {
-    fn from(error: RcZipError) -> Self {
-        Error::RcZipError(error)
-    }
-}
-
-impl From<gtfs_structures::error::Error> for Error {
-    fn from(error: gtfs_structures::error::Error) -> Self {
-        Error::GtfsError(error)
-    }
-}
-
-impl From<JoinError> for Error {
-    fn from(error: JoinError) -> Self {
-        Error::JoinError(error)
-    }
-}
-
-impl From<GtfsImportError> for Error {
-    fn from(error: GtfsImportError) -> Self {
-        Error::GtfsImportError(error)
-    }
-}
-
-impl From<SncfFetcherError> for Error {
-    fn from(error: SncfFetcherError) -> Self {
-        Error::SncfFetcherError(error)
+    // only captured if RUST_BACKTRACE is set
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        match self {
+            Error::Context { backtrace, .. } => Some(backtrace),
+            _ => None,
+        }
     }
 }
 
-impl From<CkanError> for Error {
-    fn from(error: CkanError) -> Self {
-        Error::CkanError(error)
-    }
+pub trait ErrorContext<T> {
+    fn context(self, context: &str) -> Result<T, Error>;
 }
 
-impl From<NirFetcherError> for Error {
-    fn from(error: NirFetcherError) -> Self {
-        Error::NirFetcherError(error)
+impl<T, E: Into<Error>> ErrorContext<T> for Result<T, E> {
+    fn context(self, context: &str) -> Result<T, Error> {
+        self.map_err(|x| Error::Context {
+            context: context.to_string(),
+            source: Box::new(x.into()),
+            backtrace: Box::new(Backtrace::capture()),
+        })
     }
 }
@@ -0,0 +1,76 @@
+use chrono::{DateTime, Utc};
+
+use serde::Serialize;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+
+// How messages from the realtime feeds have been parsing since startup, by source name as for
+// staleness (e.g. "gbnr-vstp"). Feeds gain fields now and then without warning; rather than
+// rejecting the messages, we count the fields we don't know, so someone can see there's something
+// new to support. Messages that can't be read at all are quarantined by their importer. For feeds
+// we hold a connection open to, this also keeps whether it's up, for the readiness check.
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct SourceTelemetry {
+    pub messages: u64,
+    pub unknown_fields: BTreeMap<String, u64>, // by path, e.g. "schedule.new_field"; once a message
+    pub quarantined: u64,
+    pub last_quarantined: Option<DateTime<Utc>>,
+    pub last_error: Option<String>, // why the last one was quarantined
+    pub connected: Option<bool>,    // for feeds we hold a connection open to, e.g. STOMP
+    pub connection_changed: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+pub struct FeedTelemetry {
+    sources: Mutex<HashMap<String, SourceTelemetry>>,
+}
+
+impl FeedTelemetry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // a message that was read, with any fields in it we don't know
+    pub fn message(&self, source: &str, unknown_fields: &[String]) {
+        let mut sources = self.sources.lock().unwrap();
+        let telemetry = sources.entry(source.to_string()).or_default();
+        telemetry.messages += 1;
+        for field in unknown_fields {
+            let count = telemetry.unknown_fields.entry(field.clone()).or_default();
+            if *count == 0 {
+                println!("WARNING: New field {} in {}", field, source);
+            }
+            *count += 1;
+        }
+    }
+
+    pub fn quarantined(&self, source: &str, error: String) {
+        let mut sources = self.sources.lock().unwrap();
+        let telemetry = sources.entry(source.to_string()).or_default();
+        telemetry.messages += 1;
+        telemetry.quarantined += 1;
+        telemetry.last_quarantined = Some(Utc::now());
+        telemetry.last_error = Some(error);
+    }
+
+    // whether a feed's connection is up, as it goes up or down
+    pub fn connection(&self, source: &str, connected: bool) {
+        let mut sources = self.sources.lock().unwrap();
+        let telemetry = sources.entry(source.to_string()).or_default();
+        if telemetry.connected != Some(connected) {
+            telemetry.connected = Some(connected);
+            telemetry.connection_changed = Some(Utc::now());
+        }
+    }
+
+    pub fn get(&self) -> BTreeMap<String, SourceTelemetry> {
+        self.sources
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(source, telemetry)| (source.clone(), telemetry.clone()))
+            .collect()
+    }
+}
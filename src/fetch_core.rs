@@ -0,0 +1,233 @@
+use crate::error::Error;
+
+use chrono::{DateTime, Utc};
+
+use futures::stream::TryStreamExt;
+
+use rand::Rng;
+
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, Proxy, RequestBuilder, Response, StatusCode};
+
+use serde::{Deserialize, Serialize};
+
+use tokio::io::{AsyncBufRead, BufReader};
+use tokio_util::io::StreamReader;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// The one HTTP client everything that downloads timetables goes through, so timeouts, retries,
+// proxying and the user agent are set in one place, and so we can see how each source's downloads
+// are going. Only getting the response is retried; a download that breaks part way through fails
+// like any other error, and the manager's supervisor deals with it.
+
+#[derive(Clone, Deserialize)]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct HttpConfig {
+    timeout_secs: Option<u64>, // for the whole download; default an hour
+    connect_timeout_secs: Option<u64>, // default 30
+    retries: Option<u32>,      // default 3
+    retry_delay_secs: Option<u64>, // default 5, doubled each time, plus jitter
+    max_retry_after_secs: Option<u64>, // give up if told to wait longer; default 600
+    proxy: Option<String>,     // e.g. http://proxy.example.com:3128
+    user_agent: Option<String>,
+    auth: Option<HashMap<String, BasicAuth>>, // by source, for feeds that need logging in to
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DownloadMetrics {
+    pub source: String,
+    pub requests: u64,
+    pub retries: u64,
+    pub failures: u64,     // given up on after retrying
+    pub rate_limited: u64, // 429s
+    pub bytes: u64,        // of response bodies, as far as they were read
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_failure: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub last_response_ms: Option<u64>, // until the headers came back
+}
+
+type MetricsBySource = Arc<Mutex<HashMap<String, DownloadMetrics>>>;
+
+fn record(metrics: &MetricsBySource, source: &str, f: impl FnOnce(&mut DownloadMetrics)) {
+    let mut metrics = metrics.lock().unwrap();
+    f(metrics
+        .entry(source.to_string())
+        .or_insert_with(|| DownloadMetrics {
+            source: source.to_string(),
+            ..Default::default()
+        }))
+}
+
+pub struct HttpClient {
+    client: Client,
+    retries: u32,
+    retry_delay: Duration,
+    max_retry_after: Duration,
+    auth: HashMap<String, BasicAuth>,
+    metrics: MetricsBySource,
+}
+
+impl HttpClient {
+    pub fn new(config: HttpConfig) -> Result<Self, Error> {
+        let mut builder = Client::builder()
+            .timeout(Duration::from_secs(config.timeout_secs.unwrap_or(3600)))
+            .connect_timeout(Duration::from_secs(
+                config.connect_timeout_secs.unwrap_or(30),
+            ))
+            .user_agent(config.user_agent.unwrap_or_else(|| {
+                concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).to_string()
+            }));
+        match config.proxy {
+            Some(x) => builder = builder.proxy(Proxy::all(x)?),
+            None => (),
+        }
+        Ok(Self {
+            client: builder.build()?,
+            retries: config.retries.unwrap_or(3),
+            retry_delay: Duration::from_secs(config.retry_delay_secs.unwrap_or(5)),
+            max_retry_after: Duration::from_secs(config.max_retry_after_secs.unwrap_or(600)),
+            auth: config.auth.unwrap_or_default(),
+            metrics: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    // Credentials from the config take precedence over whatever the fetcher was given.
+    pub async fn get(
+        &self,
+        source: &str,
+        url: &str,
+        auth: Option<&BasicAuth>,
+    ) -> Result<Response, Error> {
+        let auth = self.auth.get(source).or(auth);
+        self.send(source, || {
+            let request = self.client.get(url);
+            match auth {
+                Some(x) => request.basic_auth(&x.username, Some(&x.password)),
+                None => request,
+            }
+        })
+        .await
+    }
+
+    // for requests that need more than get() does, e.g. logging in
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub async fn send(
+        &self,
+        source: &str,
+        request: impl Fn() -> RequestBuilder,
+    ) -> Result<Response, Error> {
+        let mut attempt = 0;
+        loop {
+            let started = Instant::now();
+            record(&self.metrics, source, |x| x.requests += 1);
+            let result = request().send().await;
+            let retry_after = match &result {
+                Ok(x) => retry_after(x),
+                Err(_) => None,
+            };
+            let result = result.and_then(|x| x.error_for_status());
+            if result.as_ref().err().and_then(|x| x.status()) == Some(StatusCode::TOO_MANY_REQUESTS)
+            {
+                record(&self.metrics, source, |x| x.rate_limited += 1);
+            }
+            // other client errors, e.g. a bad password, won't fix themselves, and if we've been
+            // told to go away for ages the supervisor can wait instead
+            let retryable = match &result {
+                Ok(_) => false,
+                Err(x) => {
+                    x.status().map_or(true, |x| {
+                        x.is_server_error() || x == StatusCode::TOO_MANY_REQUESTS
+                    }) && retry_after.map_or(true, |x| x <= self.max_retry_after)
+                }
+            };
+            match result {
+                Ok(response) => {
+                    record(&self.metrics, source, |x| {
+                        x.last_success = Some(Utc::now());
+                        x.last_response_ms = Some(started.elapsed().as_millis() as u64);
+                    });
+                    return Ok(response);
+                }
+                Err(e) if retryable && attempt < self.retries => {
+                    // the jitter stops everything hammering a feed the moment it comes back
+                    let delay = match retry_after {
+                        Some(x) => x,
+                        None => {
+                            let delay = self.retry_delay * 2u32.pow(attempt);
+                            delay + delay.mul_f64(rand::thread_rng().gen::<f64>())
+                        }
+                    };
+                    attempt += 1;
+                    println!(
+                        "WARNING: Fetching from {} failed, retrying in {}s: {}",
+                        source,
+                        delay.as_secs(),
+                        e
+                    );
+                    record(&self.metrics, source, |x| x.retries += 1);
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => {
+                    record(&self.metrics, source, |x| {
+                        x.failures += 1;
+                        x.last_failure = Some(Utc::now());
+                        x.last_error = Some(e.to_string());
+                    });
+                    return Err(e.into());
+                }
+            }
+        }
+    }
+
+    // the body as it arrives, counting the bytes read against the source
+    pub fn reader(&self, source: &str, response: Response) -> Box<dyn AsyncBufRead + Unpin + Send> {
+        let metrics = self.metrics.clone();
+        let source = source.to_string();
+        Box::new(BufReader::new(StreamReader::new(
+            response
+                .bytes_stream()
+                .inspect_ok(move |x| record(&metrics, &source, |y| y.bytes += x.len() as u64))
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e)),
+        )))
+    }
+
+    pub fn metrics(&self) -> Vec<DownloadMetrics> {
+        let mut metrics = self
+            .metrics
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        metrics.sort_by(|x, y| x.source.cmp(&y.source));
+        metrics
+    }
+}
+
+// Retry-After is either a number of seconds or an HTTP date
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    match value.parse::<u64>() {
+        Ok(x) => Some(Duration::from_secs(x)),
+        Err(_) => {
+            let at = DateTime::parse_from_rfc2822(value).ok()?;
+            Some(
+                (at.with_timezone(&Utc) - Utc::now())
+                    .to_std()
+                    .unwrap_or_default(),
+            )
+        }
+    }
+}
@@ -6,11 +6,13 @@ use tokio::io::AsyncBufRead;
 
 use gtfs_structures::Gtfs;
 
+/// Fetches a complete text feed from somewhere, ready to be handed to a SlowStreamingImporter.
 #[async_trait]
 pub trait StreamingFetcher {
     async fn fetch(&self) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, Error>;
 }
 
+/// Fetches and parses a complete GTFS feed, ready to be handed to a SlowGtfsImporter.
 #[async_trait]
 pub trait GtfsFetcher {
     async fn fetch(&self) -> Result<Gtfs, Error>;
@@ -0,0 +1,29 @@
+use crate::error::Error;
+use crate::fetcher::StreamingFetcher;
+
+use async_trait::async_trait;
+
+use tokio::fs::File;
+use tokio::io::AsyncBufRead;
+use tokio::io::BufReader;
+
+pub struct FileFetcher {
+    filename: String,
+}
+
+impl FileFetcher {
+    pub fn new(filename: &str) -> Self {
+        Self {
+            filename: filename.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl StreamingFetcher for FileFetcher {
+    async fn fetch(&self) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, Error> {
+        println!("Reading {}", self.filename);
+        let file = File::open(&self.filename).await?;
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
@@ -0,0 +1,265 @@
+use crate::cold_routes::peek_train;
+use crate::intern::IStr;
+use crate::schedule::{get_train_version, operating_dates_between, Schedule, Train};
+
+use chrono::{Days, NaiveDate, Timelike};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+#[derive(Clone, Default, Deserialize)]
+pub struct FlowsConfig {
+    max_days: Option<u64>, // longest range one request can ask about
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct FlowCounts {
+    pub total: usize,
+    pub by_operator: BTreeMap<String, usize>, // by operator ID, "unknown" where there isn't one
+}
+
+impl FlowCounts {
+    fn add(&mut self, operator: &str) {
+        self.total += 1;
+        *self.by_operator.entry(operator.to_string()).or_default() += 1;
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct DailyFlow {
+    pub date: NaiveDate,
+    #[serde(flatten)]
+    pub counts: FlowCounts,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct HourlyFlow {
+    pub hour: u32, // of departure from the origin, local time, over the whole range
+    #[serde(flatten)]
+    pub counts: FlowCounts,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FlowReport {
+    pub namespace: String,
+    pub from_location_ids: Vec<String>,
+    pub to_location_ids: Vec<String>,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    #[serde(flatten)]
+    pub counts: FlowCounts,
+    pub by_date: Vec<DailyFlow>,
+    pub by_hour: Vec<HourlyFlow>,
+}
+
+// Which trains might run directly from one location to another, by origin then destination. Any
+// version of a train counts, so this only narrows down what's worth looking at on each date.
+type OriginDestinationIndex = HashMap<IStr, HashMap<IStr, Vec<IStr>>>;
+
+fn index_train(train: &Train, pairs: &mut HashSet<(IStr, IStr)>) {
+    for (i, origin) in train.route.iter().enumerate() {
+        if origin.public_dep.is_none() {
+            continue;
+        }
+        for destination in &train.route[i + 1..] {
+            if destination.public_arr.is_some() {
+                pairs.insert((origin.id.clone(), destination.id.clone()));
+            }
+        }
+    }
+    for replacement in &train.replacements {
+        index_train(replacement, pairs);
+    }
+}
+
+fn build_index(schedule: &Schedule) -> OriginDestinationIndex {
+    let mut index = OriginDestinationIndex::new();
+    for (train_id, trains) in &schedule.trains {
+        let mut pairs = HashSet::new();
+        for train in trains {
+            index_train(&peek_train(train), &mut pairs);
+        }
+        let train_id = IStr::from(train_id);
+        for (origin, destination) in pairs {
+            index
+                .entry(origin)
+                .or_default()
+                .entry(destination)
+                .or_default()
+                .push(train_id.clone());
+        }
+    }
+    index
+}
+
+// Counts of direct passenger services between two places, for comparing service levels. Working
+// them out means going through every train that could run on every date, so the candidates are
+// indexed per namespace ahead of time and the index is rebuilt after each import.
+pub struct Flows {
+    max_days: u64,
+    indexes: RwLock<HashMap<String, Arc<OriginDestinationIndex>>>,
+}
+
+impl Flows {
+    pub fn new(config: FlowsConfig) -> Self {
+        Self {
+            max_days: config.max_days.unwrap_or(400),
+            indexes: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub async fn refresh(self: Arc<Self>, schedule: Arc<Schedule>) {
+        let namespace = schedule.namespace.clone();
+        let index = match tokio::task::spawn_blocking(move || build_index(&schedule)).await {
+            Ok(x) => x,
+            Err(x) => {
+                println!("WARNING: Failed to index flows for {}: {}", namespace, x);
+                return;
+            }
+        };
+        self.indexes
+            .write()
+            .unwrap()
+            .insert(namespace, Arc::new(index));
+    }
+
+    // built here if nothing has been imported since startup, e.g. after restoring a snapshot
+    fn index(&self, schedule: &Schedule) -> Arc<OriginDestinationIndex> {
+        match self.indexes.read().unwrap().get(&schedule.namespace) {
+            Some(x) => return x.clone(),
+            None => (),
+        }
+        let index = Arc::new(build_index(schedule));
+        self.indexes
+            .write()
+            .unwrap()
+            .insert(schedule.namespace.clone(), index.clone());
+        index
+    }
+
+    // Everything leaving any of the from locations on a date in the range (inclusive) and later
+    // arriving at any of the to locations, each train counted once per day. Dates and hours are
+    // those of departure from the origin. The index is always the latest, so for an older schedule
+    // trains that have since gone away entirely are missed.
+    pub fn report(
+        &self,
+        schedule: &Schedule,
+        from_location_ids: &HashSet<String>,
+        to_location_ids: &HashSet<String>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> FlowReport {
+        let to = std::cmp::min(to, from + Days::new(self.max_days));
+        let index = self.index(schedule);
+
+        let mut train_ids = HashSet::new();
+        for origin in from_location_ids {
+            let destinations = match index.get(origin.as_str()) {
+                Some(x) => x,
+                None => continue,
+            };
+            for destination in to_location_ids {
+                match destinations.get(destination.as_str()) {
+                    Some(x) => train_ids.extend(x.iter()),
+                    None => (),
+                }
+            }
+        }
+
+        let mut counts = FlowCounts::default();
+        let mut by_date = BTreeMap::new();
+        let mut by_hour = BTreeMap::new();
+        for train_id in train_ids {
+            let trains = match schedule.trains.get(train_id.as_str()) {
+                Some(x) if !x.is_empty() => x,
+                _ => continue,
+            };
+            // trains that started a day or two before can still leave in the range
+            for date in operating_dates_between(trains, from, to) {
+                let train = match get_train_version(trains, date) {
+                    Some((base, Some(overlay), false)) => &trains[base].replacements[overlay],
+                    Some((base, None, false)) => &trains[base],
+                    _ => continue,
+                };
+                let origin = match train.route.iter().position(|x| {
+                    x.public_dep.is_some() && from_location_ids.contains(x.id.as_str())
+                }) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                if !train.route[origin + 1..]
+                    .iter()
+                    .any(|x| x.public_arr.is_some() && to_location_ids.contains(x.id.as_str()))
+                {
+                    continue;
+                }
+                let location = &train.route[origin];
+                let timezone = match schedule.locations.get(location.id.as_str()) {
+                    Some(x) => x.timezone,
+                    None => continue,
+                };
+                let departure = match location.local_times(date, timezone).public_dep {
+                    Some(x) => x,
+                    None => continue,
+                };
+                if departure.date_naive() < from || departure.date_naive() > to {
+                    continue;
+                }
+
+                // the operator at the origin, as it can change along the way
+                let variable_train = train.route[..=origin]
+                    .iter()
+                    .rev()
+                    .find_map(|x| x.change_en_route.as_ref())
+                    .unwrap_or(&train.variable_train);
+                let operator = match &variable_train.operator {
+                    Some(x) => x.id.as_str(),
+                    None => "unknown",
+                };
+
+                counts.add(operator);
+                by_date
+                    .entry(departure.date_naive())
+                    .or_insert_with(FlowCounts::default)
+                    .add(operator);
+                by_hour
+                    .entry(departure.hour())
+                    .or_insert_with(FlowCounts::default)
+                    .add(operator);
+            }
+        }
+
+        // every day in the range, even the empty ones, so gaps in service stand out
+        let by_date = from
+            .iter_days()
+            .take_while(|x| *x <= to)
+            .map(|date| DailyFlow {
+                date,
+                counts: by_date.remove(&date).unwrap_or_default(),
+            })
+            .collect();
+        let by_hour = (0..24)
+            .map(|hour| HourlyFlow {
+                hour,
+                counts: by_hour.remove(&hour).unwrap_or_default(),
+            })
+            .collect();
+
+        let mut from_location_ids = from_location_ids.iter().cloned().collect::<Vec<_>>();
+        from_location_ids.sort();
+        let mut to_location_ids = to_location_ids.iter().cloned().collect::<Vec<_>>();
+        to_location_ids.sort();
+        FlowReport {
+            namespace: schedule.namespace.clone(),
+            from_location_ids,
+            to_location_ids,
+            from,
+            to,
+            counts,
+            by_date,
+            by_hour,
+        }
+    }
+}
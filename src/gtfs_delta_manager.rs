@@ -0,0 +1,244 @@
+use crate::download_cache::DownloadCache;
+use crate::error::{Error, ErrorContext};
+use crate::fetch_core::HttpClient;
+use crate::fetcher::GtfsFetcher;
+use crate::gtfs_importer::GtfsImporter;
+use crate::gtfs_url_fetcher::GtfsUrlFetcher;
+use crate::importer::SlowGtfsImporter;
+use crate::manager::{ImportScheduler, Manager};
+use crate::schedule::Schedule;
+use crate::schedule_manager::ScheduleManager;
+use crate::triggers::{DailyRun, Triggers};
+
+use chrono::offset::Utc;
+use chrono::{NaiveDate, NaiveTime, TimeZone};
+use chrono_tz::Tz;
+
+use serde::Deserialize;
+
+use async_trait::async_trait;
+
+use std::str::FromStr;
+use std::sync::Arc;
+
+// A GTFS feed published as an occasional full extract plus a file of what changed each day, as
+// the HAFAS-based feeds for DB and ÖBB are.
+#[derive(Clone, Deserialize)]
+pub struct GtfsDeltaConfig {
+    namespace: String, // e.g. "atobb"
+    description: String,
+    full_url: String,
+    delta_url: String,             // with {date} for the day's date as YYYYMMDD
+    publisher: Option<String>,     // for log messages
+    timezone: Option<String>,      // of update_time, defaulting to Europe/Berlin
+    update_time: Option<String>,   // HH:MM, when the day's delta should be out
+    full_reload_days: Option<u64>, // start again from a full extract this often, in case of drift
+}
+
+pub struct GtfsDeltaManager {
+    config: GtfsDeltaConfig,
+    timezone: Tz,
+    update_time: NaiveTime,
+    schedule_manager: Arc<ScheduleManager>,
+    download_cache: Option<Arc<DownloadCache>>,
+    http_client: Arc<HttpClient>,
+    triggers: Arc<Triggers>,
+    import_scheduler: Arc<ImportScheduler>,
+}
+
+impl GtfsDeltaConfig {
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+}
+
+impl GtfsDeltaManager {
+    pub async fn new(
+        config: GtfsDeltaConfig,
+        schedule_manager: Arc<ScheduleManager>,
+        download_cache: Option<Arc<DownloadCache>>,
+        http_client: Arc<HttpClient>,
+        triggers: Arc<Triggers>,
+        import_scheduler: Arc<ImportScheduler>,
+    ) -> Result<GtfsDeltaManager, Error> {
+        let timezone = config.timezone.as_deref().unwrap_or("Europe/Berlin");
+        let timezone = Tz::from_str(timezone)
+            .map_err(|x| anyhow::anyhow!("Invalid time zone {}: {}", timezone, x))?;
+        let update_time = match &config.update_time {
+            Some(x) => NaiveTime::parse_from_str(x, "%H:%M")
+                .map_err(|e| anyhow::anyhow!("Invalid update time {}: {}", x, e))?,
+            None => NaiveTime::from_hms_opt(5, 0, 0).unwrap(),
+        };
+        Ok(GtfsDeltaManager {
+            config,
+            timezone,
+            update_time,
+            schedule_manager,
+            download_cache,
+            http_client,
+            triggers,
+            import_scheduler,
+        })
+    }
+
+    fn publisher(&self) -> &str {
+        self.config
+            .publisher
+            .as_deref()
+            .unwrap_or(&self.config.description)
+    }
+
+    fn source(&self, what: &str) -> String {
+        format!("{}-{}", self.config.namespace, what)
+    }
+
+    async fn reload_gtfs(
+        &self,
+        gtfs_fetcher: &GtfsUrlFetcher,
+        gtfs_importer: &mut GtfsImporter,
+    ) -> Result<(), Error> {
+        let slot = self.import_scheduler.start(&self.config.namespace).await;
+        let gtfs = gtfs_fetcher
+            .fetch()
+            .await
+            .context(&format!("Fetching {} GTFS", self.config.namespace))?;
+
+        let schedule = Schedule::new(
+            self.config.namespace.clone(),
+            self.config.description.clone(),
+        );
+        let schedule = gtfs_importer
+            .overlay(gtfs, schedule)
+            .await
+            .context(&format!("Importing {} GTFS", self.config.namespace))?;
+
+        // deltas are applied from this same task, so none can be lost by only locking now
+        let mut transaction = self.schedule_manager.transactional_write().await;
+        transaction.put(&self.config.namespace, schedule);
+        transaction.source_updated(&self.source("gtfs"));
+        transaction.archive();
+        transaction.commit();
+        slot.finished();
+
+        Ok(())
+    }
+
+    async fn apply_delta(
+        &self,
+        date: NaiveDate,
+        gtfs_importer: &mut GtfsImporter,
+    ) -> Result<(), Error> {
+        let url = self
+            .config
+            .delta_url
+            .replace("{date}", &date.format("%Y%m%d").to_string());
+        let delta_fetcher = GtfsUrlFetcher::new_with_cache(
+            self.http_client.clone(),
+            &url,
+            self.publisher(),
+            &self.source("gtfs-delta"),
+            self.download_cache.clone(),
+        );
+        let delta = delta_fetcher.fetch().await.context(&format!(
+            "Fetching {} GTFS delta for {}",
+            self.config.namespace, date
+        ))?;
+
+        let mut transaction = self.schedule_manager.transactional_write().await;
+
+        let schedule = match transaction.take(&self.config.namespace) {
+            Some(x) => x,
+            None => Err(anyhow::anyhow!(
+                "No {} schedule to apply a delta to",
+                self.config.namespace
+            ))?,
+        };
+        let schedule = gtfs_importer
+            .apply_delta(delta, schedule)
+            .await
+            .context(&format!(
+                "Applying {} GTFS delta for {}",
+                self.config.namespace, date
+            ))?;
+
+        transaction.put(&self.config.namespace, schedule);
+        transaction.source_updated(&self.source("gtfs-delta"));
+        transaction.archive();
+        transaction.commit();
+
+        Ok(())
+    }
+
+    async fn update_gtfs(
+        &self,
+        gtfs_fetcher: &GtfsUrlFetcher,
+        gtfs_importer: &mut GtfsImporter,
+    ) -> Result<(), Error> {
+        let full_reload_days = self.config.full_reload_days.unwrap_or(7);
+        let mut last_full = self
+            .timezone
+            .from_utc_datetime(&Utc::now().naive_utc())
+            .date_naive();
+        // the delta is announced as a change to the full feed's source, as that's what it updates
+        let mut daily = DailyRun::new(
+            self.timezone,
+            self.update_time,
+            &self.triggers,
+            &self.source("gtfs"),
+        );
+        loop {
+            if daily.wait().await {
+                println!(
+                    "Fetching {} GTFS delta early, as upstream says it's out",
+                    self.config.namespace
+                );
+            }
+            let today = self
+                .timezone
+                .from_utc_datetime(&Utc::now().naive_utc())
+                .date_naive();
+
+            if (today - last_full).num_days() >= full_reload_days as i64 {
+                self.reload_gtfs(gtfs_fetcher, gtfs_importer).await?;
+                last_full = today;
+                continue;
+            }
+
+            // A missed delta can't be made up for later, so anything going wrong means starting
+            // again from the full extract. Only if that fails too is it the supervisor's problem.
+            match self.apply_delta(today, gtfs_importer).await {
+                Ok(()) => (),
+                Err(x) => {
+                    println!(
+                        "WARNING: Failed to apply {} GTFS delta, reloading in full: {}",
+                        self.config.namespace, x
+                    );
+                    self.reload_gtfs(gtfs_fetcher, gtfs_importer).await?;
+                    last_full = today;
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Manager for GtfsDeltaManager {
+    async fn run(&mut self) -> Result<(), Error> {
+        let gtfs_fetcher = GtfsUrlFetcher::new_with_cache(
+            self.http_client.clone(),
+            &self.config.full_url,
+            self.publisher(),
+            &self.source("gtfs"),
+            self.download_cache.clone(),
+        );
+        let mut gtfs_importer = GtfsImporter::new();
+
+        self.reload_gtfs(&gtfs_fetcher, &mut gtfs_importer).await?;
+
+        tokio::try_join!(async {
+            return self.update_gtfs(&gtfs_fetcher, &mut gtfs_importer).await;
+        },)?;
+
+        Ok(())
+    }
+}
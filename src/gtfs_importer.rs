@@ -1,8 +1,10 @@
 use crate::error::Error;
 use crate::importer::SlowGtfsImporter;
+use crate::intern::IStr;
 use crate::schedule::{
-    Activities, DaysOfWeek, Location, ReservationField, Reservations, Schedule, Train,
-    TrainLocation, TrainOperator, TrainSource, TrainType, TrainValidityPeriod, VariableTrain,
+    Activities, Coordinate, DaysOfWeek, Location, ReservationField, Reservations, Schedule, Train,
+    TrainCancellation, TrainLocation, TrainOperator, TrainSource, TrainType, TrainValidityPeriod,
+    VariableTrain,
 };
 
 use async_trait::async_trait;
@@ -11,8 +13,8 @@ use chrono::{Datelike, NaiveTime, TimeZone};
 use chrono_tz::{ParseError, Tz};
 
 use gtfs_structures::{
-    Availability, BikesAllowedType, Calendar, CalendarDate, Exception, Gtfs, LocationType,
-    PickupDropOffType, RouteType, Stop, StopTime, TimepointType,
+    Availability, BikesAllowedType, Calendar, CalendarDate, ExactTimes, Exception, Gtfs,
+    LocationType, PickupDropOffType, RouteType, Shape, Stop, StopTime, TimepointType, Trip,
 };
 
 use tokio::task::block_in_place;
@@ -39,6 +41,7 @@ pub enum GtfsErrorType {
     UnknownBicyclesAllowed(BikesAllowedType),
     NotEnoughStops,
     UnknownStopType(PickupDropOffType),
+    NoBaseFeed,
 }
 
 impl fmt::Display for GtfsErrorType {
@@ -65,26 +68,18 @@ impl fmt::Display for GtfsErrorType {
             GtfsErrorType::UnknownStopType(x) => {
                 write!(f, "Stop type {:#?} unknown", x)
             }
+            GtfsErrorType::NoBaseFeed => write!(f, "No full feed imported to apply a delta to"),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
+#[error("Error reading GTFS file {file}: {error_type}")]
 pub struct GtfsImportError {
     error_type: GtfsErrorType,
     file: String,
 }
 
-impl fmt::Display for GtfsImportError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Error reading GTFS file {}: {}",
-            self.file, self.error_type
-        )
-    }
-}
-
 fn load_stop(stop: &Stop, default_timezone: &str) -> Result<Location, GtfsImportError> {
     let timezone = stop
         .timezone
@@ -116,6 +111,14 @@ fn load_stop(stop: &Stop, default_timezone: &str) -> Result<Location, GtfsImport
                 })
             }
         },
+        position: match (stop.latitude, stop.longitude) {
+            (Some(latitude), Some(longitude)) => Some(Coordinate {
+                latitude,
+                longitude,
+            }),
+            _ => None,
+        },
+        unverified: false,
     })
 }
 
@@ -146,8 +149,10 @@ fn calculate_validities(
         }
     };
 
+    // Services only for holidays and the like often come with no days in calendar at all, and run
+    // on the dates calendar_dates adds alone
     let mut validity = match calendar {
-        Some(x) => vec![TrainValidityPeriod {
+        Some(x) if calculate_days_of_week(x).into_iter().any(|x| x) => vec![TrainValidityPeriod {
             valid_begin: timezone
                 .from_local_datetime(&x.start_date.and_hms_opt(0, 0, 0).unwrap())
                 .unwrap(),
@@ -156,14 +161,21 @@ fn calculate_validities(
                 .unwrap(),
             days_of_week: calculate_days_of_week(x),
         }],
-        None => vec![],
+        _ => vec![],
     };
 
     match calendar_dates {
         None => (),
         Some(x) => {
             for calendar_date in &**x {
+                let date = calendar_date.date;
+                let already_running = validity.iter().any(|x| {
+                    x.valid_begin.date_naive() <= date
+                        && x.valid_end.date_naive() >= date
+                        && x.days_of_week.get_by_weekday(date.weekday())
+                });
                 match calendar_date.exception_type {
+                    Exception::Added if already_running => (),
                     Exception::Added => validity.push(TrainValidityPeriod {
                         valid_begin: timezone
                             .from_local_datetime(&calendar_date.date.and_hms_opt(0, 0, 0).unwrap())
@@ -185,7 +197,7 @@ fn calculate_validities(
 fn calculate_cancellations(
     calendar_dates: &Option<&Vec<CalendarDate>>,
     timezone: &str,
-) -> Result<Vec<(TrainValidityPeriod, TrainSource)>, GtfsImportError> {
+) -> Result<Vec<TrainCancellation>, GtfsImportError> {
     let timezone = match Tz::from_str(&timezone) {
         Ok(x) => x,
         Err(x) => {
@@ -203,8 +215,8 @@ fn calculate_cancellations(
         Some(x) => {
             for calendar_date in &**x {
                 match calendar_date.exception_type {
-                    Exception::Deleted => cancellations.push((
-                        TrainValidityPeriod {
+                    Exception::Deleted => cancellations.push(TrainCancellation {
+                        validity: TrainValidityPeriod {
                             valid_begin: timezone
                                 .from_local_datetime(
                                     &calendar_date.date.and_hms_opt(0, 0, 0).unwrap(),
@@ -219,8 +231,11 @@ fn calculate_cancellations(
                                 calendar_date.date.weekday(),
                             ),
                         },
-                        TrainSource::ShortTerm,
-                    )),
+                        // a day the timetable leaves out, usually a public holiday, rather than
+                        // a cancellation
+                        source: TrainSource::LongTerm,
+                        reason: None,
+                    }),
                     Exception::Added => (),
                 }
             }
@@ -230,8 +245,11 @@ fn calculate_cancellations(
     Ok(cancellations)
 }
 
+// offset_secs moves every time along, for trips generated from frequencies
 fn calculate_route(
     stop_times: &Vec<StopTime>,
+    offset_secs: i64,
+    times_approximate: bool,
     variable_train: &VariableTrain,
     timezone: &str,
     stops: &HashMap<String, Arc<Stop>>,
@@ -250,8 +268,18 @@ fn calculate_route(
     let mut route = vec![];
 
     for (i, stop_time) in stop_times.iter().enumerate() {
+        // GTFS gives both times everywhere, but nothing arrives at its origin or leaves its
+        // destination
+        let arrival_time = stop_time
+            .arrival_time
+            .filter(|_| i > 0)
+            .map(|x| (i64::from(x) + offset_secs) as u32);
+        let departure_time = stop_time
+            .departure_time
+            .filter(|_| i + 1 < stop_times.len())
+            .map(|x| (i64::from(x) + offset_secs) as u32);
         let (working_arr, working_arr_day) = match stop_time.drop_off_type {
-            PickupDropOffType::NotAvailable => match stop_time.arrival_time {
+            PickupDropOffType::NotAvailable => match arrival_time {
                 Some(x) => (
                     Some(
                         NaiveTime::from_num_seconds_from_midnight_opt(x % (60 * 60 * 24), 0)
@@ -272,7 +300,7 @@ fn calculate_route(
             }
         };
         let (working_dep, working_dep_day) = match stop_time.pickup_type {
-            PickupDropOffType::NotAvailable => match stop_time.departure_time {
+            PickupDropOffType::NotAvailable => match departure_time {
                 Some(x) => (
                     Some(
                         NaiveTime::from_num_seconds_from_midnight_opt(x % (60 * 60 * 24), 0)
@@ -296,7 +324,7 @@ fn calculate_route(
             PickupDropOffType::NotAvailable => (None, None),
             PickupDropOffType::Regular
             | PickupDropOffType::ArrangeByPhone
-            | PickupDropOffType::CoordinateWithDriver => match stop_time.arrival_time {
+            | PickupDropOffType::CoordinateWithDriver => match arrival_time {
                 Some(x) => (
                     Some(
                         NaiveTime::from_num_seconds_from_midnight_opt(x % (60 * 60 * 24), 0)
@@ -317,7 +345,7 @@ fn calculate_route(
             PickupDropOffType::NotAvailable => (None, None),
             PickupDropOffType::Regular
             | PickupDropOffType::ArrangeByPhone
-            | PickupDropOffType::CoordinateWithDriver => match stop_time.departure_time {
+            | PickupDropOffType::CoordinateWithDriver => match departure_time {
                 Some(x) => (
                     Some(
                         NaiveTime::from_num_seconds_from_midnight_opt(x % (60 * 60 * 24), 0)
@@ -370,7 +398,7 @@ fn calculate_route(
                     })
                 }
             }),
-            id: actual_stop_id.clone(),
+            id: actual_stop_id.as_str().into(),
             id_suffix: Some(stop_time.stop_sequence.to_string()),
             working_arr,
             working_arr_day,
@@ -382,14 +410,16 @@ fn calculate_route(
             public_arr_day,
             public_dep,
             public_dep_day,
-            platform: stops
+            scheduled_platform: stops
                 .get(&actual_platform_id)
                 .unwrap()
                 .platform_code
-                .clone(),
+                .as_deref()
+                .map(IStr::from),
+            live_platform: None,
             platform_zone: match actual_zone_id {
                 None => None,
-                Some(x) => stops.get(&x).unwrap().name.clone(),
+                Some(x) => stops.get(&x).unwrap().name.as_deref().map(IStr::from),
             },
             line: None,
             path: None,
@@ -414,10 +444,11 @@ fn calculate_route(
                     && stop_time.drop_off_type != PickupDropOffType::NotAvailable,
                 train_begins: i == 0,
                 train_finishes: i == stop_times.len() - 1,
-                times_approximate: match stop_time.timepoint {
-                    TimepointType::Approximate => true,
-                    TimepointType::Exact => false,
-                },
+                times_approximate: times_approximate
+                    || match stop_time.timepoint {
+                        TimepointType::Approximate => true,
+                        TimepointType::Exact => false,
+                    },
                 ..Default::default()
             },
             change_en_route,
@@ -431,7 +462,7 @@ fn calculate_route(
 
         schedule
             .trains_indexed_by_location
-            .entry(train_location.id.clone())
+            .entry(train_location.id.to_string())
             .or_insert(HashSet::new())
             .insert(train_id.to_string());
 
@@ -441,73 +472,54 @@ fn calculate_route(
     Ok(route)
 }
 
-impl GtfsImporter {
-    pub fn new() -> GtfsImporter {
-        GtfsImporter { base_gtfs: None }
+fn default_timezone(gtfs: &Gtfs) -> Result<(String, Tz), GtfsImportError> {
+    if gtfs.agencies.len() == 0 {
+        return Err(GtfsImportError {
+            error_type: GtfsErrorType::NoAgencyDefined,
+            file: "agency".to_string(),
+        });
     }
 
-    fn overlay_worker(
-        &mut self,
-        gtfs: Gtfs,
-        mut schedule: Schedule,
-    ) -> Result<Schedule, GtfsImportError> {
-        if gtfs.agencies.len() == 0 {
-            return Err(GtfsImportError {
-                error_type: GtfsErrorType::NoAgencyDefined,
-                file: "agency".to_string(),
-            });
-        }
-
-        let default_timezone = gtfs.agencies[0].timezone.clone();
+    let default_timezone = gtfs.agencies[0].timezone.clone();
 
-        let default_timezone_tz = match Tz::from_str(&default_timezone) {
-            Ok(x) => x,
-            Err(x) => {
-                return Err(GtfsImportError {
-                    error_type: GtfsErrorType::InvalidTimezone(default_timezone.to_string(), x),
-                    file: "agency".to_string(),
-                })
-            }
-        };
+    match Tz::from_str(&default_timezone) {
+        Ok(x) => Ok((default_timezone, x)),
+        Err(x) => Err(GtfsImportError {
+            error_type: GtfsErrorType::InvalidTimezone(default_timezone.to_string(), x),
+            file: "agency".to_string(),
+        }),
+    }
+}
 
-        for feed_info in &gtfs.feed_info {
-            schedule.their_id = feed_info.version.clone();
-            schedule.valid_begin = feed_info.start_date.map(|x| {
-                default_timezone_tz
-                    .from_local_datetime(&x.and_hms_opt(0, 0, 0).unwrap())
-                    .unwrap()
-            });
-            schedule.valid_end = feed_info.end_date.map(|x| {
-                default_timezone_tz
-                    .from_local_datetime(&x.and_hms_opt(0, 0, 0).unwrap())
-                    .unwrap()
-            });
-        }
+fn load_feed_info(gtfs: &Gtfs, default_timezone_tz: Tz, schedule: &mut Schedule) {
+    for feed_info in &gtfs.feed_info {
+        schedule.their_id = feed_info.version.clone();
+        schedule.valid_begin = feed_info.start_date.map(|x| {
+            default_timezone_tz
+                .from_local_datetime(&x.and_hms_opt(0, 0, 0).unwrap())
+                .unwrap()
+        });
+        schedule.valid_end = feed_info.end_date.map(|x| {
+            default_timezone_tz
+                .from_local_datetime(&x.and_hms_opt(0, 0, 0).unwrap())
+                .unwrap()
+        });
+    }
+}
 
-        for (stop_id, stop) in &gtfs.stops {
-            match stop.location_type {
-                LocationType::StopPoint => {
-                    if stop.parent_station.is_none() {
-                        schedule
-                            .locations
-                            .insert(stop_id.clone(), load_stop(stop, &default_timezone)?);
-                        match &stop.code {
-                            Some(x) if x == "0" => (),
-                            Some(x) => {
-                                schedule
-                                    .locations_indexed_by_public_id
-                                    .entry(x.clone())
-                                    .or_insert(HashSet::new())
-                                    .insert(stop_id.clone());
-                            }
-                            None => (),
-                        }
-                    }
-                }
-                LocationType::StopArea => {
+fn load_stops(
+    stops: &HashMap<String, Arc<Stop>>,
+    default_timezone: &str,
+    schedule: &mut Schedule,
+) -> Result<(), GtfsImportError> {
+    for (stop_id, stop) in stops {
+        match stop.location_type {
+            LocationType::StopPoint => {
+                if stop.parent_station.is_none() {
                     schedule
                         .locations
-                        .insert(stop_id.clone(), load_stop(stop, &default_timezone)?);
+                        .insert(stop_id.clone(), load_stop(stop, default_timezone)?);
+                    schedule.index_location_for_search(stop_id);
                     match &stop.code {
                         Some(x) if x == "0" => (),
                         Some(x) => {
@@ -520,170 +532,433 @@ impl GtfsImporter {
                         None => (),
                     }
                 }
-                LocationType::StationEntrance => (), // don't care
-                LocationType::GenericNode => (),     // also don't care
-                LocationType::BoardingArea => (), // also don't care, will be looked up later if needed
-                LocationType::Unknown(x) => {
-                    return Err(GtfsImportError {
-                        error_type: GtfsErrorType::UnknownLocationType(x),
-                        file: "stops".to_string(),
-                    })
+            }
+            LocationType::StopArea => {
+                schedule
+                    .locations
+                    .insert(stop_id.clone(), load_stop(stop, default_timezone)?);
+                schedule.index_location_for_search(stop_id);
+                match &stop.code {
+                    Some(x) if x == "0" => (),
+                    Some(x) => {
+                        schedule
+                            .locations_indexed_by_public_id
+                            .entry(x.clone())
+                            .or_insert(HashSet::new())
+                            .insert(stop_id.clone());
+                    }
+                    None => (),
                 }
             }
+            LocationType::StationEntrance => (), // don't care
+            LocationType::GenericNode => (),     // also don't care
+            LocationType::BoardingArea => (), // also don't care, will be looked up later if needed
+            LocationType::Unknown(x) => {
+                return Err(GtfsImportError {
+                    error_type: GtfsErrorType::UnknownLocationType(x),
+                    file: "stops".to_string(),
+                })
+            }
         }
+    }
+    Ok(())
+}
 
-        for (trip_id, trip) in &gtfs.trips {
-            let route = match &gtfs.routes.get(&trip.route_id) {
-                Some(x) => (*x).clone(),
-                None => {
-                    return Err(GtfsImportError {
-                        error_type: GtfsErrorType::RouteNotPresent(trip.route_id.clone()),
-                        file: "trips".to_string(),
-                    })
-                }
-            };
+fn load_shape(points: &Vec<Shape>) -> Vec<Coordinate> {
+    let mut points = points.iter().collect::<Vec<_>>();
+    points.sort_by_key(|x| x.sequence);
+    points
+        .iter()
+        .map(|x| Coordinate {
+            latitude: x.latitude,
+            longitude: x.longitude,
+        })
+        .collect()
+}
 
-            let agency = match &route.agency_id {
-                Some(x) => match &gtfs.agencies.iter().find(|y| y.id == Some(x.clone())) {
-                    Some(x) => (*x).clone(),
-                    None => {
-                        return Err(GtfsImportError {
-                            error_type: GtfsErrorType::AgencyNotPresent(x.to_string()),
-                            file: "routes".to_string(),
-                        })
-                    }
-                },
-                None => gtfs.agencies[0].clone(),
-            };
+// Metro-style feeds give a trip's stop times once and say in frequencies.txt that it runs every so
+// often between two times. Each run becomes a train of its own, as if it had been written out in
+// full, with an ID of the trip's and its first departure, e.g. "T1@07:30:00", and every time moved
+// along to suit; unless the feed says the times are exact, they're marked approximate. Returns the
+// IDs with how far to move the times along and whether they're approximate.
+fn trip_instances(trip_id: &str, trip: &Trip) -> Vec<(String, i64, bool)> {
+    if trip.frequencies.is_empty() {
+        return vec![(trip_id.to_string(), 0, false)];
+    }
+    let first = trip
+        .stop_times
+        .first()
+        .and_then(|x| x.departure_time.or(x.arrival_time))
+        .unwrap_or(0);
+    let mut instances = vec![];
+    for frequency in &trip.frequencies {
+        if frequency.headway_secs == 0 {
+            continue;
+        }
+        let approximate = !matches!(frequency.exact_times, Some(ExactTimes::ScheduleBased));
+        let mut start = frequency.start_time;
+        while start < frequency.end_time {
+            instances.push((
+                format!(
+                    "{}@{:02}:{:02}:{:02}",
+                    trip_id,
+                    start / 3600,
+                    start / 60 % 60,
+                    start % 60
+                ),
+                i64::from(start) - i64::from(first),
+                approximate,
+            ));
+            start += frequency.headway_secs;
+        }
+    }
+    instances
+}
 
-            let variable_train = VariableTrain {
-                train_type: match gtfs.routes.get(&trip.route_id).unwrap().route_type {
-                    RouteType::Tramway => TrainType::Tram,
-                    RouteType::Subway => TrainType::Metro,
-                    RouteType::Rail => TrainType::OrdinaryPassenger,
-                    RouteType::Bus => TrainType::Bus,
-                    RouteType::Ferry => TrainType::Ship,
-                    RouteType::CableCar => TrainType::CableTram,
-                    RouteType::Gondola => TrainType::CableCar,
-                    RouteType::Funicular => TrainType::Funicular,
-                    RouteType::Coach => TrainType::Coach,
-                    RouteType::Taxi => TrainType::Taxi,
-                    RouteType::Air => TrainType::Air,
-                    RouteType::Other(11) => TrainType::Trolleybus,
-                    RouteType::Other(12) => TrainType::Monorail,
-                    x => {
-                        return Err(GtfsImportError {
-                            error_type: GtfsErrorType::UnknownRouteType(x),
-                            file: "routes".to_string(),
-                        })
-                    }
-                },
-                public_id: trip.trip_short_name.clone(),
-                headcode: trip.trip_headsign.clone(),
-                service_group: gtfs.routes.get(&trip.route_id).unwrap().long_name.clone(),
-                power_type: None,
-                timing_allocation: None,
-                actual_allocation: None,
-                timing_speed_m_per_s: None,
-                operating_characteristics: None,
-                has_first_class_seats: None,
-                has_second_class_seats: None,
-                has_first_class_sleepers: None,
-                has_second_class_sleepers: None,
-                carries_vehicles: None,
-                reservations: Reservations {
-                    seats: ReservationField::Unknown,
-                    bicycles: ReservationField::Unknown,
-                    sleepers: ReservationField::Unknown,
-                    vehicles: ReservationField::Unknown,
-                    wheelchairs: ReservationField::Unknown,
-                },
-                catering: None,
-                brand: None,
-                name: gtfs.routes.get(&trip.route_id).unwrap().short_name.clone(),
-                uic_code: None,
-                operator: Some(TrainOperator {
-                    id: match &agency.id {
-                        Some(x) => x.clone(),
-                        None => agency.name.clone(),
-                    },
-                    description: Some(agency.name.clone()),
-                }),
-                wheelchair_accessible: match trip.wheelchair_accessible {
-                    Availability::InformationNotAvailable => None,
-                    Availability::Available => Some(true),
-                    Availability::NotAvailable => Some(false),
-                    x => {
-                        return Err(GtfsImportError {
-                            error_type: GtfsErrorType::UnknownWheelchairAccessibility(x),
-                            file: "trips".to_string(),
-                        })
-                    }
-                },
-                bicycles_allowed: match trip.bikes_allowed {
-                    BikesAllowedType::NoBikeInfo => None,
-                    BikesAllowedType::AtLeastOneBike => Some(true),
-                    BikesAllowedType::NoBikesAllowed => Some(false),
-                    x => {
-                        return Err(GtfsImportError {
-                            error_type: GtfsErrorType::UnknownBicyclesAllowed(x),
-                            file: "trips".to_string(),
-                        })
-                    }
-                },
-            };
+fn load_trip(
+    gtfs: &Gtfs,
+    trip_id: &String,
+    trip: &Trip,
+    default_timezone: &str,
+    schedule: &mut Schedule,
+) -> Result<(), GtfsImportError> {
+    let route = match &gtfs.routes.get(&trip.route_id) {
+        Some(x) => (*x).clone(),
+        None => {
+            return Err(GtfsImportError {
+                error_type: GtfsErrorType::RouteNotPresent(trip.route_id.clone()),
+                file: "trips".to_string(),
+            })
+        }
+    };
 
-            let train = Train {
-                id: trip_id.clone(),
-                validity: calculate_validities(
-                    &gtfs.calendar.get(&trip.service_id),
-                    &gtfs.calendar_dates.get(&trip.service_id),
-                    &default_timezone,
-                )?,
-                cancellations: calculate_cancellations(
-                    &gtfs.calendar_dates.get(&trip.service_id),
-                    &default_timezone,
-                )?,
-                replacements: vec![], // not a thing in GTFS
-                variable_train: variable_train.clone(),
-                source: Some(TrainSource::LongTerm), // no distinction between long and short in GTFS
-                runs_as_required: false,             // not a thing in GTFS
-                performance_monitoring: None,        // not a thing in GTFS
-                route: calculate_route(
-                    &trip.stop_times,
-                    &variable_train,
-                    &default_timezone,
-                    &gtfs.stops,
-                    &trip_id,
-                    &mut schedule,
-                )?,
-            };
+    let agency = match &route.agency_id {
+        Some(x) => match &gtfs.agencies.iter().find(|y| y.id == Some(x.clone())) {
+            Some(x) => (*x).clone(),
+            None => {
+                return Err(GtfsImportError {
+                    error_type: GtfsErrorType::AgencyNotPresent(x.to_string()),
+                    file: "routes".to_string(),
+                })
+            }
+        },
+        None => gtfs.agencies[0].clone(),
+    };
 
-            match &train.variable_train.public_id {
+    let variable_train = VariableTrain {
+        train_type: match gtfs.routes.get(&trip.route_id).unwrap().route_type {
+            RouteType::Tramway => TrainType::Tram,
+            RouteType::Subway => TrainType::Metro,
+            RouteType::Rail => TrainType::OrdinaryPassenger,
+            RouteType::Bus => TrainType::Bus,
+            RouteType::Ferry => TrainType::Ship,
+            RouteType::CableCar => TrainType::CableTram,
+            RouteType::Gondola => TrainType::CableCar,
+            RouteType::Funicular => TrainType::Funicular,
+            RouteType::Coach => TrainType::Coach,
+            RouteType::Taxi => TrainType::Taxi,
+            RouteType::Air => TrainType::Air,
+            RouteType::Other(11) => TrainType::Trolleybus,
+            RouteType::Other(12) => TrainType::Monorail,
+            x => {
+                return Err(GtfsImportError {
+                    error_type: GtfsErrorType::UnknownRouteType(x),
+                    file: "routes".to_string(),
+                })
+            }
+        },
+        public_id: trip.trip_short_name.clone(),
+        headcode: trip.trip_headsign.clone(),
+        service_group: gtfs.routes.get(&trip.route_id).unwrap().long_name.clone(),
+        power_type: None,
+        timing_allocation: None,
+        actual_allocation: None,
+        timing_speed_m_per_s: None,
+        operating_characteristics: None,
+        has_first_class_seats: None,
+        has_second_class_seats: None,
+        has_first_class_sleepers: None,
+        has_second_class_sleepers: None,
+        carries_vehicles: None,
+        reservations: Reservations {
+            seats: ReservationField::Unknown,
+            bicycles: ReservationField::Unknown,
+            sleepers: ReservationField::Unknown,
+            vehicles: ReservationField::Unknown,
+            wheelchairs: ReservationField::Unknown,
+        },
+        catering: None,
+        brand: None,
+        name: gtfs.routes.get(&trip.route_id).unwrap().short_name.clone(),
+        uic_code: None,
+        operator: Some(TrainOperator {
+            id: match &agency.id {
+                Some(x) => x.into(),
+                None => agency.name.as_str().into(),
+            },
+            description: Some(agency.name.as_str().into()),
+        }),
+        wheelchair_accessible: match trip.wheelchair_accessible {
+            Availability::InformationNotAvailable => None,
+            Availability::Available => Some(true),
+            Availability::NotAvailable => Some(false),
+            x => {
+                return Err(GtfsImportError {
+                    error_type: GtfsErrorType::UnknownWheelchairAccessibility(x),
+                    file: "trips".to_string(),
+                })
+            }
+        },
+        bicycles_allowed: match trip.bikes_allowed {
+            BikesAllowedType::NoBikeInfo => None,
+            BikesAllowedType::AtLeastOneBike => Some(true),
+            BikesAllowedType::NoBikesAllowed => Some(false),
+            x => {
+                return Err(GtfsImportError {
+                    error_type: GtfsErrorType::UnknownBicyclesAllowed(x),
+                    file: "trips".to_string(),
+                })
+            }
+        },
+    };
+
+    let validity = calculate_validities(
+        &gtfs.calendar.get(&trip.service_id),
+        &gtfs.calendar_dates.get(&trip.service_id),
+        default_timezone,
+    )?;
+    let cancellations =
+        calculate_cancellations(&gtfs.calendar_dates.get(&trip.service_id), default_timezone)?;
+
+    for (train_id, offset_secs, times_approximate) in trip_instances(trip_id, trip) {
+        let train = Train {
+            id: train_id.clone(),
+            validity: validity.clone(),
+            cancellations: cancellations.clone(),
+            replacements: vec![], // not a thing in GTFS
+            variable_train: variable_train.clone(),
+            source: Some(TrainSource::LongTerm), // no distinction between long and short in GTFS
+            runs_as_required: false,             // not a thing in GTFS
+            performance_monitoring: None,        // not a thing in GTFS
+            route: calculate_route(
+                &trip.stop_times,
+                offset_secs,
+                times_approximate,
+                &variable_train,
+                default_timezone,
+                &gtfs.stops,
+                &train_id,
+                schedule,
+            )?
+            .into(),
+        };
+
+        // only keep the shapes something actually uses
+        match trip
+            .shape_id
+            .as_ref()
+            .and_then(|x| Some((x, gtfs.shapes.get(x)?)))
+        {
+            Some((shape_id, points)) => {
+                if !schedule.shapes.contains_key(shape_id) {
+                    schedule.shapes.insert(shape_id.clone(), load_shape(points));
+                }
+                schedule
+                    .shapes_indexed_by_train
+                    .insert(train.id.clone(), shape_id.clone());
+            }
+            None => (),
+        }
+
+        match &train.variable_train.public_id {
+            Some(x) => {
+                schedule
+                    .trains_indexed_by_public_id
+                    .entry(x.clone())
+                    .or_insert(HashSet::new())
+                    .insert(train.id.clone());
+            }
+            None => (),
+        }
+        schedule
+            .trains
+            .entry(train.id.clone())
+            .or_insert(vec![])
+            .push(train);
+    }
+    Ok(())
+}
+
+// Takes a trip back out of the schedule, along with everything that indexes it, so a delta can
+// put in the new version or leave it gone
+fn remove_trip(schedule: &mut Schedule, trip_id: &str) {
+    let trains = match schedule.trains.remove(trip_id) {
+        Some(x) => x,
+        None => return,
+    };
+    for train in &trains {
+        for location in &train.route {
+            match schedule
+                .trains_indexed_by_location
+                .get_mut(location.id.as_str())
+            {
                 Some(x) => {
-                    schedule
-                        .trains_indexed_by_public_id
-                        .entry(x.clone())
-                        .or_insert(HashSet::new())
-                        .insert(train.id.clone());
+                    x.remove(trip_id);
                 }
                 None => (),
             }
-            schedule
-                .trains
-                .entry(train.id.clone())
-                .or_insert(vec![])
-                .push(train);
+        }
+        match train
+            .variable_train
+            .public_id
+            .as_ref()
+            .and_then(|x| schedule.trains_indexed_by_public_id.get_mut(x))
+        {
+            Some(x) => {
+                x.remove(trip_id);
+            }
+            None => (),
+        }
+    }
+    schedule.shapes_indexed_by_train.remove(trip_id);
+}
+
+impl GtfsImporter {
+    pub fn new() -> GtfsImporter {
+        GtfsImporter { base_gtfs: None }
+    }
+
+    fn overlay_worker(
+        &mut self,
+        gtfs: Gtfs,
+        mut schedule: Schedule,
+    ) -> Result<Schedule, GtfsImportError> {
+        let (default_timezone, default_timezone_tz) = default_timezone(&gtfs)?;
+        load_feed_info(&gtfs, default_timezone_tz, &mut schedule);
+        load_stops(&gtfs.stops, &default_timezone, &mut schedule)?;
+        for (trip_id, trip) in &gtfs.trips {
+            load_trip(&gtfs, trip_id, trip, &default_timezone, &mut schedule)?;
         }
         self.base_gtfs = Some(gtfs);
         Ok(schedule)
     }
+
+    // Some feeds publish a full extract now and then and only what changed each day in between.
+    // A delta is itself a GTFS feed, where anything replaces what's in the previous feed with the
+    // same ID (calendar dates by service ID) and a trip with no stop times removes it. Only the
+    // trips the delta touches, directly or through their calendar, route or stops, are imported
+    // again. This has to follow a full overlay, and after a failure wants another one, as the
+    // previous feed is only kept while everything goes to plan.
+    fn apply_delta_worker(
+        &mut self,
+        delta: Gtfs,
+        mut schedule: Schedule,
+    ) -> Result<Schedule, GtfsImportError> {
+        let mut base = match self.base_gtfs.take() {
+            Some(x) => x,
+            None => {
+                return Err(GtfsImportError {
+                    error_type: GtfsErrorType::NoBaseFeed,
+                    file: "trips".to_string(),
+                })
+            }
+        };
+
+        let mut affected = delta.trips.keys().cloned().collect::<HashSet<_>>();
+        for (trip_id, trip) in &base.trips {
+            if delta.calendar.contains_key(&trip.service_id)
+                || delta.calendar_dates.contains_key(&trip.service_id)
+                || delta.routes.contains_key(&trip.route_id)
+                || trip
+                    .stop_times
+                    .iter()
+                    .any(|x| delta.stops.contains_key(&x.stop.id))
+            {
+                affected.insert(trip_id.clone());
+            }
+        }
+
+        for agency in &delta.agencies {
+            match base.agencies.iter_mut().find(|x| x.id == agency.id) {
+                Some(x) => *x = agency.clone(),
+                None => base.agencies.push(agency.clone()),
+            }
+        }
+        if !delta.feed_info.is_empty() {
+            base.feed_info = delta.feed_info.clone();
+        }
+        base.stops.extend(delta.stops.clone());
+        base.routes.extend(delta.routes.clone());
+        base.calendar.extend(delta.calendar.clone());
+        base.calendar_dates.extend(delta.calendar_dates.clone());
+        base.shapes.extend(delta.shapes.clone());
+
+        let (default_timezone, default_timezone_tz) = default_timezone(&base)?;
+        load_feed_info(&delta, default_timezone_tz, &mut schedule);
+        load_stops(&delta.stops, &default_timezone, &mut schedule)?;
+        for shape_id in delta.shapes.keys() {
+            // ones nothing used yet get picked up along with the trips that do
+            if schedule.shapes.contains_key(shape_id) {
+                schedule
+                    .shapes
+                    .insert(shape_id.clone(), load_shape(&base.shapes[shape_id]));
+            }
+        }
+
+        // as they were, so any generated from frequencies go too
+        let mut old_train_ids = vec![];
+        for trip_id in &affected {
+            match base.trips.get(trip_id) {
+                Some(trip) => old_train_ids
+                    .extend(trip_instances(trip_id, trip).into_iter().map(|(x, _, _)| x)),
+                None => (),
+            }
+        }
+
+        let mut removed = 0;
+        for (trip_id, trip) in delta.trips {
+            if trip.stop_times.is_empty() {
+                base.trips.remove(&trip_id);
+                removed += 1;
+            } else {
+                base.trips.insert(trip_id, trip);
+            }
+        }
+        for train_id in &old_train_ids {
+            remove_trip(&mut schedule, train_id);
+        }
+        for trip_id in &affected {
+            match base.trips.get(trip_id) {
+                Some(trip) => load_trip(&base, trip_id, trip, &default_timezone, &mut schedule)?,
+                None => (),
+            }
+        }
+        println!(
+            "Applied GTFS delta: {} trips imported again, {} removed",
+            affected.len() - removed,
+            removed
+        );
+
+        self.base_gtfs = Some(base);
+        Ok(schedule)
+    }
+
+    pub async fn apply_delta(
+        &mut self,
+        delta: Gtfs,
+        mut schedule: Schedule,
+    ) -> Result<Schedule, Error> {
+        schedule = block_in_place(move || self.apply_delta_worker(delta, schedule))?;
+        println!("Interned {}", block_in_place(|| schedule.intern_strings()));
+        Ok(schedule)
+    }
 }
 
 #[async_trait]
 impl SlowGtfsImporter for GtfsImporter {
     async fn overlay(&mut self, gtfs: Gtfs, mut schedule: Schedule) -> Result<Schedule, Error> {
         schedule = block_in_place(move || self.overlay_worker(gtfs, schedule))?;
+        println!("Interned {}", block_in_place(|| schedule.intern_strings()));
         Ok(schedule)
     }
 }
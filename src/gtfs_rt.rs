@@ -0,0 +1,609 @@
+use crate::alerts::Alert;
+use crate::delay_propagation::{expected_times, ExpectedTime};
+use crate::schedule::{
+    get_cancellation, get_train_instance, LocalTimes, Schedule, Train, TrainRealtime, TrainSource,
+};
+
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use chrono_tz::Europe::London;
+use chrono_tz::Tz;
+
+use serde::{Deserialize, Serialize};
+
+// Realtime from the UK's own feeds as GTFS-RT, for apps that only speak GTFS: trips VSTP has
+// added, changed or cancelled, delays from TRUST, and an alert for every cancellation, along with
+// whatever alerts we've been given ourselves. Trip IDs are our train IDs (CIF UIDs for gbnr), so
+// this lines up with static GTFS converted from the same CIF that keeps those as the trip IDs.
+//
+// There's no protobuf library here, so the few messages this needs are encoded by hand, following
+// gtfs-realtime.proto; the same structs give the JSON form.
+
+#[derive(Clone, Default, Deserialize)]
+pub struct GtfsRtConfig {
+    timezone: Option<Tz>, // for which days' trains are today's; default Europe/London
+    public_stop_ids: Option<bool>, // stop IDs are public IDs (e.g. CRS codes), not TIPLOCs; default false
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum TripScheduleRelationship {
+    Scheduled = 0,
+    Added = 1,
+    Canceled = 3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Cause {
+    UnknownCause = 1,
+    OtherCause = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum Effect {
+    NoService = 1,
+    OtherEffect = 7,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FeedHeader {
+    pub gtfs_realtime_version: String,
+    pub incrementality: String, // always FULL_DATASET
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TripDescriptor {
+    pub trip_id: String,
+    pub start_date: String, // YYYYMMDD
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schedule_relationship: Option<TripScheduleRelationship>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StopTimeEvent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delay: Option<i32>, // seconds
+    pub time: i64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct StopTimeUpdate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_sequence: Option<u32>, // only for added trips, as static GTFS numbers its own
+    pub stop_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub arrival: Option<StopTimeEvent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub departure: Option<StopTimeEvent>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TripUpdate {
+    pub trip: TripDescriptor,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stop_time_update: Vec<StopTimeUpdate>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TimeRange {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct EntitySelector {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agency_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trip: Option<TripDescriptor>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stop_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Translation {
+    pub text: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TranslatedString {
+    pub translation: Vec<Translation>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct GtfsRtAlert {
+    pub active_period: Vec<TimeRange>,
+    pub informed_entity: Vec<EntitySelector>,
+    pub cause: Cause,
+    pub effect: Effect,
+    pub header_text: TranslatedString,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description_text: Option<TranslatedString>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FeedEntity {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trip_update: Option<TripUpdate>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alert: Option<GtfsRtAlert>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct FeedMessage {
+    pub header: FeedHeader,
+    pub entity: Vec<FeedEntity>,
+}
+
+pub struct GtfsRt {
+    timezone: Tz,
+    public_stop_ids: bool,
+}
+
+fn translated(text: String) -> TranslatedString {
+    TranslatedString {
+        translation: vec![Translation { text }],
+    }
+}
+
+fn event(expected: &Option<ExpectedTime>, planned: Option<DateTime<Tz>>) -> Option<StopTimeEvent> {
+    let time = expected.as_ref().map(|x| x.time).or(planned)?;
+    Some(StopTimeEvent {
+        delay: planned.map(|x| (time - x).num_seconds() as i32),
+        time: time.timestamp(),
+    })
+}
+
+// what gets a trip update at all: anything VSTP has been at, or with anything from TRUST
+fn touched_by_realtime(versions: &[Train], realtime: Option<&TrainRealtime>) -> bool {
+    realtime.is_some_and(|x| x.cancellation.is_some() || !x.events.is_empty())
+        || versions
+            .iter()
+            .flat_map(|x| std::iter::once(x).chain(x.replacements.iter()))
+            .any(|x| {
+                x.source == Some(TrainSource::VeryShortTerm)
+                    || x.cancellations
+                        .iter()
+                        .any(|y| y.source == TrainSource::VeryShortTerm)
+            })
+}
+
+impl GtfsRt {
+    pub fn new(config: GtfsRtConfig) -> Self {
+        Self {
+            timezone: config.timezone.unwrap_or(London),
+            public_stop_ids: config.public_stop_ids.unwrap_or(false),
+        }
+    }
+
+    fn stop_id(&self, schedule: &Schedule, location_id: &str) -> String {
+        match schedule.locations.get(location_id) {
+            Some(x) if self.public_stop_ids => x.public_id.clone().unwrap_or(x.id.clone()),
+            _ => location_id.to_string(),
+        }
+    }
+
+    fn train_entities(
+        &self,
+        schedule: &Schedule,
+        train_id: &str,
+        date: NaiveDate,
+    ) -> Option<Vec<FeedEntity>> {
+        let versions = schedule.trains.get(train_id)?;
+        let realtime = schedule.realtime.get(train_id).and_then(|x| x.get(&date));
+        if !touched_by_realtime(versions, realtime) {
+            return None;
+        }
+        let (train, cancelled, _) = get_train_instance(versions, date);
+        let train = train?;
+        let train_type = train.variable_train.train_type;
+        if train_type.is_freight() || train_type.is_empty_stock() {
+            return None;
+        }
+        // only cancellations the static timetable can't already have
+        let vstp_cancellation = versions
+            .iter()
+            .filter_map(|x| get_cancellation(x, date))
+            .find(|x| x.source == TrainSource::VeryShortTerm);
+        let realtime_cancellation = realtime.and_then(|x| x.cancellation.as_ref());
+        if cancelled && vstp_cancellation.is_none() && realtime_cancellation.is_none() {
+            return None;
+        }
+
+        let planned = train
+            .route
+            .iter()
+            .map(|x| {
+                match schedule
+                    .locations
+                    .get(x.id.as_str())
+                    .map(|y| y.timezone)
+                    .or(x.timing_tz)
+                {
+                    Some(timezone) => x.local_times(date, timezone),
+                    None => LocalTimes::default(),
+                }
+            })
+            .collect::<Vec<_>>();
+        let calls = train
+            .route
+            .iter()
+            .zip(&planned)
+            .filter(|(x, _)| x.passenger_flags().public)
+            .collect::<Vec<_>>();
+        let (first, last) = match (calls.first(), calls.last()) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return None,
+        };
+        let added = versions
+            .iter()
+            .all(|x| x.source == Some(TrainSource::VeryShortTerm));
+        let trip = TripDescriptor {
+            trip_id: train.id.clone(),
+            start_date: date.format("%Y%m%d").to_string(),
+            schedule_relationship: Some(
+                match (cancelled || realtime_cancellation.is_some(), added) {
+                    (true, _) => TripScheduleRelationship::Canceled,
+                    (false, true) => TripScheduleRelationship::Added,
+                    (false, false) => TripScheduleRelationship::Scheduled,
+                },
+            ),
+        };
+        let id = format!("{}-{}", train.id, date.format("%Y%m%d"));
+
+        if trip.schedule_relationship == Some(TripScheduleRelationship::Canceled) {
+            let name = |x: &str| {
+                schedule
+                    .locations
+                    .get(x)
+                    .map_or(x.to_string(), |y| y.name.clone())
+            };
+            let departs = first.1.public_dep.or(first.1.public_arr);
+            let arrives = last.1.public_arr.or(last.1.public_dep);
+            let reason = realtime_cancellation
+                .and_then(|x| x.reason.clone())
+                .or(vstp_cancellation.and_then(|x| x.reason.clone()));
+            let alert = GtfsRtAlert {
+                active_period: vec![TimeRange {
+                    start: departs.map(|x| x.timestamp() as u64),
+                    end: arrives.map(|x| x.timestamp() as u64),
+                }],
+                informed_entity: vec![EntitySelector {
+                    trip: Some(trip.clone()),
+                    ..Default::default()
+                }],
+                cause: match reason {
+                    Some(_) => Cause::OtherCause,
+                    None => Cause::UnknownCause,
+                },
+                effect: Effect::NoService,
+                header_text: translated(format!(
+                    "The {} from {} to {} is cancelled",
+                    departs.map_or(String::new(), |x| x.format("%H:%M").to_string()),
+                    name(first.0.id.as_str()),
+                    name(last.0.id.as_str())
+                )),
+                description_text: reason.map(|x| translated(format!("This is because of {}.", x))),
+            };
+            return Some(vec![
+                FeedEntity {
+                    id: id.clone(),
+                    trip_update: Some(TripUpdate {
+                        trip,
+                        stop_time_update: vec![],
+                    }),
+                    alert: None,
+                },
+                FeedEntity {
+                    id: format!("{}-cancelled", id),
+                    trip_update: None,
+                    alert: Some(alert),
+                },
+            ]);
+        }
+
+        // Every call for a trip VSTP has written, as static GTFS won't have it as it is; otherwise
+        // only the ones TRUST has told us something about
+        let expected = match realtime {
+            Some(x) => expected_times(x, &train.route, &planned),
+            None => vec![None; train.route.len()],
+        };
+        let mut stop_time_update = vec![];
+        for (i, ((location, planned), expected)) in
+            train.route.iter().zip(&planned).zip(&expected).enumerate()
+        {
+            if !location.passenger_flags().public {
+                continue;
+            }
+            if expected.is_none() && train.source != Some(TrainSource::VeryShortTerm) {
+                continue;
+            }
+            let expected = expected.clone().unwrap_or_default();
+            stop_time_update.push(StopTimeUpdate {
+                stop_sequence: match added {
+                    true => Some(i as u32 + 1),
+                    false => None,
+                },
+                stop_id: self.stop_id(schedule, location.id.as_str()),
+                arrival: event(&expected.arrival, planned.public_arr),
+                departure: event(&expected.departure, planned.public_dep),
+            });
+        }
+        if stop_time_update.is_empty() {
+            return None;
+        }
+        Some(vec![FeedEntity {
+            id,
+            trip_update: Some(TripUpdate {
+                trip,
+                stop_time_update,
+            }),
+            alert: None,
+        }])
+    }
+
+    // Our own alerts, as long as they say what they're about: GTFS-RT has no way to send one that
+    // isn't about anything
+    fn alert_entity(&self, schedule: &Schedule, alert: &Alert) -> Option<FeedEntity> {
+        let new_alert = &alert.alert;
+        match &new_alert.namespace {
+            Some(x) if *x != schedule.namespace => return None,
+            _ => (),
+        }
+        if new_alert.operator_id.is_none() && new_alert.location_id.is_none() {
+            return None;
+        }
+        Some(FeedEntity {
+            id: alert.id.clone(),
+            trip_update: None,
+            alert: Some(GtfsRtAlert {
+                active_period: vec![TimeRange {
+                    start: new_alert.starts.map(|x| x.timestamp() as u64),
+                    end: new_alert.expires.map(|x| x.timestamp() as u64),
+                }],
+                informed_entity: vec![EntitySelector {
+                    agency_id: new_alert.operator_id.clone(),
+                    trip: None,
+                    stop_id: new_alert
+                        .location_id
+                        .as_ref()
+                        .map(|x| self.stop_id(schedule, x)),
+                }],
+                cause: Cause::UnknownCause,
+                effect: Effect::OtherEffect,
+                header_text: translated(new_alert.message.clone()),
+                description_text: None,
+            }),
+        })
+    }
+
+    // Everything for today's and yesterday's trains (some of which are still running), and the
+    // alerts that are current
+    pub fn feed(&self, schedule: &Schedule, alerts: &[Alert], now: DateTime<Utc>) -> FeedMessage {
+        let today = now.with_timezone(&self.timezone).date_naive();
+        let mut train_ids = schedule.trains.keys().collect::<Vec<_>>();
+        train_ids.sort();
+        let mut entity = vec![];
+        for date in [today - Days::new(1), today] {
+            for train_id in &train_ids {
+                match self.train_entities(schedule, train_id, date) {
+                    Some(x) => entity.extend(x),
+                    None => (),
+                }
+            }
+        }
+        for alert in alerts.iter().filter(|x| x.is_current(now)) {
+            match self.alert_entity(schedule, alert) {
+                Some(x) => entity.push(x),
+                None => (),
+            }
+        }
+        FeedMessage {
+            header: FeedHeader {
+                gtfs_realtime_version: "2.0".to_string(),
+                incrementality: "FULL_DATASET".to_string(),
+                timestamp: now.timestamp() as u64,
+            },
+            entity,
+        }
+    }
+}
+
+// Protobuf's wire format, as far as GTFS-RT needs it
+#[derive(Default)]
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn varint(&mut self, mut x: u64) {
+        while x >= 0x80 {
+            self.0.push((x as u8) | 0x80);
+            x >>= 7;
+        }
+        self.0.push(x as u8);
+    }
+
+    fn uint(&mut self, field: u32, x: u64) {
+        self.varint((field as u64) << 3);
+        self.varint(x);
+    }
+
+    // int32 and int64, which go out sign-extended to 64 bits
+    fn int(&mut self, field: u32, x: i64) {
+        self.uint(field, x as u64);
+    }
+
+    fn bytes(&mut self, field: u32, x: &[u8]) {
+        self.varint(((field as u64) << 3) | 2);
+        self.varint(x.len() as u64);
+        self.0.extend_from_slice(x);
+    }
+
+    fn string(&mut self, field: u32, x: &str) {
+        self.bytes(field, x.as_bytes());
+    }
+
+    fn message(&mut self, field: u32, x: &impl Encode) {
+        let mut writer = Writer::default();
+        x.encode(&mut writer);
+        self.bytes(field, &writer.0);
+    }
+}
+
+trait Encode {
+    fn encode(&self, writer: &mut Writer);
+}
+
+impl Encode for FeedHeader {
+    fn encode(&self, writer: &mut Writer) {
+        writer.string(1, &self.gtfs_realtime_version);
+        writer.uint(2, 0); // FULL_DATASET
+        writer.uint(3, self.timestamp);
+    }
+}
+
+impl Encode for TripDescriptor {
+    fn encode(&self, writer: &mut Writer) {
+        writer.string(1, &self.trip_id);
+        writer.string(3, &self.start_date);
+        match self.schedule_relationship {
+            Some(x) => writer.uint(4, x as u64),
+            None => (),
+        }
+    }
+}
+
+impl Encode for StopTimeEvent {
+    fn encode(&self, writer: &mut Writer) {
+        match self.delay {
+            Some(x) => writer.int(1, x as i64),
+            None => (),
+        }
+        writer.int(2, self.time);
+    }
+}
+
+impl Encode for StopTimeUpdate {
+    fn encode(&self, writer: &mut Writer) {
+        match self.stop_sequence {
+            Some(x) => writer.uint(1, x as u64),
+            None => (),
+        }
+        match &self.arrival {
+            Some(x) => writer.message(2, x),
+            None => (),
+        }
+        match &self.departure {
+            Some(x) => writer.message(3, x),
+            None => (),
+        }
+        writer.string(4, &self.stop_id);
+    }
+}
+
+impl Encode for TripUpdate {
+    fn encode(&self, writer: &mut Writer) {
+        writer.message(1, &self.trip);
+        for x in &self.stop_time_update {
+            writer.message(2, x);
+        }
+    }
+}
+
+impl Encode for TimeRange {
+    fn encode(&self, writer: &mut Writer) {
+        match self.start {
+            Some(x) => writer.uint(1, x),
+            None => (),
+        }
+        match self.end {
+            Some(x) => writer.uint(2, x),
+            None => (),
+        }
+    }
+}
+
+impl Encode for EntitySelector {
+    fn encode(&self, writer: &mut Writer) {
+        match &self.agency_id {
+            Some(x) => writer.string(1, x),
+            None => (),
+        }
+        match &self.trip {
+            Some(x) => writer.message(4, x),
+            None => (),
+        }
+        match &self.stop_id {
+            Some(x) => writer.string(5, x),
+            None => (),
+        }
+    }
+}
+
+impl Encode for Translation {
+    fn encode(&self, writer: &mut Writer) {
+        writer.string(1, &self.text);
+    }
+}
+
+impl Encode for TranslatedString {
+    fn encode(&self, writer: &mut Writer) {
+        for x in &self.translation {
+            writer.message(1, x);
+        }
+    }
+}
+
+impl Encode for GtfsRtAlert {
+    fn encode(&self, writer: &mut Writer) {
+        for x in &self.active_period {
+            writer.message(1, x);
+        }
+        for x in &self.informed_entity {
+            writer.message(5, x);
+        }
+        writer.uint(6, self.cause as u64);
+        writer.uint(7, self.effect as u64);
+        writer.message(10, &self.header_text);
+        match &self.description_text {
+            Some(x) => writer.message(11, x),
+            None => (),
+        }
+    }
+}
+
+impl Encode for FeedEntity {
+    fn encode(&self, writer: &mut Writer) {
+        writer.string(1, &self.id);
+        match &self.trip_update {
+            Some(x) => writer.message(3, x),
+            None => (),
+        }
+        match &self.alert {
+            Some(x) => writer.message(5, x),
+            None => (),
+        }
+    }
+}
+
+impl Encode for FeedMessage {
+    fn encode(&self, writer: &mut Writer) {
+        writer.message(1, &self.header);
+        for x in &self.entity {
+            writer.message(2, x);
+        }
+    }
+}
+
+impl FeedMessage {
+    // as protobuf, which is what GTFS-RT consumers expect
+    pub fn to_protobuf(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+        self.encode(&mut writer);
+        writer.0
+    }
+}
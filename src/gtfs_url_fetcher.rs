@@ -1,32 +1,104 @@
+use crate::download_cache::DownloadCache;
 use crate::error::Error;
+use crate::fetch_core::HttpClient;
 use crate::fetcher::GtfsFetcher;
 
 use async_trait::async_trait;
 
 use gtfs_structures::{Gtfs, GtfsReader};
 
+use tokio::io::AsyncReadExt;
+
+use std::io::Cursor;
+use std::sync::Arc;
+
 pub struct GtfsUrlFetcher {
+    client: Arc<HttpClient>,
     url: String,
     source: String,
+    name: String, // in the cache and the download metrics
+    cache: Option<Arc<DownloadCache>>,
 }
 
 impl GtfsUrlFetcher {
-    pub fn new(url: &str, source: &str) -> Self {
+    pub fn new(client: Arc<HttpClient>, url: &str, source: &str, name: &str) -> Self {
+        Self::new_with_cache(client, url, source, name, None)
+    }
+
+    pub fn new_with_cache(
+        client: Arc<HttpClient>,
+        url: &str,
+        source: &str,
+        name: &str,
+        cache: Option<Arc<DownloadCache>>,
+    ) -> Self {
         Self {
+            client,
             url: url.to_string(),
             source: source.to_string(),
+            name: name.to_string(),
+            cache,
         }
     }
+
+    fn reader() -> GtfsReader {
+        GtfsReader::default()
+            .read_shapes(true) // for route geometry
+            .unkown_enum_as_default(false)
+    }
+
+    // GTFS needs seeking around the zip, so download it into the cache and read it from there
+    async fn fetch_via_cache(&self, cache: &DownloadCache) -> Result<Gtfs, Error> {
+        let name = &self.name;
+        let version = if cache.is_offline() {
+            cache.latest(name).await?
+        } else {
+            match self.client.get(name, &self.url, None).await {
+                Ok(response) => {
+                    cache
+                        .store(name, self.client.reader(name, response))
+                        .await?
+                }
+                Err(x) => {
+                    println!(
+                        "WARNING: Failed to fetch {}, trying the cache instead: {}",
+                        name, x
+                    );
+                    cache.latest(name).await?
+                }
+            }
+        };
+
+        let path = cache.path_of(&version);
+        Ok(tokio::task::spawn_blocking(move || {
+            Self::reader().read_from_path(path.display().to_string())
+        })
+        .await??)
+    }
 }
 
 #[async_trait]
 impl GtfsFetcher for GtfsUrlFetcher {
     async fn fetch(&self) -> Result<Gtfs, Error> {
         println!("Fetching GTFS from {}", self.source);
-        Ok(GtfsReader::default()
-            .read_shapes(false)
-            .unkown_enum_as_default(false)
-            .read_from_url_async(self.url.clone())
-            .await?)
+        match &self.cache {
+            Some(cache) => self.fetch_via_cache(cache).await,
+            None => {
+                let response = self.client.get(&self.name, &self.url, None).await?;
+                let mut bytes = vec![];
+                self.client
+                    .reader(&self.name, response)
+                    .read_to_end(&mut bytes)
+                    .await?;
+                // only the raw reader takes anything but a path
+                Ok(tokio::task::spawn_blocking(move || {
+                    Self::reader()
+                        .raw()
+                        .read_from_reader(Cursor::new(bytes))
+                        .and_then(Gtfs::try_from)
+                })
+                .await??)
+            }
+        }
     }
 }
@@ -1,12 +1,20 @@
+use crate::change_log::TrainChange;
 use crate::error::Error;
 use crate::schedule::Schedule;
 
 use async_trait::async_trait;
 
+use serde::Serialize;
+
+use std::fmt;
+
 use tokio::io::AsyncBufReadExt;
 
 use gtfs_structures::Gtfs;
 
+/// Parses a (potentially huge) text feed, such as a CIF file, line by line and overlays it on top
+/// of an existing schedule. Full extracts should be given an empty schedule; updates should be
+/// given the schedule the previous import produced.
 #[async_trait]
 pub trait SlowStreamingImporter {
     async fn overlay(
@@ -16,18 +24,68 @@ pub trait SlowStreamingImporter {
     ) -> Result<Schedule, Error>;
 }
 
+/// Same as SlowStreamingImporter, but for an already-parsed GTFS feed.
 #[async_trait]
 pub trait SlowGtfsImporter {
     async fn overlay(&mut self, gtfs: Gtfs, schedule: Schedule) -> Result<Schedule, Error>;
 }
 
+/// Applies a single small message (e.g. one VSTP JSON message) to a schedule. This is called with
+/// the schedule write lock held, so it must not block.
 #[async_trait]
 pub trait FastImporter {
     fn overlay(&self, data: Vec<u8>, schedule: Schedule) -> Result<Schedule, Error>;
+
+    /// The IDs of the trains changed by overlays since this was last called, for the schedule
+    /// manager's hooks. Importers which can't easily tell just say nothing.
+    fn take_changed_trains(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// What those overlays did to each train, by train ID, for the schedule manager's change log.
+    /// Importers which can't easily tell just say nothing.
+    fn take_changes(&self) -> Vec<(String, TrainChange)> {
+        vec![]
+    }
 }
 
+/// An importer whose data would otherwise be lost when the underlying schedule is reloaded from
+/// scratch. `repopulate` reapplies everything it has seen since to a fresh schedule, and `persist`
+/// saves that to disk so it survives restarts.
 #[async_trait]
 pub trait EphemeralImporter {
     async fn repopulate(&self, schedule: Schedule) -> Result<Schedule, Error>;
     async fn persist(&self) -> Result<(), Error>;
 }
+
+// a broken feed is usually broken the same way thousands of times, so only keep the first few
+const MAX_REPORTED_ERRORS: usize = 100;
+
+/// What a lenient import skipped over rather than giving up on the whole feed.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub errors: Vec<String>,
+    pub error_count: usize,
+    pub warnings: Vec<String>, // things we made sense of, but shouldn't have had to
+    pub warning_count: usize,
+}
+
+impl ImportReport {
+    pub fn add(&mut self, error: &impl fmt::Display) {
+        if self.errors.len() < MAX_REPORTED_ERRORS {
+            self.errors.push(error.to_string());
+        }
+        self.error_count += 1;
+    }
+
+    pub fn warn(&mut self, warning: &impl fmt::Display) {
+        if self.warnings.len() < MAX_REPORTED_ERRORS {
+            self.warnings.push(warning.to_string());
+        }
+        self.warning_count += 1;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.error_count == 0
+    }
+}
@@ -0,0 +1,277 @@
+use crate::error::Error;
+use crate::partitioned_map::PartitionedMap;
+use crate::schedule::{train_index_keys, Schedule, Train};
+use crate::schedule_manager::ScheduleManager;
+
+use chrono::{DateTime, Utc};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+// Checks every so often that what's loaded still holds together: that the indexes match the trains
+// and locations, that every route goes somewhere and that every association's other train is
+// there. None of this should ever fail, so when it does it's an importer bug, and better caught
+// here than by someone finding a train missing from a board.
+
+#[derive(Clone, Default, Deserialize)]
+pub struct IntegrityConfig {
+    check_interval_secs: Option<u64>, // 3600 if not given
+    max_examples: Option<usize>,      // of each kind of violation, per namespace; 10 if not given
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct IntegrityReport {
+    pub namespace: String,
+    pub checked: Option<DateTime<Utc>>,
+    pub last_imported: Option<DateTime<Utc>>, // of what was checked
+    pub missing_index_entries: usize, // a train or location not indexed under something it has
+    pub dangling_index_entries: usize, // index entries for trains or locations that aren't there
+    pub short_routes: usize,          // schedules with fewer than two locations
+    pub missing_association_targets: usize, // associations with a train that isn't there
+    pub violations: usize,            // all of the above
+    pub examples: Vec<String>,
+}
+
+pub struct Integrity {
+    schedule_manager: Arc<ScheduleManager>,
+    check_interval: Duration,
+    max_examples: usize,
+    reports: RwLock<HashMap<String, IntegrityReport>>, // the latest, by namespace
+}
+
+struct Checker {
+    report: IntegrityReport,
+    max_examples: usize,
+    examples: HashMap<&'static str, usize>,
+}
+
+impl Checker {
+    fn violation(&mut self, kind: &'static str, example: impl FnOnce() -> String) {
+        match kind {
+            "missing_index_entries" => self.report.missing_index_entries += 1,
+            "dangling_index_entries" => self.report.dangling_index_entries += 1,
+            "short_routes" => self.report.short_routes += 1,
+            "missing_association_targets" => self.report.missing_association_targets += 1,
+            _ => unreachable!(),
+        }
+        self.report.violations += 1;
+        let examples = self.examples.entry(kind).or_insert(0);
+        if *examples < self.max_examples {
+            *examples += 1;
+            self.report.examples.push(example());
+        }
+    }
+
+    fn check_train(&mut self, schedule: &Schedule, train_id: &str, train: &Train) {
+        let length = train.route.peek(|x| x.len());
+        if length < 2 {
+            self.violation("short_routes", || {
+                format!("{} has {} locations", train_id, length)
+            });
+        }
+        let other_train_ids = train.route.peek(|route| {
+            route
+                .iter()
+                .flat_map(|x| {
+                    x.divides_to_form
+                        .iter()
+                        .chain(x.joins_to.iter())
+                        .chain(x.becomes.iter())
+                })
+                .map(|x| x.other_train_id.to_string())
+                .collect::<Vec<_>>()
+        });
+        for other_train_id in other_train_ids {
+            if !schedule.trains.contains_key(&other_train_id) {
+                self.violation("missing_association_targets", || {
+                    format!(
+                        "{} is associated with {}, which isn't there",
+                        train_id, other_train_id
+                    )
+                });
+            }
+        }
+        for replacement in &train.replacements {
+            self.check_train(schedule, train_id, replacement);
+        }
+    }
+
+    fn check_index(
+        &mut self,
+        name: &str,
+        index: &PartitionedMap<HashSet<String>>,
+        exists: impl Fn(&str) -> bool,
+    ) {
+        for (key, ids) in index {
+            for id in ids {
+                if !exists(id) {
+                    self.violation("dangling_index_entries", || {
+                        format!("{} has {} under {}, which isn't there", name, id, key)
+                    });
+                }
+            }
+        }
+    }
+
+    fn check_indexed(
+        &mut self,
+        name: &str,
+        index: &PartitionedMap<HashSet<String>>,
+        id: &str,
+        key: &str,
+    ) {
+        if !index.get(key).is_some_and(|x| x.contains(id)) {
+            self.violation("missing_index_entries", || {
+                format!("{} doesn't have {} under {}", name, id, key)
+            });
+        }
+    }
+}
+
+pub fn integrity_report(schedule: &Schedule, max_examples: usize) -> IntegrityReport {
+    let mut checker = Checker {
+        report: IntegrityReport {
+            namespace: schedule.namespace.clone(),
+            checked: Some(Utc::now()),
+            last_imported: schedule.last_imported,
+            ..Default::default()
+        },
+        max_examples,
+        examples: HashMap::new(),
+    };
+
+    for (train_id, trains) in &schedule.trains {
+        for train in trains {
+            checker.check_train(schedule, train_id, train);
+        }
+        let keys = train_index_keys(trains);
+        for (name, index, keys) in [
+            (
+                "trains_indexed_by_location",
+                &schedule.trains_indexed_by_location,
+                keys.locations,
+            ),
+            (
+                "trains_indexed_by_public_id",
+                &schedule.trains_indexed_by_public_id,
+                keys.public_ids,
+            ),
+            (
+                "trains_indexed_by_uic",
+                &schedule.trains_indexed_by_uic,
+                keys.uic_codes,
+            ),
+        ] {
+            for key in keys {
+                checker.check_indexed(name, index, train_id, &key);
+            }
+        }
+    }
+    for location in schedule.locations.values() {
+        match &location.public_id {
+            Some(x) => checker.check_indexed(
+                "locations_indexed_by_public_id",
+                &schedule.locations_indexed_by_public_id,
+                &location.id,
+                x,
+            ),
+            None => (),
+        }
+    }
+
+    let train_exists = |x: &str| schedule.trains.contains_key(x);
+    let location_exists = |x: &str| schedule.locations.contains_key(x);
+    checker.check_index(
+        "trains_indexed_by_location",
+        &schedule.trains_indexed_by_location,
+        train_exists,
+    );
+    checker.check_index(
+        "trains_indexed_by_public_id",
+        &schedule.trains_indexed_by_public_id,
+        train_exists,
+    );
+    checker.check_index(
+        "trains_indexed_by_uic",
+        &schedule.trains_indexed_by_uic,
+        train_exists,
+    );
+    checker.check_index(
+        "locations_indexed_by_public_id",
+        &schedule.locations_indexed_by_public_id,
+        location_exists,
+    );
+    checker.check_index(
+        "locations_indexed_by_stanox",
+        &schedule.locations_indexed_by_stanox,
+        location_exists,
+    );
+
+    checker.report
+}
+
+impl Integrity {
+    pub fn new(config: IntegrityConfig, schedule_manager: Arc<ScheduleManager>) -> Self {
+        Self {
+            schedule_manager,
+            check_interval: Duration::from_secs(config.check_interval_secs.unwrap_or(3600)),
+            max_examples: config.max_examples.unwrap_or(10),
+            reports: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // the latest for each namespace that's been checked, by name
+    pub fn reports(&self) -> Vec<IntegrityReport> {
+        let mut reports = self
+            .reports
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        reports.sort_by(|x, y| x.namespace.cmp(&y.namespace));
+        reports
+    }
+
+    pub fn check(&self, schedule: &Schedule) -> IntegrityReport {
+        let report = integrity_report(schedule, self.max_examples);
+        let previous = self
+            .reports
+            .write()
+            .unwrap()
+            .insert(schedule.namespace.clone(), report.clone());
+        // only when it changes, so a namespace that stays broken doesn't fill the log
+        let previous = previous.map_or(0, |x| x.violations);
+        if report.violations > 0 && report.violations != previous {
+            println!(
+                "WARNING: {} integrity violations in {}, e.g. {}",
+                report.violations,
+                report.namespace,
+                report.examples.first().unwrap()
+            );
+        } else if report.violations == 0 && previous > 0 {
+            println!("{} has no integrity violations again", report.namespace);
+        }
+        report
+    }
+
+    pub async fn run(self: Arc<Self>) -> Result<(), Error> {
+        let mut interval = tokio::time::interval(self.check_interval);
+        loop {
+            interval.tick().await;
+            let schedules = self
+                .schedule_manager
+                .read()
+                .values()
+                .cloned()
+                .collect::<Vec<_>>();
+            for schedule in schedules {
+                let integrity = self.clone();
+                tokio::task::spawn_blocking(move || integrity.check(&schedule)).await?;
+            }
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+
+// Where a location is given by public ID (e.g. a CRS code from the ATOC files) it is looked up
+// against the schedule's public ID index as well as its own location IDs.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub struct InterchangeLocation {
+    pub namespace: String,
+    pub id: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MinimumConnectionOverride {
+    location: InterchangeLocation,
+    minimum_connection_secs: u32,
+}
+
+// A way to get between two stations other than by train, e.g. walking between Euston and Euston
+// Square, or between networks. Links always work in both directions.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct FixedLink {
+    pub from: InterchangeLocation,
+    pub to: InterchangeLocation,
+    pub mode: String,
+    pub duration_secs: u32,
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct InterchangeConfig {
+    default_minimum_connection_secs: Option<u32>,
+    msn_file: Option<String>, // ATOC master station names, for the gbnr namespace
+    fixed_links_file: Option<String>, // ATOC fixed links, again for gbnr
+    overrides: Option<Vec<MinimumConnectionOverride>>,
+    links: Option<Vec<FixedLink>>, // including ones between namespaces, e.g. NR and NIR
+}
+
+pub struct Interchange {
+    default_minimum_connection_secs: u32,
+    minimum_connection_secs: HashMap<InterchangeLocation, u32>,
+    links: Vec<FixedLink>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct InterchangeInfo {
+    pub minimum_connection_secs: u32,
+    pub links: Vec<FixedLink>, // always starting from the location asked about
+}
+
+impl Interchange {
+    pub fn new(config: InterchangeConfig) -> Result<Self, Error> {
+        let mut interchange = Self {
+            default_minimum_connection_secs: config.default_minimum_connection_secs.unwrap_or(300),
+            minimum_connection_secs: HashMap::new(),
+            links: vec![],
+        };
+
+        match &config.msn_file {
+            Some(x) => interchange.load_msn(x)?,
+            None => (),
+        }
+        match &config.fixed_links_file {
+            Some(x) => interchange.load_fixed_links(x)?,
+            None => (),
+        }
+
+        // config always wins over the files
+        for mct in config.overrides.unwrap_or_default() {
+            interchange
+                .minimum_connection_secs
+                .insert(mct.location, mct.minimum_connection_secs);
+        }
+        interchange.links.extend(config.links.unwrap_or_default());
+
+        Ok(interchange)
+    }
+
+    // We only care about the station records, e.g.
+    // A    ABBEY WOOD                    2ABWDXR ABW   ABW15473 61790 4
+    // where the last field is the minimum connection time in minutes
+    fn load_msn(&mut self, filename: &str) -> Result<(), Error> {
+        println!("Loading minimum connection times from {}", filename);
+        let contents = std::fs::read_to_string(filename)?;
+        for line in contents.lines() {
+            if !line.starts_with("A    ") || line.len() < 65 {
+                continue;
+            }
+            let crs = line[49..52].trim();
+            let minutes = match line[63..65].trim().parse::<u32>() {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            if crs.is_empty() {
+                continue;
+            }
+            self.minimum_connection_secs.insert(
+                InterchangeLocation {
+                    namespace: "gbnr".to_string(),
+                    id: crs.to_string(),
+                },
+                minutes * 60,
+            );
+        }
+        Ok(())
+    }
+
+    // e.g. ADDITIONAL LINK: WALK BETWEEN EUS AND EUX IN 5 MINUTES
+    fn load_fixed_links(&mut self, filename: &str) -> Result<(), Error> {
+        println!("Loading fixed links from {}", filename);
+        let contents = std::fs::read_to_string(filename)?;
+        for line in contents.lines() {
+            let words = match line.split_once(":") {
+                Some((_, x)) => x.split_whitespace().collect::<Vec<_>>(),
+                None => continue,
+            };
+            match words.as_slice() {
+                [mode, "BETWEEN", from, "AND", to, "IN", minutes, "MINUTES"] => {
+                    let minutes = match minutes.parse::<u32>() {
+                        Ok(x) => x,
+                        Err(_) => {
+                            println!("WARNING: Unreadable fixed link: {}", line);
+                            continue;
+                        }
+                    };
+                    self.links.push(FixedLink {
+                        from: InterchangeLocation {
+                            namespace: "gbnr".to_string(),
+                            id: from.to_string(),
+                        },
+                        to: InterchangeLocation {
+                            namespace: "gbnr".to_string(),
+                            id: to.to_string(),
+                        },
+                        mode: mode.to_lowercase(),
+                        duration_secs: minutes * 60,
+                    });
+                }
+                _ => println!("WARNING: Unreadable fixed link: {}", line),
+            }
+        }
+        Ok(())
+    }
+
+    // `ids` should be every ID the location is known by, i.e. its own and any public one
+    pub fn get(&self, namespace: &str, ids: &[String]) -> InterchangeInfo {
+        let matches = |x: &InterchangeLocation| x.namespace == namespace && ids.contains(&x.id);
+
+        let minimum_connection_secs = ids
+            .iter()
+            .find_map(|id| {
+                self.minimum_connection_secs.get(&InterchangeLocation {
+                    namespace: namespace.to_string(),
+                    id: id.clone(),
+                })
+            })
+            .copied()
+            .unwrap_or(self.default_minimum_connection_secs);
+
+        let mut links = vec![];
+        for link in &self.links {
+            if matches(&link.from) {
+                links.push(link.clone());
+            } else if matches(&link.to) {
+                links.push(FixedLink {
+                    from: link.to.clone(),
+                    to: link.from.clone(),
+                    mode: link.mode.clone(),
+                    duration_secs: link.duration_secs,
+                });
+            }
+        }
+
+        InterchangeInfo {
+            minimum_connection_secs,
+            links,
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::borrow::Borrow;
+use std::collections::HashSet;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// An immutable, cheaply cloned string. On its own it's no smaller than a String, but after
+/// `Interner` has been over a schedule every copy of the same text shares one allocation, which
+/// matters when every train in the country calls at the same few thousand TIPLOCs.
+#[derive(Clone, Eq, Ord, PartialOrd)]
+pub struct IStr(Arc<str>);
+
+impl IStr {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Deref for IStr {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for IStr {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for IStr {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+// must agree with str's, as we're looked up by it
+impl Hash for IStr {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state)
+    }
+}
+
+impl PartialEq for IStr {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0) || self.0 == other.0
+    }
+}
+
+impl PartialEq<str> for IStr {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for IStr {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialEq<String> for IStr {
+    fn eq(&self, other: &String) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<IStr> for String {
+    fn eq(&self, other: &IStr) -> bool {
+        self == other.as_str()
+    }
+}
+
+impl PartialEq<IStr> for str {
+    fn eq(&self, other: &IStr) -> bool {
+        self == other.as_str()
+    }
+}
+
+impl PartialEq<IStr> for &str {
+    fn eq(&self, other: &IStr) -> bool {
+        *self == other.as_str()
+    }
+}
+
+impl From<&str> for IStr {
+    fn from(s: &str) -> Self {
+        Self(Arc::from(s))
+    }
+}
+
+impl From<String> for IStr {
+    fn from(s: String) -> Self {
+        Self(Arc::from(s))
+    }
+}
+
+impl From<&String> for IStr {
+    fn from(s: &String) -> Self {
+        Self(Arc::from(s.as_str()))
+    }
+}
+
+impl From<IStr> for String {
+    fn from(s: IStr) -> Self {
+        s.as_str().to_string()
+    }
+}
+
+impl fmt::Display for IStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.as_str(), f)
+    }
+}
+
+impl fmt::Debug for IStr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Serialize for IStr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for IStr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(String::deserialize(deserializer)?.into())
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InternStats {
+    pub strings: usize, // everything we looked at
+    pub unique: usize,
+    pub bytes_saved: usize, // roughly: the text of every duplicate, plus Arc's counts
+}
+
+impl fmt::Display for InternStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} strings, {} unique, about {} KiB saved",
+            self.strings,
+            self.unique,
+            self.bytes_saved / 1024
+        )
+    }
+}
+
+/// A pool of strings seen so far. Importers create strings as they please; run the result through
+/// one of these afterwards (see Schedule::intern_strings) to share them out.
+#[derive(Default)]
+pub struct Interner {
+    pool: HashSet<IStr>,
+    stats: InternStats,
+}
+
+impl Interner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // one that shares out these, e.g. strings a schedule has already been interned with
+    pub fn with_strings(strings: impl IntoIterator<Item = IStr>) -> Self {
+        Self {
+            pool: strings.into_iter().collect(),
+            stats: InternStats::default(),
+        }
+    }
+
+    pub fn intern(&mut self, s: &mut IStr) {
+        self.stats.strings += 1;
+        match self.pool.get(s.as_str()) {
+            Some(x) => {
+                if !Arc::ptr_eq(&x.0, &s.0) {
+                    // only actually saved once the old copy is dropped, but it will be
+                    self.stats.bytes_saved += s.len() + 2 * std::mem::size_of::<usize>();
+                    *s = x.clone();
+                }
+            }
+            None => {
+                self.pool.insert(s.clone());
+            }
+        }
+    }
+
+    pub fn intern_option(&mut self, s: &mut Option<IStr>) {
+        match s {
+            Some(x) => self.intern(x),
+            None => (),
+        }
+    }
+
+    // every string in the pool, so another can be started from them later
+    pub fn into_strings(self) -> Vec<IStr> {
+        self.pool.into_iter().collect()
+    }
+
+    pub fn stats(&self) -> InternStats {
+        InternStats {
+            unique: self.pool.len(),
+            ..self.stats
+        }
+    }
+}
@@ -1,30 +1,45 @@
-use crate::error::Error;
+use crate::download_cache::DownloadCache;
+use crate::error::{Error, ErrorContext};
+use crate::fetch_core::HttpClient;
 use crate::fetcher::GtfsFetcher;
 use crate::gtfs_importer::GtfsImporter;
 use crate::gtfs_url_fetcher::GtfsUrlFetcher;
 use crate::importer::SlowGtfsImporter;
-use crate::manager::Manager;
+use crate::manager::{ImportScheduler, Manager};
 use crate::schedule::Schedule;
 use crate::schedule_manager::ScheduleManager;
+use crate::triggers::{DailyRun, Triggers};
 
-use chrono::offset::Utc;
-use chrono::{Days, NaiveTime, TimeZone};
+use chrono::NaiveTime;
 use chrono_tz::Europe::Dublin;
 
-use tokio::time;
-use tokio::time::Duration;
-
 use async_trait::async_trait;
 
 use std::sync::Arc;
 
 pub struct IrManager {
     schedule_manager: Arc<ScheduleManager>,
+    download_cache: Option<Arc<DownloadCache>>,
+    http_client: Arc<HttpClient>,
+    triggers: Arc<Triggers>,
+    import_scheduler: Arc<ImportScheduler>,
 }
 
 impl IrManager {
-    pub async fn new(schedule_manager: Arc<ScheduleManager>) -> Result<IrManager, Error> {
-        Ok(IrManager { schedule_manager })
+    pub async fn new(
+        schedule_manager: Arc<ScheduleManager>,
+        download_cache: Option<Arc<DownloadCache>>,
+        http_client: Arc<HttpClient>,
+        triggers: Arc<Triggers>,
+        import_scheduler: Arc<ImportScheduler>,
+    ) -> Result<IrManager, Error> {
+        Ok(IrManager {
+            schedule_manager,
+            download_cache,
+            http_client,
+            triggers,
+            import_scheduler,
+        })
     }
 
     async fn reload_gtfs(
@@ -32,23 +47,32 @@ impl IrManager {
         gtfs_fetcher: &GtfsUrlFetcher,
         gtfs_importer: &mut GtfsImporter,
     ) -> Result<(), Error> {
+        let slot = self.import_scheduler.start("ieir").await;
         {
-            // lock for writing now, such that there will be no chance of smaller updates being
-            // lost
-            let mut transaction = self.schedule_manager.transactional_write().await;
-
             let mut schedule = Schedule::new(
                 "ieir".to_string(),
                 "Ireland — Irish Rail/Iarnród Éireann".to_string(),
             );
 
-            let gtfs = gtfs_fetcher.fetch().await?;
-            schedule = gtfs_importer.overlay(gtfs, schedule).await?;
+            let gtfs = gtfs_fetcher
+                .fetch()
+                .await
+                .context("Fetching Irish Rail GTFS")?;
+            schedule = gtfs_importer
+                .overlay(gtfs, schedule)
+                .await
+                .context("Importing Irish Rail GTFS")?;
+
+            // nothing else changes it, so there's nothing to lose by only locking now
+            let mut transaction = self.schedule_manager.transactional_write().await;
 
             // always replace the schedule
-            transaction.insert("ieir".to_string(), schedule);
+            transaction.put("ieir", schedule);
+            transaction.source_updated("ieir-gtfs");
+            transaction.archive();
             transaction.commit();
         }
+        slot.finished();
 
         Ok(())
     }
@@ -58,26 +82,15 @@ impl IrManager {
         gtfs_fetcher: &GtfsUrlFetcher,
         gtfs_importer: &mut GtfsImporter,
     ) -> Result<(), Error> {
+        let mut daily = DailyRun::new(
+            Dublin,
+            NaiveTime::from_hms_opt(4, 4, 0).unwrap(),
+            &self.triggers,
+            "ieir-gtfs",
+        );
         loop {
-            let now = Dublin.from_utc_datetime(&Utc::now().naive_utc());
-            let new_time = if now.time() > NaiveTime::from_hms_opt(4, 4, 0).unwrap() {
-                Dublin
-                    .from_local_datetime(
-                        &now.date_naive()
-                            .checked_add_days(Days::new(1))
-                            .unwrap()
-                            .and_hms_opt(4, 4, 0)
-                            .unwrap(),
-                    )
-                    .unwrap()
-            } else {
-                Dublin
-                    .from_local_datetime(&now.date_naive().and_hms_opt(4, 4, 0).unwrap())
-                    .unwrap()
-            };
-            let mut interval = time::interval(Duration::from_secs(15));
-            while Dublin.from_utc_datetime(&Utc::now().naive_utc()) < new_time {
-                interval.tick().await;
+            if daily.wait().await {
+                println!("Fetching Irish Rail GTFS early, as upstream says it's out");
             }
 
             self.reload_gtfs(gtfs_fetcher, gtfs_importer).await?;
@@ -88,9 +101,12 @@ impl IrManager {
 #[async_trait]
 impl Manager for IrManager {
     async fn run(&mut self) -> Result<(), Error> {
-        let gtfs_fetcher = GtfsUrlFetcher::new(
+        let gtfs_fetcher = GtfsUrlFetcher::new_with_cache(
+            self.http_client.clone(),
             "https://www.transportforireland.ie/transitData/Data/GTFS_Irish_Rail.zip",
             "the National Transport Authority",
+            "ieir-gtfs",
+            self.download_cache.clone(),
         );
         let mut gtfs_importer = GtfsImporter::new();
 
@@ -0,0 +1,79 @@
+//! World Rail Timetables: parsers, importers and an in-memory schedule store for rail
+//! timetable data (Network Rail CIF/VSTP, NIR CIF, GTFS). The web UI is in here too, but nothing
+//! stops you using the importers on their own.
+
+// the OpenAPI document is one big json! macro
+#![recursion_limit = "256"]
+
+pub mod alerts;
+pub mod area_filter;
+pub mod branding;
+pub mod change_log;
+pub mod cold_routes;
+pub mod consist_importer;
+pub mod consistency;
+pub mod delay_propagation;
+pub mod download_cache;
+pub mod dump;
+pub mod duplicates;
+pub mod dwell;
+pub mod environments;
+pub mod error;
+pub mod feed_telemetry;
+pub mod fetch_core;
+pub mod fetcher;
+pub mod file_fetcher;
+pub mod flows;
+pub mod gtfs_delta_manager;
+pub mod gtfs_importer;
+pub mod gtfs_rt;
+pub mod gtfs_url_fetcher;
+pub mod importer;
+pub mod integrity;
+pub mod interchange;
+pub mod intern;
+pub mod ir_manager;
+pub mod live_positions;
+pub mod localisation;
+pub mod manager;
+pub mod movements;
+pub mod nir_fetcher;
+pub mod nir_manager;
+pub mod notifications;
+pub mod nr_fetcher;
+pub mod nr_manager;
+pub mod nr_trust_importer;
+pub mod nr_vstp_subscriber;
+pub mod openapi;
+pub mod output_format;
+pub mod paging;
+pub mod partitioned_map;
+pub mod platform_occupancy;
+pub mod proxy;
+pub mod quality;
+pub mod query_cache;
+pub mod redaction;
+pub mod regions;
+pub mod resolved_train;
+pub mod response_headers;
+pub mod restrictions_importer;
+pub mod route_geometry;
+pub mod running_days;
+pub mod schedule;
+pub mod schedule_manager;
+pub mod segments;
+pub mod snapshot;
+pub mod sncf_fetcher;
+pub mod sql_export;
+pub mod staleness;
+pub mod station_groups;
+pub mod subscriber;
+pub mod timetable_export;
+pub mod train_id;
+pub mod train_names_importer;
+pub mod triggers;
+pub mod uk_importer;
+pub mod validation;
+pub mod vehicles;
+pub mod vstp_replay;
+pub mod webui;
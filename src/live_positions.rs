@@ -0,0 +1,221 @@
+use crate::delay_propagation::expected_times;
+use crate::route_geometry::RouteGeometry;
+use crate::schedule::{get_train_instance, Coordinate, LocalTimes, Schedule, TrainRealtime};
+use crate::train_id::GlobalTrainId;
+
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+// Where trains probably are right now, for putting on a map. We only hear from a train when it
+// reports at a timing point, so between reports it's assumed to keep to the timetable, running as
+// late as delay_propagation expects, and is placed along the track in proportion to the time. This is a guess,
+// and no substitute for berth-by-berth train describer data.
+
+// west,south,east,north in degrees, as GeoJSON has it
+#[derive(Clone, Copy, Debug)]
+pub struct BoundingBox {
+    west: f64,
+    south: f64,
+    east: f64,
+    north: f64,
+}
+
+impl BoundingBox {
+    pub fn parse(s: &str) -> Option<Self> {
+        let values = s
+            .split(',')
+            .map(|x| x.trim().parse::<f64>().ok())
+            .collect::<Option<Vec<_>>>()?;
+        match values[..] {
+            [west, south, east, north] if south <= north => Some(Self {
+                west,
+                south,
+                east,
+                north,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn contains(&self, position: &Coordinate) -> bool {
+        let in_longitude = match self.west <= self.east {
+            true => position.longitude >= self.west && position.longitude <= self.east,
+            // across the antimeridian
+            false => position.longitude >= self.west || position.longitude <= self.east,
+        };
+        in_longitude && position.latitude >= self.south && position.latitude <= self.north
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LivePosition {
+    pub global_id: GlobalTrainId,
+    pub namespace: String,
+    pub train_id: String,
+    pub public_id: Option<String>,
+    pub position: Coordinate,
+    pub from: String,       // the location it's at or last left
+    pub to: Option<String>, // the next one, unless it's standing at `from`
+    pub delay_minutes: i64, // as of its last report
+    pub last_report: DateTime<Tz>,
+}
+
+// Expected arrival and departure at a located point on the route. A train that passes is both.
+struct Expected {
+    location_id: String,
+    arrival: DateTime<Tz>,
+    departure: DateTime<Tz>,
+}
+
+fn estimate(
+    schedule: &Schedule,
+    geometry: &RouteGeometry,
+    train_id: &str,
+    global_id: GlobalTrainId,
+    realtime: &TrainRealtime,
+    now: DateTime<Utc>,
+) -> Option<LivePosition> {
+    if realtime.terminated || realtime.cancellation.is_some() {
+        return None;
+    }
+    let (train, _, _) = get_train_instance(schedule.trains.get(train_id)?, global_id.date);
+    let train = train?;
+
+    // work along the route with the reports, so a location visited twice matches the right visit
+    let mut reported = None;
+    let mut cursor = 0;
+    for event in &realtime.events {
+        match train.route[cursor..]
+            .iter()
+            .position(|x| event.location_ids.iter().any(|y| y == x.id.as_str()))
+        {
+            Some(x) => {
+                cursor += x;
+                reported = Some((cursor, event));
+            }
+            None => (),
+        }
+    }
+    let (reported_index, event) = reported?;
+    if reported_index + 1 == train.route.len() {
+        return None;
+    }
+    let planned = train
+        .route
+        .iter()
+        .map(|x| match schedule.locations.get(x.id.as_str()) {
+            Some(y) => x.local_times(global_id.date, y.timezone),
+            None => LocalTimes::default(),
+        })
+        .collect::<Vec<_>>();
+    let expected = expected_times(realtime, &train.route, &planned);
+
+    let mut timeline = vec![];
+    for (i, location) in train.route.iter().enumerate().skip(reported_index) {
+        let expected = match &expected[i] {
+            Some(x) => x,
+            None => continue,
+        };
+        let arrival = expected.arrival.or(expected.pass).or(expected.departure);
+        let departure = expected.departure.or(expected.pass).or(expected.arrival);
+        let (arrival, mut departure) = match (arrival, departure) {
+            (Some(x), Some(y)) => (x.time, y.time),
+            _ => continue,
+        };
+        if i == reported_index {
+            // it can't leave before it got there, however late it is
+            departure = std::cmp::max(departure, arrival);
+        }
+        if geometry.position(schedule, &location.id).is_some() {
+            timeline.push(Expected {
+                location_id: location.id.to_string(),
+                arrival,
+                departure,
+            });
+        }
+    }
+
+    // somewhere before the first place we can put on the map
+    if timeline.first()?.location_id != train.route[reported_index].id.as_str() {
+        return None;
+    }
+
+    let now = now.with_timezone(&event.actual.timezone());
+    let (from, to, fraction) = match timeline.iter().position(|x| x.arrival > now) {
+        // still standing at the last place it reported from
+        Some(0) => (&timeline[0], None, 0.0),
+        Some(i) if timeline[i - 1].departure > now => (&timeline[i - 1], None, 0.0),
+        Some(i) => {
+            let (from, to) = (&timeline[i - 1], &timeline[i]);
+            let fraction = (now - from.departure).num_seconds() as f64
+                / std::cmp::max((to.arrival - from.departure).num_seconds(), 1) as f64;
+            (from, Some(to), fraction)
+        }
+        // should have got there by now, so wait for it to say so
+        None => (timeline.last()?, None, 0.0),
+    };
+    let position = match to {
+        Some(to) => geometry.between(schedule, &from.location_id, &to.location_id, fraction)?,
+        None => geometry.position(schedule, &from.location_id)?,
+    };
+
+    Some(LivePosition {
+        namespace: global_id.namespace.clone(),
+        global_id,
+        train_id: train_id.to_string(),
+        public_id: train.variable_train.public_id.clone(),
+        position,
+        from: from.location_id.clone(),
+        to: to.map(|x| x.location_id.clone()),
+        delay_minutes: event.delay_minutes,
+        last_report: event.actual,
+    })
+}
+
+pub fn estimate_positions(
+    schedule: &Schedule,
+    geometry: &RouteGeometry,
+    now: DateTime<Utc>,
+    bbox: Option<&BoundingBox>,
+) -> Vec<LivePosition> {
+    let mut positions = vec![];
+    for (train_id, dates) in &schedule.realtime {
+        for (date, realtime) in dates {
+            // nothing runs for more than a couple of days
+            if *date > now.date_naive() || *date + Duration::days(3) < now.date_naive() {
+                continue;
+            }
+            let global_id = GlobalTrainId::new(&schedule.namespace, train_id, *date);
+            match estimate(schedule, geometry, train_id, global_id, realtime, now) {
+                Some(x) if bbox.map_or(true, |y| y.contains(&x.position)) => positions.push(x),
+                _ => (),
+            }
+        }
+    }
+    positions.sort_by(|x, y| x.global_id.cmp(&y.global_id));
+    positions
+}
+
+pub fn positions_to_geojson(positions: &[LivePosition]) -> Value {
+    json!({
+        "type": "FeatureCollection",
+        "features": positions
+            .iter()
+            .map(|x| {
+                let mut properties = serde_json::to_value(x).unwrap();
+                properties.as_object_mut().unwrap().remove("position");
+                json!({
+                    "type": "Feature",
+                    "geometry": {
+                        "type": "Point",
+                        "coordinates": [x.position.longitude, x.position.latitude],
+                    },
+                    "properties": properties,
+                })
+            })
+            .collect::<Vec<_>>(),
+    })
+}
@@ -0,0 +1,148 @@
+use crate::error::Error;
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+// Per-language overrides for display names, e.g. Welsh station names on the gbnr namespace or
+// Irish ones on ieir. Anything without an override keeps the name from the feed.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LocalisedNames {
+    #[serde(default)]
+    locations: HashMap<String, String>,
+    #[serde(default)]
+    operators: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NamespaceLocalisation {
+    default_language: Option<String>, // the language the feed's own names are already in
+    #[serde(default)]
+    languages: HashMap<String, LocalisedNames>,
+}
+
+// keyed by namespace
+#[derive(Clone, Debug, Default)]
+pub struct Localisation {
+    namespaces: HashMap<String, NamespaceLocalisation>,
+}
+
+impl Localisation {
+    pub fn new(namespaces: HashMap<String, NamespaceLocalisation>) -> Self {
+        Self { namespaces }
+    }
+
+    // Files are JSON of the same shape as the config, later ones overriding earlier ones
+    pub fn load_file(&mut self, filename: &str) -> Result<(), Error> {
+        println!("Loading localisation from {}", filename);
+        let contents = std::fs::read_to_string(filename)?;
+        let namespaces = serde_json::from_str::<HashMap<String, NamespaceLocalisation>>(&contents)?;
+        self.merge(namespaces);
+        Ok(())
+    }
+
+    pub fn merge(&mut self, namespaces: HashMap<String, NamespaceLocalisation>) {
+        for (namespace, localisation) in namespaces {
+            let existing = self.namespaces.entry(namespace).or_default();
+            if localisation.default_language.is_some() {
+                existing.default_language = localisation.default_language.map(|x| x.to_lowercase());
+            }
+            for (language, names) in localisation.languages {
+                let existing_names = existing
+                    .languages
+                    .entry(language.to_lowercase())
+                    .or_default();
+                existing_names.locations.extend(names.locations);
+                existing_names.operators.extend(names.operators);
+            }
+        }
+    }
+
+    // Works out which overrides apply for someone who reads these languages, in order of
+    // preference. Languages after the feed's own one are never used.
+    fn effective<'a>(
+        &'a self,
+        namespace: &str,
+        languages: &[String],
+        get: impl Fn(&'a LocalisedNames) -> &'a HashMap<String, String>,
+    ) -> HashMap<&'a str, &'a str> {
+        let mut names = HashMap::new();
+        let localisation = match self.namespaces.get(namespace) {
+            Some(x) => x,
+            None => return names,
+        };
+        let languages = match languages
+            .iter()
+            .position(|x| localisation.default_language.as_ref() == Some(x))
+        {
+            Some(x) => &languages[..x],
+            None => languages,
+        };
+        // least preferred first, so better matches overwrite
+        for language in languages.iter().rev() {
+            match localisation.languages.get(language) {
+                Some(x) => names.extend(get(x).iter().map(|(k, v)| (k.as_str(), v.as_str()))),
+                None => (),
+            }
+        }
+        names
+    }
+
+    pub fn location_names<'a>(
+        &'a self,
+        namespace: &str,
+        languages: &[String],
+    ) -> HashMap<&'a str, &'a str> {
+        self.effective(namespace, languages, |x| &x.locations)
+    }
+
+    pub fn operator_names<'a>(
+        &'a self,
+        namespace: &str,
+        languages: &[String],
+    ) -> HashMap<&'a str, &'a str> {
+        self.effective(namespace, languages, |x| &x.operators)
+    }
+}
+
+// Turns an Accept-Language header into a list of language tags, most preferred first. Each tag
+// is followed by its primary subtag, so cy-GB falls back to cy before moving on.
+pub fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut weighted = header
+        .split(",")
+        .filter_map(|part| {
+            let mut pieces = part.split(";");
+            let tag = pieces.next()?.trim().to_lowercase();
+            if tag.is_empty() || tag == "*" {
+                return None;
+            }
+            let mut weight = 1.0;
+            for piece in pieces {
+                match piece.trim().strip_prefix("q=") {
+                    Some(x) => weight = x.trim().parse::<f64>().ok()?,
+                    None => (),
+                }
+            }
+            if weight <= 0.0 {
+                return None;
+            }
+            Some((tag, weight))
+        })
+        .collect::<Vec<_>>();
+    // stable, so equal weights keep the order they were sent in
+    weighted.sort_by(|(_, a), (_, b)| b.partial_cmp(a).unwrap());
+
+    let mut languages = vec![];
+    for (tag, _) in weighted {
+        let primary = tag.split("-").next().unwrap().to_string();
+        if !languages.contains(&tag) {
+            languages.push(tag);
+        }
+        if !languages.contains(&primary) {
+            languages.push(primary);
+        }
+    }
+    languages
+}
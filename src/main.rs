@@ -1,70 +1,504 @@
-mod error;
-mod fetcher;
-mod gtfs_importer;
-mod gtfs_url_fetcher;
-mod importer;
-mod ir_manager;
-mod manager;
-mod nir_fetcher;
-mod nir_manager;
-mod nr_fetcher;
-mod nr_manager;
-mod nr_vstp_subscriber;
-mod schedule;
-mod schedule_manager;
-mod sncf_fetcher;
-mod subscriber;
-mod uk_importer;
-mod webui;
-
 use config_file::FromConfigFile;
 use serde::Deserialize;
 
-use crate::ir_manager::IrManager;
-use crate::manager::Manager;
-use crate::nir_manager::{NirConfig, NirManager};
-use crate::nr_manager::{NrConfig, NrManager};
+use worldrailtimetables::area_filter::{AreaFilter, AreaFilterConfig};
+use worldrailtimetables::cold_routes::{ColdRoutes, ColdRoutesConfig};
+use worldrailtimetables::download_cache::{DownloadCache, DownloadCacheConfig};
+use worldrailtimetables::environments::{Environments, MAIN_ENVIRONMENT};
+use worldrailtimetables::error;
+use worldrailtimetables::feed_telemetry::FeedTelemetry;
+use worldrailtimetables::fetch_core::{HttpClient, HttpConfig};
+use worldrailtimetables::gtfs_delta_manager::{GtfsDeltaConfig, GtfsDeltaManager};
+use worldrailtimetables::integrity::{Integrity, IntegrityConfig};
+use worldrailtimetables::ir_manager::IrManager;
+use worldrailtimetables::manager::{ImportScheduler, ImportSchedulerConfig, Manager};
+use worldrailtimetables::nir_manager::{NirConfig, NirManager};
+use worldrailtimetables::notifications::{NotificationConfig, Notifications};
+use worldrailtimetables::nr_manager::{NrConfig, NrManager};
+use worldrailtimetables::schedule_manager::ScheduleManager;
+use worldrailtimetables::snapshot::{read_snapshot, write_snapshot};
+use worldrailtimetables::sql_export::{SqlExport, SqlExportConfig};
+use worldrailtimetables::staleness::{Staleness, StalenessConfig};
+use worldrailtimetables::timetable_export::{
+    departures_poster, export, export_weekly, weekly_timetable, ExportFormat,
+};
+use worldrailtimetables::triggers::Triggers;
+use worldrailtimetables::webui;
+use worldrailtimetables::webui::WebUiConfig;
+
+use chrono::{DateTime, NaiveDate, Utc};
 
+use std::backtrace::BacktraceStatus;
+use std::collections::HashMap;
+use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 #[derive(Clone, Deserialize)]
 struct Config {
     nr: NrConfig,
     nir: NirConfig,
+    #[serde(default)]
+    webui: WebUiConfig,
+    download_cache: Option<DownloadCacheConfig>,
+    #[serde(default)]
+    notifications: NotificationConfig,
+    schedule_generations: Option<usize>, // previous imports to keep for as-of queries
+    change_log_per_train: Option<usize>, // realtime changes to keep for each train; 50 if not given
+    sql_export: Option<SqlExportConfig>,
+    #[serde(default)]
+    staleness: StalenessConfig,
+    #[serde(default)]
+    integrity: IntegrityConfig, // how often to check the schedules still hold together
+    gtfs_deltas: Option<Vec<GtfsDeltaConfig>>, // e.g. DB and ÖBB, each in its own namespace
+    #[serde(default)]
+    http: HttpConfig,
+    area_filter: Option<AreaFilterConfig>, // only keep trains calling somewhere in here
+    cold_routes: Option<ColdRoutesConfig>, // compress the routes of trains nobody's looking at
+    environments: Option<Vec<EnvironmentConfig>>, // other feeds alongside these, e.g. to trial them
+    #[serde(default)]
+    import_scheduler: ImportSchedulerConfig, // how many full imports at once, and what waits for what
+}
+
+// Another set of schedules, from its own feeds, which the web UI answers from if asked
+#[derive(Clone, Deserialize)]
+struct EnvironmentConfig {
+    name: String, // asked for with an X-Environment header, or under /env/<name>/
+    nr: Option<NrConfig>,
+    gtfs_deltas: Option<Vec<GtfsDeltaConfig>>,
+    schedule_generations: Option<usize>,
+}
+
+// Restarts a manager whenever it fails with something that might go away by itself, e.g. a feed
+// being down. A restart means a full reload, so back off in case upstream is having a bad day.
+async fn supervise(
+    name: &str,
+    mut manager: impl Manager,
+    schedule_manager: Arc<ScheduleManager>,
+) -> Result<(), error::Error> {
+    let mut backoff = Duration::from_secs(5);
+    loop {
+        let started = Instant::now();
+        let result = manager.run().await;
+        match &result {
+            Err(x) => schedule_manager.source_failed(name, x),
+            Ok(()) => (),
+        }
+        match result {
+            Ok(()) => return Ok(()),
+            Err(x) if x.is_retryable() => {
+                // it ran happily for a while, so this is a new problem
+                if started.elapsed() > Duration::from_secs(3600) {
+                    backoff = Duration::from_secs(5);
+                }
+                println!(
+                    "WARNING: {} manager failed, restarting in {}s: {}",
+                    name,
+                    backoff.as_secs(),
+                    x
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = std::cmp::min(backoff * 2, Duration::from_secs(300));
+            }
+            Err(x) => return Err(x),
+        }
+    }
 }
 
 async fn do_main() -> Result<(), error::Error> {
     let config = Config::from_config_file("./config.toml")?; // TODO improve
 
-    let schedule_manager = Arc::new(schedule_manager::ScheduleManager::new());
+    let schedule_manager = Arc::new(ScheduleManager::new_with_history(
+        config.schedule_generations.unwrap_or(0),
+    ));
+    match &config.area_filter {
+        Some(x) => schedule_manager.set_area_filter(AreaFilter::new(x.clone())?),
+        None => (),
+    }
+    match &config.cold_routes {
+        Some(x) => schedule_manager.set_cold_routes(ColdRoutes::new(x.clone())),
+        None => (),
+    }
+    match config.change_log_per_train {
+        Some(x) => schedule_manager.change_log().set_max_per_train(x),
+        None => (),
+    }
+    match config.sql_export {
+        Some(x) => {
+            let sql_export = Arc::new(SqlExport::new(x));
+            schedule_manager.on_import_complete(move |namespace, schedule| {
+                if !sql_export.wants(namespace) {
+                    return;
+                }
+                let namespace = namespace.to_string();
+                let export = sql_export.clone().export(schedule.clone());
+                tokio::spawn(async move {
+                    match export.await {
+                        Ok(()) => println!("Exported {} to SQL", namespace),
+                        Err(x) => {
+                            println!("WARNING: Failed to export {} to SQL: {}", namespace, x)
+                        }
+                    }
+                });
+            });
+        }
+        None => (),
+    }
+    let download_cache = config
+        .download_cache
+        .map(|x| Arc::new(DownloadCache::new(x)));
 
-    let mut nr_manager = NrManager::new(config.nr, schedule_manager.clone()).await?;
-    let mut nir_manager = NirManager::new(config.nir, schedule_manager.clone()).await?;
-    let mut ir_manager = IrManager::new(schedule_manager.clone()).await?;
+    let (notifications, webhook_pusher) = Notifications::new(config.notifications);
+    let notifications = Arc::new(notifications);
+    let staleness = Arc::new(Staleness::new(config.staleness, schedule_manager.clone()));
+    let integrity = Arc::new(Integrity::new(config.integrity, schedule_manager.clone()));
+    let triggers = Arc::new(Triggers::new());
+    let replayer = download_cache
+        .clone()
+        .map(|x| Arc::new(config.nr.replayer(x)));
+    let http_client = Arc::new(HttpClient::new(config.http)?);
+    let feed_telemetry = Arc::new(FeedTelemetry::new());
+    let import_scheduler = Arc::new(ImportScheduler::new(config.import_scheduler.clone()));
 
-    let nr_manager_fut = tokio::spawn(async move { nr_manager.run().await });
-    let nir_manager_fut = tokio::spawn(async move { nir_manager.run().await });
-    let ir_manager_fut = tokio::spawn(async move { ir_manager.run().await });
-    let webui_fut = tokio::spawn(async move { webui::rocket(schedule_manager.clone()).await });
+    let nr_manager = NrManager::new(
+        config.nr,
+        schedule_manager.clone(),
+        download_cache.clone(),
+        http_client.clone(),
+        notifications.clone(),
+        triggers.clone(),
+        feed_telemetry.clone(),
+        import_scheduler.clone(),
+    )
+    .await?;
+    let nir_manager = NirManager::new(
+        config.nir,
+        schedule_manager.clone(),
+        download_cache.clone(),
+        http_client.clone(),
+        triggers.clone(),
+        import_scheduler.clone(),
+    )
+    .await?;
+    let ir_manager = IrManager::new(
+        schedule_manager.clone(),
+        download_cache.clone(),
+        http_client.clone(),
+        triggers.clone(),
+        import_scheduler.clone(),
+    )
+    .await?;
+    let mut gtfs_delta_managers = vec![];
+    for gtfs_delta_config in config.gtfs_deltas.unwrap_or_default() {
+        let namespace = gtfs_delta_config.namespace().to_string();
+        let gtfs_delta_manager = GtfsDeltaManager::new(
+            gtfs_delta_config,
+            schedule_manager.clone(),
+            download_cache.clone(),
+            http_client.clone(),
+            triggers.clone(),
+            import_scheduler.clone(),
+        )
+        .await?;
+        gtfs_delta_managers.push((namespace, gtfs_delta_manager));
+    }
+    let mut namespaces = vec!["gbnr".to_string(), "gbni".to_string(), "ieir".to_string()];
+    namespaces.extend(gtfs_delta_managers.iter().map(|(x, _)| x.clone()));
+    import_scheduler.check(&namespaces)?;
+
+    let mut environments = Environments::new(schedule_manager.clone());
+    let mut schedule_managers = vec![schedule_manager.clone()];
+    let mut environment_futs = vec![];
+    for environment in config.environments.unwrap_or_default() {
+        if environment.name == MAIN_ENVIRONMENT || environments.get(&environment.name).is_some() {
+            Err(anyhow::anyhow!(
+                "Environment {} is there more than once",
+                environment.name
+            ))?;
+        }
+        let environment_schedule_manager = Arc::new(ScheduleManager::new_for_environment(
+            &environment.name,
+            environment.schedule_generations.unwrap_or(0),
+        ));
+        match &config.area_filter {
+            Some(x) => environment_schedule_manager.set_area_filter(AreaFilter::new(x.clone())?),
+            None => (),
+        }
+        match &config.cold_routes {
+            Some(x) => environment_schedule_manager.set_cold_routes(ColdRoutes::new(x.clone())),
+            None => (),
+        }
+        match config.change_log_per_train {
+            Some(x) => environment_schedule_manager
+                .change_log()
+                .set_max_per_train(x),
+            None => (),
+        }
+        // Its own of everything else, so nothing from a trial feed goes out to subscribers or ends
+        // up in the download cache. Nobody subscribes to these notifications, so there's nothing
+        // to push.
+        let (environment_notifications, _) = Notifications::new(NotificationConfig::default());
+        let environment_notifications = Arc::new(environment_notifications);
+        let environment_triggers = Arc::new(Triggers::new());
+        let environment_telemetry = Arc::new(FeedTelemetry::new());
+        // its imports don't hold up ours or wait for them
+        let environment_import_scheduler = Arc::new(ImportScheduler::new(
+            config.import_scheduler.without_dependencies(),
+        ));
+        match environment.nr {
+            Some(x) => {
+                let manager = NrManager::new(
+                    x,
+                    environment_schedule_manager.clone(),
+                    None,
+                    http_client.clone(),
+                    environment_notifications.clone(),
+                    environment_triggers.clone(),
+                    environment_telemetry.clone(),
+                    environment_import_scheduler.clone(),
+                )
+                .await?;
+                environment_futs.push(tokio::spawn(supervise(
+                    "gbnr",
+                    manager,
+                    environment_schedule_manager.clone(),
+                )));
+            }
+            None => (),
+        }
+        for gtfs_delta_config in environment.gtfs_deltas.unwrap_or_default() {
+            let namespace = gtfs_delta_config.namespace().to_string();
+            let manager = GtfsDeltaManager::new(
+                gtfs_delta_config,
+                environment_schedule_manager.clone(),
+                None,
+                http_client.clone(),
+                environment_triggers.clone(),
+                environment_import_scheduler.clone(),
+            )
+            .await?;
+            let schedule_manager = environment_schedule_manager.clone();
+            environment_futs.push(tokio::spawn(async move {
+                supervise(&namespace, manager, schedule_manager).await
+            }));
+        }
+        println!("Running environment {} alongside", environment.name);
+        schedule_managers.push(environment_schedule_manager.clone());
+        environments.insert(environment.name, environment_schedule_manager);
+    }
+
+    let nr_manager_fut = tokio::spawn(supervise("gbnr", nr_manager, schedule_manager.clone()));
+    let nir_manager_fut = tokio::spawn(supervise("gbni", nir_manager, schedule_manager.clone()));
+    let ir_manager_fut = tokio::spawn(supervise("ieir", ir_manager, schedule_manager.clone()));
+    let gtfs_delta_schedule_manager = schedule_manager.clone();
+    let gtfs_delta_fut = tokio::spawn(async move {
+        futures::future::try_join_all(gtfs_delta_managers.into_iter().map(
+            |(namespace, manager)| {
+                let schedule_manager = gtfs_delta_schedule_manager.clone();
+                async move { supervise(&namespace, manager, schedule_manager).await }
+            },
+        ))
+        .await
+        .map(|_| ())
+    });
+    let environments_fut = tokio::spawn(async move {
+        if environment_futs.is_empty() {
+            return futures::future::pending().await;
+        }
+        futures::future::try_join_all(
+            environment_futs
+                .into_iter()
+                .map(|x| async move { x.await? }),
+        )
+        .await
+        .map(|_| ())
+    });
+    let webhook_fut = tokio::spawn(webhook_pusher.run());
+    let staleness_fut = tokio::spawn(staleness.clone().run());
+    let integrity_fut = tokio::spawn(integrity.clone().run());
+    let cold_routes_fut = tokio::spawn(async move {
+        // every five minutes, or sooner if enough have been decompressed since
+        let mut interval = tokio::time::interval(Duration::from_secs(10));
+        let mut last = Instant::now();
+        loop {
+            interval.tick().await;
+            if last.elapsed() < Duration::from_secs(300)
+                && !schedule_managers.iter().any(|x| x.cold_routes_due())
+            {
+                continue;
+            }
+            last = Instant::now();
+            for schedule_manager in &schedule_managers {
+                schedule_manager.compress_cold_routes().await;
+            }
+        }
+    });
+    let webui_fut = tokio::spawn(async move {
+        webui::rocket(
+            environments,
+            notifications,
+            staleness,
+            integrity,
+            triggers,
+            replayer,
+            http_client,
+            feed_telemetry,
+            config.webui,
+        )
+        .await
+    });
     tokio::select!(
         x = nr_manager_fut => x,
         x = nir_manager_fut => x,
         x = ir_manager_fut => x,
+        x = gtfs_delta_fut => x,
+        x = environments_fut => x,
+        x = webhook_fut => x,
+        x = staleness_fut => x,
+        x = integrity_fut => x,
+        x = cold_routes_fut => x,
         x = webui_fut => x
     )??;
 
     Ok(())
 }
 
+// worldrailtimetables export <snapshot> <namespace> <from> <to> <csv|pdf|xlsx> <output directory> <location ID>...
+// writes a departure poster for each location from an admin snapshot, without fetching anything
+async fn do_export(args: &[String]) -> Result<(), error::Error> {
+    if args.len() < 7 {
+        Err(anyhow::anyhow!("Usage: worldrailtimetables export <snapshot> <namespace> <from> <to> <csv|pdf|xlsx> <output directory> <location ID>..."))?;
+    }
+    let date = |x: &str| {
+        NaiveDate::parse_from_str(x, "%Y-%m-%d")
+            .map_err(|e| anyhow::anyhow!("Invalid date {}: {}", x, e))
+    };
+    let from = date(&args[2])?;
+    let to = date(&args[3])?;
+    let format = match ExportFormat::parse(&args[4]) {
+        Some(x) => x,
+        None => Err(anyhow::anyhow!("Unknown format {}", args[4]))?,
+    };
+
+    let schedules = read_snapshot(tokio::fs::read(&args[0]).await?, None).await?;
+    let schedule = match schedules.get(&args[1]) {
+        Some(x) => x,
+        None => Err(anyhow::anyhow!("No {} schedule in the snapshot", args[1]))?,
+    };
+    for location_id in &args[6..] {
+        let poster = match departures_poster(schedule, location_id, from, to) {
+            Some(x) => x,
+            None => {
+                println!("WARNING: No location {} in {}", location_id, args[1]);
+                continue;
+            }
+        };
+        let path = Path::new(&args[5]).join(format!("{}.{}", location_id, format.extension()));
+        tokio::fs::write(&path, export(&poster, format)).await?;
+        println!(
+            "Wrote {} departures to {}",
+            poster.rows.len(),
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+// worldrailtimetables export-weekly <snapshot> <namespace> <week commencing> <csv|pdf|xlsx> <output directory> <location ID>...
+// writes a weekly timetable, a row for each train and a column for each day, for each location
+async fn do_export_weekly(args: &[String]) -> Result<(), error::Error> {
+    if args.len() < 6 {
+        Err(anyhow::anyhow!("Usage: worldrailtimetables export-weekly <snapshot> <namespace> <week commencing> <csv|pdf|xlsx> <output directory> <location ID>..."))?;
+    }
+    let week_commencing = NaiveDate::parse_from_str(&args[2], "%Y-%m-%d")
+        .map_err(|e| anyhow::anyhow!("Invalid date {}: {}", args[2], e))?;
+    let format = match ExportFormat::parse(&args[3]) {
+        Some(x) => x,
+        None => Err(anyhow::anyhow!("Unknown format {}", args[3]))?,
+    };
+
+    let schedules = read_snapshot(tokio::fs::read(&args[0]).await?, None).await?;
+    let schedule = match schedules.get(&args[1]) {
+        Some(x) => x,
+        None => Err(anyhow::anyhow!("No {} schedule in the snapshot", args[1]))?,
+    };
+    for location_id in &args[5..] {
+        let weekly = match weekly_timetable(schedule, location_id, week_commencing) {
+            Some(x) => x,
+            None => {
+                println!("WARNING: No location {} in {}", location_id, args[1]);
+                continue;
+            }
+        };
+        let path = Path::new(&args[4]).join(format!(
+            "{}-{}.{}",
+            location_id,
+            week_commencing,
+            format.extension()
+        ));
+        tokio::fs::write(&path, export_weekly(&weekly, format)).await?;
+        println!("Wrote {} trains to {}", weekly.rows.len(), path.display());
+    }
+
+    Ok(())
+}
+
+// worldrailtimetables replay <time> <output snapshot>
+// rebuilds the gbnr schedule as it was at that time from the download cache and the VSTP log, so
+// we can see what we were showing when something went wrong
+async fn do_replay(args: &[String]) -> Result<(), error::Error> {
+    if args.len() < 2 {
+        Err(anyhow::anyhow!(
+            "Usage: worldrailtimetables replay <time, e.g. 2024-06-02T14:00:00Z> <output snapshot>"
+        ))?;
+    }
+    let at = DateTime::parse_from_rfc3339(&args[0])
+        .map_err(|e| anyhow::anyhow!("Invalid time {}: {}", args[0], e))?
+        .with_timezone(&Utc);
+
+    let config = Config::from_config_file("./config.toml")?;
+    let download_cache = match config.download_cache {
+        Some(x) => Arc::new(DownloadCache::new(x)),
+        None => Err(anyhow::anyhow!(
+            "Replaying needs a download_cache in the config"
+        ))?,
+    };
+    let (schedule, summary) = config.nr.replayer(download_cache).replay(at).await?;
+    println!(
+        "Replayed to {}: full extract from {}, updates {:?}, {} VSTP messages",
+        summary.at, summary.full_extract_fetched, summary.updates, summary.vstp_applied
+    );
+
+    let schedules = HashMap::from([(schedule.namespace.clone(), Arc::new(schedule))]);
+    tokio::fs::write(&args[1], write_snapshot(schedules).await?).await?;
+    println!("Wrote snapshot to {}", args[1]);
+
+    Ok(())
+}
+
 #[rocket::main]
 async fn main() -> Result<(), error::Error> {
     //tracing_subscriber::fmt().with_max_level(tracing::Level::TRACE).init();
 
-    match do_main().await {
+    let args = std::env::args().collect::<Vec<_>>();
+    let result = match args.get(1).map(|x| x.as_str()) {
+        Some("export") => do_export(&args[2..]).await,
+        Some("export-weekly") => do_export_weekly(&args[2..]).await,
+        Some("replay") => do_replay(&args[2..]).await,
+        _ => do_main().await,
+    };
+    match result {
         Ok(()) => Ok(()),
         Err(x) => {
             println!("Error! {}", x);
+            match x.backtrace() {
+                Some(backtrace) if backtrace.status() == BacktraceStatus::Captured => {
+                    println!("{}", backtrace)
+                }
+                _ => (),
+            }
             Err(x)
-        },
+        }
     }
 }
@@ -2,7 +2,132 @@ use crate::error::Error;
 
 use async_trait::async_trait;
 
+use serde::Deserialize;
+
+use tokio::sync::{watch, Semaphore, SemaphorePermit};
+
+use std::collections::{HashMap, HashSet};
+
+/// Owns the fetchers and importers for one data source and keeps its schedules up to date. `run`
+/// only returns on error.
 #[async_trait]
 pub trait Manager {
     async fn run(&mut self) -> Result<(), Error>;
 }
+
+#[derive(Clone, Default, Deserialize)]
+pub struct ImportSchedulerConfig {
+    max_concurrent: Option<usize>, // full imports at once; 2 if not given
+    after: Option<HashMap<String, Vec<String>>>, // by namespace, those it waits to be imported
+}
+
+impl ImportSchedulerConfig {
+    // An environment has only some of our feeds, so could wait forever for the others
+    pub fn without_dependencies(&self) -> Self {
+        Self {
+            max_concurrent: self.max_concurrent,
+            after: None,
+        }
+    }
+}
+
+/// Decides when each manager's full import (the slow fetch and parse of a whole timetable) runs.
+/// They run alongside each other, up to a limit as each can want a lot of memory, rather than one
+/// after another as they queue for the schedule manager's lock. A namespace can be made to wait
+/// for others to have been imported first, e.g. one that takes its locations from another's.
+pub struct ImportScheduler {
+    workers: Semaphore,
+    after: HashMap<String, Vec<String>>,
+    imported: watch::Sender<HashSet<String>>, // namespaces imported at least once
+}
+
+pub struct ImportSlot<'a> {
+    scheduler: &'a ImportScheduler,
+    namespace: String,
+    _permit: SemaphorePermit<'a>,
+}
+
+impl ImportScheduler {
+    pub fn new(config: ImportSchedulerConfig) -> Self {
+        Self {
+            workers: Semaphore::new(config.max_concurrent.unwrap_or(2).max(1)),
+            after: config.after.unwrap_or_default(),
+            imported: watch::Sender::new(HashSet::new()),
+        }
+    }
+
+    // Anything waited for has to be something we import, and nothing can end up waiting for
+    // itself, or it'd never start
+    pub fn check(&self, namespaces: &[String]) -> Result<(), Error> {
+        for (namespace, after) in &self.after {
+            for x in after {
+                if !namespaces.contains(x) {
+                    Err(anyhow::anyhow!(
+                        "{} is to be imported after {}, which isn't configured",
+                        namespace,
+                        x
+                    ))?;
+                }
+            }
+        }
+        for namespace in self.after.keys() {
+            let mut waiting_for = self.after[namespace].clone();
+            let mut seen = HashSet::new();
+            while let Some(x) = waiting_for.pop() {
+                if x == *namespace {
+                    Err(anyhow::anyhow!("{} ends up waiting for itself", namespace))?;
+                }
+                if seen.insert(x.clone()) {
+                    waiting_for.extend(self.after.get(&x).cloned().unwrap_or_default());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until everything `namespace` is to be imported after has been, then for a free
+    /// worker. Call `finished` on what comes back once the new schedule's in.
+    pub async fn start(&self, namespace: &str) -> ImportSlot<'_> {
+        let after = self.after.get(namespace).cloned().unwrap_or_default();
+        let mut imported = self.imported.subscribe();
+        if !after.iter().all(|x| imported.borrow().contains(x)) {
+            println!(
+                "Waiting for {} to be imported before importing {}",
+                after.join(", "),
+                namespace
+            );
+            // the sender's in here with us, so it can't go away
+            imported
+                .wait_for(|x| after.iter().all(|y| x.contains(y)))
+                .await
+                .unwrap();
+        }
+        ImportSlot {
+            scheduler: self,
+            namespace: namespace.to_string(),
+            _permit: self.workers.acquire().await.unwrap(),
+        }
+    }
+
+    pub fn is_imported(&self, namespace: &str) -> bool {
+        self.imported.borrow().contains(namespace)
+    }
+
+    pub async fn wait_imported(&self, namespace: &str) {
+        self.imported
+            .subscribe()
+            .wait_for(|x| x.contains(namespace))
+            .await
+            .unwrap();
+    }
+}
+
+impl ImportSlot<'_> {
+    // If it's dropped without this, e.g. as the import failed, anything waiting keeps waiting
+    // until a retry succeeds.
+    pub fn finished(self) {
+        self.scheduler.imported.send_modify(|x| {
+            x.insert(self.namespace.clone());
+        });
+    }
+}
@@ -0,0 +1,191 @@
+use crate::resolved_train::resolve_train;
+use crate::schedule::{
+    operating_dates_between, Activities, ActivityNote, LocalTimes, OperatingCharacteristics,
+    Schedule, TrainOperator, TrainPower, TrainSource, TrainType,
+};
+use crate::train_id::GlobalTrainId;
+
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime};
+use chrono_tz::Tz;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashSet;
+
+// A signaller's or controller's view of a location: every train through it between two times,
+// passes and empty stock included, in working time order, with everything operational we know
+// about each. The passenger boards only go by public times and leave most of this out.
+
+#[derive(Clone, Default, Deserialize)]
+pub struct MovementsConfig {
+    max_hours: Option<i64>, // longest span one request can ask about; 24 if not given
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum MovementType {
+    Starts,
+    Stops,
+    Passes,
+    Terminates,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Movement {
+    pub global_id: GlobalTrainId,
+    pub train_id: String,
+    pub headcode: Option<String>,
+    pub date: NaiveDate, // that the train starts on
+    pub location_id: String,
+    pub location_suffix: Option<String>,
+    pub movement: MovementType,
+    pub working_time: DateTime<Tz>, // what they're in order of
+    #[serde(flatten)]
+    pub times: LocalTimes,
+    pub platform: Option<String>,
+    pub line: Option<String>,
+    pub path: Option<String>,
+    pub engineering_allowance_s: Option<u32>,
+    pub pathing_allowance_s: Option<u32>,
+    pub performance_allowance_s: Option<u32>,
+    pub activities: Activities,
+    pub activity_notes: Vec<ActivityNote>,
+    pub origin: String,
+    pub destination: String,
+    pub train_type: TrainType,
+    pub operator: Option<TrainOperator>,
+    pub power_type: Option<TrainPower>,
+    pub timing_speed_m_per_s: Option<f64>,
+    pub operating_characteristics: Option<OperatingCharacteristics>,
+    pub runs_as_required: bool,
+    pub source: Option<TrainSource>,
+    pub modified: bool,
+    pub cancelled: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct MovementReport {
+    pub namespace: String,
+    pub location_ids: Vec<String>,
+    pub from: NaiveDateTime,
+    pub to: NaiveDateTime,
+    pub movements: Vec<Movement>,
+}
+
+pub struct Movements {
+    max_hours: i64,
+}
+
+impl Movements {
+    pub fn new(config: MovementsConfig) -> Self {
+        Self {
+            max_hours: config.max_hours.unwrap_or(24).max(1),
+        }
+    }
+
+    // Times are local to the locations. Anything after the longest span we allow is cut off.
+    pub fn report(
+        &self,
+        schedule: &Schedule,
+        location_ids: &HashSet<String>,
+        from: NaiveDateTime,
+        to: NaiveDateTime,
+    ) -> MovementReport {
+        let to = to.min(from + Duration::hours(self.max_hours));
+        let mut movements = vec![];
+
+        let mut train_ids = HashSet::new();
+        for location_id in location_ids {
+            match schedule.trains_indexed_by_location.get(location_id) {
+                Some(x) => train_ids.extend(x.iter()),
+                None => (),
+            }
+        }
+
+        for train_id in train_ids {
+            let versions = match schedule.trains.get(train_id) {
+                Some(x) if !x.is_empty() => x,
+                _ => continue,
+            };
+            // trains that started a day or two before can still be here
+            for date in operating_dates_between(versions, from.date(), to.date()) {
+                let train = match resolve_train(schedule, train_id, date) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                for (i, call) in train.route.iter().enumerate() {
+                    if !location_ids.contains(call.id.as_str()) {
+                        continue;
+                    }
+                    let times = &call.times;
+                    let working_time = match times
+                        .working_dep
+                        .or(times.working_pass)
+                        .or(times.working_arr)
+                        .or(times.public_dep)
+                        .or(times.public_arr)
+                    {
+                        Some(x) if x.naive_local() >= from && x.naive_local() <= to => x,
+                        _ => continue,
+                    };
+                    let movement = if i == 0 {
+                        MovementType::Starts
+                    } else if i + 1 == train.route.len() {
+                        MovementType::Terminates
+                    } else if times.working_pass.is_some() {
+                        MovementType::Passes
+                    } else {
+                        MovementType::Stops
+                    };
+
+                    let variable_train = train.variable_train_at(call);
+                    movements.push(Movement {
+                        global_id: train.global_id.clone(),
+                        train_id: train.id.clone(),
+                        headcode: variable_train.public_id.clone(),
+                        date,
+                        location_id: call.id.to_string(),
+                        location_suffix: call.id_suffix.clone(),
+                        movement,
+                        working_time,
+                        times: times.clone(),
+                        platform: call.platform.as_ref().map(|x| x.to_string()),
+                        line: call.line.as_ref().map(|x| x.to_string()),
+                        path: call.path.as_ref().map(|x| x.to_string()),
+                        engineering_allowance_s: call.engineering_allowance_s,
+                        pathing_allowance_s: call.pathing_allowance_s,
+                        performance_allowance_s: call.performance_allowance_s,
+                        activities: call.activities.clone(),
+                        activity_notes: call.activity_notes.clone(),
+                        origin: train.route.first().unwrap().id.to_string(),
+                        destination: train.route.last().unwrap().id.to_string(),
+                        train_type: variable_train.train_type,
+                        operator: variable_train.operator.clone(),
+                        power_type: variable_train.power_type,
+                        timing_speed_m_per_s: variable_train.timing_speed_m_per_s,
+                        operating_characteristics: variable_train.operating_characteristics.clone(),
+                        runs_as_required: train.runs_as_required,
+                        source: train.source,
+                        modified: train.modified,
+                        cancelled: train.cancelled,
+                    });
+                }
+            }
+        }
+
+        movements.sort_by(|a, b| {
+            a.working_time
+                .cmp(&b.working_time)
+                .then_with(|| a.train_id.cmp(&b.train_id))
+        });
+
+        let mut location_ids = location_ids.iter().cloned().collect::<Vec<_>>();
+        location_ids.sort();
+        MovementReport {
+            namespace: schedule.namespace.clone(),
+            location_ids,
+            from,
+            to,
+            movements,
+        }
+    }
+}
@@ -1,20 +1,21 @@
 use crate::error::Error;
+use crate::fetch_core::HttpClient;
 use crate::fetcher::StreamingFetcher;
 use async_trait::async_trait;
-use futures::stream::TryStreamExt;
-use reqwest::Client;
 // I tried to use ReadZiptreaming here, but sadly these files sometimes have malformed local
 // headers (with size == 0) which means this is impossible
 use rc_zip_tokio::ReadZip;
 use serde::{Deserialize, Serialize};
 
 use tokio::io::{AsyncBufRead, AsyncReadExt, BufReader};
-use tokio_util::io::StreamReader;
 
 use std::fmt;
 use std::io::Cursor;
+use std::sync::Arc;
 
-pub struct NirFetcher {}
+pub struct NirFetcher {
+    client: Arc<HttpClient>,
+}
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct NirCkanOrganization {
@@ -147,22 +148,13 @@ impl fmt::Display for CkanErrorType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
+#[error("Could not fetch NIR CIF URL; error reading Ckan JSON field {field_name}: {error_type}")]
 pub struct CkanError {
     error_type: CkanErrorType,
     field_name: String,
 }
 
-impl fmt::Display for CkanError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Could not fetch NIR CIF URL; error reading Ckan JSON field {}: {}",
-            self.field_name, self.error_type
-        )
-    }
-}
-
 #[derive(Clone, Debug)]
 pub enum NirFetcherErrorType {
     NoCifEntry,
@@ -176,20 +168,15 @@ impl fmt::Display for NirFetcherErrorType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
+#[error("Error fetching NIR CIF: {error_type}")]
 pub struct NirFetcherError {
     error_type: NirFetcherErrorType,
 }
 
-impl fmt::Display for NirFetcherError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Error fetching NIR CIF: {}", self.error_type)
-    }
-}
-
 impl NirFetcher {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(client: Arc<HttpClient>) -> Self {
+        Self { client }
     }
 
     fn extract_url_from_ckan(&self, json: NirCkanResponse) -> Result<String, CkanError> {
@@ -242,19 +229,19 @@ impl NirFetcher {
     }
 
     async fn get_url(&self) -> Result<String, Error> {
-        let client = Client::new();
-        let response = client
-            .get("https://admin.opendatani.gov.uk/api/3/action/package_show?id=nir20160126v2")
-            .send()
-            .await?
-            .error_for_status()?;
-        let reader = StreamReader::new(
-            response
-                .bytes_stream()
-                .map_err(|e| futures::io::Error::new(futures::io::ErrorKind::Other, e)),
-        );
+        let response = self
+            .client
+            .get(
+                "nir-ckan",
+                "https://admin.opendatani.gov.uk/api/3/action/package_show?id=nir20160126v2",
+                None,
+            )
+            .await?;
         let mut json_str = String::new();
-        BufReader::new(reader).read_to_string(&mut json_str).await?;
+        self.client
+            .reader("nir-ckan", response)
+            .read_to_string(&mut json_str)
+            .await?;
         let json = serde_json::from_str::<NirCkanResponse>(&json_str)?;
 
         Ok(self.extract_url_from_ckan(json)?)
@@ -265,11 +252,14 @@ impl NirFetcher {
 impl StreamingFetcher for NirFetcher {
     async fn fetch(&self) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, Error> {
         println!("Fetching NIR Rail CIF data from OpenDataNI");
-        let client = Client::new();
         let url = self.get_url().await?;
         println!("{}", url);
-        let response = client.get(url).send().await?.error_for_status()?;
-        let response_bytes = Vec::<u8>::from(response.bytes().await?);
+        let response = self.client.get("nir-cif", &url, None).await?;
+        let mut response_bytes = vec![];
+        self.client
+            .reader("nir-cif", response)
+            .read_to_end(&mut response_bytes)
+            .await?;
         let reader = response_bytes.read_zip().await?;
         for entry in reader.entries() {
             if entry
@@ -1,18 +1,21 @@
-use crate::error::Error;
-use crate::fetcher::StreamingFetcher;
-use crate::importer::SlowStreamingImporter;
-use crate::manager::Manager;
+use crate::download_cache::{CachingFetcher, DownloadCache};
+use crate::error::{Error, ErrorContext};
+use crate::fetch_core::HttpClient;
+use crate::fetcher::{GtfsFetcher, StreamingFetcher};
+use crate::gtfs_importer::GtfsImporter;
+use crate::gtfs_url_fetcher::GtfsUrlFetcher;
+use crate::importer::{SlowGtfsImporter, SlowStreamingImporter};
+use crate::manager::{ImportScheduler, Manager};
 use crate::nir_fetcher::NirFetcher;
 use crate::schedule::Schedule;
 use crate::schedule_manager::ScheduleManager;
+use crate::triggers::{DailyRun, Triggers};
 use crate::uk_importer::{CifImporter, CifImporterConfig};
 
-use chrono::offset::Utc;
-use chrono::{Days, NaiveTime, TimeZone};
+use chrono::NaiveTime;
 use chrono_tz::Europe::London;
 
-use tokio::time;
-use tokio::time::Duration;
+use tokio::task::block_in_place;
 
 use serde::Deserialize;
 
@@ -23,78 +26,110 @@ use std::sync::Arc;
 #[derive(Clone, Deserialize)]
 pub struct NirConfig {
     cif_importer: CifImporterConfig,
+    gtfs_url: Option<String>, // import Translink's GTFS from here instead of the CIF
+    infer_passing_times: Option<bool>, // for locations the feed doesn't time; on by default
+}
+
+// what we're importing from, which is the CIF unless told otherwise
+enum NirSource {
+    Cif(CachingFetcher<NirFetcher>, CifImporter),
+    Gtfs(GtfsUrlFetcher, GtfsImporter),
 }
 
 pub struct NirManager {
     schedule_manager: Arc<ScheduleManager>,
     config: NirConfig,
+    download_cache: Option<Arc<DownloadCache>>,
+    http_client: Arc<HttpClient>,
+    triggers: Arc<Triggers>,
+    import_scheduler: Arc<ImportScheduler>,
 }
 
 impl NirManager {
     pub async fn new(
         config: NirConfig,
         schedule_manager: Arc<ScheduleManager>,
+        download_cache: Option<Arc<DownloadCache>>,
+        http_client: Arc<HttpClient>,
+        triggers: Arc<Triggers>,
+        import_scheduler: Arc<ImportScheduler>,
     ) -> Result<NirManager, Error> {
         Ok(NirManager {
             schedule_manager,
             config,
+            download_cache,
+            http_client,
+            triggers,
+            import_scheduler,
         })
     }
 
-    async fn reload_cif(
-        &self,
-        nir_fetcher: &NirFetcher,
-        cif_importer: &mut CifImporter,
-    ) -> Result<(), Error> {
+    async fn reload(&self, source: &mut NirSource) -> Result<(), Error> {
+        let slot = self.import_scheduler.start("gbni").await;
         {
-            // lock for writing now, such that there will be no chance of smaller updates being
-            // lost
-            let mut transaction = self.schedule_manager.transactional_write().await;
-
             let mut schedule = Schedule::new(
                 "gbni".to_string(),
                 "United Kingdom — Translink NI Railways".to_string(),
             );
 
-            let mut reader = nir_fetcher.fetch().await?;
-            schedule = cif_importer.overlay(&mut reader, schedule).await?;
+            let source_name = match source {
+                NirSource::Cif(nir_fetcher, cif_importer) => {
+                    let mut reader = nir_fetcher.fetch().await.context("Fetching NIR CIF")?;
+                    schedule = cif_importer
+                        .overlay(&mut reader, schedule)
+                        .await
+                        .context("Importing NIR CIF")?;
+                    "gbni-cif"
+                }
+                NirSource::Gtfs(gtfs_fetcher, gtfs_importer) => {
+                    let gtfs = gtfs_fetcher
+                        .fetch()
+                        .await
+                        .context("Fetching Translink GTFS")?;
+                    schedule = gtfs_importer
+                        .overlay(gtfs, schedule)
+                        .await
+                        .context("Importing Translink GTFS")?;
+                    "gbni-gtfs"
+                }
+            };
+
+            if self.config.infer_passing_times.unwrap_or(true) {
+                let inferred = block_in_place(|| schedule.infer_passing_times());
+                println!("Inferred passing times for {} NIR locations", inferred);
+            }
+
+            // nothing else changes it, so there's nothing to lose by only locking now
+            let mut transaction = self.schedule_manager.transactional_write().await;
 
             // always replace the schedule
-            transaction.insert("gbni".to_string(), schedule);
+            transaction.put("gbni", schedule);
+            transaction.source_updated(source_name);
+            transaction.archive();
             transaction.commit();
         }
+        slot.finished();
 
         Ok(())
     }
 
-    async fn update_cif(
-        &self,
-        nir_fetcher: &NirFetcher,
-        cif_importer: &mut CifImporter,
-    ) -> Result<(), Error> {
+    async fn update(&self, source: &mut NirSource) -> Result<(), Error> {
+        let source_name = match source {
+            NirSource::Cif(_, _) => "gbni-cif",
+            NirSource::Gtfs(_, _) => "gbni-gtfs",
+        };
+        let mut daily = DailyRun::new(
+            London,
+            NaiveTime::from_hms_opt(3, 12, 0).unwrap(),
+            &self.triggers,
+            source_name,
+        );
         loop {
-            let now = London.from_utc_datetime(&Utc::now().naive_utc());
-            let new_time = if now.time() > NaiveTime::from_hms_opt(3, 12, 0).unwrap() {
-                London
-                    .from_local_datetime(
-                        &now.date_naive()
-                            .checked_add_days(Days::new(1))
-                            .unwrap()
-                            .and_hms_opt(3, 12, 0)
-                            .unwrap(),
-                    )
-                    .unwrap()
-            } else {
-                London
-                    .from_local_datetime(&now.date_naive().and_hms_opt(3, 12, 0).unwrap())
-                    .unwrap()
-            };
-            let mut interval = time::interval(Duration::from_secs(15));
-            while London.from_utc_datetime(&Utc::now().naive_utc()) < new_time {
-                interval.tick().await;
+            if daily.wait().await {
+                println!("Fetching NIR timetable early, as upstream says it's out");
             }
 
-            self.reload_cif(nir_fetcher, cif_importer).await?;
+            self.reload(source).await?;
         }
     }
 }
@@ -102,13 +137,31 @@ impl NirManager {
 #[async_trait]
 impl Manager for NirManager {
     async fn run(&mut self) -> Result<(), Error> {
-        let nir_fetcher = NirFetcher::new();
-        let mut cif_importer = CifImporter::new(self.config.cif_importer.clone());
-
-        self.reload_cif(&nir_fetcher, &mut cif_importer).await?;
+        let mut source = match &self.config.gtfs_url {
+            Some(x) => NirSource::Gtfs(
+                GtfsUrlFetcher::new_with_cache(
+                    self.http_client.clone(),
+                    x,
+                    "Translink",
+                    "nir-gtfs",
+                    self.download_cache.clone(),
+                ),
+                GtfsImporter::new(),
+            ),
+            None => NirSource::Cif(
+                CachingFetcher::new(
+                    NirFetcher::new(self.http_client.clone()),
+                    "nir-cif",
+                    self.download_cache.clone(),
+                ),
+                CifImporter::new(self.config.cif_importer.clone()),
+            ),
+        };
+
+        self.reload(&mut source).await?;
 
         tokio::try_join!(async {
-            return self.update_cif(&nir_fetcher, &mut cif_importer).await;
+            return self.update(&mut source).await;
         },)?;
 
         Ok(())
@@ -0,0 +1,351 @@
+use crate::error::Error;
+use crate::schedule::{get_train_instance, Schedule};
+use crate::train_id::GlobalTrainId;
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+
+use rand::RngCore;
+
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::RwLock;
+
+#[derive(Clone, Default, Deserialize)]
+pub struct NotificationConfig {
+    max_subscriptions: Option<usize>,
+    max_outbox: Option<usize>, // per subscription; the oldest are dropped first
+    expiry_hours: Option<i64>, // from when the subscription was made
+    webhook_timeout_secs: Option<u64>,
+    // Hosts webhooks may go to, e.g. "hooks.example.com"; default none, so only polling works.
+    // Anyone can subscribe, so without this they could have us POST to anything we can reach.
+    webhook_hosts: Option<Vec<String>>,
+}
+
+// What someone wants to hear about: either one train on one date, or anything calling at or
+// passing a location in a window of time
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum Interest {
+    Train {
+        namespace: String,
+        train_id: String,
+        date: NaiveDate,
+    },
+    Location {
+        namespace: String,
+        location_id: String, // or a public ID, e.g. a CRS code
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type")]
+pub enum TrainEvent {
+    Retimed {
+        location_ids: Vec<String>,
+        delay_minutes: i64, // negative if early
+    },
+    PlatformChanged {
+        location_ids: Vec<String>,
+        platform: String,
+        planned_platform: Option<String>,
+    },
+    Cancelled {
+        location_ids: Vec<String>,
+        reason: Option<String>,
+    },
+    Reinstated,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Notification {
+    pub seq: u64, // increasing within a subscription, for acknowledging what's been seen
+    pub timestamp: DateTime<Utc>,
+    pub global_id: GlobalTrainId,
+    pub namespace: String,
+    pub train_id: String,
+    pub date: NaiveDate,
+    pub event: TrainEvent,
+}
+
+struct Subscription {
+    interest: Interest,
+    webhook: Option<String>,
+    expires: DateTime<Utc>,
+    next_seq: u64,
+    outbox: VecDeque<Notification>,
+}
+
+impl Interest {
+    fn namespace(&self) -> &str {
+        match self {
+            Interest::Train { namespace, .. } => namespace,
+            Interest::Location { namespace, .. } => namespace,
+        }
+    }
+
+    fn matches(&self, schedule: &Schedule, train_id: &str, date: NaiveDate) -> bool {
+        if self.namespace() != schedule.namespace {
+            return false;
+        }
+        match self {
+            Interest::Train {
+                train_id: x,
+                date: y,
+                ..
+            } => x == train_id && *y == date,
+            Interest::Location {
+                location_id,
+                from,
+                to,
+                ..
+            } => {
+                let train = match schedule
+                    .trains
+                    .get(train_id)
+                    .and_then(|x| get_train_instance(x, date).0)
+                {
+                    Some(x) => x,
+                    None => return false,
+                };
+                train.route.iter().any(|train_location| {
+                    let location = match schedule.locations.get(train_location.id.as_str()) {
+                        Some(x) => x,
+                        None => return false,
+                    };
+                    if train_location.id != *location_id
+                        && location.public_id.as_ref() != Some(location_id)
+                    {
+                        return false;
+                    }
+                    let times = train_location.local_times(date, location.timezone);
+                    [times.working_arr, times.working_dep, times.working_pass]
+                        .iter()
+                        .flatten()
+                        .any(|x| *x >= *from && *x <= *to)
+                })
+            }
+        }
+    }
+}
+
+// Realtime importers publish what happens to trains here, and anyone who has subscribed gets it
+// in their outbox, to poll for or have pushed to a webhook. Nothing is kept across restarts.
+pub struct Notifications {
+    config: NotificationConfig,
+    subscriptions: RwLock<HashMap<String, Subscription>>,
+    webhooks: UnboundedSender<(String, Notification)>,
+}
+
+pub struct WebhookPusher {
+    timeout: std::time::Duration,
+    queue: UnboundedReceiver<(String, Notification)>,
+}
+
+impl Notifications {
+    pub fn new(config: NotificationConfig) -> (Self, WebhookPusher) {
+        let (sender, receiver) = unbounded_channel();
+        let pusher = WebhookPusher {
+            timeout: std::time::Duration::from_secs(config.webhook_timeout_secs.unwrap_or(10)),
+            queue: receiver,
+        };
+        (
+            Self {
+                config,
+                subscriptions: RwLock::new(HashMap::new()),
+                webhooks: sender,
+            },
+            pusher,
+        )
+    }
+
+    // whether a webhook is one we'll push to: http(s), and to a host that's been allowed
+    pub fn webhook_allowed(&self, url: &str) -> bool {
+        let url = match reqwest::Url::parse(url) {
+            Ok(x) => x,
+            Err(_) => return false,
+        };
+        let hosts = self.config.webhook_hosts.as_deref().unwrap_or_default();
+        matches!(url.scheme(), "http" | "https")
+            && url.username().is_empty()
+            && url
+                .host_str()
+                .is_some_and(|x| hosts.iter().any(|y| y.eq_ignore_ascii_case(x)))
+    }
+
+    fn prune(subscriptions: &mut HashMap<String, Subscription>) {
+        let now = Utc::now();
+        subscriptions.retain(|_, x| x.expires > now);
+    }
+
+    // returns the new subscription's ID, or None if there are too many already
+    pub fn subscribe(&self, interest: Interest, webhook: Option<String>) -> Option<String> {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        Self::prune(&mut subscriptions);
+        if subscriptions.len() >= self.config.max_subscriptions.unwrap_or(10000) {
+            return None;
+        }
+
+        // the ID is all you need to read or cancel a subscription, so it mustn't be guessable
+        let mut bytes = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let id = bytes
+            .iter()
+            .map(|x| format!("{:02x}", x))
+            .collect::<String>();
+
+        subscriptions.insert(
+            id.clone(),
+            Subscription {
+                interest,
+                webhook,
+                expires: Utc::now() + Duration::hours(self.config.expiry_hours.unwrap_or(48)),
+                next_seq: 1,
+                outbox: VecDeque::new(),
+            },
+        );
+        Some(id)
+    }
+
+    pub fn unsubscribe(&self, id: &str) -> bool {
+        self.subscriptions.write().unwrap().remove(id).is_some()
+    }
+
+    // Everything in the outbox after `after`; anything up to and including it is taken as seen
+    // and thrown away.
+    pub fn poll(&self, id: &str, after: Option<u64>) -> Option<Vec<Notification>> {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        let subscription = subscriptions.get_mut(id)?;
+        match after {
+            Some(after) => subscription.outbox.retain(|x| x.seq > after),
+            None => (),
+        }
+        Some(subscription.outbox.iter().cloned().collect())
+    }
+
+    pub fn publish(&self, schedule: &Schedule, train_id: &str, date: NaiveDate, event: TrainEvent) {
+        let mut subscriptions = self.subscriptions.write().unwrap();
+        let max_outbox = self.config.max_outbox.unwrap_or(100);
+        for subscription in subscriptions.values_mut() {
+            if !subscription.interest.matches(schedule, train_id, date) {
+                continue;
+            }
+            let notification = Notification {
+                seq: subscription.next_seq,
+                timestamp: Utc::now(),
+                global_id: GlobalTrainId::new(&schedule.namespace, train_id, date),
+                namespace: schedule.namespace.clone(),
+                train_id: train_id.to_string(),
+                date,
+                event: event.clone(),
+            };
+            subscription.next_seq += 1;
+            match &subscription.webhook {
+                // only fails if the pusher has gone, and then there's no one to tell
+                Some(x) => drop(self.webhooks.send((x.clone(), notification.clone()))),
+                None => (),
+            }
+            subscription.outbox.push_back(notification);
+            while subscription.outbox.len() > max_outbox {
+                subscription.outbox.pop_front();
+            }
+        }
+    }
+}
+
+// Loopback, link-local (which has cloud metadata services on it), private and the like: nothing a
+// webhook has any business on, whatever name it got there by
+fn internal_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(x) => {
+            x.is_loopback()
+                || x.is_private()
+                || x.is_link_local()
+                || x.is_unspecified()
+                || x.is_broadcast()
+                || x.is_multicast()
+                || x.octets()[0] == 0
+                || (x.octets()[0] == 100 && x.octets()[1] & 0xc0 == 64) // carrier-grade NAT
+        }
+        IpAddr::V6(x) => match x.to_ipv4_mapped() {
+            Some(y) => internal_address(IpAddr::V4(y)),
+            None => {
+                x.is_loopback()
+                    || x.is_unspecified()
+                    || x.is_multicast()
+                    || x.segments()[0] & 0xfe00 == 0xfc00 // unique local
+                    || x.segments()[0] & 0xffc0 == 0xfe80 // link-local
+            }
+        },
+    }
+}
+
+// Where to send a webhook, checked now rather than when it was subscribed, as names can be
+// pointed somewhere else since. The client is then pinned to it, so it can't be re-resolved to
+// somewhere internal in between.
+async fn webhook_address(url: &str) -> Result<(String, SocketAddr), String> {
+    let url = reqwest::Url::parse(url).map_err(|x| x.to_string())?;
+    let host = url.host_str().ok_or("no host")?.to_string();
+    let port = url.port_or_known_default().ok_or("no port")?;
+    let addresses = tokio::net::lookup_host((host.trim_matches(['[', ']']), port))
+        .await
+        .map_err(|x| x.to_string())?
+        .collect::<Vec<_>>();
+    if addresses.is_empty() {
+        return Err("resolves to nothing".to_string());
+    }
+    match addresses.iter().find(|x| internal_address(x.ip())) {
+        Some(x) => Err(format!("resolves to {}, which is internal", x.ip())),
+        None => Ok((host, addresses[0])),
+    }
+}
+
+impl WebhookPusher {
+    // Webhooks get one go each; if it fails the notification is still in the outbox to poll for.
+    pub async fn run(mut self) -> Result<(), Error> {
+        loop {
+            let (url, notification) = match self.queue.recv().await {
+                Some(x) => x,
+                None => return Ok(()),
+            };
+            let body = serde_json::to_vec(&notification)?;
+            let timeout = self.timeout;
+            // one slow webhook shouldn't hold up everyone else's
+            tokio::spawn(async move {
+                let (host, address) = match webhook_address(&url).await {
+                    Ok(x) => x,
+                    Err(x) => {
+                        println!("WARNING: Not pushing notification to {}: {}", url, x);
+                        return;
+                    }
+                };
+                // nor following redirects, which could go anywhere
+                let client = reqwest::Client::builder()
+                    .timeout(timeout)
+                    .redirect(reqwest::redirect::Policy::none())
+                    .resolve(&host, address)
+                    .build();
+                let result = match client {
+                    Ok(client) => {
+                        client
+                            .post(&url)
+                            .header(reqwest::header::CONTENT_TYPE, "application/json")
+                            .body(body)
+                            .send()
+                            .await
+                    }
+                    Err(x) => Err(x),
+                };
+                match result.and_then(|x| x.error_for_status()) {
+                    Ok(_) => (),
+                    Err(x) => println!("WARNING: Failed to push notification to {}: {}", url, x),
+                }
+            });
+        }
+    }
+}
@@ -1,30 +1,193 @@
 use crate::error::Error;
+use crate::fetch_core::{BasicAuth, HttpClient};
 use crate::fetcher::StreamingFetcher;
 use async_compression::tokio::bufread::GzipDecoder;
 use async_trait::async_trait;
-use futures::stream::TryStreamExt;
-use reqwest::Client;
+use reqwest::{Response, StatusCode};
 use serde::Deserialize;
 
 use tokio::io::AsyncBufRead;
 use tokio::io::BufReader;
-use tokio_util::compat::FuturesAsyncReadCompatExt;
+use tokio::sync::Mutex;
 
-pub struct NrFetcher {
-    config: NrFetcherConfig,
-    url: String,
-}
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Network Rail's feeds either take the username and password with every request, or, where
+// auth_url is set, swap them for a token that's sent instead until it expires. Either way we check
+// them when starting up, so a wrong password says so there rather than as a failed download later.
 
 #[derive(Clone, Deserialize)]
 pub struct NrFetcherConfig {
     username: String,
     password: String,
+    auth_url: Option<String>, // e.g. https://opendata.nationalrail.co.uk/authenticate
+    token_lifetime_secs: Option<u64>, // get a new one after this; default 3000
+}
+
+#[derive(Clone, Debug)]
+pub enum NrFetcherErrorType {
+    CredentialsRejected(StatusCode),
+    NoToken,
+}
+
+impl fmt::Display for NrFetcherErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NrFetcherErrorType::CredentialsRejected(x) => write!(
+                f,
+                "Username or password rejected ({}); check nr.fetcher in the config",
+                x
+            ),
+            NrFetcherErrorType::NoToken => write!(f, "Logged in, but no token came back"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Error authenticating with Network Rail: {error_type}")]
+pub struct NrFetcherError {
+    error_type: NrFetcherErrorType,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    token: Option<String>,
+}
+
+fn is_rejection(e: &Error) -> bool {
+    match e {
+        Error::HttpRequestError(x) => matches!(
+            x.status(),
+            Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+        ),
+        _ => false,
+    }
+}
+
+fn rejected(e: Error) -> Error {
+    match &e {
+        Error::HttpRequestError(x) if is_rejection(&e) => Error::NrFetcherError(NrFetcherError {
+            error_type: NrFetcherErrorType::CredentialsRejected(x.status().unwrap()),
+        }),
+        _ => e,
+    }
+}
+
+// shared by all the fetchers, so they log in once between them
+pub struct NrAuth {
+    client: Arc<HttpClient>,
+    auth: BasicAuth,
+    auth_url: Option<String>,
+    token_lifetime: Duration,
+    token: Mutex<Option<(String, Instant)>>, // and when it was got
+}
+
+impl NrAuth {
+    pub fn new(client: Arc<HttpClient>, config: NrFetcherConfig) -> Self {
+        Self {
+            client,
+            auth: BasicAuth {
+                username: config.username,
+                password: config.password,
+            },
+            auth_url: config.auth_url,
+            token_lifetime: Duration::from_secs(config.token_lifetime_secs.unwrap_or(3000)),
+            token: Mutex::new(None),
+        }
+    }
+
+    async fn token(&self, auth_url: &str) -> Result<String, Error> {
+        let mut token = self.token.lock().await;
+        match &*token {
+            Some((x, got)) if got.elapsed() < self.token_lifetime => return Ok(x.clone()),
+            _ => (),
+        }
+        println!("Getting a new Network Rail token");
+        let response = self
+            .client
+            .send("nr-auth", || {
+                self.client.client().post(auth_url).form(&[
+                    ("username", &self.auth.username),
+                    ("password", &self.auth.password),
+                ])
+            })
+            .await
+            .map_err(rejected)?;
+        let new_token =
+            match serde_json::from_slice::<TokenResponse>(&response.bytes().await?)?.token {
+                Some(x) => x,
+                None => {
+                    return Err(Error::NrFetcherError(NrFetcherError {
+                        error_type: NrFetcherErrorType::NoToken,
+                    }))
+                }
+            };
+        *token = Some((new_token.clone(), Instant::now()));
+        Ok(new_token)
+    }
+
+    async fn get_once(&self, source: &str, url: &str) -> Result<Response, Error> {
+        match &self.auth_url {
+            Some(auth_url) => {
+                let token = self.token(auth_url).await?;
+                self.client
+                    .send(source, || {
+                        self.client.client().get(url).header("X-Auth-Token", &token)
+                    })
+                    .await
+            }
+            None => self.client.get(source, url, Some(&self.auth)).await,
+        }
+    }
+
+    // A token can stop working before we think it's expired, so if it's turned down we get a new
+    // one and try once more
+    pub async fn get(&self, source: &str, url: &str) -> Result<Response, Error> {
+        match self.get_once(source, url).await {
+            Err(e) if self.auth_url.is_some() && is_rejection(&e) => {
+                println!("WARNING: Network Rail token rejected, getting a new one");
+                *self.token.lock().await = None;
+                self.get_once(source, url).await.map_err(rejected)
+            }
+            x => x.map_err(rejected),
+        }
+    }
+
+    // Only turning the credentials down is fatal; if the feed's just unreachable for now, the
+    // manager will find out and retry as usual.
+    pub async fn validate(&self, url: &str) -> Result<(), Error> {
+        let result = match &self.auth_url {
+            Some(x) => self.token(x).await.map(|_| ()),
+            // only the headers are read; dropping the response abandons the rest
+            None => self.get("nr-auth", url).await.map(|_| ()),
+        };
+        match result {
+            Err(e @ Error::NrFetcherError(_)) => Err(e),
+            Err(e) => {
+                println!(
+                    "WARNING: Couldn't check Network Rail credentials at startup: {}",
+                    e
+                );
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+}
+
+pub struct NrFetcher {
+    auth: Arc<NrAuth>,
+    name: String, // for download metrics
+    url: String,
 }
 
 impl NrFetcher {
-    pub fn new(config: NrFetcherConfig, url: &str) -> Self {
+    pub fn new(auth: Arc<NrAuth>, name: &str, url: &str) -> Self {
         Self {
-            config,
+            auth,
+            name: name.to_string(),
             url: url.to_string(),
         }
     }
@@ -34,22 +197,8 @@ impl NrFetcher {
 impl StreamingFetcher for NrFetcher {
     async fn fetch(&self) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, Error> {
         println!("Fetching SCHEDULE from Network Rail");
-        let client = Client::new();
-        let response = client
-            .get(self.url.clone())
-            .basic_auth(
-                self.config.username.clone(),
-                Some(self.config.password.clone()),
-            )
-            .send()
-            .await?
-            .error_for_status()?;
-        let reader = response
-            .bytes_stream()
-            .map_err(|e| futures::io::Error::new(futures::io::ErrorKind::Other, e))
-            .into_async_read()
-            .compat();
-        let gz = GzipDecoder::new(BufReader::new(reader));
+        let response = self.auth.get(&self.name, &self.url).await?;
+        let gz = GzipDecoder::new(self.auth.client.reader(&self.name, response));
         Ok(Box::new(BufReader::new(gz)))
     }
 }
@@ -1,18 +1,30 @@
-use crate::error::Error;
+use crate::consist_importer::ConsistImporter;
+use crate::download_cache::{CachingFetcher, DownloadCache};
+use crate::error::{Error, ErrorContext};
+use crate::feed_telemetry::FeedTelemetry;
+use crate::fetch_core::HttpClient;
 use crate::fetcher::StreamingFetcher;
+use crate::file_fetcher::FileFetcher;
 use crate::importer::{EphemeralImporter, FastImporter, SlowStreamingImporter};
-use crate::manager::Manager;
-use crate::nr_fetcher::{NrFetcher, NrFetcherConfig};
+use crate::manager::{ImportScheduler, Manager};
+use crate::notifications::Notifications;
+use crate::nr_fetcher::{NrAuth, NrFetcher, NrFetcherConfig};
+use crate::nr_trust_importer::NrTrustImporter;
 use crate::nr_vstp_subscriber::{NrVstpSubscriber, NrVstpSubscriberConfig};
+use crate::restrictions_importer::RestrictionsImporter;
 use crate::schedule::Schedule;
 use crate::schedule_manager::ScheduleManager;
 use crate::subscriber::Subscriber;
+use crate::train_names_importer::TrainNamesImporter;
+use crate::triggers::{DailyRun, Triggers};
 use crate::uk_importer::{CifImporter, CifImporterConfig, NrJsonImporter, NrJsonImporterConfig};
+use crate::vstp_replay::Replayer;
 
 use chrono::offset::Utc;
 use chrono::{Datelike, Days, NaiveTime, TimeZone};
 use chrono_tz::Europe::London;
 
+use tokio::task::block_in_place;
 use tokio::time;
 use tokio::time::Duration;
 
@@ -20,53 +32,185 @@ use async_trait::async_trait;
 
 use serde::Deserialize;
 
+use std::collections::VecDeque;
 use std::sync::Arc;
 
+// Names of the CIF files, as NR has them and as they're kept in the download cache. The updates
+// are in the order they're applied after the full extract, which is published on Friday night.
+pub const FULL_CIF: &str = "toc-full";
+pub const CIF_UPDATES: [&str; 7] = [
+    "toc-update-sat",
+    "toc-update-sun",
+    "toc-update-mon",
+    "toc-update-tue",
+    "toc-update-wed",
+    "toc-update-thu",
+    "toc-update-fri",
+];
+
+fn cif_url(file_type: &str, name: &str) -> String {
+    format!(
+        "https://publicdatafeeds.networkrail.co.uk/ntrod/CifFileAuthenticate?type={}&day={}.CIF.gz",
+        file_type, name
+    )
+}
+
 #[derive(Clone, Deserialize)]
 pub struct NrConfig {
     fetcher: NrFetcherConfig,
     vstp_subscriber: NrVstpSubscriberConfig,
     json_importer: NrJsonImporterConfig,
     cif_importer: CifImporterConfig,
+    restrictions: Option<String>,
+    consists: Option<String>,    // freight consists, as in consist_importer
+    train_names: Option<String>, // a CSV, as in train_names_importer
+    trust: Option<bool>, // subscribe to train movements too, with the same credentials as VSTP
+    retention_days: Option<u64>, // how long to keep overlays etc. around after they finish
+}
+
+impl NrConfig {
+    // for rebuilding the schedule as it was at some point, from what's been fetched since
+    pub fn replayer(&self, download_cache: Arc<DownloadCache>) -> Replayer {
+        Replayer::new(
+            download_cache,
+            self.cif_importer.clone(),
+            self.json_importer.clone(),
+        )
+    }
 }
 
 pub struct NrManager {
     schedule_manager: Arc<ScheduleManager>,
     config: NrConfig,
+    download_cache: Option<Arc<DownloadCache>>,
+    nr_auth: Arc<NrAuth>,
+    notifications: Arc<Notifications>,
+    triggers: Arc<Triggers>,
+    feed_telemetry: Arc<FeedTelemetry>,
+    import_scheduler: Arc<ImportScheduler>,
 }
 
 impl NrManager {
     pub async fn new(
         config: NrConfig,
         schedule_manager: Arc<ScheduleManager>,
+        download_cache: Option<Arc<DownloadCache>>,
+        http_client: Arc<HttpClient>,
+        notifications: Arc<Notifications>,
+        triggers: Arc<Triggers>,
+        feed_telemetry: Arc<FeedTelemetry>,
+        import_scheduler: Arc<ImportScheduler>,
     ) -> Result<NrManager, Error> {
+        let nr_auth = Arc::new(NrAuth::new(http_client, config.fetcher.clone()));
+        nr_auth
+            .validate(&cif_url("CIF_ALL_UPDATE_DAILY", CIF_UPDATES[0]))
+            .await?;
         Ok(NrManager {
             schedule_manager,
             config,
+            download_cache,
+            nr_auth,
+            notifications,
+            triggers,
+            feed_telemetry,
+            import_scheduler,
         })
     }
 
+    async fn reload_restrictions(&self, mut schedule: Schedule) -> Result<Schedule, Error> {
+        let filename = match &self.config.restrictions {
+            Some(x) => x,
+            None => return Ok(schedule),
+        };
+
+        // the file is always the full list, so start afresh
+        schedule.restrictions.clear();
+        schedule.restrictions_indexed_by_location.clear();
+
+        let mut reader = match FileFetcher::new(filename).fetch().await {
+            Ok(x) => x,
+            Err(x) => {
+                println!("WARNING: Failed to load engineering restrictions: {}", x);
+                return Ok(schedule);
+            }
+        };
+        RestrictionsImporter::new()
+            .overlay(&mut reader, schedule)
+            .await
+    }
+
+    async fn reload_consists(&self, mut schedule: Schedule) -> Result<Schedule, Error> {
+        let filename = match &self.config.consists {
+            Some(x) => x,
+            None => return Ok(schedule),
+        };
+
+        // this is always the full list too
+        schedule.consists.clear();
+
+        let mut reader = match FileFetcher::new(filename).fetch().await {
+            Ok(x) => x,
+            Err(x) => {
+                println!("WARNING: Failed to load freight consists: {}", x);
+                return Ok(schedule);
+            }
+        };
+        ConsistImporter::new().overlay(&mut reader, schedule).await
+    }
+
+    async fn reload_train_names(&self, mut schedule: Schedule) -> Result<Schedule, Error> {
+        let filename = match &self.config.train_names {
+            Some(x) => x,
+            None => return Ok(schedule),
+        };
+
+        // the whole list every time
+        schedule.train_names.clear();
+
+        let mut reader = match FileFetcher::new(filename).fetch().await {
+            Ok(x) => x,
+            Err(x) => {
+                println!("WARNING: Failed to load train names: {}", x);
+                return Ok(schedule);
+            }
+        };
+        TrainNamesImporter::new()
+            .overlay(&mut reader, schedule)
+            .await
+    }
+
+    fn garbage_collect(&self, schedule: &mut Schedule) {
+        let cutoff = London
+            .from_utc_datetime(&Utc::now().naive_utc())
+            .date_naive()
+            .checked_sub_days(Days::new(self.config.retention_days.unwrap_or(7)))
+            .unwrap();
+        let removed = schedule.garbage_collect(cutoff);
+        println!("Garbage collected {} finished overlays etc.", removed);
+    }
+
     // TODO fetch these circular-ly for the daily updates as we are supposed to
     async fn reload_cif(
         &self,
-        nr_fetcher: &NrFetcher,
-        nr_update_fetcher: &Vec<NrFetcher>,
+        nr_fetcher: &CachingFetcher<NrFetcher>,
+        nr_update_fetcher: &Vec<CachingFetcher<NrFetcher>>,
         cif_importer: &mut CifImporter,
         nr_json_importer: &NrJsonImporter,
+        nr_trust_importer: &NrTrustImporter,
     ) -> Result<(), Error> {
+        let slot = self.import_scheduler.start("gbnr").await;
         {
-            // lock for writing now, such that there will be no chance of smaller updates being
-            // lost
-            let mut transaction = self.schedule_manager.transactional_write().await;
-
             let mut schedule = Schedule::new(
                 "gbnr".to_string(),
                 "United Kingdom — Network Rail".to_string(),
             );
 
             let now = London.from_utc_datetime(&Utc::now().naive_utc());
-            let mut reader = nr_fetcher.fetch().await?;
-            schedule = cif_importer.overlay(&mut reader, schedule).await?;
+            let mut reader = nr_fetcher.fetch().await.context("Fetching NR full CIF")?;
+            schedule = cif_importer
+                .overlay(&mut reader, schedule)
+                .await
+                .context("Importing NR full CIF")?;
 
             let mut current_day: usize = now
                 .date_naive()
@@ -86,16 +230,36 @@ impl NrManager {
 
             for i in 0..current_day {
                 println!("Fetching updates for day {}", i);
-                let mut reader = nr_update_fetcher[i].fetch().await?;
-                schedule = cif_importer.overlay(&mut reader, schedule).await?;
+                let mut reader = nr_update_fetcher[i]
+                    .fetch()
+                    .await
+                    .context("Fetching NR CIF update")?;
+                schedule = cif_importer
+                    .overlay(&mut reader, schedule)
+                    .await
+                    .context("Importing NR CIF update")?;
             }
 
+            schedule = self.reload_restrictions(schedule).await?;
+            schedule = self.reload_consists(schedule).await?;
+            schedule = self.reload_train_names(schedule).await?;
+
+            // Only lock for writing now, so other imports and VSTP can carry on meanwhile. VSTP and
+            // TRUST that came in while we were importing went on the old schedule, and are put on
+            // this one from the importers' own records, with nothing more able to come in before
+            // it replaces the old one.
+            let mut transaction = self.schedule_manager.transactional_write().await;
+
             schedule = nr_json_importer.repopulate(schedule).await?;
+            schedule = nr_trust_importer.repopulate(schedule).await?;
 
             // always replace the schedule
-            transaction.insert("gbnr".to_string(), schedule);
+            transaction.put("gbnr", schedule);
+            transaction.source_updated("gbnr-cif");
+            transaction.archive();
             transaction.commit();
         }
+        slot.finished();
 
         nr_json_importer.persist().await?;
 
@@ -107,53 +271,153 @@ impl NrManager {
         nr_json_importer: &NrJsonImporter,
         nr_vstp_subscriber: &mut NrVstpSubscriber,
     ) -> Result<(), Error> {
+        // keep receiving while something else holds the transaction lock, and apply everything
+        // once it's done, rather than leaving the STOMP connection unread for ages. Nothing's
+        // applied before the first CIF import, as anything about trains we don't have yet would
+        // be thrown away.
+        let mut pending = VecDeque::new();
         loop {
-            let res = nr_vstp_subscriber.receive().await?;
-            {
-                let mut schedules = self.schedule_manager.immediate_write().await;
-                let mut schedule = match schedules.remove("gbnr") {
-                    Some(x) => x,
-                    None => Schedule::new(
-                        "gbnr".to_string(),
-                        "United Kingdom — Network Rail".to_string(),
-                    ),
-                };
-                schedule = nr_json_importer.overlay(res, schedule)?;
-                schedules.insert("gbnr".to_string(), schedule);
+            if pending.is_empty() {
+                pending.push_back(
+                    nr_vstp_subscriber
+                        .receive()
+                        .await
+                        .context("Receiving VSTP")?,
+                );
+                continue;
+            }
+
+            tokio::select! {
+                biased;
+
+                mut transaction = async {
+                    self.import_scheduler.wait_imported("gbnr").await;
+                    self.schedule_manager.transactional_write().await
+                } => {
+                    if pending.len() > 1 {
+                        println!("Applying {} buffered VSTP messages", pending.len());
+                    }
+                    // taking our own copy of the schedule can take a moment
+                    block_in_place(|| -> Result<(), Error> {
+                        let mut schedule = match transaction.take("gbnr") {
+                            Some(x) => x,
+                            None => Schedule::new(
+                                "gbnr".to_string(),
+                                "United Kingdom — Network Rail".to_string(),
+                            ),
+                        };
+                        while let Some(res) = pending.pop_front() {
+                            schedule = nr_json_importer
+                                .overlay(res, schedule)
+                                .context("Importing VSTP")?;
+                        }
+                        for train_id in nr_json_importer.take_changed_trains() {
+                            transaction.train_changed("gbnr", &train_id);
+                        }
+                        for (train_id, change) in nr_json_importer.take_changes() {
+                            transaction.log_change("gbnr", &train_id, change);
+                        }
+                        transaction.put("gbnr", schedule);
+                        transaction.source_updated("gbnr-vstp");
+                        Ok(())
+                    })?;
+                    transaction.commit();
+                }
+                res = nr_vstp_subscriber.receive() => {
+                    pending.push_back(res.context("Receiving VSTP")?);
+                    continue;
+                }
             }
             nr_json_importer.persist().await?;
+            // only now is it safe for the broker to forget them
+            nr_vstp_subscriber.ack().await?;
+        }
+    }
+
+    async fn read_trust(
+        &self,
+        nr_trust_importer: &NrTrustImporter,
+        nr_trust_subscriber: &mut NrVstpSubscriber,
+    ) -> Result<(), Error> {
+        // there are a lot of these, so apply them in batches rather than committing a new schedule
+        // (and throwing away every cached board) several times a second. As with VSTP, keep
+        // receiving while waiting for the transaction lock, and keep them until there's a
+        // timetable to put them on.
+        let mut pending = vec![];
+        let mut interval = time::interval(Duration::from_secs(10));
+        let mut due = false;
+        loop {
+            if !due || pending.is_empty() {
+                tokio::select! {
+                    _ = interval.tick() => due = true,
+                    res = nr_trust_subscriber.receive() => {
+                        pending.push(res.context("Receiving TRUST")?);
+                    }
+                }
+                continue;
+            }
+
+            tokio::select! {
+                biased;
+
+                mut transaction = async {
+                    self.import_scheduler.wait_imported("gbnr").await;
+                    self.schedule_manager.transactional_write().await
+                } => {
+                    block_in_place(|| -> Result<(), Error> {
+                        let mut schedule = match transaction.take("gbnr") {
+                            Some(x) => x,
+                            None => Schedule::new(
+                                "gbnr".to_string(),
+                                "United Kingdom — Network Rail".to_string(),
+                            ),
+                        };
+                        for res in pending.drain(..) {
+                            schedule = nr_trust_importer
+                                .overlay(res, schedule)
+                                .context("Importing TRUST")?;
+                        }
+                        for train_id in nr_trust_importer.take_changed_trains() {
+                            transaction.train_changed("gbnr", &train_id);
+                        }
+                        for (train_id, change) in nr_trust_importer.take_changes() {
+                            transaction.log_change("gbnr", &train_id, change);
+                        }
+                        transaction.put("gbnr", schedule);
+                        transaction.source_updated("gbnr-trust");
+                        Ok(())
+                    })?;
+                    transaction.commit();
+                    nr_trust_subscriber.ack().await?;
+                    due = false;
+                }
+                res = nr_trust_subscriber.receive() => {
+                    pending.push(res.context("Receiving TRUST")?);
+                }
+            }
         }
     }
 
     // TODO fetch these circular-ly for the daily updates as we are supposed to
     async fn update_cif(
         &self,
-        nr_fetcher: &NrFetcher,
-        nr_update_fetcher: &Vec<NrFetcher>,
+        nr_fetcher: &CachingFetcher<NrFetcher>,
+        nr_update_fetcher: &Vec<CachingFetcher<NrFetcher>>,
         cif_importer: &mut CifImporter,
         nr_json_importer: &NrJsonImporter,
+        nr_trust_importer: &NrTrustImporter,
     ) -> Result<(), Error> {
+        let mut daily = DailyRun::new(
+            London,
+            NaiveTime::from_hms_opt(2, 9, 0).unwrap(),
+            &self.triggers,
+            "gbnr-cif",
+        );
         loop {
-            let now = London.from_utc_datetime(&Utc::now().naive_utc());
-            let new_time = if now.time() > NaiveTime::from_hms_opt(2, 9, 0).unwrap() {
-                London
-                    .from_local_datetime(
-                        &now.date_naive()
-                            .checked_add_days(Days::new(1))
-                            .unwrap()
-                            .and_hms_opt(2, 9, 0)
-                            .unwrap(),
-                    )
-                    .unwrap()
-            } else {
-                London
-                    .from_local_datetime(&now.date_naive().and_hms_opt(2, 9, 0).unwrap())
-                    .unwrap()
-            };
-            let mut interval = time::interval(Duration::from_secs(15));
-            while London.from_utc_datetime(&Utc::now().naive_utc()) < new_time {
-                interval.tick().await;
+            if daily.wait().await {
+                println!("Fetching NR CIF update early, as upstream says it's out");
             }
+            let now = London.from_utc_datetime(&Utc::now().naive_utc());
 
             let current_day: usize = now
                 .date_naive()
@@ -167,22 +431,46 @@ impl NrManager {
                     nr_update_fetcher,
                     cif_importer,
                     nr_json_importer,
+                    nr_trust_importer,
                 )
                 .await?;
             } else {
                 {
-                    let mut transaction = self.schedule_manager.transactional_write().await;
-
-                    let mut schedule = match transaction.remove("gbnr") {
-                        Some(x) => x,
-                        None => Schedule::new(
-                            "gbnr".to_string(),
-                            "United Kingdom — Network Rail".to_string(),
-                        ),
+                    // As with a full import, the update goes on our own copy of the schedule, so
+                    // VSTP and TRUST can carry on while it's fetched and imported. The copy's
+                    // taken under the lock, so we know which VSTP messages it already has.
+                    let (mut schedule, vstp_received) = {
+                        let transaction = self.schedule_manager.transactional_write().await;
+                        let schedule = match transaction.get("gbnr") {
+                            Some(x) => Schedule::clone(x),
+                            None => Schedule::new(
+                                "gbnr".to_string(),
+                                "United Kingdom — Network Rail".to_string(),
+                            ),
+                        };
+                        (schedule, nr_json_importer.received())
                     };
-                    let mut reader = nr_update_fetcher[current_day].fetch().await?;
-                    schedule = cif_importer.overlay(&mut reader, schedule).await?;
-                    transaction.insert("gbnr".to_string(), schedule);
+                    let mut reader = nr_update_fetcher[current_day]
+                        .fetch()
+                        .await
+                        .context("Fetching NR CIF update")?;
+                    schedule = cif_importer
+                        .overlay(&mut reader, schedule)
+                        .await
+                        .context("Importing NR CIF update")?;
+                    block_in_place(|| self.garbage_collect(&mut schedule));
+                    schedule = self.reload_restrictions(schedule).await?;
+                    schedule = self.reload_consists(schedule).await?;
+                    schedule = self.reload_train_names(schedule).await?;
+
+                    // then whatever VSTP and TRUST did to the old one meanwhile goes on this
+                    let mut transaction = self.schedule_manager.transactional_write().await;
+                    schedule =
+                        block_in_place(|| nr_json_importer.reapply(schedule, vstp_received))?;
+                    schedule = nr_trust_importer.repopulate(schedule).await?;
+                    transaction.put("gbnr", schedule);
+                    transaction.source_updated("gbnr-cif");
+                    transaction.archive();
 
                     transaction.commit();
                 }
@@ -194,29 +482,48 @@ impl NrManager {
 #[async_trait]
 impl Manager for NrManager {
     async fn run(&mut self) -> Result<(), Error> {
-        let nr_main_fetcher = NrFetcher::new(self.config.fetcher.clone(), "https://publicdatafeeds.networkrail.co.uk/ntrod/CifFileAuthenticate?type=CIF_ALL_FULL_DAILY&day=toc-full.CIF.gz");
-        let nr_update_fetchers = vec![
-            NrFetcher::new(self.config.fetcher.clone(), "https://publicdatafeeds.networkrail.co.uk/ntrod/CifFileAuthenticate?type=CIF_ALL_UPDATE_DAILY&day=toc-update-sat.CIF.gz"),
-            NrFetcher::new(self.config.fetcher.clone(), "https://publicdatafeeds.networkrail.co.uk/ntrod/CifFileAuthenticate?type=CIF_ALL_UPDATE_DAILY&day=toc-update-sun.CIF.gz"),
-            NrFetcher::new(self.config.fetcher.clone(), "https://publicdatafeeds.networkrail.co.uk/ntrod/CifFileAuthenticate?type=CIF_ALL_UPDATE_DAILY&day=toc-update-mon.CIF.gz"),
-            NrFetcher::new(self.config.fetcher.clone(), "https://publicdatafeeds.networkrail.co.uk/ntrod/CifFileAuthenticate?type=CIF_ALL_UPDATE_DAILY&day=toc-update-tue.CIF.gz"),
-            NrFetcher::new(self.config.fetcher.clone(), "https://publicdatafeeds.networkrail.co.uk/ntrod/CifFileAuthenticate?type=CIF_ALL_UPDATE_DAILY&day=toc-update-wed.CIF.gz"),
-            NrFetcher::new(self.config.fetcher.clone(), "https://publicdatafeeds.networkrail.co.uk/ntrod/CifFileAuthenticate?type=CIF_ALL_UPDATE_DAILY&day=toc-update-thu.CIF.gz"),
-            NrFetcher::new(self.config.fetcher.clone(), "https://publicdatafeeds.networkrail.co.uk/ntrod/CifFileAuthenticate?type=CIF_ALL_UPDATE_DAILY&day=toc-update-fri.CIF.gz"),
-        ];
+        let nr_main_fetcher = CachingFetcher::new(
+            NrFetcher::new(
+                self.nr_auth.clone(),
+                FULL_CIF,
+                &cif_url("CIF_ALL_FULL_DAILY", FULL_CIF),
+            ),
+            FULL_CIF,
+            self.download_cache.clone(),
+        );
+        let nr_update_fetchers = CIF_UPDATES
+            .iter()
+            .map(|x| {
+                CachingFetcher::new(
+                    NrFetcher::new(self.nr_auth.clone(), x, &cif_url("CIF_ALL_UPDATE_DAILY", x)),
+                    x,
+                    self.download_cache.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
         let mut cif_importer = CifImporter::new(self.config.cif_importer.clone());
-        let mut nr_vstp_subscriber = NrVstpSubscriber::new(self.config.vstp_subscriber.clone());
-        let nr_json_importer = NrJsonImporter::new(self.config.json_importer.clone()).await?;
+        let mut nr_vstp_subscriber = NrVstpSubscriber::new(self.config.vstp_subscriber.clone())
+            .with_telemetry(self.feed_telemetry.clone(), "gbnr-vstp");
+        let nr_json_importer = NrJsonImporter::new(self.config.json_importer.clone())
+            .await?
+            .with_telemetry(self.feed_telemetry.clone());
+        let nr_trust_importer = NrTrustImporter::new_with_notifications(self.notifications.clone());
+        let mut nr_trust_subscriber = match self.config.trust {
+            Some(true) => Some(
+                NrVstpSubscriber::new_with_topic(
+                    self.config.vstp_subscriber.clone(),
+                    "TRAIN_MVT_ALL_TOC",
+                )
+                .with_telemetry(self.feed_telemetry.clone(), "gbnr-trust"),
+            ),
+            _ => None,
+        };
 
         nr_vstp_subscriber.subscribe().await?;
-
-        self.reload_cif(
-            &nr_main_fetcher,
-            &nr_update_fetchers,
-            &mut cif_importer,
-            &nr_json_importer,
-        )
-        .await?;
+        match &mut nr_trust_subscriber {
+            Some(x) => x.subscribe().await?,
+            None => (),
+        }
 
         tokio::try_join!(
             async {
@@ -225,12 +532,28 @@ impl Manager for NrManager {
                     .await;
             },
             async {
+                return match &mut nr_trust_subscriber {
+                    Some(x) => self.read_trust(&nr_trust_importer, x).await,
+                    None => Ok(()),
+                };
+            },
+            async {
+                self.reload_cif(
+                    &nr_main_fetcher,
+                    &nr_update_fetchers,
+                    &mut cif_importer,
+                    &nr_json_importer,
+                    &nr_trust_importer,
+                )
+                .await?;
+
                 return self
                     .update_cif(
                         &nr_main_fetcher,
                         &nr_update_fetchers,
                         &mut cif_importer,
                         &nr_json_importer,
+                        &nr_trust_importer,
                     )
                     .await;
             },
@@ -0,0 +1,507 @@
+use crate::change_log::{ChangeKind, TrainChange};
+use crate::error::Error;
+use crate::importer::{EphemeralImporter, FastImporter};
+use crate::notifications::{Notifications, TrainEvent};
+use crate::schedule::{
+    get_train_instance, RealtimeCancellation, RealtimeEvent, RealtimeEventType, Schedule,
+    TrainRealtime, TrainSource,
+};
+
+use async_trait::async_trait;
+
+use chrono::{DateTime, Days, NaiveDate, TimeZone, Utc};
+use chrono_tz::Europe::London;
+use chrono_tz::Tz;
+
+use serde::Deserialize;
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+
+// Each STOMP message from TRAIN_MVT_ALL_TOC is a JSON array of these. Everything in the body is a
+// string, and which fields are present depends on the message type, so take what we need.
+#[derive(Deserialize)]
+struct TrustMessage {
+    header: TrustHeader,
+    body: TrustBody,
+}
+
+#[derive(Deserialize)]
+struct TrustHeader {
+    msg_type: String,
+    #[serde(default)]
+    msg_queue_timestamp: Option<String>, // when TRUST sent it on
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct TrustBody {
+    train_id: Option<String>,
+    train_uid: Option<String>,
+    schedule_source: Option<String>, // C for CIF, V for VSTP
+    schedule_type: Option<String>,   // see activated_version
+    schedule_start_date: Option<String>,
+    train_service_code: Option<String>,
+    tp_origin_timestamp: Option<String>,
+    origin_dep_timestamp: Option<String>,
+    creation_timestamp: Option<String>,
+    canx_reason_code: Option<String>,
+    canx_type: Option<String>,
+    canx_timestamp: Option<String>,
+    loc_stanox: Option<String>,
+    event_type: Option<String>,
+    planned_timestamp: Option<String>,
+    actual_timestamp: Option<String>,
+    platform: Option<String>,
+    timetable_variation: Option<String>,
+    variation_status: Option<String>,
+    train_terminated: Option<String>,
+    offroute_ind: Option<String>,
+    revised_train_id: Option<String>,
+}
+
+#[derive(Default)]
+struct TrustState {
+    activations: HashMap<String, (String, NaiveDate)>, // TRUST ID to train UID and date it runs
+    realtime: HashMap<String, HashMap<NaiveDate, TrainRealtime>>,
+    changed_trains: HashSet<String>,     // since anyone last asked
+    changes: Vec<(String, TrainChange)>, // likewise
+    pruned: Option<NaiveDate>,           // the day we last did, in UK time
+}
+
+// TRUST says what trains actually did, rather than what they were planned to do. Trains are
+// activated shortly before they run, which is the only message that tells us the UID and date; all
+// later messages only have the TRUST ID, so we have to remember the mapping.
+pub struct NrTrustImporter {
+    state: Arc<RwLock<TrustState>>,
+    notifications: Option<Arc<Notifications>>,
+}
+
+impl Default for NrTrustImporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NrTrustImporter {
+    pub fn new() -> NrTrustImporter {
+        NrTrustImporter {
+            state: Arc::new(RwLock::new(TrustState::default())),
+            notifications: None,
+        }
+    }
+
+    pub fn new_with_notifications(notifications: Arc<Notifications>) -> NrTrustImporter {
+        NrTrustImporter {
+            state: Arc::new(RwLock::new(TrustState::default())),
+            notifications: Some(notifications),
+        }
+    }
+}
+
+// TRUST timestamps are milliseconds since the epoch; show them in UK time
+fn read_trust_timestamp(timestamp: &Option<String>) -> Option<DateTime<Tz>> {
+    let millis = timestamp.as_ref()?.trim().parse::<i64>().ok()?;
+    Some(
+        Utc.timestamp_millis_opt(millis)
+            .single()?
+            .with_timezone(&London),
+    )
+}
+
+fn read_trust_date(timestamp: &Option<String>) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(timestamp.as_ref()?.trim(), "%Y-%m-%d").ok()
+}
+
+// Cancellation reasons are delay attribution codes, and there are hundreds of them, but the first
+// letter gives the broad cause, which is about as much as a passenger wants to know anyway.
+fn describe_reason_code(code: &str) -> Option<String> {
+    let code = code.trim();
+    let description = match code.chars().next()? {
+        'I' | 'J' => "a fault with the signalling or track",
+        'M' | 'N' => "a train fault",
+        'O' => "an operational problem",
+        'P' => "planned engineering work",
+        'R' => "a problem at a station",
+        'T' | 'F' => "a problem with the train crew or operator",
+        'V' => "an incident involving passengers",
+        'X' => "circumstances outside the railway's control",
+        'Y' => "an earlier disruption",
+        _ => return None,
+    };
+    Some(format!("{} ({})", description, code))
+}
+
+// what the timetable says the platform is at the first of these locations the train calls at
+fn planned_platform(
+    schedule: &Schedule,
+    train_uid: &str,
+    date: NaiveDate,
+    location_ids: &[String],
+) -> Option<String> {
+    let train = get_train_instance(schedule.trains.get(train_uid)?, date).0?;
+    train
+        .route
+        .iter()
+        .find(|x| location_ids.iter().any(|y| x.id == *y))?
+        .scheduled_platform
+        .as_ref()
+        .map(|x| x.to_string())
+}
+
+fn stanox_to_location_ids(schedule: &Schedule, stanox: &Option<String>) -> Vec<String> {
+    let stanox = match stanox {
+        Some(x) => x,
+        None => return vec![],
+    };
+    let mut location_ids = schedule
+        .locations_indexed_by_stanox
+        .get(stanox)
+        .map(|x| x.iter().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    location_ids.sort();
+    location_ids
+}
+
+// An activation says which schedule it's for, not just which UID: whether it came from CIF or VSTP,
+// whether it's the permanent one or a short-term one, when that starts, and its service code. It's
+// only taken if that's the version we'd run that day, as otherwise everything TRUST says about the
+// train would be put against the wrong one, e.g. the permanent schedule while an overlay runs.
+fn activated_version(
+    schedule: &Schedule,
+    body: &TrustBody,
+    train_uid: &str,
+    date: NaiveDate,
+) -> Result<(), String> {
+    let train = match schedule.trains.get(train_uid) {
+        Some(x) => match get_train_instance(x, date).0 {
+            Some(x) => x,
+            None => return Err("it doesn't run that day".to_string()),
+        },
+        None => return Err("we don't have it".to_string()),
+    };
+    // TRUST has O and P the other way round from CIF, so O's the permanent schedule
+    let source = match (
+        body.schedule_source.as_deref(),
+        body.schedule_type.as_deref(),
+    ) {
+        (Some("V"), _) => Some(TrainSource::VeryShortTerm),
+        (_, Some("O")) => Some(TrainSource::LongTerm),
+        (_, Some("P")) | (_, Some("N")) => Some(TrainSource::ShortTerm),
+        _ => None,
+    };
+    match (source, train.source) {
+        (Some(x), Some(y)) if x != y => {
+            return Err(format!("it's for a {:?} schedule, but {:?} runs", x, y))
+        }
+        _ => (),
+    }
+    match read_trust_date(&body.schedule_start_date) {
+        Some(x)
+            if !train
+                .validity
+                .iter()
+                .any(|y| y.valid_begin.date_naive() == x) =>
+        {
+            return Err(format!("it's for a schedule starting {}", x))
+        }
+        _ => (),
+    }
+    match (
+        &body.train_service_code,
+        &train.variable_train.service_group,
+    ) {
+        (Some(x), Some(y)) if x.trim() != y.trim() => {
+            return Err(format!("it's for service code {}, not {}", x.trim(), y))
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+impl TrustState {
+    fn get_realtime_mut(
+        &mut self,
+        trust_id: &str,
+    ) -> Option<(&String, NaiveDate, &mut TrainRealtime)> {
+        let (train_uid, date) = match self.activations.get(trust_id) {
+            Some(x) => x,
+            None => return None, // activated before we started listening, most likely
+        };
+        let realtime = self.realtime.get_mut(train_uid)?.get_mut(date)?;
+        Some((train_uid, *date, realtime))
+    }
+
+    // returns the train UID and date which changed, if any, adding anything worth telling
+    // subscribers about to `events`
+    fn apply(
+        &mut self,
+        message: &TrustMessage,
+        schedule: &Schedule,
+        events: &mut Vec<(String, NaiveDate, TrainEvent)>,
+    ) -> Option<(String, NaiveDate)> {
+        let body = &message.body;
+        let trust_id = body.train_id.as_ref()?;
+        match message.header.msg_type.as_str() {
+            "0001" => {
+                // activation
+                let train_uid = body.train_uid.as_ref()?.trim().to_string();
+                let date = read_trust_date(&body.tp_origin_timestamp)?;
+                match activated_version(schedule, body, &train_uid, date) {
+                    Ok(()) => (),
+                    Err(x) => {
+                        println!(
+                            "Ignoring TRUST activation of {} on {}, as {}",
+                            train_uid, date, x
+                        );
+                        return None;
+                    }
+                }
+                self.activations
+                    .insert(trust_id.clone(), (train_uid.clone(), date));
+                self.realtime.entry(train_uid.clone()).or_default().insert(
+                    date,
+                    TrainRealtime {
+                        realtime_id: trust_id.clone(),
+                        activated: read_trust_timestamp(&body.creation_timestamp)
+                            .or(read_trust_timestamp(&body.origin_dep_timestamp)),
+                        cancellation: None,
+                        events: vec![],
+                        terminated: false,
+                    },
+                );
+                Some((train_uid, date))
+            }
+            "0002" => {
+                // cancellation
+                let location_ids = stanox_to_location_ids(schedule, &body.loc_stanox);
+                let (train_uid, date, realtime) = self.get_realtime_mut(trust_id)?;
+                let cancellation = RealtimeCancellation {
+                    location_ids,
+                    reason_code: body.canx_reason_code.clone(),
+                    reason: body
+                        .canx_reason_code
+                        .as_deref()
+                        .and_then(describe_reason_code),
+                    cancellation_type: body.canx_type.clone(),
+                    // sometimes left empty, and it's not worth losing the cancellation over
+                    timestamp: read_trust_timestamp(&body.canx_timestamp)
+                        .or(read_trust_timestamp(&message.header.msg_queue_timestamp))?,
+                };
+                events.push((
+                    train_uid.clone(),
+                    date,
+                    TrainEvent::Cancelled {
+                        location_ids: cancellation.location_ids.clone(),
+                        reason: cancellation.reason.clone(),
+                    },
+                ));
+                realtime.cancellation = Some(cancellation);
+                Some((train_uid.clone(), date))
+            }
+            "0003" => {
+                // movement
+                let location_ids = stanox_to_location_ids(schedule, &body.loc_stanox);
+                let event_type = match body.event_type.as_ref()?.as_str() {
+                    "ARRIVAL" => RealtimeEventType::Arrival,
+                    "DEPARTURE" => RealtimeEventType::Departure,
+                    _ => return None,
+                };
+                let variation = body
+                    .timetable_variation
+                    .as_ref()
+                    .and_then(|x| x.trim().parse::<i64>().ok())
+                    .unwrap_or(0);
+                let delay_minutes = match body.variation_status.as_deref() {
+                    Some("EARLY") => -variation,
+                    Some("LATE") | Some("OFF ROUTE") => variation,
+                    _ => 0,
+                };
+                let event = RealtimeEvent {
+                    location_ids,
+                    event_type,
+                    planned: read_trust_timestamp(&body.planned_timestamp),
+                    actual: read_trust_timestamp(&body.actual_timestamp)?,
+                    platform: body
+                        .platform
+                        .as_ref()
+                        .map(|x| x.trim().to_string())
+                        .filter(|x| !x.is_empty()),
+                    delay_minutes,
+                    off_route: body.offroute_ind.as_deref() == Some("true"),
+                };
+                let terminated = body.train_terminated.as_deref() == Some("true");
+                let (train_uid, date, realtime) = self.get_realtime_mut(trust_id)?;
+
+                // only tell people when the delay changes, not at every timing point
+                let previous_delay = realtime.events.last().map(|x| x.delay_minutes);
+                if previous_delay.unwrap_or(0) != event.delay_minutes {
+                    events.push((
+                        train_uid.clone(),
+                        date,
+                        TrainEvent::Retimed {
+                            location_ids: event.location_ids.clone(),
+                            delay_minutes: event.delay_minutes,
+                        },
+                    ));
+                }
+                match &event.platform {
+                    Some(platform) => {
+                        let planned =
+                            planned_platform(schedule, train_uid, date, &event.location_ids);
+                        if planned.as_ref() != Some(platform) {
+                            events.push((
+                                train_uid.clone(),
+                                date,
+                                TrainEvent::PlatformChanged {
+                                    location_ids: event.location_ids.clone(),
+                                    platform: platform.clone(),
+                                    planned_platform: planned,
+                                },
+                            ));
+                        }
+                    }
+                    None => (),
+                }
+
+                realtime.events.push(event);
+                realtime.terminated = realtime.terminated || terminated;
+                Some((train_uid.clone(), date))
+            }
+            "0005" => {
+                // reinstatement
+                let (train_uid, date, realtime) = self.get_realtime_mut(trust_id)?;
+                realtime.cancellation = None;
+                events.push((train_uid.clone(), date, TrainEvent::Reinstated));
+                Some((train_uid.clone(), date))
+            }
+            "0007" => {
+                // change of identity; keep the same train under its new TRUST ID
+                let revised_train_id = body.revised_train_id.as_ref()?.clone();
+                let activation = self.activations.remove(trust_id)?;
+                self.activations
+                    .insert(revised_train_id.clone(), activation.clone());
+                let (train_uid, date, realtime) = self.get_realtime_mut(&revised_train_id)?;
+                realtime.realtime_id = revised_train_id;
+                Some((train_uid.clone(), date))
+            }
+            _ => None, // changes of origin and location don't tell us anything we use yet
+        }
+    }
+
+    // trains from more than a couple of days ago won't get any more messages; gives the first day
+    // that's kept
+    fn prune(&mut self) -> NaiveDate {
+        let today = London
+            .from_utc_datetime(&Utc::now().naive_utc())
+            .date_naive();
+        let cutoff = today.checked_sub_days(Days::new(2)).unwrap();
+        self.pruned = Some(today);
+        self.activations.retain(|_, (_, date)| *date >= cutoff);
+        for dates in self.realtime.values_mut() {
+            dates.retain(|date, _| *date >= cutoff);
+        }
+        self.realtime.retain(|_, dates| !dates.is_empty());
+        cutoff
+    }
+}
+
+#[async_trait]
+impl FastImporter for NrTrustImporter {
+    fn overlay(&self, data: Vec<u8>, mut schedule: Schedule) -> Result<Schedule, Error> {
+        // one bad batch shouldn't take down the whole feed
+        let messages = match serde_json::from_slice::<Vec<TrustMessage>>(&data) {
+            Ok(x) => x,
+            Err(x) => {
+                println!("Failed to read TRUST message: {}", x);
+                return Ok(schedule);
+            }
+        };
+        let mut state = self.state.write().unwrap();
+        // otherwise nothing's thrown away until the next full import, which can be a week off
+        let today = London
+            .from_utc_datetime(&Utc::now().naive_utc())
+            .date_naive();
+        if state.pruned != Some(today) {
+            let cutoff = state.prune();
+            for dates in schedule.realtime.values_mut() {
+                dates.retain(|date, _| *date >= cutoff);
+            }
+            schedule.realtime.retain(|_, dates| !dates.is_empty());
+        }
+        let mut changed = HashSet::new();
+        let mut events = vec![];
+        for message in &messages {
+            match state.apply(message, &schedule, &mut events) {
+                Some(x) => {
+                    changed.insert(x);
+                }
+                None => (),
+            }
+        }
+
+        for (train_uid, date) in changed {
+            state.changed_trains.insert(train_uid.clone());
+            match state.realtime.get(&train_uid).and_then(|x| x.get(&date)) {
+                Some(x) => {
+                    schedule
+                        .realtime
+                        .entry(train_uid)
+                        .or_default()
+                        .insert(date, x.clone());
+                }
+                None => (),
+            }
+        }
+
+        for (train_uid, date, event) in &events {
+            state.changes.push((
+                train_uid.clone(),
+                TrainChange::new(
+                    *date,
+                    *date,
+                    ChangeKind::Realtime {
+                        event: event.clone(),
+                    },
+                ),
+            ));
+        }
+
+        match &self.notifications {
+            Some(notifications) => {
+                for (train_uid, date, event) in events {
+                    notifications.publish(&schedule, &train_uid, date, event);
+                }
+            }
+            None => (),
+        }
+
+        Ok(schedule)
+    }
+
+    fn take_changed_trains(&self) -> Vec<String> {
+        let mut state = self.state.write().unwrap();
+        state.changed_trains.drain().collect()
+    }
+
+    fn take_changes(&self) -> Vec<(String, TrainChange)> {
+        let mut state = self.state.write().unwrap();
+        std::mem::take(&mut state.changes)
+    }
+}
+
+#[async_trait]
+impl EphemeralImporter for NrTrustImporter {
+    async fn repopulate(&self, mut schedule: Schedule) -> Result<Schedule, Error> {
+        println!("Repopulating TRUST entries...");
+        let mut state = self.state.write().unwrap();
+        state.prune();
+        schedule.realtime = state.realtime.clone().into();
+        Ok(schedule)
+    }
+
+    // what actually happened is only interesting for a day or so, so not worth keeping on disk
+    async fn persist(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
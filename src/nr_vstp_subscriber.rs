@@ -1,14 +1,20 @@
 use crate::error::Error;
+use crate::feed_telemetry::FeedTelemetry;
 use crate::subscriber::Subscriber;
 use async_trait::async_trait;
 use serde::Deserialize;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
 use tokio::task::JoinHandle;
 
-use tokio_stomp::client;
-use tokio_stomp::client::ClientTransport;
+use tokio_stomp::client::ClientCodec;
+use tokio_stomp::AckMode;
 use tokio_stomp::FromServer;
 use tokio_stomp::ToServer;
 
+use tokio::net::TcpStream;
+use tokio_util::bytes::{BufMut, BytesMut};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
 use futures::stream::SplitSink;
 use futures::stream::SplitStream;
 use futures::SinkExt;
@@ -16,125 +22,517 @@ use futures::StreamExt;
 
 use tokio::time::Duration;
 
-use std::fmt;
+use std::sync::Arc;
 
+// Despite the name this will read any of the Network Rail STOMP topics; VSTP was just the first.
 pub struct NrVstpSubscriber {
     config: NrVstpSubscriberConfig,
-    stream: Option<SplitStream<ClientTransport>>,
+    topic: String,
+    stream: Option<SplitStream<StompTransport>>,
+    outgoing: Option<UnboundedSender<tokio_stomp::Message<ToServer>>>,
     keepalive: Option<JoinHandle<Result<(), Error>>>,
+    sequence: MessageSequence,
+    unacked: Vec<String>,
+    telemetry: Option<(Arc<FeedTelemetry>, String)>, // and the source name to report under
 }
 
 #[derive(Clone, Deserialize)]
 pub struct NrVstpSubscriberConfig {
     username: String,
     password: String,
+    heartbeat_timeout_secs: Option<u64>,
+    client_id: Option<String>, // defaults to the username; must be unique to each of our hosts
 }
 
 impl NrVstpSubscriber {
     pub fn new(config: NrVstpSubscriberConfig) -> Self {
+        Self::new_with_topic(config, "VSTP_ALL")
+    }
+
+    pub fn new_with_topic(config: NrVstpSubscriberConfig, topic: &str) -> Self {
         Self {
             config,
+            topic: topic.to_string(),
             stream: None,
+            outgoing: None,
             keepalive: None,
+            sequence: MessageSequence::default(),
+            unacked: vec![],
+            telemetry: None,
+        }
+    }
+
+    // to report whether we're connected, e.g. as "gbnr-vstp"
+    pub fn with_telemetry(mut self, telemetry: Arc<FeedTelemetry>, source: &str) -> Self {
+        self.telemetry = Some((telemetry, source.to_string()));
+        self
+    }
+
+    fn connected(&self, connected: bool) {
+        match &self.telemetry {
+            Some((telemetry, source)) => telemetry.connection(source, connected),
+            None => (),
+        }
+    }
+
+    async fn connect(&mut self) -> Result<(), Error> {
+        match self.keepalive.take() {
+            Some(x) => x.abort(),
+            None => (),
+        }
+        self.stream = None;
+        self.outgoing = None;
+        // anything we hadn't acked yet will come round again on the new connection
+        self.unacked.clear();
+        self.connected(false);
+
+        // A durable subscription, so the broker holds on to anything we miss while disconnected.
+        // ActiveMQ wants the same client-id and subscription name each time, and the client-id
+        // can't be shared between connections, hence one per topic.
+        let subscription_name = format!(
+            "{}-{}",
+            self.config
+                .client_id
+                .as_ref()
+                .unwrap_or(&self.config.username),
+            self.topic
+        );
+
+        let tcp = TcpStream::connect("publicdatafeeds.networkrail.co.uk:61618").await?;
+        let mut transport = StompCodec.framed(tcp);
+        let mut connect: tokio_stomp::Message<ToServer> = ToServer::Connect {
+            accept_version: "1.2".to_string(),
+            host: "/".to_string(),
+            login: Some(self.config.username.clone()),
+            passcode: Some(self.config.password.clone()),
+            heartbeat: None,
+        }
+        .into();
+        connect.extra_headers.push((
+            b"client-id".to_vec(),
+            subscription_name.clone().into_bytes(),
+        ));
+        transport.send(connect).await?;
+        match transport.next().await {
+            Some(Ok(tokio_stomp::Message {
+                content: FromServer::Connected { .. },
+                ..
+            })) => (),
+            Some(Err(x)) => return Err(x.into()),
+            x => {
+                return Err(Error::NrVstpError(NrVstpError {
+                    what: format!("Unexpected reply to CONNECT: {:?}", x),
+                }))
+            }
+        }
+        let (mut sink, stream) = transport.split();
+
+        let mut subscribe: tokio_stomp::Message<ToServer> = ToServer::Subscribe {
+            destination: format!("/topic/{}", self.topic),
+            id: "1".to_string(),
+            ack: Some(AckMode::ClientIndividual),
+        }
+        .into();
+        subscribe.extra_headers.push((
+            b"activemq.subscriptionName".to_vec(),
+            subscription_name.into_bytes(),
+        ));
+        sink.send(subscribe).await?;
+
+        let (outgoing_tx, outgoing_rx) = unbounded_channel();
+        self.stream = Some(stream);
+        self.outgoing = Some(outgoing_tx);
+        self.keepalive = Some(tokio::spawn(async move {
+            return keep_alive(sink, outgoing_rx).await;
+        }));
+        self.connected(true);
+
+        Ok(())
+    }
+
+    async fn reconnect(&mut self) {
+        let mut backoff = Duration::from_secs(5);
+        loop {
+            println!("Reconnecting to {} data from Network Rail", self.topic);
+            match self.connect().await {
+                Ok(()) => return,
+                Err(x) => {
+                    println!(
+                        "Failed to reconnect to {} stream, retrying in {} seconds: {}",
+                        self.topic,
+                        backoff.as_secs(),
+                        x
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = std::cmp::min(backoff * 2, Duration::from_secs(300));
+                }
+            }
+        }
+    }
+
+    fn check_sequence(&mut self, message_id: &str) {
+        let missed = self.sequence.check(message_id);
+        if missed > 0 {
+            println!(
+                "Warning: missed {} {} message(s) before {}",
+                missed, self.topic, message_id
+            );
         }
     }
 }
 
-#[derive(Debug)]
-pub struct NrVstpError {
-    what: String,
+// Where we're up to in a STOMP topic, so we know when we've missed something. ActiveMQ message
+// IDs end in a per-producer sequence number, e.g.
+// ID:some-host-12345-1691564234236-11:1:1:1:5469
+#[derive(Default)]
+pub struct MessageSequence {
+    last: Option<(String, u64)>,
+}
+
+impl MessageSequence {
+    // how many were missed just before this one; none if we can't tell, e.g. after the broker
+    // restarts and the producer changes
+    pub fn check(&mut self, message_id: &str) -> u64 {
+        let (producer, sequence) = match message_id.rsplit_once(':') {
+            Some((producer, sequence)) => match sequence.parse::<u64>() {
+                Ok(sequence) => (producer, sequence),
+                Err(_) => return 0,
+            },
+            None => return 0,
+        };
+
+        let missed = match &self.last {
+            Some((last_producer, last_sequence))
+                if last_producer == producer && sequence > last_sequence + 1 =>
+            {
+                sequence - last_sequence - 1
+            }
+            _ => 0,
+        };
+        self.last = Some((producer.to_string(), sequence));
+        missed
+    }
+}
+
+type StompTransport = Framed<TcpStream, StompCodec>;
+
+// a frame's command, its headers (left out where None) and its body
+type Frame<'a> = (&'a str, Vec<(&'a str, Option<String>)>, Option<Vec<u8>>);
+
+// tokio_stomp's own codec drops extra_headers when it writes a frame, and we need them for the
+// durable subscription, so this writes frames itself and leaves reading them to tokio_stomp.
+struct StompCodec;
+
+impl Decoder for StompCodec {
+    type Item = tokio_stomp::Message<FromServer>;
+    type Error = anyhow::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        ClientCodec.decode(src)
+    }
+}
+
+impl Encoder<tokio_stomp::Message<ToServer>> for StompCodec {
+    type Error = anyhow::Error;
+
+    fn encode(
+        &mut self,
+        item: tokio_stomp::Message<ToServer>,
+        dst: &mut BytesMut,
+    ) -> Result<(), Self::Error> {
+        let (command, headers, body): Frame = match item.content {
+            ToServer::Connect {
+                accept_version,
+                host,
+                login,
+                passcode,
+                heartbeat,
+            } => (
+                "CONNECT",
+                vec![
+                    ("accept-version", Some(accept_version)),
+                    ("host", Some(host)),
+                    ("login", login),
+                    ("passcode", passcode),
+                    ("heart-beat", heartbeat.map(|(x, y)| format!("{},{}", x, y))),
+                ],
+                None,
+            ),
+            ToServer::Disconnect { receipt } => ("DISCONNECT", vec![("receipt", receipt)], None),
+            ToServer::Subscribe {
+                destination,
+                id,
+                ack,
+            } => (
+                "SUBSCRIBE",
+                vec![
+                    ("destination", Some(destination)),
+                    ("id", Some(id)),
+                    (
+                        "ack",
+                        ack.map(|x| {
+                            match x {
+                                AckMode::Auto => "auto",
+                                AckMode::Client => "client",
+                                AckMode::ClientIndividual => "client-individual",
+                            }
+                            .to_string()
+                        }),
+                    ),
+                ],
+                None,
+            ),
+            ToServer::Unsubscribe { id } => ("UNSUBSCRIBE", vec![("id", Some(id))], None),
+            ToServer::Send {
+                destination,
+                transaction,
+                body,
+            } => (
+                "SEND",
+                vec![
+                    ("destination", Some(destination)),
+                    ("transaction", transaction),
+                ],
+                body,
+            ),
+            ToServer::Ack { id, transaction } => (
+                "ACK",
+                vec![("id", Some(id)), ("transaction", transaction)],
+                None,
+            ),
+            ToServer::Nack { id, transaction } => (
+                "NACK",
+                vec![("id", Some(id)), ("transaction", transaction)],
+                None,
+            ),
+            ToServer::Begin { transaction } => {
+                ("BEGIN", vec![("transaction", Some(transaction))], None)
+            }
+            ToServer::Commit { transaction } => {
+                ("COMMIT", vec![("transaction", Some(transaction))], None)
+            }
+            ToServer::Abort { transaction } => {
+                ("ABORT", vec![("transaction", Some(transaction))], None)
+            }
+        };
+
+        dst.put_slice(command.as_bytes());
+        dst.put_u8(b'\n');
+        // CONNECT headers aren't escaped, everything else's are
+        let escape = |x: &[u8]| -> Vec<u8> {
+            match command {
+                "CONNECT" => x.to_vec(),
+                _ => x.iter().fold(vec![], |mut acc, c| {
+                    match c {
+                        b'\\' => acc.extend_from_slice(b"\\\\"),
+                        b'\n' => acc.extend_from_slice(b"\\n"),
+                        b'\r' => acc.extend_from_slice(b"\\r"),
+                        b':' => acc.extend_from_slice(b"\\c"),
+                        x => acc.push(*x),
+                    }
+                    acc
+                }),
+            }
+        };
+        let headers = headers
+            .into_iter()
+            .filter_map(|(k, v)| v.map(|v| (k.as_bytes().to_vec(), v.into_bytes())))
+            .chain(item.extra_headers);
+        for (k, v) in headers {
+            dst.put_slice(&escape(&k));
+            dst.put_u8(b':');
+            dst.put_slice(&escape(&v));
+            dst.put_u8(b'\n');
+        }
+        match &body {
+            Some(x) => dst.put_slice(format!("content-length:{}\n", x.len()).as_bytes()),
+            None => (),
+        }
+        dst.put_u8(b'\n');
+        match body {
+            Some(x) => dst.put_slice(&x),
+            None => (),
+        }
+        dst.put_u8(0);
+        Ok(())
+    }
 }
 
-impl fmt::Display for NrVstpError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Error reading from VSTP STOMP stream: {}", self.what)
+fn unescape(x: &[u8]) -> String {
+    let mut result = vec![];
+    let mut iter = x.iter();
+    while let Some(c) = iter.next() {
+        match (c, iter.clone().next()) {
+            (b'\\', Some(b'\\')) => result.push(b'\\'),
+            (b'\\', Some(b'n')) => result.push(b'\n'),
+            (b'\\', Some(b'r')) => result.push(b'\r'),
+            (b'\\', Some(b'c')) => result.push(b':'),
+            (c, _) => {
+                result.push(*c);
+                continue;
+            }
+        }
+        iter.next();
     }
+    String::from_utf8_lossy(&result).to_string()
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Error reading from VSTP STOMP stream: {what}")]
+pub struct NrVstpError {
+    what: String,
 }
 
 async fn keep_alive(
-    mut sink: SplitSink<ClientTransport, tokio_stomp::Message<ToServer>>,
+    mut sink: SplitSink<StompTransport, tokio_stomp::Message<ToServer>>,
+    mut outgoing: UnboundedReceiver<tokio_stomp::Message<ToServer>>,
 ) -> Result<(), Error> {
     // horrible hacky workaround for tokio_stomp's lack of heartbeat support. I'm truly sorry.
+    // We ask for a receipt each time, so receive() can tell whether the connection is still alive.
+    let mut interval = tokio::time::interval(Duration::from_secs(15));
+    let mut in_transaction = false;
     loop {
-        tokio::time::sleep(Duration::from_secs(15)).await;
-        sink.send(
-            ToServer::Begin {
-                transaction: "foo".to_string(),
+        tokio::select! {
+            _ = interval.tick() => {
+                let mut msg: tokio_stomp::Message<ToServer> = if in_transaction {
+                    ToServer::Abort {
+                        transaction: "foo".to_string(),
+                    }
+                    .into()
+                } else {
+                    ToServer::Begin {
+                        transaction: "foo".to_string(),
+                    }
+                    .into()
+                };
+                msg.extra_headers
+                    .push((b"receipt".to_vec(), b"keepalive".to_vec()));
+                sink.send(msg).await?;
+                in_transaction = !in_transaction;
             }
-            .into(),
-        )
-        .await?;
-        tokio::time::sleep(Duration::from_secs(15)).await;
-        sink.send(
-            ToServer::Abort {
-                transaction: "foo".to_string(),
-            }
-            .into(),
-        )
-        .await?;
+            msg = outgoing.recv() => match msg {
+                Some(x) => sink.send(x).await?,
+                None => return Ok(()),
+            },
+        }
     }
 }
 
 #[async_trait]
 impl Subscriber for NrVstpSubscriber {
     async fn subscribe(&mut self) -> Result<(), Error> {
-        println!("Subscribing to VSTP data from Network Rail");
-        let (mut sink, stream) = client::connect(
-            "publicdatafeeds.networkrail.co.uk:61618",
-            "/".to_string(),
-            Some(self.config.username.clone()),
-            Some(self.config.password.clone()),
-        )
-        .await?
-        .split();
-        self.stream = Some(stream);
-
-        sink.send(client::subscribe("/topic/VSTP_ALL", "1")).await?;
-
-        self.keepalive = Some(tokio::spawn(async move {
-            return keep_alive(sink).await;
-        }));
-
-        Ok(())
+        println!("Subscribing to {} data from Network Rail", self.topic);
+        self.connect().await
     }
 
     async fn receive(&mut self) -> Result<Vec<u8>, Error> {
-        let msg = match &mut self.stream {
-            Some(x) => x.next().await.transpose()?,
-            None => {
-                return Err(Error::NrVstpError(NrVstpError {
-                    what: "Subscribe not yet called".to_string(),
-                }))
-            }
-        };
-        println!("Received VSTP data from Network Rail");
-        let msg = match msg {
-            Some(x) => x,
-            None => {
-                return Err(Error::NrVstpError(NrVstpError {
-                    what: "Received empty message".to_string(),
-                }))
+        if self.keepalive.is_none() {
+            return Err(Error::NrVstpError(NrVstpError {
+                what: "Subscribe not yet called".to_string(),
+            }));
+        }
+
+        let heartbeat_timeout =
+            Duration::from_secs(self.config.heartbeat_timeout_secs.unwrap_or(60));
+
+        loop {
+            let keepalive_dead = match &self.keepalive {
+                Some(x) => x.is_finished(),
+                None => true,
+            };
+            if self.stream.is_none() || keepalive_dead {
+                self.connected(false);
+                self.reconnect().await;
             }
-        };
 
-        match msg.content {
-            FromServer::Message { body, .. } => Ok(match body {
-                Some(x) => x,
-                None => {
+            let msg =
+                match tokio::time::timeout(heartbeat_timeout, self.stream.as_mut().unwrap().next())
+                    .await
+                {
+                    Ok(Some(Ok(x))) => x,
+                    Ok(Some(Err(x))) => {
+                        println!("Error reading from {} stream: {}", self.topic, x);
+                        self.stream = None;
+                        continue;
+                    }
+                    Ok(None) => {
+                        println!("{} stream closed", self.topic);
+                        self.stream = None;
+                        continue;
+                    }
+                    Err(_) => {
+                        println!(
+                            "Nothing received from {} stream for {} seconds",
+                            self.topic,
+                            heartbeat_timeout.as_secs()
+                        );
+                        self.stream = None;
+                        continue;
+                    }
+                };
+
+            match msg.content {
+                FromServer::Message {
+                    message_id, body, ..
+                } => {
+                    println!("Received {} data from Network Rail", self.topic);
+                    // tokio_stomp doesn't unescape headers, and ActiveMQ escapes the colons in IDs
+                    let message_id = unescape(message_id.as_bytes());
+                    self.check_sequence(&message_id);
+
+                    // STOMP 1.2 wants the ack header echoed back, older versions the message ID.
+                    // tokio_stomp leaves headers it doesn't know about in extra_headers. We don't
+                    // ack until the caller says it's applied, so the broker sends it again if we
+                    // go down first.
+                    self.unacked
+                        .push(match msg.extra_headers.iter().find(|(k, _)| k == b"ack") {
+                            Some((_, v)) => unescape(v),
+                            None => message_id,
+                        });
+
+                    return match body {
+                        Some(x) => Ok(x),
+                        None => Err(Error::NrVstpError(NrVstpError {
+                            what: "No body".to_string(),
+                        })),
+                    };
+                }
+                FromServer::Receipt { .. } => continue, // just our keepalive
+                FromServer::Error { message, .. } => {
+                    println!(
+                        "Error from {} stream: {}",
+                        self.topic,
+                        message.unwrap_or("unknown".to_string())
+                    );
+                    self.stream = None;
+                    continue;
+                }
+                _ => {
                     return Err(Error::NrVstpError(NrVstpError {
-                        what: "No body".to_string(),
+                        what: "Received unknown message".to_string(),
                     }))
                 }
-            }),
-            FromServer::Receipt { .. } => Err(Error::NrVstpError(NrVstpError {
-                what: "Received Receipt".to_string(),
-            })),
-            FromServer::Error { message, .. } => Err(Error::NrVstpError(NrVstpError {
-                what: message.unwrap(),
-            })),
-            _ => Err(Error::NrVstpError(NrVstpError {
-                what: "Received unknown message".to_string(),
-            })),
+            }
+        }
+    }
+
+    async fn ack(&mut self) -> Result<(), Error> {
+        match &self.outgoing {
+            Some(x) => {
+                for id in self.unacked.drain(..) {
+                    // if this fails the connection is on its way down anyway; we'll notice next
+                    // time round, and get the message again once we've reconnected
+                    let _ = x.send(
+                        ToServer::Ack {
+                            id,
+                            transaction: None,
+                        }
+                        .into(),
+                    );
+                }
+            }
+            None => self.unacked.clear(),
         }
+        Ok(())
     }
 }
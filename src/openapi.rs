@@ -0,0 +1,1892 @@
+use serde_json::{json, Map, Value};
+
+// OpenAPI 3 description of the JSON half of the web UI, for people generating clients. There's no
+// derive for this that copes with chrono-tz and friends, so it's written out by hand: if you change
+// one of the response structs in webui.rs, change it here too.
+
+fn string() -> Value {
+    json!({ "type": "string" })
+}
+
+fn nullable_string() -> Value {
+    json!({ "type": "string", "nullable": true })
+}
+
+// calling point times are always full local date-times with the offset, with any day offsets
+// already applied
+fn nullable_datetime() -> Value {
+    json!({ "type": "string", "format": "date-time", "nullable": true })
+}
+
+fn date() -> Value {
+    json!({ "type": "string", "format": "date" })
+}
+
+fn boolean() -> Value {
+    json!({ "type": "boolean" })
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn array_of(items: Value) -> Value {
+    json!({ "type": "array", "items": items })
+}
+
+fn enum_of(values: &[&str]) -> Value {
+    json!({ "type": "string", "enum": values })
+}
+
+fn object(properties: Value) -> Value {
+    json!({ "type": "object", "properties": properties })
+}
+
+fn path_parameter(name: &str, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": string(),
+    })
+}
+
+fn query_parameter(name: &str, description: &str, schema: Value) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "description": description,
+        "schema": schema,
+    })
+}
+
+fn source_filter() -> Value {
+    query_parameter(
+        "source",
+        "Only trains from these, comma-separated: ltp, stp and/or vstp",
+        string(),
+    )
+}
+
+// every read-only endpoint can answer from an older import
+fn schedule_as_of() -> Value {
+    query_parameter(
+        "schedule_as_of",
+        "Answer from the schedules current at this time (RFC 3339, or YYYY-MM-DDTHH:MM in UTC)",
+        json!({ "type": "string", "format": "date-time" }),
+    )
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } },
+    })
+}
+
+fn html_response(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/html": { "schema": string() } },
+    })
+}
+
+fn not_found() -> Value {
+    json!({ "description": "Unknown namespace, train or location" })
+}
+
+// Boards come in every combination of where trains have come from, where they're going and when,
+// so their paths are made here rather than written out twelve times
+fn board_paths() -> Map<String, Value> {
+    let from = path_parameter(
+        "from_id",
+        "Only trains that called here before, e.g. a TIPLOC",
+    );
+    let to = path_parameter("to_id", "Only trains that call here afterwards");
+    let date = path_parameter("date", "YYYY-MM-DD");
+    let vias = [
+        ("", vec![], ""),
+        (
+            "/from/{from_id}",
+            vec![from.clone()],
+            ", coming from somewhere",
+        ),
+        ("/to/{to_id}", vec![to.clone()], ", going somewhere"),
+        (
+            "/from/{from_id}/to/{to_id}",
+            vec![from, to],
+            ", coming from one place and going to another",
+        ),
+    ];
+    let whens = [
+        ("", vec![], "from half an hour ago to two hours ahead"),
+        (
+            "/{date}/{time}",
+            vec![date.clone(), path_parameter("time", "HH:MM, local")],
+            "from half an hour before a time to two hours after",
+        ),
+        (
+            "/{date}/{from_time}/to/{to_time}",
+            vec![
+                date,
+                path_parameter("from_time", "HH:MM, local"),
+                path_parameter("to_time", "HH:MM, local; the next day if before from_time"),
+            ],
+            "between two times",
+        ),
+    ];
+
+    let mut paths = Map::new();
+    for (via, via_parameters, via_summary) in &vias {
+        for (when, when_parameters, when_summary) in &whens {
+            let mut parameters = vec![
+                path_parameter(
+                    "namespace",
+                    "Schedule namespace and ID type, e.g. gbnr-public for CRS codes or gbnr-internal for TIPLOCs",
+                ),
+                path_parameter("location_id", "Location ID or station group code"),
+            ];
+            parameters.extend(via_parameters.iter().cloned());
+            parameters.extend(when_parameters.iter().cloned());
+            parameters.extend([
+                query_parameter(
+                    "mode",
+                    "Only these modes, comma-separated, e.g. bus,ship",
+                    string(),
+                ),
+                query_parameter("empty_stock", "Include empty stock moves", boolean()),
+                query_parameter("freight", "Include freight", boolean()),
+                query_parameter("buses", "Include buses and coaches", boolean()),
+                query_parameter(
+                    "passes",
+                    "Include trains passing without stopping",
+                    boolean(),
+                ),
+                query_parameter(
+                    "runs_as_required",
+                    "Include paths that only run when needed",
+                    boolean(),
+                ),
+                query_parameter("advanced", "Show lines, paths and allowances", boolean()),
+                source_filter(),
+                schedule_as_of(),
+            ]);
+            paths.insert(
+                format!("/location/{{namespace}}/{{location_id}}{}{}", via, when),
+                json!({
+                    "get": {
+                        "summary": format!("Departure board web page{}, {}", via_summary, when_summary),
+                        "parameters": parameters,
+                        "responses": {
+                            "200": html_response("The board"),
+                            "400": { "description": "Unknown mode" },
+                            "404": not_found(),
+                        },
+                    },
+                }),
+            );
+        }
+    }
+    paths
+}
+
+fn schemas() -> Value {
+    let train_source = enum_of(&["LongTerm", "ShortTerm", "VeryShortTerm"]);
+    let train_event = json!({
+        "oneOf": [
+            object(json!({
+                "type": enum_of(&["Retimed"]),
+                "location_ids": array_of(string()),
+                "delay_minutes": { "type": "integer", "description": "Negative if early" },
+            })),
+            object(json!({
+                "type": enum_of(&["PlatformChanged"]),
+                "location_ids": array_of(string()),
+                "platform": string(),
+                "planned_platform": nullable_string(),
+            })),
+            object(json!({
+                "type": enum_of(&["Cancelled"]),
+                "location_ids": array_of(string()),
+                "reason": nullable_string(),
+            })),
+            object(json!({ "type": enum_of(&["Reinstated"]) })),
+        ],
+    });
+    let transport_mode = enum_of(&[
+        "Rail",
+        "Bus",
+        "Ship",
+        "Tram",
+        "LightRail",
+        "Metro",
+        "CableCar",
+        "Funicular",
+        "Trolleybus",
+        "Monorail",
+        "Taxi",
+        "Air",
+    ]);
+    let global_id = json!({
+        "type": "string",
+        "description": "Namespace, train ID and start date, e.g. gbnr:C12345:20240501, unique across namespaces",
+    });
+    let by_operator = json!({
+        "type": "object",
+        "additionalProperties": { "type": "integer" },
+        "description": "Counts by operator ID, or unknown",
+    });
+
+    let reservation_field = enum_of(&[
+        "Possible",
+        "Mandatory",
+        "Recommended",
+        "Impossible",
+        "NotMandatory",
+        "NotApplicable",
+        "Unknown",
+    ]);
+
+    json!({
+        "RunState": object(json!({
+            "source": string(),
+            "paused": boolean(),
+            "missed": { "type": "boolean", "description": "A run came due or was triggered while paused" },
+            "time": { "type": "string", "description": "Daily fetch time, local to the source" },
+            "timezone": string(),
+            "next_run": { "type": "string", "format": "date-time" },
+            "last_run": { "type": "string", "format": "date", "nullable": true },
+        })),
+        "SourceTelemetry": object(json!({
+            "messages": { "type": "integer", "description": "Including quarantined ones" },
+            "unknown_fields": {
+                "type": "object",
+                "description": "Messages with each field we don't know, by path",
+                "additionalProperties": { "type": "integer" },
+            },
+            "quarantined": { "type": "integer", "description": "Messages that couldn't be read, or had unknown fields when strict" },
+            "last_quarantined": { "type": "string", "format": "date-time", "nullable": true },
+            "last_error": nullable_string(),
+            "connected": { "type": "boolean", "nullable": true, "description": "For feeds we hold a connection open to" },
+            "connection_changed": nullable_datetime(),
+        })),
+        "IndexRebuild": object(json!({
+            "namespace": string(),
+            "stale_removed": { "type": "integer", "description": "Entries for trains that no longer call there or have that ID" },
+            "missing_added": { "type": "integer", "description": "Entries an importer should have made but didn't" },
+        })),
+        "DownloadMetrics": object(json!({
+            "source": { "type": "string", "description": "Download name, e.g. toc-full or ieir-gtfs" },
+            "requests": { "type": "integer", "description": "Including retries" },
+            "retries": { "type": "integer" },
+            "failures": { "type": "integer", "description": "Given up on after retrying" },
+            "rate_limited": { "type": "integer", "description": "Responses that were HTTP 429" },
+            "bytes": { "type": "integer", "description": "Of response bodies, as far as they were read" },
+            "last_success": nullable_datetime(),
+            "last_failure": nullable_datetime(),
+            "last_error": nullable_string(),
+            "last_response_ms": { "type": "integer", "nullable": true, "description": "Until the response headers came back" },
+        })),
+        "TrainOperator": object(json!({
+            "id": string(),
+            "description": nullable_string(),
+        })),
+        "Branding": object(json!({
+            "name": nullable_string(),
+            "short_name": nullable_string(),
+            "colour": { "type": "string", "nullable": true, "description": "A CSS colour, e.g. #0a493e" },
+            "text_colour": nullable_string(),
+            "logo_url": nullable_string(),
+        })),
+        "OperatorInfo": {
+            "allOf": [
+                reference("Branding"),
+                object(json!({
+                    "namespace": string(),
+                    "id": { "type": "string", "description": "Operator ID, e.g. an ATOC code, or brand name" },
+                    "is_brand": boolean(),
+                })),
+            ],
+        },
+        "TrainRealtime": object(json!({
+            "realtime_id": string(),
+            "activated": nullable_datetime(),
+            "cancellation": {
+                "type": "object",
+                "nullable": true,
+                "properties": {
+                    "location_ids": array_of(string()),
+                    "reason_code": nullable_string(),
+                    "reason": nullable_string(),
+                    "cancellation_type": nullable_string(),
+                    "timestamp": { "type": "string", "format": "date-time" },
+                },
+            },
+            "events": array_of(json!({ "type": "object" })),
+            "terminated": boolean(),
+        })),
+        "ResolvedServiceLocation": object(json!({
+            "id": string(),
+            "id_suffix": nullable_string(),
+            "name": nullable_string(),
+            "public_id": nullable_string(),
+            "mode": transport_mode,
+            "working_arr": nullable_datetime(),
+            "working_dep": nullable_datetime(),
+            "working_pass": nullable_datetime(),
+            "public_arr": nullable_datetime(),
+            "public_dep": nullable_datetime(),
+            "dwell_secs": { "type": "integer", "nullable": true },
+            "platform": { "type": "string", "nullable": true, "description": "As timetabled" },
+            "live_platform": { "type": "string", "nullable": true, "description": "As reported by the train, where that's been sent" },
+            "line": nullable_string(),
+            "path": nullable_string(),
+            "engineering_allowance_s": { "type": "integer", "nullable": true },
+            "pathing_allowance_s": { "type": "integer", "nullable": true },
+            "performance_allowance_s": { "type": "integer", "nullable": true },
+            "activities": { "type": "object", "additionalProperties": boolean() },
+            "activity_notes": array_of(reference("ActivityNote")),
+            "flags": reference("PassengerFlags"),
+            "facilities": reference("Facilities"),
+            "segment": { "type": "integer", "description": "Index into segments of what applies here" },
+            "expected": {
+                "allOf": [reference("ExpectedTimes")],
+                "nullable": true,
+                "description": "From realtime reports, where there are any",
+            },
+        })),
+        "ExpectedTimes": object(json!({
+            "arrival": { "allOf": [reference("ExpectedTime")], "nullable": true },
+            "departure": { "allOf": [reference("ExpectedTime")], "nullable": true },
+            "pass": { "allOf": [reference("ExpectedTime")], "nullable": true },
+            "delay_minutes": { "type": "integer", "nullable": true, "description": "As it leaves, where it stops; negative if early" },
+        })),
+        "ExpectedTime": object(json!({
+            "time": { "type": "string", "format": "date-time" },
+            // projected ones carry the last reported delay forward, less any allowances on the way
+            "source": enum_of(&["Reported", "Projected"]),
+        })),
+        "PassengerFlags": object(json!({
+            "public": { "type": "boolean", "description": "Passengers can get on or off here; false for operational stops and passing points" },
+            "request_stop": boolean(),
+            "request_by_telephone": boolean(),
+            "pick_up_only": boolean(),
+            "set_down_only": boolean(),
+        })),
+        "ResolvedService": object(json!({
+            "global_id": global_id,
+            "namespace": string(),
+            "id": string(),
+            "date": date(),
+            "departure_date": {
+                "type": "string",
+                "format": "date",
+                "description": "When it leaves its origin, which is the day after date for a service timetabled past midnight",
+            },
+            "source": { "allOf": [train_source], "nullable": true },
+            "modified": boolean(),
+            "cancelled": boolean(),
+            "cancellation_reason": nullable_string(),
+            "runs_as_required": { "type": "boolean", "description": "A Q path, which only runs when it's needed" },
+            "runs_to_locations_as_required": { "type": "boolean", "description": "Only goes as far as it's needed, e.g. into a yard, as of the origin" },
+            "branding": { "allOf": [reference("Branding")], "nullable": true },
+            "running_days": {
+                "type": "string",
+                "nullable": true,
+                "description": "When the train runs, e.g. Mondays to Fridays until 12 December, not 25 November",
+            },
+            "mode": transport_mode,
+            "variable_train": {
+                "allOf": [reference("VariableTrain")],
+                "description": "Everything about the train that can change en route",
+            },
+            "facilities": reference("Facilities"),
+            "segments": {
+                "type": "array",
+                "items": reference("RouteSegment"),
+                "description": "The route split up wherever the train's details change en route; just one if they never do",
+            },
+            "route": array_of(reference("ResolvedServiceLocation")),
+            "realtime": { "allOf": [reference("TrainRealtime")], "nullable": true },
+            "duplicates": array_of(reference("TrainRef")),
+            "duplicate_of": { "allOf": [reference("TrainRef")], "nullable": true },
+            "through": { "allOf": [reference("ThroughService")], "nullable": true },
+            "alerts": array_of(reference("Alert")),
+        })),
+        "ActivityNote": object(json!({
+            "activity": { "type": "string", "description": "Which of activities this is" },
+            "category": enum_of(&["Passenger", "Operational", "DataOnly"]),
+            "note": { "type": "string", "description": "What it means, e.g. \"Sets down passengers only\"" },
+        })),
+        "RouteSegment": object(json!({
+            "from": { "type": "string", "description": "Location ID where these details start applying" },
+            "from_suffix": nullable_string(),
+            "to": { "type": "string", "description": "Location ID of the next change, or the destination" },
+            "to_suffix": nullable_string(),
+            "operator_changed": { "type": "boolean", "description": "From the segment before; never for the first" },
+            "changed": {
+                "type": "array",
+                "items": string(),
+                "description": "Names of the fields in variable_train that differ from the segment before",
+            },
+            "variable_train": {
+                "allOf": [reference("VariableTrain")],
+                "description": "As in ResolvedService, for this segment",
+            },
+        })),
+        "VariableTrain": object(json!({
+            "train_type": string(),
+            "public_id": nullable_string(),
+            "headcode": nullable_string(),
+            "service_group": nullable_string(),
+            "power_type": nullable_string(),
+            "timing_allocation": { "allOf": [reference("TrainAllocation")], "nullable": true },
+            "actual_allocation": { "allOf": [reference("TrainAllocation")], "nullable": true },
+            "timing_speed_m_per_s": { "type": "number", "nullable": true },
+            "operating_characteristics": {
+                "type": "object",
+                "nullable": true,
+                "additionalProperties": boolean(),
+            },
+            "has_first_class_seats": { "type": "boolean", "nullable": true },
+            "has_second_class_seats": { "type": "boolean", "nullable": true },
+            "has_first_class_sleepers": { "type": "boolean", "nullable": true },
+            "has_second_class_sleepers": { "type": "boolean", "nullable": true },
+            "carries_vehicles": { "type": "boolean", "nullable": true },
+            "reservations": {
+                "type": "object",
+                "additionalProperties": reservation_field,
+            },
+            "catering": {
+                "type": "object",
+                "nullable": true,
+                "additionalProperties": boolean(),
+            },
+            "brand": nullable_string(),
+            "name": nullable_string(),
+            "uic_code": nullable_string(),
+            "operator": { "allOf": [reference("TrainOperator")], "nullable": true },
+            "wheelchair_accessible": { "type": "boolean", "nullable": true },
+            "bicycles_allowed": { "type": "boolean", "nullable": true },
+        })),
+        "TrainAllocation": object(json!({
+            "id": { "type": "string", "description": "As the feed gives it, e.g. a UK timing load such as \"EMU350\"" },
+            "description": string(),
+            "vehicles": {
+                "type": "array",
+                "items": reference("TrainVehicle"),
+                "nullable": true,
+                "description": "Every class the allocation could be, where they're known; for a freight consist, each wagon type in it",
+            },
+        })),
+        "TrainVehicle": object(json!({
+            "id": { "type": "string", "description": "The class, e.g. \"800\" or \"165/1\"" },
+            "description": string(),
+            "cars": { "type": "array", "items": { "type": "integer" }, "description": "The formations units come in; for wagons, how many there are" },
+            "car_length_m": { "type": "number", "nullable": true },
+            "max_speed_mph": { "type": "integer", "nullable": true },
+            "accessibility": object(json!({
+                "wheelchair_spaces": { "type": "boolean", "nullable": true },
+                "accessible_toilet": { "type": "boolean", "nullable": true },
+                "passenger_information": { "type": "boolean", "nullable": true },
+            })),
+        })),
+        "ThroughService": object(json!({
+            "legs": array_of(reference("TrainRef")),
+            "calls": array_of(object(json!({
+                "namespace": string(),
+                "location_id": string(),
+                "name": string(),
+                "time": { "type": "string", "format": "date-time" },
+            }))),
+        })),
+        "Facilities": object(json!({
+            "first_class": { "type": "boolean", "nullable": true },
+            "sleepers": { "type": "boolean", "nullable": true },
+            "catering": {
+                "type": "object",
+                "nullable": true,
+                "additionalProperties": boolean(),
+            },
+            "reservations": {
+                "type": "object",
+                "additionalProperties": reservation_field,
+            },
+            "wheelchair_accessible": { "type": "boolean", "nullable": true },
+            "bicycles_allowed": { "type": "boolean", "nullable": true },
+            "descriptions": array_of(string()),
+        })),
+        "TrainCalendar": object(json!({
+            "namespace": string(),
+            "id": string(),
+            "variants": array_of(object(json!({
+                "base": { "type": "integer", "description": "Which of the train's base schedules" },
+                "overlay": { "type": "integer", "nullable": true, "description": "Which of that one's replacements, if one applies" },
+                "source": { "allOf": [train_source], "nullable": true },
+                "public_id": nullable_string(),
+                "runs_as_required": boolean(),
+            }))),
+            "days": array_of(object(json!({
+                "date": date(),
+                "variant": { "type": "integer", "nullable": true, "description": "Index into variants, or null if the train doesn't run" },
+                "cancelled": boolean(),
+                "cancellation_source": { "allOf": [train_source], "nullable": true },
+                "cancellation_reason": nullable_string(),
+            }))),
+        })),
+        "TrainHistory": object(json!({
+            "global_id": global_id,
+            "namespace": string(),
+            "id": string(),
+            "date": date(),
+            "changes": array_of(object(json!({
+                "time": { "type": "string", "format": "date-time", "description": "When it was applied" },
+                "first_date": date(),
+                "last_date": date(),
+                "change": object(json!({
+                    "type": enum_of(&["VstpCreated", "VstpReplaced", "VstpCancelled", "VstpDeleted", "VstpUpdated", "Realtime"]),
+                    "event": train_event.clone(),
+                })),
+            }))),
+        })),
+        "UicTrain": object(json!({
+            "global_id": global_id,
+            "namespace": string(),
+            "id": string(),
+            "date": date(),
+            "public_id": nullable_string(),
+            "operator": { "allOf": [reference("TrainOperator")], "nullable": true },
+            "origin": nullable_string(),
+            "destination": nullable_string(),
+            "departure": nullable_datetime(),
+            "source": { "allOf": [train_source], "nullable": true },
+            "modified": boolean(),
+            "cancelled": boolean(),
+        })),
+        "NextTrain": object(json!({
+            "global_id": global_id,
+            "namespace": string(),
+            "id": string(),
+            "date": { "type": "string", "format": "date", "description": "Date the train starts" },
+            "public_id": nullable_string(),
+            "operator": { "allOf": [reference("TrainOperator")], "nullable": true },
+            "from_id": string(),
+            "to_id": string(),
+            "departure": { "type": "string", "format": "date-time" },
+            "arrival": { "type": "string", "format": "date-time" },
+            "platform": nullable_string(),
+            "departure_flags": reference("PassengerFlags"),
+            "arrival_flags": reference("PassengerFlags"),
+            "source": { "allOf": [train_source], "nullable": true },
+            "modified": boolean(),
+            "cancelled": boolean(),
+            "runs_as_required": boolean(),
+            "runs_to_locations_as_required": boolean(),
+            "branding": { "allOf": [reference("Branding")], "nullable": true },
+        })),
+        "RegionDeparture": object(json!({
+            "global_id": global_id,
+            "namespace": string(),
+            "id": string(),
+            "date": { "type": "string", "format": "date", "description": "Date the train starts" },
+            "public_id": nullable_string(),
+            "operator": { "allOf": [reference("TrainOperator")], "nullable": true },
+            "location_id": { "type": "string", "description": "Where it departs from" },
+            "destination_id": string(),
+            "departure": { "type": "string", "format": "date-time" },
+            "platform": nullable_string(),
+            "source": { "allOf": [train_source], "nullable": true },
+            "modified": boolean(),
+            "cancelled": boolean(),
+            "runs_as_required": boolean(),
+            "branding": { "allOf": [reference("Branding")], "nullable": true },
+        })),
+        "LivePositionProperties": object(json!({
+            "global_id": global_id,
+            "namespace": string(),
+            "train_id": string(),
+            "public_id": nullable_string(),
+            "from": { "type": "string", "description": "Location it's at or last left" },
+            "to": { "type": "string", "nullable": true, "description": "Next location, or null if it's standing at from" },
+            "delay_minutes": { "type": "integer", "description": "As of its last report; negative if early" },
+            "last_report": { "type": "string", "format": "date-time" },
+        })),
+        "TrainRef": object(json!({
+            "namespace": string(),
+            "id": string(),
+        })),
+        "FreightTrainLocation": object(json!({
+            "id": string(),
+            "id_suffix": nullable_string(),
+            "name": nullable_string(),
+            "working_arr": nullable_datetime(),
+            "working_dep": nullable_datetime(),
+            "working_pass": nullable_datetime(),
+            "public_arr": nullable_datetime(),
+            "public_dep": nullable_datetime(),
+            "platform": nullable_string(),
+            "line": nullable_string(),
+            "path": nullable_string(),
+        })),
+        "FreightTrain": object(json!({
+            "global_id": global_id,
+            "namespace": string(),
+            "id": string(),
+            "date": date(),
+            "headcode": nullable_string(),
+            "train_type": string(),
+            "service_group": nullable_string(),
+            "operator": { "allOf": [reference("TrainOperator")], "nullable": true },
+            "power_type": nullable_string(),
+            "timing_speed_m_per_s": { "type": "number", "nullable": true },
+            "operating_characteristics": { "type": "object", "nullable": true },
+            "consist": {
+                "allOf": [reference("TrainAllocation")],
+                "nullable": true,
+                "description": "What's making up the train on the day, with a vehicle for each wagon type, where it's known",
+            },
+            "runs_as_required": boolean(),
+            "source": { "allOf": [train_source], "nullable": true },
+            "modified": boolean(),
+            "cancelled": boolean(),
+            "route": array_of(reference("FreightTrainLocation")),
+        })),
+        "LocationSearchResult": object(json!({
+            "namespace": string(),
+            "id": string(),
+            "name": string(),
+            "public_id": nullable_string(),
+            "country": { "type": "string", "nullable": true, "description": "ISO 3166-1 alpha-2, e.g. GB" },
+            "region": { "type": "string", "nullable": true, "description": "e.g. Wales" },
+            "score": { "type": "number" },
+        })),
+        "FixedLink": object(json!({
+            "from": object(json!({ "namespace": string(), "id": string() })),
+            "to": object(json!({ "namespace": string(), "id": string() })),
+            "mode": string(),
+            "duration_secs": { "type": "integer" },
+        })),
+        "LocationInterchange": object(json!({
+            "namespace": string(),
+            "location_id": string(),
+            "minimum_connection_secs": { "type": "integer" },
+            "links": array_of(reference("FixedLink")),
+        })),
+        "PlatformOccupation": object(json!({
+            "train_id": string(),
+            "public_id": nullable_string(),
+            "date": date(),
+            "location_id": string(),
+            "id_suffix": nullable_string(),
+            "platform": string(),
+            "platform_zone": nullable_string(),
+            "from": { "type": "string", "format": "date-time" },
+            "to": { "type": "string", "format": "date-time" },
+        })),
+        "PlatformConflict": object(json!({
+            "platform": string(),
+            "first": { "type": "integer", "description": "Index into occupations" },
+            "second": { "type": "integer", "description": "Index into occupations" },
+            "overlap_secs": { "type": "integer" },
+        })),
+        "OccupancyReport": object(json!({
+            "namespace": string(),
+            "location_ids": array_of(string()),
+            "date": date(),
+            "turnback_margin_secs": { "type": "integer" },
+            "occupations": array_of(reference("PlatformOccupation")),
+            "conflicts": array_of(reference("PlatformConflict")),
+        })),
+        "DailyFlow": object(json!({
+            "date": date(),
+            "total": { "type": "integer" },
+            "by_operator": by_operator,
+        })),
+        "HourlyFlow": object(json!({
+            "hour": { "type": "integer", "description": "Local hour of departure from the origin" },
+            "total": { "type": "integer" },
+            "by_operator": by_operator,
+        })),
+        "FlowReport": object(json!({
+            "namespace": string(),
+            "from_location_ids": array_of(string()),
+            "to_location_ids": array_of(string()),
+            "from": date(),
+            "to": date(),
+            "total": { "type": "integer" },
+            "by_operator": by_operator,
+            "by_date": array_of(reference("DailyFlow")),
+            "by_hour": array_of(reference("HourlyFlow")),
+        })),
+        "DwellCall": object(json!({
+            "global_id": global_id,
+            "train_id": string(),
+            "public_id": nullable_string(),
+            "date": date(),
+            "location_id": string(),
+            "arrival": { "type": "string", "format": "date-time" },
+            "departure": { "type": "string", "format": "date-time" },
+            "dwell_secs": { "type": "integer" },
+        })),
+        "Turnaround": object(json!({
+            "global_id": global_id,
+            "train_id": string(),
+            "date": date(),
+            "forms_global_id": global_id,
+            "forms_train_id": string(),
+            "forms_date": date(),
+            "location_id": string(),
+            "arrival": { "type": "string", "format": "date-time" },
+            "departure": { "type": "string", "format": "date-time" },
+            "turnaround_secs": { "type": "integer" },
+        })),
+        "DwellDistribution": object(json!({
+            "count": { "type": "integer" },
+            "min_secs": { "type": "integer", "nullable": true },
+            "median_secs": { "type": "integer", "nullable": true },
+            "mean_secs": { "type": "number", "nullable": true },
+            "max_secs": { "type": "integer", "nullable": true },
+            "buckets": array_of(object(json!({
+                "from_secs": { "type": "integer" },
+                "count": { "type": "integer" },
+            }))),
+        })),
+        "DwellReport": object(json!({
+            "namespace": string(),
+            "location_ids": array_of(string()),
+            "from": date(),
+            "to": date(),
+            "bucket_secs": { "type": "integer" },
+            "calls": array_of(reference("DwellCall")),
+            "turnarounds": array_of(reference("Turnaround")),
+            "dwell": reference("DwellDistribution"),
+            "turnaround": reference("DwellDistribution"),
+        })),
+        "Movement": object(json!({
+            "global_id": global_id,
+            "train_id": string(),
+            "headcode": nullable_string(),
+            "date": date(),
+            "location_id": string(),
+            "location_suffix": nullable_string(),
+            "movement": enum_of(&["Starts", "Stops", "Passes", "Terminates"]),
+            "working_time": { "type": "string", "format": "date-time" },
+            "working_arr": nullable_datetime(),
+            "working_dep": nullable_datetime(),
+            "working_pass": nullable_datetime(),
+            "public_arr": nullable_datetime(),
+            "public_dep": nullable_datetime(),
+            "platform": nullable_string(),
+            "line": nullable_string(),
+            "path": nullable_string(),
+            "engineering_allowance_s": { "type": "integer", "nullable": true },
+            "pathing_allowance_s": { "type": "integer", "nullable": true },
+            "performance_allowance_s": { "type": "integer", "nullable": true },
+            "activities": { "type": "object", "additionalProperties": boolean() },
+            "activity_notes": array_of(reference("ActivityNote")),
+            "origin": string(),
+            "destination": string(),
+            "train_type": string(),
+            "operator": { "allOf": [reference("TrainOperator")], "nullable": true },
+            "power_type": nullable_string(),
+            "timing_speed_m_per_s": { "type": "number", "nullable": true },
+            "operating_characteristics": { "type": "object", "nullable": true },
+            "runs_as_required": boolean(),
+            "source": { "allOf": [train_source], "nullable": true },
+            "modified": boolean(),
+            "cancelled": boolean(),
+        })),
+        "MovementReport": object(json!({
+            "namespace": string(),
+            "location_ids": array_of(string()),
+            "from": string(),
+            "to": string(),
+            "movements": array_of(reference("Movement")),
+        })),
+        "ValidationReport": object(json!({
+            "lines": { "type": "integer" },
+            "records": { "type": "object", "additionalProperties": { "type": "integer" } },
+            "trains": { "type": "integer" },
+            "locations": { "type": "integer" },
+            "errors": object(json!({
+                "errors": array_of(string()),
+                "error_count": { "type": "integer" },
+                "warnings": array_of(string()),
+                "warning_count": { "type": "integer" },
+            })),
+            "unknown_operators": array_of(string()),
+            "unknown_locations": array_of(string()),
+            "invariant_violations": array_of(object(json!({
+                "train_id": string(),
+                "problem": string(),
+            }))),
+        })),
+        "NewAlert": object(json!({
+            "message": string(),
+            "severity": enum_of(&["Info", "Warning"]),
+            "starts": { "type": "string", "format": "date-time", "nullable": true, "description": "Straight away if not given" },
+            "expires": { "type": "string", "format": "date-time", "nullable": true, "description": "Never if not given" },
+            "namespace": nullable_string(),
+            "operator_id": nullable_string(),
+            "location_id": { "type": "string", "nullable": true, "description": "Location ID, e.g. a TIPLOC, not a public ID" },
+        })),
+        "Alert": {
+            "allOf": [
+                reference("NewAlert"),
+                object(json!({
+                    "id": string(),
+                    "created": { "type": "string", "format": "date-time" },
+                })),
+            ],
+        },
+        "SubscriptionRequest": object(json!({
+            "interest": {
+                "oneOf": [
+                    object(json!({
+                        "type": enum_of(&["Train"]),
+                        "namespace": string(),
+                        "train_id": string(),
+                        "date": date(),
+                    })),
+                    object(json!({
+                        "type": enum_of(&["Location"]),
+                        "namespace": string(),
+                        "location_id": string(),
+                        "from": { "type": "string", "format": "date-time" },
+                        "to": { "type": "string", "format": "date-time" },
+                    })),
+                ],
+            },
+            "webhook": { "type": "string", "nullable": true, "description": "Only to hosts the deployment allows, and never to internal addresses" },
+        })),
+        "Notification": object(json!({
+            "seq": { "type": "integer" },
+            "timestamp": { "type": "string", "format": "date-time" },
+            "global_id": global_id,
+            "namespace": string(),
+            "train_id": string(),
+            "date": date(),
+            "event": train_event,
+        })),
+        "QueryCacheStats": object(json!({
+            "hits": { "type": "integer" },
+            "misses": { "type": "integer" },
+            "hit_rate": { "type": "number", "nullable": true },
+            "entries": { "type": "integer" },
+            "generation": { "type": "integer" },
+            "ttl_secs": { "type": "integer" },
+        })),
+        "PendingOverlay": object(json!({
+            "train_id": string(),
+            "valid_begin": { "type": "string", "format": "date" },
+            "valid_end": { "type": "string", "format": "date" },
+            "standalone": { "type": "boolean", "description": "Running as a train of its own meanwhile, as there's nothing else under its ID" },
+        })),
+        "QualityReport": object(json!({
+            "namespace": string(),
+            "computed": { "type": "string", "format": "date-time" },
+            "last_imported": nullable_datetime(),
+            "schedules": { "type": "integer", "description": "Every version of every train, overlays included" },
+            "passenger_schedules": { "type": "integer" },
+            "with_public_times": { "type": "integer", "description": "Of the passenger ones" },
+            "with_operator": { "type": "integer", "description": "With a name for the operator, not only an ID" },
+            "associations": { "type": "integer" },
+            "valid_associations": { "type": "integer", "description": "With another train that's there and calls at the location" },
+            "pending_associations": { "type": "integer", "description": "Still waiting for the other train to turn up" },
+            "overlays": { "type": "integer" },
+            "orphaned_overlays": { "type": "integer", "description": "Never apply, as what they replace doesn't run on any of their days" },
+            "pending_overlays": { "type": "integer", "description": "Still waiting for what they replace to turn up" },
+            "public_times_pct": { "type": "number", "nullable": true },
+            "operator_pct": { "type": "number", "nullable": true },
+            "valid_associations_pct": { "type": "number", "nullable": true },
+            "orphaned_overlays_pct": { "type": "number", "nullable": true },
+            "score": { "type": "number", "nullable": true, "description": "0-100: the mean of the percentages, orphaned overlays counting against" },
+        })),
+        "ScheduleStats": object(json!({
+            "description": string(),
+            "trains": { "type": "integer" },
+            "schedules": { "type": "integer" },
+            "cancellations": { "type": "integer" },
+            "by_source": { "type": "object", "additionalProperties": { "type": "integer" } },
+            "by_operator": { "type": "object", "additionalProperties": { "type": "integer" } },
+            "by_type": { "type": "object", "additionalProperties": { "type": "integer" } },
+            "locations": { "type": "integer" },
+            "associations": { "type": "integer" },
+            "restrictions": { "type": "integer" },
+            "valid_begin": nullable_datetime(),
+            "valid_end": nullable_datetime(),
+            "last_updated": nullable_datetime(),
+            "last_imported": nullable_datetime(),
+            "locations_by_country": { "type": "object", "additionalProperties": { "type": "integer" } },
+            "trains_by_country": {
+                "type": "object",
+                "additionalProperties": { "type": "integer" },
+                "description": "Distinct train IDs calling anywhere in each country",
+            },
+        })),
+        "IntegrityReport": object(json!({
+            "namespace": string(),
+            "checked": nullable_datetime(),
+            "last_imported": { "type": "string", "format": "date-time", "nullable": true, "description": "Of what was checked" },
+            "missing_index_entries": { "type": "integer", "description": "A train or location not indexed under something it has" },
+            "dangling_index_entries": { "type": "integer", "description": "Index entries for trains or locations that aren't there" },
+            "short_routes": { "type": "integer", "description": "Schedules with fewer than two locations" },
+            "missing_association_targets": { "type": "integer", "description": "Associations with a train that isn't there" },
+            "violations": { "type": "integer", "description": "All of the above" },
+            "examples": array_of(string()),
+        })),
+        "SourceStatus": object(json!({
+            "source": string(),
+            "last_updated": nullable_datetime(),
+            "age_secs": { "type": "integer", "description": "Since startup, if never updated" },
+            "max_age_secs": { "type": "integer", "nullable": true },
+            "stale": boolean(),
+        })),
+        "Readiness": object(json!({
+            "ready": boolean(),
+            "imported": {
+                "type": "object",
+                "additionalProperties": nullable_datetime(),
+                "description": "Namespaces loaded, and when each was last written to",
+            },
+            "sources": array_of(reference("SourceReadiness")),
+        })),
+        "SourceReadiness": object(json!({
+            "source": string(),
+            "last_updated": nullable_datetime(),
+            "age_secs": { "type": "integer", "description": "Since startup, if never updated" },
+            "max_age_secs": { "type": "integer", "nullable": true },
+            "stale": boolean(),
+            "connected": { "type": "boolean", "nullable": true, "description": "For feeds we hold a connection open to, e.g. VSTP" },
+            "ready": { "type": "boolean", "description": "Connected, not yet stale, or not a feed we hold a connection to" },
+        })),
+    })
+}
+
+pub fn openapi_document() -> Value {
+    let mut document = json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "World Rail Timetables",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Every JSON response takes units (m/s, km/h or mph, renaming speed fields to match), time_format (rfc3339, 24h or 12h) and seconds (true or false) as query parameters, or as parameters of the Accept header, e.g. application/json; units=mph. They also take fields, comma separated, to keep only those fields or, with a - in front, to leave them out, going further in with dots, e.g. fields=id,route.id or fields=-route; in a list, that's each item's fields. Lists can be paged through with limit, giving a Link header with rel=\"next\" and a cursor if there's more; so can the main list in some reports, e.g. a movement report's movements. A cursor only lasts as long as the schedules it came from: once they've changed, it's refused with a 400, and paging starts again from the first page. Endpoints with a limit of their own, e.g. /locations/search, aren't paged. Depending on the deployment, working times, allowances, freight and staff trains may be left out unless an operational token is sent as a bearer token; in public mode, so are trains passengers can't travel on, calls they can't use and operational activities. Dates identifying a train, in paths, global IDs and date fields, are its operating date: the day its timetable is for. That's usually the day it leaves its origin, but some operators timetable trains just after midnight as part of the day before, e.g. a Saturday-night 00:30 given as Saturday at 24:30, and those keep the earlier date; departure_date on a service gives the calendar date. Where a deployment runs other environments alongside the main one, e.g. a trial feed, any path answers from one of them with an X-Environment header naming it, or under /env/<name>, e.g. /env/trial/stats. Complete responses come with an ETag, so sending it back as If-None-Match gets a 304 Not Modified if nothing has changed; depending on the deployment, they may also say how long they can be cached for, and let browsers call from other origins.",
+        },
+        "paths": {
+            "/": {
+                "get": {
+                    "summary": "Web page listing the namespaces loaded",
+                    "responses": {
+                        "200": html_response("The index"),
+                    },
+                },
+            },
+            "/train/{namespace}/{train_id}/{date}": {
+                "get": {
+                    "summary": "Web page for a train as it runs on one date",
+                    "parameters": [
+                        path_parameter("namespace", "Schedule namespace, e.g. gbnr"),
+                        path_parameter("train_id", "Train ID, e.g. a CIF UID"),
+                        path_parameter("date", "Date the train starts, YYYY-MM-DD"),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": html_response("The train"),
+                        "404": not_found(),
+                    },
+                },
+            },
+            "/service/{train_id}/{date}": {
+                "get": {
+                    "summary": "A train as it runs on one date, with all overlays applied",
+                    "parameters": [
+                        path_parameter("train_id", "Train ID, e.g. a CIF UID"),
+                        path_parameter("date", "Date the train starts, YYYY-MM-DD"),
+                        query_parameter(
+                            "all_locations",
+                            "Include passing points and operational stops, which are left out by default",
+                            boolean(),
+                        ),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("The service", reference("ResolvedService")),
+                        "404": not_found(),
+                    },
+                },
+            },
+            "/service/{global_id}": {
+                "get": {
+                    "summary": "The same, by an ID as given in global_id, so from the namespace it came from",
+                    "parameters": [
+                        path_parameter("global_id", "Global train ID, e.g. gbnr:C12345:20240501"),
+                        query_parameter(
+                            "all_locations",
+                            "Include passing points and operational stops, which are left out by default",
+                            boolean(),
+                        ),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("The service", reference("ResolvedService")),
+                        "404": not_found(),
+                    },
+                },
+            },
+            "/train/{train_id}/{date}/geometry": {
+                "get": {
+                    "summary": "Where a train goes, as a GeoJSON line followed by a point for each location",
+                    "parameters": [
+                        path_parameter("train_id", "Train ID, e.g. a CIF UID"),
+                        path_parameter("date", "Date the train starts, YYYY-MM-DD"),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "A GeoJSON FeatureCollection",
+                            "content": { "application/json": { "schema": { "type": "object" } } },
+                        },
+                        "404": { "description": "Unknown train, or we don't know where it goes" },
+                    },
+                },
+            },
+            "/train/{global_id}/geometry": {
+                "get": {
+                    "summary": "The same, by an ID as given in global_id",
+                    "parameters": [
+                        path_parameter("global_id", "Global train ID, e.g. gbnr:C12345:20240501"),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "A GeoJSON FeatureCollection",
+                            "content": { "application/json": { "schema": { "type": "object" } } },
+                        },
+                        "404": { "description": "Unknown train, or we don't know where it goes" },
+                    },
+                },
+            },
+            "/live/positions": {
+                "get": {
+                    "summary": "Where trains with live running data probably are now, as GeoJSON points",
+                    "description": "Estimated from each train's last report, assuming it keeps to the timetable at the same delay since, and placed along the track between timing points.",
+                    "parameters": [
+                        query_parameter("bbox", "Only trains within west,south,east,north, in degrees", string()),
+                    ],
+                    "responses": {
+                        "200": json_response(
+                            "A GeoJSON FeatureCollection with a point per train",
+                            object(json!({
+                                "type": string(),
+                                "features": array_of(object(json!({
+                                    "type": string(),
+                                    "geometry": { "type": "object" },
+                                    "properties": reference("LivePositionProperties"),
+                                }))),
+                            })),
+                        ),
+                        "400": { "description": "Unreadable bounding box" },
+                    },
+                },
+            },
+            "/freight/{namespace}/{date}": {
+                "get": {
+                    "summary": "Freight and light engine workings running on a date",
+                    "parameters": [
+                        path_parameter("namespace", "Schedule namespace, e.g. gbnr"),
+                        path_parameter("date", "YYYY-MM-DD"),
+                        query_parameter("origin", "Only trains starting here", string()),
+                        query_parameter("destination", "Only trains ending here", string()),
+                        query_parameter("location", "Only trains calling or passing here", string()),
+                        source_filter(),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("Matching trains", array_of(reference("FreightTrain"))),
+                        "404": not_found(),
+                    },
+                },
+            },
+            "/train/{namespace}/{train_id}/calendar/{from}/{to}": {
+                "get": {
+                    "summary": "Which version of a train applies on each day of a range, for drawing a calendar",
+                    "parameters": [
+                        path_parameter("namespace", "Schedule namespace, e.g. gbnr"),
+                        path_parameter("train_id", "Train ID, e.g. a CIF UID"),
+                        path_parameter("from", "First date, YYYY-MM-DD"),
+                        path_parameter("to", "Last date, YYYY-MM-DD; at most 400 days after from"),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("The train's calendar", reference("TrainCalendar")),
+                        "404": not_found(),
+                    },
+                },
+            },
+            "/train/{train_id}/{date}/history": {
+                "get": {
+                    "summary": "How realtime feeds have changed a train's plan for a date, oldest first, e.g. replaced by VSTP and then cancelled",
+                    "parameters": [
+                        path_parameter("train_id", "Train ID, e.g. a CIF UID"),
+                        path_parameter("date", "Date the train starts, YYYY-MM-DD"),
+                    ],
+                    "responses": {
+                        "200": json_response("The train's changes since startup, up to a limit per train", reference("TrainHistory")),
+                        "404": not_found(),
+                    },
+                },
+            },
+            "/uic/{uic_code}/{date}": {
+                "get": {
+                    "summary": "Trains running on a date with a UIC train number, in any namespace",
+                    "parameters": [
+                        path_parameter("uic_code", "UIC train number"),
+                        path_parameter("date", "Date the train starts, YYYY-MM-DD"),
+                        source_filter(),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("Matching trains", array_of(reference("UicTrain"))),
+                    },
+                },
+            },
+            "/next": {
+                "get": {
+                    "summary": "The next direct trains between two locations, from now",
+                    "parameters": [
+                        query_parameter("from", "Location ID, public ID (e.g. a CRS code) or station group code", string()),
+                        query_parameter("to", "Location ID, public ID (e.g. a CRS code) or station group code", string()),
+                        query_parameter("count", "How many trains, default 5", json!({ "type": "integer" })),
+                        query_parameter("namespace", "Only look in this namespace", string()),
+                        query_parameter("runs_as_required", "Include runs-as-required (Q) paths, which are left out unless the server is set to show them", boolean()),
+                        source_filter(),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("Trains in order of departure", array_of(reference("NextTrain"))),
+                    },
+                },
+            },
+            "/departures": {
+                "get": {
+                    "summary": "Departures from every location in a country or region over the next day, from now",
+                    "parameters": [
+                        query_parameter("country", "ISO 3166-1 alpha-2 code, e.g. GB", string()),
+                        query_parameter("region", "Region name, e.g. Wales", string()),
+                        query_parameter("count", "How many departures, default 20", json!({ "type": "integer" })),
+                        query_parameter("namespace", "Only look in this namespace", string()),
+                        query_parameter("runs_as_required", "Include runs-as-required (Q) paths, which are left out unless the server is set to show them", boolean()),
+                        source_filter(),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("Departures in order", array_of(reference("RegionDeparture"))),
+                        "400": { "description": "Neither country nor region given" },
+                    },
+                },
+            },
+            "/locations/search": {
+                "get": {
+                    "summary": "Fuzzy search for locations by name or code",
+                    "parameters": [
+                        query_parameter("q", "Search text", string()),
+                        query_parameter("namespace", "Only search this namespace", string()),
+                        query_parameter("limit", "Maximum results, default 20", json!({ "type": "integer" })),
+                        query_parameter("country", "Only locations in this country (ISO 3166-1 alpha-2)", string()),
+                        query_parameter("region", "Only locations in this region, e.g. Wales", string()),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("Best matches first", array_of(reference("LocationSearchResult"))),
+                    },
+                },
+            },
+            "/operators": {
+                "get": {
+                    "summary": "Colours, logos and short names for operators and brands, as configured",
+                    "parameters": [
+                        query_parameter("namespace", "Only this namespace", string()),
+                    ],
+                    "responses": {
+                        "200": json_response("By namespace, then operators before brands", array_of(reference("OperatorInfo"))),
+                    },
+                },
+            },
+            "/interchange/{namespace}/{location_id}": {
+                "get": {
+                    "summary": "Minimum connection time and fixed links for a location",
+                    "parameters": [
+                        path_parameter("namespace", "Schedule namespace, e.g. gbnr"),
+                        path_parameter("location_id", "Location ID, e.g. a TIPLOC"),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("Interchange details", reference("LocationInterchange")),
+                        "404": not_found(),
+                    },
+                },
+            },
+            "/platforms/{namespace}/{location_id}/{date}": {
+                "get": {
+                    "summary": "Platform occupations at a station on a date, with any overlaps",
+                    "parameters": [
+                        path_parameter(
+                            "namespace",
+                            "Schedule namespace and ID type, e.g. gbnr-public for CRS codes or gbnr-internal for TIPLOCs",
+                        ),
+                        path_parameter("location_id", "Location ID or station group code"),
+                        path_parameter("date", "YYYY-MM-DD"),
+                        query_parameter(
+                            "turnback_margin_secs",
+                            "How long a platform must be clear between trains, overriding the configured margin",
+                            json!({ "type": "integer" }),
+                        ),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("Occupations and conflicts", reference("OccupancyReport")),
+                        "404": not_found(),
+                    },
+                },
+            },
+            "/flows/{namespace}/{from_location_id}/{to_location_id}/{from}/{to}": {
+                "get": {
+                    "summary": "Counts of direct services between two places by day and hour, and by operator",
+                    "parameters": [
+                        path_parameter(
+                            "namespace",
+                            "Schedule namespace and ID type, e.g. gbnr-public for CRS codes or gbnr-internal for TIPLOCs",
+                        ),
+                        path_parameter("from_location_id", "Location ID or station group code trains leave from"),
+                        path_parameter("to_location_id", "Location ID or station group code trains go to"),
+                        path_parameter("from", "First date, YYYY-MM-DD"),
+                        path_parameter("to", "Last date, YYYY-MM-DD; ranges are cut to the configured maximum"),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("Service counts", reference("FlowReport")),
+                        "404": not_found(),
+                    },
+                },
+            },
+            "/dwell/{namespace}/{location_id}/{date}": {
+                "get": {
+                    "summary": "Dwell and turnaround times at a station, with their distributions",
+                    "parameters": [
+                        path_parameter(
+                            "namespace",
+                            "Schedule namespace and ID type, e.g. gbnr-public for CRS codes or gbnr-internal for TIPLOCs",
+                        ),
+                        path_parameter("location_id", "Location ID"),
+                        path_parameter("date", "First date, YYYY-MM-DD"),
+                        query_parameter(
+                            "days",
+                            "How many days to cover, default 1, up to the configured maximum",
+                            json!({ "type": "integer" }),
+                        ),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("Dwell and turnaround times", reference("DwellReport")),
+                        "404": not_found(),
+                    },
+                },
+            },
+            "/movements/{namespace}/{location_id}": {
+                "get": {
+                    "summary": "Every train through a location between two times, including passes and empty stock, in working time order with full operational detail",
+                    "parameters": [
+                        path_parameter(
+                            "namespace",
+                            "Schedule namespace and ID type, e.g. gbnr-internal for TIPLOCs",
+                        ),
+                        path_parameter("location_id", "Location ID"),
+                        query_parameter(
+                            "from",
+                            "YYYY-MM-DDTHH:MM local to the location; now if not given",
+                            string(),
+                        ),
+                        query_parameter(
+                            "to",
+                            "YYYY-MM-DDTHH:MM local to the location; an hour after from if not given, and cut off at the configured maximum",
+                            string(),
+                        ),
+                        source_filter(),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": json_response("Movements", reference("MovementReport")),
+                        "400": { "description": "Invalid time, or to before from" },
+                        "404": not_found(),
+                    },
+                },
+            },
+            "/gtfs-rt": {
+                "get": {
+                    "summary": "Trips VSTP has added, changed or cancelled, delays from TRUST, and alerts for cancellations and anything else current, as GTFS-RT; trip IDs are our train IDs",
+                    "parameters": [
+                        query_parameter("namespace", "gbnr if not given", string()),
+                        query_parameter("format", "protobuf (the default) or json", enum_of(&["protobuf", "json"])),
+                        query_parameter(
+                            "entities",
+                            "Only trip_updates or only alerts; both if not given",
+                            enum_of(&["trip_updates", "alerts"]),
+                        ),
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "A GTFS-RT FeedMessage, with every entity each time",
+                            "content": {
+                                "application/x-protobuf": { "schema": { "type": "string", "format": "binary" } },
+                                "application/json": { "schema": { "type": "object" } },
+                            },
+                        },
+                        "400": { "description": "Unknown format or entities" },
+                        "404": not_found(),
+                    },
+                },
+            },
+            "/dump/trains.jsonl": {
+                "get": {
+                    "summary": "Every train running on a date, one per line, with overlays applied, for loading the whole dataset in one go",
+                    "parameters": [
+                        query_parameter("date", "YYYY-MM-DD", date()),
+                        query_parameter("namespace", "Only this namespace; all of them if not given", string()),
+                        query_parameter("gzip", "Gzip the lines; false if not given", boolean()),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "One JSON object per line, each with the same fields as a train in ResolvedService but with every location and its working times",
+                            "content": {
+                                "application/x-ndjson": { "schema": string() },
+                                "application/gzip": { "schema": { "type": "string", "format": "binary" } },
+                            },
+                        },
+                        "404": not_found(),
+                        "422": { "description": "Missing or invalid date" },
+                    },
+                },
+            },
+            "/proxy/{name}/{path}": {
+                "get": {
+                    "summary": "A configured upstream API's answer, cached, with local_train and local_location added wherever a train or location in it matches our own schedules",
+                    "parameters": [
+                        path_parameter("name", "Proxied endpoint, as configured"),
+                        path_parameter("path", "Passed upstream, along with the query string"),
+                    ],
+                    "responses": {
+                        "200": json_response("Upstream's answer", json!({ "type": "object" })),
+                        "404": { "description": "Unknown endpoint" },
+                        "502": { "description": "Upstream failed or didn't answer with JSON" },
+                    },
+                },
+            },
+            "/stats": {
+                "get": {
+                    "summary": "Counts of what each namespace has loaded",
+                    "parameters": [schedule_as_of()],
+                    "responses": {
+                        "200": json_response(
+                            "Statistics by namespace",
+                            json!({ "type": "object", "additionalProperties": reference("ScheduleStats") })
+                        ),
+                    },
+                },
+            },
+            "/quality": {
+                "get": {
+                    "summary": "How good each namespace's data has looked over the last few imports",
+                    "responses": {
+                        "200": json_response(
+                            "Reports by namespace, oldest first",
+                            json!({ "type": "object", "additionalProperties": array_of(reference("QualityReport")) })
+                        ),
+                    },
+                },
+            },
+            "/overlays/pending": {
+                "get": {
+                    "summary": "STP overlays that haven't found the schedule they replace yet, which every import tries them against again",
+                    "parameters": [schedule_as_of()],
+                    "responses": {
+                        "200": json_response(
+                            "Pending overlays by namespace",
+                            json!({ "type": "object", "additionalProperties": object(json!({
+                                "count": { "type": "integer" },
+                                "overlays": array_of(reference("PendingOverlay")),
+                            })) })
+                        ),
+                    },
+                },
+            },
+            "/status": {
+                "get": {
+                    "summary": "How long since each source last brought anything new, whether that's too long, and whether the schedules still hold together",
+                    "responses": {
+                        "200": json_response(
+                            "Source status",
+                            object(json!({
+                                "stale": boolean(),
+                                "sources": array_of(reference("SourceStatus")),
+                                "integrity_violations": { "type": "integer", "description": "In any namespace, as of the last check" },
+                                "integrity": array_of(reference("IntegrityReport")),
+                            }))
+                        ),
+                    },
+                },
+            },
+            "/healthz": {
+                "get": {
+                    "summary": "Liveness, for orchestrators: answers as long as the process isn't wedged, whatever the feeds are doing",
+                    "responses": {
+                        "200": json_response(
+                            "Alive",
+                            object(json!({
+                                "alive": boolean(),
+                                "namespaces": { "type": "integer", "description": "Loaded so far" },
+                            }))
+                        ),
+                    },
+                },
+            },
+            "/readyz": {
+                "get": {
+                    "summary": "Readiness, for load balancers: at least one complete import, and every feed we hold a connection to connected or within its staleness budget",
+                    "responses": {
+                        "200": json_response("Ready", reference("Readiness")),
+                        "503": json_response("Not ready, with the same detail", reference("Readiness")),
+                    },
+                },
+            },
+            "/admin/snapshot": {
+                "get": {
+                    "summary": "Download every schedule (or one namespace) as a gzipped binary snapshot",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [
+                        query_parameter("namespace", "Only snapshot this namespace", string()),
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The snapshot",
+                            "content": { "application/octet-stream": {} },
+                        },
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled, or unknown namespace" },
+                    },
+                },
+            },
+            "/admin/restore": {
+                "post": {
+                    "summary": "Swap in the namespaces from a snapshot",
+                    "security": [{ "adminToken": [] }],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/octet-stream": {} },
+                    },
+                    "responses": {
+                        "200": json_response("Namespaces restored", array_of(string())),
+                        "400": { "description": "Not a snapshot this version can read" },
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled" },
+                        "413": { "description": "Snapshot bigger than max_restore_mb, or than max_restore_unpacked_mb once unpacked" },
+                    },
+                },
+            },
+            "/admin/replay": {
+                "get": {
+                    "summary": "Rebuild the gbnr schedule as it was at some time from the download cache and VSTP log, as a snapshot",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [
+                        {
+                            "name": "at",
+                            "in": "query",
+                            "required": true,
+                            "description": "When to rebuild it as of (RFC 3339, or YYYY-MM-DDTHH:MM in UTC)",
+                            "schema": { "type": "string", "format": "date-time" },
+                        },
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The snapshot",
+                            "content": { "application/octet-stream": {} },
+                        },
+                        "400": { "description": "Invalid time" },
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints or the download cache not enabled" },
+                        "500": { "description": "Nothing cached from that far back, or the import failed" },
+                    },
+                },
+            },
+            "/admin/validate": {
+                "post": {
+                    "summary": "Run a file through an importer and report what it found, without changing anything",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [
+                        query_parameter("format", "cif (the default) or gtfs", enum_of(&["cif", "gtfs"])),
+                        query_parameter(
+                            "namespace",
+                            "Apply the file to a copy of this namespace, as for an update",
+                            string(),
+                        ),
+                    ],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/octet-stream": {} },
+                    },
+                    "responses": {
+                        "200": json_response("What the importer made of it", reference("ValidationReport")),
+                        "400": { "description": "Unknown format" },
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled, or unknown namespace" },
+                        "413": { "description": "File bigger than max_restore_mb" },
+                        "422": { "description": "The file couldn't be read at all" },
+                    },
+                },
+            },
+            "/admin/export/{namespace}/{location_id}/{from}/{to}": {
+                "get": {
+                    "summary": "A departure poster for a location: each departure time and destination, with the days it runs",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [
+                        path_parameter("namespace", "Schedule namespace, e.g. gbnr"),
+                        path_parameter("location_id", "Location ID, e.g. a TIPLOC"),
+                        path_parameter("from", "First date, YYYY-MM-DD"),
+                        path_parameter("to", "Last date, YYYY-MM-DD, no more than 400 days later"),
+                        query_parameter(
+                            "format",
+                            "csv (the default), pdf or xlsx",
+                            enum_of(&["csv", "pdf", "xlsx"]),
+                        ),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The poster",
+                            "content": {
+                                "text/csv": {},
+                                "application/pdf": {},
+                                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet": {},
+                            },
+                        },
+                        "400": { "description": "Unknown format, or bad date range" },
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled, or unknown namespace or location" },
+                    },
+                },
+            },
+            "/admin/weekly/{namespace}/{location_id}/{week_commencing}": {
+                "get": {
+                    "summary": "A week's timetable for a location: a row for each train, with when it calls on each day",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [
+                        path_parameter("namespace", "Schedule namespace, e.g. gbnr"),
+                        path_parameter("location_id", "Location ID, e.g. a TIPLOC"),
+                        path_parameter("week_commencing", "First day of the week, YYYY-MM-DD"),
+                        query_parameter(
+                            "format",
+                            "csv (the default), pdf or xlsx",
+                            enum_of(&["csv", "pdf", "xlsx"]),
+                        ),
+                        schedule_as_of(),
+                    ],
+                    "responses": {
+                        "200": {
+                            "description": "The timetable; times with an a after them are arrivals where the train terminates",
+                            "content": {
+                                "text/csv": {},
+                                "application/pdf": {},
+                                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet": {},
+                            },
+                        },
+                        "400": { "description": "Unknown format, or bad date" },
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled, or unknown namespace or location" },
+                    },
+                },
+            },
+            "/admin/reindex": {
+                "post": {
+                    "summary": "Rebuild the train indexes from the trains themselves, reporting how far they'd drifted",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [query_parameter("namespace", "Only this namespace", string())],
+                    "responses": {
+                        "200": json_response("By namespace", array_of(reference("IndexRebuild"))),
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled, or unknown namespace" },
+                    },
+                },
+            },
+            "/admin/downloads": {
+                "get": {
+                    "summary": "How downloading from each source has been going since startup",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": json_response("By source name", array_of(reference("DownloadMetrics"))),
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled" },
+                    },
+                },
+            },
+            "/admin/sources": {
+                "get": {
+                    "summary": "When each source next fetches, and whether it's paused",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": json_response("By source name", array_of(reference("RunState"))),
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled" },
+                    },
+                },
+            },
+            "/admin/feeds": {
+                "get": {
+                    "summary": "How each realtime feed's messages have been reading since startup",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": json_response(
+                            "By source name, e.g. gbnr-vstp",
+                            json!({ "type": "object", "additionalProperties": reference("SourceTelemetry") })
+                        ),
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled" },
+                    },
+                },
+            },
+            "/admin/sources/{source}": {
+                "get": {
+                    "summary": "When one source next fetches, and whether it's paused",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [path_parameter("source", "Source name, e.g. gbnr-cif")],
+                    "responses": {
+                        "200": json_response("The source's state", reference("RunState")),
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled, or unknown source" },
+                    },
+                },
+            },
+            "/admin/sources/{source}/pause": {
+                "post": {
+                    "summary": "Stop a source fetching, scheduled or triggered, until it's resumed",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [path_parameter("source", "Source name, e.g. gbnr-cif")],
+                    "responses": {
+                        "202": { "description": "Paused" },
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled, or unknown source" },
+                    },
+                },
+            },
+            "/admin/sources/{source}/resume": {
+                "post": {
+                    "summary": "Let a source fetch again, straight away if it missed a run while paused",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [path_parameter("source", "Source name, e.g. gbnr-cif")],
+                    "responses": {
+                        "202": { "description": "Resumed" },
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled, or unknown source" },
+                    },
+                },
+            },
+            "/admin/sources/{source}/time/{time}": {
+                "post": {
+                    "summary": "Move a source's daily fetch until the next restart",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [
+                        path_parameter("source", "Source name, e.g. gbnr-cif"),
+                        path_parameter("time", "HH:MM, local to the source"),
+                    ],
+                    "responses": {
+                        "202": { "description": "Moved" },
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled, or unknown source" },
+                    },
+                },
+            },
+            "/upstream": {
+                "get": {
+                    "summary": "Sources that upstream can tell us have published something new",
+                    "security": [{ "upstreamToken": [] }],
+                    "responses": {
+                        "200": json_response("Source names, e.g. gbnr-cif", array_of(string())),
+                        "401": { "description": "Missing or wrong upstream token" },
+                        "404": { "description": "Upstream notifications not enabled" },
+                    },
+                },
+            },
+            "/upstream/{source}": {
+                "post": {
+                    "summary": "Fetch a source now, as a new extract or update is out, instead of at its usual time",
+                    "security": [{ "upstreamToken": [] }],
+                    "parameters": [path_parameter("source", "Source name, e.g. gbnr-cif or ieir-gtfs")],
+                    "responses": {
+                        "202": { "description": "The fetch will start shortly" },
+                        "401": { "description": "Missing or wrong upstream token" },
+                        "404": { "description": "Upstream notifications not enabled, or unknown source" },
+                    },
+                },
+            },
+            "/subscriptions": {
+                "post": {
+                    "summary": "Ask to be told about retimings, platform changes and cancellations",
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": reference("SubscriptionRequest") } },
+                    },
+                    "responses": {
+                        "200": json_response("The new subscription", object(json!({ "id": string() }))),
+                        "400": { "description": "Webhook isn't an HTTP(S) URL to a host that's been allowed" },
+                        "503": { "description": "Too many subscriptions already" },
+                    },
+                },
+            },
+            "/subscriptions/{id}/events": {
+                "get": {
+                    "summary": "Notifications waiting for a subscription, oldest first",
+                    "parameters": [
+                        path_parameter("id", "Subscription ID"),
+                        query_parameter(
+                            "after",
+                            "Throw away notifications up to and including this seq, as already seen",
+                            json!({ "type": "integer" }),
+                        ),
+                    ],
+                    "responses": {
+                        "200": json_response("Notifications", array_of(reference("Notification"))),
+                        "404": { "description": "Unknown or expired subscription" },
+                    },
+                },
+            },
+            "/subscriptions/{id}": {
+                "delete": {
+                    "summary": "Cancel a subscription",
+                    "parameters": [path_parameter("id", "Subscription ID")],
+                    "responses": {
+                        "204": { "description": "Cancelled" },
+                        "404": { "description": "Unknown or expired subscription" },
+                    },
+                },
+            },
+            "/alerts": {
+                "get": {
+                    "summary": "Alerts about known problems with the data that are up at the moment",
+                    "parameters": [
+                        query_parameter("namespace", "Only those about this namespace", string()),
+                        query_parameter("operator_id", "Only those about this operator", string()),
+                        query_parameter("location_id", "Only those about this location", string()),
+                    ],
+                    "responses": {
+                        "200": json_response("The alerts", array_of(reference("Alert"))),
+                    },
+                },
+            },
+            "/admin/alerts": {
+                "get": {
+                    "summary": "Every alert, including ones that have expired or not started yet",
+                    "security": [{ "adminToken": [] }],
+                    "responses": {
+                        "200": json_response("The alerts", array_of(reference("Alert"))),
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled" },
+                    },
+                },
+                "post": {
+                    "summary": "Put up an alert, shown with everything it's scoped to (or everything, if it isn't)",
+                    "security": [{ "adminToken": [] }],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": reference("NewAlert") } },
+                    },
+                    "responses": {
+                        "200": json_response("The new alert", reference("Alert")),
+                        "400": { "description": "No message" },
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled" },
+                    },
+                },
+            },
+            "/admin/alerts/{id}/expire": {
+                "post": {
+                    "summary": "Take an alert down now, keeping it for the record",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [path_parameter("id", "Alert ID")],
+                    "responses": {
+                        "204": { "description": "Expired" },
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled, or unknown alert" },
+                    },
+                },
+            },
+            "/admin/alerts/{id}": {
+                "delete": {
+                    "summary": "Delete an alert",
+                    "security": [{ "adminToken": [] }],
+                    "parameters": [path_parameter("id", "Alert ID")],
+                    "responses": {
+                        "204": { "description": "Deleted" },
+                        "401": { "description": "Missing or wrong admin token" },
+                        "404": { "description": "Admin endpoints not enabled, or unknown alert" },
+                    },
+                },
+            },
+            "/cache/stats": {
+                "get": {
+                    "summary": "Query cache hit rates",
+                    "responses": {
+                        "200": json_response("Cache statistics", reference("QueryCacheStats")),
+                    },
+                },
+            },
+            "/openapi.json": {
+                "get": {
+                    "summary": "This document",
+                    "responses": {
+                        "200": json_response("OpenAPI 3 document", json!({ "type": "object" })),
+                    },
+                },
+            },
+            "/docs": {
+                "get": {
+                    "summary": "Swagger UI for this document",
+                    "responses": {
+                        "200": html_response("The page"),
+                    },
+                },
+            },
+        },
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "adminToken": { "type": "http", "scheme": "bearer" },
+                "upstreamToken": { "type": "http", "scheme": "bearer" },
+            },
+        },
+    });
+    document["paths"]
+        .as_object_mut()
+        .unwrap()
+        .extend(board_paths());
+    document
+}
+
+// Swagger UI from a CDN, pointed at our document
+pub const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="utf-8">
+    <title>World Rail Timetables API</title>
+    <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+  </head>
+  <body>
+    <div id="swagger-ui"></div>
+    <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+    <script>
+      window.onload = () => {
+        window.ui = SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+      };
+    </script>
+  </body>
+</html>
+"##;
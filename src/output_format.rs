@@ -0,0 +1,221 @@
+use chrono::{DateTime, NaiveTime};
+
+use serde_json::Value;
+
+// Speeds are m/s and times RFC 3339 everywhere internally, and that's what the API gives unless
+// asked otherwise. Rather than every endpoint knowing about units, responses are rewritten on the
+// way out, going by field names: those ending in m_per_s for speeds, and those in TIME_FIELDS for
+// times, so nothing else that happens to look like a time (a headcode, a note) is touched.
+
+// every field that holds a date-time or a time of day, in any response
+const TIME_FIELDS: &[&str] = &[
+    "activated",
+    "actual",
+    "after",
+    "arrival",
+    "as_of",
+    "at",
+    "checked",
+    "computed",
+    "connection_changed",
+    "created",
+    "dep_time",
+    "departure",
+    "expires",
+    "fetched",
+    "from",
+    "from_time",
+    "full_extract_fetched",
+    "last_failure",
+    "last_imported",
+    "last_quarantined",
+    "last_report",
+    "last_success",
+    "last_updated",
+    "new_begin",
+    "new_end",
+    "next_run",
+    "now",
+    "planned",
+    "public_arr",
+    "public_dep",
+    "received",
+    "since",
+    "started",
+    "starts",
+    "time",
+    "timestamp",
+    "to",
+    "to_time",
+    "until",
+    "update_time",
+    "valid_begin",
+    "valid_end",
+    "working_arr",
+    "working_dep",
+    "working_pass",
+    "working_time",
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SpeedUnit {
+    #[default]
+    MetresPerSecond,
+    KilometresPerHour,
+    MilesPerHour,
+}
+
+impl SpeedUnit {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "m/s" | "mps" | "ms" => Some(Self::MetresPerSecond),
+            "km/h" | "kmh" | "kph" => Some(Self::KilometresPerHour),
+            "mph" => Some(Self::MilesPerHour),
+            _ => None,
+        }
+    }
+
+    // what replaces "m_per_s" at the end of field names
+    fn suffix(&self) -> &'static str {
+        match self {
+            Self::MetresPerSecond => "m_per_s",
+            Self::KilometresPerHour => "km_per_h",
+            Self::MilesPerHour => "mph",
+        }
+    }
+
+    fn convert(&self, m_per_s: f64) -> f64 {
+        match self {
+            Self::MetresPerSecond => m_per_s,
+            Self::KilometresPerHour => m_per_s * 3.6,
+            Self::MilesPerHour => m_per_s * 3600.0 / 1609.344,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TimeFormat {
+    #[default]
+    Rfc3339,
+    TwentyFourHour, // "2024-05-01 14:05 +01:00", in the time's own offset
+    TwelveHour,     // "2024-05-01 2:05pm +01:00"
+}
+
+impl TimeFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "rfc3339" | "iso" => Some(Self::Rfc3339),
+            "24h" | "24" => Some(Self::TwentyFourHour),
+            "12h" | "12" => Some(Self::TwelveHour),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputFormat {
+    pub speed: SpeedUnit,
+    pub time: TimeFormat,
+    pub seconds: bool, // timetables are to the half minute at best, so seconds are often noise
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        Self {
+            speed: SpeedUnit::default(),
+            time: TimeFormat::default(),
+            seconds: true,
+        }
+    }
+}
+
+impl OutputFormat {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    fn time_pattern(&self) -> &'static str {
+        match (self.time, self.seconds) {
+            (TimeFormat::Rfc3339, true) => "%H:%M:%S",
+            (TimeFormat::Rfc3339, false) => "%H:%M",
+            (TimeFormat::TwentyFourHour, true) => "%H:%M:%S",
+            (TimeFormat::TwentyFourHour, false) => "%H:%M",
+            (TimeFormat::TwelveHour, true) => "%-I:%M:%S%P",
+            (TimeFormat::TwelveHour, false) => "%-I:%M%P",
+        }
+    }
+
+    fn format_string(&self, s: &str) -> Option<String> {
+        match DateTime::parse_from_rfc3339(s) {
+            Ok(x) => {
+                let pattern = match self.time {
+                    TimeFormat::Rfc3339 => format!("%Y-%m-%dT{}%:z", self.time_pattern()),
+                    _ => format!("%Y-%m-%d {} %:z", self.time_pattern()),
+                };
+                return Some(x.format(&pattern).to_string());
+            }
+            Err(_) => (),
+        }
+        // times of day, as in raw timetable entries
+        match NaiveTime::parse_from_str(s, "%H:%M:%S") {
+            Ok(x) => Some(x.format(self.time_pattern()).to_string()),
+            Err(_) => None,
+        }
+    }
+
+    // a time field's value, which may be a list of them
+    fn apply_time(&self, value: &mut Value) {
+        match value {
+            Value::String(s) => match self.format_string(s) {
+                Some(x) => *s = x,
+                None => (),
+            },
+            Value::Array(x) => {
+                for item in x {
+                    self.apply_time(item);
+                }
+            }
+            _ => self.apply(value),
+        }
+    }
+
+    pub fn apply(&self, value: &mut Value) {
+        match value {
+            Value::Array(x) => {
+                for item in x {
+                    self.apply(item);
+                }
+            }
+            Value::Object(x) => {
+                let speeds = x
+                    .keys()
+                    .filter(|key| key.ends_with("m_per_s"))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                for key in speeds {
+                    let speed = match x.remove(&key) {
+                        Some(Value::Number(n)) => match n.as_f64() {
+                            Some(n) => Value::from(self.speed.convert(n)),
+                            None => Value::Number(n),
+                        },
+                        Some(other) => other,
+                        None => continue,
+                    };
+                    let key = format!(
+                        "{}{}",
+                        key.strip_suffix("m_per_s").unwrap(),
+                        self.speed.suffix()
+                    );
+                    x.insert(key, speed);
+                }
+                for (key, item) in x.iter_mut() {
+                    match TIME_FIELDS.contains(&key.as_str()) {
+                        true => self.apply_time(item),
+                        false => self.apply(item),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,223 @@
+use serde_json::{Map, Value};
+
+// Any JSON response can be cut down with ?fields=, and any list paged through with ?limit= and
+// ?cursor=, rather than every endpoint knowing about either. Whole services and long lists of
+// trains are big, and most clients only want a few fields of the first few. A response that's an
+// object is paged through its one main list, if its endpoint names one, e.g. a movement report's
+// movements.
+//
+// Cursors say which generation of the schedules the page came from, and which item it ended on,
+// so a page never silently starts in the wrong place because the list changed in between. Once
+// the schedules have moved on, the cursor's refused, and the client starts again.
+//
+// fields is comma separated: names to keep only those, or names with "-" in front to leave those
+// out, e.g. fields=id,date,route or fields=-route,-duplicates. Dots go further in, through lists as
+// well as objects, e.g. fields=id,route.id or fields=-route.activities. In a list, it's each item's
+// fields. Names are as before output formatting, so speeds are still in _m_per_s.
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Paging {
+    pub after: Option<PageCursor>, // where the last page ended; clients only get it from that page
+    pub limit: Option<usize>,
+    pub keep: Vec<Vec<String>>, // field paths, nothing else if there are any
+    pub drop: Vec<Vec<String>>,
+}
+
+// Opaque to clients, as hex, so nobody's tempted to make their own
+#[derive(Clone, Debug, PartialEq)]
+pub struct PageCursor {
+    generation: u64,
+    offset: usize, // how many items came before
+    key: String,   // that of the last of those, see item_key()
+}
+
+impl PageCursor {
+    fn encode(&self) -> String {
+        format!("{}.{}.{}", self.generation, self.offset, self.key)
+            .bytes()
+            .map(|x| format!("{:02x}", x))
+            .collect()
+    }
+
+    fn decode(s: &str) -> Option<Self> {
+        let bytes = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<_>>>()?;
+        let s = String::from_utf8(bytes).ok()?;
+        let mut parts = s.splitn(3, '.');
+        Some(Self {
+            generation: parts.next()?.parse().ok()?,
+            offset: parts.next()?.parse().ok()?,
+            key: parts.next()?.to_string(),
+        })
+    }
+}
+
+// What identifies an item in a list, as far as it says: its ID and date, for a train
+fn item_key(item: &Value) -> String {
+    ["id", "train_id", "date"]
+        .iter()
+        .filter_map(|x| item.get(x)?.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn field_path(field: &str) -> Result<Vec<String>, String> {
+    let path = field.split('.').map(|x| x.to_string()).collect::<Vec<_>>();
+    match path.iter().any(|x| x.is_empty()) {
+        true => Err(format!("Bad field {}", field)),
+        false => Ok(path),
+    }
+}
+
+fn keep_paths(value: &Value, paths: &[&[String]]) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(items.iter().map(|x| keep_paths(x, paths)).collect()),
+        Value::Object(fields) => {
+            let mut kept = Map::new();
+            for (key, item) in fields {
+                let rest = paths
+                    .iter()
+                    .filter(|x| x[0] == *key)
+                    .map(|x| &x[1..])
+                    .collect::<Vec<_>>();
+                if rest.is_empty() {
+                    continue;
+                }
+                // all of it if it's asked for itself, not only some of its fields
+                match rest.iter().any(|x| x.is_empty()) {
+                    true => kept.insert(key.clone(), item.clone()),
+                    false => kept.insert(key.clone(), keep_paths(item, &rest)),
+                };
+            }
+            Value::Object(kept)
+        }
+        x => x.clone(),
+    }
+}
+
+fn drop_path(value: &mut Value, path: &[String]) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                drop_path(item, path);
+            }
+        }
+        Value::Object(fields) if path.len() == 1 => {
+            fields.remove(&path[0]);
+        }
+        Value::Object(fields) => match fields.get_mut(&path[0]) {
+            Some(x) => drop_path(x, &path[1..]),
+            None => (),
+        },
+        _ => (),
+    }
+}
+
+impl Paging {
+    // None if none of them were given, so the response can be left as it is
+    pub fn parse(
+        cursor: Option<&str>,
+        limit: Option<&str>,
+        fields: Option<&str>,
+    ) -> Result<Option<Self>, String> {
+        if cursor.is_none() && limit.is_none() && fields.is_none() {
+            return Ok(None);
+        }
+        let mut paging = Paging::default();
+        match cursor {
+            Some(x) => {
+                paging.after = Some(PageCursor::decode(x).ok_or(format!("Bad cursor {}", x))?);
+            }
+            None => (),
+        }
+        match limit {
+            Some(x) => {
+                paging.limit = Some(x.parse().map_err(|_| format!("Bad limit {}", x))?);
+            }
+            None => (),
+        }
+        for field in fields.unwrap_or("").split(',').map(|x| x.trim()) {
+            match field.strip_prefix('-') {
+                _ if field.is_empty() => (),
+                Some(x) => paging.drop.push(field_path(x)?),
+                None => paging.keep.push(field_path(field)?),
+            }
+        }
+        Ok(Some(paging))
+    }
+
+    fn select(&self, value: &mut Value) {
+        if !self.keep.is_empty() {
+            let keep = self.keep.iter().map(|x| x.as_slice()).collect::<Vec<_>>();
+            *value = keep_paths(value, &keep);
+        }
+        for path in &self.drop {
+            drop_path(value, path);
+        }
+    }
+
+    fn page(&self, items: &mut Vec<Value>, generation: u64) -> Result<Option<String>, String> {
+        let start = match &self.after {
+            Some(x) if x.generation != generation => {
+                return Err(
+                    "The list has changed since that cursor, so start again from the first page"
+                        .to_string(),
+                )
+            }
+            Some(x) if x.offset == 0 => 0,
+            Some(x) => match items.get(x.offset - 1) {
+                Some(item) if item_key(item) == x.key => x.offset,
+                _ => return Err("Bad cursor".to_string()),
+            },
+            None => 0,
+        };
+        let end = match self.limit {
+            Some(x) => std::cmp::min(start.saturating_add(x), items.len()),
+            None => items.len(),
+        };
+        let next = match end < items.len() {
+            true => Some(PageCursor {
+                generation,
+                offset: end,
+                key: match end {
+                    0 => String::new(),
+                    _ => item_key(&items[end - 1]),
+                },
+            }),
+            false => None,
+        };
+        items.truncate(end);
+        items.drain(..std::cmp::min(start, end));
+        Ok(next.map(|x| x.encode()))
+    }
+
+    // Cuts the response down to the page and fields asked for, giving the cursor for the next page
+    // if there's more. `list` is where the list to page is, as a JSON pointer, if the response is
+    // an object; `generation` is that of the schedules it was made from.
+    pub fn apply(
+        &self,
+        value: &mut Value,
+        list: Option<&str>,
+        generation: u64,
+    ) -> Result<Option<String>, String> {
+        let items = match list {
+            Some(x) => value.pointer_mut(x),
+            None => Some(&mut *value),
+        };
+        let next = match items {
+            Some(Value::Array(items)) => self.page(items, generation)?,
+            _ => None,
+        };
+        match value {
+            Value::Array(items) => {
+                for item in items.iter_mut() {
+                    self.select(item);
+                }
+            }
+            x => self.select(x),
+        }
+        Ok(next)
+    }
+}
@@ -0,0 +1,249 @@
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::borrow::Borrow;
+use std::collections::hash_map::{DefaultHasher, Entry};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::ops::Index;
+use std::sync::Arc;
+
+const PARTITIONS: usize = 256;
+
+/// A map from IDs to whatever, split into partitions which are each behind an Arc. Cloning one
+/// only copies the pointers, and changing it only copies the partitions that change, so a writer
+/// can take its own copy of a whole schedule to apply one VSTP message to without copying every
+/// train in the country. Otherwise it's used as a HashMap would be.
+pub struct PartitionedMap<V> {
+    partitions: Vec<Arc<HashMap<String, V>>>,
+}
+
+// the same for every process, unlike HashMap's own, though nothing should depend on it
+fn partition<Q: Hash + ?Sized>(key: &Q) -> usize {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    (hasher.finish() % PARTITIONS as u64) as usize
+}
+
+pub type Iter<'a, V> = std::iter::FlatMap<
+    std::slice::Iter<'a, Arc<HashMap<String, V>>>,
+    std::collections::hash_map::Iter<'a, String, V>,
+    fn(&'a Arc<HashMap<String, V>>) -> std::collections::hash_map::Iter<'a, String, V>,
+>;
+
+impl<V> PartitionedMap<V> {
+    pub fn new() -> Self {
+        Self {
+            partitions: (0..PARTITIONS).map(|_| Arc::new(HashMap::new())).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.partitions.iter().map(|x| x.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.partitions.iter().all(|x| x.is_empty())
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        String: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.partitions[partition(key)].get(key)
+    }
+
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&String, &V)>
+    where
+        String: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.partitions[partition(key)].get_key_value(key)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        String: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.partitions[partition(key)].contains_key(key)
+    }
+
+    pub fn iter(&self) -> Iter<'_, V> {
+        self.partitions.iter().flat_map(|x| x.iter())
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &String> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+
+    // how many partitions the two still share, i.e. that nothing's been changed in since one was
+    // cloned from the other
+    pub fn shared_partitions(&self, other: &Self) -> usize {
+        self.partitions
+            .iter()
+            .zip(&other.partitions)
+            .filter(|(x, y)| Arc::ptr_eq(x, y))
+            .count()
+    }
+}
+
+impl<V: Clone> PartitionedMap<V> {
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        String: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let partition = &mut self.partitions[partition(key)];
+        // only copy the partition if there's something in it to change
+        if !partition.contains_key(key) {
+            return None;
+        }
+        Arc::make_mut(partition).get_mut(key)
+    }
+
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+        Arc::make_mut(&mut self.partitions[partition(&key)]).insert(key, value)
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        String: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let partition = &mut self.partitions[partition(key)];
+        if !partition.contains_key(key) {
+            return None;
+        }
+        Arc::make_mut(partition).remove(key)
+    }
+
+    pub fn entry(&mut self, key: String) -> Entry<'_, String, V> {
+        Arc::make_mut(&mut self.partitions[partition(&key)]).entry(key)
+    }
+
+    // these copy every partition that isn't only ours, so are for full imports rather than updates
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&String, &mut V)> {
+        self.partitions
+            .iter_mut()
+            .flat_map(|x| Arc::make_mut(x).iter_mut())
+    }
+
+    pub fn values_mut(&mut self) -> impl Iterator<Item = &mut V> {
+        self.iter_mut().map(|(_, v)| v)
+    }
+
+    // only copies the partitions something's taken out of
+    pub fn retain(&mut self, mut f: impl FnMut(&String, &V) -> bool) {
+        for partition in &mut self.partitions {
+            if partition.iter().all(|(k, v)| f(k, v)) {
+                continue;
+            }
+            Arc::make_mut(partition).retain(|k, v| f(k, v));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl<V> Default for PartitionedMap<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// only copies the pointers
+impl<V> Clone for PartitionedMap<V> {
+    fn clone(&self) -> Self {
+        Self {
+            partitions: self.partitions.clone(),
+        }
+    }
+}
+
+impl<Q, V> Index<&Q> for PartitionedMap<V>
+where
+    String: Borrow<Q>,
+    Q: Hash + Eq + ?Sized,
+{
+    type Output = V;
+
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for PartitionedMap<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl<'a, V> IntoIterator for &'a PartitionedMap<V> {
+    type Item = (&'a String, &'a V);
+    type IntoIter = Iter<'a, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<V: Clone> IntoIterator for PartitionedMap<V> {
+    type Item = (String, V);
+    type IntoIter = std::vec::IntoIter<(String, V)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.partitions
+            .into_iter()
+            .flat_map(Arc::unwrap_or_clone)
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+impl<V: Clone> FromIterator<(String, V)> for PartitionedMap<V> {
+    fn from_iter<I: IntoIterator<Item = (String, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<V: Clone> Extend<(String, V)> for PartitionedMap<V> {
+    fn extend<I: IntoIterator<Item = (String, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<V: Clone> From<HashMap<String, V>> for PartitionedMap<V> {
+    fn from(map: HashMap<String, V>) -> Self {
+        map.into_iter().collect()
+    }
+}
+
+// the same as a HashMap's, so snapshots don't care which they were written from
+impl<V: Serialize> Serialize for PartitionedMap<V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.len()))?;
+        for (key, value) in self {
+            map.serialize_entry(key, value)?;
+        }
+        map.end()
+    }
+}
+
+impl<'de, V: Deserialize<'de> + Clone> Deserialize<'de> for PartitionedMap<V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(HashMap::<String, V>::deserialize(deserializer)?.into())
+    }
+}
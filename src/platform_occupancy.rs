@@ -0,0 +1,210 @@
+use crate::schedule::{get_train_instance, max_day_offset, Schedule, TrainLocation};
+
+use chrono::{DateTime, Days, Duration, NaiveDate};
+use chrono_tz::Tz;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Default, Deserialize)]
+pub struct PlatformOccupancyConfig {
+    turnback_margin_secs: Option<i64>, // how long a platform must be clear between two trains
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlatformOccupation {
+    pub train_id: String,
+    pub public_id: Option<String>,
+    pub date: NaiveDate, // that the train starts on, which needn't be the date asked about
+    pub location_id: String,
+    pub id_suffix: Option<String>,
+    pub platform: String,
+    pub platform_zone: Option<String>,
+    pub from: DateTime<Tz>,
+    pub to: DateTime<Tz>, // the same as from for trains that pass through
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct PlatformConflict {
+    pub platform: String,
+    pub first: usize, // indices into the occupations, first being the earlier
+    pub second: usize,
+    pub overlap_secs: i64, // including the turnback margin
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OccupancyReport {
+    pub namespace: String,
+    pub location_ids: Vec<String>,
+    pub date: NaiveDate,
+    pub turnback_margin_secs: i64,
+    pub occupations: Vec<PlatformOccupation>, // by platform, then time
+    pub conflicts: Vec<PlatformConflict>,
+}
+
+pub struct PlatformOccupancy {
+    turnback_margin_secs: i64,
+}
+
+// trains that join, divide or form each other here are meant to share a platform
+fn associated_trains(location: &TrainLocation) -> HashSet<String> {
+    location
+        .divides_to_form
+        .iter()
+        .chain(location.joins_to.iter())
+        .chain(location.becomes.iter())
+        .chain(location.divides_from.iter())
+        .chain(location.is_joined_to_by.iter())
+        .chain(location.forms_from.iter())
+        .map(|x| x.other_train_id.to_string())
+        .collect()
+}
+
+impl PlatformOccupancy {
+    pub fn new(config: PlatformOccupancyConfig) -> Self {
+        Self {
+            turnback_margin_secs: config.turnback_margin_secs.unwrap_or(180),
+        }
+    }
+
+    // Every timetabled stop or pass at the locations with a platform, on the date in the
+    // location's time zone, going by working times where there are any. Cancelled trains and
+    // anything without a platform are left out, as there's nothing to check them against.
+    pub fn report(
+        &self,
+        schedule: &Schedule,
+        location_ids: &HashSet<String>,
+        date: NaiveDate,
+        turnback_margin_secs: Option<i64>,
+    ) -> OccupancyReport {
+        let turnback_margin_secs = turnback_margin_secs.unwrap_or(self.turnback_margin_secs);
+        let mut occupations = vec![];
+        let mut associations = HashMap::new();
+
+        let mut train_ids = HashSet::new();
+        for location_id in location_ids {
+            match schedule.trains_indexed_by_location.get(location_id) {
+                Some(x) => train_ids.extend(x.iter()),
+                None => (),
+            }
+        }
+
+        for train_id in train_ids {
+            let versions = match schedule.trains.get(train_id) {
+                Some(x) if !x.is_empty() => x,
+                _ => continue,
+            };
+            // trains that started a day or two before can still be here today
+            for days_before in 0..=u64::from(max_day_offset(versions)) {
+                let start_date = match date.checked_sub_days(Days::new(days_before)) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                let train = match get_train_instance(versions, start_date) {
+                    (Some(x), false, _) => x,
+                    _ => continue,
+                };
+                for location in &train.route {
+                    if !location_ids.contains(location.id.as_str()) {
+                        continue;
+                    }
+                    let platform = match &location.scheduled_platform {
+                        Some(x) => x.to_string(),
+                        None => continue,
+                    };
+                    let timezone = match schedule.locations.get(location.id.as_str()) {
+                        Some(x) => x.timezone,
+                        None => continue,
+                    };
+                    let times = location.local_times(start_date, timezone);
+                    let arr = times
+                        .working_arr
+                        .or(times.public_arr)
+                        .or(times.working_pass);
+                    let dep = times
+                        .working_dep
+                        .or(times.public_dep)
+                        .or(times.working_pass);
+                    let (from, to) = match (arr.or(dep), dep.or(arr)) {
+                        (Some(x), Some(y)) => (x, y),
+                        _ => continue,
+                    };
+                    if from.date_naive() != date && to.date_naive() != date {
+                        continue;
+                    }
+                    associations
+                        .entry(train.id.clone())
+                        .or_insert_with(HashSet::new)
+                        .extend(associated_trains(location));
+                    occupations.push(PlatformOccupation {
+                        train_id: train.id.clone(),
+                        public_id: train.variable_train.public_id.clone(),
+                        date: start_date,
+                        location_id: location.id.to_string(),
+                        id_suffix: location.id_suffix.clone(),
+                        platform,
+                        platform_zone: location.platform_zone.as_ref().map(|x| x.to_string()),
+                        from,
+                        to,
+                    });
+                }
+            }
+        }
+
+        occupations.sort_by(|a, b| {
+            a.platform
+                .cmp(&b.platform)
+                .then(a.from.cmp(&b.from))
+                .then(a.train_id.cmp(&b.train_id))
+        });
+
+        let is_associated = |a: &PlatformOccupation, b: &PlatformOccupation| {
+            associations
+                .get(&a.train_id)
+                .is_some_and(|x| x.contains(&b.train_id))
+                || associations
+                    .get(&b.train_id)
+                    .is_some_and(|x| x.contains(&a.train_id))
+        };
+
+        let margin = Duration::seconds(turnback_margin_secs);
+        let mut conflicts = vec![];
+        for (i, first) in occupations.iter().enumerate() {
+            for (j, second) in occupations.iter().enumerate().skip(i + 1) {
+                if second.platform != first.platform {
+                    break;
+                }
+                // sorted by start, so nothing later can overlap either
+                if second.from >= first.to + margin {
+                    break;
+                }
+                if first.train_id == second.train_id || is_associated(first, second) {
+                    continue;
+                }
+                // both ends of a long platform
+                match (&first.platform_zone, &second.platform_zone) {
+                    (Some(x), Some(y)) if x != y => continue,
+                    _ => (),
+                }
+                conflicts.push(PlatformConflict {
+                    platform: first.platform.clone(),
+                    first: i,
+                    second: j,
+                    overlap_secs: (first.to + margin - second.from).num_seconds(),
+                });
+            }
+        }
+
+        let mut location_ids = location_ids.iter().cloned().collect::<Vec<_>>();
+        location_ids.sort();
+        OccupancyReport {
+            namespace: schedule.namespace.clone(),
+            location_ids,
+            date,
+            turnback_margin_secs,
+            occupations,
+            conflicts,
+        }
+    }
+}
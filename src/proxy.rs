@@ -0,0 +1,359 @@
+use crate::error::Error;
+use crate::fetch_core::HttpClient;
+use crate::schedule::{get_train_instance, Schedule, TrainOperator, TrainSource, TrainType};
+use crate::train_id::GlobalTrainId;
+
+use chrono::{NaiveDate, Utc};
+use chrono_tz::Europe::London;
+use chrono_tz::Tz;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// A stepping stone for apps still built on someone else's API, e.g. Darwin's boards. Calls to the
+// endpoints configured here are passed through upstream, with the answers cached for a little
+// while, and every train and location in them that we can match up gets what our own schedules
+// say about it alongside, under local_train and local_location. An app can then move over to our
+// data a field at a time, and to our own endpoints once it no longer needs upstream's.
+//
+// Nothing of the client's own request goes upstream but the path after the endpoint's name and
+// the query parameters listed for the endpoint; its headers never do. Every request upstream
+// carries the endpoint's configured headers, whoever asked, so anyone who can reach the proxy can
+// use whatever key is in them for those paths.
+
+#[derive(Clone, Deserialize)]
+pub struct ProxyEndpointConfig {
+    url: String, // with {path} for whatever follows the endpoint's name, e.g. https://x/{path}
+    headers: Option<HashMap<String, String>>, // sent upstream with every request, e.g. an API key
+    query_params: Option<Vec<String>>, // passed on from the client if given; any others are dropped
+    cache_secs: Option<u64>, // default 30
+    namespace: Option<String>, // schedules to match against; default gbnr
+    train_id_fields: Option<Vec<String>>, // fields holding our train ID, e.g. ["uid"] for Darwin
+    public_id_fields: Option<Vec<String>>, // or a headcode, only used if that's unambiguous
+    location_fields: Option<Vec<String>>, // holding a location ID or public ID, e.g. ["crs"]
+    date_field: Option<String>, // the train's date, YYYY-MM-DD, e.g. "sdd"
+    timezone: Option<Tz>, // for working out today, where there's no date; default Europe/London
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct ProxyConfig {
+    endpoints: Option<HashMap<String, ProxyEndpointConfig>>, // by name, as in /proxy/<name>/...
+    only: Option<bool>, // serve nothing but these and the status, for a deployment that's a proxy
+    max_cached: Option<usize>, // answers kept across all endpoints; default 1000
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LocalTrain {
+    pub global_id: GlobalTrainId,
+    pub namespace: String,
+    pub id: String,
+    pub date: NaiveDate,
+    pub public_id: Option<String>,
+    pub train_type: TrainType,
+    pub operator: Option<TrainOperator>,
+    pub origin: Option<String>,
+    pub destination: Option<String>,
+    pub source: Option<TrainSource>,
+    pub runs_as_required: bool,
+    pub modified: bool,
+    pub cancelled: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct LocalLocation {
+    pub namespace: String,
+    pub id: String,
+    pub name: String,
+    pub public_id: Option<String>,
+}
+
+struct Cached {
+    expires: Instant,
+    last_used: u64, // from Cache::clock
+    value: Value,
+}
+
+// Bounded, so clients asking for lots of different paths can't fill memory; the least recently
+// used answer goes first once it's full
+struct Cache {
+    entries: HashMap<String, Cached>, // by upstream URL
+    clock: u64,
+}
+
+pub struct Proxy {
+    endpoints: HashMap<String, ProxyEndpointConfig>,
+    only: bool,
+    max_cached: usize,
+    cache: Mutex<Cache>,
+}
+
+fn local_train(schedule: &Schedule, train_id: &str, date: NaiveDate) -> Option<LocalTrain> {
+    let (train, cancelled, modified) =
+        match get_train_instance(schedule.trains.get(train_id)?, date) {
+            (Some(x), y, z) => (x, y, z),
+            _ => return None,
+        };
+    Some(LocalTrain {
+        global_id: GlobalTrainId::new(&schedule.namespace, &train.id, date),
+        namespace: schedule.namespace.clone(),
+        id: train.id.clone(),
+        date,
+        public_id: train.variable_train.public_id.clone(),
+        train_type: train.variable_train.train_type,
+        operator: train.variable_train.operator.clone(),
+        origin: train.route.first().map(|x| x.id.to_string()),
+        destination: train.route.last().map(|x| x.id.to_string()),
+        source: train.source,
+        runs_as_required: train.runs_as_required,
+        modified,
+        cancelled,
+    })
+}
+
+fn local_location(schedule: &Schedule, id: &str) -> Option<LocalLocation> {
+    let location = match schedule.locations.get(id) {
+        Some(x) => x,
+        // only if the public ID is unambiguous
+        None => match schedule.locations_indexed_by_public_id.get(id) {
+            Some(x) if x.len() == 1 => schedule.locations.get(x.iter().next()?)?,
+            _ => return None,
+        },
+    };
+    Some(LocalLocation {
+        namespace: schedule.namespace.clone(),
+        id: location.id.clone(),
+        name: location.name.clone(),
+        public_id: location.public_id.clone(),
+    })
+}
+
+impl ProxyEndpointConfig {
+    // only the parameters upstream is meant to get, in a fixed order so the same ones in another
+    // order are cached together
+    fn query(&self, query: Option<&str>) -> Option<String> {
+        let allowed = self.query_params.as_deref().unwrap_or_default();
+        let mut params = query?
+            .split('&')
+            .filter(|x| {
+                let name = x.split('=').next().unwrap_or("");
+                allowed.iter().any(|y| y == name)
+            })
+            .collect::<Vec<_>>();
+        params.sort_unstable();
+        match params.is_empty() {
+            true => None,
+            false => Some(params.join("&")),
+        }
+    }
+
+    fn fields<'a>(
+        fields: &'a Option<Vec<String>>,
+        x: &'a serde_json::Map<String, Value>,
+    ) -> impl Iterator<Item = &'a str> {
+        fields.iter().flatten().filter_map(|y| x.get(y)?.as_str())
+    }
+
+    fn train(
+        &self,
+        schedule: &Schedule,
+        x: &serde_json::Map<String, Value>,
+        today: NaiveDate,
+    ) -> Option<LocalTrain> {
+        let date = match self.date_field.as_ref().and_then(|y| x.get(y)?.as_str()) {
+            Some(y) => NaiveDate::parse_from_str(y, "%Y-%m-%d").ok()?,
+            None => today,
+        };
+        for train_id in Self::fields(&self.train_id_fields, x) {
+            match local_train(schedule, train_id, date) {
+                Some(y) => return Some(y),
+                None => (),
+            }
+        }
+        for public_id in Self::fields(&self.public_id_fields, x) {
+            let mut running = schedule
+                .trains_indexed_by_public_id
+                .get(public_id)
+                .into_iter()
+                .flatten()
+                .filter_map(|y| local_train(schedule, y, date));
+            match (running.next(), running.next()) {
+                (Some(y), None) => return Some(y),
+                _ => (),
+            }
+        }
+        None
+    }
+
+    // goes all the way down, as upstream APIs tend to nest trains inside boards inside wrappers
+    fn enrich(&self, schedule: &Schedule, value: &mut Value, today: NaiveDate) {
+        match value {
+            Value::Array(x) => {
+                for item in x {
+                    self.enrich(schedule, item, today);
+                }
+            }
+            Value::Object(x) => {
+                for item in x.values_mut() {
+                    self.enrich(schedule, item, today);
+                }
+                match self.train(schedule, x, today) {
+                    Some(y) => {
+                        x.insert("local_train".to_string(), serde_json::to_value(y).unwrap());
+                    }
+                    None => (),
+                }
+                let location = Self::fields(&self.location_fields, x)
+                    .find_map(|y| local_location(schedule, y));
+                match location {
+                    Some(y) => {
+                        x.insert(
+                            "local_location".to_string(),
+                            serde_json::to_value(y).unwrap(),
+                        );
+                    }
+                    None => (),
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Proxy {
+    pub fn new(config: ProxyConfig) -> Self {
+        Self {
+            endpoints: config.endpoints.unwrap_or_default(),
+            only: config.only.unwrap_or(false),
+            max_cached: config.max_cached.unwrap_or(1000),
+            cache: Mutex::new(Cache {
+                entries: HashMap::new(),
+                clock: 0,
+            }),
+        }
+    }
+
+    // the URL a request to an endpoint is passed on to
+    pub fn upstream_url(&self, name: &str, path: &str, query: Option<&str>) -> Option<String> {
+        let endpoint = self.endpoints.get(name)?;
+        let url = endpoint.url.replace("{path}", path);
+        Some(match endpoint.query(query) {
+            Some(x) => format!("{}?{}", url, x),
+            None => url,
+        })
+    }
+
+    fn cached(&self, url: &str) -> Option<Value> {
+        let mut cache = self.cache.lock().unwrap();
+        cache.clock += 1;
+        let clock = cache.clock;
+        let cached = cache.entries.get_mut(url)?;
+        if cached.expires <= Instant::now() {
+            return None;
+        }
+        cached.last_used = clock;
+        Some(cached.value.clone())
+    }
+
+    fn cache(&self, url: String, ttl: Duration, value: Value) {
+        let mut cache = self.cache.lock().unwrap();
+        let now = Instant::now();
+        cache.entries.retain(|_, x| x.expires > now);
+        while cache.entries.len() >= self.max_cached && !cache.entries.contains_key(&url) {
+            let oldest = cache
+                .entries
+                .iter()
+                .min_by_key(|(_, x)| x.last_used)
+                .map(|(x, _)| x.clone());
+            match oldest {
+                Some(x) => cache.entries.remove(&x),
+                None => break,
+            };
+        }
+        if self.max_cached == 0 {
+            return;
+        }
+        cache.clock += 1;
+        let last_used = cache.clock;
+        cache.entries.insert(
+            url,
+            Cached {
+                expires: now + ttl,
+                last_used,
+                value,
+            },
+        );
+    }
+
+    pub fn cached_count(&self) -> usize {
+        self.cache.lock().unwrap().entries.len()
+    }
+
+    pub fn only(&self) -> bool {
+        self.only
+    }
+
+    // the namespace an endpoint's answers are matched against, if there's such an endpoint
+    pub fn namespace(&self, name: &str) -> Option<&str> {
+        Some(
+            self.endpoints
+                .get(name)?
+                .namespace
+                .as_deref()
+                .unwrap_or("gbnr"),
+        )
+    }
+
+    // What upstream says, from the cache if it's recent enough; None if there's no such endpoint
+    pub async fn fetch(
+        &self,
+        http_client: &HttpClient,
+        name: &str,
+        path: &str,
+        query: Option<&str>,
+    ) -> Result<Option<Value>, Error> {
+        let (endpoint, url) = match (
+            self.endpoints.get(name),
+            self.upstream_url(name, path, query),
+        ) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return Ok(None),
+        };
+        match self.cached(&url) {
+            Some(x) => return Ok(Some(x)),
+            None => (),
+        }
+
+        // not holding the lock while we wait on upstream
+        let response = http_client
+            .send(&format!("proxy-{}", name), || {
+                let mut request = http_client.client().get(&url);
+                for (header, value) in endpoint.headers.iter().flatten() {
+                    request = request.header(header, value);
+                }
+                request
+            })
+            .await?;
+        let value = serde_json::from_slice::<Value>(&response.bytes().await?)?;
+
+        let ttl = Duration::from_secs(endpoint.cache_secs.unwrap_or(30));
+        self.cache(url, ttl, value.clone());
+        Ok(Some(value))
+    }
+
+    // Adds local_train and local_location wherever something in an answer matches up with the
+    // schedule
+    pub fn enrich(&self, name: &str, schedule: &Schedule, value: &mut Value) {
+        let endpoint = match self.endpoints.get(name) {
+            Some(x) => x,
+            None => return,
+        };
+        let today = Utc::now()
+            .with_timezone(&endpoint.timezone.unwrap_or(London))
+            .date_naive();
+        endpoint.enrich(schedule, value, today);
+    }
+}
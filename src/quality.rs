@@ -0,0 +1,228 @@
+use crate::cold_routes::peek_train;
+use crate::schedule::{AssociationNode, Schedule, Train, TrainValidityPeriod};
+
+use chrono::{DateTime, Datelike, Utc};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::RwLock;
+
+// How good each namespace's data looks after an import, as a few percentages and a score made from
+// them. The last few imports' are kept, so a feed that's got worse shows up against the ones
+// before, and there's a warning when the score drops by much.
+
+#[derive(Clone, Default, Deserialize)]
+pub struct QualityConfig {
+    history: Option<usize>, // imports to keep reports for, per namespace; 10 if not given
+    warn_drop: Option<f64>, // score points lost since the import before worth a warning; 5 if not given
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QualityReport {
+    pub namespace: String,
+    pub computed: DateTime<Utc>,
+    pub last_imported: Option<DateTime<Utc>>,
+    pub schedules: usize, // every version of every train, overlays included
+    pub passenger_schedules: usize,
+    pub with_public_times: usize, // of the passenger ones
+    pub with_operator: usize,     // with a name for it, not only an ID
+    pub associations: usize,
+    pub valid_associations: usize, // with another train that's there and calls at the location
+    pub pending_associations: usize, // still waiting for the other train to turn up
+    pub overlays: usize,
+    pub orphaned_overlays: usize, // never apply, as what they replace doesn't run on any of their days
+    pub pending_overlays: usize,  // still waiting for what they replace to turn up
+    pub public_times_pct: Option<f64>,
+    pub operator_pct: Option<f64>,
+    pub valid_associations_pct: Option<f64>,
+    pub orphaned_overlays_pct: Option<f64>,
+    pub score: Option<f64>, // 0-100: the mean of the percentages, orphaned overlays counting against
+}
+
+fn percentage(count: usize, total: usize) -> Option<f64> {
+    match total {
+        0 => None,
+        _ => Some(count as f64 * 100.0 / total as f64),
+    }
+}
+
+// whether any day of one validity is also a day of the other
+fn overlaps(a: &TrainValidityPeriod, b: &TrainValidityPeriod) -> bool {
+    let begin = std::cmp::max(a.valid_begin.date_naive(), b.valid_begin.date_naive());
+    let end = std::cmp::min(a.valid_end.date_naive(), b.valid_end.date_naive());
+    // a week has every day in it, so there's no need to look further
+    begin
+        .iter_days()
+        .take_while(|x| *x <= end)
+        .take(7)
+        .any(|x| {
+            a.days_of_week.get_by_weekday(x.weekday()) && b.days_of_week.get_by_weekday(x.weekday())
+        })
+}
+
+fn is_valid_association(
+    schedule: &Schedule,
+    location_id: &str,
+    association: &AssociationNode,
+) -> bool {
+    match schedule.trains.get(association.other_train_id.as_str()) {
+        Some(x) => x
+            .iter()
+            .flat_map(|y| std::iter::once(y).chain(y.replacements.iter()))
+            .any(|y| y.route.iter().any(|z| z.id.as_str() == location_id)),
+        None => false,
+    }
+}
+
+fn count_schedule(report: &mut QualityReport, schedule: &Schedule, train: &Train) {
+    report.schedules += 1;
+    let train_type = train.variable_train.train_type;
+    if !train_type.is_freight() && !train_type.is_empty_stock() {
+        report.passenger_schedules += 1;
+        if train
+            .route
+            .iter()
+            .any(|x| x.public_arr.is_some() || x.public_dep.is_some())
+        {
+            report.with_public_times += 1;
+        }
+    }
+    match &train.variable_train.operator {
+        Some(x) if x.description.is_some() => report.with_operator += 1,
+        _ => (),
+    }
+    for location in &train.route {
+        for association in location
+            .divides_to_form
+            .iter()
+            .chain(location.joins_to.iter())
+            .chain(location.becomes.iter())
+        {
+            report.associations += 1;
+            if is_valid_association(schedule, location.id.as_str(), association) {
+                report.valid_associations += 1;
+            }
+        }
+    }
+}
+
+pub fn quality_report(schedule: &Schedule) -> QualityReport {
+    let mut report = QualityReport {
+        namespace: schedule.namespace.clone(),
+        computed: Utc::now(),
+        last_imported: schedule.last_imported,
+        schedules: 0,
+        passenger_schedules: 0,
+        with_public_times: 0,
+        with_operator: 0,
+        associations: 0,
+        valid_associations: 0,
+        pending_associations: schedule
+            .pending_associations
+            .values()
+            .map(|x| x.len())
+            .sum(),
+        overlays: 0,
+        orphaned_overlays: 0,
+        pending_overlays: schedule.pending_overlays.values().map(|x| x.len()).sum(),
+        public_times_pct: None,
+        operator_pct: None,
+        valid_associations_pct: None,
+        orphaned_overlays_pct: None,
+        score: None,
+    };
+    for trains in schedule.trains.values() {
+        for train in trains {
+            let train = peek_train(train);
+            count_schedule(&mut report, schedule, &train);
+            for replacement in &train.replacements {
+                count_schedule(&mut report, schedule, replacement);
+                report.overlays += 1;
+                if !replacement
+                    .validity
+                    .iter()
+                    .any(|x| train.validity.iter().any(|y| overlaps(x, y)))
+                {
+                    report.orphaned_overlays += 1;
+                }
+            }
+        }
+    }
+
+    report.public_times_pct = percentage(report.with_public_times, report.passenger_schedules);
+    report.operator_pct = percentage(report.with_operator, report.schedules);
+    report.valid_associations_pct = percentage(report.valid_associations, report.associations);
+    report.orphaned_overlays_pct = percentage(report.orphaned_overlays, report.overlays);
+    let scores = [
+        report.public_times_pct,
+        report.operator_pct,
+        report.valid_associations_pct,
+        report.orphaned_overlays_pct.map(|x| 100.0 - x),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    if !scores.is_empty() {
+        report.score = Some(scores.iter().sum::<f64>() / scores.len() as f64);
+    }
+    report
+}
+
+pub struct Quality {
+    config: QualityConfig,
+    reports: RwLock<HashMap<String, VecDeque<QualityReport>>>, // by namespace, oldest first
+}
+
+impl Quality {
+    pub fn new(config: QualityConfig) -> Self {
+        Self {
+            config,
+            reports: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // after each import, so it's compared with the one before
+    pub fn record(&self, schedule: &Schedule) -> QualityReport {
+        let report = quality_report(schedule);
+        let warn_drop = self.config.warn_drop.unwrap_or(5.0);
+        let mut reports = self.reports.write().unwrap();
+        let history = reports.entry(schedule.namespace.clone()).or_default();
+        match (history.back().and_then(|x| x.score), report.score) {
+            (Some(before), Some(after)) if before - after >= warn_drop => println!(
+                "WARNING: Data quality score for {} dropped from {:.1} to {:.1}",
+                schedule.namespace, before, after
+            ),
+            _ => (),
+        }
+        history.push_back(report.clone());
+        while history.len() > self.config.history.unwrap_or(10) {
+            history.pop_front();
+        }
+        report
+    }
+
+    // Each namespace's reports, oldest first. One is worked out now for any schedule nothing has
+    // been imported into since startup, e.g. after restoring a snapshot.
+    pub fn reports<'a>(
+        &self,
+        schedules: impl IntoIterator<Item = &'a Schedule>,
+    ) -> BTreeMap<String, Vec<QualityReport>> {
+        for schedule in schedules {
+            if !self
+                .reports
+                .read()
+                .unwrap()
+                .contains_key(&schedule.namespace)
+            {
+                self.record(schedule);
+            }
+        }
+        self.reports
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(namespace, reports)| (namespace.clone(), reports.iter().cloned().collect()))
+            .collect()
+    }
+}
@@ -0,0 +1,90 @@
+use serde::Serialize;
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Caches the results of expensive queries (departure boards etc.) until either the TTL expires or
+// the schedules they were made from change, whichever comes first. Each is kept with the
+// generation it was made from, which is whatever the caller says covers everything it read, e.g.
+// the namespace's for a board in it.
+pub struct QueryCache<V: Clone> {
+    entries: Mutex<HashMap<String, (Instant, u64, V)>>, // when each was made, and from which generation
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct QueryCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: Option<f64>,
+    pub entries: usize,
+    pub generation: u64, // the newest anything in here was made from
+    pub ttl_secs: u64,
+}
+
+impl<V: Clone> QueryCache<V> {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get_or_insert_with<F>(&self, key: String, generation: u64, f: F) -> V
+    where
+        F: FnOnce() -> V,
+    {
+        {
+            let entries = self.entries.lock().unwrap();
+            match entries.get(&key) {
+                Some((inserted, x, value)) if *x == generation && inserted.elapsed() < self.ttl => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return value.clone();
+                }
+                _ => (),
+            }
+        }
+
+        // don't hold the lock while computing, or every other request would queue behind us
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let value = f();
+
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        let ttl = self.ttl;
+        entries.retain(|_, (inserted, _, _)| now - *inserted < ttl);
+        match entries.get(&key) {
+            // someone else got there first, from a newer schedule than ours
+            Some((_, x, _)) if *x > generation => (),
+            _ => {
+                entries.insert(key, (now, generation, value.clone()));
+            }
+        }
+
+        value
+    }
+
+    pub fn stats(&self) -> QueryCacheStats {
+        let entries = self.entries.lock().unwrap();
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        QueryCacheStats {
+            hits,
+            misses,
+            hit_rate: if hits + misses == 0 {
+                None
+            } else {
+                Some(hits as f64 / (hits + misses) as f64)
+            },
+            entries: entries.len(),
+            generation: entries.values().map(|(_, x, _)| *x).max().unwrap_or(0),
+            ttl_secs: self.ttl.as_secs(),
+        }
+    }
+}
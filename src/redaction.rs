@@ -0,0 +1,201 @@
+use crate::environments;
+use crate::schedule::TrainType;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use std::collections::HashMap;
+
+// What to keep from the public on a deployment that anyone can reach. Everything is shown unless
+// turned off here.
+#[derive(Clone, Default, Deserialize)]
+pub struct RedactionPolicy {
+    working_times: Option<bool>, // leave out working times and days, keeping public ones
+    allowances: Option<bool>,    // engineering, pathing and performance allowances
+    freight: Option<bool>,       // leave out freight trains and light engines altogether
+    staff_trains: Option<bool>,  // and empty stock and staff trains
+    non_passenger: Option<bool>, // every train passengers can't travel on, e.g. parcels too
+    passing_calls: Option<bool>, // and calls passengers can't use, including passing points
+    activities: Option<bool>,    // operational activities, keeping those for passengers
+    fields: Option<Vec<String>>, // anything else, by field name wherever it turns up
+}
+
+// what public mode hides: everything that isn't for passengers
+const PUBLIC_POLICY: RedactionPolicy = RedactionPolicy {
+    working_times: Some(true),
+    allowances: Some(true),
+    freight: Some(true),
+    staff_trains: Some(true),
+    non_passenger: Some(true),
+    passing_calls: Some(true),
+    activities: Some(true),
+    fields: None,
+};
+
+// the activities that mean something to passengers, which are kept when the others aren't
+const PASSENGER_ACTIVITIES: &[&str] = &[
+    "normal_passenger_stop",
+    "train_begins",
+    "train_finishes",
+    "set_down_only",
+    "pick_up_only",
+    "request_pick_up",
+    "request_set_down",
+    "request_pick_up_by_telephone",
+    "request_set_down_by_telephone",
+];
+
+#[derive(Clone, Default, Deserialize)]
+pub struct RedactionConfig {
+    tokens: Option<Vec<String>>, // sent as "Authorization: Bearer <token>" to see everything
+    public_mode: Option<bool>, // hide everything that isn't for passengers from endpoints not listed
+    default: Option<RedactionPolicy>, // for endpoints not listed, if not in public mode
+    endpoints: Option<HashMap<String, RedactionPolicy>>, // by first part of the path, e.g. "service"
+}
+
+pub struct Redaction {
+    tokens: Vec<String>,
+    public_mode: bool,
+    default: Option<RedactionPolicy>,
+    endpoints: HashMap<String, RedactionPolicy>,
+}
+
+fn is_staff_train(train_type: &TrainType) -> bool {
+    train_type.is_empty_stock() || matches!(train_type, TrainType::Staff)
+}
+
+fn is_passenger_train(train_type: &TrainType) -> bool {
+    !train_type.is_freight()
+        && !is_staff_train(train_type)
+        && !matches!(
+            train_type,
+            TrainType::UnadvertisedPassenger
+                | TrainType::UnadvertisedExpressPassenger
+                | TrainType::Post
+                | TrainType::Parcels
+        )
+}
+
+impl RedactionPolicy {
+    fn hides_field(&self, key: &str) -> bool {
+        (self.working_times.unwrap_or(false) && key.starts_with("working_"))
+            || (self.allowances.unwrap_or(false) && key.ends_with("_allowance_s"))
+            || self
+                .fields
+                .as_ref()
+                .is_some_and(|x| x.iter().any(|y| y == key))
+    }
+
+    // Trains come out either with their own train_type, as on location boards, or with it inside
+    // variable_train, as for a whole service.
+    fn hides_train(&self, x: &serde_json::Map<String, Value>) -> bool {
+        let train_type = match x
+            .get("train_type")
+            .or_else(|| x.get("variable_train")?.get("train_type"))
+        {
+            Some(x) => x,
+            None => return false,
+        };
+        let train_type = match serde_json::from_value::<TrainType>(train_type.clone()) {
+            Ok(x) => x,
+            Err(_) => return false,
+        };
+        (self.freight.unwrap_or(false) && train_type.is_freight())
+            || (self.staff_trains.unwrap_or(false) && is_staff_train(&train_type))
+            || (self.non_passenger.unwrap_or(false) && !is_passenger_train(&train_type))
+    }
+
+    // A call passengers can't use. One from a whole service says so in its flags; one straight
+    // from a schedule just has no public times.
+    fn hides_call(&self, x: &serde_json::Map<String, Value>) -> bool {
+        if !self.passing_calls.unwrap_or(false) {
+            return false;
+        }
+        match x.get("flags").and_then(|x| x.get("public")) {
+            Some(Value::Bool(public)) => !public,
+            _ => {
+                x.contains_key("working_pass")
+                    && x.get("public_arr").map_or(true, Value::is_null)
+                    && x.get("public_dep").map_or(true, Value::is_null)
+            }
+        }
+    }
+
+    // false if the whole response is about something that shouldn't be seen
+    pub fn apply(&self, value: &mut Value) -> bool {
+        self.redact(value, false)
+    }
+
+    // The same for what a page is rendered from. Fields are nulled rather than left out, as the
+    // templates test and compare them without checking they're there.
+    pub fn apply_to_page(&self, value: &mut Value) -> bool {
+        self.redact(value, true)
+    }
+
+    fn redact(&self, value: &mut Value, null_fields: bool) -> bool {
+        match value {
+            Value::Array(x) => {
+                x.retain_mut(|item| self.redact(item, null_fields));
+                true
+            }
+            Value::Object(x) => {
+                if self.hides_train(x) || self.hides_call(x) {
+                    return false;
+                }
+                match null_fields {
+                    true => x
+                        .iter_mut()
+                        .filter(|(key, _)| self.hides_field(key))
+                        .for_each(|(_, item)| *item = Value::Null),
+                    false => x.retain(|key, _| !self.hides_field(key)),
+                }
+                match x.get_mut("activities") {
+                    Some(Value::Object(activities)) if self.activities.unwrap_or(false) => {
+                        activities.retain(|key, _| PASSENGER_ACTIVITIES.contains(&key.as_str()))
+                    }
+                    _ => (),
+                }
+                match x.get_mut("activity_notes") {
+                    Some(Value::Array(notes)) if self.activities.unwrap_or(false) => {
+                        notes.retain(|note| note["category"] == "Passenger")
+                    }
+                    _ => (),
+                }
+                for (_, item) in x.iter_mut() {
+                    // a hidden train inside something else, e.g. an association, just goes null
+                    if !self.redact(item, null_fields) {
+                        *item = Value::Null;
+                    }
+                }
+                true
+            }
+            _ => true,
+        }
+    }
+}
+
+impl Redaction {
+    pub fn new(config: RedactionConfig) -> Self {
+        Self {
+            tokens: config.tokens.unwrap_or_default(),
+            public_mode: config.public_mode.unwrap_or(false),
+            default: config.default,
+            endpoints: config.endpoints.unwrap_or_default(),
+        }
+    }
+
+    // for operational users to be let through
+    pub fn tokens(&self) -> &[String] {
+        &self.tokens
+    }
+
+    pub fn policy(&self, path: &str) -> Option<&RedactionPolicy> {
+        let (_, path) = environments::split_path(path);
+        let endpoint = path.trim_start_matches('/').split('/').next().unwrap_or("");
+        match self.endpoints.get(endpoint) {
+            Some(x) => Some(x),
+            None if self.public_mode => Some(&PUBLIC_POLICY),
+            None => self.default.as_ref(),
+        }
+    }
+}
@@ -0,0 +1,256 @@
+use crate::error::Error;
+use crate::schedule::{Coordinate, Schedule};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+// Which country, and which region of it, each location is in, for filtering by ("departures from
+// stations in Wales") and counting by. Hardly any feed says, so it's from reference data in the
+// config where there is some, otherwise from where the location is against boundaries loaded from
+// GeoJSON (e.g. Natural Earth's admin-1 areas), otherwise the namespace's country. Testing points
+// against polygons is slow, so each location's answer is kept until it moves.
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Region {
+    pub country: Option<String>, // ISO 3166-1 alpha-2, e.g. "GB"
+    pub region: Option<String>,  // as the boundary files name it, e.g. "Wales"
+}
+
+impl Region {
+    // either may be left out, and case doesn't matter
+    pub fn matches(&self, country: Option<&str>, region: Option<&str>) -> bool {
+        let matches = |x: &Option<String>, y: Option<&str>| match y {
+            Some(y) => x.as_ref().is_some_and(|x| x.eq_ignore_ascii_case(y)),
+            None => true,
+        };
+        matches(&self.country, country) && matches(&self.region, region)
+    }
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct RegionsConfig {
+    boundary_files: Option<Vec<String>>, // earlier files win where areas overlap
+    locations: Option<HashMap<String, HashMap<String, Region>>>, // by namespace, then location ID
+    countries: Option<HashMap<String, String>>, // by namespace, for anything nothing else places
+}
+
+// Polygon and MultiPolygon features with "country" and/or "region" properties; anything else is
+// ignored
+#[derive(Deserialize)]
+struct FeatureCollection {
+    features: Vec<Feature>,
+}
+
+#[derive(Deserialize)]
+struct Feature {
+    geometry: Option<Geometry>,
+    properties: Option<Region>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum Geometry {
+    Polygon {
+        coordinates: Vec<Vec<Vec<f64>>>,
+    },
+    MultiPolygon {
+        coordinates: Vec<Vec<Vec<Vec<f64>>>>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+struct Boundary {
+    region: Region,
+    polygons: Vec<Vec<Vec<Coordinate>>>, // each is its outer ring, then any holes
+    west: f64,
+    south: f64,
+    east: f64,
+    north: f64,
+}
+
+fn read_ring(ring: &[Vec<f64>]) -> Vec<Coordinate> {
+    ring.iter()
+        .filter_map(|x| match x[..] {
+            [longitude, latitude, ..] => Some(Coordinate {
+                latitude,
+                longitude,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+// even-odd rule, treating degrees as flat, which is fine away from the poles
+fn ring_contains(ring: &[Coordinate], point: &Coordinate) -> bool {
+    let mut inside = false;
+    for (i, a) in ring.iter().enumerate() {
+        let b = &ring[(i + ring.len() - 1) % ring.len()];
+        if (a.latitude > point.latitude) != (b.latitude > point.latitude)
+            && point.longitude
+                < (b.longitude - a.longitude) * (point.latitude - a.latitude)
+                    / (b.latitude - a.latitude)
+                    + a.longitude
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+impl Boundary {
+    fn new(region: Region, polygons: Vec<Vec<Vec<Coordinate>>>) -> Option<Self> {
+        let points = polygons.iter().filter_map(|x| x.first()).flatten();
+        let mut boundary = Self {
+            region,
+            polygons: vec![],
+            west: f64::MAX,
+            south: f64::MAX,
+            east: f64::MIN,
+            north: f64::MIN,
+        };
+        for point in points {
+            boundary.west = boundary.west.min(point.longitude);
+            boundary.south = boundary.south.min(point.latitude);
+            boundary.east = boundary.east.max(point.longitude);
+            boundary.north = boundary.north.max(point.latitude);
+        }
+        boundary.polygons = polygons;
+        match boundary.west <= boundary.east {
+            true => Some(boundary),
+            false => None,
+        }
+    }
+
+    fn contains(&self, point: &Coordinate) -> bool {
+        if point.longitude < self.west
+            || point.longitude > self.east
+            || point.latitude < self.south
+            || point.latitude > self.north
+        {
+            return false;
+        }
+        self.polygons
+            .iter()
+            .any(|polygon| match polygon.split_first() {
+                Some((outer, holes)) => {
+                    ring_contains(outer, point) && !holes.iter().any(|x| ring_contains(x, point))
+                }
+                None => false,
+            })
+    }
+}
+
+pub struct Regions {
+    boundaries: Vec<Boundary>,
+    locations: HashMap<String, HashMap<String, Region>>,
+    countries: HashMap<String, String>,
+    // by namespace then location ID, with the position it was worked out from
+    cache: RwLock<HashMap<String, HashMap<String, (Option<Coordinate>, Region)>>>,
+}
+
+impl Regions {
+    pub fn new(config: RegionsConfig) -> Result<Self, Error> {
+        let mut boundaries = vec![];
+        for filename in config.boundary_files.unwrap_or_default() {
+            println!("Loading region boundaries from {}", filename);
+            let contents = std::fs::read_to_string(&filename)?;
+            let collection = serde_json::from_str::<FeatureCollection>(&contents)?;
+            let before = boundaries.len();
+            for feature in collection.features {
+                let polygons = match feature.geometry {
+                    Some(Geometry::Polygon { coordinates }) => vec![coordinates],
+                    Some(Geometry::MultiPolygon { coordinates }) => coordinates,
+                    _ => continue,
+                };
+                let polygons = polygons
+                    .iter()
+                    .map(|x| x.iter().map(|y| read_ring(y)).collect::<Vec<_>>())
+                    .collect::<Vec<_>>();
+                match Boundary::new(feature.properties.unwrap_or_default(), polygons) {
+                    Some(x) => boundaries.push(x),
+                    None => println!("WARNING: Empty boundary in {}", filename),
+                }
+            }
+            println!("Loaded {} boundaries", boundaries.len() - before);
+        }
+        Ok(Self {
+            boundaries,
+            locations: config.locations.unwrap_or_default(),
+            countries: config.countries.unwrap_or_default(),
+            cache: RwLock::new(HashMap::new()),
+        })
+    }
+
+    fn within_boundaries(&self, position: &Coordinate) -> Region {
+        let mut region = Region::default();
+        for boundary in self.boundaries.iter().filter(|x| x.contains(position)) {
+            if region.country.is_none() {
+                region.country = boundary.region.country.clone();
+            }
+            if region.region.is_none() {
+                region.region = boundary.region.region.clone();
+            }
+            if region.country.is_some() && region.region.is_some() {
+                break;
+            }
+        }
+        region
+    }
+
+    pub fn locate(&self, schedule: &Schedule, location_id: &str) -> Region {
+        match self
+            .locations
+            .get(&schedule.namespace)
+            .and_then(|x| x.get(location_id))
+        {
+            Some(x) => return x.clone(),
+            None => (),
+        }
+        let position = schedule.locations.get(location_id).and_then(|x| x.position);
+        match self
+            .cache
+            .read()
+            .unwrap()
+            .get(&schedule.namespace)
+            .and_then(|x| x.get(location_id))
+        {
+            Some((cached_position, region)) if *cached_position == position => {
+                return region.clone()
+            }
+            _ => (),
+        }
+
+        let mut region = match &position {
+            Some(x) => self.within_boundaries(x),
+            None => Region::default(),
+        };
+        if region.country.is_none() {
+            region.country = self.countries.get(&schedule.namespace).cloned();
+        }
+        self.cache
+            .write()
+            .unwrap()
+            .entry(schedule.namespace.clone())
+            .or_default()
+            .insert(location_id.to_string(), (position, region.clone()));
+        region
+    }
+
+    // every location in the schedule in the country and/or region
+    pub fn locations_in(
+        &self,
+        schedule: &Schedule,
+        country: Option<&str>,
+        region: Option<&str>,
+    ) -> Vec<String> {
+        schedule
+            .locations
+            .keys()
+            .filter(|x| self.locate(schedule, x).matches(country, region))
+            .cloned()
+            .collect()
+    }
+}
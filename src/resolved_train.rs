@@ -0,0 +1,273 @@
+use crate::delay_propagation::{expected_times, ExpectedTimes};
+use crate::intern::IStr;
+use crate::running_days::running_days_text;
+use crate::schedule::{
+    get_association, get_cancellation, get_train_instance, name_train, Activities, ActivityNote,
+    AssociationCategory, AssociationNode, LocalTimes, PassengerFlags, Schedule, Train,
+    TrainCancellation, TrainLocation, TrainRealtime, TrainSource, VariableTrain,
+};
+use crate::segments::{route_segments, RouteSegment};
+use crate::train_id::GlobalTrainId;
+
+use chrono::{Duration, NaiveDate};
+
+use serde::Serialize;
+
+// One train on one date, with everything the schedule leaves to whoever reads it already worked
+// out: which version runs, whether it's cancelled (as planned, or by realtime), its times as real
+// datetimes, what applies between each change en route, and which trains it's associated with on
+// the day. Endpoints and exports start from this rather than from the versions themselves, so
+// they all agree on what a train is doing.
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ResolvedCall {
+    pub id: IStr,
+    pub id_suffix: Option<String>,
+    #[serde(flatten)]
+    pub times: LocalTimes,
+    pub expected: Option<ExpectedTimes>, // from realtime reports, where there are any
+    pub platform: Option<IStr>,          // as timetabled
+    pub live_platform: Option<IStr>,     // where it's been reported at, if that's been sent
+    pub line: Option<IStr>,
+    pub path: Option<IStr>,
+    pub engineering_allowance_s: Option<u32>,
+    pub pathing_allowance_s: Option<u32>,
+    pub performance_allowance_s: Option<u32>,
+    pub activities: Activities,
+    pub activity_notes: Vec<ActivityNote>,
+    pub flags: PassengerFlags,
+    pub segment: usize, // which of segments applies here
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ResolvedAssociation {
+    pub category: AssociationCategory, // from this train's point of view
+    pub location_id: IStr,
+    pub location_suffix: Option<String>,
+    pub train_id: String,
+    pub date: NaiveDate, // the other train's operating date
+    pub for_passengers: bool,
+    pub running_days: String, // of the association, which can be less often than either train
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ResolvedTrain {
+    pub global_id: GlobalTrainId,
+    pub namespace: String,
+    pub id: String,
+    pub date: NaiveDate, // the operating date, which is what identifies it
+    pub departure_date: NaiveDate, // the day after date for a train timetabled past midnight
+    pub source: Option<TrainSource>, // which variant actually runs: LTP base, STP overlay or VSTP
+    pub modified: bool,  // an overlay applies on this date
+    pub cancelled: bool, // including partway through the journey
+    pub cancelled_en_route: bool, // only partway, so it still calls before that
+    pub cancellation_reason: Option<String>,
+    pub runs_as_required: bool,
+    pub variable_train: VariableTrain, // at the origin, with the day's consist if we have one
+    pub segments: Vec<RouteSegment>,   // split wherever variable_train changes en route
+    pub route: Vec<ResolvedCall>,
+    pub associations: Vec<ResolvedAssociation>,
+    pub realtime: Option<TrainRealtime>,
+    #[serde(skip)]
+    pub train: Train, // the version that runs, with live platforms, for anything not above
+}
+
+// Why a train isn't running on a date, if anyone has told us. What TRUST says on the day beats
+// whatever came with the planned cancellation.
+pub fn cancellation_reason(
+    versions: &[Train],
+    realtime: Option<&TrainRealtime>,
+    date: NaiveDate,
+) -> Option<String> {
+    match realtime.and_then(|x| x.cancellation.as_ref()) {
+        Some(x) if x.reason.is_some() => return x.reason.clone(),
+        _ => (),
+    }
+    versions
+        .iter()
+        .find_map(|train| get_cancellation(train, date)?.reason.clone())
+}
+
+// From the association as a whole rather than any one-off replacement, over all its periods
+pub fn association_running_days(assoc: &AssociationNode, date: NaiveDate) -> String {
+    let cancellations = assoc
+        .cancellations
+        .iter()
+        .map(|(validity, source)| TrainCancellation {
+            validity: validity.clone(),
+            source: *source,
+            reason: None,
+        })
+        .collect::<Vec<_>>();
+    running_days_text(&assoc.validity, &cancellations, date)
+}
+
+// those at one location that apply on the date, in the order the location has them
+fn location_associations(location: &TrainLocation, date: NaiveDate) -> Vec<ResolvedAssociation> {
+    let categories = location
+        .divides_to_form
+        .iter()
+        .map(|x| (AssociationCategory::Divide, x))
+        .chain(
+            location
+                .joins_to
+                .iter()
+                .map(|x| (AssociationCategory::Join, x)),
+        )
+        .chain(
+            location
+                .divides_from
+                .iter()
+                .map(|x| (AssociationCategory::DividesFrom, x)),
+        )
+        .chain(
+            location
+                .is_joined_to_by
+                .iter()
+                .map(|x| (AssociationCategory::IsJoinedToBy, x)),
+        )
+        .chain(
+            location
+                .becomes
+                .iter()
+                .map(|x| (AssociationCategory::Next, x)),
+        )
+        .chain(
+            location
+                .forms_from
+                .iter()
+                .map(|x| (AssociationCategory::FormsFrom, x)),
+        );
+    categories
+        .filter_map(|(category, assoc)| {
+            let applying = get_association(assoc, date)?;
+            Some(ResolvedAssociation {
+                category,
+                location_id: location.id.clone(),
+                location_suffix: location.id_suffix.clone(),
+                train_id: applying.other_train_id.to_string(),
+                date: date + Duration::days(applying.day_diff.into()),
+                for_passengers: applying.for_passengers,
+                running_days: association_running_days(assoc, date),
+            })
+        })
+        .collect()
+}
+
+// None if it doesn't run on the date, or its route goes somewhere we don't know the time zone of
+pub fn resolve_train(
+    schedule: &Schedule,
+    train_id: &str,
+    date: NaiveDate,
+) -> Option<ResolvedTrain> {
+    let versions = schedule.trains.get(train_id)?;
+    let (train, cancelled, modified) = get_train_instance(versions, date);
+    let mut train = train?;
+    let realtime = schedule
+        .realtime
+        .get(train_id)
+        .and_then(|x| x.get(&date))
+        .cloned();
+    match &realtime {
+        Some(x) => x.apply_platforms(&mut train.route),
+        None => (),
+    }
+    // what's actually making up a freight train today, where we've been told
+    match schedule.consists.get(train_id).and_then(|x| x.get(&date)) {
+        Some(x) => train.variable_train.actual_allocation = Some(x.clone()),
+        None => (),
+    }
+    name_train(&schedule.train_names, &mut train, date);
+
+    let realtime_cancellation = realtime.as_ref().and_then(|x| x.cancellation.as_ref());
+    let cancelled_en_route = !cancelled
+        && realtime_cancellation
+            .is_some_and(|x| x.cancellation_type.as_deref() == Some("EN ROUTE"));
+    let cancelled = cancelled || realtime_cancellation.is_some();
+    let cancellation_reason = match cancelled {
+        true => cancellation_reason(versions, realtime.as_ref(), date),
+        false => None,
+    };
+
+    let planned = train
+        .route
+        .iter()
+        .map(|x| {
+            let timezone = schedule
+                .locations
+                .get(x.id.as_str())
+                .map(|y| y.timezone)
+                .or(x.timing_tz)?;
+            Some(x.local_times(date, timezone))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let expected = match &realtime {
+        Some(x) => expected_times(x, &train.route, &planned),
+        None => vec![None; train.route.len()],
+    };
+
+    let segments = route_segments(&train);
+    let mut segment = 0;
+    let mut route = vec![];
+    let mut associations = vec![];
+    for ((location, times), expected) in train.route.iter().zip(planned).zip(expected) {
+        match segments.get(segment + 1) {
+            Some(x)
+                if location.change_en_route.is_some()
+                    && x.from == location.id
+                    && x.from_suffix == location.id_suffix =>
+            {
+                segment += 1
+            }
+            _ => (),
+        }
+        associations.extend(location_associations(location, date));
+        route.push(ResolvedCall {
+            id: location.id.clone(),
+            id_suffix: location.id_suffix.clone(),
+            times,
+            expected,
+            platform: location.scheduled_platform.clone(),
+            live_platform: location.live_platform.clone(),
+            line: location.line.clone(),
+            path: location.path.clone(),
+            engineering_allowance_s: location.engineering_allowance_s,
+            pathing_allowance_s: location.pathing_allowance_s,
+            performance_allowance_s: location.performance_allowance_s,
+            activities: location.activities.clone(),
+            activity_notes: location.activities.notes(),
+            flags: location.passenger_flags(),
+            segment,
+        });
+    }
+
+    Some(ResolvedTrain {
+        global_id: GlobalTrainId::new(&schedule.namespace, &train.id, date),
+        namespace: schedule.namespace.clone(),
+        id: train.id.clone(),
+        date,
+        departure_date: train.departure_date(date).unwrap_or(date),
+        source: train.source,
+        modified,
+        cancelled,
+        cancelled_en_route,
+        cancellation_reason,
+        runs_as_required: train.runs_as_required,
+        variable_train: train.variable_train.clone(),
+        segments,
+        route,
+        associations,
+        realtime,
+        train,
+    })
+}
+
+impl ResolvedTrain {
+    // what applies at a call, after any change en route before it
+    pub fn variable_train_at(&self, call: &ResolvedCall) -> &VariableTrain {
+        match self.segments.get(call.segment) {
+            Some(x) => &x.variable_train,
+            None => &self.variable_train,
+        }
+    }
+}
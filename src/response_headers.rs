@@ -0,0 +1,166 @@
+use crate::environments;
+
+use serde::Deserialize;
+
+use sha2::{Digest, Sha256};
+
+use std::collections::HashMap;
+
+// CORS and caching headers for the web UI's responses, so browser frontends can call the API
+// straight from another origin, and caches in between can keep hold of a board until the
+// schedules change under it. Both can be set for everything, then differently for particular
+// endpoints, by the first part of the path as for redaction (after any /env/<name>/, so other
+// environments get the same).
+//
+// ETags are the generation of the namespace the response was made from (or of all of them, for
+// responses that aren't for just one), which moves on with every import or realtime update to it,
+// along with a hash of the response itself, as some responses (e.g. next trains, or alerts) change
+// with the time without the schedules changing at all.
+
+#[derive(Clone, Default, Deserialize)]
+pub struct CorsPolicy {
+    allowed_origins: Option<Vec<String>>, // e.g. "https://example.com", or "*" for any; default none
+    allowed_headers: Option<Vec<String>>, // in requests; default as below
+    allow_credentials: Option<bool>,      // let browsers send cookies and the like; default false
+    max_age_secs: Option<u64>,            // for browsers to remember a preflight; default 3600
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct CachePolicy {
+    max_age_secs: Option<u64>, // sent as Cache-Control; default none, so caches must check back
+    shared: Option<bool>,      // let shared caches keep it, unless it was for a token; default true
+    etag: Option<bool>,        // send ETags and answer If-None-Match; default true
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct EndpointHeaders {
+    cors: Option<CorsPolicy>,     // instead of the default one
+    caching: Option<CachePolicy>, // likewise
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct ResponseHeadersConfig {
+    cors: Option<CorsPolicy>,
+    caching: Option<CachePolicy>,
+    endpoints: Option<HashMap<String, EndpointHeaders>>, // by first part of the path, e.g. "location"
+}
+
+const DEFAULT_ALLOWED_HEADERS: &[&str] =
+    &["Accept", "Authorization", "If-None-Match", "X-Environment"];
+
+// what browsers' scripts get to read, beyond the handful they always can
+const EXPOSED_HEADERS: &str = "ETag, Link, X-Environment";
+
+pub struct ResponseHeaders {
+    cors: Option<CorsPolicy>,
+    caching: CachePolicy,
+    endpoints: HashMap<String, EndpointHeaders>,
+}
+
+fn endpoint(path: &str) -> &str {
+    let (_, path) = environments::split_path(path);
+    path.trim_start_matches('/').split('/').next().unwrap_or("")
+}
+
+impl ResponseHeaders {
+    pub fn new(config: ResponseHeadersConfig) -> Self {
+        Self {
+            cors: config.cors,
+            caching: config.caching.unwrap_or_default(),
+            endpoints: config.endpoints.unwrap_or_default(),
+        }
+    }
+
+    fn cors_policy(&self, path: &str) -> Option<&CorsPolicy> {
+        match self.endpoints.get(endpoint(path)) {
+            Some(EndpointHeaders { cors: Some(x), .. }) => Some(x),
+            _ => self.cors.as_ref(),
+        }
+    }
+
+    fn cache_policy(&self, path: &str) -> &CachePolicy {
+        match self.endpoints.get(endpoint(path)) {
+            Some(EndpointHeaders {
+                caching: Some(x), ..
+            }) => x,
+            _ => &self.caching,
+        }
+    }
+
+    // The CORS headers for a request from the given origin, if it's let in at all. A preflight also
+    // gets told what it can send.
+    pub fn cors(&self, path: &str, origin: &str, preflight: bool) -> Vec<(&'static str, String)> {
+        let policy = match self.cors_policy(path) {
+            Some(x) => x,
+            None => return vec![],
+        };
+        let allowed_origins = policy.allowed_origins.as_deref().unwrap_or_default();
+        let credentials = policy.allow_credentials.unwrap_or(false);
+        let any = allowed_origins.iter().any(|x| x == "*");
+        if !any && !allowed_origins.iter().any(|x| x == origin) {
+            return vec![];
+        }
+        // browsers won't send credentials to "*", so those get their own origin back
+        let mut headers = vec![(
+            "Access-Control-Allow-Origin",
+            match any && !credentials {
+                true => "*".to_string(),
+                false => origin.to_string(),
+            },
+        )];
+        if credentials {
+            headers.push(("Access-Control-Allow-Credentials", "true".to_string()));
+        }
+        match preflight {
+            true => {
+                headers.push(("Access-Control-Allow-Methods", "GET, OPTIONS".to_string()));
+                headers.push((
+                    "Access-Control-Allow-Headers",
+                    match &policy.allowed_headers {
+                        Some(x) => x.join(", "),
+                        None => DEFAULT_ALLOWED_HEADERS.join(", "),
+                    },
+                ));
+                headers.push((
+                    "Access-Control-Max-Age",
+                    policy.max_age_secs.unwrap_or(3600).to_string(),
+                ));
+            }
+            false => headers.push(("Access-Control-Expose-Headers", EXPOSED_HEADERS.to_string())),
+        }
+        headers
+    }
+
+    // Cache-Control for a response to this path, or None to leave it to caches themselves.
+    // Anything asked for with a token may have had less redacted, so that stays with whoever
+    // asked.
+    pub fn cache_control(&self, path: &str, authorised: bool) -> Option<String> {
+        let policy = self.cache_policy(path);
+        let max_age = policy.max_age_secs?;
+        let shared = policy.shared.unwrap_or(true) && !authorised;
+        Some(format!(
+            "{}, max-age={}",
+            match shared {
+                true => "public",
+                false => "private",
+            },
+            max_age
+        ))
+    }
+
+    pub fn etag(&self, path: &str, generation: u64, body: &[u8]) -> Option<String> {
+        if !self.cache_policy(path).etag.unwrap_or(true) {
+            return None;
+        }
+        let hash = format!("{:x}", Sha256::digest(body));
+        Some(format!("\"{}-{}\"", generation, &hash[..16]))
+    }
+}
+
+// whether If-None-Match has the ETag, or is "*"
+pub fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|x| x.trim().trim_start_matches("W/"))
+        .any(|x| x == etag || x == "*")
+}
@@ -0,0 +1,79 @@
+use crate::error::Error;
+use crate::importer::SlowStreamingImporter;
+use crate::schedule::{Restriction, Schedule};
+
+use async_trait::async_trait;
+
+use chrono::{DateTime, FixedOffset};
+use chrono_tz::Tz;
+
+use serde::Deserialize;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+use std::collections::HashSet;
+
+// There's no nice machine-readable feed of engineering works, so for now this reads a JSON array
+// of these, put together from the Schedule of Restrictions or late-notice possession notices.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RestrictionJson {
+    id: String,
+    description: String,
+    location_ids: Vec<String>,
+    valid_begin: DateTime<FixedOffset>,
+    valid_end: DateTime<FixedOffset>,
+}
+
+pub struct RestrictionsImporter {}
+
+impl RestrictionsImporter {
+    pub fn new() -> RestrictionsImporter {
+        RestrictionsImporter {}
+    }
+}
+
+#[async_trait]
+impl SlowStreamingImporter for RestrictionsImporter {
+    async fn overlay(
+        &mut self,
+        mut reader: impl AsyncBufReadExt + Unpin + Send,
+        mut schedule: Schedule,
+    ) -> Result<Schedule, Error> {
+        println!("Importing engineering restrictions");
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).await?;
+        let restrictions = serde_json::from_str::<Vec<RestrictionJson>>(&contents)?;
+
+        for restriction in restrictions {
+            // show times in the local time of the works, if we know where they are
+            let timezone = restriction
+                .location_ids
+                .iter()
+                .find_map(|x| schedule.locations.get(x))
+                .map(|x| x.timezone)
+                .unwrap_or(Tz::UTC);
+
+            for location_id in &restriction.location_ids {
+                schedule
+                    .restrictions_indexed_by_location
+                    .entry(location_id.clone())
+                    .or_insert(HashSet::new())
+                    .insert(restriction.id.clone());
+            }
+
+            schedule.restrictions.insert(
+                restriction.id.clone(),
+                Restriction {
+                    id: restriction.id,
+                    description: restriction.description,
+                    location_ids: restriction.location_ids,
+                    valid_begin: restriction.valid_begin.with_timezone(&timezone),
+                    valid_end: restriction.valid_end.with_timezone(&timezone),
+                },
+            );
+        }
+
+        Ok(schedule)
+    }
+}
@@ -0,0 +1,274 @@
+use crate::error::Error;
+use crate::schedule::{Coordinate, Schedule, Train};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+
+// The files are GeoJSON feature collections, e.g. made from NR's network model. Points with an
+// "id" property say where a location is, and line strings with "from" and "to" properties are the
+// track between two adjacent timing points, in either direction. Anything else is ignored.
+#[derive(Deserialize)]
+struct FeatureCollection {
+    features: Vec<Feature>,
+}
+
+#[derive(Deserialize)]
+struct Feature {
+    geometry: Option<Geometry>,
+    properties: Option<FeatureProperties>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct FeatureProperties {
+    id: Option<String>,
+    from: Option<String>,
+    to: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum Geometry {
+    Point {
+        coordinates: Vec<f64>,
+    },
+    LineString {
+        coordinates: Vec<Vec<f64>>,
+    },
+    #[serde(other)]
+    Other,
+}
+
+// GeoJSON is longitude first, and may have an altitude we don't care about
+fn read_position(position: &[f64]) -> Option<Coordinate> {
+    match position {
+        [longitude, latitude, ..] => Some(Coordinate {
+            latitude: *latitude,
+            longitude: *longitude,
+        }),
+        _ => None,
+    }
+}
+
+fn write_position(coordinate: &Coordinate) -> Value {
+    json!([coordinate.longitude, coordinate.latitude])
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct RouteGeometryConfig {
+    files: Option<HashMap<String, String>>, // by namespace
+}
+
+#[derive(Default)]
+struct NamespaceGeometry {
+    positions: HashMap<String, Coordinate>,
+    links: HashMap<(String, String), Vec<Coordinate>>,
+}
+
+pub struct RouteGeometry {
+    namespaces: HashMap<String, NamespaceGeometry>,
+}
+
+pub struct TrainGeometry {
+    pub line: Vec<Coordinate>,
+    pub locations: Vec<(String, Coordinate)>, // every location on the route we could place
+    pub complete: bool, // false if we had to draw a straight line or leave a location out
+}
+
+impl RouteGeometry {
+    pub fn new(config: RouteGeometryConfig) -> Result<Self, Error> {
+        let mut namespaces = HashMap::new();
+        for (namespace, filename) in config.files.unwrap_or_default() {
+            namespaces.insert(namespace, Self::load_file(&filename)?);
+        }
+        Ok(Self { namespaces })
+    }
+
+    fn load_file(filename: &str) -> Result<NamespaceGeometry, Error> {
+        println!("Loading route geometry from {}", filename);
+        let contents = std::fs::read_to_string(filename)?;
+        let collection = serde_json::from_str::<FeatureCollection>(&contents)?;
+        let mut geometry = NamespaceGeometry::default();
+        for feature in collection.features {
+            let properties = feature.properties.unwrap_or_default();
+            match (feature.geometry, properties) {
+                (Some(Geometry::Point { coordinates }), FeatureProperties { id: Some(id), .. }) => {
+                    match read_position(&coordinates) {
+                        Some(x) => {
+                            geometry.positions.insert(id, x);
+                        }
+                        None => println!("WARNING: Unreadable position for {}", id),
+                    }
+                }
+                (
+                    Some(Geometry::LineString { coordinates }),
+                    FeatureProperties {
+                        from: Some(from),
+                        to: Some(to),
+                        ..
+                    },
+                ) => {
+                    let line = coordinates
+                        .iter()
+                        .filter_map(|x| read_position(x))
+                        .collect::<Vec<_>>();
+                    if line.len() < 2 {
+                        println!("WARNING: Unreadable link from {} to {}", from, to);
+                        continue;
+                    }
+                    geometry.links.insert((from, to), line);
+                }
+                _ => (),
+            }
+        }
+        println!(
+            "Loaded {} positions and {} links",
+            geometry.positions.len(),
+            geometry.links.len()
+        );
+        Ok(geometry)
+    }
+
+    // the geometry files win over the feed, as they're there to fix it up
+    pub fn position(&self, schedule: &Schedule, location_id: &str) -> Option<Coordinate> {
+        match self
+            .namespaces
+            .get(&schedule.namespace)
+            .and_then(|x| x.positions.get(location_id))
+        {
+            Some(x) => Some(*x),
+            None => schedule.locations.get(location_id)?.position,
+        }
+    }
+
+    fn link(&self, namespace: &str, from: &str, to: &str) -> Option<Vec<Coordinate>> {
+        let geometry = self.namespaces.get(namespace)?;
+        match geometry.links.get(&(from.to_string(), to.to_string())) {
+            Some(x) => Some(x.clone()),
+            None => geometry
+                .links
+                .get(&(to.to_string(), from.to_string()))
+                .map(|x| x.iter().rev().cloned().collect()),
+        }
+    }
+
+    // A point some fraction of the way from one location to another, going along the track if we
+    // know it and in a straight line if not
+    pub fn between(
+        &self,
+        schedule: &Schedule,
+        from: &str,
+        to: &str,
+        fraction: f64,
+    ) -> Option<Coordinate> {
+        let line = match self.link(&schedule.namespace, from, to) {
+            Some(x) => x,
+            None => vec![self.position(schedule, from)?, self.position(schedule, to)?],
+        };
+        Some(point_along(&line, fraction))
+    }
+
+    // A shape from the feed covers the whole train, so use that if there is one. Otherwise join up
+    // the links between each pair of locations the train goes through, passing points included,
+    // falling back to a straight line where we don't know the track.
+    pub fn stitch(&self, schedule: &Schedule, train: &Train) -> Option<TrainGeometry> {
+        let mut complete = true;
+        let mut locations = vec![];
+        for location in &train.route {
+            match self.position(schedule, &location.id) {
+                Some(x) => locations.push((location.id.to_string(), x)),
+                None => complete = false,
+            }
+        }
+
+        let shape = schedule
+            .shapes_indexed_by_train
+            .get(&train.id)
+            .and_then(|x| schedule.shapes.get(x));
+        let line = match shape {
+            Some(x) => x.clone(),
+            None => {
+                let mut line: Vec<Coordinate> = vec![];
+                for pair in locations.windows(2) {
+                    let ((from, from_position), (to, to_position)) = (&pair[0], &pair[1]);
+                    let link = match self.link(&schedule.namespace, from, to) {
+                        Some(x) => x,
+                        None => {
+                            complete = false;
+                            vec![*from_position, *to_position]
+                        }
+                    };
+                    for point in link {
+                        if line.last() != Some(&point) {
+                            line.push(point);
+                        }
+                    }
+                }
+                line
+            }
+        };
+
+        if line.len() < 2 {
+            return None;
+        }
+        Some(TrainGeometry {
+            line,
+            locations,
+            complete,
+        })
+    }
+}
+
+fn point_along(line: &[Coordinate], fraction: f64) -> Coordinate {
+    let lengths = line
+        .windows(2)
+        .map(|x| x[0].distance_m(&x[1]))
+        .collect::<Vec<_>>();
+    let mut remaining = lengths.iter().sum::<f64>() * fraction.clamp(0.0, 1.0);
+    for (i, length) in lengths.iter().enumerate() {
+        if remaining <= *length && *length > 0.0 {
+            let part = remaining / length;
+            return Coordinate {
+                latitude: line[i].latitude + (line[i + 1].latitude - line[i].latitude) * part,
+                longitude: line[i].longitude + (line[i + 1].longitude - line[i].longitude) * part,
+            };
+        }
+        remaining -= length;
+    }
+    *line.last().unwrap()
+}
+
+impl TrainGeometry {
+    // A feature collection with the line first, then a point for each location. `properties` go on
+    // the line, and `names` are used for the points.
+    pub fn to_geojson(&self, mut properties: Value, names: &HashMap<String, String>) -> Value {
+        properties["complete"] = json!(self.complete);
+        let mut features = vec![json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "LineString",
+                "coordinates": self.line.iter().map(write_position).collect::<Vec<_>>(),
+            },
+            "properties": properties,
+        })];
+        for (id, position) in &self.locations {
+            features.push(json!({
+                "type": "Feature",
+                "geometry": {
+                    "type": "Point",
+                    "coordinates": write_position(position),
+                },
+                "properties": {
+                    "id": id,
+                    "name": names.get(id),
+                },
+            }));
+        }
+        json!({
+            "type": "FeatureCollection",
+            "features": features,
+        })
+    }
+}
@@ -0,0 +1,231 @@
+use crate::schedule::{DaysOfWeek, TrainCancellation, TrainValidityPeriod};
+
+use chrono::{Datelike, NaiveDate, Weekday};
+
+use std::collections::BTreeSet;
+
+// Text for when a train runs, as printed in timetables, e.g. "Mondays to Fridays until 12 December,
+// not 25 November". Written relative to a date (usually today, or the date asked about), so dates
+// in its year don't need the year, and cancellations before it aren't mentioned.
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+fn plural(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "Mondays",
+        Weekday::Tue => "Tuesdays",
+        Weekday::Wed => "Wednesdays",
+        Weekday::Thu => "Thursdays",
+        Weekday::Fri => "Fridays",
+        Weekday::Sat => "Saturdays",
+        Weekday::Sun => "Sundays",
+    }
+}
+
+// "daily", "Mondays to Fridays", "Fridays to Mondays", or a list such as "Tuesdays and Thursdays"
+pub fn days_of_week_text(days: &DaysOfWeek) -> String {
+    let running = WEEKDAYS
+        .iter()
+        .map(|x| days.get_by_weekday(*x))
+        .collect::<Vec<_>>();
+    let count = running.iter().filter(|x| **x).count();
+    if count == 7 {
+        return "daily".to_string();
+    }
+    if count == 0 {
+        return "never".to_string();
+    }
+
+    // a single run of three or more days, which can go round the end of the week
+    if count >= 3 {
+        let first = (0..7).find(|i| running[*i] && !running[(i + 6) % 7]);
+        match first {
+            Some(first) if (0..count).all(|i| running[(first + i) % 7]) => {
+                return format!(
+                    "{} to {}",
+                    plural(WEEKDAYS[first]),
+                    plural(WEEKDAYS[(first + count - 1) % 7])
+                );
+            }
+            _ => (),
+        }
+    }
+
+    let names = WEEKDAYS
+        .iter()
+        .filter(|x| days.get_by_weekday(**x))
+        .map(|x| plural(*x))
+        .collect::<Vec<_>>();
+    join_list(&names)
+}
+
+fn join_list(items: &[impl AsRef<str>]) -> String {
+    match items {
+        [] => String::new(),
+        [only] => only.as_ref().to_string(),
+        [rest @ .., last] => format!(
+            "{} and {}",
+            rest.iter()
+                .map(|x| x.as_ref())
+                .collect::<Vec<_>>()
+                .join(", "),
+            last.as_ref()
+        ),
+    }
+}
+
+fn date_text(date: NaiveDate, relative_to: NaiveDate) -> String {
+    if date.year() == relative_to.year() {
+        date.format("%-d %B").to_string()
+    } else {
+        date.format("%-d %B %Y").to_string()
+    }
+}
+
+fn date_range_text(first: NaiveDate, last: NaiveDate, relative_to: NaiveDate) -> String {
+    if first == last {
+        date_text(first, relative_to)
+    } else if first.year() == last.year() && first.month() == last.month() {
+        format!(
+            "{} to {}",
+            first.format("%-d"),
+            date_text(last, relative_to)
+        )
+    } else {
+        format!(
+            "{} to {}",
+            date_text(first, relative_to),
+            date_text(last, relative_to)
+        )
+    }
+}
+
+fn is_cancelled(cancellations: &[TrainCancellation], date: NaiveDate) -> bool {
+    cancellations.iter().any(|x| {
+        x.validity.valid_begin.date_naive() <= date
+            && x.validity.valid_end.date_naive() >= date
+            && x.validity.days_of_week.get_by_weekday(date.weekday())
+    })
+}
+
+// The candidate dates that are left out, grouped into runs of candidates one after another, e.g.
+// "20 to 24 November and 1 December"
+fn exceptions_text(
+    candidates: impl Iterator<Item = NaiveDate>,
+    excluded: impl Fn(NaiveDate) -> bool,
+    relative_to: NaiveDate,
+) -> Option<String> {
+    let mut exceptions = vec![];
+    let mut run: Option<(NaiveDate, NaiveDate)> = None;
+    for date in candidates {
+        if excluded(date) {
+            run = match run {
+                Some((first, _)) => Some((first, date)),
+                None => Some((date, date)),
+            };
+        } else {
+            match run.take() {
+                Some((first, last)) => exceptions.push(date_range_text(first, last, relative_to)),
+                None => (),
+            }
+        }
+    }
+    match run {
+        Some((first, last)) => exceptions.push(date_range_text(first, last, relative_to)),
+        None => (),
+    }
+    match exceptions.is_empty() {
+        true => None,
+        false => Some(join_list(&exceptions)),
+    }
+}
+
+// One validity period, with the cancellations falling in it from relative_to on
+fn period_text(
+    period: &TrainValidityPeriod,
+    cancellations: &[TrainCancellation],
+    relative_to: NaiveDate,
+) -> String {
+    let begin = period.valid_begin.date_naive();
+    let end = period.valid_end.date_naive();
+    let mut text = if begin == end {
+        format!("{} only", date_text(begin, relative_to))
+    } else {
+        let mut text = days_of_week_text(&period.days_of_week);
+        if begin > relative_to {
+            text += &format!(" from {}", date_text(begin, relative_to));
+        }
+        text += &format!(" until {}", date_text(end, relative_to));
+        text
+    };
+
+    let candidates = std::cmp::max(begin, relative_to)
+        .iter_days()
+        .take_while(|x| *x <= end)
+        .filter(|x| period.days_of_week.get_by_weekday(x.weekday()));
+    match exceptions_text(candidates, |x| is_cancelled(cancellations, x), relative_to) {
+        Some(x) => text += &format!(", not {}", x),
+        None => (),
+    }
+    text
+}
+
+// For something seen on some of the dates from one date to another, e.g. a departure on a
+// timetable poster: the days of the week it's there at all, less the dates it isn't
+pub fn dates_text(dates: &BTreeSet<NaiveDate>, from: NaiveDate, to: NaiveDate) -> String {
+    let seen_on = |weekday| dates.iter().any(|x| x.weekday() == weekday);
+    let days = DaysOfWeek {
+        monday: seen_on(Weekday::Mon),
+        tuesday: seen_on(Weekday::Tue),
+        wednesday: seen_on(Weekday::Wed),
+        thursday: seen_on(Weekday::Thu),
+        friday: seen_on(Weekday::Fri),
+        saturday: seen_on(Weekday::Sat),
+        sunday: seen_on(Weekday::Sun),
+    };
+    let mut text = days_of_week_text(&days);
+    let candidates = from
+        .iter_days()
+        .take_while(|x| *x <= to)
+        .filter(|x| days.get_by_weekday(x.weekday()));
+    match exceptions_text(candidates, |x| !dates.contains(&x), from) {
+        Some(x) => text += &format!(", not {}", x),
+        None => (),
+    }
+    text
+}
+
+pub fn running_days_text(
+    validity: &[TrainValidityPeriod],
+    cancellations: &[TrainCancellation],
+    relative_to: NaiveDate,
+) -> String {
+    let mut periods = validity
+        .iter()
+        .filter(|x| x.valid_end.date_naive() >= relative_to)
+        .collect::<Vec<_>>();
+    if periods.is_empty() {
+        return "no longer runs".to_string();
+    }
+    periods.sort_by_key(|x| x.valid_begin);
+    let text = periods
+        .into_iter()
+        .map(|x| period_text(x, cancellations, relative_to))
+        .collect::<Vec<_>>()
+        .join("; ");
+
+    // sentence case, as it's usually shown on its own
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => text,
+    }
+}
@@ -1,43 +1,278 @@
-use chrono::{DateTime, NaiveTime, Weekday};
+use chrono::offset::LocalResult;
+use chrono::{
+    DateTime, Datelike, Days, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike,
+    Utc, Weekday,
+};
 use chrono_tz::Tz;
 
+use crate::cold_routes::Route;
+use crate::intern::{IStr, InternStats, Interner};
+use crate::partitioned_map::PartitionedMap;
+
 use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
 use std::collections::HashSet;
 
-#[derive(Clone, Debug, Serialize)]
+/// Everything we know from one source (a "namespace", e.g. "gbnr"). Trains are keyed by their
+/// source ID, and each ID may have several base schedules with non-overlapping validities; STP
+/// overlays and cancellations hang off those in `replacements` and `cancellations`.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Schedule {
-    pub locations: HashMap<String, Location>,
-    pub trains: HashMap<String, Vec<Train>>, // one ID could have multiple permanent schedules on
+    pub locations: PartitionedMap<Location>,
+    pub trains: PartitionedMap<Vec<Train>>, // one ID could have multiple permanent schedules on
     // different dates
     pub namespace: String,   // this is defined by me
     pub description: String, // what this schedule actually is, again defined by me
     pub their_id: Option<String>,
+    #[serde(with = "option_tz_datetime")]
     pub valid_begin: Option<DateTime<Tz>>,
+    #[serde(with = "option_tz_datetime")]
     pub valid_end: Option<DateTime<Tz>>,
+    #[serde(with = "option_tz_datetime")]
     pub last_updated: Option<DateTime<Tz>>,
-    pub trains_indexed_by_location: HashMap<String, HashSet<String>>,
-    pub trains_indexed_by_public_id: HashMap<String, HashSet<String>>,
-    pub locations_indexed_by_public_id: HashMap<String, HashSet<String>>,
+    pub last_imported: Option<DateTime<Utc>>, // when we last imported it in full or updated it daily, as opposed to the feed's own timestamp
+    pub trains_indexed_by_location: PartitionedMap<HashSet<String>>,
+    pub trains_indexed_by_public_id: PartitionedMap<HashSet<String>>,
+    pub trains_indexed_by_uic: PartitionedMap<HashSet<String>>, // UIC train numbers, as used in Europe
+    pub locations_indexed_by_public_id: PartitionedMap<HashSet<String>>,
+    pub locations_indexed_by_trigram: PartitionedMap<HashSet<String>>, // for fuzzy search
+    pub restrictions: PartitionedMap<Restriction>,
+    pub restrictions_indexed_by_location: PartitionedMap<HashSet<String>>,
+    pub locations_indexed_by_stanox: PartitionedMap<HashSet<String>>, // NR's TRUST reports by STANOX
+    pub realtime: PartitionedMap<HashMap<NaiveDate, TrainRealtime>>,  // by train ID then date
+    pub shapes: PartitionedMap<Vec<Coordinate>>, // e.g. GTFS shapes, by their own ID
+    pub shapes_indexed_by_train: PartitionedMap<String>,
+    pub pending_associations: PartitionedMap<Vec<PendingAssociation>>, // by the missing train's ID
+    pub consists: PartitionedMap<HashMap<NaiveDate, TrainAllocation>>, // freight, by train ID then date
+    pub pending_overlays: PartitionedMap<Vec<PendingOverlay>>, // by train ID, waiting for what they replace
+    pub train_names: Vec<TrainName>, // from reference data, as the timetable rarely has them
 }
 
 impl Schedule {
     pub fn new(namespace: String, description: String) -> Self {
         Self {
-            locations: HashMap::new(),
-            trains: HashMap::new(),
+            locations: PartitionedMap::new(),
+            trains: PartitionedMap::new(),
             namespace,
             description,
             their_id: None,
             valid_begin: None,
             valid_end: None,
             last_updated: None,
-            trains_indexed_by_location: HashMap::new(),
-            trains_indexed_by_public_id: HashMap::new(),
-            locations_indexed_by_public_id: HashMap::new(),
+            last_imported: None,
+            trains_indexed_by_location: PartitionedMap::new(),
+            trains_indexed_by_public_id: PartitionedMap::new(),
+            trains_indexed_by_uic: PartitionedMap::new(),
+            locations_indexed_by_public_id: PartitionedMap::new(),
+            locations_indexed_by_trigram: PartitionedMap::new(),
+            restrictions: PartitionedMap::new(),
+            restrictions_indexed_by_location: PartitionedMap::new(),
+            locations_indexed_by_stanox: PartitionedMap::new(),
+            realtime: PartitionedMap::new(),
+            shapes: PartitionedMap::new(),
+            shapes_indexed_by_train: PartitionedMap::new(),
+            pending_associations: PartitionedMap::new(),
+            consists: PartitionedMap::new(),
+            pending_overlays: PartitionedMap::new(),
+            train_names: vec![],
+        }
+    }
+
+    // call this after inserting/updating a location. Stale entries are harmless as search results
+    // are always rescored against the real location.
+    pub fn index_location_for_search(&mut self, location_id: &str) {
+        let location = match self.locations.get(location_id) {
+            Some(x) => x,
+            None => return,
+        };
+        let mut trigrams = get_trigrams(&location.name);
+        trigrams.extend(get_trigrams(&location.id));
+        match &location.public_id {
+            Some(x) => trigrams.extend(get_trigrams(x)),
+            None => (),
+        }
+        for trigram in trigrams {
+            self.locations_indexed_by_trigram
+                .entry(trigram)
+                .or_insert(HashSet::new())
+                .insert(location_id.to_string());
+        }
+    }
+
+    // Call this after deleting or replacing any of a train ID's schedules. Importers add index
+    // entries as they go, but only this takes out the ones for locations and IDs the train no
+    // longer has. It goes through every key in the indexes, so isn't for full imports.
+    pub fn reindex_train(&mut self, train_id: &str) {
+        let keys = match self.trains.get(train_id) {
+            Some(x) => train_index_keys(x),
+            None => TrainIndexKeys::default(),
+        };
+        reindex(
+            &mut self.trains_indexed_by_location,
+            train_id,
+            &keys.locations,
+        );
+        reindex(
+            &mut self.trains_indexed_by_public_id,
+            train_id,
+            &keys.public_ids,
+        );
+        reindex(&mut self.trains_indexed_by_uic, train_id, &keys.uic_codes);
+    }
+
+    // Throws the train indexes away and builds them again from the trains themselves, for when
+    // they've drifted anyway
+    pub fn rebuild_train_indexes(&mut self) -> IndexRebuild {
+        let old = [
+            std::mem::take(&mut self.trains_indexed_by_location),
+            std::mem::take(&mut self.trains_indexed_by_public_id),
+            std::mem::take(&mut self.trains_indexed_by_uic),
+        ];
+        for (train_id, trains) in &self.trains {
+            let keys = train_index_keys(trains);
+            for (index, keys) in [
+                (&mut self.trains_indexed_by_location, keys.locations),
+                (&mut self.trains_indexed_by_public_id, keys.public_ids),
+                (&mut self.trains_indexed_by_uic, keys.uic_codes),
+            ] {
+                for key in keys {
+                    index.entry(key).or_default().insert(train_id.clone());
+                }
+            }
+        }
+
+        let count = |from: &PartitionedMap<HashSet<String>>,
+                     missing_in: &PartitionedMap<HashSet<String>>| {
+            from.iter()
+                .map(|(key, ids)| match missing_in.get(key) {
+                    Some(x) => ids.difference(x).count(),
+                    None => ids.len(),
+                })
+                .sum::<usize>()
+        };
+        let new = [
+            &self.trains_indexed_by_location,
+            &self.trains_indexed_by_public_id,
+            &self.trains_indexed_by_uic,
+        ];
+        let mut rebuild = IndexRebuild {
+            namespace: self.namespace.clone(),
+            ..Default::default()
+        };
+        for (old, new) in old.iter().zip(new) {
+            rebuild.stale_removed += count(old, new);
+            rebuild.missing_added += count(new, old);
+        }
+        rebuild
+    }
+}
+
+// What a train ID's schedules should be indexed under: the locations they call at, and their
+// public IDs and UIC numbers, including those of replacements. UIC numbers can change en route.
+#[derive(Debug, Default)]
+pub struct TrainIndexKeys {
+    pub locations: HashSet<String>,
+    pub public_ids: HashSet<String>,
+    pub uic_codes: HashSet<String>,
+}
+
+fn collect_index_keys(train: &Train, keys: &mut TrainIndexKeys) {
+    match &train.variable_train.public_id {
+        Some(x) => {
+            keys.public_ids.insert(x.clone());
+        }
+        None => (),
+    }
+    match &train.variable_train.uic_code {
+        Some(x) => {
+            keys.uic_codes.insert(x.clone());
+        }
+        None => (),
+    }
+    // without keeping it decompressed, as this goes through every train for integrity checks
+    train.route.peek(|route| {
+        for location in route {
+            keys.locations.insert(location.id.to_string());
+            match location
+                .change_en_route
+                .as_ref()
+                .and_then(|x| x.uic_code.as_ref())
+            {
+                Some(x) => {
+                    keys.uic_codes.insert(x.clone());
+                }
+                None => (),
+            }
+        }
+    });
+    for replacement in &train.replacements {
+        collect_index_keys(replacement, keys);
+    }
+}
+
+pub fn train_index_keys(trains: &[Train]) -> TrainIndexKeys {
+    let mut keys = TrainIndexKeys::default();
+    for train in trains {
+        collect_index_keys(train, &mut keys);
+    }
+    keys
+}
+
+// how many entries a rebuild of one namespace's train indexes took out and put in
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct IndexRebuild {
+    pub namespace: String,
+    pub stale_removed: usize,
+    pub missing_added: usize,
+}
+
+// makes the index have train_id under exactly `keys`, dropping sets that end up empty
+fn reindex(index: &mut PartitionedMap<HashSet<String>>, train_id: &str, keys: &HashSet<String>) {
+    // only looking, so only what changes is copied
+    let stale = index
+        .iter()
+        .filter(|(key, ids)| !keys.contains(*key) && ids.contains(train_id))
+        .map(|(key, _)| key.clone())
+        .collect::<Vec<_>>();
+    for key in stale {
+        let empty = match index.get_mut(&key) {
+            Some(ids) => {
+                ids.remove(train_id);
+                ids.is_empty()
+            }
+            None => false,
+        };
+        if empty {
+            index.remove(&key);
+        }
+    }
+    for key in keys {
+        if index.get(key).is_some_and(|x| x.contains(train_id)) {
+            continue;
+        }
+        index
+            .entry(key.clone())
+            .or_default()
+            .insert(train_id.to_string());
+    }
+}
+
+// pg_trgm-style: lowercase, split into words, pad each word with two spaces in front and one
+// behind, and take every three-character window
+pub fn get_trigrams(text: &str) -> HashSet<String> {
+    let mut trigrams = HashSet::new();
+    let text = text.to_lowercase();
+    for word in text.split(|c: char| !c.is_alphanumeric()) {
+        if word.is_empty() {
+            continue;
+        }
+        let padded = format!("  {} ", word).chars().collect::<Vec<_>>();
+        for window in padded.windows(3) {
+            trigrams.insert(window.iter().collect::<String>());
         }
     }
+    trigrams
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -47,16 +282,112 @@ pub struct Location {
     pub public_id: Option<String>, // some countries have an internal ID for planning and a public
     // ID for retail; we should expose the public one.
     pub timezone: Tz,
+    pub position: Option<Coordinate>,
+    pub unverified: bool, // made up for an ID a train used before anything said what it was
 }
 
-#[derive(Clone, Debug, Serialize)]
+// WGS84, in degrees
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Coordinate {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Coordinate {
+    // great circle, which is near enough for spacing out stations
+    pub fn distance_m(&self, other: &Coordinate) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.longitude - self.longitude).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * 6_371_000.0 * a.sqrt().asin()
+    }
+}
+
+// planned engineering works, possessions etc. affecting some locations for a while
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Restriction {
+    pub id: String,
+    pub description: String,
+    pub location_ids: Vec<String>,
+    #[serde(with = "tz_datetime")]
+    pub valid_begin: DateTime<Tz>,
+    #[serde(with = "tz_datetime")]
+    pub valid_end: DateTime<Tz>,
+}
+
+// What actually happened to one instance of a train, as opposed to what was planned
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TrainRealtime {
+    pub realtime_id: String, // e.g. the TRUST train ID
+    #[serde(with = "option_tz_datetime")]
+    pub activated: Option<DateTime<Tz>>,
+    pub cancellation: Option<RealtimeCancellation>,
+    pub events: Vec<RealtimeEvent>,
+    pub terminated: bool,
+}
+
+impl TrainRealtime {
+    // Sets live_platform wherever a report gave one, on a route resolved for the same date. Works
+    // along the route with the reports, so a location visited twice gets the right visit's.
+    pub fn apply_platforms(&self, route: &mut [TrainLocation]) {
+        let mut cursor = 0;
+        for event in &self.events {
+            let i = match route[cursor..]
+                .iter()
+                .position(|x| event.location_ids.iter().any(|y| y == x.id.as_str()))
+            {
+                Some(x) => cursor + x,
+                None => continue,
+            };
+            cursor = i;
+            match &event.platform {
+                Some(x) => route[i].live_platform = Some(x.as_str().into()),
+                None => (),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RealtimeCancellation {
+    pub location_ids: Vec<String>,
+    pub reason_code: Option<String>, // delay attribution code, e.g. MN
+    pub reason: Option<String>,      // passenger-friendly, e.g. "a train fault"
+    pub cancellation_type: Option<String>, // at origin, en route etc.
+    #[serde(with = "tz_datetime")]
+    pub timestamp: DateTime<Tz>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum RealtimeEventType {
+    Arrival,
+    Departure,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RealtimeEvent {
+    pub location_ids: Vec<String>, // reports can be for an area covering several locations
+    pub event_type: RealtimeEventType,
+    #[serde(with = "option_tz_datetime")]
+    pub planned: Option<DateTime<Tz>>,
+    #[serde(with = "tz_datetime")]
+    pub actual: DateTime<Tz>,
+    pub platform: Option<String>,
+    pub delay_minutes: i64, // negative if early
+    pub off_route: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct TrainValidityPeriod {
+    #[serde(with = "tz_datetime")]
     pub valid_begin: DateTime<Tz>,
+    #[serde(with = "tz_datetime")]
     pub valid_end: DateTime<Tz>,
     pub days_of_week: DaysOfWeek,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub struct DaysOfWeek {
     pub monday: bool,
     pub tuesday: bool,
@@ -121,7 +452,7 @@ impl IntoIterator for &DaysOfWeek {
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum TrainType {
     Bus,
     ServiceBus,
@@ -180,6 +511,8 @@ pub enum TrainType {
     Ship,
     Trip,
     Tram,
+    TramTrain, // trams that also run on heavy rail lines
+    LightRail,
     CableTram,
     CableCar,
     Funicular,
@@ -190,14 +523,104 @@ pub enum TrainType {
     Air,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+impl TrainType {
+    pub fn is_freight(&self) -> bool {
+        matches!(
+            self,
+            TrainType::Freight
+                | TrainType::FreightDepartmental
+                | TrainType::FreightCivilEngineer
+                | TrainType::FreightMechanicalElectricalEngineer
+                | TrainType::FreightStores
+                | TrainType::FreightTest
+                | TrainType::FreightSignalTelecoms
+                | TrainType::FreightAutomotiveComponents
+                | TrainType::FreightAutomotiveVehicles
+                | TrainType::FreightEdibleProducts
+                | TrainType::FreightIndustrialMinerals
+                | TrainType::FreightChemicals
+                | TrainType::FreightWagonloadBuildingMaterials
+                | TrainType::FreightMerchandise
+                | TrainType::FreightInternational
+                | TrainType::FreightInternationalMixed
+                | TrainType::FreightInternationalIntermodal
+                | TrainType::FreightInternationalAutomotive
+                | TrainType::FreightInternationalContract
+                | TrainType::FreightInternationalHaulmark
+                | TrainType::FreightInternationalJointVenture
+                | TrainType::FreightIntermodalContracts
+                | TrainType::FreightIntermodalOther
+                | TrainType::FreightCoalDistributive
+                | TrainType::FreightCoalElectricity
+                | TrainType::FreightNuclear
+                | TrainType::FreightMetals
+                | TrainType::FreightAggregates
+                | TrainType::FreightWaste
+                | TrainType::FreightTrainloadBuildingMaterials
+                | TrainType::FreightPetroleum
+                | TrainType::LocomotiveBrakeVan
+                | TrainType::Locomotive
+                | TrainType::Trip
+        )
+    }
+
+    // stock moving without passengers, e.g. to and from depots
+    pub fn is_empty_stock(&self) -> bool {
+        matches!(
+            self,
+            TrainType::EmptyPassenger
+                | TrainType::EmptyPassengerAndStaff
+                | TrainType::EmptyMetro
+                | TrainType::EmptyNonPassenger
+        )
+    }
+
+    pub fn mode(&self) -> TransportMode {
+        match self {
+            TrainType::Bus
+            | TrainType::ServiceBus
+            | TrainType::ReplacementBus
+            | TrainType::Coach => TransportMode::Bus,
+            TrainType::Ship => TransportMode::Ship,
+            TrainType::Tram | TrainType::CableTram => TransportMode::Tram,
+            TrainType::TramTrain | TrainType::LightRail => TransportMode::LightRail,
+            TrainType::Metro | TrainType::EmptyMetro => TransportMode::Metro,
+            TrainType::CableCar => TransportMode::CableCar,
+            TrainType::Funicular => TransportMode::Funicular,
+            TrainType::Trolleybus => TransportMode::Trolleybus,
+            TrainType::Monorail => TransportMode::Monorail,
+            TrainType::Taxi => TransportMode::Taxi,
+            TrainType::Air => TransportMode::Air,
+            _ => TransportMode::Rail,
+        }
+    }
+}
+
+/// What actually turns up: lets replacement buses and ferry legs be told apart from trains
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+pub enum TransportMode {
+    Rail,
+    Bus,
+    Ship,
+    Tram,
+    LightRail,
+    Metro,
+    CableCar,
+    Funicular,
+    Trolleybus,
+    Monorail,
+    Taxi,
+    Air,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum TrainSource {
     LongTerm,
     ShortTerm,
     VeryShortTerm,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum TrainPower {
     DieselLocomotive,
     DieselElectricMultipleUnit,
@@ -210,31 +633,44 @@ pub enum TrainPower {
     ElectricAndDieselMultipleUnit,
     BatteryLocomotive,
     BatteryMultipleUnit,
+    ElectricAndBatteryMultipleUnit,
+    ElectricTram, // light rail vehicles too
+    ElectricAndBatteryTram,
     SteamLocomotive,
     SteamRailcar,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct VehicleAccessibility {
+    pub wheelchair_spaces: Option<bool>,
+    pub accessible_toilet: Option<bool>,
+    pub passenger_information: Option<bool>, // audio and visual next stop announcements
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TrainVehicle {
-    pub id: String,
+    pub id: String, // the class, e.g. "800" or "165/1"
     pub description: String,
-    // TODO more here, types etc.?
+    pub cars: Vec<u8>, // the formations units come in; may run coupled. For wagons, how many there are
+    pub car_length_m: Option<f64>,
+    pub max_speed_mph: Option<u16>,
+    pub accessibility: VehicleAccessibility,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TrainAllocation {
-    pub id: String,
-    pub description: String,
+    pub id: IStr,
+    pub description: IStr,
     pub vehicles: Option<Vec<TrainVehicle>>,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TrainOperator {
-    pub id: String,
-    pub description: Option<String>,
+    pub id: IStr,
+    pub description: Option<IStr>,
 }
 
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct OperatingCharacteristics {
     pub vacuum_braked: bool,
     pub one_hundred_mph: bool,
@@ -249,7 +685,7 @@ pub struct OperatingCharacteristics {
     pub sb1c_gauge: bool,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum ReservationField {
     Possible,
     Mandatory,
@@ -260,7 +696,7 @@ pub enum ReservationField {
     Unknown,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Reservations {
     pub seats: ReservationField,
     pub bicycles: ReservationField,
@@ -269,7 +705,7 @@ pub struct Reservations {
     pub wheelchairs: ReservationField,
 }
 
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Catering {
     pub buffet: bool,
     pub first_class_restaurant: bool,
@@ -279,7 +715,7 @@ pub struct Catering {
     pub trolley: bool,
 }
 
-#[derive(Clone, Debug, Default, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct Activities {
     pub detach: bool,
     pub attach: bool,
@@ -319,12 +755,100 @@ pub struct Activities {
     pub request_pick_up_by_telephone: bool,
     pub request_set_down_by_telephone: bool,
     pub times_approximate: bool,
+    pub times_inferred: bool, // worked out by us from the times either side; see infer_passing_times
+}
+
+// What an activity means to whoever's reading: passengers, those running the railway, or nobody
+// much, as it's only there for whoever prints or processes the timetable (e.g. H, HH and TS)
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ActivityCategory {
+    Passenger,
+    Operational,
+    DataOnly,
 }
 
+// One activity at a call, said in words, so clients don't need to know CIF's codes
 #[derive(Clone, Debug, Serialize)]
+pub struct ActivityNote {
+    pub activity: &'static str, // as the field in Activities
+    pub category: ActivityCategory,
+    pub note: &'static str,
+}
+
+type ActivityDescription = (
+    &'static str,
+    ActivityCategory,
+    &'static str,
+    fn(&Activities) -> bool,
+);
+
+// one activity to a line, as a table, in the order notes come out in: passengers' first
+#[rustfmt::skip]
+const ACTIVITY_DESCRIPTIONS: &[ActivityDescription] = {
+    use ActivityCategory::*;
+    &[
+        ("train_begins", Passenger, "Train begins", |x| x.train_begins),
+        ("train_finishes", Passenger, "Train finishes", |x| x.train_finishes),
+        ("normal_passenger_stop", Passenger, "Picks up and sets down passengers", |x| x.normal_passenger_stop),
+        ("pick_up_only", Passenger, "Picks up passengers only", |x| x.pick_up_only),
+        ("set_down_only", Passenger, "Sets down passengers only", |x| x.set_down_only),
+        ("request_pick_up", Passenger, "Stops on request to pick up passengers", |x| x.request_pick_up),
+        ("request_set_down", Passenger, "Stops on request to set down passengers", |x| x.request_set_down),
+        ("request_pick_up_by_telephone", Passenger, "Picks up passengers if arranged by telephone", |x| x.request_pick_up_by_telephone),
+        ("request_set_down_by_telephone", Passenger, "Sets down passengers if arranged by telephone", |x| x.request_set_down_by_telephone),
+        ("detach", Passenger, "Detaches coaches", |x| x.detach),
+        ("attach", Passenger, "Attaches coaches", |x| x.attach),
+        ("ticket_collection", Passenger, "Tickets are collected", |x| x.ticket_collection),
+        ("ticket_examination", Passenger, "Tickets are examined", |x| x.ticket_examination),
+        ("first_class_ticket_examination", Passenger, "First class tickets are examined", |x| x.first_class_ticket_examination),
+        ("selective_ticket_examination", Passenger, "Some tickets are examined", |x| x.selective_ticket_examination),
+        ("times_approximate", Passenger, "Times are approximate", |x| x.times_approximate),
+        ("unadvertised_stop", Operational, "Stops, but not for passengers to use", |x| x.unadvertised_stop),
+        ("operational_stop", Operational, "Stops for operating reasons", |x| x.operational_stop),
+        ("staff_stop", Operational, "Stops for railway staff only", |x| x.staff_stop),
+        ("other_trains_pass", Operational, "Stops or shunts for other trains to pass", |x| x.other_trains_pass),
+        ("cross_at_passing_point", Operational, "Crosses another train at a passing point", |x| x.cross_at_passing_point),
+        ("attach_or_detach_assisting_loco", Operational, "Attaches or detaches an assisting locomotive", |x| x.attach_or_detach_assisting_loco),
+        ("banking_loco", Operational, "Stops for a banking locomotive", |x| x.banking_loco),
+        ("change_loco", Operational, "Changes locomotive", |x| x.change_loco),
+        ("train_locomotive_on_rear", Operational, "Locomotive on the rear", |x| x.train_locomotive_on_rear),
+        ("propelling", Operational, "Propelling", |x| x.propelling),
+        ("reversing_move", Operational, "Reverses, or the driver changes ends", |x| x.reversing_move),
+        ("run_round", Operational, "Locomotive runs round the train", |x| x.run_round),
+        ("crew_change", Operational, "Crew change", |x| x.crew_change),
+        ("examination", Operational, "Stops for examination", |x| x.examination),
+        ("passenger_count", Operational, "Passengers are counted", |x| x.passenger_count),
+        ("token_etc", Operational, "Token, staff or tablet is exchanged", |x| x.token_etc),
+        ("watering_stock", Operational, "Coaches are watered", |x| x.watering_stock),
+        ("x_on_arrival", DataOnly, "Shown as X on arrival in the working timetable", |x| x.x_on_arrival),
+        ("gbprtt", DataOnly, "To be added to the published timetable", |x| x.gbprtt),
+        ("prevent_column_merge", DataOnly, "Keeps working timetable columns from being merged", |x| x.prevent_column_merge),
+        ("prevent_third_column_merge", DataOnly, "Keeps a third working timetable column from being merged", |x| x.prevent_third_column_merge),
+        ("tops_reporting", DataOnly, "Consist details to be reported to TOPS", |x| x.tops_reporting),
+        ("times_inferred", DataOnly, "Passing time worked out from the times either side", |x| x.times_inferred),
+    ]
+};
+
+impl Activities {
+    // everything that's set, in words
+    pub fn notes(&self) -> Vec<ActivityNote> {
+        ACTIVITY_DESCRIPTIONS
+            .iter()
+            .filter(|(_, _, _, set)| set(self))
+            .map(|(activity, category, note, _)| ActivityNote {
+                activity,
+                category: *category,
+                note,
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct AssociationNode {
-    pub other_train_id: String,
+    pub other_train_id: IStr,
     pub other_train_location_id_suffix: Option<String>,
+    // one per insert for the same pair of trains, e.g. different days of the week in the summer
     pub validity: Vec<TrainValidityPeriod>,
     pub cancellations: Vec<(TrainValidityPeriod, TrainSource)>,
     pub replacements: Vec<AssociationNode>,
@@ -333,10 +857,41 @@ pub struct AssociationNode {
     pub source: Option<TrainSource>,
 }
 
-#[derive(Clone, Debug, Serialize)]
+// Which of a train location's association fields an association goes in, i.e. what it means from
+// the point of view of the train it's on
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub enum AssociationCategory {
+    Join,
+    Divide,
+    Next,
+    IsJoinedToBy,
+    DividesFrom,
+    FormsFrom,
+}
+
+// An association read for a train we don't have, usually one that only exists in VSTP, kept until
+// the train turns up so both ends get it
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PendingAssociation {
+    pub location_id: String,
+    pub location_id_suffix: Option<String>,
+    pub category: AssociationCategory,
+    pub association: AssociationNode,
+}
+
+// An STP overlay read before the schedule it replaces, usually because that's only coming in a
+// later file, kept until it turns up. Meanwhile one for a train we have nothing else for runs on
+// its own, as it may well have been meant as a new train all along.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PendingOverlay {
+    pub overlay: Train,
+    pub standalone: bool, // in trains as a version of its own for now
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct TrainLocation {
     pub timing_tz: Option<Tz>, // TZ for timings, if different from the location TZ (GTFS)
-    pub id: String,
+    pub id: IStr,
     pub id_suffix: Option<String>, // to allow associations to be matched when the same location
     // occurs multiple times in a given train
     pub working_arr: Option<NaiveTime>,
@@ -349,10 +904,12 @@ pub struct TrainLocation {
     pub public_arr_day: Option<u8>,
     pub public_dep: Option<NaiveTime>,
     pub public_dep_day: Option<u8>,
-    pub platform: Option<String>,
-    pub platform_zone: Option<String>,
-    pub line: Option<String>,
-    pub path: Option<String>,
+    pub scheduled_platform: Option<IStr>,
+    // from realtime reports, so only ever set on one instance of the train; see apply_platforms
+    pub live_platform: Option<IStr>,
+    pub platform_zone: Option<IStr>,
+    pub line: Option<IStr>,
+    pub path: Option<IStr>,
     pub engineering_allowance_s: Option<u32>,
     pub pathing_allowance_s: Option<u32>,
     pub performance_allowance_s: Option<u32>,
@@ -366,7 +923,165 @@ pub struct TrainLocation {
     pub forms_from: Option<AssociationNode>,
 }
 
-#[derive(Clone, Debug, Serialize)]
+/// A calling point's times as real instants in the location's time zone, for a train starting on
+/// a given date, so clients don't have to apply the day offsets themselves.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct LocalTimes {
+    pub working_arr: Option<DateTime<Tz>>,
+    pub working_dep: Option<DateTime<Tz>>,
+    pub working_pass: Option<DateTime<Tz>>,
+    pub public_arr: Option<DateTime<Tz>>,
+    pub public_dep: Option<DateTime<Tz>>,
+}
+
+/// What a calling point means for someone wanting to travel, boiled down from the activity codes
+/// so clients don't need to know CIF's.
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct PassengerFlags {
+    pub public: bool, // passengers can get on or off here at all; otherwise it's operational
+    pub request_stop: bool,
+    pub request_by_telephone: bool, // only if arranged in advance, e.g. some GTFS demand responsive
+    pub pick_up_only: bool,
+    pub set_down_only: bool,
+}
+
+impl LocalTimes {
+    // how long the train stands here, by working times if there are any
+    pub fn dwell_secs(&self) -> Option<i64> {
+        let arrival = self.working_arr.or(self.public_arr)?;
+        let departure = self.working_dep.or(self.public_dep)?;
+        Some((departure - arrival).num_seconds())
+    }
+}
+
+// Times are in the timing TZ if there is one, but shown in the location's
+pub fn local_datetime(
+    date: NaiveDate,
+    day_offset: Option<u8>,
+    time: Option<NaiveTime>,
+    timing_tz: Option<Tz>,
+    location_tz: Tz,
+) -> Option<DateTime<Tz>> {
+    let date_time = date
+        .checked_add_days(Days::new(day_offset.unwrap_or(0).into()))?
+        .and_time(time?);
+    let timing_tz = timing_tz.unwrap_or(location_tz);
+    let date_time = match timing_tz.from_local_datetime(&date_time) {
+        LocalResult::Single(x) => x,
+        LocalResult::Ambiguous(x, _) => x, // clocks went back; timetables mean the first one
+        // clocks went forward past this time, so it's really an hour later on the new clock
+        LocalResult::None => timing_tz
+            .from_local_datetime(&(date_time + Duration::hours(1)))
+            .earliest()?,
+    };
+    Some(date_time.with_timezone(&location_tz))
+}
+
+// A train's operating date is the day its timetable is for. Validity, overlays, realtime, global
+// IDs and every date identifying a train in the API go by it. It's usually the day the train
+// leaves its origin, but some operators timetable services just after midnight as part of the
+// day before, e.g. ScotRail, TfW and London Overground each have Saturday-night trains leaving at
+// 00:30 on Sunday morning that their GTFS gives as Saturday at 24:30. Those start on day 1, and
+// are still Saturday's trains.
+pub fn calendar_date(operating_date: NaiveDate, day_offset: Option<u8>) -> Option<NaiveDate> {
+    operating_date.checked_add_days(Days::new(day_offset.unwrap_or(0).into()))
+}
+
+pub fn operating_date(calendar_date: NaiveDate, day_offset: Option<u8>) -> Option<NaiveDate> {
+    calendar_date.checked_sub_days(Days::new(day_offset.unwrap_or(0).into()))
+}
+
+// Which of a train's calls at a location something refers to, for trains which are there more than
+// once, e.g. circulars and reversals. CIF gives the second and later calls a suffix ("2", "3" and
+// so on) which associations refer to; VSTP doesn't, so there the suffix is taken as which call it
+// is, counting from one. No suffix at all is the first call. Only ever one call, so an association
+// at the start of a circular doesn't also end up on its last call.
+pub fn call_index(
+    route: &[TrainLocation],
+    location: &str,
+    suffix: &Option<String>,
+) -> Option<usize> {
+    match route
+        .iter()
+        .position(|x| x.id == location && x.id_suffix == *suffix)
+    {
+        Some(x) => return Some(x),
+        None => (),
+    }
+    let ordinal = match suffix {
+        Some(x) => x.parse::<usize>().ok()?,
+        None => 1,
+    };
+    route
+        .iter()
+        .enumerate()
+        .filter(|(_, x)| x.id == location)
+        .nth(ordinal.checked_sub(1)?)
+        .map(|(i, _)| i)
+}
+
+impl TrainLocation {
+    // Of the first time the train has here, by working times if there are any
+    pub fn day_offset(&self) -> Option<u8> {
+        [
+            (self.working_arr, self.working_arr_day),
+            (self.working_pass, self.working_pass_day),
+            (self.working_dep, self.working_dep_day),
+            (self.public_arr, self.public_arr_day),
+            (self.public_dep, self.public_dep_day),
+        ]
+        .into_iter()
+        .find_map(|(time, day)| time.and(day))
+    }
+
+    // When a board has it here on an operating date: leaving if it does, else passing, else
+    // arriving, by working times before public ones. Naive, as boards are in local time.
+    pub fn board_time(&self, date: NaiveDate) -> Option<NaiveDateTime> {
+        let (time, day) = [
+            (self.working_dep, self.working_dep_day),
+            (self.public_dep, self.public_dep_day),
+            (self.working_pass, self.working_pass_day),
+            (self.working_arr, self.working_arr_day),
+            (self.public_arr, self.public_arr_day),
+        ]
+        .into_iter()
+        .find(|(time, _)| time.is_some())?;
+        Some(calendar_date(date, day)?.and_time(time?))
+    }
+
+    pub fn local_times(&self, date: NaiveDate, location_tz: Tz) -> LocalTimes {
+        let get =
+            |day_offset, time| local_datetime(date, day_offset, time, self.timing_tz, location_tz);
+        LocalTimes {
+            working_arr: get(self.working_arr_day, self.working_arr),
+            working_dep: get(self.working_dep_day, self.working_dep),
+            working_pass: get(self.working_pass_day, self.working_pass),
+            public_arr: get(self.public_arr_day, self.public_arr),
+            public_dep: get(self.public_dep_day, self.public_dep),
+        }
+    }
+
+    // Going by public times as well as activities, as not every feed bothers with the activities
+    // when a stop is simply not in the public timetable.
+    pub fn passenger_flags(&self) -> PassengerFlags {
+        let activities = &self.activities;
+        let public = (self.public_arr.is_some() || self.public_dep.is_some())
+            && !activities.operational_stop
+            && !activities.unadvertised_stop
+            && !activities.staff_stop;
+        PassengerFlags {
+            public,
+            request_stop: public && (activities.request_pick_up || activities.request_set_down),
+            request_by_telephone: public
+                && (activities.request_pick_up_by_telephone
+                    || activities.request_set_down_by_telephone),
+            pick_up_only: public && activities.pick_up_only,
+            set_down_only: public && activities.set_down_only,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct VariableTrain {
     pub train_type: TrainType,
     pub public_id: Option<String>,
@@ -392,15 +1107,845 @@ pub struct VariableTrain {
     pub bicycles_allowed: Option<bool>,
 }
 
+/// What a passenger gets on board, as described to them. Anything the feed doesn't tell us is
+/// left out rather than guessed at.
 #[derive(Clone, Debug, Serialize)]
+pub struct Facilities {
+    pub first_class: Option<bool>,
+    pub sleepers: Option<bool>,
+    pub catering: Option<Catering>,
+    pub reservations: Reservations,
+    pub wheelchair_accessible: Option<bool>,
+    pub bicycles_allowed: Option<bool>,
+    pub descriptions: Vec<String>, // e.g. "Buffet service", "Seat reservations compulsory"
+}
+
+fn describe_reservation(what: &str, field: ReservationField) -> Option<String> {
+    match field {
+        ReservationField::Mandatory => Some(format!("{} reservations compulsory", what)),
+        ReservationField::Recommended => Some(format!("{} reservations recommended", what)),
+        ReservationField::Possible => Some(format!("{} reservations available", what)),
+        _ => None,
+    }
+}
+
+impl VariableTrain {
+    // Trains can change part of the way along, so for a particular location this wants to be
+    // called on the most recent change_en_route at or before it, if there is one.
+    pub fn facilities(&self) -> Facilities {
+        let mut descriptions = vec![];
+
+        match (self.has_first_class_seats, self.has_second_class_seats) {
+            (Some(true), Some(false)) => descriptions.push("First class only".to_string()),
+            (Some(true), _) => descriptions.push("First class available".to_string()),
+            (_, Some(true)) => descriptions.push("Standard class only".to_string()),
+            _ => (),
+        }
+        match (
+            self.has_first_class_sleepers,
+            self.has_second_class_sleepers,
+        ) {
+            (Some(true), Some(true)) => {
+                descriptions.push("First and standard class sleepers".to_string())
+            }
+            (Some(true), _) => descriptions.push("First class sleepers".to_string()),
+            (_, Some(true)) => descriptions.push("Standard class sleepers".to_string()),
+            _ => (),
+        }
+
+        match &self.catering {
+            Some(catering) => {
+                let services = [
+                    (catering.buffet, "Buffet service"),
+                    (
+                        catering.first_class_restaurant,
+                        "Restaurant for first class passengers",
+                    ),
+                    (catering.hot_food, "Hot food"),
+                    (
+                        catering.first_class_meal,
+                        "Meal included for first class passengers",
+                    ),
+                    (catering.restaurant, "Restaurant car"),
+                    (catering.trolley, "Trolley"),
+                ];
+                for (available, description) in services {
+                    if available {
+                        descriptions.push(description.to_string());
+                    }
+                }
+            }
+            None => (),
+        }
+
+        let reservations = [
+            ("Seat", self.reservations.seats),
+            ("Sleeper", self.reservations.sleepers),
+            ("Wheelchair space", self.reservations.wheelchairs),
+            ("Bicycle", self.reservations.bicycles),
+            ("Vehicle", self.reservations.vehicles),
+        ];
+        for (what, field) in reservations {
+            match describe_reservation(what, field) {
+                Some(x) => descriptions.push(x),
+                None => (),
+            }
+        }
+
+        match self.wheelchair_accessible {
+            Some(true) => descriptions.push("Wheelchair accessible".to_string()),
+            Some(false) => descriptions.push("Not wheelchair accessible".to_string()),
+            None => (),
+        }
+        match self.bicycles_allowed {
+            Some(true) => descriptions.push("Bicycles allowed".to_string()),
+            Some(false) => descriptions.push("No bicycles".to_string()),
+            None => (),
+        }
+        match self.carries_vehicles {
+            Some(true) => descriptions.push("Conveys road vehicles".to_string()),
+            _ => (),
+        }
+
+        Facilities {
+            first_class: self.has_first_class_seats,
+            sleepers: match (
+                self.has_first_class_sleepers,
+                self.has_second_class_sleepers,
+            ) {
+                (None, None) => None,
+                (x, y) => Some(x.unwrap_or(false) || y.unwrap_or(false)),
+            },
+            catering: self.catering.clone(),
+            reservations: self.reservations.clone(),
+            wheelchair_accessible: self.wheelchair_accessible,
+            bicycles_allowed: self.bicycles_allowed,
+            descriptions,
+        }
+    }
+
+    // only as far as it's needed, e.g. into a yard; like facilities, this can change en route
+    pub fn runs_to_locations_as_required(&self) -> bool {
+        self.operating_characteristics
+            .as_ref()
+            .map_or(false, |x| x.runs_to_locations_as_required)
+    }
+}
+
+// A name a train carries, e.g. "The Flying Scotsman", for the train with an ID or every one with a
+// public ID (e.g. "1S15"), from one date to another
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TrainName {
+    pub name: String,
+    pub train_id: Option<String>,
+    pub public_id: Option<String>,
+    pub valid_from: Option<NaiveDate>, // default always
+    pub valid_to: Option<NaiveDate>,   // likewise
+}
+
+// The name a train has on a date, from the names given, if it doesn't have one of its own. One
+// for its ID beats one for its public ID.
+pub fn train_name<'a>(names: &'a [TrainName], train: &Train, date: NaiveDate) -> Option<&'a str> {
+    if train.variable_train.name.is_some() {
+        return None;
+    }
+    let applying = || {
+        names.iter().filter(move |x| {
+            x.valid_from.map_or(true, |y| y <= date) && x.valid_to.map_or(true, |y| y >= date)
+        })
+    };
+    applying()
+        .find(|x| x.train_id.as_ref() == Some(&train.id))
+        .or_else(|| {
+            applying()
+                .find(|x| x.public_id.is_some() && x.public_id == train.variable_train.public_id)
+        })
+        .map(|x| x.name.as_str())
+}
+
+// Gives a train its name on a date, everywhere along its route, so a change en route doesn't look
+// like it changes the name too
+pub fn name_train(names: &[TrainName], train: &mut Train, date: NaiveDate) {
+    let name = match train_name(names, train, date) {
+        Some(x) => x.to_string(),
+        None => return,
+    };
+    // only written to if there's somewhere to name, as that decompresses a cold route
+    let unnamed = train.route.peek(|route| {
+        route
+            .iter()
+            .any(|x| matches!(&x.change_en_route, Some(x) if x.name.is_none()))
+    });
+    if unnamed {
+        for location in train.route.iter_mut() {
+            match &mut location.change_en_route {
+                Some(x) if x.name.is_none() => x.name = Some(name.clone()),
+                _ => (),
+            }
+        }
+    }
+    train.variable_train.name = Some(name);
+}
+
+// A LongTerm one is a day the timetable itself leaves out, e.g. a public holiday GTFS removes a
+// service from, so the train just doesn't run then. Any other is a cancellation, which boards and
+// the like show as one.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TrainCancellation {
+    pub validity: TrainValidityPeriod,
+    pub source: TrainSource,
+    pub reason: Option<String>, // passenger-friendly, e.g. "a train fault"
+}
+
+/// One base schedule for a train. It runs on a given date if that date is in `validity`, unless
+/// it's in one of the `cancellations`; if the date is in one of the `replacements` then that
+/// replacement runs instead of this.
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Train {
     pub id: String,
     pub validity: Vec<TrainValidityPeriod>,
-    pub cancellations: Vec<(TrainValidityPeriod, TrainSource)>,
+    pub cancellations: Vec<TrainCancellation>,
     pub replacements: Vec<Train>,
     pub variable_train: VariableTrain,
     pub source: Option<TrainSource>,
     pub runs_as_required: bool,
     pub performance_monitoring: Option<bool>,
-    pub route: Vec<TrainLocation>,
+    pub route: Route, // see cold_routes
+}
+
+fn cancellation_applies(cancellation: &TrainCancellation, date: NaiveDate) -> bool {
+    cancellation.validity.valid_begin.date_naive() <= date
+        && cancellation.validity.valid_end.date_naive() >= date
+        && cancellation
+            .validity
+            .days_of_week
+            .get_by_weekday(date.weekday())
+}
+
+pub fn get_cancellation(train: &Train, date: NaiveDate) -> Option<&TrainCancellation> {
+    train.cancellations.iter().find(|cancellation| {
+        cancellation.source != TrainSource::LongTerm && cancellation_applies(cancellation, date)
+    })
+}
+
+// whether the date is one the train's timetable leaves out, rather than one it's cancelled on
+pub fn left_out(train: &Train, date: NaiveDate) -> bool {
+    train.cancellations.iter().any(|cancellation| {
+        cancellation.source == TrainSource::LongTerm && cancellation_applies(cancellation, date)
+    })
+}
+
+// The version of an association that applies on a date, if any and it isn't cancelled
+pub fn get_association(assoc: &AssociationNode, date: NaiveDate) -> Option<AssociationNode> {
+    let mut final_assoc = None;
+    let mut cancelled = false;
+    for validity in &assoc.validity {
+        if validity.valid_begin.date_naive() <= date
+            && validity.valid_end.date_naive() >= date
+            && validity.days_of_week.get_by_weekday(date.weekday())
+        {
+            cancelled = false;
+            'replacement: for replacement in &assoc.replacements {
+                for validity in &replacement.validity {
+                    if validity.valid_begin.date_naive() <= date
+                        && validity.valid_end.date_naive() >= date
+                        && validity.days_of_week.get_by_weekday(date.weekday())
+                    {
+                        final_assoc = Some(replacement.clone());
+                        break 'replacement;
+                    }
+                }
+            }
+            if final_assoc.is_none() {
+                final_assoc = Some(assoc.clone());
+            }
+            for (cancellation, _source) in &assoc.cancellations {
+                if cancellation.valid_begin.date_naive() <= date
+                    && cancellation.valid_end.date_naive() >= date
+                    && cancellation.days_of_week.get_by_weekday(date.weekday())
+                {
+                    cancelled = true;
+                }
+            }
+        }
+    }
+
+    if final_assoc.is_none() || cancelled {
+        None
+    } else {
+        final_assoc
+    }
+}
+
+// The version of a train that runs on a date, if any, and whether it's cancelled or modified
+pub fn get_train_instance(trains: &Vec<Train>, date: NaiveDate) -> (Option<Train>, bool, bool) {
+    // let's make life easy and find the right train
+    let mut final_train = None;
+    let mut cancelled = false;
+    let mut modified = false;
+    for train in trains {
+        for validity in &train.validity {
+            if validity.valid_begin.date_naive() <= date
+                && validity.valid_end.date_naive() >= date
+                && validity.days_of_week.get_by_weekday(date.weekday())
+                && !left_out(train, date)
+            {
+                cancelled = false;
+                modified = false;
+                'replacement: for replacement in &train.replacements {
+                    for validity in &replacement.validity {
+                        if validity.valid_begin.date_naive() <= date
+                            && validity.valid_end.date_naive() >= date
+                            && validity.days_of_week.get_by_weekday(date.weekday())
+                        {
+                            final_train = Some(replacement.clone());
+                            modified = true;
+                            break 'replacement;
+                        }
+                    }
+                }
+                if final_train.is_none() {
+                    final_train = Some(train.clone());
+                }
+                if get_cancellation(train, date).is_some() {
+                    cancelled = true;
+                }
+            }
+        }
+    }
+
+    return (final_train, cancelled, modified);
+}
+
+fn applies_on(validity: &[TrainValidityPeriod], date: NaiveDate) -> bool {
+    validity.iter().any(|x| {
+        x.valid_begin.date_naive() <= date
+            && x.valid_end.date_naive() >= date
+            && x.days_of_week.get_by_weekday(date.weekday())
+    })
+}
+
+// The same decision as get_train_instance, but as indices rather than a copy of the train, for
+// when it's wanted for a lot of dates: which base schedule, which of its replacements if any, and
+// whether it's cancelled
+pub fn get_train_version(
+    trains: &[Train],
+    date: NaiveDate,
+) -> Option<(usize, Option<usize>, bool)> {
+    let mut version = None;
+    let mut cancelled = false;
+    for (i, train) in trains.iter().enumerate() {
+        if !applies_on(&train.validity, date) || left_out(train, date) {
+            continue;
+        }
+        match train
+            .replacements
+            .iter()
+            .position(|x| applies_on(&x.validity, date))
+        {
+            Some(x) => version = Some((i, Some(x))),
+            None if version.is_none() => version = Some((i, None)),
+            None => (),
+        }
+        cancelled = get_cancellation(train, date).is_some();
+    }
+    version.map(|(base, replacement)| (base, replacement, cancelled))
+}
+
+// The most days past its operating date any version of a train gets, e.g. 1 for one leaving late
+// in the evening and arriving after midnight. Overlays can run later than the base schedule.
+pub fn max_day_offset(versions: &[Train]) -> u8 {
+    versions
+        .iter()
+        .flat_map(|x| std::iter::once(x).chain(x.replacements.iter()))
+        .flat_map(|x| x.route.iter())
+        .flat_map(|x| {
+            [
+                x.working_arr_day,
+                x.working_dep_day,
+                x.working_pass_day,
+                x.public_arr_day,
+                x.public_dep_day,
+            ]
+        })
+        .flatten()
+        .max()
+        .unwrap_or(0)
+}
+
+// The operating dates a train could be anywhere on calendar dates first to last: those, and as
+// many before as it runs past midnight, so yesterday's trains still going after midnight count
+pub fn operating_dates_between(
+    versions: &[Train],
+    first: NaiveDate,
+    last: NaiveDate,
+) -> impl Iterator<Item = NaiveDate> {
+    let first = first
+        .checked_sub_days(Days::new(max_day_offset(versions).into()))
+        .unwrap_or(first);
+    first.iter_days().take_while(move |x| *x <= last)
+}
+
+// A train at a location on a board, by the operating date it's there on
+#[derive(Clone, Debug, PartialEq)]
+pub struct BoardCall {
+    pub train_id: String,
+    pub operating_date: NaiveDate,
+    pub location_index: usize, // in the route of the version running that day
+    pub time: NaiveDateTime,
+}
+
+impl Schedule {
+    // Everything at any of these locations between two times, cancelled or not, in time order.
+    // Trains are looked at on each operating date that could put them there, including the
+    // previous day's for calls after midnight.
+    pub fn calls_between(
+        &self,
+        location_ids: &HashSet<String>,
+        start: NaiveDateTime,
+        end: NaiveDateTime,
+    ) -> Vec<BoardCall> {
+        let train_ids = location_ids
+            .iter()
+            .filter_map(|x| self.trains_indexed_by_location.get(x))
+            .flatten()
+            .collect::<HashSet<_>>();
+        let mut calls = vec![];
+        for train_id in train_ids {
+            let versions = match self.trains.get(train_id) {
+                Some(x) if !x.is_empty() => x,
+                _ => continue,
+            };
+            for date in operating_dates_between(versions, start.date(), end.date()) {
+                let train = match get_train_instance(versions, date) {
+                    (Some(x), _, _) => x,
+                    _ => continue,
+                };
+                for (i, location) in train.route.iter().enumerate() {
+                    if !location_ids.contains(location.id.as_str()) {
+                        continue;
+                    }
+                    match location.board_time(date) {
+                        Some(x) if x >= start && x <= end => calls.push(BoardCall {
+                            train_id: train_id.clone(),
+                            operating_date: date,
+                            location_index: i,
+                            time: x,
+                        }),
+                        _ => (),
+                    }
+                }
+            }
+        }
+        calls.sort_by(|a, b| {
+            a.time
+                .cmp(&b.time)
+                .then_with(|| a.train_id.cmp(&b.train_id))
+        });
+        calls
+    }
+}
+
+fn ended_before(validity: &[TrainValidityPeriod], cutoff: NaiveDate) -> bool {
+    validity.iter().all(|x| x.valid_end.date_naive() < cutoff)
+}
+
+impl AssociationNode {
+    fn garbage_collect(&mut self, cutoff: NaiveDate) -> usize {
+        let before = self.cancellations.len() + self.replacements.len();
+        self.cancellations
+            .retain(|(x, _)| x.valid_end.date_naive() >= cutoff);
+        self.replacements
+            .retain(|x| !ended_before(&x.validity, cutoff));
+        before - self.cancellations.len() - self.replacements.len()
+    }
+}
+
+fn garbage_collect_assocs(assocs: &mut Vec<AssociationNode>, cutoff: NaiveDate) -> usize {
+    let before = assocs.len();
+    assocs.retain(|x| !ended_before(&x.validity, cutoff));
+    let mut removed = before - assocs.len();
+    for assoc in assocs.iter_mut() {
+        removed += assoc.garbage_collect(cutoff);
+    }
+    removed
+}
+
+// whether garbage_collect_assoc(s) would drop anything from here, without writing to it
+fn has_garbage_assoc(assoc: &AssociationNode, cutoff: NaiveDate) -> bool {
+    ended_before(&assoc.validity, cutoff)
+        || assoc
+            .cancellations
+            .iter()
+            .any(|(x, _)| x.valid_end.date_naive() < cutoff)
+        || assoc
+            .replacements
+            .iter()
+            .any(|x| ended_before(&x.validity, cutoff))
+}
+
+fn has_garbage_assocs(location: &TrainLocation, cutoff: NaiveDate) -> bool {
+    location
+        .divides_to_form
+        .iter()
+        .chain(&location.joins_to)
+        .chain(&location.becomes)
+        .chain(&location.divides_from)
+        .chain(&location.is_joined_to_by)
+        .chain(&location.forms_from)
+        .any(|x| has_garbage_assoc(x, cutoff))
+}
+
+fn garbage_collect_assoc(assoc: &mut Option<AssociationNode>, cutoff: NaiveDate) -> usize {
+    match assoc {
+        Some(x) if ended_before(&x.validity, cutoff) => {
+            *assoc = None;
+            1
+        }
+        Some(x) => x.garbage_collect(cutoff),
+        None => 0,
+    }
+}
+
+impl Train {
+    // Drops overlays, cancellations and associations which finished before `cutoff`, and flattens
+    // replacements of replacements into our own list. Validity periods are never trimmed, as
+    // updates find things to change by their first one. Returns how much was dropped.
+    pub fn garbage_collect(&mut self, cutoff: NaiveDate) -> usize {
+        let mut removed = 0;
+
+        let before = self.cancellations.len();
+        self.cancellations
+            .retain(|x| x.validity.valid_end.date_naive() >= cutoff);
+        removed += before - self.cancellations.len();
+
+        let mut flattened = vec![];
+        for mut replacement in self.replacements.drain(..) {
+            if ended_before(&replacement.validity, cutoff) {
+                removed += 1;
+                continue;
+            }
+            removed += replacement.garbage_collect(cutoff);
+            // these were laid on top of the replacement, so they have to be found before it
+            flattened.append(&mut replacement.replacements);
+            flattened.push(replacement);
+        }
+        self.replacements = flattened;
+
+        // most routes have nothing to drop, and writing to one decompresses it if it's cold
+        if !self
+            .route
+            .peek(|route| route.iter().any(|x| has_garbage_assocs(x, cutoff)))
+        {
+            return removed;
+        }
+        for location in self.route.iter_mut() {
+            removed += garbage_collect_assocs(&mut location.divides_to_form, cutoff);
+            removed += garbage_collect_assocs(&mut location.joins_to, cutoff);
+            removed += garbage_collect_assoc(&mut location.becomes, cutoff);
+            removed += garbage_collect_assocs(&mut location.divides_from, cutoff);
+            removed += garbage_collect_assocs(&mut location.is_joined_to_by, cutoff);
+            removed += garbage_collect_assoc(&mut location.forms_from, cutoff);
+        }
+
+        removed
+    }
+}
+
+impl Schedule {
+    // Over a long timetable period overlays pile up; this reclaims everything that stopped
+    // mattering before `cutoff`. Train IDs stay in the map even if all their schedules go, as
+    // with deletions.
+    pub fn garbage_collect(&mut self, cutoff: NaiveDate) -> usize {
+        let mut removed = 0;
+        for trains in self.trains.values_mut() {
+            let before = trains.len();
+            trains.retain(|x| !ended_before(&x.validity, cutoff));
+            removed += before - trains.len();
+            for train in trains.iter_mut() {
+                removed += train.garbage_collect(cutoff);
+            }
+        }
+        // what went may have been all that called somewhere, and there's usually a lot of it, so
+        // start the indexes afresh rather than going train by train
+        if removed > 0 {
+            self.rebuild_train_indexes();
+        }
+        for pending in self.pending_associations.values_mut() {
+            let before = pending.len();
+            pending.retain(|x| !ended_before(&x.association.validity, cutoff));
+            removed += before - pending.len();
+        }
+        self.pending_associations.retain(|_, x| !x.is_empty());
+        for pending in self.pending_overlays.values_mut() {
+            let before = pending.len();
+            pending.retain(|x| !ended_before(&x.overlay.validity, cutoff));
+            removed += before - pending.len();
+        }
+        self.pending_overlays.retain(|_, x| !x.is_empty());
+        removed
+    }
+
+    // Importers make a new string for every TIPLOC, platform and so on they read, so after a full
+    // import there are millions of copies of the same few thousand. This shares them out.
+    pub fn intern_strings(&mut self) -> InternStats {
+        let mut interner = Interner::new();
+        for trains in self.trains.values_mut() {
+            for train in trains.iter_mut() {
+                train.intern_strings(&mut interner);
+            }
+        }
+        interner.stats()
+    }
+
+    // Some feeds (NIR's in particular) list stations a train runs through without stopping but
+    // give no time for them, which leaves them off boards and confuses anything that wants a time
+    // for every location. This gives them a passing time somewhere between the timed locations
+    // either side: by distance if we know where everything is, otherwise evenly spaced. They're
+    // marked with times_inferred so nobody mistakes them for the timetable. Returns how many.
+    pub fn infer_passing_times(&mut self) -> usize {
+        let mut inferred = 0;
+        for trains in self.trains.values_mut() {
+            for train in trains.iter_mut() {
+                inferred += train.infer_passing_times(&self.locations);
+            }
+        }
+        inferred
+    }
+}
+
+// seconds since midnight on the day the train starts, of the first time we have for a location
+fn elapsed_secs(location: &TrainLocation, departure: bool) -> Option<i64> {
+    let times = [
+        (location.working_arr, location.working_arr_day),
+        (location.public_arr, location.public_arr_day),
+        (location.working_pass, location.working_pass_day),
+        (location.working_dep, location.working_dep_day),
+        (location.public_dep, location.public_dep_day),
+    ];
+    // leaving somewhere we want the departure, arriving the arrival
+    let mut order = times.iter().collect::<Vec<_>>();
+    if departure {
+        order.reverse();
+    }
+    order.into_iter().find_map(|(time, day)| {
+        let time = (*time)?;
+        Some(i64::from(day.unwrap_or(0)) * 86400 + i64::from(time.num_seconds_from_midnight()))
+    })
+}
+
+fn intern_variable_train(variable_train: &mut VariableTrain, interner: &mut Interner) {
+    for allocation in [
+        &mut variable_train.timing_allocation,
+        &mut variable_train.actual_allocation,
+    ]
+    .into_iter()
+    .flatten()
+    {
+        interner.intern(&mut allocation.id);
+        interner.intern(&mut allocation.description);
+    }
+    match &mut variable_train.operator {
+        Some(x) => {
+            interner.intern(&mut x.id);
+            interner.intern_option(&mut x.description);
+        }
+        None => (),
+    }
+}
+
+fn intern_assoc(assoc: &mut AssociationNode, interner: &mut Interner) {
+    interner.intern(&mut assoc.other_train_id);
+    for replacement in assoc.replacements.iter_mut() {
+        intern_assoc(replacement, interner);
+    }
+}
+
+impl Train {
+    // The calendar date it leaves its origin on, which is the day after the operating date for a
+    // service timetabled past midnight
+    pub fn departure_date(&self, operating_date: NaiveDate) -> Option<NaiveDate> {
+        calendar_date(operating_date, self.route.first()?.day_offset())
+    }
+
+    // Which operating date has it leaving its origin on this calendar date
+    pub fn operating_date_for_departure(&self, departure_date: NaiveDate) -> Option<NaiveDate> {
+        operating_date(departure_date, self.route.first()?.day_offset())
+    }
+
+    fn infer_passing_times(&mut self, locations: &PartitionedMap<Location>) -> usize {
+        let mut inferred = 0;
+        let mut last_timed: Option<usize> = None;
+        for i in 0..self.route.len() {
+            if elapsed_secs(&self.route[i], false).is_none() {
+                continue;
+            }
+            // anything untimed between here and the last timed location; untimed locations
+            // before the first or after the last have nothing to go on, so are left alone
+            match last_timed {
+                Some(start) if i > start + 1 => {
+                    inferred += self.interpolate(start, i, locations);
+                }
+                _ => (),
+            }
+            last_timed = Some(i);
+        }
+        for replacement in self.replacements.iter_mut() {
+            inferred += replacement.infer_passing_times(locations);
+        }
+        inferred
+    }
+
+    fn interpolate(
+        &mut self,
+        start: usize,
+        end: usize,
+        locations: &PartitionedMap<Location>,
+    ) -> usize {
+        let from = elapsed_secs(&self.route[start], true).unwrap();
+        let to = elapsed_secs(&self.route[end], false).unwrap();
+        if to < from {
+            return 0; // nonsense, so best not to make it worse
+        }
+
+        // how far along each location is, by distance if we can, otherwise by count
+        let positions = self.route[start..=end]
+            .iter()
+            .map(|x| locations.get(x.id.as_str()).and_then(|x| x.position))
+            .collect::<Option<Vec<_>>>();
+        let mut along = vec![0.0];
+        match positions {
+            Some(positions) => {
+                for pair in positions.windows(2) {
+                    along.push(along.last().unwrap() + pair[0].distance_m(&pair[1]));
+                }
+            }
+            None => along.extend((1..=end - start).map(|x| x as f64)),
+        }
+        let mut total = *along.last().unwrap();
+        if total <= 0.0 {
+            // all in the same place, as far as we know
+            along = (0..=end - start).map(|x| x as f64).collect();
+            total = (end - start) as f64;
+        }
+
+        for (offset, location) in self.route[start + 1..end].iter_mut().enumerate() {
+            let secs = from + ((to - from) as f64 * along[offset + 1] / total).round() as i64;
+            location.working_pass =
+                NaiveTime::from_num_seconds_from_midnight_opt((secs % 86400) as u32, 0);
+            location.working_pass_day = Some((secs / 86400) as u8);
+            location.activities.times_inferred = true;
+        }
+        end - start - 1
+    }
+
+    fn intern_strings(&mut self, interner: &mut Interner) {
+        intern_variable_train(&mut self.variable_train, interner);
+        // a compressed route was interned as it was compressed, and writing to it would decompress
+        // it for good
+        if !self.route.is_compressed() {
+            intern_route(&mut self.route, interner);
+        }
+        for replacement in self.replacements.iter_mut() {
+            replacement.intern_strings(interner);
+        }
+    }
+}
+
+// also for cold_routes, which has to intern routes again as it decompresses them
+pub(crate) fn intern_route(route: &mut [TrainLocation], interner: &mut Interner) {
+    for location in route.iter_mut() {
+        interner.intern(&mut location.id);
+        interner.intern_option(&mut location.scheduled_platform);
+        interner.intern_option(&mut location.platform_zone);
+        interner.intern_option(&mut location.line);
+        interner.intern_option(&mut location.path);
+        match &mut location.change_en_route {
+            Some(x) => intern_variable_train(x, interner),
+            None => (),
+        }
+        for assoc in location
+            .divides_to_form
+            .iter_mut()
+            .chain(location.joins_to.iter_mut())
+            .chain(location.becomes.iter_mut())
+            .chain(location.divides_from.iter_mut())
+            .chain(location.is_joined_to_by.iter_mut())
+            .chain(location.forms_from.iter_mut())
+        {
+            intern_assoc(assoc, interner);
+        }
+    }
+}
+
+// chrono writes DateTime<Tz> as an RFC 3339 string, which is what the web UI and API want, but it
+// only keeps the offset and can't be read back. Binary formats (i.e. snapshots) get the timestamp
+// and zone name instead so that schedules round-trip exactly.
+mod tz_datetime {
+    use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+    use chrono_tz::Tz;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub fn serialize<S: Serializer>(
+        datetime: &DateTime<Tz>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            datetime.serialize(serializer)
+        } else {
+            (
+                datetime.timestamp(),
+                datetime.timestamp_subsec_nanos(),
+                datetime.timezone(),
+            )
+                .serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Tz>, D::Error> {
+        if deserializer.is_human_readable() {
+            // the zone name is lost, so this is the best we can do
+            let datetime = DateTime::<FixedOffset>::deserialize(deserializer)?;
+            Ok(datetime.with_timezone(&Tz::UTC))
+        } else {
+            let (secs, nanos, timezone) = <(i64, u32, Tz)>::deserialize(deserializer)?;
+            match Utc.timestamp_opt(secs, nanos).single() {
+                Some(x) => Ok(x.with_timezone(&timezone)),
+                None => Err(D::Error::custom(format!("Invalid timestamp {}", secs))),
+            }
+        }
+    }
+}
+
+mod option_tz_datetime {
+    use chrono::DateTime;
+    use chrono_tz::Tz;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    struct Wrapper<'a>(&'a DateTime<Tz>);
+
+    impl Serialize for Wrapper<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            super::tz_datetime::serialize(self.0, serializer)
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct OwnedWrapper(#[serde(with = "super::tz_datetime")] DateTime<Tz>);
+
+    pub fn serialize<S: Serializer>(
+        datetime: &Option<DateTime<Tz>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        datetime.as_ref().map(Wrapper).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Tz>>, D::Error> {
+        Ok(Option::<OwnedWrapper>::deserialize(deserializer)?.map(|x| x.0))
+    }
 }
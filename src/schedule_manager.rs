@@ -1,61 +1,295 @@
+use crate::area_filter::AreaFilter;
+use crate::change_log::{ChangeLog, TrainChange};
+use crate::cold_routes::ColdRoutes;
+use crate::error::Error;
 use crate::schedule::Schedule;
 
+use chrono::{DateTime, Utc};
+
 use tokio::sync::{Mutex, OwnedMutexGuard};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::ops::{Deref, DerefMut};
-use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
 
-pub struct ImmediateWriter<'a> {
-    schedules: RwLockWriteGuard<'a, HashMap<String, Schedule>>,
+// Schedules are kept behind Arcs and never modified in place: writers work on their own copy of
+// whichever schedule they're changing and swap it in on commit, so the RwLock is only ever held
+// for long enough to copy or swap some pointers, and readers never wait on an import. A copy
+// shares everything big with the original (see partitioned_map.rs) until it's changed.
+pub struct TransactionalWriter {
+    new_schedules: HashMap<String, Arc<Schedule>>,
+    schedules_ref: Arc<RwLock<HashMap<String, Arc<Schedule>>>>,
+    generation_ref: Arc<AtomicU64>,
+    namespace_generations_ref: Arc<RwLock<HashMap<String, u64>>>,
+    history_ref: Arc<RwLock<History>>,
+    hooks_ref: Arc<RwLock<Hooks>>,
+    sources_ref: Arc<RwLock<HashMap<String, DateTime<Utc>>>>,
+    change_log_ref: Arc<ChangeLog>,
+    area_filter: Option<Arc<AreaFilter>>,
+    cold_routes: Option<Arc<ColdRoutes>>,
+    archive: bool,
+    imported: Vec<String>, // namespaces put() here, which count as imported if this is archived
+    changed_trains: Vec<(String, String)>, // namespace and train ID
+    changes: Vec<(String, String, TrainChange)>, // namespace, train ID and what happened to it
+    updated_sources: Vec<String>,
     _transaction_lock: OwnedMutexGuard<()>,
 }
 
-impl Deref for ImmediateWriter<'_> {
-    type Target = HashMap<String, Schedule>;
+impl Deref for TransactionalWriter {
+    type Target = HashMap<String, Arc<Schedule>>;
 
     fn deref(&self) -> &Self::Target {
-        &self.schedules
+        &self.new_schedules
     }
 }
 
-impl DerefMut for ImmediateWriter<'_> {
+impl DerefMut for TransactionalWriter {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.schedules
+        &mut self.new_schedules
     }
 }
 
-pub struct TransactionalWriter {
-    new_schedules: HashMap<String, Schedule>,
-    schedules_ref: Arc<RwLock<HashMap<String, Schedule>>>,
-    _transaction_lock: OwnedMutexGuard<()>,
-}
+impl TransactionalWriter {
+    // Gets our own copy of a schedule to modify, unless nobody else can see it. Only the parts that
+    // are then changed are really copied.
+    pub fn take(&mut self, namespace: &str) -> Option<Schedule> {
+        self.new_schedules
+            .remove(namespace)
+            .map(Arc::unwrap_or_clone)
+    }
 
-impl Deref for TransactionalWriter {
-    type Target = HashMap<String, Schedule>;
+    pub fn put(&mut self, namespace: &str, schedule: Schedule) {
+        self.imported.push(namespace.to_string());
+        self.new_schedules
+            .insert(namespace.to_string(), Arc::new(schedule));
+    }
 
-    fn deref(&self) -> &Self::Target {
-        &self.new_schedules
+    // Call this for full imports and daily updates, so whatever they replace is kept around for
+    // as-of queries. Small updates (VSTP, TRUST) just change the current generation.
+    pub fn archive(&mut self) {
+        self.archive = true;
     }
-}
 
-impl DerefMut for TransactionalWriter {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.new_schedules
+    // for the on_train_changed hooks, which run once this commits
+    pub fn train_changed(&mut self, namespace: &str, train_id: &str) {
+        self.changed_trains
+            .push((namespace.to_string(), train_id.to_string()));
+    }
+
+    // for the change log, which is only written to once this commits
+    pub fn log_change(&mut self, namespace: &str, train_id: &str, change: TrainChange) {
+        self.changes
+            .push((namespace.to_string(), train_id.to_string(), change));
+    }
+
+    // Says something new came in from a source, e.g. "gbnr-vstp", for the staleness checks. Only
+    // counts once this commits, so a failed import doesn't.
+    pub fn source_updated(&mut self, source: &str) {
+        self.updated_sources.push(source.to_string());
+    }
+
+    // Runs whatever put() schedules go through before anyone can see them. Only at commit, as
+    // archive() and train_changed() can come either side of put(). Only the trains changed with
+    // train_changed() are checked against the area filter or compressed, unless this is a full
+    // import.
+    fn prepare(&mut self) {
+        let mut namespaces = self.imported.clone();
+        namespaces.sort();
+        namespaces.dedup();
+        for namespace in namespaces {
+            let schedule = match self.new_schedules.get_mut(&namespace) {
+                Some(x) => Arc::make_mut(x),
+                None => continue,
+            };
+            let changed = self
+                .changed_trains
+                .iter()
+                .filter(|(x, _)| x == &namespace)
+                .map(|(_, y)| y.clone())
+                .collect::<HashSet<_>>();
+            match &self.area_filter {
+                Some(area_filter) => {
+                    let changed = match self.archive {
+                        true => None,
+                        false => Some(&changed),
+                    };
+                    let dropped = area_filter.apply(schedule, changed);
+                    if dropped > 0 && changed.is_none() {
+                        println!(
+                            "Dropped {} trains outside the area from {}",
+                            dropped, namespace
+                        );
+                    }
+                }
+                None => (),
+            }
+            match &self.cold_routes {
+                Some(cold_routes) if cold_routes.wants(&namespace) => match self.archive {
+                    true => {
+                        let stats = cold_routes.apply(schedule);
+                        println!("Compressed {} for {}", stats, namespace);
+                    }
+                    false => {
+                        cold_routes.apply_changed(schedule, &changed);
+                    }
+                },
+                _ => (),
+            }
+        }
+    }
+
+    pub fn commit(mut self) {
+        self.prepare();
+
+        // the same time for everything, so a schedule's last_imported is exactly when as_of()
+        // starts finding it
+        let now = Utc::now();
+        // only full imports and daily updates count for last_imported, not every VSTP or TRUST
+        // message, which would make it just say when the last train ran
+        if self.archive {
+            for namespace in &self.imported {
+                match self.new_schedules.get_mut(namespace) {
+                    Some(x) => Arc::make_mut(x).last_imported = Some(now),
+                    None => (),
+                }
+            }
+        }
+
+        let mut imported = vec![];
+        {
+            let mut schedules = self.schedules_ref.write().unwrap();
+            let mut sources = self.sources_ref.write().unwrap();
+            for source in &self.updated_sources {
+                sources.insert(source.clone(), now);
+            }
+            let mut history = self.history_ref.write().unwrap();
+            let mut changed = schedules
+                .keys()
+                .filter(|x| !self.new_schedules.contains_key(*x))
+                .cloned()
+                .collect::<Vec<_>>();
+            for (namespace, schedule) in &self.new_schedules {
+                match schedules.get(namespace) {
+                    Some(x) if Arc::ptr_eq(x, schedule) => (),
+                    _ => changed.push(namespace.clone()),
+                }
+                match schedules.get(namespace) {
+                    Some(x) if self.archive && !Arc::ptr_eq(x, schedule) => {
+                        history.archive(namespace, x.clone(), now);
+                        imported.push((namespace.clone(), schedule.clone()));
+                    }
+                    Some(_) => (),
+                    None => {
+                        history.current_since.insert(namespace.clone(), now);
+                        imported.push((namespace.clone(), schedule.clone()));
+                    }
+                }
+            }
+            *schedules = self.new_schedules;
+            // only once they're in, so nothing's cached from the old ones under the new generation
+            let generation = self.generation_ref.load(Ordering::SeqCst) + 1;
+            let mut namespace_generations = self.namespace_generations_ref.write().unwrap();
+            for namespace in changed {
+                namespace_generations.insert(namespace, generation);
+            }
+            self.generation_ref.store(generation, Ordering::SeqCst);
+        }
+        for (namespace, train_id, change) in self.changes {
+            self.change_log_ref.record(&namespace, &train_id, change);
+        }
+
+        // with the locks dropped, so hooks can read the schedules
+        let hooks = self.hooks_ref.read().unwrap();
+        for (namespace, schedule) in &imported {
+            for hook in &hooks.import_complete {
+                hook(namespace, schedule);
+            }
+        }
+        for (namespace, train_id) in &self.changed_trains {
+            for hook in &hooks.train_changed {
+                hook(namespace, train_id);
+            }
+        }
     }
 }
 
-impl TransactionalWriter {
-    pub fn commit(self) {
-        let mut schedules = self.schedules_ref.write().unwrap();
-        *schedules = self.new_schedules
+type ImportHook = Box<dyn Fn(&str, &Arc<Schedule>) + Send + Sync>;
+type TrainHook = Box<dyn Fn(&str, &str) + Send + Sync>;
+type FailureHook = Box<dyn Fn(&str, &Error) + Send + Sync>;
+
+// Callbacks for anyone embedding us who wants to know when things change, e.g. to invalidate their
+// own caches or publish to a message bus. They're called synchronously from whatever made the
+// change, so anything slow should be handed off to a task of its own.
+#[derive(Default)]
+struct Hooks {
+    import_complete: Vec<ImportHook>,
+    train_changed: Vec<TrainHook>,
+    source_failure: Vec<FailureHook>,
+}
+
+struct Generation {
+    since: DateTime<Utc>,
+    until: DateTime<Utc>,
+    schedule: Arc<Schedule>,
+}
+
+// Previous generations of each namespace, oldest first. Each is as it was just before it was
+// replaced, so includes any small updates made to it along the way.
+#[derive(Default)]
+struct History {
+    max_generations: usize,
+    generations: HashMap<String, VecDeque<Generation>>,
+    current_since: HashMap<String, DateTime<Utc>>,
+}
+
+impl History {
+    fn archive(&mut self, namespace: &str, schedule: Arc<Schedule>, now: DateTime<Utc>) {
+        let since = self
+            .current_since
+            .insert(namespace.to_string(), now)
+            .unwrap_or(now);
+        if self.max_generations == 0 {
+            return;
+        }
+        let generations = self.generations.entry(namespace.to_string()).or_default();
+        generations.push_back(Generation {
+            since,
+            until: now,
+            schedule,
+        });
+        while generations.len() > self.max_generations {
+            generations.pop_front();
+        }
+    }
+
+    fn get(&self, namespace: &str, as_of: DateTime<Utc>) -> Option<Option<Arc<Schedule>>> {
+        for generation in self.generations.get(namespace)? {
+            if as_of < generation.since {
+                return Some(None); // from before anything we kept
+            }
+            if as_of < generation.until {
+                return Some(Some(generation.schedule.clone()));
+            }
+        }
+        None
     }
 }
 
 #[derive(Default)]
 pub struct ScheduleManager {
-    schedules: Arc<RwLock<HashMap<String, Schedule>>>,
+    schedules: Arc<RwLock<HashMap<String, Arc<Schedule>>>>,
+    generation: Arc<AtomicU64>, // bumped on every commit, so caches know when to throw things away
+    namespace_generations: Arc<RwLock<HashMap<String, u64>>>, // the generation each last changed in
     transaction_lock: Arc<Mutex<()>>,
+    history: Arc<RwLock<History>>,
+    hooks: Arc<RwLock<Hooks>>,
+    sources: Arc<RwLock<HashMap<String, DateTime<Utc>>>>, // when each last brought anything new
+    area_filter: Arc<RwLock<Option<Arc<AreaFilter>>>>,
+    cold_routes: Arc<RwLock<Option<Arc<ColdRoutes>>>>,
+    change_log: Arc<ChangeLog>,
+    as_of: Option<DateTime<Utc>>, // set if this is a view of the past from as_of()
+    environment: Option<String>,  // set if this isn't the main environment
 }
 
 impl ScheduleManager {
@@ -65,27 +299,198 @@ impl ScheduleManager {
         }
     }
 
-    pub fn read(&self) -> RwLockReadGuard<HashMap<String, Schedule>> {
-        self.schedules.read().unwrap()
+    // keeps the last `max_generations` imports of each namespace, as well as the current one
+    pub fn new_with_history(max_generations: usize) -> Self {
+        let manager = Self::new();
+        manager.history.write().unwrap().max_generations = max_generations;
+        manager
     }
 
-    pub async fn immediate_write<'a>(&'a self) -> ImmediateWriter<'a> {
-        let trans_lock = self.transaction_lock.clone().lock_owned().await;
+    // for an environment other than the main one, see environments
+    pub fn new_for_environment(environment: &str, max_generations: usize) -> Self {
+        let mut manager = Self::new_with_history(max_generations);
+        manager.environment = Some(environment.to_string());
+        manager
+    }
 
-        ImmediateWriter {
-            schedules: self.schedules.write().unwrap(),
-            _transaction_lock: trans_lock,
+    // A read-only view of the schedules as they were at the time, leaving out any namespace we
+    // don't have that far back. Shares the generation counter with us, so caches still work.
+    pub fn as_of(&self, as_of: DateTime<Utc>) -> ScheduleManager {
+        let schedules = {
+            let history = self.history.read().unwrap();
+            let current = self.schedules.read().unwrap();
+            current
+                .iter()
+                .filter_map(|(namespace, schedule)| {
+                    let schedule = match history.get(namespace, as_of) {
+                        Some(x) => x?,
+                        None => {
+                            if history.current_since.get(namespace)? > &as_of {
+                                return None;
+                            }
+                            schedule.clone()
+                        }
+                    };
+                    Some((namespace.clone(), schedule))
+                })
+                .collect()
+        };
+        ScheduleManager {
+            schedules: Arc::new(RwLock::new(schedules)),
+            generation: self.generation.clone(),
+            namespace_generations: self.namespace_generations.clone(),
+            transaction_lock: Arc::new(Mutex::new(())),
+            history: Arc::new(RwLock::new(History::default())),
+            hooks: Arc::new(RwLock::new(Hooks::default())),
+            sources: self.sources.clone(),
+            area_filter: Arc::new(RwLock::new(None)),
+            cold_routes: Arc::new(RwLock::new(None)),
+            change_log: self.change_log.clone(),
+            as_of: Some(as_of),
+            environment: self.environment.clone(),
         }
     }
 
+    // Anything committed from now on only keeps trains in the filter's area
+    pub fn set_area_filter(&self, area_filter: AreaFilter) {
+        *self.area_filter.write().unwrap() = Some(Arc::new(area_filter));
+    }
+
+    pub fn set_cold_routes(&self, cold_routes: ColdRoutes) {
+        *self.cold_routes.write().unwrap() = Some(Arc::new(cold_routes));
+    }
+
+    pub fn cold_routes_due(&self) -> bool {
+        match &*self.cold_routes.read().unwrap() {
+            Some(x) => x.due(),
+            None => false,
+        }
+    }
+
+    // Routes decompressed since a namespace was last written stay that way until it's next
+    // written, which for some is only once a day, so call this every so often, and whenever
+    // cold_routes_due() says, to drop them. The trains themselves don't change, so neither does the
+    // generation.
+    pub async fn compress_cold_routes(&self) {
+        let cold_routes = match self.cold_routes.read().unwrap().clone() {
+            Some(x) => x,
+            None => return,
+        };
+        let _lock = self.transaction_lock.lock().await;
+        let schedules = self.schedules.read().unwrap().clone();
+        for (namespace, schedule) in schedules {
+            if !cold_routes.wants(&namespace) || !cold_routes.over_limit(&schedule) {
+                continue;
+            }
+            // always a copy, as the one in use can't be changed under its readers, but one that
+            // shares everything apply() doesn't change with it
+            let mut schedule = Arc::unwrap_or_clone(schedule);
+            cold_routes.apply(&mut schedule);
+            self.schedules
+                .write()
+                .unwrap()
+                .insert(namespace, Arc::new(schedule));
+        }
+    }
+
+    pub fn get_as_of(&self) -> Option<DateTime<Utc>> {
+        self.as_of
+    }
+
+    pub fn environment(&self) -> Option<&str> {
+        self.environment.as_deref()
+    }
+
+    // what realtime feeds have done to each train, which isn't kept in the schedules themselves
+    pub fn change_log(&self) -> &ChangeLog {
+        &self.change_log
+    }
+
+    // after a full import or daily update of a namespace is committed, with the new schedule, which
+    // hooks can keep hold of to do something slow with on another task
+    pub fn on_import_complete(&self, hook: impl Fn(&str, &Arc<Schedule>) + Send + Sync + 'static) {
+        self.hooks
+            .write()
+            .unwrap()
+            .import_complete
+            .push(Box::new(hook));
+    }
+
+    // after a small update (e.g. VSTP or TRUST) to a train is committed, with its namespace and ID
+    pub fn on_train_changed(&self, hook: impl Fn(&str, &str) + Send + Sync + 'static) {
+        self.hooks
+            .write()
+            .unwrap()
+            .train_changed
+            .push(Box::new(hook));
+    }
+
+    // when a source fails, with its name, whether or not it's going to be retried
+    pub fn on_source_failure(&self, hook: impl Fn(&str, &Error) + Send + Sync + 'static) {
+        self.hooks
+            .write()
+            .unwrap()
+            .source_failure
+            .push(Box::new(hook));
+    }
+
+    pub fn source_failed(&self, source: &str, error: &Error) {
+        for hook in &self.hooks.read().unwrap().source_failure {
+            hook(source, error);
+        }
+    }
+
+    // when each source last had something committed, by name
+    pub fn source_updates(&self) -> HashMap<String, DateTime<Utc>> {
+        self.sources.read().unwrap().clone()
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, HashMap<String, Arc<Schedule>>> {
+        self.schedules.read().unwrap()
+    }
+
+    // for readers that want to spend a while with a schedule without holding up anyone else
+    pub fn get(&self, namespace: &str) -> Option<Arc<Schedule>> {
+        self.schedules.read().unwrap().get(namespace).cloned()
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    // The generation a namespace last changed in, for caching anything that only reads that one,
+    // so a VSTP message for one doesn't throw away everything cached for the others
+    pub fn namespace_generation(&self, namespace: &str) -> u64 {
+        self.namespace_generations
+            .read()
+            .unwrap()
+            .get(namespace)
+            .copied()
+            .unwrap_or(0)
+    }
+
     pub async fn transactional_write(&self) -> TransactionalWriter {
         let trans_lock = self.transaction_lock.clone().lock_owned().await;
 
+        // only clones the pointers
         let schedules = self.schedules.read().unwrap();
 
         TransactionalWriter {
             new_schedules: schedules.clone(),
             schedules_ref: self.schedules.clone(),
+            generation_ref: self.generation.clone(),
+            namespace_generations_ref: self.namespace_generations.clone(),
+            history_ref: self.history.clone(),
+            hooks_ref: self.hooks.clone(),
+            sources_ref: self.sources.clone(),
+            change_log_ref: self.change_log.clone(),
+            area_filter: self.area_filter.read().unwrap().clone(),
+            cold_routes: self.cold_routes.read().unwrap().clone(),
+            archive: false,
+            imported: vec![],
+            changed_trains: vec![],
+            changes: vec![],
+            updated_sources: vec![],
             _transaction_lock: trans_lock,
         }
     }
@@ -0,0 +1,78 @@
+use crate::intern::IStr;
+use crate::schedule::{Train, VariableTrain};
+
+use serde::Serialize;
+
+// A train's route split up wherever its details change en route, e.g. one operator handing over to
+// another, or a sleeper losing its sleeping cars partway, so clients can show that without having
+// to compare the change_en_route on every location themselves.
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RouteSegment {
+    pub from: IStr, // where these details start applying
+    pub from_suffix: Option<String>,
+    pub to: IStr, // and the next change, or the destination
+    pub to_suffix: Option<String>,
+    pub operator_changed: bool, // from the segment before, which the first never is
+    pub changed: Vec<String>, // names of the fields in variable_train that differ from the segment before
+    pub variable_train: VariableTrain,
+}
+
+// the top level fields that differ, by name
+fn differences(before: &VariableTrain, after: &VariableTrain) -> Vec<String> {
+    let before = serde_json::to_value(before).unwrap();
+    let after = serde_json::to_value(after).unwrap();
+    let (before, after) = match (before.as_object(), after.as_object()) {
+        (Some(x), Some(y)) => (x.clone(), y.clone()),
+        _ => return vec![],
+    };
+    let mut changed = after
+        .iter()
+        .filter(|(name, value)| before.get(*name) != Some(value))
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    changed.sort();
+    changed
+}
+
+// Always at least one, for the whole route, unless there's no route at all. A change that doesn't
+// actually change anything doesn't start a new segment.
+pub fn route_segments(train: &Train) -> Vec<RouteSegment> {
+    let (first, last) = match (train.route.first(), train.route.last()) {
+        (Some(x), Some(y)) => (x, y),
+        _ => return vec![],
+    };
+    let mut segments = vec![RouteSegment {
+        from: first.id.clone(),
+        from_suffix: first.id_suffix.clone(),
+        to: last.id.clone(),
+        to_suffix: last.id_suffix.clone(),
+        operator_changed: false,
+        changed: vec![],
+        variable_train: train.variable_train.clone(),
+    }];
+    for location in train.route.iter().skip(1) {
+        let variable_train = match &location.change_en_route {
+            Some(x) => x,
+            None => continue,
+        };
+        let previous = segments.last_mut().unwrap();
+        let changed = differences(&previous.variable_train, variable_train);
+        if changed.is_empty() {
+            continue;
+        }
+        previous.to = location.id.clone();
+        previous.to_suffix = location.id_suffix.clone();
+        let operator_changed = changed.iter().any(|x| x == "operator");
+        segments.push(RouteSegment {
+            from: location.id.clone(),
+            from_suffix: location.id_suffix.clone(),
+            to: last.id.clone(),
+            to_suffix: last.id_suffix.clone(),
+            operator_changed,
+            changed,
+            variable_train: variable_train.clone(),
+        });
+    }
+    segments
+}
@@ -0,0 +1,106 @@
+use crate::error::Error;
+use crate::schedule::Schedule;
+
+use async_compression::tokio::bufread::GzipDecoder;
+use async_compression::tokio::write::GzipEncoder;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// A snapshot is every schedule we hold, bincoded and gzipped, after a short header. Bincode isn't
+// self-describing, so bump the version whenever anything in schedule.rs changes shape, or old
+// snapshots will fail to load (or worse, load as nonsense).
+const MAGIC: &[u8] = b"WRTSNAP";
+const VERSION: u8 = 12;
+
+#[derive(Debug, thiserror::Error)]
+#[error("Error in snapshot: {what}")]
+pub struct SnapshotError {
+    what: String,
+    too_large: bool,
+}
+
+impl SnapshotError {
+    // bigger than it was allowed to be once unpacked, as opposed to not being a snapshot at all
+    pub fn too_large(&self) -> bool {
+        self.too_large
+    }
+}
+
+pub async fn write_snapshot(schedules: HashMap<String, Arc<Schedule>>) -> Result<Vec<u8>, Error> {
+    let encoded = tokio::task::spawn_blocking(move || {
+        // encodes the same as HashMap<String, Schedule>, which is what we read back
+        let schedules = schedules
+            .iter()
+            .map(|(k, v)| (k, v.as_ref()))
+            .collect::<HashMap<_, _>>();
+        bincode::serialize(&schedules)
+    })
+    .await??;
+
+    let mut output = MAGIC.to_vec();
+    output.push(VERSION);
+    let mut encoder = GzipEncoder::new(output);
+    encoder.write_all(&encoded).await?;
+    encoder.shutdown().await?;
+    Ok(encoder.into_inner())
+}
+
+// Snapshots from anyone other than ourselves should have a max_size, in bytes once unpacked, as a
+// small gzip can unpack to far more than we have memory for.
+pub async fn read_snapshot(
+    data: Vec<u8>,
+    max_size: Option<u64>,
+) -> Result<HashMap<String, Schedule>, Error> {
+    let body = match data.strip_prefix(MAGIC) {
+        Some(x) => x,
+        None => {
+            return Err(Error::SnapshotError(SnapshotError {
+                what: "Not a snapshot".to_string(),
+                too_large: false,
+            }))
+        }
+    };
+    match body.first() {
+        Some(&VERSION) => (),
+        Some(x) => {
+            return Err(Error::SnapshotError(SnapshotError {
+                what: format!("Snapshot is version {}, we need {}", x, VERSION),
+                too_large: false,
+            }))
+        }
+        None => {
+            return Err(Error::SnapshotError(SnapshotError {
+                what: "Snapshot is truncated".to_string(),
+                too_large: false,
+            }))
+        }
+    }
+
+    let mut decoded = vec![];
+    GzipDecoder::new(&body[1..])
+        .take(max_size.map_or(u64::MAX, |x| x.saturating_add(1)))
+        .read_to_end(&mut decoded)
+        .await?;
+    match max_size {
+        Some(x) if decoded.len() as u64 > x => {
+            return Err(Error::SnapshotError(SnapshotError {
+                what: format!("Snapshot is bigger than {} bytes unpacked", x),
+                too_large: true,
+            }))
+        }
+        _ => (),
+    }
+
+    Ok(tokio::task::spawn_blocking(move || {
+        let mut schedules: HashMap<String, Schedule> = bincode::deserialize(&decoded)?;
+        // every string comes out of bincode as its own copy
+        for schedule in schedules.values_mut() {
+            schedule.intern_strings();
+        }
+        Ok::<_, Error>(schedules)
+    })
+    .await??)
+}
@@ -8,8 +8,6 @@ use reqwest::Client;
 use tokio::io::{AsyncBufRead, BufReader};
 use tokio_util::compat::FuturesAsyncReadCompatExt;
 
-use std::fmt;
-
 pub struct SncfFetcher {
     url: String,
     subset: String,
@@ -26,17 +24,12 @@ impl SncfFetcher {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
+#[error("Error fetching SNCF data: {what}")]
 pub struct SncfFetcherError {
     what: String,
 }
 
-impl fmt::Display for SncfFetcherError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Error fetching SNCF data: {}", self.what)
-    }
-}
-
 #[async_trait]
 impl StreamingFetcher for SncfFetcher {
     async fn fetch(&self) -> Result<Box<dyn AsyncBufRead + Unpin + Send>, Error> {
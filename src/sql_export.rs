@@ -0,0 +1,525 @@
+use crate::error::Error;
+use crate::schedule::{AssociationNode, DaysOfWeek, Schedule, Train, TrainValidityPeriod};
+
+use chrono::{DateTime, NaiveTime};
+use chrono_tz::Tz;
+
+use serde::Deserialize;
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+// rows per INSERT; both SQLite and Postgres are much happier with a few big statements than a
+// great many small ones
+const BATCH_SIZE: usize = 500;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SqlDialect {
+    Sqlite,
+    Postgres,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct SqlExportConfig {
+    dialect: SqlDialect,
+    // A client to pipe the SQL into, e.g. ["sqlite3", "/var/lib/wrt/timetables.db"] or
+    // ["psql", "-q", "postgres://wrt@localhost/timetables"]. We have no database driver of our
+    // own, so this is how the tables get written. Whether or not the client stops at the first
+    // error, a namespace is written all or nothing; see Writer::write.
+    command: Option<Vec<String>>,
+    dump_dir: Option<String>, // also (or instead) write <namespace>.sql here
+    namespaces: Option<Vec<String>>, // only these; everything if unset
+}
+
+// Copies each schedule into SQL tables once it's imported, for deployments that want it kept
+// somewhere durable or want to point their own analytics at it. It's an export only: nothing here
+// reads the tables back, so every query is still answered from the in-memory schedule, and the
+// tables are a one-way copy, rewritten a namespace at a time.
+//
+// The tables are:
+//   locations: one row per location
+//   trains: one row per version of a train, i.e. each base schedule and each overlay on one,
+//       `replaces` being the version an overlay applies to
+//   train_validity: when each version runs, and when it's cancelled
+//   calls: every location on each version's route, in order
+//   associations: joins, divides and next workings at each call, one row per validity period
+pub struct SqlExport {
+    config: SqlExportConfig,
+    lock: Mutex<()>, // one write at a time, as SQLite in particular won't take two
+}
+
+struct Writer<'a, W: Write> {
+    out: &'a mut W,
+    dialect: SqlDialect,
+    namespace: String,
+    table: &'static str,
+    columns: &'static str,
+    rows: usize,
+    written: Vec<(&'static str, usize)>, // rows for each table so far
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn text(s: Option<&str>) -> String {
+    match s {
+        Some(x) => quote(x),
+        None => "NULL".to_string(),
+    }
+}
+
+fn boolean(b: bool) -> &'static str {
+    if b {
+        "TRUE"
+    } else {
+        "FALSE"
+    }
+}
+
+fn number<T: ToString>(x: Option<T>) -> String {
+    match x {
+        Some(x) => x.to_string(),
+        None => "NULL".to_string(),
+    }
+}
+
+fn timestamp(x: &DateTime<Tz>) -> String {
+    quote(&x.to_rfc3339())
+}
+
+fn time(x: Option<NaiveTime>) -> String {
+    match x {
+        Some(x) => quote(&x.format("%H:%M:%S").to_string()),
+        None => "NULL".to_string(),
+    }
+}
+
+// Monday first, as in CIF
+fn days(days_of_week: &DaysOfWeek) -> String {
+    let days = [
+        days_of_week.monday,
+        days_of_week.tuesday,
+        days_of_week.wednesday,
+        days_of_week.thursday,
+        days_of_week.friday,
+        days_of_week.saturday,
+        days_of_week.sunday,
+    ];
+    quote(
+        &days
+            .iter()
+            .map(|x| if *x { '1' } else { '0' })
+            .collect::<String>(),
+    )
+}
+
+fn validity(period: &TrainValidityPeriod) -> String {
+    format!(
+        "{}, {}, {}",
+        timestamp(&period.valid_begin),
+        timestamp(&period.valid_end),
+        days(&period.days_of_week)
+    )
+}
+
+impl SqlDialect {
+    fn timestamp_type(&self) -> &'static str {
+        match self {
+            SqlDialect::Sqlite => "TEXT",
+            SqlDialect::Postgres => "TIMESTAMPTZ",
+        }
+    }
+
+    fn time_type(&self) -> &'static str {
+        match self {
+            SqlDialect::Sqlite => "TEXT",
+            SqlDialect::Postgres => "TIME",
+        }
+    }
+
+    fn schema(&self) -> String {
+        let timestamp = self.timestamp_type();
+        let time = self.time_type();
+        let validity = format!(
+            "valid_begin {} NOT NULL, valid_end {} NOT NULL, days_of_week TEXT NOT NULL",
+            timestamp, timestamp
+        );
+        let mut times = String::new();
+        for x in [
+            "working_arr",
+            "working_dep",
+            "working_pass",
+            "public_arr",
+            "public_dep",
+        ] {
+            times.push_str(&format!("{} {}, {}_day INTEGER, ", x, time, x));
+        }
+        format!(
+            "CREATE TABLE IF NOT EXISTS locations (namespace TEXT NOT NULL, id TEXT NOT NULL, \
+             name TEXT NOT NULL, public_id TEXT, timezone TEXT NOT NULL, \
+             latitude DOUBLE PRECISION, longitude DOUBLE PRECISION, PRIMARY KEY (namespace, id));\n\
+             CREATE TABLE IF NOT EXISTS trains (namespace TEXT NOT NULL, id TEXT NOT NULL, \
+             version INTEGER NOT NULL, replaces INTEGER, source TEXT, train_type TEXT NOT NULL, \
+             public_id TEXT, headcode TEXT, uic_code TEXT, operator TEXT, name TEXT, \
+             runs_as_required BOOLEAN NOT NULL, PRIMARY KEY (namespace, id, version));\n\
+             CREATE TABLE IF NOT EXISTS train_validity (namespace TEXT NOT NULL, \
+             train_id TEXT NOT NULL, version INTEGER NOT NULL, {}, cancelled BOOLEAN NOT NULL, \
+             reason TEXT);\n\
+             CREATE TABLE IF NOT EXISTS calls (namespace TEXT NOT NULL, train_id TEXT NOT NULL, \
+             version INTEGER NOT NULL, seq INTEGER NOT NULL, location_id TEXT NOT NULL, \
+             id_suffix TEXT, {}platform TEXT, line TEXT, path TEXT, \
+             PRIMARY KEY (namespace, train_id, version, seq));\n\
+             CREATE TABLE IF NOT EXISTS associations (namespace TEXT NOT NULL, \
+             train_id TEXT NOT NULL, version INTEGER NOT NULL, seq INTEGER NOT NULL, \
+             kind TEXT NOT NULL, other_train_id TEXT NOT NULL, other_id_suffix TEXT, \
+             day_diff INTEGER NOT NULL, for_passengers BOOLEAN NOT NULL, source TEXT, {}, \
+             cancelled BOOLEAN NOT NULL);\n\
+             CREATE INDEX IF NOT EXISTS calls_by_location ON calls (namespace, location_id);\n",
+            validity, times, validity
+        )
+    }
+}
+
+impl<'a, W: Write> Writer<'a, W> {
+    fn start(&mut self, table: &'static str, columns: &'static str) -> std::io::Result<()> {
+        self.finish()?;
+        self.table = table;
+        self.columns = columns;
+        self.written.push((table, 0));
+        writeln!(
+            self.out,
+            "DELETE FROM {} WHERE namespace = {};",
+            table,
+            quote(&self.namespace)
+        )
+    }
+
+    fn row(&mut self, values: &str) -> std::io::Result<()> {
+        if self.rows == 0 {
+            write!(
+                self.out,
+                "INSERT INTO {} ({}) VALUES\n({}, {})",
+                self.table,
+                self.columns,
+                quote(&self.namespace),
+                values
+            )?;
+        } else {
+            write!(self.out, ",\n({}, {})", quote(&self.namespace), values)?;
+        }
+        self.rows += 1;
+        self.written.last_mut().unwrap().1 += 1;
+        if self.rows == BATCH_SIZE {
+            self.finish()?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::io::Result<()> {
+        if self.rows > 0 {
+            writeln!(self.out, ";")?;
+            self.rows = 0;
+        }
+        Ok(())
+    }
+
+    // Either all of a namespace is replaced or none of it is, however the client deals with
+    // errors. Postgres won't run anything more in a transaction once something in it has failed,
+    // and rolls it back on COMMIT. SQLite carries on, so check() rolls it back before that if
+    // anything didn't go in.
+    fn write(&mut self, schedule: &Schedule) -> std::io::Result<()> {
+        writeln!(self.out, "BEGIN;")?;
+        writeln!(self.out, "{}", self.dialect.schema())?;
+
+        self.start(
+            "locations",
+            "namespace, id, name, public_id, timezone, latitude, longitude",
+        )?;
+        let mut location_ids = schedule.locations.keys().collect::<Vec<_>>();
+        location_ids.sort();
+        for id in location_ids {
+            let location = &schedule.locations[id];
+            self.row(&format!(
+                "{}, {}, {}, {}, {}, {}",
+                quote(id),
+                quote(&location.name),
+                text(location.public_id.as_deref()),
+                quote(location.timezone.name()),
+                number(location.position.map(|x| x.latitude)),
+                number(location.position.map(|x| x.longitude))
+            ))?;
+        }
+
+        // each table is written separately so no INSERT has to be interrupted by another
+        let mut train_ids = schedule.trains.keys().collect::<Vec<_>>();
+        train_ids.sort();
+        let mut versions = vec![];
+        for id in train_ids {
+            for train in &schedule.trains[id] {
+                flatten(train, None, &mut versions);
+            }
+        }
+        let versions = versions
+            .into_iter()
+            .enumerate()
+            .map(|(version, (train, replaces))| (version, train, replaces))
+            .collect::<Vec<_>>();
+
+        self.start(
+            "trains",
+            "namespace, id, version, replaces, source, train_type, public_id, headcode, \
+             uic_code, operator, name, runs_as_required",
+        )?;
+        for (version, train, replaces) in &versions {
+            let variable_train = &train.variable_train;
+            self.row(&format!(
+                "{}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}",
+                quote(&train.id),
+                version,
+                number(*replaces),
+                text(train.source.map(|x| format!("{:?}", x)).as_deref()),
+                quote(&format!("{:?}", variable_train.train_type)),
+                text(variable_train.public_id.as_deref()),
+                text(variable_train.headcode.as_deref()),
+                text(variable_train.uic_code.as_deref()),
+                text(variable_train.operator.as_ref().map(|x| x.id.as_str())),
+                text(variable_train.name.as_deref()),
+                boolean(train.runs_as_required)
+            ))?;
+        }
+
+        self.start(
+            "train_validity",
+            "namespace, train_id, version, valid_begin, valid_end, days_of_week, cancelled, \
+             reason",
+        )?;
+        for (version, train, _) in &versions {
+            for period in &train.validity {
+                self.row(&format!(
+                    "{}, {}, {}, FALSE, NULL",
+                    quote(&train.id),
+                    version,
+                    validity(period)
+                ))?;
+            }
+            for cancellation in &train.cancellations {
+                self.row(&format!(
+                    "{}, {}, {}, TRUE, {}",
+                    quote(&train.id),
+                    version,
+                    validity(&cancellation.validity),
+                    text(cancellation.reason.as_deref())
+                ))?;
+            }
+        }
+
+        self.start(
+            "calls",
+            "namespace, train_id, version, seq, location_id, id_suffix, working_arr, \
+             working_arr_day, working_dep, working_dep_day, working_pass, working_pass_day, \
+             public_arr, public_arr_day, public_dep, public_dep_day, platform, line, path",
+        )?;
+        for (version, train, _) in &versions {
+            for (seq, location) in train.route.iter().enumerate() {
+                self.row(&format!(
+                    "{}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}",
+                    quote(&train.id),
+                    version,
+                    seq,
+                    quote(&location.id),
+                    text(location.id_suffix.as_deref()),
+                    time(location.working_arr),
+                    number(location.working_arr_day),
+                    time(location.working_dep),
+                    number(location.working_dep_day),
+                    time(location.working_pass),
+                    number(location.working_pass_day),
+                    time(location.public_arr),
+                    number(location.public_arr_day),
+                    time(location.public_dep),
+                    number(location.public_dep_day),
+                    text(location.scheduled_platform.as_deref()),
+                    text(location.line.as_deref()),
+                    text(location.path.as_deref())
+                ))?;
+            }
+        }
+
+        self.start(
+            "associations",
+            "namespace, train_id, version, seq, kind, other_train_id, other_id_suffix, \
+             day_diff, for_passengers, source, valid_begin, valid_end, days_of_week, cancelled",
+        )?;
+        for (version, train, _) in &versions {
+            for (seq, location) in train.route.iter().enumerate() {
+                let kinds = [
+                    ("divides_to_form", location.divides_to_form.iter().collect()),
+                    ("joins_to", location.joins_to.iter().collect()),
+                    ("becomes", location.becomes.iter().collect()),
+                    ("divides_from", location.divides_from.iter().collect()),
+                    ("is_joined_to_by", location.is_joined_to_by.iter().collect()),
+                    ("forms_from", location.forms_from.iter().collect::<Vec<_>>()),
+                ];
+                for (kind, nodes) in kinds {
+                    for node in nodes {
+                        self.association(&train.id, *version, seq, kind, node)?;
+                    }
+                }
+            }
+        }
+
+        self.finish()?;
+        self.check()?;
+        writeln!(self.out, "COMMIT;")?;
+        self.out.flush()
+    }
+
+    // Rolls back unless every table has exactly the rows we wrote for the namespace, which they
+    // won't if any DELETE or INSERT failed. Leaving out a NOT NULL column is the one failure
+    // SQLite can be told to roll back on.
+    fn check(&mut self) -> std::io::Result<()> {
+        if self.dialect != SqlDialect::Sqlite {
+            return Ok(());
+        }
+        let counts = self
+            .written
+            .iter()
+            .map(|(table, rows)| {
+                format!(
+                    "(SELECT count(*) FROM {} WHERE namespace = {}) = {}",
+                    table,
+                    quote(&self.namespace),
+                    rows
+                )
+            })
+            .collect::<Vec<_>>();
+        writeln!(
+            self.out,
+            "CREATE TEMP TABLE IF NOT EXISTS loaded (ok INTEGER NOT NULL);\n\
+             INSERT OR ROLLBACK INTO loaded SELECT CASE WHEN {} THEN 1 END;",
+            counts.join(" AND ")
+        )
+    }
+
+    fn association(
+        &mut self,
+        train_id: &str,
+        version: usize,
+        seq: usize,
+        kind: &str,
+        node: &AssociationNode,
+    ) -> std::io::Result<()> {
+        let common = format!(
+            "{}, {}, {}, {}, {}, {}, {}, {}, {}",
+            quote(train_id),
+            version,
+            seq,
+            quote(kind),
+            quote(&node.other_train_id),
+            text(node.other_train_location_id_suffix.as_deref()),
+            node.day_diff,
+            boolean(node.for_passengers),
+            text(node.source.map(|x| format!("{:?}", x)).as_deref())
+        );
+        for period in &node.validity {
+            self.row(&format!("{}, {}, FALSE", common, validity(period)))?;
+        }
+        for (period, _) in &node.cancellations {
+            self.row(&format!("{}, {}, TRUE", common, validity(period)))?;
+        }
+        for replacement in &node.replacements {
+            self.association(train_id, version, seq, kind, replacement)?;
+        }
+        Ok(())
+    }
+}
+
+// every version of a train, overlays after what they replace, with the index of that
+fn flatten<'a>(
+    train: &'a Train,
+    replaces: Option<usize>,
+    out: &mut Vec<(&'a Train, Option<usize>)>,
+) {
+    let index = out.len();
+    out.push((train, replaces));
+    for replacement in &train.replacements {
+        flatten(replacement, Some(index), out);
+    }
+}
+
+impl SqlExport {
+    pub fn new(config: SqlExportConfig) -> Self {
+        Self {
+            config,
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn wants(&self, namespace: &str) -> bool {
+        match &self.config.namespaces {
+            Some(x) => x.iter().any(|x| x == namespace),
+            None => true,
+        }
+    }
+
+    pub fn write_sql(&self, schedule: &Schedule, out: &mut impl Write) -> std::io::Result<()> {
+        Writer {
+            out,
+            dialect: self.config.dialect,
+            namespace: schedule.namespace.clone(),
+            table: "",
+            columns: "",
+            rows: 0,
+            written: vec![],
+        }
+        .write(schedule)
+    }
+
+    // Blocks for as long as it takes, which for all of GB is a while; see export()
+    fn export_blocking(&self, schedule: &Schedule) -> Result<(), Error> {
+        let _lock = self.lock.lock().unwrap();
+
+        match &self.config.dump_dir {
+            Some(x) => {
+                let path = Path::new(x).join(format!("{}.sql", schedule.namespace));
+                let mut file = BufWriter::new(File::create(path)?);
+                self.write_sql(schedule, &mut file)?;
+            }
+            None => (),
+        }
+
+        match &self.config.command {
+            Some(command) if !command.is_empty() => {
+                let mut child = Command::new(&command[0])
+                    .args(&command[1..])
+                    .stdin(Stdio::piped())
+                    .spawn()?;
+                let result = {
+                    let mut stdin = BufWriter::new(child.stdin.take().unwrap());
+                    self.write_sql(schedule, &mut stdin)
+                };
+                // wait even if writing failed, so we don't leave a zombie behind
+                let status = child.wait()?;
+                result?;
+                if !status.success() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::Other,
+                        format!("{} exited with {}", command[0], status),
+                    )
+                    .into());
+                }
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    pub async fn export(self: Arc<Self>, schedule: Arc<Schedule>) -> Result<(), Error> {
+        tokio::task::spawn_blocking(move || self.export_blocking(&schedule)).await?
+    }
+}
@@ -0,0 +1,124 @@
+use crate::error::Error;
+use crate::schedule_manager::ScheduleManager;
+
+use chrono::{DateTime, Utc};
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Clone, Default, Deserialize)]
+pub struct StalenessConfig {
+    // Longest a source can go without anything new before we complain, by source name, e.g.
+    // "gbnr-vstp". These apply from startup; the built-in ones only once a source has been seen,
+    // so feeds a deployment doesn't use don't set them off.
+    max_age_secs: Option<HashMap<String, i64>>,
+    check_interval_secs: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct SourceStatus {
+    pub source: String,
+    pub last_updated: Option<DateTime<Utc>>,
+    pub age_secs: i64,             // since startup, if it's never been updated
+    pub max_age_secs: Option<i64>, // None if nothing says how old is too old
+    pub stale: bool,
+}
+
+// Realtime feeds tend to fail by going quiet rather than by erroring, so the supervisor never
+// notices. This keeps an eye on how long it's been since each source last brought anything.
+pub struct Staleness {
+    schedule_manager: Arc<ScheduleManager>,
+    max_age_secs: HashMap<String, i64>,
+    configured: HashSet<String>,
+    check_interval: Duration,
+    started: DateTime<Utc>,
+}
+
+fn default_max_age_secs() -> HashMap<String, i64> {
+    [
+        ("gbnr-cif", 36 * 3600),
+        ("gbnr-vstp", 3600),
+        ("gbnr-trust", 15 * 60),
+        ("gbni-cif", 36 * 3600),
+        ("gbni-gtfs", 36 * 3600),
+        ("ieir-gtfs", 36 * 3600),
+    ]
+    .into_iter()
+    .map(|(source, secs)| (source.to_string(), secs))
+    .collect()
+}
+
+impl Staleness {
+    pub fn new(config: StalenessConfig, schedule_manager: Arc<ScheduleManager>) -> Self {
+        let configured = config.max_age_secs.unwrap_or_default();
+        let mut max_age_secs = default_max_age_secs();
+        max_age_secs.extend(configured.iter().map(|(x, y)| (x.clone(), *y)));
+        Self {
+            schedule_manager,
+            max_age_secs,
+            configured: configured.into_keys().collect(),
+            check_interval: Duration::from_secs(config.check_interval_secs.unwrap_or(60)),
+            started: Utc::now(),
+        }
+    }
+
+    // every source we've heard from or have a configured limit for, by name
+    pub fn status(&self) -> Vec<SourceStatus> {
+        let now = Utc::now();
+        let updates = self.schedule_manager.source_updates();
+        let sources = updates
+            .keys()
+            .chain(self.configured.iter())
+            .collect::<BTreeSet<_>>();
+        sources
+            .into_iter()
+            .map(|source| self.source_status(source, updates.get(source).copied(), now))
+            .collect()
+    }
+
+    // one source, even if it's not in status() yet, e.g. a feed that's connected but quiet
+    pub fn status_of(&self, source: &str) -> SourceStatus {
+        let last_updated = self.schedule_manager.source_updates().get(source).copied();
+        self.source_status(source, last_updated, Utc::now())
+    }
+
+    fn source_status(
+        &self,
+        source: &str,
+        last_updated: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> SourceStatus {
+        let age_secs = (now - last_updated.unwrap_or(self.started)).num_seconds();
+        let max_age_secs = self.max_age_secs.get(source).copied();
+        SourceStatus {
+            source: source.to_string(),
+            last_updated,
+            age_secs,
+            max_age_secs,
+            stale: max_age_secs.is_some_and(|x| age_secs > x),
+        }
+    }
+
+    pub async fn run(self: Arc<Self>) -> Result<(), Error> {
+        let mut interval = tokio::time::interval(self.check_interval);
+        let mut stale = HashSet::new(); // as of the last check, to only warn when it changes
+        loop {
+            interval.tick().await;
+            for status in self.status() {
+                if status.stale && stale.insert(status.source.clone()) {
+                    println!(
+                        "WARNING: Nothing new from {} for {} minutes (limit {})",
+                        status.source,
+                        status.age_secs / 60,
+                        status.max_age_secs.unwrap_or_default() / 60
+                    );
+                } else if !status.stale && stale.remove(&status.source) {
+                    println!("{} is up to date again", status.source);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use crate::error::Error;
+use crate::schedule::Schedule;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::{HashMap, HashSet};
+
+// Places with more than one station that people think of as one, e.g. "London" for London
+// Terminals or "Birmingham" for New Street, Moor Street and Snow Hill. Anywhere a board or journey
+// takes a location, a group code can be given instead to get all its members at once. Groups come
+// from the config or from reference data files in the same shape, by namespace then group code.
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct StationGroup {
+    pub name: String,
+    pub members: Vec<String>, // location IDs or public IDs, e.g. CRS codes
+}
+
+#[derive(Clone, Default, Deserialize)]
+pub struct StationGroupsConfig {
+    groups: Option<HashMap<String, HashMap<String, StationGroup>>>,
+    files: Option<Vec<String>>, // loaded after the above, replacing any groups with the same code
+}
+
+pub struct StationGroups {
+    groups: HashMap<String, HashMap<String, StationGroup>>,
+}
+
+impl StationGroups {
+    pub fn new(config: StationGroupsConfig) -> Result<Self, Error> {
+        let mut groups = config.groups.unwrap_or_default();
+        for filename in config.files.unwrap_or_default() {
+            println!("Loading station groups from {}", filename);
+            let contents = std::fs::read_to_string(&filename)?;
+            let file =
+                serde_json::from_str::<HashMap<String, HashMap<String, StationGroup>>>(&contents)?;
+            let mut count = 0;
+            for (namespace, file_groups) in file {
+                count += file_groups.len();
+                groups.entry(namespace).or_default().extend(file_groups);
+            }
+            println!("Loaded {} station groups", count);
+        }
+        Ok(Self { groups })
+    }
+
+    pub fn get(&self, namespace: &str, code: &str) -> Option<&StationGroup> {
+        self.groups.get(namespace)?.get(code)
+    }
+
+    // What a location given in a request means: the location with that ID, and everything with it
+    // as a public ID, or if there's neither, the members of the group with that code. Members we
+    // don't have are left out.
+    pub fn location_ids(&self, schedule: &Schedule, id: &str) -> HashSet<String> {
+        let ids = member_ids(schedule, id);
+        match ids.is_empty() {
+            true => self.members(schedule, id),
+            false => ids,
+        }
+    }
+
+    // empty if there's no such group
+    pub fn members(&self, schedule: &Schedule, code: &str) -> HashSet<String> {
+        match self.get(&schedule.namespace, code) {
+            Some(x) => x
+                .members
+                .iter()
+                .flat_map(|x| member_ids(schedule, x))
+                .collect(),
+            None => HashSet::new(),
+        }
+    }
+}
+
+fn member_ids(schedule: &Schedule, id: &str) -> HashSet<String> {
+    let mut ids = schedule
+        .locations_indexed_by_public_id
+        .get(id)
+        .cloned()
+        .unwrap_or_default();
+    if schedule.locations.contains_key(id) {
+        ids.insert(id.to_string());
+    }
+    ids
+}
@@ -2,8 +2,12 @@ use crate::error::Error;
 
 use async_trait::async_trait;
 
+/// A push feed of small messages. `receive` waits for the next message and returns its raw body.
+/// Nothing is acknowledged until `ack` is called, which should be once everything received so far
+/// has been safely applied, so the feed can send it again if we fall over first.
 #[async_trait]
 pub trait Subscriber {
     async fn subscribe(&mut self) -> Result<(), Error>;
     async fn receive(&mut self) -> Result<Vec<u8>, Error>;
+    async fn ack(&mut self) -> Result<(), Error>;
 }
@@ -0,0 +1,718 @@
+use crate::running_days::dates_text;
+use crate::schedule::{get_train_instance, operating_dates_between, Schedule, VariableTrain};
+
+use chrono::{Days, NaiveDate, NaiveTime};
+use flate2::Crc;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+// Departure posters for stations, as pinned up on platforms: every departure over a range of
+// dates, once per time and destination, with the days it runs. Trains are resolved for each date
+// the same way as for the live boards, so overlays and cancellations come out the same.
+//
+// Also weekly timetables for planners, with a row for each train and a column for each day of the
+// week showing when it calls, which they tend to want as spreadsheets.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExportFormat {
+    Csv,
+    Pdf,
+    Xlsx,
+}
+
+impl ExportFormat {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Some(Self::Csv),
+            "pdf" => Some(Self::Pdf),
+            "xlsx" => Some(Self::Xlsx),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Pdf => "pdf",
+            Self::Xlsx => "xlsx",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PosterRow {
+    pub time: NaiveTime, // local to the station
+    pub destination: String,
+    pub platform: Option<String>,
+    pub operator: Option<String>,
+    pub days: String, // e.g. "Mondays to Fridays, not 25 December"
+}
+
+#[derive(Clone, Debug)]
+pub struct Poster {
+    pub namespace: String,
+    pub location_id: String,
+    pub location_name: String,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub rows: Vec<PosterRow>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WeeklyCall {
+    pub time: NaiveTime,    // local to the station
+    pub arrival_only: bool, // where it terminates
+}
+
+#[derive(Clone, Debug)]
+pub struct WeeklyRow {
+    pub train_id: String,
+    pub public_id: Option<String>,
+    pub origin: String,
+    pub destination: String,
+    pub operator: Option<String>,
+    pub days: [Vec<WeeklyCall>; 7], // from the week's first day; more than one for a circular route
+}
+
+#[derive(Clone, Debug)]
+pub struct WeeklyTimetable {
+    pub namespace: String,
+    pub location_id: String,
+    pub location_name: String,
+    pub week_commencing: NaiveDate,
+    pub rows: Vec<WeeklyRow>,
+}
+
+fn location_name(schedule: &Schedule, location_id: &str) -> String {
+    match schedule.locations.get(location_id) {
+        Some(x) => x.name.clone(),
+        None => location_id.to_string(),
+    }
+}
+
+fn operator_name(variable_train: &VariableTrain) -> Option<String> {
+    variable_train.operator.as_ref().map(|x| {
+        x.description
+            .as_ref()
+            .map(|y| y.to_string())
+            .unwrap_or_else(|| x.id.to_string())
+    })
+}
+
+// None if there's no such location
+pub fn departures_poster(
+    schedule: &Schedule,
+    location_id: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Option<Poster> {
+    let location = schedule.locations.get(location_id)?;
+    let mut departures: BTreeMap<_, BTreeSet<NaiveDate>> = BTreeMap::new();
+
+    for train_id in schedule
+        .trains_indexed_by_location
+        .get(location_id)
+        .into_iter()
+        .flatten()
+    {
+        let versions = match schedule.trains.get(train_id.as_str()) {
+            Some(x) if !x.is_empty() => x,
+            _ => continue,
+        };
+        for date in operating_dates_between(versions, from, to) {
+            let train = match get_train_instance(versions, date) {
+                (Some(x), false, _) => x,
+                _ => continue,
+            };
+            let destination = match train.route.last() {
+                Some(x) => location_name(schedule, x.id.as_str()),
+                None => continue,
+            };
+            let mut variable_train = &train.variable_train;
+            for (i, train_location) in train.route.iter().enumerate() {
+                match &train_location.change_en_route {
+                    Some(x) => variable_train = x,
+                    None => (),
+                }
+                if train_location.id.as_str() != location_id
+                    || i + 1 == train.route.len()
+                    || !train_location.passenger_flags().public
+                {
+                    continue;
+                }
+                let departure = match train_location
+                    .local_times(date, location.timezone)
+                    .public_dep
+                {
+                    Some(x) if x.date_naive() >= from && x.date_naive() <= to => x,
+                    _ => continue,
+                };
+                let operator = operator_name(variable_train);
+                departures
+                    .entry((
+                        departure.time(),
+                        destination.clone(),
+                        train_location
+                            .scheduled_platform
+                            .as_ref()
+                            .map(|x| x.to_string()),
+                        operator,
+                    ))
+                    .or_default()
+                    .insert(departure.date_naive());
+            }
+        }
+    }
+
+    let rows = departures
+        .into_iter()
+        .map(
+            |((time, destination, platform, operator), dates)| PosterRow {
+                time,
+                destination,
+                platform,
+                operator,
+                days: dates_text(&dates, from, to),
+            },
+        )
+        .collect();
+
+    Some(Poster {
+        namespace: schedule.namespace.clone(),
+        location_id: location_id.to_string(),
+        location_name: location.name.clone(),
+        from,
+        to,
+        rows,
+    })
+}
+
+// None if there's no such location. Arrivals are given where trains terminate, but not
+// departures where they start from, so a train is in the week's grid however it calls.
+pub fn weekly_timetable(
+    schedule: &Schedule,
+    location_id: &str,
+    week_commencing: NaiveDate,
+) -> Option<WeeklyTimetable> {
+    let location = schedule.locations.get(location_id)?;
+    let last_day = week_commencing + Days::new(6);
+    let mut rows: BTreeMap<_, [Vec<WeeklyCall>; 7]> = BTreeMap::new();
+
+    for train_id in schedule
+        .trains_indexed_by_location
+        .get(location_id)
+        .into_iter()
+        .flatten()
+    {
+        let versions = match schedule.trains.get(train_id.as_str()) {
+            Some(x) if !x.is_empty() => x,
+            _ => continue,
+        };
+        for date in operating_dates_between(versions, week_commencing, last_day) {
+            let train = match get_train_instance(versions, date) {
+                (Some(x), false, _) => x,
+                _ => continue,
+            };
+            let (origin, destination) = match (train.route.first(), train.route.last()) {
+                (Some(x), Some(y)) => (
+                    location_name(schedule, x.id.as_str()),
+                    location_name(schedule, y.id.as_str()),
+                ),
+                _ => continue,
+            };
+            let mut variable_train = &train.variable_train;
+            for (i, train_location) in train.route.iter().enumerate() {
+                match &train_location.change_en_route {
+                    Some(x) => variable_train = x,
+                    None => (),
+                }
+                if train_location.id.as_str() != location_id
+                    || !train_location.passenger_flags().public
+                {
+                    continue;
+                }
+                let times = train_location.local_times(date, location.timezone);
+                let (time, arrival_only) = match (i + 1 == train.route.len(), times.public_dep) {
+                    (false, Some(x)) => (x, false),
+                    (true, _) => match times.public_arr {
+                        Some(x) => (x, true),
+                        None => continue,
+                    },
+                    _ => continue,
+                };
+                let day = (time.date_naive() - week_commencing).num_days();
+                if !(0..7).contains(&day) {
+                    continue;
+                }
+                rows.entry((
+                    train_id.clone(),
+                    variable_train.public_id.clone(),
+                    origin.clone(),
+                    destination.clone(),
+                    operator_name(variable_train),
+                ))
+                .or_default()[day as usize]
+                    .push(WeeklyCall {
+                        time: time.time(),
+                        arrival_only,
+                    });
+            }
+        }
+    }
+
+    let mut rows = rows
+        .into_iter()
+        .map(
+            |((train_id, public_id, origin, destination, operator), days)| WeeklyRow {
+                train_id,
+                public_id,
+                origin,
+                destination,
+                operator,
+                days,
+            },
+        )
+        .collect::<Vec<_>>();
+    // in the order they first call in the day, whichever days that is
+    rows.sort_by_key(|x| x.days.iter().flatten().map(|y| y.time).min());
+
+    Some(WeeklyTimetable {
+        namespace: schedule.namespace.clone(),
+        location_id: location_id.to_string(),
+        location_name: location.name.clone(),
+        week_commencing,
+        rows,
+    })
+}
+
+fn call_text(call: &WeeklyCall) -> String {
+    match call.arrival_only {
+        true => format!("{}a", call.time.format("%H:%M")),
+        false => call.time.format("%H:%M").to_string(),
+    }
+}
+
+fn day_heading(week_commencing: NaiveDate, day: usize) -> String {
+    (week_commencing + Days::new(day as u64))
+        .format("%a %-d %b")
+        .to_string()
+}
+
+// Each export is a table, with its headings first, which the CSV and XLSX are made from
+fn poster_table(poster: &Poster) -> Vec<Vec<String>> {
+    let mut table = vec![["time", "destination", "platform", "operator", "days"]
+        .map(|x| x.to_string())
+        .to_vec()];
+    for row in &poster.rows {
+        table.push(vec![
+            row.time.format("%H:%M").to_string(),
+            row.destination.clone(),
+            row.platform.clone().unwrap_or_default(),
+            row.operator.clone().unwrap_or_default(),
+            row.days.clone(),
+        ]);
+    }
+    table
+}
+
+fn weekly_table(weekly: &WeeklyTimetable) -> Vec<Vec<String>> {
+    let mut headings = ["train", "headcode", "origin", "destination", "operator"]
+        .map(|x| x.to_string())
+        .to_vec();
+    headings.extend((0..7).map(|x| day_heading(weekly.week_commencing, x)));
+    let mut table = vec![headings];
+    for row in &weekly.rows {
+        let mut fields = vec![
+            row.train_id.clone(),
+            row.public_id.clone().unwrap_or_default(),
+            row.origin.clone(),
+            row.destination.clone(),
+            row.operator.clone().unwrap_or_default(),
+        ];
+        fields.extend(
+            row.days
+                .iter()
+                .map(|x| x.iter().map(call_text).collect::<Vec<_>>().join(" ")),
+        );
+        table.push(fields);
+    }
+    table
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn table_csv(table: &[Vec<String>]) -> String {
+    let mut csv = String::new();
+    for fields in table {
+        csv += &fields
+            .iter()
+            .map(|x| csv_field(x))
+            .collect::<Vec<_>>()
+            .join(",");
+        csv += "\r\n";
+    }
+    csv
+}
+
+pub fn to_csv(poster: &Poster) -> String {
+    table_csv(&poster_table(poster))
+}
+
+pub fn weekly_to_csv(weekly: &WeeklyTimetable) -> String {
+    table_csv(&weekly_table(weekly))
+}
+
+pub fn export(poster: &Poster, format: ExportFormat) -> Vec<u8> {
+    match format {
+        ExportFormat::Csv => to_csv(poster).into_bytes(),
+        ExportFormat::Pdf => to_pdf(poster),
+        ExportFormat::Xlsx => to_xlsx("Departures", &poster_table(poster)),
+    }
+}
+
+pub fn export_weekly(weekly: &WeeklyTimetable, format: ExportFormat) -> Vec<u8> {
+    match format {
+        ExportFormat::Csv => weekly_to_csv(weekly).into_bytes(),
+        ExportFormat::Pdf => weekly_to_pdf(weekly),
+        ExportFormat::Xlsx => to_xlsx("Timetable", &weekly_table(weekly)),
+    }
+}
+
+// Just enough PDF for pages of text in the standard fonts, which every reader has, so there's
+// nothing to embed and no PDF library to pull in.
+const PAGE_WIDTH: f32 = 595.0; // A4, in points
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 40.0;
+const ROW_HEIGHT: f32 = 12.0;
+const ROW_CHARS: usize = 95; // of 9pt Courier across the page
+
+// WinAnsiEncoding is near enough Latin-1 for station names
+fn pdf_string(text: &str) -> Vec<u8> {
+    let mut output = vec![b'('];
+    for c in text.chars() {
+        match c {
+            '(' | ')' | '\\' => {
+                output.push(b'\\');
+                output.push(c as u8);
+            }
+            c if (c as u32) < 256 => output.push(c as u32 as u8),
+            _ => output.push(b'?'),
+        }
+    }
+    output.push(b')');
+    output
+}
+
+fn pad(text: &str, width: usize) -> String {
+    let mut text = text.chars().take(width - 1).collect::<String>();
+    while text.chars().count() < width {
+        text.push(' ');
+    }
+    text
+}
+
+// the lines of text under the heading, with the days wrapped onto more lines where they're long
+fn poster_lines(poster: &Poster) -> Vec<String> {
+    const PREFIX: usize = 6 + 30 + 6 + 20;
+    let mut lines = vec![];
+    for row in &poster.rows {
+        let mut line = format!(
+            "{}{}{}{}",
+            pad(&row.time.format("%H:%M").to_string(), 6),
+            pad(&row.destination, 30),
+            pad(row.platform.as_deref().unwrap_or(""), 6),
+            pad(row.operator.as_deref().unwrap_or(""), 20),
+        );
+        let mut width = PREFIX;
+        for word in row.days.split(' ') {
+            if width > PREFIX && width + 1 + word.chars().count() > ROW_CHARS {
+                lines.push(line);
+                line = " ".repeat(PREFIX);
+                width = PREFIX;
+            }
+            if width > PREFIX {
+                line.push(' ');
+                width += 1;
+            }
+            line += word;
+            width += word.chars().count();
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+pub fn to_pdf(poster: &Poster) -> Vec<u8> {
+    let title = format!("Departures from {}", poster.location_name);
+    let subtitle = format!(
+        "{} to {}",
+        poster.from.format("%-d %B %Y"),
+        poster.to.format("%-d %B %Y")
+    );
+    let heading = format!(
+        "{}{}{}{}{}",
+        pad("Time", 6),
+        pad("Destination", 30),
+        pad("Plat", 6),
+        pad("Operator", 20),
+        "Days"
+    );
+    pdf_document(&title, &subtitle, &heading, &poster_lines(poster))
+}
+
+// a line for each train, or more where it calls more than once a day, with a call in each column
+fn weekly_lines(weekly: &WeeklyTimetable) -> Vec<String> {
+    let mut lines = vec![];
+    for row in &weekly.rows {
+        let count = row.days.iter().map(|x| x.len()).max().unwrap_or(0);
+        for i in 0..count {
+            let mut line = match i {
+                0 => format!(
+                    "{}{}",
+                    pad(row.public_id.as_deref().unwrap_or(""), 6),
+                    pad(&row.destination, 26)
+                ),
+                _ => " ".repeat(6 + 26),
+            };
+            for day in &row.days {
+                line += &pad(&day.get(i).map(call_text).unwrap_or_default(), 9);
+            }
+            lines.push(line);
+        }
+    }
+    lines
+}
+
+pub fn weekly_to_pdf(weekly: &WeeklyTimetable) -> Vec<u8> {
+    let title = format!("Weekly timetable for {}", weekly.location_name);
+    let subtitle = format!(
+        "Week commencing {}",
+        weekly.week_commencing.format("%-d %B %Y")
+    );
+    let mut heading = format!("{}{}", pad("Train", 6), pad("Destination", 26));
+    for day in 0..7 {
+        let date = weekly.week_commencing + Days::new(day);
+        heading += &pad(&date.format("%a %-d").to_string(), 9);
+    }
+    pdf_document(&title, &subtitle, &heading, &weekly_lines(weekly))
+}
+
+fn pdf_document(title: &str, subtitle: &str, heading: &str, lines: &[String]) -> Vec<u8> {
+    let per_page = ((PAGE_HEIGHT - 2.0 * MARGIN - 60.0) / ROW_HEIGHT) as usize;
+    let mut pages = lines.chunks(per_page).collect::<Vec<_>>();
+    if pages.is_empty() {
+        pages.push(&[]);
+    }
+
+    // objects 1 to 4 are the catalogue, page tree and fonts, then a page and its content for each
+    let text = |font: &str, size: f32, y: f32, s: &str| -> Vec<u8> {
+        let mut output = format!("BT /{} {} Tf {} {} Td ", font, size, MARGIN, y).into_bytes();
+        output.extend(pdf_string(s));
+        output.extend(b" Tj ET\n");
+        output
+    };
+    let mut objects: Vec<Vec<u8>> = vec![
+        b"<< /Type /Catalog /Pages 2 0 R >>".to_vec(),
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            (0..pages.len())
+                .map(|i| format!("{} 0 R", 5 + 2 * i))
+                .collect::<Vec<_>>()
+                .join(" "),
+            pages.len()
+        )
+        .into_bytes(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica-Bold /Encoding /WinAnsiEncoding >>"
+            .to_vec(),
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>".to_vec(),
+    ];
+    for (i, page) in pages.iter().enumerate() {
+        let mut content = vec![];
+        let mut y = PAGE_HEIGHT - MARGIN - 16.0;
+        content.extend(text("F1", 16.0, y, title));
+        y -= 20.0;
+        content.extend(text(
+            "F2",
+            9.0,
+            y,
+            &format!("{}    page {} of {}", subtitle, i + 1, pages.len()),
+        ));
+        y -= 24.0;
+        content.extend(text("F1", 9.0, y, heading));
+        for line in page.iter() {
+            y -= ROW_HEIGHT;
+            content.extend(text("F2", 9.0, y, line));
+        }
+
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                6 + 2 * i
+            )
+            .into_bytes(),
+        );
+        let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+        stream.extend(content);
+        stream.extend(b"endstream");
+        objects.push(stream);
+    }
+
+    let mut output = b"%PDF-1.4\n".to_vec();
+    let mut offsets = vec![];
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(output.len());
+        output.extend(format!("{} 0 obj\n", i + 1).into_bytes());
+        output.extend(object);
+        output.extend(b"\nendobj\n");
+    }
+    let xref = output.len();
+    output.extend(format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).into_bytes());
+    for offset in offsets {
+        output.extend(format!("{:010} 00000 n \n", offset).into_bytes());
+    }
+    output.extend(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        )
+        .into_bytes(),
+    );
+    output
+}
+
+// An XLSX file is a zip of XML parts, of which a single sheet of text needs only a handful. Zip's
+// stored method doesn't compress, but it's the simplest and everything reads it.
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn zip(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut output = vec![];
+    let mut directory = vec![];
+    for (name, data) in files {
+        let offset = output.len() as u32;
+        // the same in the local header and the central directory: version 2.0, no flags, stored,
+        // 1 January 1980, then the checksum, sizes and name length
+        let mut common = vec![];
+        for x in [20u16, 0, 0, 0, 0x21] {
+            common.extend(x.to_le_bytes());
+        }
+        let mut crc = Crc::new();
+        crc.update(data);
+        common.extend(crc.sum().to_le_bytes());
+        common.extend((data.len() as u32).to_le_bytes());
+        common.extend((data.len() as u32).to_le_bytes());
+        common.extend((name.len() as u16).to_le_bytes());
+
+        output.extend(0x04034b50u32.to_le_bytes());
+        output.extend(&common);
+        output.extend(0u16.to_le_bytes()); // extra field length
+        output.extend(name.as_bytes());
+        output.extend(data);
+
+        directory.extend(0x02014b50u32.to_le_bytes());
+        directory.extend(20u16.to_le_bytes()); // made by
+        directory.extend(&common);
+        directory.extend([0; 12]); // extra, comment, disk, attributes
+        directory.extend(offset.to_le_bytes());
+        directory.extend(name.as_bytes());
+    }
+    let directory_offset = output.len() as u32;
+    let directory_size = directory.len() as u32;
+    output.extend(directory);
+    output.extend(0x06054b50u32.to_le_bytes());
+    output.extend([0; 4]); // disk numbers
+    output.extend((files.len() as u16).to_le_bytes());
+    output.extend((files.len() as u16).to_le_bytes());
+    output.extend(directory_size.to_le_bytes());
+    output.extend(directory_offset.to_le_bytes());
+    output.extend(0u16.to_le_bytes()); // comment length
+    output
+}
+
+// A, B, ..., Z, AA, ...
+fn column_name(mut column: usize) -> String {
+    let mut name = vec![];
+    loop {
+        name.insert(0, b'A' + (column % 26) as u8);
+        if column < 26 {
+            break;
+        }
+        column = column / 26 - 1;
+    }
+    String::from_utf8(name).unwrap()
+}
+
+pub fn to_xlsx(sheet_name: &str, table: &[Vec<String>]) -> Vec<u8> {
+    let mut sheet = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+        <worksheet xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\"><sheetData>"
+        .to_string();
+    for (i, fields) in table.iter().enumerate() {
+        sheet += &format!("<row r=\"{}\">", i + 1);
+        for (j, field) in fields.iter().enumerate() {
+            sheet += &format!(
+                "<c r=\"{}{}\" t=\"inlineStr\"><is><t>{}</t></is></c>",
+                column_name(j),
+                i + 1,
+                xml_escape(field)
+            );
+        }
+        sheet += "</row>";
+    }
+    sheet += "</sheetData></worksheet>";
+
+    let files = [
+        (
+            "[Content_Types].xml",
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+            <Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\
+            <Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>\
+            <Default Extension=\"xml\" ContentType=\"application/xml\"/>\
+            <Override PartName=\"/xl/workbook.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml\"/>\
+            <Override PartName=\"/xl/worksheets/sheet1.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml\"/>\
+            </Types>"
+                .to_string(),
+        ),
+        (
+            "_rels/.rels",
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+            <Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+            <Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"xl/workbook.xml\"/>\
+            </Relationships>"
+                .to_string(),
+        ),
+        (
+            "xl/workbook.xml",
+            format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+                <workbook xmlns=\"http://schemas.openxmlformats.org/spreadsheetml/2006/main\" \
+                xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\">\
+                <sheets><sheet name=\"{}\" sheetId=\"1\" r:id=\"rId1\"/></sheets></workbook>",
+                xml_escape(sheet_name)
+            ),
+        ),
+        (
+            "xl/_rels/workbook.xml.rels",
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+            <Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+            <Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet\" Target=\"worksheets/sheet1.xml\"/>\
+            </Relationships>"
+                .to_string(),
+        ),
+        ("xl/worksheets/sheet1.xml", sheet),
+    ];
+    zip(&files.map(|(name, contents)| (name, contents.into_bytes())))
+}
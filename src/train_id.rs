@@ -0,0 +1,72 @@
+use crate::schedule::{Schedule, Train};
+
+use chrono::NaiveDate;
+
+use serde::{Serialize, Serializer};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+// IDs from upstream (NR UIDs, GTFS trip IDs, Irish Rail's codes) are only unique within a
+// namespace, and only pick out one journey together with a date. This puts all three together as
+// "<namespace>:<train ID>:<YYYYMMDD>", e.g. "gbnr:C12345:20240501", so an ID from the API can be
+// looked up again without knowing where it came from. Namespaces never contain colons and the date
+// is always last, so train IDs with colons in (as some GTFS trip IDs have) still come apart.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct GlobalTrainId {
+    pub namespace: String,
+    pub train_id: String,
+    pub date: NaiveDate, // that the train starts on
+}
+
+impl GlobalTrainId {
+    pub fn new(namespace: &str, train_id: &str, date: NaiveDate) -> Self {
+        Self {
+            namespace: namespace.to_string(),
+            train_id: train_id.to_string(),
+            date,
+        }
+    }
+
+    pub fn parse(id: &str) -> Option<Self> {
+        let (namespace, rest) = id.split_once(':')?;
+        let (train_id, date) = rest.rsplit_once(':')?;
+        if namespace.is_empty() || train_id.is_empty() {
+            return None;
+        }
+        Some(Self {
+            namespace: namespace.to_string(),
+            train_id: train_id.to_string(),
+            date: NaiveDate::parse_from_str(date, "%Y%m%d").ok()?,
+        })
+    }
+
+    // the schedule and every version of the train, if we have it at all; whether it runs on the
+    // date is up to the caller
+    pub fn lookup<'a>(
+        &self,
+        schedules: &'a HashMap<String, Arc<Schedule>>,
+    ) -> Option<(&'a Arc<Schedule>, &'a Vec<Train>)> {
+        let schedule = schedules.get(&self.namespace)?;
+        Some((schedule, schedule.trains.get(self.train_id.as_str())?))
+    }
+}
+
+impl fmt::Display for GlobalTrainId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}",
+            self.namespace,
+            self.train_id,
+            self.date.format("%Y%m%d")
+        )
+    }
+}
+
+impl Serialize for GlobalTrainId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
@@ -0,0 +1,118 @@
+use crate::error::Error;
+use crate::importer::SlowStreamingImporter;
+use crate::schedule::{Schedule, TrainName};
+
+use async_trait::async_trait;
+
+use chrono::NaiveDate;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt};
+
+// Names trains carry, e.g. "The Flying Scotsman" or "Night Riviera", which the CIF has no room for.
+// These come from a CSV kept by hand, with a header row naming its columns, in any order:
+//
+// name     - required
+// train_id - the CIF UID, for one train
+// headcode - or its signalling ID, e.g. "1S15", for every train running as that
+// from     - the first date it has the name, as YYYY-MM-DD; default always
+// to       - the last; likewise
+//
+// Each row needs a train ID or a headcode. Names only apply to trains that don't already have one
+// from the timetable.
+
+// Fields as timetable_export writes them: quoted if they have a comma or a quote in, with quotes
+// inside doubled
+fn csv_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields.into_iter().map(|x| x.trim().to_string()).collect()
+}
+
+pub struct TrainNamesImporter {}
+
+impl TrainNamesImporter {
+    pub fn new() -> TrainNamesImporter {
+        TrainNamesImporter {}
+    }
+}
+
+#[async_trait]
+impl SlowStreamingImporter for TrainNamesImporter {
+    async fn overlay(
+        &mut self,
+        mut reader: impl AsyncBufReadExt + Unpin + Send,
+        mut schedule: Schedule,
+    ) -> Result<Schedule, Error> {
+        println!("Importing train names");
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).await?;
+        let mut lines = contents.lines().filter(|x| !x.trim().is_empty());
+
+        let header = csv_fields(lines.next().unwrap_or(""));
+        let column = |name: &str| header.iter().position(|x| x == name);
+        let name_column = match column("name") {
+            Some(x) => x,
+            None => Err(anyhow::anyhow!("Train names have no name column"))?,
+        };
+        let train_id_column = column("train_id");
+        let headcode_column = column("headcode");
+        let from_column = column("from");
+        let to_column = column("to");
+
+        let mut skipped = 0;
+        for line in lines {
+            let fields = csv_fields(line);
+            let field = |column: Option<usize>| {
+                column
+                    .and_then(|x| fields.get(x))
+                    .filter(|x| !x.is_empty())
+                    .cloned()
+            };
+            let date = |column: Option<usize>| match field(column) {
+                Some(x) => NaiveDate::parse_from_str(&x, "%Y-%m-%d").map(Some),
+                None => Ok(None),
+            };
+            let (name, train_id, public_id) = (
+                field(Some(name_column)),
+                field(train_id_column),
+                field(headcode_column),
+            );
+            match (name, date(from_column), date(to_column)) {
+                (Some(name), Ok(valid_from), Ok(valid_to))
+                    if train_id.is_some() || public_id.is_some() =>
+                {
+                    schedule.train_names.push(TrainName {
+                        name,
+                        train_id,
+                        public_id,
+                        valid_from,
+                        valid_to,
+                    })
+                }
+                _ => skipped += 1,
+            }
+        }
+        if skipped > 0 {
+            println!(
+                "Skipped {} train names without a name, a train to go on, or good dates",
+                skipped
+            );
+        }
+
+        Ok(schedule)
+    }
+}
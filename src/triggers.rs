@@ -0,0 +1,237 @@
+use chrono::offset::Utc;
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeZone};
+use chrono_tz::Tz;
+
+use serde::Serialize;
+
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::time;
+use tokio::time::Duration;
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+// Ways for upstream (or a cron job) to tell a manager there's something new to fetch, rather than
+// it waiting for its usual time, and for the admin endpoints to control when it fetches. By source
+// name, as used for staleness, e.g. "gbnr-cif".
+#[derive(Default)]
+pub struct Triggers {
+    notifies: Mutex<HashMap<String, Arc<Notify>>>,
+    commands: Mutex<HashMap<String, mpsc::Sender<ManagerCommand>>>,
+}
+
+#[derive(Debug)]
+pub enum ManagerCommand {
+    Pause,  // no scheduled or triggered runs until resumed
+    Resume, // running straight away if one was missed meanwhile
+    SetTime(NaiveTime),
+    DumpState(oneshot::Sender<RunState>),
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RunState {
+    pub source: String,
+    pub paused: bool,
+    pub missed: bool,    // a run came due or was triggered while paused
+    pub time: NaiveTime, // local to the source, every day
+    pub timezone: Tz,
+    pub next_run: DateTime<Tz>, // as things stand, if it isn't paused
+    pub last_run: Option<NaiveDate>,
+}
+
+impl Triggers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // for a manager to wait on
+    pub fn get(&self, source: &str) -> Arc<Notify> {
+        self.notifies
+            .lock()
+            .unwrap()
+            .entry(source.to_string())
+            .or_default()
+            .clone()
+    }
+
+    // false if nothing is waiting on this source, so a typo in someone's webhook config shows up
+    pub fn trigger(&self, source: &str) -> bool {
+        match self.notifies.lock().unwrap().get(source) {
+            Some(x) => {
+                x.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    // for a manager to take commands from; there's only one listener per source
+    pub fn commands(&self, source: &str) -> mpsc::Receiver<ManagerCommand> {
+        let (sender, receiver) = mpsc::channel(16);
+        self.commands
+            .lock()
+            .unwrap()
+            .insert(source.to_string(), sender);
+        receiver
+    }
+
+    // false if nothing is listening for this source
+    pub async fn send(&self, source: &str, command: ManagerCommand) -> bool {
+        let sender = match self.commands.lock().unwrap().get(source) {
+            Some(x) => x.clone(),
+            None => return false,
+        };
+        sender.send(command).await.is_ok()
+    }
+
+    pub async fn state(&self, source: &str) -> Option<RunState> {
+        let (sender, receiver) = oneshot::channel();
+        if !self.send(source, ManagerCommand::DumpState(sender)).await {
+            return None;
+        }
+        receiver.await.ok()
+    }
+
+    pub async fn states(&self) -> Vec<RunState> {
+        let mut sources = self
+            .commands
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        sources.sort();
+        let mut states = vec![];
+        for source in sources {
+            match self.state(&source).await {
+                Some(x) => states.push(x),
+                None => (),
+            }
+        }
+        states
+    }
+
+    pub fn sources(&self) -> Vec<String> {
+        let mut sources = self
+            .notifies
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        sources.sort();
+        sources
+    }
+}
+
+// Something a manager does once a day at a set local time, or sooner if triggered. A triggered
+// run stands in for the next scheduled one, so the same update isn't fetched twice. The admin
+// endpoints can pause it, move the time, or ask how it stands.
+pub struct DailyRun {
+    source: String,
+    timezone: Tz,
+    time: NaiveTime,
+    trigger: Arc<Notify>,
+    commands: mpsc::Receiver<ManagerCommand>,
+    done: Option<NaiveDate>,
+    paused: bool,
+    missed: bool,
+}
+
+impl DailyRun {
+    pub fn new(timezone: Tz, time: NaiveTime, triggers: &Triggers, source: &str) -> Self {
+        Self {
+            source: source.to_string(),
+            timezone,
+            time,
+            trigger: triggers.get(source),
+            commands: triggers.commands(source),
+            done: None,
+            paused: false,
+            missed: false,
+        }
+    }
+
+    // the next date to run for, and when that is
+    fn next_run(&self) -> (NaiveDate, DateTime<Tz>) {
+        let now = self.timezone.from_utc_datetime(&Utc::now().naive_utc());
+        let mut date = now.date_naive();
+        if now.time() > self.time {
+            date = date.succ_opt().unwrap();
+        }
+        // a triggered run can be for tomorrow's already
+        match self.done {
+            Some(x) if x >= date => date = x.succ_opt().unwrap(),
+            _ => (),
+        }
+        let time = self
+            .timezone
+            .from_local_datetime(&date.and_time(self.time))
+            .earliest()
+            .unwrap();
+        (date, time)
+    }
+
+    fn handle(&mut self, command: ManagerCommand, next_run: &mut (NaiveDate, DateTime<Tz>)) {
+        match command {
+            ManagerCommand::Pause => {
+                println!("Pausing {}", self.source);
+                self.paused = true;
+            }
+            ManagerCommand::Resume => {
+                println!("Resuming {}", self.source);
+                self.paused = false;
+            }
+            ManagerCommand::SetTime(x) => {
+                println!("Running {} at {} from now on", self.source, x);
+                self.time = x;
+                *next_run = self.next_run();
+            }
+            ManagerCommand::DumpState(x) => {
+                // nobody waiting for the answer any more is fine
+                let _ = x.send(RunState {
+                    source: self.source.clone(),
+                    paused: self.paused,
+                    missed: self.missed,
+                    time: self.time,
+                    timezone: self.timezone,
+                    next_run: next_run.1,
+                    last_run: self.done,
+                });
+            }
+        }
+    }
+
+    // returns whether it was triggered rather than the time coming round
+    pub async fn wait(&mut self) -> bool {
+        let mut next_run = self.next_run();
+
+        let mut interval = time::interval(Duration::from_secs(15));
+        loop {
+            if !self.paused && self.missed {
+                self.missed = false;
+                return false;
+            }
+            if self.timezone.from_utc_datetime(&Utc::now().naive_utc()) >= next_run.1 {
+                self.done = Some(next_run.0);
+                if !self.paused {
+                    return false;
+                }
+                self.missed = true;
+                next_run = self.next_run();
+            }
+            tokio::select! {
+                _ = interval.tick() => (),
+                _ = self.trigger.notified() => {
+                    self.done = Some(next_run.0);
+                    if !self.paused {
+                        return true;
+                    }
+                    self.missed = true;
+                    next_run = self.next_run();
+                }
+                Some(command) = self.commands.recv() => self.handle(command, &mut next_run),
+            }
+        }
+    }
+}
@@ -1,21 +1,28 @@
+use crate::change_log::{ChangeKind, TrainChange};
 use crate::error::Error;
-use crate::importer::{EphemeralImporter, FastImporter, SlowStreamingImporter};
+use crate::feed_telemetry::FeedTelemetry;
+use crate::importer::{EphemeralImporter, FastImporter, ImportReport, SlowStreamingImporter};
+use crate::intern::IStr;
+use crate::partitioned_map::PartitionedMap;
 use crate::schedule::{
-    Activities, AssociationNode, Catering, DaysOfWeek, Location, OperatingCharacteristics,
-    ReservationField, Reservations, Schedule, Train, TrainAllocation, TrainLocation, TrainOperator,
-    TrainPower, TrainSource, TrainType, TrainValidityPeriod, VariableTrain,
+    call_index, Activities, AssociationCategory, AssociationNode, Catering, DaysOfWeek, Location,
+    OperatingCharacteristics, PendingAssociation, PendingOverlay, ReservationField, Reservations,
+    Schedule, Train, TrainAllocation, TrainCancellation, TrainLocation, TrainOperator, TrainPower,
+    TrainSource, TrainType, TrainValidityPeriod, VariableTrain,
 };
+use crate::vehicles;
 
 use async_trait::async_trait;
 use chrono::format::ParseError;
 use chrono::naive::Days;
-use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
 use chrono_tz::Europe::London;
 use chrono_tz::Tz;
 use itertools::Itertools;
 
 use serde::{Deserialize, Serialize};
 
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt;
@@ -29,8 +36,76 @@ use tokio::sync::Mutex;
 #[derive(Clone, Default, Deserialize)]
 pub struct CifImporterConfig {
     location_overrides: Option<String>,
+    #[serde(default)]
+    dialect: CifDialect,
+    lenient: Option<bool>, // skip bad records instead of failing the whole import
+    ignore_record_types: Option<Vec<String>>, // new ones to skip rather than fail on, e.g. ["TS"]
 }
 
+impl CifImporterConfig {
+    pub fn with_lenient(mut self, lenient: bool) -> Self {
+        self.lenient = Some(lenient);
+        self
+    }
+
+    // whether an ATOC code means anything to us, even if we've been told to ignore it when not
+    pub fn knows_operator(&self, atoc_code: &str) -> bool {
+        self.dialect.operators.contains_key(atoc_code)
+            || read_train_operator(atoc_code, |x| x).is_ok()
+    }
+}
+
+// Not every CIF is quite NR's CIF. Anything not given here falls back to the NR tables.
+#[derive(Clone, Default, Deserialize)]
+pub struct CifDialect {
+    timezone: Option<Tz>,
+    #[serde(default)]
+    train_categories: HashMap<String, TrainType>,
+    #[serde(default)]
+    operators: HashMap<String, String>,
+    #[serde(default)]
+    ignore_unknown_operators: bool,
+    // NIR gives intermediate locations a train runs through with no times at all. These are let
+    // through rather than rejected, to have times inferred later (Schedule::infer_passing_times).
+    #[serde(default)]
+    untimed_locations: bool,
+}
+
+impl CifDialect {
+    fn timezone(&self) -> Tz {
+        self.timezone.unwrap_or(London)
+    }
+
+    fn read_train_type<F, T>(&self, slice: &str, error_logic: F) -> Result<Option<TrainType>, T>
+    where
+        F: FnOnce(CifErrorType) -> T,
+    {
+        match self.train_categories.get(slice.trim()) {
+            Some(x) => Ok(Some(*x)),
+            None => read_train_type(slice, error_logic),
+        }
+    }
+
+    fn read_train_operator<F, T>(&self, slice: &str, error_logic: F) -> Result<Option<String>, T>
+    where
+        F: FnOnce(CifErrorType) -> T,
+    {
+        match self.operators.get(slice) {
+            Some(x) => Ok(Some(x.clone())),
+            None => match read_train_operator(slice, |x| x) {
+                Ok(x) => Ok(x),
+                Err(_) if self.ignore_unknown_operators => Ok(None),
+                Err(x) => Err(error_logic(x)),
+            },
+        }
+    }
+}
+
+// everything we know how to read; only others can be ignored
+const RECORD_TYPES: &[&str] = &[
+    "HD", "TI", "TA", "TD", "AA", "BS", "BX", "LO", "LI", "LT", "CR", "ZZ",
+];
+
 #[derive(Default)]
 pub struct CifImporter {
     last_train: Option<(String, DateTime<Tz>, ModificationType, bool)>,
@@ -40,6 +115,22 @@ pub struct CifImporter {
     cr_location: Option<(String, Option<String>)>,
     orphaned_overlay_trains: HashMap<(String, DateTime<Tz>), Train>,
     config: CifImporterConfig,
+    skipping_train: bool, // a record for the current train was bad, so ignore the rest of it
+    record_state: TrainRecordState,
+    report: ImportReport,
+    ignored_records: HashMap<String, u64>, // by record type, in the last overlay
+}
+
+// Where we are in the records for a train, which go BS, BX, LO, any number of LIs (each of which
+// may have a CR before it), then LT. Deletes and STP cancellations are a BS on its own.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum TrainRecordState {
+    #[default]
+    Between,
+    Started,  // BS
+    Extended, // BX
+    Running,  // LO or LI
+    Changing, // CR, which has to be followed by the LI or LT it applies at
 }
 
 #[derive(Clone, Debug)]
@@ -75,6 +166,11 @@ pub enum CifErrorType {
     InvalidDaysOfWeek(String),
     NoScheduleSegments,
     NotEnoughLocations,
+    RecordOutOfOrder(String, &'static str),
+    LastTrainNotFound(String),
+    EndsBeforeBeginning(DateTime<Tz>, DateTime<Tz>),
+    NonAsciiRecord,
+    UnknownFields(String),
 }
 
 impl fmt::Display for CifErrorType {
@@ -111,43 +207,30 @@ impl fmt::Display for CifErrorType {
             CifErrorType::InvalidDaysOfWeek(x) => write!(f, "Invalid days of week string {}", x),
             CifErrorType::NoScheduleSegments => write!(f, "No schedule segments"),
             CifErrorType::NotEnoughLocations => write!(f, "Not enough locations"),
+            CifErrorType::RecordOutOfOrder(x, y) => write!(f, "{} record out of order; expected {}", x, y),
+            CifErrorType::LastTrainNotFound(x) => write!(f, "Unable to find last-written train {}", x),
+            CifErrorType::NonAsciiRecord => write!(f, "Record contains characters outside ASCII"),
+            CifErrorType::EndsBeforeBeginning(x, y) => write!(f, "Ends on {} before it begins on {}", y.date_naive(), x.date_naive()),
+            CifErrorType::UnknownFields(x) => write!(f, "Unknown fields {}", x),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
+#[error("Error reading CIF file line {line} column {column}: {error_type}")]
 pub struct CifError {
     error_type: CifErrorType,
     line: u64,
     column: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, thiserror::Error)]
+#[error("Error reading VSTP JSON field {field_name}: {error_type}")]
 pub struct NrJsonError {
     error_type: CifErrorType,
     field_name: String,
 }
 
-impl fmt::Display for CifError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Error reading CIF file line {} column {}: {}",
-            self.line, self.column, self.error_type
-        )
-    }
-}
-
-impl fmt::Display for NrJsonError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "Error reading VSTP JSON field {}: {}",
-            self.field_name, self.error_type
-        )
-    }
-}
-
 #[derive(Clone, Copy, Debug, PartialEq)]
 enum ModificationType {
     Insert,
@@ -155,16 +238,6 @@ enum ModificationType {
     Delete,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum AssociationCategory {
-    Join,
-    Divide,
-    Next,
-    IsJoinedToBy,
-    DividesFrom,
-    FormsFrom,
-}
-
 #[derive(Clone, Copy, Debug, PartialEq)]
 enum TrainStatus {
     Bus,
@@ -243,8 +316,186 @@ fn check_date_applicability(
     }
 }
 
+// whether any of the new periods has days in common with any of the existing ones
+fn periods_overlap(existing: &[TrainValidityPeriod], new: &[TrainValidityPeriod]) -> bool {
+    new.iter().any(|x| {
+        existing
+            .iter()
+            .any(|y| check_date_applicability(y, x.valid_begin, x.valid_end, &x.days_of_week))
+    })
+}
+
+// the version standing in for a pending overlay, which is a copy of it
+fn is_standalone_copy(train: &Train, overlay: &Train) -> bool {
+    train.source == overlay.source
+        && train.validity[0].valid_begin == overlay.validity[0].valid_begin
+}
+
+// Puts pending overlays on whatever they replace, now that it may have turned up. Those still
+// with nothing to replace stay pending; if there's nothing at all under their train ID, they run
+// as trains of their own until there is. Returns how many were attached.
+fn attach_pending_overlays(schedule: &mut Schedule) -> usize {
+    let mut attached = 0;
+    let train_ids = schedule
+        .pending_overlays
+        .keys()
+        .cloned()
+        .collect::<Vec<_>>();
+    for train_id in train_ids {
+        let pending = schedule.pending_overlays.remove(&train_id).unwrap();
+        let mut trains = schedule.trains.remove(&train_id).unwrap_or_default();
+        trains.retain(|train| {
+            !pending
+                .iter()
+                .any(|x| x.standalone && is_standalone_copy(train, &x.overlay))
+        });
+
+        let mut still_pending = vec![];
+        for mut x in pending {
+            let validity = &x.overlay.validity[0];
+            let mut replaced = false;
+            for train in trains.iter_mut() {
+                if !check_date_applicability(
+                    &train.validity[0],
+                    validity.valid_begin,
+                    validity.valid_end,
+                    &validity.days_of_week,
+                ) {
+                    continue;
+                }
+                replaced = true;
+                train.replacements.retain(|replacement| {
+                    replacement.validity[0].valid_begin != validity.valid_begin
+                });
+                train.replacements.push(x.overlay.clone());
+            }
+            match replaced {
+                true => attached += 1,
+                false => {
+                    x.standalone = false;
+                    still_pending.push(x);
+                }
+            }
+        }
+        // This orphaned overlay was probably intended to be an N instead.
+        if trains.is_empty() {
+            for x in still_pending.iter_mut() {
+                x.standalone = true;
+                trains.push(x.overlay.clone());
+            }
+        }
+
+        if !trains.is_empty() {
+            schedule.trains.insert(train_id.clone(), trains);
+        }
+        if !still_pending.is_empty() {
+            schedule
+                .pending_overlays
+                .insert(train_id.clone(), still_pending);
+        }
+        schedule.reindex_train(&train_id);
+    }
+    attached
+}
+
+// for when a later file deletes an overlay that never found what it replaces
+fn delete_pending_overlay(schedule: &mut Schedule, train_id: &str, begin: &DateTime<Tz>) {
+    let pending = match schedule.pending_overlays.get_mut(train_id) {
+        Some(x) => x,
+        None => return,
+    };
+    let position = match pending
+        .iter()
+        .position(|x| x.overlay.validity[0].valid_begin == *begin)
+    {
+        Some(x) => x,
+        None => return,
+    };
+    let removed = pending.remove(position);
+    if pending.is_empty() {
+        schedule.pending_overlays.remove(train_id);
+    }
+    if removed.standalone {
+        match schedule.trains.get_mut(train_id) {
+            Some(trains) => {
+                trains.retain(|train| !is_standalone_copy(train, &removed.overlay));
+                if trains.is_empty() {
+                    schedule.trains.remove(train_id);
+                }
+            }
+            None => (),
+        }
+        schedule.reindex_train(train_id);
+    }
+}
+
+// Whether two nodes are the same association bar when it applies, so can be one node with both
+// sets of periods. NR sends a separate insert for each period, e.g. when a pair of trains join on
+// different days of the week in the summer.
+fn is_same_association(assoc: &AssociationNode, other: &AssociationNode) -> bool {
+    assoc.other_train_id == other.other_train_id
+        && assoc.other_train_location_id_suffix == other.other_train_location_id_suffix
+        && assoc.source == other.source
+        && assoc.day_diff == other.day_diff
+        && assoc.for_passengers == other.for_passengers
+}
+
+fn merge_assoc_periods(assoc: &mut AssociationNode, other: &AssociationNode) {
+    for period in &other.validity {
+        if !assoc.validity.contains(period) {
+            assoc.validity.push(period.clone());
+        }
+    }
+    assoc
+        .cancellations
+        .extend(other.cancellations.iter().cloned());
+    assoc
+        .replacements
+        .extend(other.replacements.iter().cloned());
+}
+
+fn add_assoc(assocs: &mut Vec<AssociationNode>, new_assoc: &AssociationNode) {
+    match assocs
+        .iter_mut()
+        .find(|x| is_same_association(x, new_assoc))
+    {
+        Some(x) => merge_assoc_periods(x, new_assoc),
+        None => assocs.push(new_assoc.clone()),
+    }
+}
+
+// there's only room for one, so anything else is replaced
+fn add_single_assoc(assoc: &mut Option<AssociationNode>, new_assoc: &AssociationNode) {
+    match assoc {
+        Some(x) if is_same_association(x, new_assoc) => merge_assoc_periods(x, new_assoc),
+        _ => *assoc = Some(new_assoc.clone()),
+    }
+}
+
+fn add_unwritten_assoc(
+    assocs: &mut Vec<(AssociationNode, AssociationCategory)>,
+    new_assoc: AssociationNode,
+    category: AssociationCategory,
+) {
+    match assocs
+        .iter_mut()
+        .find(|(x, y)| *y == category && is_same_association(x, &new_assoc))
+    {
+        Some((x, _)) => merge_assoc_periods(x, &new_assoc),
+        None => assocs.push((new_assoc, category)),
+    }
+}
+
+// Drops the period an AA delete or amend refers to, leaving any others
+fn delete_assoc_period(assoc: &mut AssociationNode, begin: &DateTime<Tz>, use_rev: bool) {
+    let day_diff = assoc.day_diff;
+    assoc.validity.retain(|x| {
+        !is_matching_assoc_for_modify_replacement_or_cancel(x, begin, day_diff, use_rev)
+    });
+}
+
 fn write_assocs_to_trains(
-    trains: &mut Vec<Train>,
+    trains: &mut [Train],
     location: &str,
     location_suffix: &Option<String>,
     assocs: &Vec<(AssociationNode, AssociationCategory)>,
@@ -258,35 +509,31 @@ fn write_assocs_to_trains(
             &assocs,
         );
 
-        for ref mut train_location in train.route.iter_mut() {
-            if train_location.id == location && train_location.id_suffix == *location_suffix {
-                for (assoc, category) in assocs {
-                    if !check_date_applicability(
-                        &train.validity[0],
-                        assoc.validity[0].valid_begin,
-                        assoc.validity[0].valid_end,
-                        &assoc.validity[0].days_of_week,
-                    ) {
-                        continue;
-                    }
-                    // we now know this is applicable to this train, so add it
-                    match category {
-                        AssociationCategory::Join => train_location.joins_to.push(assoc.clone()),
-                        AssociationCategory::Divide => {
-                            train_location.divides_to_form.push(assoc.clone())
-                        }
-                        AssociationCategory::Next => train_location.becomes = Some(assoc.clone()),
-                        AssociationCategory::IsJoinedToBy => {
-                            train_location.is_joined_to_by.push(assoc.clone())
-                        }
-                        AssociationCategory::DividesFrom => {
-                            train_location.divides_from.push(assoc.clone())
-                        }
-                        AssociationCategory::FormsFrom => {
-                            train_location.forms_from = Some(assoc.clone())
-                        }
-                    };
+        if let Some(index) = call_index(&train.route, location, location_suffix) {
+            let train_location = &mut train.route[index];
+            for (assoc, category) in assocs {
+                if !periods_overlap(&train.validity, &assoc.validity) {
+                    continue;
                 }
+                // we now know this is applicable to this train, so add it
+                match category {
+                    AssociationCategory::Join => add_assoc(&mut train_location.joins_to, assoc),
+                    AssociationCategory::Divide => {
+                        add_assoc(&mut train_location.divides_to_form, assoc)
+                    }
+                    AssociationCategory::Next => {
+                        add_single_assoc(&mut train_location.becomes, assoc)
+                    }
+                    AssociationCategory::IsJoinedToBy => {
+                        add_assoc(&mut train_location.is_joined_to_by, assoc)
+                    }
+                    AssociationCategory::DividesFrom => {
+                        add_assoc(&mut train_location.divides_from, assoc)
+                    }
+                    AssociationCategory::FormsFrom => {
+                        add_single_assoc(&mut train_location.forms_from, assoc)
+                    }
+                };
             }
         }
     }
@@ -303,13 +550,9 @@ fn is_matching_assoc_for_modify_insertion(
     return match is_stp {
         false => assoc.source.unwrap() == TrainSource::LongTerm, // match the entire association for deleted or modified inserts
         true => assoc.source.unwrap() == TrainSource::ShortTerm,
-    } && assoc.validity[0].valid_begin
-        == if use_rev {
-            rev_date(begin, assoc.day_diff)
-        } else {
-            *begin
-        }
-        && other_train_id == assoc.other_train_id
+    } && assoc.validity.iter().any(|x| {
+        is_matching_assoc_for_modify_replacement_or_cancel(x, begin, assoc.day_diff, use_rev)
+    }) && other_train_id == assoc.other_train_id
         && *other_train_location_suffix == assoc.other_train_location_id_suffix;
 }
 
@@ -342,12 +585,14 @@ fn delete_single_assoc_replacements_cancellations(
     }
     if *stp_modification_type == ModificationType::Amend {
         assoc.replacements.retain(|assoc| {
-            !is_matching_assoc_for_modify_replacement_or_cancel(
-                &assoc.validity[0],
-                begin,
-                assoc.day_diff,
-                use_rev,
-            )
+            !assoc.validity.iter().any(|x| {
+                is_matching_assoc_for_modify_replacement_or_cancel(
+                    x,
+                    begin,
+                    assoc.day_diff,
+                    use_rev,
+                )
+            })
         });
     } else if *stp_modification_type == ModificationType::Delete {
         assoc.cancellations.retain(|(validity, _source)| {
@@ -371,16 +616,19 @@ fn delete_single_vec_assocs(
     use_rev: bool,
 ) {
     if *stp_modification_type == ModificationType::Insert {
-        assocs.retain(|assoc| {
-            !is_matching_assoc_for_modify_insertion(
+        for assoc in assocs.iter_mut() {
+            if is_matching_assoc_for_modify_insertion(
                 assoc,
                 other_train_id,
                 begin,
                 other_train_location_suffix,
                 is_stp,
                 use_rev,
-            )
-        });
+            ) {
+                delete_assoc_period(assoc, begin, use_rev);
+            }
+        }
+        assocs.retain(|x| !x.validity.is_empty());
     } else {
         for ref mut assoc in assocs.iter_mut() {
             delete_single_assoc_replacements_cancellations(
@@ -395,6 +643,54 @@ fn delete_single_vec_assocs(
     }
 }
 
+fn delete_pending_assocs(
+    pending: &mut PartitionedMap<Vec<PendingAssociation>>,
+    main_train_id: &str,
+    location: &str,
+    location_suffix: &Option<String>,
+    other_train_id: &str,
+    begin: &DateTime<Tz>,
+    other_train_location_suffix: &Option<String>,
+    stp_modification_type: &ModificationType,
+    is_stp: bool,
+    use_rev: bool,
+) {
+    let pending = match pending.get_mut(main_train_id) {
+        Some(x) => x,
+        None => return,
+    };
+    let at_location = |x: &PendingAssociation| {
+        x.location_id == location && x.location_id_suffix == *location_suffix
+    };
+
+    if *stp_modification_type == ModificationType::Insert {
+        for x in pending.iter_mut().filter(|x| at_location(x)) {
+            if is_matching_assoc_for_modify_insertion(
+                &x.association,
+                other_train_id,
+                begin,
+                other_train_location_suffix,
+                is_stp,
+                use_rev,
+            ) {
+                delete_assoc_period(&mut x.association, begin, use_rev);
+            }
+        }
+        pending.retain(|x| !x.association.validity.is_empty());
+    } else {
+        for x in pending.iter_mut().filter(|x| at_location(x)) {
+            delete_single_assoc_replacements_cancellations(
+                &mut x.association,
+                other_train_id,
+                begin,
+                other_train_location_suffix,
+                stp_modification_type,
+                use_rev,
+            );
+        }
+    }
+}
+
 fn amend_individual_assoc(
     assoc: &mut AssociationNode,
     begin: &DateTime<Tz>,
@@ -412,11 +708,19 @@ fn amend_individual_assoc(
             rev_days(days_of_week, assoc.day_diff),
         ),
     };
-    assoc.validity = vec![TrainValidityPeriod {
-        valid_begin: new_begin.clone(),
-        valid_end: new_end.clone(),
-        days_of_week: new_days.clone(),
-    }];
+    // only the period this is for; a new date indicator or category does apply to all of them,
+    // but NR only sends those when the whole association changes
+    let old_day_diff = assoc.day_diff;
+    for period in assoc.validity.iter_mut() {
+        if is_matching_assoc_for_modify_replacement_or_cancel(period, begin, old_day_diff, use_rev)
+        {
+            *period = TrainValidityPeriod {
+                valid_begin: new_begin.clone(),
+                valid_end: new_end.clone(),
+                days_of_week: new_days.clone(),
+            };
+        }
+    }
     match day_diff {
         None => (),
         Some(x) => assoc.day_diff = x * if use_rev { -1 } else { 1 },
@@ -454,7 +758,7 @@ fn amend_single_assoc_replacements_cancellations(
     }
     if *stp_modification_type == ModificationType::Amend {
         for replacement in assoc.replacements.iter_mut() {
-            if replacement.validity[0].valid_begin == *begin {
+            if replacement.validity.iter().any(|x| x.valid_begin == *begin) {
                 amend_individual_assoc(
                     replacement,
                     begin,
@@ -551,14 +855,14 @@ fn cancel_single_assoc(
             (*begin, *end, *days_of_week)
         };
 
-        if !check_date_applicability(&assoc.validity[0], rev_begin, rev_end, &rev_days_of_week) {
-            return;
-        }
         let new_cancel = TrainValidityPeriod {
             valid_begin: rev_begin,
             valid_end: rev_end,
             days_of_week: rev_days_of_week.clone(),
         };
+        if !periods_overlap(&assoc.validity, &[new_cancel.clone()]) {
+            return;
+        }
         assoc
             .cancellations
             .push((new_cancel, TrainSource::ShortTerm))
@@ -598,12 +902,7 @@ fn replace_single_vec_assocs(
             && *other_train_location_suffix == assoc.other_train_location_id_suffix
         {
             // check for no overlapping days at all
-            if !check_date_applicability(
-                &assoc.validity[0],
-                new_assoc.validity[0].valid_begin,
-                new_assoc.validity[0].valid_end,
-                &new_assoc.validity[0].days_of_week,
-            ) {
+            if !periods_overlap(&assoc.validity, &new_assoc.validity) {
                 continue;
             }
             assoc.replacements.push(new_assoc.clone());
@@ -648,10 +947,8 @@ fn trains_delete_assoc(
             is_stp,
         );
 
-        for ref mut train_location in train.route.iter_mut() {
-            if train_location.id != location || train_location.id_suffix != *location_suffix {
-                continue;
-            }
+        if let Some(index) = call_index(&train.route, location, location_suffix) {
+            let train_location = &mut train.route[index];
             delete_single_vec_assocs(
                 &mut train_location.divides_to_form,
                 other_train_id,
@@ -689,7 +986,10 @@ fn trains_delete_assoc(
                         false,
                     )
                 {
-                    train_location.becomes = None;
+                    delete_assoc_period(assoc, begin, false);
+                    if assoc.validity.is_empty() {
+                        train_location.becomes = None;
+                    }
                 }
             }
         }
@@ -719,10 +1019,8 @@ fn trains_delete_rev_assoc(
             is_stp,
         );
 
-        for ref mut train_location in train.route.iter_mut() {
-            if train_location.id != location || train_location.id_suffix != *location_suffix {
-                continue;
-            }
+        if let Some(index) = call_index(&train.route, location, location_suffix) {
+            let train_location = &mut train.route[index];
             delete_single_vec_assocs(
                 &mut train_location.divides_from,
                 other_train_id,
@@ -760,7 +1058,10 @@ fn trains_delete_rev_assoc(
                         true,
                     )
                 {
-                    train_location.forms_from = None;
+                    delete_assoc_period(assoc, begin, true);
+                    if assoc.validity.is_empty() {
+                        train_location.forms_from = None;
+                    }
                 }
             }
         }
@@ -798,10 +1099,8 @@ fn trains_amend_assoc(
             for_passengers,
         );
 
-        for ref mut train_location in train.route.iter_mut() {
-            if train_location.id != location || train_location.id_suffix != *location_suffix {
-                continue;
-            }
+        if let Some(index) = call_index(&train.route, location, location_suffix) {
+            let train_location = &mut train.route[index];
             amend_single_vec_assocs(
                 &mut train_location.divides_to_form,
                 other_train_id,
@@ -897,10 +1196,8 @@ fn trains_amend_rev_assoc(
             for_passengers,
         );
 
-        for ref mut train_location in train.route.iter_mut() {
-            if train_location.id != location || train_location.id_suffix != *location_suffix {
-                continue;
-            }
+        if let Some(index) = call_index(&train.route, location, location_suffix) {
+            let train_location = &mut train.route[index];
             amend_single_vec_assocs(
                 &mut train_location.divides_from,
                 other_train_id,
@@ -988,19 +1285,29 @@ fn trains_cancel_assoc(
             &other_train_location_suffix,
         );
 
-        for ref mut train_location in train.route.iter_mut() {
-            if train_location.id == location && train_location.id_suffix == *location_suffix {
-                cancel_single_vec_assocs(
-                    &mut train_location.divides_to_form,
-                    other_train_id,
-                    begin,
-                    end,
-                    days_of_week,
-                    other_train_location_suffix,
-                    false,
-                );
-                cancel_single_vec_assocs(
-                    &mut train_location.joins_to,
+        if let Some(index) = call_index(&train.route, location, location_suffix) {
+            let train_location = &mut train.route[index];
+            cancel_single_vec_assocs(
+                &mut train_location.divides_to_form,
+                other_train_id,
+                begin,
+                end,
+                days_of_week,
+                other_train_location_suffix,
+                false,
+            );
+            cancel_single_vec_assocs(
+                &mut train_location.joins_to,
+                other_train_id,
+                begin,
+                end,
+                days_of_week,
+                other_train_location_suffix,
+                false,
+            );
+            if let Some(assoc) = &mut train_location.becomes {
+                cancel_single_assoc(
+                    assoc,
                     other_train_id,
                     begin,
                     end,
@@ -1008,17 +1315,6 @@ fn trains_cancel_assoc(
                     other_train_location_suffix,
                     false,
                 );
-                if let Some(assoc) = &mut train_location.becomes {
-                    cancel_single_assoc(
-                        assoc,
-                        other_train_id,
-                        begin,
-                        end,
-                        days_of_week,
-                        other_train_location_suffix,
-                        false,
-                    );
-                }
             }
         }
     }
@@ -1047,19 +1343,29 @@ fn trains_cancel_rev_assoc(
             &other_train_location_suffix,
         );
 
-        for ref mut train_location in train.route.iter_mut() {
-            if train_location.id == location && train_location.id_suffix == *location_suffix {
-                cancel_single_vec_assocs(
-                    &mut train_location.divides_from,
-                    other_train_id,
-                    begin,
-                    end,
-                    days_of_week,
-                    other_train_location_suffix,
-                    true,
-                );
-                cancel_single_vec_assocs(
-                    &mut train_location.is_joined_to_by,
+        if let Some(index) = call_index(&train.route, location, location_suffix) {
+            let train_location = &mut train.route[index];
+            cancel_single_vec_assocs(
+                &mut train_location.divides_from,
+                other_train_id,
+                begin,
+                end,
+                days_of_week,
+                other_train_location_suffix,
+                true,
+            );
+            cancel_single_vec_assocs(
+                &mut train_location.is_joined_to_by,
+                other_train_id,
+                begin,
+                end,
+                days_of_week,
+                other_train_location_suffix,
+                true,
+            );
+            if let Some(assoc) = &mut train_location.forms_from {
+                cancel_single_assoc(
+                    assoc,
                     other_train_id,
                     begin,
                     end,
@@ -1067,17 +1373,6 @@ fn trains_cancel_rev_assoc(
                     other_train_location_suffix,
                     true,
                 );
-                if let Some(assoc) = &mut train_location.forms_from {
-                    cancel_single_assoc(
-                        assoc,
-                        other_train_id,
-                        begin,
-                        end,
-                        days_of_week,
-                        other_train_location_suffix,
-                        true,
-                    );
-                }
             }
         }
     }
@@ -1102,35 +1397,29 @@ fn trains_replace_assoc(
             &new_assoc,
         );
 
-        for ref mut train_location in train.route.iter_mut() {
-            if train_location.id == location && train_location.id_suffix == *location_suffix {
-                replace_single_vec_assocs(
-                    &mut train_location.divides_to_form,
-                    other_train_id,
-                    other_train_location_suffix,
-                    new_assoc,
-                );
-                replace_single_vec_assocs(
-                    &mut train_location.joins_to,
-                    other_train_id,
-                    other_train_location_suffix,
-                    new_assoc,
-                );
-                if let Some(assoc) = &mut train_location.becomes {
-                    if other_train_id == assoc.other_train_id
-                        && *other_train_location_suffix == assoc.other_train_location_id_suffix
-                    {
-                        // check for no overlapping days at all
-                        if !check_date_applicability(
-                            &assoc.validity[0],
-                            new_assoc.validity[0].valid_begin,
-                            new_assoc.validity[0].valid_end,
-                            &new_assoc.validity[0].days_of_week,
-                        ) {
-                            continue;
-                        }
-                        assoc.replacements.push(new_assoc.clone());
+        if let Some(index) = call_index(&train.route, location, location_suffix) {
+            let train_location = &mut train.route[index];
+            replace_single_vec_assocs(
+                &mut train_location.divides_to_form,
+                other_train_id,
+                other_train_location_suffix,
+                new_assoc,
+            );
+            replace_single_vec_assocs(
+                &mut train_location.joins_to,
+                other_train_id,
+                other_train_location_suffix,
+                new_assoc,
+            );
+            if let Some(assoc) = &mut train_location.becomes {
+                if other_train_id == assoc.other_train_id
+                    && *other_train_location_suffix == assoc.other_train_location_id_suffix
+                {
+                    // check for no overlapping days at all
+                    if !periods_overlap(&assoc.validity, &new_assoc.validity) {
+                        continue;
                     }
+                    assoc.replacements.push(new_assoc.clone());
                 }
             }
         }
@@ -1156,35 +1445,29 @@ fn trains_replace_rev_assoc(
             &new_assoc,
         );
 
-        for ref mut train_location in train.route.iter_mut() {
-            if train_location.id == location && train_location.id_suffix == *location_suffix {
-                replace_single_vec_assocs(
-                    &mut train_location.divides_from,
-                    other_train_id,
-                    other_train_location_suffix,
-                    new_assoc,
-                );
-                replace_single_vec_assocs(
-                    &mut train_location.is_joined_to_by,
-                    other_train_id,
-                    other_train_location_suffix,
-                    new_assoc,
-                );
-                if let Some(assoc) = &mut train_location.forms_from {
-                    if other_train_id == assoc.other_train_id
-                        && *other_train_location_suffix == assoc.other_train_location_id_suffix
-                    {
-                        // check for no overlapping days at all
-                        if !check_date_applicability(
-                            &assoc.validity[0],
-                            new_assoc.validity[0].valid_begin,
-                            new_assoc.validity[0].valid_end,
-                            &new_assoc.validity[0].days_of_week,
-                        ) {
-                            continue;
-                        }
-                        assoc.replacements.push(new_assoc.clone());
+        if let Some(index) = call_index(&train.route, location, location_suffix) {
+            let train_location = &mut train.route[index];
+            replace_single_vec_assocs(
+                &mut train_location.divides_from,
+                other_train_id,
+                other_train_location_suffix,
+                new_assoc,
+            );
+            replace_single_vec_assocs(
+                &mut train_location.is_joined_to_by,
+                other_train_id,
+                other_train_location_suffix,
+                new_assoc,
+            );
+            if let Some(assoc) = &mut train_location.forms_from {
+                if other_train_id == assoc.other_train_id
+                    && *other_train_location_suffix == assoc.other_train_location_id_suffix
+                {
+                    // check for no overlapping days at all
+                    if !periods_overlap(&assoc.validity, &new_assoc.validity) {
+                        continue;
                     }
+                    assoc.replacements.push(new_assoc.clone());
                 }
             }
         }
@@ -1255,46 +1538,48 @@ where
     return Ok((stp_modification_type, is_stp));
 }
 
-fn read_date<F, T>(date_slice: &str, error_logic: F) -> Result<DateTime<Tz>, T>
+fn read_date_with_format<F, T>(
+    date_slice: &str,
+    format: &str,
+    timezone: &Tz,
+    error_logic: F,
+) -> Result<DateTime<Tz>, T>
 where
     F: FnOnce(CifErrorType) -> T,
 {
-    let parsed_date = NaiveDate::parse_from_str(date_slice, "%y%m%d");
+    let parsed_date = NaiveDate::parse_from_str(date_slice, format);
     let parsed_date = match parsed_date {
         Ok(x) => x,
         Err(x) => return Err(error_logic(CifErrorType::ChronoParseError(x))),
     };
-    Ok(London
+    Ok(timezone
         .from_local_datetime(&parsed_date.and_hms_opt(0, 0, 0).unwrap())
         .unwrap())
 }
 
-fn read_backwards_date<F, T>(date_slice: &str, error_logic: F) -> Result<DateTime<Tz>, T>
+fn read_date<F, T>(date_slice: &str, timezone: &Tz, error_logic: F) -> Result<DateTime<Tz>, T>
 where
     F: FnOnce(CifErrorType) -> T,
 {
-    let parsed_date = NaiveDate::parse_from_str(date_slice, "%d%m%y");
-    let parsed_date = match parsed_date {
-        Ok(x) => x,
-        Err(x) => return Err(error_logic(CifErrorType::ChronoParseError(x))),
-    };
-    Ok(London
-        .from_local_datetime(&parsed_date.and_hms_opt(0, 0, 0).unwrap())
-        .unwrap())
+    read_date_with_format(date_slice, "%y%m%d", timezone, error_logic)
+}
+
+fn read_backwards_date<F, T>(
+    date_slice: &str,
+    timezone: &Tz,
+    error_logic: F,
+) -> Result<DateTime<Tz>, T>
+where
+    F: FnOnce(CifErrorType) -> T,
+{
+    read_date_with_format(date_slice, "%d%m%y", timezone, error_logic)
 }
 
 fn read_vstp_date<F, T>(date_slice: &str, error_logic: F) -> Result<DateTime<Tz>, T>
 where
     F: FnOnce(CifErrorType) -> T,
 {
-    let parsed_date = NaiveDate::parse_from_str(date_slice, "%Y-%m-%d");
-    let parsed_date = match parsed_date {
-        Ok(x) => x,
-        Err(x) => return Err(error_logic(CifErrorType::ChronoParseError(x))),
-    };
-    Ok(London
-        .from_local_datetime(&parsed_date.and_hms_opt(0, 0, 0).unwrap())
-        .unwrap())
+    read_date_with_format(date_slice, "%Y-%m-%d", &London, error_logic)
 }
 
 fn read_optional_string(slice: &str) -> Option<String> {
@@ -2100,6 +2385,25 @@ fn get_working_time(location: &TrainLocation) -> (NaiveTime, u8) {
     }
 }
 
+// untimed locations (see CifDialect::untimed_locations) have nothing to go on, so skip those
+fn last_working_time(route: &[TrainLocation]) -> (NaiveTime, u8) {
+    get_working_time(
+        route
+            .iter()
+            .rev()
+            .find(|x| x.working_dep.is_some() || x.working_pass.is_some())
+            .unwrap(),
+    )
+}
+
+fn last_train_not_found(train_id: &str, number: u64) -> CifError {
+    CifError {
+        error_type: CifErrorType::LastTrainNotFound(train_id.to_string()),
+        line: number,
+        column: 0,
+    }
+}
+
 fn calculate_day(
     time: &Option<NaiveTime>,
     last_wtt_time: &NaiveTime,
@@ -2119,12 +2423,25 @@ fn calculate_day(
 
 impl CifImporter {
     pub fn new(config: CifImporterConfig) -> CifImporter {
+        for x in config.ignore_record_types.iter().flatten() {
+            if RECORD_TYPES.contains(&x.as_str()) {
+                println!(
+                    "WARNING: Not ignoring {} records, as we know how to read them",
+                    x
+                );
+            }
+        }
         CifImporter {
             config,
             ..Default::default()
         }
     }
 
+    // what the last overlay skipped, if it was lenient
+    pub fn report(&self) -> &ImportReport {
+        &self.report
+    }
+
     fn delete_unwritten_assocs(
         &mut self,
         main_train_id: &str,
@@ -2148,16 +2465,19 @@ impl CifImporter {
         };
 
         if *stp_modification_type == ModificationType::Insert {
-            old_assoc.retain(|(assoc, _category)| {
-                !is_matching_assoc_for_modify_insertion(
+            for (ref mut assoc, ref _category) in old_assoc.iter_mut() {
+                if is_matching_assoc_for_modify_insertion(
                     assoc,
                     other_train_id,
                     &begin,
                     &other_train_location_suffix,
                     is_stp,
                     use_rev,
-                )
-            });
+                ) {
+                    delete_assoc_period(assoc, begin, use_rev);
+                }
+            }
+            old_assoc.retain(|(assoc, _category)| !assoc.validity.is_empty());
         } else {
             for (ref mut assoc, ref _category) in old_assoc.iter_mut() {
                 delete_single_assoc_replacements_cancellations(
@@ -2326,12 +2646,7 @@ impl CifImporter {
                 && *other_train_location_suffix == assoc.other_train_location_id_suffix
             {
                 // check for no overlapping days at all
-                if !check_date_applicability(
-                    &assoc.validity[0],
-                    new_assoc.validity[0].valid_begin,
-                    new_assoc.validity[0].valid_end,
-                    &new_assoc.validity[0].days_of_week,
-                ) {
+                if !periods_overlap(&assoc.validity, &new_assoc.validity) {
                     continue;
                 }
                 assoc.replacements.push(new_assoc.clone());
@@ -2378,9 +2693,9 @@ impl CifImporter {
                 .get_mut(&(main_train_id.clone(), begin.clone()))
             {
                 Some(x) => return Ok(x),
-                None => panic!("Unable to find last-written train, even in orphaned overlays"),
+                None => return Err(last_train_not_found(main_train_id, number)),
             },
-            _ => panic!("Unable to find last-written train"),
+            _ => return Err(last_train_not_found(main_train_id, number)),
         };
 
         let train = match (&stp_modification_type, &is_stp) {
@@ -2393,7 +2708,8 @@ impl CifImporter {
                     && train.validity[0].valid_begin == *begin
             }),
             (ModificationType::Amend, _) => find_replacement_train(trains, begin),
-            (ModificationType::Delete, _) => panic!("Unexpected train modification type"),
+            // STP cancellations have no records after the BS, so this belongs to something else
+            (ModificationType::Delete, _) => None,
         };
 
         Ok(match (train, &stp_modification_type) {
@@ -2403,15 +2719,60 @@ impl CifImporter {
                 .get_mut(&(main_train_id.clone(), begin.clone()))
             {
                 Some(x) => x,
-                None => panic!(
-                    "Unable to find last-written train, even in orphaned overlays; line {}",
-                    number
-                ),
+                None => return Err(last_train_not_found(main_train_id, number)),
             },
-            _ => panic!("Unable to find last-written train"),
+            _ => return Err(last_train_not_found(main_train_id, number)),
         })
     }
 
+    // Drops a train we only got part of, as one stopping short of where it really goes (or with
+    // no route at all) is worse than not having it.
+    fn discard_last_train(&mut self, schedule: &mut Schedule) {
+        let (main_train_id, begin, stp_modification_type, is_stp) = match self.last_train.take() {
+            Some(x) => x,
+            None => return,
+        };
+        self.change_en_route = None;
+        self.cr_location = None;
+
+        match stp_modification_type {
+            ModificationType::Insert => {
+                let source = match is_stp {
+                    false => TrainSource::LongTerm,
+                    true => TrainSource::ShortTerm,
+                };
+                match schedule.trains.get_mut(&main_train_id) {
+                    Some(trains) => {
+                        trains.retain(|train| {
+                            train.source != Some(source) || train.validity[0].valid_begin != begin
+                        });
+                        if trains.is_empty() {
+                            schedule.trains.remove(&main_train_id);
+                        }
+                    }
+                    None => (),
+                }
+            }
+            ModificationType::Amend => {
+                match schedule.trains.get_mut(&main_train_id) {
+                    Some(trains) => {
+                        for train in trains.iter_mut() {
+                            train
+                                .replacements
+                                .retain(|replacement| replacement.validity[0].valid_begin != begin);
+                        }
+                    }
+                    None => (),
+                }
+                self.orphaned_overlay_trains
+                    .remove(&(main_train_id.clone(), begin));
+            }
+            ModificationType::Delete => (),
+        }
+        schedule.reindex_train(&main_train_id);
+        println!("WARNING: Discarded incomplete train {}", main_train_id);
+    }
+
     fn validate_change_en_route_location(
         &self,
         location_id: &str,
@@ -2441,9 +2802,9 @@ impl CifImporter {
     fn read_association(
         &mut self,
         line: &str,
-        mut schedule: Schedule,
+        schedule: &mut Schedule,
         number: u64,
-    ) -> Result<Schedule, CifError> {
+    ) -> Result<(), CifError> {
         let modification_type =
             read_modification_type(&line[2..3], produce_cif_error_closure(number, 2))?;
         let (stp_modification_type, is_stp) =
@@ -2451,7 +2812,11 @@ impl CifImporter {
 
         let main_train_id = &line[3..9];
         let other_train_id = &line[9..15];
-        let begin = read_date(&line[15..21], produce_cif_error_closure(number, 15))?;
+        let begin = read_date(
+            &line[15..21],
+            &self.config.dialect.timezone(),
+            produce_cif_error_closure(number, 15),
+        )?;
         let location = &line[37..44].trim();
         let location_suffix = read_optional_string(&line[44..45]);
         let other_train_location_suffix = read_optional_string(&line[45..46]);
@@ -2490,6 +2855,32 @@ impl CifImporter {
                 is_stp,
             );
 
+            // and from any waiting for VSTP trains
+            delete_pending_assocs(
+                &mut schedule.pending_associations,
+                main_train_id,
+                location,
+                &location_suffix,
+                other_train_id,
+                &begin,
+                &other_train_location_suffix,
+                &stp_modification_type,
+                is_stp,
+                false,
+            );
+            delete_pending_assocs(
+                &mut schedule.pending_associations,
+                other_train_id,
+                location,
+                &other_train_location_suffix,
+                main_train_id,
+                &begin,
+                &location_suffix,
+                &stp_modification_type,
+                is_stp,
+                true,
+            );
+
             // now delete from unwritten associations
             self.delete_unwritten_assocs(
                 main_train_id,
@@ -2514,10 +2905,14 @@ impl CifImporter {
                 true,
             );
 
-            return Ok(schedule);
+            return Ok(());
         }
 
-        let end = read_date(&line[21..27], produce_cif_error_closure(number, 21))?;
+        let end = read_date(
+            &line[21..27],
+            &self.config.dialect.timezone(),
+            produce_cif_error_closure(number, 21),
+        )?;
         let days_of_week = read_days_of_week(&line[27..34], produce_cif_error_closure(number, 27))?;
 
         // Now we handle STP cancellations; these are where long-running
@@ -2579,7 +2974,7 @@ impl CifImporter {
                 true,
             );
 
-            return Ok(schedule);
+            return Ok(());
         }
 
         let day_diff = match &line[36..37] {
@@ -2703,7 +3098,7 @@ impl CifImporter {
                 true,
             );
 
-            return Ok(schedule);
+            return Ok(());
         }
 
         let day_diff = match day_diff {
@@ -2746,7 +3141,7 @@ impl CifImporter {
 
         // all of the below will use AssociationNodes, so construct them here
         let new_assoc = AssociationNode {
-            other_train_id: other_train_id.to_string(),
+            other_train_id: other_train_id.into(),
             other_train_location_id_suffix: other_train_location_suffix.clone(),
             validity: vec![TrainValidityPeriod {
                 valid_begin: begin,
@@ -2765,7 +3160,7 @@ impl CifImporter {
         };
 
         let new_rev_assoc = AssociationNode {
-            other_train_id: main_train_id.to_string(),
+            other_train_id: main_train_id.into(),
             other_train_location_id_suffix: location_suffix.clone(),
             validity: vec![TrainValidityPeriod {
                 valid_begin: rev_begin,
@@ -2787,24 +3182,30 @@ impl CifImporter {
             && stp_modification_type == ModificationType::Insert
         {
             // As trains might not all have appeared yet, we temporarily add to unwritten_assocs
-            self.unwritten_assocs
-                .entry((
-                    main_train_id.to_string(),
-                    location.to_string(),
-                    location_suffix,
-                ))
-                .or_insert(vec![])
-                .push((new_assoc, category));
-            self.unwritten_assocs
-                .entry((
-                    other_train_id.to_string(),
-                    location.to_string(),
-                    other_train_location_suffix,
-                ))
-                .or_insert(vec![])
-                .push((new_rev_assoc, rev_category));
+            add_unwritten_assoc(
+                self.unwritten_assocs
+                    .entry((
+                        main_train_id.to_string(),
+                        location.to_string(),
+                        location_suffix,
+                    ))
+                    .or_insert(vec![]),
+                new_assoc,
+                category,
+            );
+            add_unwritten_assoc(
+                self.unwritten_assocs
+                    .entry((
+                        other_train_id.to_string(),
+                        location.to_string(),
+                        other_train_location_suffix,
+                    ))
+                    .or_insert(vec![]),
+                new_rev_assoc,
+                rev_category,
+            );
 
-            return Ok(schedule);
+            return Ok(());
         }
 
         if stp_modification_type == ModificationType::Amend {
@@ -2851,33 +3252,46 @@ impl CifImporter {
                 &new_rev_assoc,
             );
 
-            return Ok(schedule);
+            return Ok(());
         }
 
-        Ok(schedule)
+        Ok(())
     }
 
     fn read_basic_schedule(
         &mut self,
         line: &str,
-        mut schedule: Schedule,
+        schedule: &mut Schedule,
         number: u64,
-    ) -> Result<Schedule, CifError> {
+    ) -> Result<(), CifError> {
+        // whatever came before is over, even if it never had its LT; deletes and cancellations
+        // have no records of their own after this, so mustn't be left pointing at it
+        self.last_train = None;
+        self.change_en_route = None;
+        self.cr_location = None;
+
         let modification_type =
             read_modification_type(&line[2..3], produce_cif_error_closure(number, 2))?;
         let (stp_modification_type, is_stp) =
             read_stp_indicator(&line[79..80], produce_cif_error_closure(number, 79))?;
 
         let main_train_id = &line[3..9];
-        let begin = read_date(&line[9..15], produce_cif_error_closure(number, 9))?;
+        let begin = read_date(
+            &line[9..15],
+            &self.config.dialect.timezone(),
+            produce_cif_error_closure(number, 9),
+        )?;
 
         // At this stage we have all the data we need for a simple delete, so handle this here
         //
         // Note these are NOT the same as STP cancels and indeed handled completely differently
         if modification_type == ModificationType::Delete {
+            if stp_modification_type == ModificationType::Amend {
+                delete_pending_overlay(schedule, main_train_id, &begin);
+            }
             let old_trains = schedule.trains.remove(main_train_id);
             let mut old_trains = match old_trains {
-                None => return Ok(schedule),
+                None => return Ok(()),
                 Some(x) => x,
             };
 
@@ -2907,7 +3321,7 @@ impl CifImporter {
                             .retain(|replacement| replacement.validity[0].valid_begin != begin),
                         ModificationType::Delete => train
                             .cancellations
-                            .retain(|(cancellation, _source)| cancellation.valid_begin != begin),
+                            .retain(|cancellation| cancellation.validity.valid_begin != begin),
                     }
                 }
             }
@@ -2915,11 +3329,23 @@ impl CifImporter {
             schedule
                 .trains
                 .insert(main_train_id.to_string(), old_trains);
+            schedule.reindex_train(main_train_id);
 
-            return Ok(schedule);
+            return Ok(());
         }
 
-        let end = read_date(&line[15..21], produce_cif_error_closure(number, 15))?;
+        let end = read_date(
+            &line[15..21],
+            &self.config.dialect.timezone(),
+            produce_cif_error_closure(number, 15),
+        )?;
+        if end < begin {
+            return Err(CifError {
+                error_type: CifErrorType::EndsBeforeBeginning(begin, end),
+                line: number,
+                column: 15,
+            });
+        }
         let days_of_week = read_days_of_week(&line[21..28], produce_cif_error_closure(number, 27))?;
 
         // Now we handle STP cancellations; these are where long-running
@@ -2929,7 +3355,7 @@ impl CifImporter {
         {
             let old_trains = schedule.trains.remove(main_train_id);
             let mut old_trains = match old_trains {
-                None => return Ok(schedule),
+                None => return Ok(()),
                 Some(x) => x,
             };
 
@@ -2943,16 +3369,18 @@ impl CifImporter {
                     valid_end: end.clone(),
                     days_of_week: days_of_week,
                 };
-                train
-                    .cancellations
-                    .push((new_cancel, TrainSource::ShortTerm))
+                train.cancellations.push(TrainCancellation {
+                    validity: new_cancel,
+                    source: TrainSource::ShortTerm,
+                    reason: None,
+                })
             }
 
             schedule
                 .trains
                 .insert(main_train_id.to_string(), old_trains);
 
-            return Ok(schedule);
+            return Ok(());
         }
 
         if modification_type == ModificationType::Amend
@@ -2960,15 +3388,15 @@ impl CifImporter {
         {
             let old_trains = schedule.trains.remove(main_train_id);
             let mut old_trains = match old_trains {
-                None => return Ok(schedule),
+                None => return Ok(()),
                 Some(x) => x,
             };
 
             // now we clean up modifications/cancellations
             for ref mut train in old_trains.iter_mut() {
-                for (cancellation, _source) in train.cancellations.iter_mut() {
-                    if cancellation.valid_begin == begin {
-                        *cancellation = TrainValidityPeriod {
+                for cancellation in train.cancellations.iter_mut() {
+                    if cancellation.validity.valid_begin == begin {
+                        cancellation.validity = TrainValidityPeriod {
                             valid_begin: begin,
                             valid_end: end,
                             days_of_week: days_of_week.clone(),
@@ -2981,37 +3409,40 @@ impl CifImporter {
                 .trains
                 .insert(main_train_id.to_string(), old_trains);
 
-            return Ok(schedule);
+            return Ok(());
         }
 
         let train_status = read_train_status(&line[29..30], produce_cif_error_closure(number, 29))?;
 
-        let train_type =
-            match read_train_type(&line[30..32], produce_cif_error_closure(number, 30))? {
-                Some(x) => x,
-                None => match train_status {
-                    TrainStatus::Bus => TrainType::Bus,
-                    TrainStatus::Freight => TrainType::Freight,
-                    TrainStatus::PassengerParcels => TrainType::PassengerParcels,
-                    TrainStatus::Ship => TrainType::Ship,
-                    TrainStatus::Trip => TrainType::Trip,
-                    TrainStatus::StpPassengerParcels => TrainType::PassengerParcels,
-                    TrainStatus::StpFreight => TrainType::Freight,
-                    TrainStatus::StpTrip => TrainType::Trip,
-                    TrainStatus::StpShip => TrainType::Ship,
-                    TrainStatus::StpBus => TrainType::Bus,
-                    TrainStatus::VstpNone => {
-                        return Err(CifError {
-                            error_type: CifErrorType::InvalidTrainStatus(format!(
-                                "{:#?}",
-                                train_status
-                            )),
-                            line: number,
-                            column: 29,
-                        })
-                    }
-                },
-            };
+        let train_type = match self
+            .config
+            .dialect
+            .read_train_type(&line[30..32], produce_cif_error_closure(number, 30))?
+        {
+            Some(x) => x,
+            None => match train_status {
+                TrainStatus::Bus => TrainType::Bus,
+                TrainStatus::Freight => TrainType::Freight,
+                TrainStatus::PassengerParcels => TrainType::PassengerParcels,
+                TrainStatus::Ship => TrainType::Ship,
+                TrainStatus::Trip => TrainType::Trip,
+                TrainStatus::StpPassengerParcels => TrainType::PassengerParcels,
+                TrainStatus::StpFreight => TrainType::Freight,
+                TrainStatus::StpTrip => TrainType::Trip,
+                TrainStatus::StpShip => TrainType::Ship,
+                TrainStatus::StpBus => TrainType::Bus,
+                TrainStatus::VstpNone => {
+                    return Err(CifError {
+                        error_type: CifErrorType::InvalidTrainStatus(format!(
+                            "{:#?}",
+                            train_status
+                        )),
+                        line: number,
+                        column: 29,
+                    })
+                }
+            },
+        };
 
         let public_id = &line[32..36];
         let headcode = read_optional_string(&line[36..40]);
@@ -3078,9 +3509,9 @@ impl CifImporter {
                 timing_allocation: match timing_load_str {
                     None => None,
                     Some(x) => Some(TrainAllocation {
-                        id: timing_load_id.to_string(),
-                        description: x,
-                        vehicles: None,
+                        id: timing_load_id.into(),
+                        description: x.into(),
+                        vehicles: vehicles::for_timing_load(&timing_load_id),
                     }),
                 },
                 actual_allocation: None,
@@ -3107,7 +3538,7 @@ impl CifImporter {
             }),
             runs_as_required,
             performance_monitoring: None,
-            route: vec![],
+            route: vec![].into(),
         };
 
         schedule
@@ -3127,7 +3558,11 @@ impl CifImporter {
 
             let old_trains = schedule.trains.remove(main_train_id);
             let mut old_trains = match old_trains {
-                None => return Ok(schedule),
+                None => {
+                    // nothing to amend, so nothing to index it under
+                    schedule.reindex_train(main_train_id);
+                    return Ok(());
+                }
                 Some(x) => x,
             };
 
@@ -3161,8 +3596,10 @@ impl CifImporter {
             schedule
                 .trains
                 .insert(main_train_id.to_string(), old_trains);
+            // the route comes again in the records that follow, so this drops the old one's
+            schedule.reindex_train(main_train_id);
 
-            return Ok(schedule);
+            return Ok(());
         }
 
         if modification_type == ModificationType::Insert
@@ -3182,7 +3619,7 @@ impl CifImporter {
                 .or_insert(vec![])
                 .push(new_train);
 
-            return Ok(schedule);
+            return Ok(());
         }
 
         if stp_modification_type == ModificationType::Amend {
@@ -3199,7 +3636,7 @@ impl CifImporter {
                 None => {
                     self.orphaned_overlay_trains
                         .insert((main_train_id.to_string(), begin), new_train);
-                    return Ok(schedule);
+                    return Ok(());
                 }
                 Some(x) => x,
             };
@@ -3223,18 +3660,18 @@ impl CifImporter {
                 .trains
                 .insert(main_train_id.to_string(), old_trains);
 
-            return Ok(schedule);
+            return Ok(());
         }
 
-        Ok(schedule)
+        Ok(())
     }
 
     fn read_extended_schedule(
         &mut self,
         line: &str,
-        mut schedule: Schedule,
+        schedule: &mut Schedule,
         number: u64,
-    ) -> Result<Schedule, CifError> {
+    ) -> Result<(), CifError> {
         // at this stage we can only be in an insert or amend statement, for STP other than CAN. So
         // we find the train we are inserting or amending.
 
@@ -3242,30 +3679,45 @@ impl CifImporter {
 
         let atoc_code = &line[11..13];
 
-        let train_operator_desc =
-            read_train_operator(atoc_code, produce_cif_error_closure(number, 11))?;
+        let train_operator_desc = self
+            .config
+            .dialect
+            .read_train_operator(atoc_code, produce_cif_error_closure(number, 11))?;
 
         let performance_monitoring =
             read_ats_code(&line[13..14], produce_cif_error_closure(number, 13))?;
 
-        let train = self.get_last_train(&mut schedule, number, "BX")?;
+        let train = self.get_last_train(schedule, number, "BX")?;
 
-        train.variable_train.uic_code = uic_code;
+        train.variable_train.uic_code = uic_code.clone();
         train.variable_train.operator = Some(TrainOperator {
-            id: atoc_code.to_string(),
-            description: train_operator_desc,
+            id: atoc_code.into(),
+            description: train_operator_desc.map(IStr::from),
         });
+        vehicles::reclassify(&mut train.variable_train);
         train.performance_monitoring = Some(performance_monitoring);
+        let train_id = train.id.clone();
 
-        Ok(schedule)
+        match uic_code {
+            Some(x) => {
+                schedule
+                    .trains_indexed_by_uic
+                    .entry(x)
+                    .or_insert(HashSet::new())
+                    .insert(train_id);
+            }
+            None => (),
+        }
+
+        Ok(())
     }
 
     fn read_location_origin(
         &mut self,
         line: &str,
-        mut schedule: Schedule,
+        schedule: &mut Schedule,
         number: u64,
-    ) -> Result<Schedule, CifError> {
+    ) -> Result<(), CifError> {
         // at this stage we can only be in an insert or amend statement, for STP other than CAN. So
         // we find the train we are inserting or amending.
 
@@ -3288,7 +3740,7 @@ impl CifImporter {
 
         let new_location = TrainLocation {
             timing_tz: None,
-            id: location_id.to_string(),
+            id: (*location_id).into(),
             id_suffix: location_suffix,
             working_arr: None,
             working_arr_day: None,
@@ -3300,9 +3752,10 @@ impl CifImporter {
             public_arr_day: None,
             public_dep: pub_dep,
             public_dep_day: Some(0),
-            platform,
+            scheduled_platform: platform.map(IStr::from),
+            live_platform: None,
             platform_zone: None,
-            line: line_code,
+            line: line_code.map(IStr::from),
             path: None,
             engineering_allowance_s: Some(eng_allowance),
             pathing_allowance_s: Some(path_allowance),
@@ -3318,7 +3771,7 @@ impl CifImporter {
         };
 
         {
-            let train = self.get_last_train(&mut schedule, number, "LI")?;
+            let train = self.get_last_train(schedule, number, "LI")?;
 
             if !train.route.is_empty() {
                 return Err(CifError {
@@ -3339,15 +3792,15 @@ impl CifImporter {
             .or_insert(HashSet::new())
             .insert(self.last_train.as_ref().unwrap().0.clone());
 
-        Ok(schedule)
+        Ok(())
     }
 
     fn read_location_intermediate(
         &mut self,
         line: &str,
-        mut schedule: Schedule,
+        schedule: &mut Schedule,
         number: u64,
-    ) -> Result<Schedule, CifError> {
+    ) -> Result<(), CifError> {
         // at this stage we can only be in an insert or amend statement, for STP other than CAN. So
         // we find the train we are inserting or amending.
 
@@ -3364,6 +3817,7 @@ impl CifImporter {
         match (wtt_arr, wtt_dep, wtt_pass) {
             (None, None, Some(_)) => (),
             (Some(_), Some(_), None) => (),
+            (None, None, None) if self.config.dialect.untimed_locations => (),
             (_, _, _) => {
                 return Err(CifError {
                     error_type: CifErrorType::InvalidWttTimesCombo,
@@ -3391,7 +3845,7 @@ impl CifImporter {
         self.cr_location = None;
 
         {
-            let train = self.get_last_train(&mut schedule, number, "LI")?;
+            let train = self.get_last_train(schedule, number, "LI")?;
 
             if train.route.is_empty() {
                 return Err(CifError {
@@ -3404,10 +3858,14 @@ impl CifImporter {
                 });
             }
 
-            let (last_wtt_time, last_wtt_day) = get_working_time(train.route.last().unwrap());
+            let (last_wtt_time, last_wtt_day) = last_working_time(&train.route);
 
             let wtt_arr_day = calculate_day(&wtt_arr, &last_wtt_time, last_wtt_day);
-            let wtt_dep_day = calculate_day(&wtt_dep, &last_wtt_time, last_wtt_day);
+            // departures follow the arrival here, even if it's earlier than the last location's
+            let wtt_dep_day = match (&wtt_arr, wtt_arr_day) {
+                (Some(x), Some(y)) => calculate_day(&wtt_dep, x, y),
+                _ => calculate_day(&wtt_dep, &last_wtt_time, last_wtt_day),
+            };
             let wtt_pass_day = calculate_day(&wtt_pass, &last_wtt_time, last_wtt_day);
 
             // TODO maybe should change this to calculate based on last public time?
@@ -3416,7 +3874,7 @@ impl CifImporter {
 
             let new_location = TrainLocation {
                 timing_tz: None,
-                id: location_id.to_string(),
+                id: (*location_id).into(),
                 id_suffix: location_suffix,
                 working_arr: wtt_arr,
                 working_arr_day: wtt_arr_day,
@@ -3428,10 +3886,11 @@ impl CifImporter {
                 public_arr_day: pub_arr_day,
                 public_dep: pub_dep,
                 public_dep_day: pub_dep_day,
-                platform,
+                scheduled_platform: platform.map(IStr::from),
+                live_platform: None,
                 platform_zone: None,
-                line: line_code,
-                path: path_code,
+                line: line_code.map(IStr::from),
+                path: path_code.map(IStr::from),
                 engineering_allowance_s: Some(eng_allowance),
                 pathing_allowance_s: Some(path_allowance),
                 performance_allowance_s: Some(perf_allowance),
@@ -3453,15 +3912,15 @@ impl CifImporter {
             .or_insert(HashSet::new())
             .insert(self.last_train.as_ref().unwrap().0.clone());
 
-        Ok(schedule)
+        Ok(())
     }
 
     fn read_location_terminating(
         &mut self,
         line: &str,
-        mut schedule: Schedule,
+        schedule: &mut Schedule,
         number: u64,
-    ) -> Result<Schedule, CifError> {
+    ) -> Result<(), CifError> {
         // at this stage we can only be in an insert or amend statement, for STP other than CAN. So
         // we find the train we are inserting or amending.
 
@@ -3483,7 +3942,7 @@ impl CifImporter {
         let change_en_route = self.change_en_route.take();
 
         {
-            let train = self.get_last_train(&mut schedule, number, "LT")?;
+            let train = self.get_last_train(schedule, number, "LT")?;
 
             if train.route.is_empty() {
                 return Err(CifError {
@@ -3496,14 +3955,14 @@ impl CifImporter {
                 });
             }
 
-            let (last_wtt_time, last_wtt_day) = get_working_time(train.route.last().unwrap());
+            let (last_wtt_time, last_wtt_day) = last_working_time(&train.route);
 
             let wtt_arr_day = calculate_day(&Some(wtt_arr), &last_wtt_time, last_wtt_day).unwrap();
             let pub_arr_day = calculate_day(&pub_arr, &last_wtt_time, last_wtt_day);
 
             let new_location = TrainLocation {
                 timing_tz: None,
-                id: location_id.to_string(),
+                id: (*location_id).into(),
                 id_suffix: location_suffix,
                 working_arr: Some(wtt_arr),
                 working_arr_day: Some(wtt_arr_day),
@@ -3515,10 +3974,11 @@ impl CifImporter {
                 public_arr_day: pub_arr_day,
                 public_dep: None,
                 public_dep_day: None,
-                platform,
+                scheduled_platform: platform.map(IStr::from),
+                live_platform: None,
                 platform_zone: None,
                 line: None,
-                path: path_code,
+                path: path_code.map(IStr::from),
                 engineering_allowance_s: None,
                 pathing_allowance_s: None,
                 performance_allowance_s: None,
@@ -3544,20 +4004,25 @@ impl CifImporter {
         // given train
         self.last_train = None;
 
-        Ok(schedule)
+        Ok(())
     }
 
     fn read_change_en_route(
         &mut self,
         line: &str,
-        mut schedule: Schedule,
+        schedule: &mut Schedule,
         number: u64,
-    ) -> Result<Schedule, CifError> {
+    ) -> Result<(), CifError> {
         // at this stage we can only be in an insert or amend statement, for STP other than CAN. So
         // we find the train we are inserting or amending.
 
+        let read_train_type = self
+            .config
+            .dialect
+            .read_train_type(&line[10..12], produce_cif_error_closure(number, 10))?;
+
         let (train_type, operator) = {
-            let train = self.get_last_train(&mut schedule, number, "CR")?;
+            let train = self.get_last_train(schedule, number, "CR")?;
 
             if train.route.is_empty() {
                 return Err(CifError {
@@ -3570,11 +4035,10 @@ impl CifImporter {
                 });
             }
 
-            let train_type =
-                match read_train_type(&line[10..12], produce_cif_error_closure(number, 10))? {
-                    Some(x) => x,
-                    None => train.variable_train.train_type, // should only really happen for ships
-                };
+            let train_type = match read_train_type {
+                Some(x) => x,
+                None => train.variable_train.train_type, // should only really happen for ships
+            };
 
             (train_type, train.variable_train.operator.clone())
         };
@@ -3633,7 +4097,18 @@ impl CifImporter {
 
         let uic_code = read_optional_string(&line[62..67]);
 
-        self.change_en_route = Some(VariableTrain {
+        match (&uic_code, &self.last_train) {
+            (Some(x), Some((train_id, _, _, _))) => {
+                schedule
+                    .trains_indexed_by_uic
+                    .entry(x.clone())
+                    .or_insert(HashSet::new())
+                    .insert(train_id.clone());
+            }
+            _ => (),
+        }
+
+        let mut variable_train = VariableTrain {
             train_type,
             public_id: Some(public_id.to_string()),
             headcode,
@@ -3642,9 +4117,9 @@ impl CifImporter {
             timing_allocation: match timing_load_str {
                 None => None,
                 Some(x) => Some(TrainAllocation {
-                    id: timing_load_id.to_string(),
-                    description: x,
-                    vehicles: None,
+                    id: timing_load_id.into(),
+                    description: x.into(),
+                    vehicles: vehicles::for_timing_load(&timing_load_id),
                 }),
             },
             actual_allocation: None,
@@ -3663,28 +4138,33 @@ impl CifImporter {
             operator,
             wheelchair_accessible: None,
             bicycles_allowed: None,
-        });
+        };
+        vehicles::reclassify(&mut variable_train);
+        self.change_en_route = Some(variable_train);
 
-        Ok(schedule)
+        Ok(())
     }
 
     fn read_tiploc(
         &self,
         line: &str,
-        mut schedule: Schedule,
+        schedule: &mut Schedule,
         number: u64,
         modification_type: ModificationType,
-    ) -> Result<Schedule, CifError> {
+    ) -> Result<(), CifError> {
         let tiploc = &line[2..9].trim();
         let name = &line[18..44].trim();
         let opt_crs = read_optional_string(&line[53..56]);
+        let opt_stanox = read_optional_string(&line[44..49]);
 
         let location = match modification_type {
             ModificationType::Insert => Location {
                 id: tiploc.to_string(),
                 name: name.to_string(),
                 public_id: opt_crs.clone(),
-                timezone: London,
+                timezone: self.config.dialect.timezone(),
+                position: None, // CIF doesn't say where anything is
+                unverified: false,
             },
             ModificationType::Amend => {
                 let location = schedule.locations.remove(*tiploc);
@@ -3701,14 +4181,19 @@ impl CifImporter {
                 location.id = tiploc.to_string();
                 location.name = name.to_string();
                 location.public_id = opt_crs.clone();
+                location.unverified = false;
                 location
             }
             ModificationType::Delete => {
                 schedule.locations.remove(*tiploc); // it's OK if the TIPLOC isn't found
-                return Ok(schedule);
+                return Ok(());
             }
         };
-        schedule.locations.insert(tiploc.to_string(), location);
+        match schedule.locations.insert(tiploc.to_string(), location) {
+            Some(x) if x.unverified => println!("Found TIPLOC {} VSTP used: {}", tiploc, name),
+            _ => (),
+        }
+        schedule.index_location_for_search(tiploc);
         match opt_crs {
             None => (),
             Some(crs) => {
@@ -3719,15 +4204,25 @@ impl CifImporter {
                     .insert(tiploc.to_string());
             }
         }
-        Ok(schedule)
+        match opt_stanox {
+            None => (),
+            Some(stanox) => {
+                schedule
+                    .locations_indexed_by_stanox
+                    .entry(stanox)
+                    .or_insert(HashSet::new())
+                    .insert(tiploc.to_string());
+            }
+        }
+        Ok(())
     }
 
     fn read_header(
         &self,
         line: &str,
-        mut schedule: Schedule,
+        schedule: &mut Schedule,
         number: u64,
-    ) -> Result<Schedule, CifError> {
+    ) -> Result<(), CifError> {
         schedule.their_id = Some(line[2..22].to_string());
         let parsed_datetime = NaiveDateTime::parse_from_str(&line[22..32], "%y%m%d%H%M");
         let parsed_datetime = match parsed_datetime {
@@ -3740,75 +4235,95 @@ impl CifImporter {
                 })
             }
         };
-        schedule.last_updated = Some(London.from_local_datetime(&parsed_datetime).unwrap());
+        // a time skipped by the clocks going forward can only be an hour out
+        let timezone = self.config.dialect.timezone();
+        schedule.last_updated = Some(
+            match timezone.from_local_datetime(&parsed_datetime).earliest() {
+                Some(x) => x,
+                None => timezone.from_utc_datetime(&parsed_datetime),
+            },
+        );
         if &line[46..47] == "F" {
             schedule.valid_begin = Some(read_backwards_date(
                 &line[48..54],
+                &self.config.dialect.timezone(),
                 produce_cif_error_closure(number, 48),
             )?);
             schedule.valid_end = Some(read_backwards_date(
                 &line[54..60],
+                &self.config.dialect.timezone(),
                 produce_cif_error_closure(number, 48),
             )?);
         }
-        Ok(schedule)
+        Ok(())
     }
 
     fn finalise(
         &mut self,
         _line: &str,
-        mut schedule: Schedule,
-        number: u64,
-    ) -> Result<Schedule, CifError> {
+        schedule: &mut Schedule,
+        _number: u64,
+    ) -> Result<(), CifError> {
         for ((train_id, location, location_suffix), assocs) in &self.unwritten_assocs {
-            let mut trains = match schedule.trains.get_mut(train_id) {
+            let trains = match schedule.trains.get_mut(train_id) {
                 Some(x) => x,
                 None => {
-                    return Err(CifError {
-                        error_type: CifErrorType::TrainNotFound(train_id.clone()),
-                        line: number,
-                        column: 0,
-                    })
+                    // probably a VSTP train, which will pick these up when it's next sent
+                    let pending = schedule
+                        .pending_associations
+                        .entry(train_id.clone())
+                        .or_default();
+                    for (assoc, category) in assocs {
+                        match pending.iter_mut().find(|x| {
+                            x.location_id == *location
+                                && x.location_id_suffix == *location_suffix
+                                && x.category == *category
+                                && is_same_association(&x.association, assoc)
+                        }) {
+                            Some(x) => merge_assoc_periods(&mut x.association, assoc),
+                            None => pending.push(PendingAssociation {
+                                location_id: location.clone(),
+                                location_id_suffix: location_suffix.clone(),
+                                category: *category,
+                                association: assoc.clone(),
+                            }),
+                        }
+                    }
+                    continue;
                 }
             };
 
-            write_assocs_to_trains(&mut trains, &location, &location_suffix, &assocs);
+            write_assocs_to_trains(trains, &location, &location_suffix, &assocs);
         }
         self.unwritten_assocs.clear();
 
-        for ((train_id, _begin), new_train) in &self.orphaned_overlay_trains {
-            let old_trains = schedule.trains.remove(train_id);
-            let mut old_trains = match old_trains {
-                None => {
-                    // This orphaned overlay was probably intended to be an N instead.
-                    schedule
-                        .trains
-                        .insert(train_id.to_string(), vec![new_train.clone()]);
-                    continue;
-                }
-                Some(x) => x,
-            };
-
-            // we replace main trains
-            for train in old_trains.iter_mut() {
-                if !check_date_applicability(
-                    &train.validity[0],
-                    new_train.validity[0].valid_begin,
-                    new_train.validity[0].valid_end,
-                    &new_train.validity[0].days_of_week,
-                ) {
-                    continue;
-                }
-                train.replacements.push(new_train.clone())
+        // Overlays in this file with nothing to replace yet wait with any left from earlier files,
+        // then they all try again, now that this file's schedules are in
+        for ((train_id, begin), new_train) in self.orphaned_overlay_trains.drain() {
+            let pending = schedule.pending_overlays.entry(train_id).or_default();
+            match pending
+                .iter_mut()
+                .find(|x| x.overlay.validity[0].valid_begin == begin)
+            {
+                Some(x) => x.overlay = new_train,
+                None => pending.push(PendingOverlay {
+                    overlay: new_train,
+                    standalone: false,
+                }),
             }
-
-            schedule.trains.insert(train_id.to_string(), old_trains);
+        }
+        let attached = attach_pending_overlays(schedule);
+        if attached > 0 {
+            println!(
+                "Attached {} overlays that were waiting for what they replace",
+                attached
+            );
         }
 
-        Ok(schedule)
+        Ok(())
     }
 
-    async fn override_locations(&self, mut schedule: Schedule) -> Result<Schedule, Error> {
+    async fn override_locations(&mut self, mut schedule: Schedule) -> Result<Schedule, Error> {
         let mut location_overrides = vec![];
         match &self.config.location_overrides {
             None => (),
@@ -3826,6 +4341,7 @@ impl CifImporter {
             schedule
                 .locations
                 .insert(location.id.clone(), location.clone());
+            schedule.index_location_for_search(&location.id);
             match location.public_id {
                 Some(x) => {
                     schedule
@@ -3840,24 +4356,87 @@ impl CifImporter {
 
         // can now validate locations
         for (_id, trains) in &schedule.trains {
-            validate_train_locations(
+            match validate_train_locations(
                 &trains,
                 &schedule.locations,
                 &produce_cif_error_closure(0, 0),
-            )?;
+            ) {
+                Ok(()) => (),
+                // the trains are kept, so validation can say which locations were missing
+                Err(x) if self.config.lenient.unwrap_or(false) => {
+                    println!("WARNING: {}", x);
+                    self.report.add(&x);
+                }
+                Err(x) => return Err(x.into()),
+            }
         }
 
         Ok(schedule)
     }
 
+    // Checks a record comes where it should among a train's records, and moves on to the next
+    // state. Train records in the wrong place are errors, as there's no telling which train
+    // they're for; anything else in the middle of a train just means the train was cut short,
+    // which gets a warning, and we carry on without it.
+    fn check_record_order(
+        &mut self,
+        line: &str,
+        schedule: &mut Schedule,
+        number: u64,
+    ) -> Result<(), CifError> {
+        use TrainRecordState::*;
+
+        let record_type = &line[..2];
+        let (next, expected) = match (record_type, self.record_state) {
+            // a BS on its own is a delete or an STP cancellation
+            ("BS", _) if &line[2..3] == "D" || &line[79..80] == "C" => (Between, ""),
+            ("BS", _) => (Started, ""),
+            ("BX", Started) => (Extended, ""),
+            ("BX", _) => (Between, "BX only after BS"),
+            ("LO", Started | Extended) => (Running, ""),
+            ("LO", _) => (Between, "LO only after BS or BX"),
+            ("LI", Running | Changing) => (Running, ""),
+            ("LI", _) => (Between, "LI only after LO, LI or CR"),
+            ("CR", Running) => (Changing, ""),
+            ("CR", _) => (Between, "CR only before LI or LT"),
+            ("LT", Running | Changing) => (Between, ""),
+            ("LT", _) => (Between, "LT only after LO, LI or CR"),
+            _ => (Between, ""),
+        };
+
+        let previous = std::mem::replace(&mut self.record_state, next);
+        if !expected.is_empty() {
+            return Err(CifError {
+                error_type: CifErrorType::RecordOutOfOrder(record_type.to_string(), expected),
+                line: number,
+                column: 0,
+            });
+        }
+        let continues_train = matches!(record_type, "BX" | "LO" | "LI" | "CR" | "LT");
+        if !continues_train && previous != Between {
+            self.cut_short(&format!("{} record", record_type), number, schedule);
+        }
+        Ok(())
+    }
+
+    fn cut_short(&mut self, by: &str, number: u64, schedule: &mut Schedule) {
+        let warning = format!(
+            "Train ended without an LT, cut short by {} on line {}",
+            by, number
+        );
+        println!("WARNING: {}", warning);
+        self.report.warn(&warning);
+        self.discard_last_train(schedule);
+    }
+
     fn read_record(
         &mut self,
         line: String,
-        schedule: Schedule,
+        schedule: &mut Schedule,
         number: u64,
-    ) -> Result<Schedule, CifError> {
+    ) -> Result<(), CifError> {
         if line.is_empty() {
-            return Ok(schedule);
+            return Ok(());
         }
         if line.len() != 80 {
             return Err(CifError {
@@ -3866,6 +4445,37 @@ impl CifImporter {
                 column: 0,
             });
         }
+        // fields are picked out by byte, so anything else would come apart in the wrong places
+        match line.find(|x: char| !x.is_ascii()) {
+            Some(x) => {
+                return Err(CifError {
+                    error_type: CifErrorType::NonAsciiRecord,
+                    line: number,
+                    column: x,
+                })
+            }
+            None => (),
+        }
+
+        // Record types NR has added since, which we've been told we can do without. These are
+        // dropped before anything else, so one in the middle of a train doesn't cut it short.
+        let record_type = &line[..2];
+        if !RECORD_TYPES.contains(&record_type)
+            && self
+                .config
+                .ignore_record_types
+                .iter()
+                .flatten()
+                .any(|x| x == record_type)
+        {
+            *self
+                .ignored_records
+                .entry(record_type.to_string())
+                .or_default() += 1;
+            return Ok(());
+        }
+
+        self.check_record_order(&line, schedule, number)?;
 
         match &line[..2] {
             "HD" => Ok(self.read_header(&line, schedule, number)?),
@@ -3891,7 +4501,7 @@ impl CifImporter {
 
 fn validate_train_location<F, T>(
     train: &Train,
-    locations: &HashMap<String, Location>,
+    locations: &PartitionedMap<Location>,
     error_logic: &F,
 ) -> Result<(), T>
 where
@@ -3899,9 +4509,9 @@ where
 {
     validate_train_locations(&train.replacements, &locations, error_logic)?;
     for location in &train.route {
-        if !locations.contains_key(&location.id) {
+        if !locations.contains_key(location.id.as_str()) {
             return Err(error_logic(CifErrorType::LocationNotFound(
-                location.id.clone(),
+                location.id.to_string(),
             )));
         }
     }
@@ -3911,7 +4521,7 @@ where
 
 fn validate_train_locations<F, T>(
     trains: &Vec<Train>,
-    locations: &HashMap<String, Location>,
+    locations: &PartitionedMap<Location>,
     error_logic: &F,
 ) -> Result<(), T>
 where
@@ -3934,14 +4544,63 @@ impl SlowStreamingImporter for CifImporter {
         let mut lines = reader.lines();
 
         let mut i: u64 = 0;
+        self.report = ImportReport::default();
+        self.ignored_records = HashMap::new();
+        self.skipping_train = false;
+        self.record_state = TrainRecordState::Between;
 
         while let Some(line) = lines.next_line().await? {
             i += 1;
-            schedule = self.read_record(line, schedule, i)?;
+            let is_train_record = matches!(
+                line.get(..2),
+                Some("BS") | Some("BX") | Some("LO") | Some("LI") | Some("LT") | Some("CR")
+            );
+            if self.skipping_train {
+                if is_train_record && !line.starts_with("BS") {
+                    continue;
+                }
+                self.skipping_train = false;
+            }
+            match self.read_record(line, &mut schedule, i) {
+                Ok(()) => (),
+                Err(x) if self.config.lenient.unwrap_or(false) => {
+                    println!("WARNING: Skipping bad CIF record: {}", x);
+                    // rather than attach the rest of the train to the wrong one, skip it all
+                    if is_train_record {
+                        self.skipping_train = true;
+                        self.discard_last_train(&mut schedule);
+                        self.change_en_route = None;
+                        self.cr_location = None;
+                        self.record_state = TrainRecordState::Between;
+                    }
+                    self.report.add(&x);
+                }
+                Err(x) => return Err(x.into()),
+            }
+        }
+        if self.record_state != TrainRecordState::Between {
+            self.cut_short("the end of the file", i, &mut schedule);
+            self.record_state = TrainRecordState::Between;
+        }
+        // overlays were indexed as they were read, whether or not they've found anything to
+        // replace yet; any still here never got to finalise, for want of a ZZ
+        for ((train_id, _begin), _) in self.orphaned_overlay_trains.drain() {
+            schedule.reindex_train(&train_id);
+        }
+
+        if !self.report.is_empty() {
+            println!(
+                "WARNING: Skipped {} bad records in {} lines of CIF",
+                self.report.error_count, i
+            );
+        }
+        for (record_type, count) in &self.ignored_records {
+            println!("Ignored {} {} records, as configured", count, record_type);
         }
 
         schedule = self.override_locations(schedule).await?;
 
+        println!("Interned {}", schedule.intern_strings());
         println!(
             "Successfully loaded {} trains from {} lines of CIF",
             schedule.trains.len(),
@@ -3952,7 +4611,7 @@ impl SlowStreamingImporter for CifImporter {
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields, rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 struct NrJsonSender {
     organisation: String,
     application: String,
@@ -3961,22 +4620,28 @@ struct NrJsonSender {
     user_id: Option<String>,
     #[serde(rename = "sessionID")]
     session_id: Option<String>,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>, // fields we don't know (yet)
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields, rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 struct NrJsonTiploc {
     tiploc_id: String,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields, rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 struct NrJsonLocation {
     tiploc: NrJsonTiploc,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields, rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 struct NrJsonScheduleLocation {
     scheduled_arrival_time: Option<String>,
     scheduled_departure_time: Option<String>,
@@ -3998,10 +4663,12 @@ struct NrJsonScheduleLocation {
     #[serde(rename = "CIF_performance_allowance")]
     cif_performance_allowance: Option<String>,
     location: NrJsonLocation,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields, rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 struct NrJsonScheduleSegment {
     signalling_id: String,
     uic_code: Option<String>,
@@ -4039,10 +4706,12 @@ struct NrJsonScheduleSegment {
     #[serde(rename = "CIF_traction_class")]
     cif_traction_class: Option<String>,
     schedule_location: Vec<NrJsonScheduleLocation>,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields, rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 struct NrJsonSchedule {
     schedule_id: Option<String>,
     transaction_type: String,
@@ -4058,10 +4727,12 @@ struct NrJsonSchedule {
     #[serde(rename = "CIF_stp_indicator")]
     cif_stp_indicator: String,
     schedule_segment: Option<Vec<NrJsonScheduleSegment>>,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-#[serde(deny_unknown_fields, rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 struct NrJsonVstpCifMsgV1 {
     #[serde(rename = "schemaLocation")]
     schema_location: Option<String>,
@@ -4073,23 +4744,90 @@ struct NrJsonVstpCifMsgV1 {
     #[serde(rename = "Sender")]
     sender: NrJsonSender,
     schedule: NrJsonSchedule,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct NrJsonVstp {
     #[serde(rename = "VSTPCIFMsgV1")]
     vstp_cif_msg_v1: NrJsonVstpCifMsgV1,
+    #[serde(flatten)]
+    extra: HashMap<String, serde_json::Value>,
+}
+
+impl NrJsonVstp {
+    // where any fields we don't know are, once each
+    fn unknown_fields(&self) -> Vec<String> {
+        let mut fields = BTreeSet::new();
+        let mut add = |path: &str, extra: &HashMap<String, serde_json::Value>| {
+            for field in extra.keys() {
+                fields.insert(format!("{}{}", path, field));
+            }
+        };
+        let message = &self.vstp_cif_msg_v1;
+        add("", &self.extra);
+        add("VSTPCIFMsgV1.", &message.extra);
+        add("VSTPCIFMsgV1.Sender.", &message.sender.extra);
+        add("VSTPCIFMsgV1.schedule.", &message.schedule.extra);
+        for segment in message.schedule.schedule_segment.iter().flatten() {
+            add("VSTPCIFMsgV1.schedule.schedule_segment.", &segment.extra);
+            for location in &segment.schedule_location {
+                let path = "VSTPCIFMsgV1.schedule.schedule_segment.schedule_location.";
+                add(path, &location.extra);
+                add(&format!("{}location.", path), &location.location.extra);
+                add(
+                    &format!("{}location.tiploc.", path),
+                    &location.location.tiploc.extra,
+                );
+            }
+        }
+        fields.into_iter().collect()
+    }
+
+    // as stamped by NR, in milliseconds since the epoch; we don't keep when we received it, but
+    // it's never more than a few seconds later
+    fn sent_at(&self) -> Option<DateTime<Utc>> {
+        DateTime::from_timestamp_millis(self.vstp_cif_msg_v1.timestamp.trim().parse().ok()?)
+    }
+
+    // for the change log, once it's been applied
+    fn change(&self) -> Option<TrainChange> {
+        let schedule = &self.vstp_cif_msg_v1.schedule;
+        let first_date = read_vstp_date(&schedule.schedule_start_date, |_| ()).ok()?;
+        let last_date = read_vstp_date(&schedule.schedule_end_date, |_| ()).ok()?;
+        let change = match (
+            schedule.transaction_type.as_str(),
+            schedule.cif_stp_indicator.as_str(),
+        ) {
+            ("Create", "O") => ChangeKind::VstpReplaced,
+            ("Create", "C") => ChangeKind::VstpCancelled,
+            ("Create", _) => ChangeKind::VstpCreated,
+            ("Delete", _) => ChangeKind::VstpDeleted,
+            _ => ChangeKind::VstpUpdated,
+        };
+        Some(TrainChange::new(
+            first_date.date_naive(),
+            last_date.date_naive(),
+            change,
+        ))
+    }
 }
 
 pub struct NrJsonImporter {
     previously_received: Arc<RwLock<Vec<NrJsonVstp>>>,
+    changed_trains: Arc<RwLock<Vec<String>>>,
+    changes: Arc<RwLock<Vec<(String, TrainChange)>>>,
     config: NrJsonImporterConfig,
     persister_mutex: Arc<Mutex<()>>,
+    telemetry: Option<Arc<FeedTelemetry>>,
 }
 
 #[derive(Clone, Deserialize)]
 pub struct NrJsonImporterConfig {
     filename: Option<String>,
+    strict: Option<bool>, // reject messages with fields we don't know, rather than counting them
+    quarantine_file: Option<String>, // JSON lines of messages that couldn't be read, with why
 }
 
 impl NrJsonImporter {
@@ -4108,11 +4846,116 @@ impl NrJsonImporter {
         }
         Ok(NrJsonImporter {
             previously_received: Arc::new(RwLock::new(previously_received)),
+            changed_trains: Arc::new(RwLock::new(vec![])),
+            changes: Arc::new(RwLock::new(vec![])),
             config,
             persister_mutex: Arc::new(Mutex::new(())),
+            telemetry: None,
         })
     }
 
+    pub fn with_telemetry(mut self, telemetry: Arc<FeedTelemetry>) -> Self {
+        self.telemetry = Some(telemetry);
+        self
+    }
+
+    // Fields we don't know are let through unless we're being strict, and counted either way
+    fn parse(&self, data: &[u8]) -> Result<NrJsonVstp, Error> {
+        let parsed_json = serde_json::from_slice::<NrJsonVstp>(data)?;
+        let unknown_fields = parsed_json.unknown_fields();
+        if self.config.strict.unwrap_or(false) && !unknown_fields.is_empty() {
+            return Err(NrJsonError {
+                error_type: CifErrorType::UnknownFields(unknown_fields.join(", ")),
+                field_name: "VSTPCIFMsgV1".to_string(),
+            }
+            .into());
+        }
+        match &self.telemetry {
+            Some(x) => x.message("gbnr-vstp", &unknown_fields),
+            None => (),
+        }
+        Ok(parsed_json)
+    }
+
+    // A message we can't read at all is kept for someone to look at, and skipped rather than
+    // holding up the rest of the feed
+    fn quarantine(&self, data: &[u8], error: &Error) {
+        println!("WARNING: Quarantining VSTP message: {}", error);
+        match &self.telemetry {
+            Some(x) => x.quarantined("gbnr-vstp", error.to_string()),
+            None => (),
+        }
+        let filename = match &self.config.quarantine_file {
+            Some(x) => x,
+            None => return,
+        };
+        let entry = serde_json::json!({
+            "received": Utc::now(),
+            "error": error.to_string(),
+            "message": String::from_utf8_lossy(data),
+        });
+        let result = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(filename)
+            .and_then(|mut file| {
+                std::io::Write::write_all(&mut file, format!("{}\n", entry).as_bytes())
+            });
+        match result {
+            Ok(()) => (),
+            Err(x) => println!(
+                "WARNING: Failed to write to VSTP quarantine {}: {}",
+                filename, x
+            ),
+        }
+    }
+
+    // How many messages that changed something have been kept so far, for reapply()
+    pub fn received(&self) -> usize {
+        self.previously_received.read().unwrap().len()
+    }
+
+    // Applies the messages kept since there were `since` again, e.g. to a copy of the schedule
+    // taken back then that's been updated some other way meanwhile. They were logged and
+    // announced the first time, so aren't again.
+    pub fn reapply(&self, mut schedule: Schedule, since: usize) -> Result<Schedule, Error> {
+        let previously_received = self.previously_received.read().unwrap();
+        for parsed_json in previously_received.iter().skip(since) {
+            schedule = self.read_vstp_entry(parsed_json, schedule)?.0;
+        }
+        Ok(schedule)
+    }
+
+    // What repopulate would have made of only the messages sent before `until`, leaving what's
+    // been received alone. Returns how many of them changed something.
+    pub fn replay(
+        &self,
+        mut schedule: Schedule,
+        until: DateTime<Utc>,
+    ) -> Result<(Schedule, usize), Error> {
+        let previously_received = self.previously_received.read().unwrap();
+        let mut applied = 0;
+        for parsed_json in &*previously_received {
+            match parsed_json.sent_at() {
+                Some(x) if x < until => (),
+                Some(_) => continue,
+                None => {
+                    println!(
+                        "WARNING: Skipping VSTP message with bad timestamp {}",
+                        parsed_json.vstp_cif_msg_v1.timestamp
+                    );
+                    continue;
+                }
+            }
+            let (new_schedule, change_made) = self.read_vstp_entry(parsed_json, schedule)?;
+            schedule = new_schedule;
+            if change_made {
+                applied += 1;
+            }
+        }
+        Ok((schedule, applied))
+    }
+
     fn read_vstp_route(
         &self,
         schedule_segments: &Vec<NrJsonScheduleSegment>,
@@ -4184,6 +5027,9 @@ impl NrJsonImporter {
                     produce_nr_json_error_closure("scheduled_departure_time".to_string()),
                 )?;
                 let wtt_dep_day = match (&last_wtt_time, &wtt_dep) {
+                    (Some(_), y) if wtt_arr.is_some() => {
+                        calculate_day(y, wtt_arr.as_ref().unwrap(), wtt_arr_day.unwrap())
+                    }
                     (Some(x), y) => calculate_day(y, x, last_wtt_day.unwrap()),
                     (None, Some(_)) => Some(0),
                     _ => None,
@@ -4280,7 +5126,7 @@ impl NrJsonImporter {
 
                 let new_location = TrainLocation {
                     timing_tz: None,
-                    id: location_id.to_string(),
+                    id: location_id.into(),
                     id_suffix: location_suffix,
                     working_arr: wtt_arr,
                     working_arr_day: wtt_arr_day,
@@ -4292,10 +5138,11 @@ impl NrJsonImporter {
                     public_arr_day: pub_arr_day,
                     public_dep: pub_dep,
                     public_dep_day: pub_dep_day,
-                    platform,
+                    scheduled_platform: platform.map(IStr::from),
+                    live_platform: None,
                     platform_zone: None,
-                    line: line_code,
-                    path: path_code,
+                    line: line_code.map(IStr::from),
+                    path: path_code.map(IStr::from),
                     engineering_allowance_s: eng_allowance,
                     pathing_allowance_s: path_allowance,
                     performance_allowance_s: perf_allowance,
@@ -4505,6 +5352,16 @@ impl NrJsonImporter {
             Some(x) => read_optional_string(x),
             None => None,
         };
+        match &uic_code {
+            Some(x) => {
+                schedule
+                    .trains_indexed_by_uic
+                    .entry(x.clone())
+                    .or_insert(HashSet::new())
+                    .insert(train_id.to_string());
+            }
+            None => (),
+        }
 
         let atoc_code = match &schedule_segment.atoc_code {
             Some(x) => x,
@@ -4516,7 +5373,7 @@ impl NrJsonImporter {
             produce_nr_json_error_closure("atoc_code".to_string()),
         )?;
 
-        Ok(VariableTrain {
+        let mut variable_train = VariableTrain {
             train_type,
             public_id: Some(public_id.to_string()),
             headcode,
@@ -4525,9 +5382,9 @@ impl NrJsonImporter {
             timing_allocation: match timing_load_str {
                 None => None,
                 Some(x) => Some(TrainAllocation {
-                    id: timing_load_id,
-                    description: x,
-                    vehicles: None,
+                    id: timing_load_id.as_str().into(),
+                    description: x.into(),
+                    vehicles: vehicles::for_timing_load(&timing_load_id),
                 }),
             },
             actual_allocation: None,
@@ -4544,15 +5401,29 @@ impl NrJsonImporter {
             name: None,
             uic_code,
             operator: Some(TrainOperator {
-                id: atoc_code.to_string(),
-                description: train_operator_desc,
+                id: atoc_code.into(),
+                description: train_operator_desc.map(IStr::from),
             }),
             wheelchair_accessible: None,
             bicycles_allowed: None,
-        })
+        };
+        vehicles::reclassify(&mut variable_train);
+        Ok(variable_train)
     }
 
+    // Reading a message indexes the train as it goes, whatever then becomes of it, so tidy up
+    // after, e.g. for messages about trains we don't have
     fn read_vstp_entry(
+        &self,
+        parsed_json: &NrJsonVstp,
+        schedule: Schedule,
+    ) -> Result<(Schedule, bool), NrJsonError> {
+        let (mut schedule, change_made) = self.apply_vstp_entry(parsed_json, schedule)?;
+        schedule.reindex_train(parsed_json.vstp_cif_msg_v1.schedule.cif_train_uid.trim());
+        Ok((schedule, change_made))
+    }
+
+    fn apply_vstp_entry(
         &self,
         parsed_json: &NrJsonVstp,
         mut schedule: Schedule,
@@ -4635,7 +5506,7 @@ impl NrJsonImporter {
                             .retain(|replacement| replacement.validity[0].valid_begin != begin),
                         ModificationType::Delete => train
                             .cancellations
-                            .retain(|(cancellation, _source)| cancellation.valid_begin != begin),
+                            .retain(|cancellation| cancellation.validity.valid_begin != begin),
                     }
                 }
             }
@@ -4689,9 +5560,11 @@ impl NrJsonImporter {
                     valid_end: end.clone(),
                     days_of_week: days_of_week.clone(),
                 };
-                train
-                    .cancellations
-                    .push((new_cancel, TrainSource::VeryShortTerm))
+                train.cancellations.push(TrainCancellation {
+                    validity: new_cancel,
+                    source: TrainSource::VeryShortTerm,
+                    reason: None,
+                })
             }
 
             schedule
@@ -4712,9 +5585,9 @@ impl NrJsonImporter {
             };
 
             for ref mut train in old_trains.iter_mut() {
-                for (cancellation, _source) in train.cancellations.iter_mut() {
-                    if cancellation.valid_begin == begin {
-                        *cancellation = TrainValidityPeriod {
+                for cancellation in train.cancellations.iter_mut() {
+                    if cancellation.validity.valid_begin == begin {
+                        cancellation.validity = TrainValidityPeriod {
                             valid_begin: begin,
                             valid_end: end,
                             days_of_week: days_of_week.clone(),
@@ -4787,7 +5660,7 @@ impl NrJsonImporter {
             };
 
         // all of the below will use this so construct it now
-        let new_train = Train {
+        let mut new_train = Train {
             id: main_train_id.to_string(),
             validity: vec![TrainValidityPeriod {
                 valid_begin: begin,
@@ -4810,19 +5683,60 @@ impl NrJsonImporter {
             source: Some(TrainSource::VeryShortTerm),
             runs_as_required,
             performance_monitoring: performance_monitoring,
-            route: self.read_vstp_route(
-                &parsed_json
-                    .vstp_cif_msg_v1
-                    .schedule
-                    .schedule_segment
-                    .as_ref()
-                    .unwrap(),
-                &train_status,
-                main_train_id,
-                &mut schedule,
-            )?,
+            route: self
+                .read_vstp_route(
+                    &parsed_json
+                        .vstp_cif_msg_v1
+                        .schedule
+                        .schedule_segment
+                        .as_ref()
+                        .unwrap(),
+                    &train_status,
+                    main_train_id,
+                    &mut schedule,
+                )?
+                .into(),
         };
 
+        // associations from the CIF that were waiting for this train; they're kept until they
+        // expire, as the train may be sent again
+        match schedule.pending_associations.get(main_train_id) {
+            Some(pending) => {
+                for x in pending {
+                    write_assocs_to_trains(
+                        std::slice::from_mut(&mut new_train),
+                        &x.location_id,
+                        &x.location_id_suffix,
+                        &vec![(x.association.clone(), x.category)],
+                    );
+                }
+            }
+            None => (),
+        }
+
+        // VSTP can use TIPLOCs no TI record has told us about yet, e.g. for a new siding. Rather
+        // than turning the train away, make up a location for each, to be put right if a TI record
+        // for it turns up.
+        for location in &new_train.route {
+            if !schedule.locations.contains_key(location.id.as_str()) {
+                println!(
+                    "WARNING: Unknown TIPLOC {} in VSTP for {}, adding it unverified",
+                    location.id, main_train_id
+                );
+                schedule.locations.insert(
+                    location.id.to_string(),
+                    Location {
+                        id: location.id.to_string(),
+                        name: location.id.to_string(),
+                        public_id: None,
+                        timezone: London,
+                        position: None,
+                        unverified: true,
+                    },
+                );
+            }
+        }
+
         validate_train_location(
             &new_train,
             &schedule.locations,
@@ -4938,15 +5852,40 @@ impl NrJsonImporter {
 #[async_trait]
 impl FastImporter for NrJsonImporter {
     fn overlay(&self, data: Vec<u8>, schedule: Schedule) -> Result<Schedule, Error> {
-        let parsed_json = serde_json::from_slice::<NrJsonVstp>(&data)?;
+        let parsed_json = match self.parse(&data) {
+            Ok(x) => x,
+            Err(x) => {
+                self.quarantine(&data, &x);
+                return Ok(schedule);
+            }
+        };
         let (schedule, change_made) = self.read_vstp_entry(&parsed_json, schedule)?;
         if change_made {
+            let train_id = parsed_json
+                .vstp_cif_msg_v1
+                .schedule
+                .cif_train_uid
+                .trim()
+                .to_string();
+            match parsed_json.change() {
+                Some(x) => self.changes.write().unwrap().push((train_id.clone(), x)),
+                None => (),
+            }
+            self.changed_trains.write().unwrap().push(train_id);
             let mut previously_received = self.previously_received.write().unwrap();
             previously_received.push(parsed_json);
         }
 
         Ok(schedule)
     }
+
+    fn take_changed_trains(&self) -> Vec<String> {
+        std::mem::take(&mut *self.changed_trains.write().unwrap())
+    }
+
+    fn take_changes(&self) -> Vec<(String, TrainChange)> {
+        std::mem::take(&mut *self.changes.write().unwrap())
+    }
 }
 
 #[async_trait]
@@ -0,0 +1,131 @@
+use crate::consistency::{check_schedule, InvariantViolation};
+use crate::error::Error;
+use crate::gtfs_importer::GtfsImporter;
+use crate::importer::{ImportReport, SlowGtfsImporter, SlowStreamingImporter};
+use crate::schedule::{Schedule, Train};
+use crate::uk_importer::{CifImporter, CifImporterConfig};
+
+use gtfs_structures::GtfsReader;
+
+use rand::RngCore;
+
+use serde::Serialize;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+// What importing a file would do, without it going anywhere near the live schedules. Meant for
+// trying out a new upstream feed before pointing a deployment at it.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ValidationReport {
+    pub lines: usize,
+    pub records: BTreeMap<String, usize>, // by record type, for CIF
+    pub trains: usize,
+    pub locations: usize,
+    pub errors: ImportReport,
+    pub unknown_operators: BTreeSet<String>,
+    pub unknown_locations: BTreeSet<String>, // used by a train but never defined
+    pub invariant_violations: Vec<InvariantViolation>, // the importer's fault, not the file's
+}
+
+fn find_unknown_locations(schedule: &Schedule, train: &Train, unknown: &mut BTreeSet<String>) {
+    for location in &train.route {
+        if !schedule.locations.contains_key(location.id.as_str()) {
+            unknown.insert(location.id.to_string());
+        }
+    }
+    for replacement in &train.replacements {
+        find_unknown_locations(schedule, replacement, unknown);
+    }
+}
+
+fn summarise(schedule: &Schedule, report: &mut ValidationReport) {
+    report.trains = schedule.trains.len();
+    report.locations = schedule.locations.len();
+    for trains in schedule.trains.values() {
+        for train in trains {
+            find_unknown_locations(schedule, train, &mut report.unknown_locations);
+        }
+    }
+    report.invariant_violations = check_schedule(schedule);
+}
+
+// Update files only make sense on top of what they update, so `base` should be a copy of the
+// schedule the file would normally be applied to; full extracts should get an empty one.
+pub async fn validate_cif(
+    data: Vec<u8>,
+    config: CifImporterConfig,
+    base: Schedule,
+) -> Result<ValidationReport, Error> {
+    let mut report = ValidationReport::default();
+    for line in data.split(|x| *x == b'\n') {
+        if line.is_empty() {
+            continue;
+        }
+        report.lines += 1;
+        let record_type = String::from_utf8_lossy(&line[..std::cmp::min(2, line.len())]);
+        *report.records.entry(record_type.to_string()).or_default() += 1;
+        // ATOC code of extra schedule details
+        if line.starts_with(b"BX") && line.len() >= 13 {
+            let atoc_code = String::from_utf8_lossy(&line[11..13]).to_string();
+            if !config.knows_operator(&atoc_code) {
+                report.unknown_operators.insert(atoc_code);
+            }
+        }
+    }
+
+    let mut importer = CifImporter::new(config.with_lenient(true));
+    let schedule = importer.overlay(&data[..], base).await?;
+    report.errors = importer.report().clone();
+    summarise(&schedule, &mut report);
+    Ok(report)
+}
+
+// GTFS has no lenient mode, so the first problem is the only one we can report.
+pub async fn validate_gtfs(data: Vec<u8>, base: Schedule) -> Result<ValidationReport, Error> {
+    // the GTFS reader wants a file it can seek around
+    let mut bytes = [0u8; 8];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let path = std::env::temp_dir().join(format!(
+        "wrt-validate-{}.zip",
+        bytes
+            .iter()
+            .map(|x| format!("{:02x}", x))
+            .collect::<String>()
+    ));
+    tokio::fs::write(&path, data).await?;
+    let read_path = path.clone();
+    let gtfs = tokio::task::spawn_blocking(move || {
+        GtfsReader::default()
+            .read_shapes(false)
+            .unkown_enum_as_default(false)
+            .read_from_path(read_path.display().to_string())
+    })
+    .await?;
+    match tokio::fs::remove_file(&path).await {
+        Ok(()) => (),
+        Err(x) => println!("WARNING: Failed to remove {}: {}", path.display(), x),
+    }
+
+    let mut report = ValidationReport::default();
+    let gtfs = match gtfs {
+        Ok(x) => x,
+        Err(x) => {
+            report.errors.add(&x);
+            return Ok(report);
+        }
+    };
+    report.records.insert("stops".to_string(), gtfs.stops.len());
+    report
+        .records
+        .insert("routes".to_string(), gtfs.routes.len());
+    report.records.insert("trips".to_string(), gtfs.trips.len());
+    report
+        .records
+        .insert("agencies".to_string(), gtfs.agencies.len());
+
+    match GtfsImporter::new().overlay(gtfs, base).await {
+        Ok(schedule) => summarise(&schedule, &mut report),
+        Err(x) => report.errors.add(&x),
+    }
+    Ok(report)
+}
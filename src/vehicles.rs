@@ -0,0 +1,246 @@
+use crate::schedule::{TrainPower, TrainType, TrainVehicle, VariableTrain, VehicleAccessibility};
+
+// What the units behind a UK timing load are like, so an allocation can say more than its
+// description: how many cars they come in, how long those are, how fast they go and what they
+// have for disabled passengers. Timing loads often cover several classes, in which case all of
+// them are given, as there's no telling which will turn up. Accessibility is None where it varies
+// across a class or we don't know; anything still running after the 2020 PRM-TSI deadline has
+// been made compliant, give or take dispensations.
+
+struct VehicleClass {
+    id: &'static str,
+    description: &'static str,
+    cars: &'static [u8],
+    car_length_m: Option<f64>,
+    max_speed_mph: Option<u16>,
+    wheelchair_spaces: Option<bool>,
+    accessible_toilet: Option<bool>,
+    passenger_information: Option<bool>,
+}
+
+const fn class(
+    id: &'static str,
+    description: &'static str,
+    cars: &'static [u8],
+    car_length_m: f64,
+    max_speed_mph: u16,
+) -> VehicleClass {
+    VehicleClass {
+        id,
+        description,
+        cars,
+        car_length_m: Some(car_length_m),
+        max_speed_mph: Some(max_speed_mph),
+        wheelchair_spaces: Some(true),
+        accessible_toilet: Some(true),
+        passenger_information: Some(true),
+    }
+}
+
+// withdrawn from passenger service, or never in it, so nothing to say about accessibility
+const fn unknown_accessibility(class: VehicleClass) -> VehicleClass {
+    VehicleClass {
+        wheelchair_spaces: None,
+        accessible_toilet: None,
+        passenger_information: None,
+        ..class
+    }
+}
+
+const fn no_toilet(class: VehicleClass) -> VehicleClass {
+    VehicleClass {
+        accessible_toilet: Some(false),
+        ..class
+    }
+}
+
+// one class to a line, as a table
+#[rustfmt::skip]
+const CLASSES: &[VehicleClass] = &[
+    // DMUs
+    unknown_accessibility(class("142", "Class 142 'Pacer' DMU", &[2], 15.5, 75)),
+    unknown_accessibility(class("143", "Class 143 'Pacer' DMU", &[2], 15.5, 75)),
+    unknown_accessibility(class("144", "Class 144 'Pacer' DMU", &[2, 3], 15.5, 75)),
+    class("150", "Class 150 'Sprinter' DMU", &[2, 3], 20.0, 75),
+    class("153", "Class 153 'Super Sprinter' DMU", &[1], 23.2, 75),
+    class("155", "Class 155 'Super Sprinter' DMU", &[2], 23.2, 75),
+    class("156", "Class 156 'Super Sprinter' DMU", &[2], 23.0, 75),
+    class("158", "Class 158 'Express Sprinter' DMU", &[2, 3, 4], 22.6, 90),
+    class("159", "Class 159 'South Western Turbo' DMU", &[3], 22.6, 90),
+    class("165/0", "Class 165/0 'Network Turbo' DMU", &[2, 3], 23.5, 75),
+    class("165/1", "Class 165/1 'Network Turbo' DMU", &[2, 3], 23.5, 90),
+    class("166", "Class 166 'Network Express Turbo' DMU", &[3], 23.5, 90),
+    class("168", "Class 168 'Clubman' DMU", &[2, 3, 4], 23.6, 100),
+    class("170", "Class 170 'Turbostar' DMU", &[2, 3], 23.6, 100),
+    class("171", "Class 171 'Turbostar' DMU", &[2, 4], 23.6, 100),
+    class("172", "Class 172 'Turbostar' DMU", &[2, 3], 23.6, 100),
+    class("175", "Class 175 'Coradia' DMU", &[2, 3], 23.7, 100),
+    class("185", "Class 185 'Desiro' DMU", &[3], 23.8, 100),
+    class("195", "Class 195 'Civity' DMU", &[2, 3], 24.0, 100),
+    class("196", "Class 196 'Civity' DMU", &[2, 4], 24.0, 100),
+    class("197", "Class 197 'Civity' DMU", &[2, 3], 24.0, 100),
+    class("220", "Class 220 'Voyager' DMU", &[4], 23.7, 125),
+    class("221", "Class 221 'Super Voyager' DMU", &[4, 5], 23.7, 125),
+    class("222", "Class 222 'Meridian' DMU", &[5, 7], 23.9, 125),
+    class("3000", "Class 3000 'C3K' CAF DMU", &[3, 6], 23.3, 90),
+    class("4000", "Class 4000 'C4K' CAF DMU", &[3, 6], 23.3, 90),
+    class("HST", "High Speed Train (IC125)", &[4, 5, 6, 7, 8, 9, 10], 23.0, 125),
+    // EMUs
+    class("313", "Class 313 EMU", &[3], 20.3, 75),
+    class("315", "Class 315 EMU", &[4], 20.3, 75),
+    class("317", "Class 317 EMU", &[4], 20.0, 100),
+    class("318", "Class 318 EMU", &[3], 20.0, 90),
+    class("319", "Class 319 EMU", &[4], 20.0, 100),
+    class("320", "Class 320 EMU", &[3], 20.0, 90),
+    class("321", "Class 321 EMU", &[4], 20.0, 100),
+    class("322", "Class 322 EMU", &[4], 20.0, 100),
+    class("323", "Class 323 EMU", &[3], 23.4, 90),
+    unknown_accessibility(class("325", "Class 325 Parcels EMU", &[4], 20.0, 100)),
+    class("331", "Class 331 'Civity' EMU", &[3, 4], 24.0, 100),
+    class("333", "Class 333 EMU", &[4], 23.0, 100),
+    class("334", "Class 334 'Juniper' EMU", &[3], 23.0, 90),
+    no_toilet(class("345", "Class 345 'Aventra' EMU", &[7, 9], 22.8, 90)),
+    class("350", "Class 350 'Desiro' EMU", &[4], 20.0, 110),
+    class("350/1", "Class 350/1 'Desiro' EMU", &[4], 20.0, 110),
+    class("357", "Class 357 'Electrostar' EMU", &[4], 20.0, 100),
+    class("360", "Class 360 'Desiro' EMU", &[4, 5], 20.0, 110),
+    class("365", "Class 365 'Networker Express' EMU", &[4], 20.0, 100),
+    class("375", "Class 375 'Electrostar' EMU", &[3, 4], 20.0, 100),
+    class("376", "Class 376 'Electrostar' EMU", &[5], 20.0, 75),
+    class("377", "Class 377 'Electrostar' EMU", &[3, 4, 5], 20.0, 100),
+    no_toilet(class("378", "Class 378 'Capitalstar' EMU", &[5], 20.0, 75)),
+    class("379", "Class 379 'Electrostar' EMU", &[4], 20.0, 100),
+    class("380", "Class 380 'Desiro' EMU", &[3, 4], 23.0, 100),
+    class("385", "Class 385 'AT200' EMU", &[3, 4], 23.0, 100),
+    class("387", "Class 387 'Electrostar' EMU", &[4], 20.0, 110),
+    class("390", "Class 390 'Pendolino' EMU", &[9, 11], 24.0, 125),
+    class("395", "Class 395 'Javelin' EMU", &[6], 20.0, 140),
+    class("397", "Class 397 'Civity' EMU", &[5], 23.0, 125),
+    no_toilet(class("398", "Class 398 'Citylink' tram-train", &[3], 13.3, 62)),
+    no_toilet(class("399", "Class 399 'Citylink' tram-train", &[3], 12.4, 62)),
+    class("444", "Class 444 'Desiro' EMU", &[5], 23.0, 100),
+    class("450", "Class 450 'Desiro' EMU", &[4], 20.0, 100),
+    class("455", "Class 455 EMU", &[4], 20.0, 75),
+    class("456", "Class 456 EMU", &[2], 20.0, 75),
+    class("458", "Class 458 'Juniper' EMU", &[4, 5], 20.0, 100),
+    class("465", "Class 465 'Networker' EMU", &[4], 20.0, 75),
+    class("466", "Class 466 'Networker' EMU", &[2], 20.0, 75),
+    class("507", "Class 507 EMU", &[3], 20.0, 75),
+    class("508", "Class 508 EMU", &[3], 20.0, 75),
+    no_toilet(class("555", "Class 555 Tyne and Wear Metro", &[5], 12.0, 50)),
+    no_toilet(class("599", "Class 599 'Metrocar'", &[2, 4], 13.9, 50)),
+    class("700", "Class 700 'Desiro City' EMU", &[8, 12], 20.0, 100),
+    class("701", "Class 701 'Aventra' EMU", &[5, 10], 20.0, 100),
+    class("707", "Class 707 'Desiro City' EMU", &[5], 20.0, 100),
+    no_toilet(class("710", "Class 710 'Aventra' EMU", &[4, 5], 20.0, 75)),
+    class("717", "Class 717 'Desiro City' EMU", &[6], 20.0, 85),
+    class("720", "Class 720 'Aventra' EMU", &[5, 10], 24.0, 100),
+    class("730", "Class 730 'Aventra' EMU", &[3, 5], 24.0, 110),
+    class("745", "Class 745 'FLIRT' EMU", &[12], 20.0, 100),
+    class("755", "Class 755 'FLIRT' bi-mode", &[3, 4], 20.0, 100),
+    class("769", "Class 769 'Flex' bi-mode", &[4], 20.0, 100),
+    no_toilet(class("777", "Class 777 'METRO' EMU", &[4], 16.2, 75)),
+    class("800", "Class 800 'IET/Azuma' bi-mode", &[5, 9], 26.0, 125),
+    class("801", "Class 801 'Azuma' EMU", &[5, 9], 26.0, 125),
+    class("802", "Class 802 'IET/Nova 1/Paragon' bi-mode", &[5, 9], 26.0, 125),
+    class("805", "Class 805 'Evero' bi-mode", &[5], 26.0, 125),
+    class("807", "Class 807 'Evero' EMU", &[7], 26.0, 125),
+    class("810", "Class 810 'Aurora' bi-mode", &[5], 24.0, 125),
+];
+
+fn lookup(id: &str) -> Option<&'static VehicleClass> {
+    CLASSES.iter().find(|x| x.id == id)
+}
+
+// the classes each timing load can mean, as read_timing_load describes them
+fn classes<'a>(power_type: &str, timing_load: &'a str) -> Vec<&'a str> {
+    match (power_type, timing_load) {
+        ("DEM" | "DMU", "69") => vec!["172"],
+        ("DEM" | "DMU", "A") => vec!["142", "143", "144"],
+        ("DEM" | "DMU", "E") => vec!["158", "168", "170", "172", "175"],
+        ("DEM" | "DMU", "N") => vec!["165/0"],
+        ("DEM" | "DMU", "S") => vec!["150", "153", "155", "156"],
+        ("DEM" | "DMU", "T") => vec!["165/1", "166"],
+        ("DEM" | "DMU", "V") => vec!["220", "221"],
+        ("DEM" | "DMU", "X") => vec!["159"],
+        ("DEM" | "DMU", "802") => vec!["800", "802"],
+        ("DEM" | "DMU", "805") => vec!["805", "807"],
+        ("DEM" | "DMU", "CAF" | "DMU") => vec!["3000", "4000"],
+        ("DEM" | "DMU", "195" | "196" | "197" | "755" | "777" | "800" | "810") => {
+            vec![timing_load]
+        }
+        ("E", "325") => vec!["325"],
+        ("EML" | "EMU", "E") => vec!["458"],
+        ("EML" | "EMU", "0") => vec!["380"],
+        ("EML" | "EMU", "506") => vec!["350/1"],
+        ("EML" | "EMU", x) => vec![x],
+        ("HST", _) => vec!["HST"],
+        // locomotives and their loads, which could be anything
+        _ => vec![],
+    }
+}
+
+// Takes a timing load ID as it's kept on TrainAllocation: power type in the first three
+// characters, then the load. None if there's nothing known about what it could be.
+pub fn for_timing_load(timing_load_id: &str) -> Option<Vec<TrainVehicle>> {
+    let power_type = timing_load_id.get(..3).unwrap_or(timing_load_id).trim();
+    let timing_load = timing_load_id.get(3..).unwrap_or("").trim();
+    let vehicles = classes(power_type, timing_load)
+        .into_iter()
+        .filter_map(lookup)
+        .map(|x| TrainVehicle {
+            id: x.id.to_string(),
+            description: x.description.to_string(),
+            cars: x.cars.to_vec(),
+            car_length_m: x.car_length_m,
+            max_speed_mph: x.max_speed_mph,
+            accessibility: VehicleAccessibility {
+                wheelchair_spaces: x.wheelchair_spaces,
+                accessible_toilet: x.accessible_toilet,
+                passenger_information: x.passenger_information,
+            },
+        })
+        .collect::<Vec<_>>();
+    match vehicles.is_empty() {
+        true => None,
+        false => Some(vehicles),
+    }
+}
+
+// CIF has no types for trams, tram-trains or light metro, so they come in as ordinary passenger
+// trains and EMUs. Once the operator is known, the units and operators that are really one of
+// those are given the type and power that say so, so they can be filtered out from heavy rail.
+pub fn reclassify(variable_train: &mut VariableTrain) {
+    let timing_load = match &variable_train.timing_allocation {
+        Some(x) => x.id.as_str().get(3..).unwrap_or("").trim(),
+        None => "",
+    };
+    let operator = match &variable_train.operator {
+        Some(x) => x.id.as_str(),
+        None => "",
+    };
+    let (train_type, power_type) = match (operator, timing_load) {
+        // Merseyrail's, which can run off their batteries beyond the third rail
+        (_, "777") => (None, TrainPower::ElectricAndBatteryMultipleUnit),
+        // Transport for Wales' on the Cardiff valley lines
+        (_, "398") => (
+            Some(TrainType::TramTrain),
+            TrainPower::ElectricAndBatteryTram,
+        ),
+        // Sheffield Supertram's to Rotherham
+        ("SJ", _) | (_, "399") => (Some(TrainType::TramTrain), TrainPower::ElectricTram),
+        // Tyne and Wear Metro's to Sunderland
+        ("TW", _) | (_, "555" | "599") => (Some(TrainType::Metro), TrainPower::ElectricTram),
+        _ => return,
+    };
+    variable_train.power_type = Some(power_type);
+    // empty stock keeps its type, so it's still left off boards, except for the metro's own
+    variable_train.train_type = match (variable_train.train_type, train_type) {
+        (
+            TrainType::OrdinaryPassenger | TrainType::ExpressPassenger | TrainType::Metro,
+            Some(x),
+        ) => x,
+        (TrainType::EmptyPassenger, Some(TrainType::Metro)) => TrainType::EmptyMetro,
+        (x, _) => x,
+    };
+}
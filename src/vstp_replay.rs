@@ -0,0 +1,114 @@
+use crate::download_cache::{CachedVersion, DownloadCache};
+use crate::error::{Error, ErrorContext};
+use crate::importer::SlowStreamingImporter;
+use crate::nr_manager::{CIF_UPDATES, FULL_CIF};
+use crate::schedule::Schedule;
+use crate::uk_importer::{CifImporter, CifImporterConfig, NrJsonImporter, NrJsonImporterConfig};
+
+use chrono::{DateTime, Utc};
+
+use serde::Serialize;
+
+use std::sync::Arc;
+
+// The gbnr schedule as it was at some point in the past, for looking into what we were showing
+// when something went wrong: the newest full CIF extract fetched by then with the updates fetched
+// after it, then the VSTP messages sent before then. This only goes as far back as the download
+// cache keeps versions, and VSTP messages for trains that have finished since are long gone.
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ReplaySummary {
+    pub at: DateTime<Utc>,
+    pub full_extract_fetched: DateTime<Utc>,
+    pub updates: Vec<String>, // in the order they were applied
+    pub vstp_applied: usize,
+}
+
+pub struct Replayer {
+    download_cache: Arc<DownloadCache>,
+    cif_importer: CifImporterConfig,
+    json_importer: NrJsonImporterConfig,
+}
+
+// the newest version fetched between the two times
+async fn fetched_between(
+    download_cache: &DownloadCache,
+    name: &str,
+    after: DateTime<Utc>,
+    at: DateTime<Utc>,
+) -> Result<Option<CachedVersion>, Error> {
+    Ok(download_cache
+        .versions(name)
+        .await?
+        .into_iter()
+        .find(|x| x.fetched >= after && x.fetched <= at))
+}
+
+impl Replayer {
+    pub fn new(
+        download_cache: Arc<DownloadCache>,
+        cif_importer: CifImporterConfig,
+        json_importer: NrJsonImporterConfig,
+    ) -> Self {
+        Self {
+            download_cache,
+            cif_importer,
+            json_importer,
+        }
+    }
+
+    pub async fn replay(&self, at: DateTime<Utc>) -> Result<(Schedule, ReplaySummary), Error> {
+        let full = match fetched_between(&self.download_cache, FULL_CIF, DateTime::UNIX_EPOCH, at)
+            .await?
+        {
+            Some(x) => x,
+            None => Err(anyhow::anyhow!(
+                "No full CIF extract fetched before {} is still cached",
+                at
+            ))?,
+        };
+        println!(
+            "Replaying from the full CIF extract fetched {}",
+            full.fetched
+        );
+
+        let mut cif_importer = CifImporter::new(self.cif_importer.clone());
+        let mut schedule = Schedule::new(
+            "gbnr".to_string(),
+            "United Kingdom — Network Rail".to_string(),
+        );
+        let mut reader = self.download_cache.open(&full).await?;
+        schedule = cif_importer
+            .overlay(&mut reader, schedule)
+            .await
+            .context("Importing cached NR full CIF")?;
+
+        let mut updates = vec![];
+        for name in CIF_UPDATES {
+            let version =
+                match fetched_between(&self.download_cache, name, full.fetched, at).await? {
+                    Some(x) => x,
+                    None => continue,
+                };
+            let mut reader = self.download_cache.open(&version).await?;
+            schedule = cif_importer
+                .overlay(&mut reader, schedule)
+                .await
+                .context("Importing cached NR CIF update")?;
+            updates.push(name.to_string());
+        }
+
+        let nr_json_importer = NrJsonImporter::new(self.json_importer.clone()).await?;
+        let (schedule, vstp_applied) = nr_json_importer.replay(schedule, at)?;
+
+        Ok((
+            schedule,
+            ReplaySummary {
+                at,
+                full_extract_fetched: full.fetched,
+                updates,
+                vstp_applied,
+            },
+        ))
+    }
+}
@@ -1,37 +1,141 @@
 use chrono::naive::Days;
 use chrono::offset::LocalResult;
-use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, ParseError, TimeZone, Utc};
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, NaiveTime, ParseError, TimeZone, Utc};
 use chrono_tz::Tz;
 
+use crate::alerts::{Alert, Alerts, AlertsConfig, NewAlert};
+use crate::branding::{Branding, BrandingConfig, OperatorBranding, OperatorInfo};
+use crate::change_log::TrainChange;
+use crate::cold_routes::peek_train;
+use crate::dump::TrainDump;
+use crate::duplicates::{DuplicateConfig, Duplicates, ThroughService, TrainRef};
+use crate::dwell::{Dwell, DwellConfig, DwellReport};
+use crate::environments::{self, Environments, MAIN_ENVIRONMENT};
 use crate::error::Error;
-use crate::schedule::{AssociationNode, Train, TrainLocation, TrainOperator, TrainSource};
+use crate::feed_telemetry::{FeedTelemetry, SourceTelemetry};
+use crate::fetch_core::{DownloadMetrics, HttpClient};
+use crate::flows::{FlowReport, Flows, FlowsConfig};
+use crate::gtfs_rt::{GtfsRt, GtfsRtConfig};
+use crate::integrity::{Integrity, IntegrityReport};
+use crate::interchange::{Interchange, InterchangeConfig, InterchangeInfo};
+use crate::intern::IStr;
+use crate::live_positions::{estimate_positions, positions_to_geojson, BoundingBox};
+use crate::localisation::{parse_accept_language, Localisation, NamespaceLocalisation};
+use crate::movements::{MovementReport, Movements, MovementsConfig};
+use crate::notifications::{Interest, Notification, Notifications};
+use crate::openapi::{openapi_document, SWAGGER_UI};
+use crate::output_format::{OutputFormat, SpeedUnit, TimeFormat};
+use crate::paging::Paging;
+use crate::partitioned_map::PartitionedMap;
+use crate::platform_occupancy::{OccupancyReport, PlatformOccupancy, PlatformOccupancyConfig};
+use crate::proxy::{Proxy, ProxyConfig};
+use crate::quality::{Quality, QualityConfig, QualityReport};
+use crate::query_cache::{QueryCache, QueryCacheStats};
+use crate::redaction::{Redaction, RedactionConfig, RedactionPolicy};
+use crate::regions::{Region, Regions, RegionsConfig};
+use crate::resolved_train::{cancellation_reason, resolve_train, ResolvedCall, ResolvedTrain};
+use crate::response_headers::{etag_matches, ResponseHeaders, ResponseHeadersConfig};
+use crate::route_geometry::{RouteGeometry, RouteGeometryConfig};
+use crate::running_days::running_days_text;
+use crate::schedule::{
+    call_index, get_association, get_cancellation, get_train_instance, get_train_version,
+    get_trigrams, max_day_offset, name_train, AssociationCategory, Facilities, IndexRebuild,
+    LocalTimes, Location, OperatingCharacteristics, PassengerFlags, Restriction, Schedule, Train,
+    TrainAllocation, TrainLocation, TrainOperator, TrainPower, TrainRealtime, TrainSource,
+    TrainType, TransportMode, VariableTrain,
+};
 use crate::schedule_manager::ScheduleManager;
-
-use rocket::request::FromParam;
-use rocket::{get, routes, State};
+use crate::segments::RouteSegment;
+use crate::snapshot::{read_snapshot, write_snapshot};
+use crate::staleness::{SourceStatus, Staleness};
+use crate::station_groups::{StationGroups, StationGroupsConfig};
+use crate::timetable_export::{
+    departures_poster, export, export_weekly, weekly_timetable, ExportFormat,
+};
+use crate::train_id::GlobalTrainId;
+use crate::triggers::{ManagerCommand, RunState, Triggers};
+use crate::uk_importer::CifImporterConfig;
+use crate::validation::{validate_cif, validate_gtfs, ValidationReport};
+use crate::vstp_replay::Replayer;
+
+use rocket::data::{Data, ToByteUnit};
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::form::{self, FromFormField, ValueField};
+use rocket::http::uri::Origin;
+use rocket::http::{ContentType, Method, Status};
+use rocket::request::{FromParam, FromRequest, Outcome, Request};
+use rocket::response::content::RawHtml;
+use rocket::response::stream::ByteStream;
+use rocket::response::Response;
+use rocket::serde::json::Json;
+use rocket::{delete, get, options, post, routes, FromForm, State};
 use rocket_dyn_templates::{context, Template};
 
-use serde::Serialize;
+use itertools::Itertools;
+
+use serde::{Deserialize, Serialize};
 
 use std::cmp::max;
-use std::collections::{HashMap, HashSet};
-use std::fmt;
-use std::ops::{Add, Sub};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Cursor;
+use std::ops::{Add, Deref, Sub};
+use std::path::PathBuf;
 use std::sync::Arc;
 
-#[derive(Debug)]
-pub struct WebUiError {
-    what: String,
+#[derive(Clone, Default, Deserialize)]
+pub struct WebUiConfig {
+    query_cache_ttl_secs: Option<u64>,
+    localisation: Option<HashMap<String, NamespaceLocalisation>>,
+    localisation_files: Option<Vec<String>>,
+    interchange: Option<InterchangeConfig>,
+    route_geometry: Option<RouteGeometryConfig>,
+    duplicates: Option<DuplicateConfig>,
+    platform_occupancy: Option<PlatformOccupancyConfig>,
+    flows: Option<FlowsConfig>,
+    dwell: Option<DwellConfig>,
+    movements: Option<MovementsConfig>,
+    admin: Option<AdminConfig>, // admin endpoints are only there if this is
+    upstream: Option<UpstreamConfig>,
+    redaction: Option<RedactionConfig>,
+    show_runs_as_required: Option<bool>, // on boards and in next trains unless asked; default false
+    branding: Option<BrandingConfig>,
+    regions: Option<RegionsConfig>,
+    station_groups: Option<StationGroupsConfig>,
+    alerts: Option<AlertsConfig>,
+    quality: Option<QualityConfig>,
+    proxy: Option<ProxyConfig>,
+    gtfs_rt: Option<GtfsRtConfig>,
+    headers: Option<ResponseHeadersConfig>, // CORS and caching
 }
 
-impl fmt::Display for WebUiError {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "Error in web UI: {}", self.what)
-    }
+#[derive(Clone, Deserialize)]
+pub struct AdminConfig {
+    token: String,                        // sent as "Authorization: Bearer <token>"
+    max_restore_mb: Option<u64>,          // default 512, as uploaded
+    max_restore_unpacked_mb: Option<u64>, // default 4096, once a snapshot is gunzipped
+    validation: Option<HashMap<String, CifImporterConfig>>, // CIF settings to validate with, by namespace
+}
+
+// For publishers (or our own cron jobs watching them) to say there's something new to fetch
+#[derive(Clone, Deserialize)]
+pub struct UpstreamConfig {
+    token: String, // sent as "Authorization: Bearer <token>"; best not the same as the admin one
+}
+
+// Most runs-as-required (Q) paths never actually run, so they're left out of boards and next
+// trains unless asked for, or this is turned on
+struct ConditionalPaths {
+    shown: bool,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Error in web UI: {what}")]
+pub struct WebUiError {
+    what: String,
 }
 
 #[get("/")]
-fn index(schedule_manager: &State<Arc<ScheduleManager>>) -> Template {
+fn index(schedule_manager: Schedules, alerts: &State<Alerts>) -> Template {
     let namespaces = {
         let schedule_manager = schedule_manager.read();
         let mut map = HashMap::new();
@@ -43,11 +147,486 @@ fn index(schedule_manager: &State<Arc<ScheduleManager>>) -> Template {
 
     let context = context! {
         namespaces,
+        alerts: alerts.current(None, &[], []),
     };
 
     Template::render("index", &context)
 }
 
+// The schedules to answer from: the current ones, or, with ?schedule_as_of=, whichever
+// generations were current at the time. Takes RFC 3339, or a date and time in UTC. They're the
+// main environment's unless another is asked for, with an X-Environment header or under
+// /env/<name>/.
+struct Schedules(Arc<ScheduleManager>);
+
+impl Deref for Schedules {
+    type Target = Arc<ScheduleManager>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+fn parse_as_of(as_of: &str) -> Result<DateTime<Utc>, ParseError> {
+    match DateTime::parse_from_rfc3339(as_of) {
+        Ok(x) => Ok(x.with_timezone(&Utc)),
+        Err(_) => match NaiveDateTime::parse_from_str(as_of, "%Y-%m-%dT%H:%M:%S") {
+            Ok(x) => Ok(x.and_utc()),
+            Err(_) => Ok(NaiveDateTime::parse_from_str(as_of, "%Y-%m-%dT%H:%M")?.and_utc()),
+        },
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Schedules {
+    type Error = WebUiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let environments = match request.rocket().state::<Environments>() {
+            Some(x) => x,
+            None => {
+                return Outcome::Error((
+                    Status::InternalServerError,
+                    WebUiError {
+                        what: "No schedule manager".to_string(),
+                    },
+                ))
+            }
+        };
+        let environment = match environments::requested(
+            request.headers().get_one("X-Environment"),
+            request.uri().path().as_str(),
+        ) {
+            Ok(x) => x,
+            Err(x) => return Outcome::Error((Status::BadRequest, WebUiError { what: x })),
+        };
+        let schedule_manager = match environments.get(environment) {
+            Some(x) => x,
+            None => {
+                return Outcome::Error((
+                    Status::NotFound,
+                    WebUiError {
+                        what: format!("Unknown environment {}", environment),
+                    },
+                ))
+            }
+        };
+        match request.query_value::<&str>("schedule_as_of") {
+            Some(Ok(x)) => match parse_as_of(x) {
+                Ok(x) => Outcome::Success(Schedules(Arc::new(schedule_manager.as_of(x)))),
+                Err(x) => Outcome::Error((
+                    Status::BadRequest,
+                    WebUiError {
+                        what: format!("Bad schedule_as_of: {}", x),
+                    },
+                )),
+            },
+            _ => Outcome::Success(Schedules(schedule_manager.clone())),
+        }
+    }
+}
+
+// Display names in the languages the user asked for, either with ?lang= or their browser's
+// Accept-Language
+struct Localiser<'r> {
+    localisation: &'r Localisation,
+    languages: Vec<String>,
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Localiser<'r> {
+    type Error = WebUiError;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let localisation = match request.rocket().state::<Localisation>() {
+            Some(x) => x,
+            None => {
+                return Outcome::Error((
+                    Status::InternalServerError,
+                    WebUiError {
+                        what: "No localisation loaded".to_string(),
+                    },
+                ))
+            }
+        };
+        let languages = match request.query_value::<&str>("lang") {
+            Some(Ok(x)) => parse_accept_language(x),
+            _ => match request.headers().get_one("Accept-Language") {
+                Some(x) => parse_accept_language(x),
+                None => vec![],
+            },
+        };
+        Outcome::Success(Localiser {
+            localisation,
+            languages,
+        })
+    }
+}
+
+impl Localiser<'_> {
+    fn localise_locations(&self, namespace: &str, locations: &mut PartitionedMap<Location>) {
+        for (id, name) in self.localisation.location_names(namespace, &self.languages) {
+            match locations.get_mut(id) {
+                Some(x) => x.name = name.to_string(),
+                None => (),
+            }
+        }
+    }
+
+    fn localise_operator(&self, namespace: &str, operator: &mut Option<TrainOperator>) {
+        let operator = match operator {
+            Some(x) => x,
+            None => return,
+        };
+        match self
+            .localisation
+            .operator_names(namespace, &self.languages)
+            .get(operator.id.as_str())
+        {
+            Some(x) => operator.description = Some((*x).into()),
+            None => (),
+        }
+    }
+
+    // boards are cached already rendered to JSON, so localise on the way out
+    fn localise_board(&self, namespace: &str, context: &mut serde_json::Value) {
+        let locations = match context.get_mut("locations") {
+            Some(serde_json::Value::Object(x)) => x,
+            _ => return,
+        };
+        for (id, name) in self.localisation.location_names(namespace, &self.languages) {
+            match locations.get_mut(id) {
+                Some(x) => x["name"] = serde_json::Value::String(name.to_string()),
+                None => (),
+            }
+        }
+    }
+}
+
+// Units and time formats for every JSON response, from ?units=, ?time_format= and ?seconds=, or
+// the same as parameters in the Accept header, e.g. "application/json; units=mph"
+fn output_format(request: &Request<'_>) -> OutputFormat {
+    let get = |name: &str| -> Option<&str> {
+        match request.query_value::<&str>(name) {
+            Some(Ok(x)) => Some(x),
+            _ => request.accept()?.media_types().find_map(|x| x.param(name)),
+        }
+    };
+    let default = OutputFormat::default();
+    OutputFormat {
+        speed: get("units")
+            .and_then(SpeedUnit::parse)
+            .unwrap_or(default.speed),
+        time: get("time_format")
+            .and_then(TimeFormat::parse)
+            .unwrap_or(default.time),
+        seconds: get("seconds")
+            .and_then(|x| x.parse().ok())
+            .unwrap_or(default.seconds),
+    }
+}
+
+struct OutputFormatting;
+
+#[rocket::async_trait]
+impl Fairing for OutputFormatting {
+    fn info(&self) -> Info {
+        Info {
+            name: "Output units and formats",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.content_type() != Some(ContentType::JSON) {
+            return;
+        }
+        let format = output_format(request);
+        if format.is_default() {
+            return;
+        }
+        let body = match response.body_mut().to_string().await {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        let body = match serde_json::from_str::<serde_json::Value>(&body) {
+            Ok(mut x) => {
+                format.apply(&mut x);
+                x.to_string()
+            }
+            Err(_) => body,
+        };
+        response.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+// Strips what the redaction policy says the public shouldn't see, unless the request comes with
+// an operational token, or the admin one. This goes before OutputFormatting, so field names are as
+// in the policy.
+struct Redacting;
+
+// what to hide from this request, if anything
+fn redaction_policy<'r>(request: &'r Request<'_>) -> Option<&'r RedactionPolicy> {
+    let redaction = request.rocket().state::<Redaction>()?;
+    let policy = redaction.policy(request.uri().path().as_str())?;
+    let admin_token = request
+        .rocket()
+        .state::<AdminConfig>()
+        .map(|x| x.token.as_str());
+    match redaction
+        .tokens()
+        .iter()
+        .map(|x| x.as_str())
+        .chain(admin_token)
+        .any(|x| bearer_token_matches(request, x))
+    {
+        true => None,
+        false => Some(policy),
+    }
+}
+
+// For responses Redacting can't get into, e.g. streamed ones, to apply the policy themselves
+struct Redacted(Option<RedactionPolicy>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Redacted {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, ()> {
+        Outcome::Success(Redacted(redaction_policy(request).cloned()))
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for Redacting {
+    fn info(&self) -> Info {
+        Info {
+            name: "Redaction of non-public data",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.content_type() != Some(ContentType::JSON) {
+            return;
+        }
+        let policy = match redaction_policy(request) {
+            Some(x) => x,
+            None => return,
+        };
+        let body = match response.body_mut().to_string().await {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        let body = match serde_json::from_str::<serde_json::Value>(&body) {
+            Ok(mut x) => {
+                if !policy.apply(&mut x) {
+                    // as if there were no such train
+                    response.set_status(Status::NotFound);
+                    x = serde_json::Value::Null;
+                }
+                x.to_string()
+            }
+            Err(_) => body,
+        };
+        response.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+// The same request with the cursor for the next page instead of this one's
+fn next_page_uri(request: &Request<'_>, cursor: &str) -> String {
+    let mut query = request
+        .uri()
+        .query()
+        .map_or("", |x| x.as_str())
+        .split('&')
+        .filter(|x| !x.is_empty() && !x.starts_with("cursor="))
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>();
+    query.push(format!("cursor={}", cursor));
+    format!("{}?{}", request.uri().path(), query.join("&"))
+}
+
+// The main list in each endpoint's response that's an object, as a JSON pointer, by handler, so
+// ?limit= and ?cursor= page through it. Responses that are lists are paged anyway.
+const LISTS: &[(&str, &str)] = &[
+    ("dwell", "/calls"),
+    ("flows", "/by_date"),
+    ("live_train_positions", "/features"),
+    ("movements", "/movements"),
+    ("platform_occupancy", "/occupations"),
+    ("train_calendar", "/days"),
+    ("train_history", "/changes"),
+];
+
+// whether the route has a query parameter of its own by that name, e.g. /locations/search's limit
+fn route_takes(request: &Request<'_>, name: &str) -> bool {
+    match request.route().and_then(|x| x.uri.unmounted_origin.query()) {
+        Some(x) => x.as_str().contains(&format!("<{}>", name)),
+        None => false,
+    }
+}
+
+// What a response was made from last changed, for ETags and cursors: the generation of the one
+// namespace it read, if there's just one, otherwise of all of them
+async fn response_generation(request: &Request<'_>) -> u64 {
+    match request.guard::<Schedules>().await {
+        Outcome::Success(x) => match namespace_read(request) {
+            Some(namespace) => x.namespace_generation(&namespace),
+            None => x.generation(),
+        },
+        _ => 0,
+    }
+}
+
+// ?fields=, ?limit= and ?cursor= on every JSON response, as in paging.rs, with the next page in a
+// Link header, except where the route takes them itself. This goes after Redacting, so pages only
+// count what's shown, and before OutputFormatting, so field names are as documented.
+struct Listing;
+
+#[rocket::async_trait]
+impl Fairing for Listing {
+    fn info(&self) -> Info {
+        Info {
+            name: "Paging and field selection",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if response.content_type() != Some(ContentType::JSON) || response.status() != Status::Ok {
+            return;
+        }
+        let get = |name: &str| match request.query_value::<&str>(name) {
+            Some(Ok(x)) if !route_takes(request, name) => Some(x),
+            _ => None,
+        };
+        let paging = match Paging::parse(get("cursor"), get("limit"), get("fields")) {
+            Ok(Some(x)) => x,
+            Ok(None) => return,
+            Err(x) => {
+                response.set_status(Status::BadRequest);
+                response.set_sized_body(x.len(), Cursor::new(x));
+                return;
+            }
+        };
+        let list = request
+            .route()
+            .and_then(|x| x.name.as_deref())
+            .and_then(|x| LISTS.iter().find(|(name, _)| *name == x))
+            .map(|(_, list)| *list);
+        let generation = match paging.after.is_some() || paging.limit.is_some() {
+            true => response_generation(request).await,
+            false => 0,
+        };
+        let body = match response.body_mut().to_string().await {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        let body = match serde_json::from_str::<serde_json::Value>(&body) {
+            Ok(mut x) => match paging.apply(&mut x, list, generation) {
+                Ok(Some(cursor)) => {
+                    let link = format!("<{}>; rel=\"next\"", next_page_uri(request, &cursor));
+                    response.set_raw_header("Link", link);
+                    x.to_string()
+                }
+                Ok(None) => x.to_string(),
+                Err(x) => {
+                    response.set_status(Status::BadRequest);
+                    x
+                }
+            },
+            Err(_) => body,
+        };
+        response.set_sized_body(body.len(), Cursor::new(body));
+    }
+}
+
+// The one namespace a response was made from, if its route takes one, as a <namespace> in the path
+// or a ?namespace=. Anything else may have read every namespace.
+fn namespace_read(request: &Request<'_>) -> Option<String> {
+    let route = request.route()?.uri.unmounted_origin.clone();
+    let segment = route.path().segments().position(|x| x == "<namespace>");
+    let namespace = match segment {
+        Some(x) => request.routed_segment(x)?,
+        None if route.query()?.as_str().contains("<namespace>") => {
+            request.query_value::<&str>("namespace")?.ok()?
+        }
+        None => return None,
+    };
+    // e.g. gbnr-public
+    Some(namespace.split('-').next().unwrap_or(namespace).to_string())
+}
+
+// CORS and caching headers, as in response_headers.rs. This goes last, so ETags are of what's
+// actually sent. Streamed responses don't get one, as that would mean holding the whole thing.
+struct Heading;
+
+#[rocket::async_trait]
+impl Fairing for Heading {
+    fn info(&self) -> Info {
+        Info {
+            name: "CORS and caching headers",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let headers = match request.rocket().state::<ResponseHeaders>() {
+            Some(x) => x,
+            None => return,
+        };
+        let path = request.uri().path().as_str();
+        match request.headers().get_one("Origin") {
+            Some(origin) => {
+                let preflight = request.method() == Method::Options;
+                for (name, value) in headers.cors(path, origin, preflight) {
+                    response.set_raw_header(name, value);
+                }
+            }
+            None => (),
+        }
+        if request.method() != Method::Get || response.status() != Status::Ok {
+            return;
+        }
+        // everything that can change what comes back for the same URL
+        response.set_raw_header(
+            "Vary",
+            "Accept, Accept-Language, Authorization, Origin, X-Environment",
+        );
+        let authorised = request.headers().contains("Authorization");
+        match headers.cache_control(path, authorised) {
+            Some(x) => {
+                response.set_raw_header("Cache-Control", x);
+            }
+            None => (),
+        }
+        if response.body().preset_size().is_none() {
+            return;
+        }
+        let generation = response_generation(request).await;
+        let body = match response.body_mut().to_bytes().await {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        let etag = match headers.etag(path, generation, &body) {
+            Some(x) => x,
+            None => {
+                response.set_sized_body(body.len(), Cursor::new(body));
+                return;
+            }
+        };
+        match request.headers().get_one("If-None-Match") {
+            Some(x) if etag_matches(x, &etag) => {
+                response.set_status(Status::NotModified);
+                response.set_sized_body(0, Cursor::new(vec![]));
+            }
+            _ => response.set_sized_body(body.len(), Cursor::new(body)),
+        }
+        response.set_raw_header("ETag", etag);
+    }
+}
+
 pub struct NaiveDateRocket(NaiveDate);
 
 impl<'a> FromParam<'a> for NaiveDateRocket {
@@ -61,6 +640,17 @@ impl<'a> FromParam<'a> for NaiveDateRocket {
     }
 }
 
+// the same, as a query parameter
+#[rocket::async_trait]
+impl<'v> FromFormField<'v> for NaiveDateRocket {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        match NaiveDate::parse_from_str(field.value, "%Y-%m-%d") {
+            Ok(date) => Ok(NaiveDateRocket(date)),
+            Err(_) => Err(form::Error::validation("Dates are YYYY-MM-DD"))?,
+        }
+    }
+}
+
 pub struct NaiveTimeRocket(NaiveTime);
 
 impl<'a> FromParam<'a> for NaiveTimeRocket {
@@ -106,57 +696,8 @@ fn convert_tz(
     Ok(Some(output_time_tz.time()))
 }
 
-fn get_train_instance(trains: &Vec<Train>, date: NaiveDate) -> (Option<Train>, bool, bool) {
-    // let's make life easy and find the right train
-    let mut final_train = None;
-    let mut cancelled = false;
-    let mut modified = false;
-    for train in trains {
-        for validity in &train.validity {
-            if validity.valid_begin.date_naive() <= date
-                && validity.valid_end.date_naive() >= date
-                && validity.days_of_week.get_by_weekday(date.weekday())
-            {
-                cancelled = false;
-                modified = false;
-                'replacement: for replacement in &train.replacements {
-                    for validity in &replacement.validity {
-                        if validity.valid_begin.date_naive() <= date
-                            && validity.valid_end.date_naive() >= date
-                            && validity.days_of_week.get_by_weekday(date.weekday())
-                        {
-                            final_train = Some(replacement.clone());
-                            modified = true;
-                            break 'replacement;
-                        }
-                    }
-                }
-                if final_train.is_none() {
-                    final_train = Some(train.clone());
-                }
-                for (cancellation, _source) in &train.cancellations {
-                    if cancellation.valid_begin.date_naive() <= date
-                        && cancellation.valid_end.date_naive() >= date
-                        && cancellation.days_of_week.get_by_weekday(date.weekday())
-                    {
-                        cancelled = true;
-                    }
-                }
-            }
-        }
-    }
-
-    return (final_train, cancelled, modified);
-}
-
-#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
-enum AssociationCategory {
-    Join,
-    Divide,
-    Next,
-    IsJoinedToBy,
-    DividesFrom,
-    FormsFrom,
+fn get_realtime(schedule: &Schedule, train_id: &str, date: NaiveDate) -> Option<TrainRealtime> {
+    schedule.realtime.get(train_id)?.get(&date).cloned()
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -171,228 +712,97 @@ struct BasicAssocTrainDetails {
     category: AssociationCategory,
     name: Option<String>,
     dep_time: NaiveTime,
+    running_days: String, // of the association, which can be less often than either train
+    source: Option<TrainSource>,
+    modified: bool,
 }
 
-fn get_association(assoc: &AssociationNode, date: NaiveDate) -> Option<AssociationNode> {
-    let mut final_assoc = None;
-    let mut cancelled = false;
-    for validity in &assoc.validity {
-        if validity.valid_begin.date_naive() <= date
-            && validity.valid_end.date_naive() >= date
-            && validity.days_of_week.get_by_weekday(date.weekday())
-        {
-            cancelled = false;
-            'replacement: for replacement in &assoc.replacements {
-                for validity in &replacement.validity {
-                    if validity.valid_begin.date_naive() <= date
-                        && validity.valid_end.date_naive() >= date
-                        && validity.days_of_week.get_by_weekday(date.weekday())
-                    {
-                        final_assoc = Some(replacement.clone());
-                        break 'replacement;
-                    }
-                }
-            }
-            if final_assoc.is_none() {
-                final_assoc = Some(assoc.clone());
-            }
-            for (cancellation, _source) in &assoc.cancellations {
-                if cancellation.valid_begin.date_naive() <= date
-                    && cancellation.valid_end.date_naive() >= date
-                    && cancellation.days_of_week.get_by_weekday(date.weekday())
-                {
-                    cancelled = true;
-                }
-            }
-        }
-    }
-
-    if final_assoc.is_none() || cancelled {
-        None
-    } else {
-        final_assoc
-    }
-}
-
-fn add_associated_train(
-    associations: &mut Vec<(
-        String,
-        i8,
-        bool,
-        String,
-        Option<String>,
-        AssociationCategory,
-    )>,
-    assoc: &AssociationNode,
-    date: NaiveDate,
-    location: &String,
-    location_suffix: &Option<String>,
-    category: AssociationCategory,
-) -> () {
-    let final_assoc = match get_association(assoc, date) {
-        Some(x) => x,
-        None => return,
-    };
-
-    associations.push((
-        final_assoc.other_train_id,
-        final_assoc.day_diff,
-        final_assoc.for_passengers,
-        location.clone(),
-        location_suffix.clone(),
-        category,
-    ));
-}
-
-fn add_associated_trains(
-    associations: &mut Vec<(
-        String,
-        i8,
-        bool,
-        String,
-        Option<String>,
-        AssociationCategory,
-    )>,
-    assoc_vec: &Vec<AssociationNode>,
-    date: NaiveDate,
-    location: &String,
-    location_suffix: &Option<String>,
-    category: AssociationCategory,
-) -> () {
-    for assoc in assoc_vec {
-        add_associated_train(
-            associations,
-            &assoc,
-            date,
-            location,
-            location_suffix,
-            category,
-        );
-    }
-}
-
-#[get("/train/<namespace>/<train_id>/<date>")]
+// after /train/<train_id>/<date>/geometry and /history, which look the same to Rocket
+#[get("/train/<namespace>/<train_id>/<date>", rank = 1)]
 fn train(
     namespace: &str,
     train_id: &str,
     date: NaiveDateRocket,
-    schedule_manager: &State<Arc<ScheduleManager>>,
+    schedule_manager: Schedules,
+    localiser: Localiser,
+    dedup: &State<Arc<Duplicates>>,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
 ) -> Option<Template> {
-    let (trains, locations, schedule_desc) = {
+    let date = date.0;
+
+    let (resolved, mut locations, schedule_desc) = {
         let schedule_manager = schedule_manager.read();
         let schedule = &schedule_manager.get(namespace)?;
-        let train = schedule.trains.get(train_id)?;
         (
-            train.clone(),
+            resolve_train(schedule, train_id, date)?,
             schedule.locations.clone(),
             schedule.description.clone(),
         )
     };
+    let ResolvedTrain {
+        mut train,
+        cancelled,
+        cancellation_reason,
+        modified,
+        realtime,
+        associations,
+        ..
+    } = resolved;
 
-    let date = date.0;
-
-    let (final_train, cancelled, modified) = get_train_instance(&trains, date);
-
-    let mut train = final_train?;
-    let mut associations: Vec<(
-        String,
-        i8,
-        bool,
-        String,
-        Option<String>,
-        AssociationCategory,
-    )> = Vec::new();
-    for location in &train.route {
-        add_associated_trains(
-            &mut associations,
-            &location.divides_to_form,
-            date,
-            &location.id,
-            &location.id_suffix,
-            AssociationCategory::Divide,
-        );
-        add_associated_trains(
-            &mut associations,
-            &location.joins_to,
-            date,
-            &location.id,
-            &location.id_suffix,
-            AssociationCategory::Join,
-        );
-        add_associated_trains(
-            &mut associations,
-            &location.divides_from,
-            date,
-            &location.id,
-            &location.id_suffix,
-            AssociationCategory::DividesFrom,
-        );
-        add_associated_trains(
-            &mut associations,
-            &location.is_joined_to_by,
-            date,
-            &location.id,
-            &location.id_suffix,
-            AssociationCategory::IsJoinedToBy,
-        );
-        match &location.becomes {
-            Some(x) => add_associated_train(
-                &mut associations,
-                &x,
-                date,
-                &location.id,
-                &location.id_suffix,
-                AssociationCategory::Next,
-            ),
-            None => (),
-        }
-        match &location.forms_from {
-            Some(x) => add_associated_train(
-                &mut associations,
-                &x,
-                date,
-                &location.id,
-                &location.id_suffix,
-                AssociationCategory::FormsFrom,
-            ),
-            None => (),
-        }
+    let (mut duplicates, through) = {
+        let schedule_manager = schedule_manager.read();
+        let resolve = |x: &Vec<Train>| get_train_instance(x, date).0;
+        (
+            dedup.find(&schedule_manager, namespace, &train, date, resolve),
+            dedup.through_service(&schedule_manager, namespace, &train, date, resolve),
+        )
+    };
+    // the other legs of a through train aren't copies of it
+    match &through {
+        Some(x) => duplicates.retain(|y| !x.legs.contains(y)),
+        None => (),
     }
 
     let mut assoc_train_details: HashMap<String, Vec<BasicAssocTrainDetails>> = HashMap::new();
-    for (train_id, day_diff, is_public, location_id, location_suffix, category) in &associations {
+    for association in &associations {
         let trains = {
             let schedule_manager = schedule_manager.read();
             schedule_manager
                 .get(namespace)
                 .unwrap()
                 .trains
-                .get(train_id)?
+                .get(&association.train_id)?
                 .clone()
         };
-        let other_date = if *day_diff >= 0 {
-            date.add(Days::new(u64::try_from(*day_diff).unwrap()))
-        } else {
-            date.sub(Days::new(u64::try_from(-*day_diff).unwrap()))
-        };
-        let train = get_train_instance(&trains, other_date).0?;
+        let other_date = association.date;
+        let location_id = association.location_id.as_str();
+        let (train, _, modified) = get_train_instance(&trains, other_date);
+        let train = train?;
         assoc_train_details
-            .entry(location_id.clone() + "|" + &location_suffix.as_ref().unwrap_or(&"".to_string()))
+            .entry(
+                location_id.to_string()
+                    + "|"
+                    + association.location_suffix.as_deref().unwrap_or(""),
+            )
             .or_insert(vec![])
             .push(BasicAssocTrainDetails {
                 id: train.id.clone(),
                 public_id: train.variable_train.public_id.clone(),
-                origin_id: train.route.first().unwrap().id.clone(),
-                destination_id: train.route.last().unwrap().id.clone(),
+                origin_id: train.route.first().unwrap().id.to_string(),
+                destination_id: train.route.last().unwrap().id.to_string(),
                 date: other_date.clone(),
                 namespace: namespace.to_string(),
-                is_public: *is_public,
-                category: *category,
+                is_public: association.for_passengers,
+                category: association.category,
+                running_days: association.running_days.clone(),
                 name: train.variable_train.name.clone(),
+                source: train.source,
+                modified,
                 dep_time: if train.route[0].public_dep.is_none() {
                     convert_tz(
                         &other_date,
-                        &Some(0),
+                        &train.route[0].working_dep_day,
                         &train.route[0].working_dep,
                         &train.route[0].timing_tz,
                         &locations.get(location_id).unwrap().timezone,
@@ -402,7 +812,7 @@ fn train(
                 } else {
                     convert_tz(
                         &other_date,
-                        &Some(0),
+                        &train.route[0].public_dep_day,
                         &train.route[0].public_dep,
                         &train.route[0].timing_tz,
                         &locations.get(location_id).unwrap().timezone,
@@ -431,7 +841,7 @@ fn train(
             &location.working_arr_day,
             &location.working_arr,
             &location.timing_tz,
-            &locations.get(&location.id).unwrap().timezone,
+            &locations.get(location.id.as_str()).unwrap().timezone,
         )
         .ok()?;
         location.working_dep = convert_tz(
@@ -439,7 +849,7 @@ fn train(
             &location.working_dep_day,
             &location.working_dep,
             &location.timing_tz,
-            &locations.get(&location.id).unwrap().timezone,
+            &locations.get(location.id.as_str()).unwrap().timezone,
         )
         .ok()?;
         location.working_pass = convert_tz(
@@ -447,7 +857,7 @@ fn train(
             &location.working_pass_day,
             &location.working_pass,
             &location.timing_tz,
-            &locations.get(&location.id).unwrap().timezone,
+            &locations.get(location.id.as_str()).unwrap().timezone,
         )
         .ok()?;
         location.public_arr = convert_tz(
@@ -455,7 +865,7 @@ fn train(
             &location.public_arr_day,
             &location.public_arr,
             &location.timing_tz,
-            &locations.get(&location.id).unwrap().timezone,
+            &locations.get(location.id.as_str()).unwrap().timezone,
         )
         .ok()?;
         location.public_dep = convert_tz(
@@ -463,1020 +873,4154 @@ fn train(
             &location.public_dep_day,
             &location.public_dep,
             &location.timing_tz,
-            &locations.get(&location.id).unwrap().timezone,
+            &locations.get(location.id.as_str()).unwrap().timezone,
         )
         .ok()?;
     }
 
-    let context = context! {
+    localiser.localise_locations(namespace, &mut locations);
+    localiser.localise_operator(namespace, &mut train.variable_train.operator);
+    for location in train.route.iter_mut() {
+        match &mut location.change_en_route {
+            Some(x) => localiser.localise_operator(namespace, &mut x.operator),
+            None => (),
+        }
+    }
+
+    let mut context = serde_json::to_value(context! {
+        alerts: alerts.for_train(namespace, &train),
         train,
         locations,
         cancelled,
+        cancellation_reason,
         modified,
         namespace: namespace.to_string(),
         dates,
         schedule_desc,
         assoc_train_details,
-    };
+        realtime,
+        duplicates,
+        through,
+    })
+    .unwrap();
+    // Redacting only gets into JSON, so pages do it themselves; a train that's hidden altogether
+    // isn't there
+    match &redacted.0 {
+        Some(x) if !x.apply_to_page(&mut context["train"]) => return None,
+        Some(x) => {
+            x.apply_to_page(&mut context);
+        }
+        None => (),
+    }
 
     Some(Template::render("train", &context))
 }
 
+// A call as in resolved_train, with what a passenger-facing client wants alongside
 #[derive(Clone, Debug, Serialize)]
-struct BasicTrainForLocation {
-    id: String,
+struct ResolvedServiceLocation {
+    #[serde(flatten)]
+    call: ResolvedCall,
+    name: Option<String>,
     public_id: Option<String>,
-    origins: Vec<String>,
-    destinations: Vec<String>,
-    working_arr: Option<NaiveDateTime>,
-    working_dep: Option<NaiveDateTime>,
-    working_pass: Option<NaiveDateTime>,
-    public_arr: Option<NaiveDateTime>,
-    public_dep: Option<NaiveDateTime>,
-    platform: Option<String>,
-    platform_zone: Option<String>,
-    modified: bool,
-    cancelled: bool,
-    source: Option<TrainSource>,
-    runs_as_required: bool,
-    operator: Option<TrainOperator>,
-    name: Option<String>,
+    mode: TransportMode, // can change en route, e.g. a rail leg followed by a bus
+    dwell_secs: Option<i64>, // only where the train both arrives and departs
+    facilities: Facilities, // as of this location, after any change en route
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct ResolvedService {
+    global_id: GlobalTrainId,
     namespace: String,
-    date: NaiveDate,
-    is_first: bool,
-    is_last: bool,
-    cur_found_tos: usize,
+    id: String,
+    date: NaiveDate,             // the operating date, which is what identifies it
+    departure_date: NaiveDate,   // the day after date for a service timetabled past midnight
+    source: Option<TrainSource>, // which variant actually runs: LTP base, STP overlay or VSTP
+    modified: bool,              // an overlay applies on this date
+    cancelled: bool,             // including partway through the journey
+    cancellation_reason: Option<String>,
+    runs_as_required: bool, // a Q path, which only runs when it's needed
+    runs_to_locations_as_required: bool, // from the origin
+    branding: Option<Branding>, // from the origin
+    running_days: Option<String>, // e.g. "Mondays to Fridays until 12 December, not 25 November"
+    mode: TransportMode,
+    variable_train: VariableTrain,
+    facilities: Facilities,      // from the origin
+    segments: Vec<RouteSegment>, // split wherever variable_train changes en route
+    route: Vec<ResolvedServiceLocation>,
+    realtime: Option<TrainRealtime>,
+    duplicates: Vec<TrainRef>,       // the same train in other namespaces
+    duplicate_of: Option<TrainRef>,  // the copy from a more trusted namespace, if there is one
+    through: Option<ThroughService>, // if this is one leg of a train split between namespaces
+    alerts: Vec<Alert>,
 }
 
-fn get_origins(
-    i: usize,
-    location: &TrainLocation,
-    schedule_manager: Arc<ScheduleManager>,
+// Only the calls passengers can use, unless all_locations is set, which also brings back passing
+// points and operational stops
+#[get("/service/<train_id>/<date>?<all_locations>")]
+fn service(
+    train_id: &str,
+    date: NaiveDateRocket,
+    all_locations: Option<bool>,
+    schedule_manager: Schedules,
+    localiser: Localiser,
+    dedup: &State<Arc<Duplicates>>,
+    branding: &State<OperatorBranding>,
+    alerts: &State<Alerts>,
+) -> Option<Json<ResolvedService>> {
+    resolve_service(
+        train_id,
+        date.0,
+        None,
+        all_locations,
+        &schedule_manager,
+        &localiser,
+        dedup,
+        branding,
+        alerts,
+    )
+    .map(Json)
+}
+
+// The same, by an ID as given out in global_id, which says which namespace to look in
+#[get("/service/<global_id>?<all_locations>")]
+fn service_by_global_id(
+    global_id: &str,
+    all_locations: Option<bool>,
+    schedule_manager: Schedules,
+    localiser: Localiser,
+    dedup: &State<Arc<Duplicates>>,
+    branding: &State<OperatorBranding>,
+    alerts: &State<Alerts>,
+) -> Option<Json<ResolvedService>> {
+    let global_id = GlobalTrainId::parse(global_id)?;
+    resolve_service(
+        &global_id.train_id,
+        global_id.date,
+        Some(&global_id.namespace),
+        all_locations,
+        &schedule_manager,
+        &localiser,
+        dedup,
+        branding,
+        alerts,
+    )
+    .map(Json)
+}
+
+fn resolve_service(
+    train_id: &str,
     date: NaiveDate,
-    namespace: &str,
-) -> Vec<String> {
-    let mut origins = vec![];
+    only_namespace: Option<&str>,
+    all_locations: Option<bool>,
+    schedule_manager: &Schedules,
+    localiser: &Localiser,
+    dedup: &Duplicates,
+    branding: &OperatorBranding,
+    alerts: &Alerts,
+) -> Option<ResolvedService> {
+    // UIDs don't come with a namespace, so take the most trusted one that has this train on this date
+    let (mut resolved, mut locations, duplicates, through, running_days) = {
+        let schedule_manager = schedule_manager.read();
+        let namespaces = dedup.namespace_order(
+            schedule_manager
+                .keys()
+                .filter(|x| only_namespace.map_or(true, |y| *x == y)),
+        );
+        namespaces.into_iter().find_map(|namespace| {
+            let schedule = schedule_manager.get(namespace).unwrap();
+            let versions = schedule.trains.get(train_id)?;
+            let resolved = resolve_train(schedule, train_id, date)?;
+            let locations = resolved
+                .route
+                .iter()
+                .filter_map(|x| {
+                    Some((
+                        x.id.to_string(),
+                        schedule.locations.get(x.id.as_str())?.clone(),
+                    ))
+                })
+                .collect::<PartitionedMap<_>>();
+            let resolve = |x: &Vec<Train>| get_train_instance(x, date).0;
+            let mut duplicates =
+                dedup.find(&schedule_manager, namespace, &resolved.train, date, resolve);
+            let through =
+                dedup.through_service(&schedule_manager, namespace, &resolved.train, date, resolve);
+            match &through {
+                Some(x) => duplicates.retain(|y| !x.legs.contains(y)),
+                None => (),
+            }
+            // of the base schedule, as overlays only ever cover part of it
+            let running_days = get_train_version(versions, date).map(|(base, _, _)| {
+                running_days_text(
+                    &versions[base].validity,
+                    &versions[base].cancellations,
+                    date,
+                )
+            });
+            Some((resolved, locations, duplicates, through, running_days))
+        })?
+    };
+    let namespace = resolved.namespace.clone();
 
-    if i == 0 {
-        let mut found_origin = false;
-        for assoc in &location.divides_from {
-            let final_assoc = match get_association(assoc, date) {
-                Some(x) => x,
-                None => continue,
-            };
+    localiser.localise_locations(&namespace, &mut locations);
+    localiser.localise_operator(&namespace, &mut resolved.variable_train.operator);
+    for segment in resolved.segments.iter_mut() {
+        localiser.localise_operator(&namespace, &mut segment.variable_train.operator);
+    }
 
-            let trains = {
-                let schedule_manager = schedule_manager.read();
-                match schedule_manager
-                    .get(namespace)
-                    .unwrap()
-                    .trains
-                    .get(&final_assoc.other_train_id)
-                {
-                    Some(x) => x.clone(),
-                    None => continue,
-                }
-            };
+    let mut route = vec![];
+    for call in &resolved.route {
+        if !call.flags.public && !all_locations.unwrap_or(false) {
+            continue;
+        }
+        let variable_train = resolved.variable_train_at(call);
+        route.push(ResolvedServiceLocation {
+            call: call.clone(),
+            name: locations
+                .get(call.id.as_str())
+                .filter(|x| !x.unverified)
+                .map(|x| x.name.clone()),
+            public_id: locations
+                .get(call.id.as_str())
+                .and_then(|x| x.public_id.clone()),
+            mode: variable_train.train_type.mode(),
+            dwell_secs: call.times.dwell_secs(),
+            facilities: variable_train.facilities(),
+        });
+    }
 
-            let other_date = if final_assoc.day_diff >= 0 {
-                date.add(Days::new(u64::try_from(final_assoc.day_diff).unwrap()))
-            } else {
-                date.sub(Days::new(u64::try_from(-final_assoc.day_diff).unwrap()))
-            };
-            let (train, cancelled, _modified) = get_train_instance(&trains, other_date);
+    let duplicate_of = dedup.preferred(&namespace, &duplicates).cloned();
+    let branding = branding.for_train(&namespace, &resolved.variable_train);
+    let alerts = alerts.for_train(&namespace, &resolved.train);
+    let ResolvedTrain {
+        global_id,
+        id,
+        date,
+        departure_date,
+        source,
+        modified,
+        cancelled,
+        cancellation_reason,
+        runs_as_required,
+        variable_train,
+        segments,
+        realtime,
+        ..
+    } = resolved;
+    Some(ResolvedService {
+        global_id,
+        namespace,
+        id,
+        date,
+        departure_date,
+        source,
+        modified,
+        cancelled,
+        cancellation_reason,
+        runs_as_required,
+        runs_to_locations_as_required: variable_train.runs_to_locations_as_required(),
+        branding,
+        running_days,
+        mode: variable_train.train_type.mode(),
+        facilities: variable_train.facilities(),
+        variable_train,
+        segments,
+        route,
+        realtime,
+        duplicate_of,
+        duplicates,
+        through,
+        alerts,
+    })
+}
 
-            if cancelled || train.is_none() {
-                continue;
-            }
+// GeoJSON for drawing the train on a map. Like /service, this takes the first namespace that has
+// the train on this date.
+#[get("/train/<train_id>/<date>/geometry")]
+fn train_geometry(
+    train_id: &str,
+    date: NaiveDateRocket,
+    schedule_manager: Schedules,
+    localiser: Localiser,
+    route_geometry: &State<RouteGeometry>,
+    dedup: &State<Arc<Duplicates>>,
+) -> Option<Json<serde_json::Value>> {
+    resolve_geometry(
+        train_id,
+        date.0,
+        None,
+        &schedule_manager,
+        &localiser,
+        route_geometry,
+        dedup,
+    )
+    .map(Json)
+}
 
-            found_origin = true;
+#[get("/train/<global_id>/geometry")]
+fn train_geometry_by_global_id(
+    global_id: &str,
+    schedule_manager: Schedules,
+    localiser: Localiser,
+    route_geometry: &State<RouteGeometry>,
+    dedup: &State<Arc<Duplicates>>,
+) -> Option<Json<serde_json::Value>> {
+    let global_id = GlobalTrainId::parse(global_id)?;
+    resolve_geometry(
+        &global_id.train_id,
+        global_id.date,
+        Some(&global_id.namespace),
+        &schedule_manager,
+        &localiser,
+        route_geometry,
+        dedup,
+    )
+    .map(Json)
+}
 
-            for (i, other_location) in train.as_ref().unwrap().route.iter().enumerate() {
-                if location.id == other_location.id
-                    && assoc.other_train_location_id_suffix == other_location.id_suffix
-                {
-                    break;
-                }
+fn resolve_geometry(
+    train_id: &str,
+    date: NaiveDate,
+    only_namespace: Option<&str>,
+    schedule_manager: &Schedules,
+    localiser: &Localiser,
+    route_geometry: &RouteGeometry,
+    dedup: &Duplicates,
+) -> Option<serde_json::Value> {
+    let schedule_manager = schedule_manager.read();
+    let namespaces = dedup.namespace_order(
+        schedule_manager
+            .keys()
+            .filter(|x| only_namespace.map_or(true, |y| *x == y)),
+    );
+    namespaces.into_iter().find_map(|namespace| {
+        let schedule = schedule_manager.get(namespace).unwrap();
+        let (train, _, _) = get_train_instance(schedule.trains.get(train_id)?, date);
+        let train = train?;
+        let geometry = route_geometry.stitch(schedule, &train)?;
+
+        let mut locations = geometry
+            .locations
+            .iter()
+            .filter_map(|(id, _)| Some((id.clone(), schedule.locations.get(id)?.clone())))
+            .collect::<PartitionedMap<_>>();
+        localiser.localise_locations(namespace, &mut locations);
+        let names = locations
+            .into_iter()
+            .map(|(id, location)| (id, location.name))
+            .collect::<HashMap<_, _>>();
+
+        Some(geometry.to_geojson(
+            serde_json::json!({
+                "global_id": GlobalTrainId::new(namespace, &train.id, date),
+                "namespace": namespace,
+                "id": train.id,
+                "date": date,
+            }),
+            &names,
+        ))
+    })
+}
 
-                origins.append(&mut get_origins(
-                    i,
-                    other_location,
-                    schedule_manager.clone(),
-                    other_date,
-                    namespace,
-                ));
-            }
-        }
-        if !found_origin {
-            origins.push(location.id.clone());
-        }
+// Where trains with live running data probably are now, as GeoJSON points, optionally only within
+// a bounding box given as west,south,east,north
+#[get("/live/positions?<bbox>")]
+fn live_train_positions(
+    bbox: Option<&str>,
+    schedule_manager: Schedules,
+    route_geometry: &State<RouteGeometry>,
+) -> Result<Json<serde_json::Value>, Status> {
+    let bbox = match bbox {
+        Some(x) => Some(BoundingBox::parse(x).ok_or(Status::BadRequest)?),
+        None => None,
+    };
+    let now = Utc::now();
+    let schedule_manager = schedule_manager.read();
+    let mut positions = vec![];
+    for schedule in schedule_manager.values() {
+        positions.extend(estimate_positions(
+            schedule,
+            route_geometry,
+            now,
+            bbox.as_ref(),
+        ));
     }
+    Ok(Json(positions_to_geojson(&positions)))
+}
 
-    for assoc in &location.joins_to {
-        let final_assoc = match get_association(&assoc, date) {
+#[derive(Clone, Debug, Serialize)]
+struct FreightTrainLocation {
+    id: IStr,
+    id_suffix: Option<String>,
+    name: Option<String>,
+    #[serde(flatten)]
+    times: LocalTimes,
+    platform: Option<IStr>,
+    line: Option<IStr>,
+    path: Option<IStr>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct FreightTrain {
+    global_id: GlobalTrainId,
+    namespace: String,
+    id: String,
+    date: NaiveDate,
+    headcode: Option<String>,
+    train_type: TrainType,
+    service_group: Option<String>,
+    operator: Option<TrainOperator>,
+    power_type: Option<TrainPower>,
+    timing_speed_m_per_s: Option<f64>,
+    operating_characteristics: Option<OperatingCharacteristics>,
+    consist: Option<TrainAllocation>, // wagon by wagon, where we've been told
+    runs_as_required: bool,
+    source: Option<TrainSource>,
+    modified: bool,
+    cancelled: bool,
+    route: Vec<FreightTrainLocation>,
+}
+
+#[get("/freight/<namespace>/<date>?<origin>&<destination>&<location>&<source>")]
+fn freight(
+    namespace: &str,
+    date: NaiveDateRocket,
+    origin: Option<&str>,
+    destination: Option<&str>,
+    location: Option<&str>,
+    source: Option<TrainSources>,
+    schedule_manager: Schedules,
+) -> Option<Json<Vec<FreightTrain>>> {
+    let date = date.0;
+    // snapshot, so we're not holding the lock while we look through every train there is
+    let schedule = schedule_manager.get(namespace)?;
+
+    let mut freight_trains = vec![];
+    for trains in schedule.trains.values() {
+        let (train, cancelled, modified) = get_train_instance(trains, date);
+        let train = match train {
             Some(x) => x,
             None => continue,
         };
+        if !train.variable_train.train_type.is_freight()
+            || train.route.is_empty()
+            || !includes_source(&source, train.source)
+        {
+            continue;
+        }
+        match origin {
+            Some(x) if train.route.first().unwrap().id != x => continue,
+            _ => (),
+        }
+        match destination {
+            Some(x) if train.route.last().unwrap().id != x => continue,
+            _ => (),
+        }
+        match location {
+            Some(x) if !train.route.iter().any(|y| y.id == x) => continue,
+            _ => (),
+        }
 
-        let trains = {
-            let schedule_manager = schedule_manager.read();
-            match schedule_manager
-                .get(namespace)
-                .unwrap()
-                .trains
-                .get(&final_assoc.other_train_id)
-            {
-                Some(x) => x.clone(),
-                None => continue,
-            }
+        // Freight goes through plenty of TIPLOCs we have no location for, and CIF gives no time zone
+        // of its own, so those are taken to be in the same one as the rest of the route
+        let location_tz = |x: &TrainLocation| {
+            schedule
+                .locations
+                .get(x.id.as_str())
+                .map(|y| y.timezone)
+                .or(x.timing_tz)
         };
-
-        let other_date = if final_assoc.day_diff >= 0 {
-            date.add(Days::new(u64::try_from(final_assoc.day_diff).unwrap()))
-        } else {
-            date.sub(Days::new(u64::try_from(-final_assoc.day_diff).unwrap()))
+        let route_tz = match train.route.iter().find_map(location_tz) {
+            Some(x) => x,
+            None => continue,
         };
-        let (train, cancelled, _modified) = get_train_instance(&trains, other_date);
-
-        if cancelled || train.is_none() {
-            continue;
+        let mut route = vec![];
+        for train_location in &train.route {
+            let location_tz = location_tz(train_location).unwrap_or(route_tz);
+            route.push(FreightTrainLocation {
+                id: train_location.id.clone(),
+                id_suffix: train_location.id_suffix.clone(),
+                name: schedule
+                    .locations
+                    .get(train_location.id.as_str())
+                    .map(|x| x.name.clone()),
+                times: train_location.local_times(date, location_tz),
+                platform: train_location.scheduled_platform.clone(),
+                line: train_location.line.clone(),
+                path: train_location.path.clone(),
+            });
         }
 
-        for (i, other_location) in train.as_ref().unwrap().route.iter().enumerate() {
-            if location.id == other_location.id
-                && assoc.other_train_location_id_suffix == other_location.id_suffix
-            {
-                break;
-            }
-
-            origins.append(&mut get_origins(
-                i,
-                other_location,
-                schedule_manager.clone(),
-                other_date,
-                namespace,
-            ));
-        }
+        let consist = schedule
+            .consists
+            .get(&train.id)
+            .and_then(|x| x.get(&date))
+            .cloned();
+        freight_trains.push(FreightTrain {
+            global_id: GlobalTrainId::new(namespace, &train.id, date),
+            namespace: namespace.to_string(),
+            id: train.id,
+            date,
+            headcode: train.variable_train.public_id,
+            train_type: train.variable_train.train_type,
+            service_group: train.variable_train.service_group,
+            operator: train.variable_train.operator,
+            power_type: train.variable_train.power_type,
+            timing_speed_m_per_s: train.variable_train.timing_speed_m_per_s,
+            operating_characteristics: train.variable_train.operating_characteristics,
+            consist,
+            runs_as_required: train.runs_as_required,
+            source: train.source,
+            modified,
+            cancelled,
+            route,
+        });
     }
 
-    origins
+    freight_trains.sort_by_key(|x| {
+        let first = x.route.first().unwrap();
+        first.times.working_dep.or(first.times.working_pass)
+    });
+
+    Some(Json(freight_trains))
 }
 
-fn get_destinations(
-    i: usize,
-    length: usize,
-    location: &TrainLocation,
-    schedule_manager: Arc<ScheduleManager>,
+#[derive(Clone, Debug, Serialize)]
+struct UicTrain {
+    global_id: GlobalTrainId,
+    namespace: String,
+    id: String,
     date: NaiveDate,
-    namespace: &str,
-) -> Vec<String> {
-    let mut destinations = vec![];
+    public_id: Option<String>,
+    operator: Option<TrainOperator>,
+    origin: Option<String>,
+    destination: Option<String>,
+    departure: Option<DateTime<Tz>>, // from the origin, in its time zone
+    source: Option<TrainSource>,
+    modified: bool,
+    cancelled: bool,
+}
 
-    if i == length - 1 {
-        let mut found_destination = false;
-        for assoc in &location.is_joined_to_by {
-            let final_assoc = match get_association(assoc, date) {
-                Some(x) => x,
+// For matching trains up with European datasets, which tend to go by UIC train number. The number
+// can be for only part of the journey, after a change en route.
+#[get("/uic/<uic_code>/<date>?<source>")]
+fn uic_trains(
+    uic_code: &str,
+    date: NaiveDateRocket,
+    source: Option<TrainSources>,
+    schedule_manager: Schedules,
+) -> Json<Vec<UicTrain>> {
+    let date = date.0;
+    let mut uic_trains = vec![];
+    let schedule_manager = schedule_manager.read();
+    for (namespace, schedule) in schedule_manager.iter().sorted_by_key(|x| x.0) {
+        let train_ids = match schedule.trains_indexed_by_uic.get(uic_code) {
+            Some(x) => x,
+            None => continue,
+        };
+        for train_id in train_ids.iter().sorted() {
+            let (train, cancelled, modified) = match schedule.trains.get(train_id) {
+                Some(x) => get_train_instance(x, date),
                 None => continue,
             };
-
-            let trains = {
-                let schedule_manager = schedule_manager.read();
-                match schedule_manager
-                    .get(namespace)
-                    .unwrap()
-                    .trains
-                    .get(&final_assoc.other_train_id)
-                {
-                    Some(x) => x.clone(),
-                    None => continue,
-                }
-            };
-
-            let other_date = if final_assoc.day_diff >= 0 {
-                date.add(Days::new(u64::try_from(final_assoc.day_diff).unwrap()))
-            } else {
-                date.sub(Days::new(u64::try_from(-final_assoc.day_diff).unwrap()))
+            let train = match train {
+                Some(x) if includes_source(&source, x.source) => x,
+                _ => continue,
             };
-            let (train, cancelled, _modified) = get_train_instance(&trains, other_date);
-
-            if cancelled || train.is_none() {
+            // the index isn't cleaned up when a train changes, so check it's still this one
+            if train.variable_train.uic_code.as_deref() != Some(uic_code)
+                && !train.route.iter().any(|x| {
+                    x.change_en_route
+                        .as_ref()
+                        .is_some_and(|y| y.uic_code.as_deref() == Some(uic_code))
+                })
+            {
                 continue;
             }
-
-            found_destination = true;
-
-            let mut found = false;
-
-            for (i, other_location) in train.as_ref().unwrap().route.iter().enumerate() {
-                if location.id == other_location.id
-                    && assoc.other_train_location_id_suffix == other_location.id_suffix
-                {
-                    found = true;
-                    continue;
-                }
-
-                if !found {
-                    continue;
-                }
-
-                destinations.append(&mut get_destinations(
-                    i,
-                    train.as_ref().unwrap().route.len(),
-                    other_location,
-                    schedule_manager.clone(),
-                    other_date,
-                    namespace,
-                ));
-            }
-        }
-        if !found_destination {
-            destinations.push(location.id.clone());
+            let name = |x: Option<&TrainLocation>| {
+                x.and_then(|x| schedule.locations.get(x.id.as_str()))
+                    .map(|x| x.name.clone())
+            };
+            let departure = train.route.first().and_then(|x| {
+                let location = schedule.locations.get(x.id.as_str())?;
+                let times = x.local_times(date, location.timezone);
+                times.public_dep.or(times.working_dep)
+            });
+            uic_trains.push(UicTrain {
+                global_id: GlobalTrainId::new(namespace, &train.id, date),
+                namespace: namespace.clone(),
+                id: train.id.clone(),
+                date,
+                public_id: train.variable_train.public_id.clone(),
+                operator: train.variable_train.operator.clone(),
+                origin: name(train.route.first()),
+                destination: name(train.route.last()),
+                departure,
+                source: train.source,
+                modified,
+                cancelled,
+            });
         }
     }
+    Json(uic_trains)
+}
 
-    for assoc in &location.divides_to_form {
-        let final_assoc = match get_association(assoc, date) {
-            Some(x) => x,
-            None => continue,
-        };
+#[derive(Clone, Debug, Serialize)]
+struct CalendarVariant {
+    base: usize,            // which of the train's base schedules
+    overlay: Option<usize>, // which of that one's replacements, if one applies
+    source: Option<TrainSource>,
+    public_id: Option<String>,
+    runs_as_required: bool,
+}
 
-        let trains = {
-            let schedule_manager = schedule_manager.read();
-            match schedule_manager
-                .get(namespace)
-                .unwrap()
-                .trains
-                .get(&final_assoc.other_train_id)
-            {
-                Some(x) => x.clone(),
-                None => continue,
+#[derive(Clone, Debug, Serialize)]
+struct CalendarDay {
+    date: NaiveDate,
+    variant: Option<usize>, // into variants, or None if the train doesn't run
+    cancelled: bool,
+    cancellation_source: Option<TrainSource>,
+    cancellation_reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct TrainCalendar {
+    namespace: String,
+    id: String,
+    variants: Vec<CalendarVariant>, // only those that apply on at least one of the days
+    days: Vec<CalendarDay>,
+}
+
+// Which version of a train applies on each day of a range, for drawing a "runs on" calendar
+// without having to work through the validities and overlays client-side. At most a year or so.
+#[get("/train/<namespace>/<train_id>/calendar/<from>/<to>")]
+fn train_calendar(
+    namespace: &str,
+    train_id: &str,
+    from: NaiveDateRocket,
+    to: NaiveDateRocket,
+    schedule_manager: Schedules,
+) -> Option<Json<TrainCalendar>> {
+    let schedule = schedule_manager.get(namespace)?;
+    let trains = schedule.trains.get(train_id)?;
+    let to = std::cmp::min(to.0, from.0 + Days::new(400));
+
+    let mut variants: Vec<CalendarVariant> = vec![];
+    let mut days = vec![];
+    for date in from.0.iter_days().take_while(|x| *x <= to) {
+        let (base, overlay, cancelled) = match get_train_version(trains, date) {
+            Some(x) => x,
+            None => {
+                days.push(CalendarDay {
+                    date,
+                    variant: None,
+                    cancelled: false,
+                    cancellation_source: None,
+                    cancellation_reason: None,
+                });
+                continue;
             }
         };
-
-        let other_date = if final_assoc.day_diff >= 0 {
-            date.add(Days::new(u64::try_from(final_assoc.day_diff).unwrap()))
+        let variant = match variants
+            .iter()
+            .position(|x| x.base == base && x.overlay == overlay)
+        {
+            Some(x) => x,
+            None => {
+                let train = match overlay {
+                    Some(x) => &trains[base].replacements[x],
+                    None => &trains[base],
+                };
+                variants.push(CalendarVariant {
+                    base,
+                    overlay,
+                    source: train.source,
+                    public_id: train.variable_train.public_id.clone(),
+                    runs_as_required: train.runs_as_required,
+                });
+                variants.len() - 1
+            }
+        };
+        let cancellation = if cancelled {
+            trains.iter().rev().find_map(|x| get_cancellation(x, date))
         } else {
-            date.sub(Days::new(u64::try_from(-final_assoc.day_diff).unwrap()))
+            None
         };
-        let (train, cancelled, _modified) = get_train_instance(&trains, other_date);
+        days.push(CalendarDay {
+            date,
+            variant: Some(variant),
+            cancelled,
+            cancellation_source: cancellation.map(|x| x.source),
+            cancellation_reason: cancellation.and_then(|x| x.reason.clone()),
+        });
+    }
 
-        if cancelled || train.is_none() {
-            continue;
+    Some(Json(TrainCalendar {
+        namespace: namespace.to_string(),
+        id: train_id.to_string(),
+        variants,
+        days,
+    }))
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct TrainHistory {
+    global_id: GlobalTrainId,
+    namespace: String,
+    id: String,
+    date: NaiveDate,
+    changes: Vec<TrainChange>, // oldest first
+}
+
+// How realtime feeds have changed a train's plan for a date since startup, e.g. replaced by VSTP
+// and then cancelled, rather than only how it ended up. Trains VSTP has since deleted still have
+// theirs.
+#[get("/train/<train_id>/<date>/history")]
+fn train_history(
+    train_id: &str,
+    date: NaiveDateRocket,
+    schedule_manager: Schedules,
+    dedup: &State<Arc<Duplicates>>,
+) -> Option<Json<TrainHistory>> {
+    let change_log = schedule_manager.change_log();
+    let schedule_manager = schedule_manager.read();
+    let namespaces = dedup.namespace_order(schedule_manager.keys());
+    namespaces.into_iter().find_map(|namespace| {
+        let changes = change_log.history(namespace, train_id, date.0);
+        if changes.is_empty() && !schedule_manager[namespace].trains.contains_key(train_id) {
+            return None;
         }
+        Some(Json(TrainHistory {
+            global_id: GlobalTrainId::new(namespace, train_id, date.0),
+            namespace: namespace.to_string(),
+            id: train_id.to_string(),
+            date: date.0,
+            changes,
+        }))
+    })
+}
 
-        let mut found = false;
-        for (i, other_location) in train.as_ref().unwrap().route.iter().enumerate() {
-            if location.id == other_location.id
-                && assoc.other_train_location_id_suffix == other_location.id_suffix
-            {
-                found = true;
-                continue;
+#[derive(Clone, Debug, Serialize)]
+struct NextTrain {
+    global_id: GlobalTrainId,
+    namespace: String,
+    id: String,
+    date: NaiveDate, // the train starts on, which is the day before for most trains after midnight
+    public_id: Option<String>,
+    operator: Option<TrainOperator>,
+    from_id: IStr,
+    to_id: IStr,
+    departure: DateTime<Tz>,
+    arrival: DateTime<Tz>,
+    platform: Option<IStr>,
+    departure_flags: PassengerFlags, // e.g. whether it's a request stop
+    arrival_flags: PassengerFlags,
+    source: Option<TrainSource>,
+    modified: bool,
+    cancelled: bool,
+    runs_as_required: bool,
+    runs_to_locations_as_required: bool,
+    branding: Option<Branding>,
+}
+
+fn next_trains_in(
+    schedule: &Schedule,
+    from: &str,
+    to: &str,
+    now: DateTime<Utc>,
+    runs_as_required: bool,
+    sources: &Option<TrainSources>,
+    branding: &OperatorBranding,
+    station_groups: &StationGroups,
+    next_trains: &mut Vec<NextTrain>,
+) {
+    let from_ids = station_groups.location_ids(schedule, from);
+    let to_ids = station_groups.location_ids(schedule, to);
+    let trains_at = |ids: &HashSet<String>| {
+        ids.iter()
+            .filter_map(|x| schedule.trains_indexed_by_location.get(x))
+            .flatten()
+            .cloned()
+            .collect::<HashSet<_>>()
+    };
+    let to_trains = trains_at(&to_ids);
+
+    for train_id in trains_at(&from_ids).intersection(&to_trains) {
+        let versions = match schedule.trains.get(train_id) {
+            Some(x) if !x.is_empty() => x,
+            _ => continue,
+        };
+        // A train that left its origin yesterday can still be running now, so go back as many
+        // days as it runs over; a week ahead is plenty to find the next few.
+        let today = now.date_naive();
+        let mut date = today - Days::new(u64::from(max_day_offset(versions)) + 1);
+        while date <= today + Days::new(7) {
+            let (train, mut cancelled, modified) = get_train_instance(versions, date);
+            let train = match train {
+                Some(x)
+                    if (runs_as_required || !x.runs_as_required)
+                        && includes_source(sources, x.source) =>
+                {
+                    x
+                }
+                _ => {
+                    date = date + Days::new(1);
+                    continue;
+                }
+            };
+            match get_realtime(schedule, train_id, date).and_then(|x| x.cancellation) {
+                Some(x) if x.cancellation_type.as_deref() != Some("EN ROUTE") => cancelled = true,
+                _ => (),
             }
 
-            if !found {
-                continue;
+            // board at the first public call at `from`, alight at the first after it at `to`
+            let mut departure = None;
+            for location in &train.route {
+                let timezone = match schedule.locations.get(location.id.as_str()) {
+                    Some(x) => x.timezone,
+                    None => continue,
+                };
+                let times = location.local_times(date, timezone);
+                let flags = location.passenger_flags();
+                match departure {
+                    None if from_ids.contains(location.id.as_str())
+                        && flags.public
+                        && !flags.set_down_only =>
+                    {
+                        match times.public_dep {
+                            Some(x) => departure = Some((location, x, flags)),
+                            None => (),
+                        }
+                    }
+                    Some((from_location, departure_time, departure_flags))
+                        if to_ids.contains(location.id.as_str())
+                            && flags.public
+                            && !flags.pick_up_only =>
+                    {
+                        match times.public_arr {
+                            Some(arrival) => {
+                                if departure_time >= now {
+                                    next_trains.push(NextTrain {
+                                        global_id: GlobalTrainId::new(
+                                            &schedule.namespace,
+                                            &train.id,
+                                            date,
+                                        ),
+                                        namespace: schedule.namespace.clone(),
+                                        id: train.id.clone(),
+                                        date,
+                                        public_id: train.variable_train.public_id.clone(),
+                                        operator: train.variable_train.operator.clone(),
+                                        from_id: from_location.id.clone(),
+                                        to_id: location.id.clone(),
+                                        departure: departure_time,
+                                        arrival,
+                                        platform: from_location.scheduled_platform.clone(),
+                                        departure_flags,
+                                        arrival_flags: flags,
+                                        source: train.source,
+                                        modified,
+                                        cancelled,
+                                        runs_as_required: train.runs_as_required,
+                                        runs_to_locations_as_required: train
+                                            .variable_train
+                                            .runs_to_locations_as_required(),
+                                        branding: branding
+                                            .for_train(&schedule.namespace, &train.variable_train),
+                                    });
+                                }
+                                break;
+                            }
+                            None => (),
+                        }
+                    }
+                    _ => (),
+                }
             }
+            date = date + Days::new(1);
+        }
+    }
+}
 
-            destinations.append(&mut get_destinations(
-                i,
-                train.as_ref().unwrap().route.len(),
-                other_location,
-                schedule_manager.clone(),
-                other_date,
-                namespace,
-            ));
+// The next direct trains from one place to another. Going by when they actually depart rather
+// than by timetable day means the 00:10 that belongs to yesterday's timetable isn't missed.
+#[get("/next?<from>&<to>&<count>&<namespace>&<runs_as_required>&<source>")]
+fn next_trains(
+    from: &str,
+    to: &str,
+    count: Option<usize>,
+    namespace: Option<&str>,
+    runs_as_required: Option<bool>,
+    source: Option<TrainSources>,
+    schedule_manager: Schedules,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    station_groups: &State<StationGroups>,
+) -> Json<Vec<NextTrain>> {
+    let runs_as_required = runs_as_required.unwrap_or(conditional_paths.shown);
+    let now = Utc::now();
+    let mut next_trains = vec![];
+    {
+        let schedule_manager = schedule_manager.read();
+        for (schedule_namespace, schedule) in &*schedule_manager {
+            match namespace {
+                Some(x) if x != schedule_namespace => continue,
+                _ => (),
+            }
+            next_trains_in(
+                schedule,
+                from,
+                to,
+                now,
+                runs_as_required,
+                &source,
+                branding,
+                station_groups,
+                &mut next_trains,
+            );
         }
     }
+    next_trains.sort_by(|a, b| {
+        a.departure
+            .cmp(&b.departure)
+            .then_with(|| a.arrival.cmp(&b.arrival))
+    });
+    next_trains.truncate(count.unwrap_or(5));
+    Json(next_trains)
+}
 
-    destinations
+#[derive(Clone, Debug, Serialize)]
+struct RegionDeparture {
+    global_id: GlobalTrainId,
+    namespace: String,
+    id: String,
+    date: NaiveDate, // the train starts on
+    public_id: Option<String>,
+    operator: Option<TrainOperator>,
+    location_id: IStr,
+    destination_id: IStr,
+    departure: DateTime<Tz>,
+    platform: Option<IStr>,
+    source: Option<TrainSource>,
+    modified: bool,
+    cancelled: bool,
+    runs_as_required: bool,
+    branding: Option<Branding>,
 }
 
-fn location_line_up(
-    namespace: &str,
+fn region_departures_in(
+    schedule: &Schedule,
     location_ids: &HashSet<String>,
-    start_datetime: NaiveDateTime,
-    end_datetime: NaiveDateTime,
-    from_station: Option<HashSet<String>>,
-    to_station: Option<HashSet<String>>,
-    schedule_manager: Arc<ScheduleManager>,
-) -> Option<Template> {
-    let (trains, locations) = {
-        let schedule_manager = schedule_manager.read();
-        let schedule = &schedule_manager.get(namespace)?;
-        let mut trains = vec![];
-        for location_id in location_ids {
-            if !schedule.locations.contains_key(location_id) {
-                return None;
+    now: DateTime<Utc>,
+    runs_as_required: bool,
+    sources: &Option<TrainSources>,
+    branding: &OperatorBranding,
+    departures: &mut Vec<RegionDeparture>,
+) {
+    let until = now + Duration::days(1);
+    let train_ids = location_ids
+        .iter()
+        .filter_map(|x| schedule.trains_indexed_by_location.get(x))
+        .flatten()
+        .collect::<HashSet<_>>();
+    for train_id in train_ids {
+        let versions = match schedule.trains.get(train_id) {
+            Some(x) if !x.is_empty() => x,
+            _ => continue,
+        };
+        // as for next trains, yesterday's trains may still be going
+        let today = now.date_naive();
+        let mut date = today - Days::new(u64::from(max_day_offset(versions)) + 1);
+        while date <= today + Days::new(1) {
+            let (train, mut cancelled, modified) = get_train_instance(versions, date);
+            date = date + Days::new(1);
+            let train = match train {
+                Some(x)
+                    if (runs_as_required || !x.runs_as_required)
+                        && includes_source(sources, x.source) =>
+                {
+                    x
+                }
+                _ => continue,
+            };
+            let date = date - Days::new(1);
+            match get_realtime(schedule, train_id, date).and_then(|x| x.cancellation) {
+                Some(x) if x.cancellation_type.as_deref() != Some("EN ROUTE") => cancelled = true,
+                _ => (),
             }
-            for train_id in schedule
-                .trains_indexed_by_location
-                .get(location_id)
-                .unwrap_or(&HashSet::new())
-            {
-                let train = schedule.trains.get(train_id)?;
-                trains.push(train.clone());
+            let destination = train.route.last().unwrap();
+            for location in &train.route {
+                if !location_ids.contains(location.id.as_str()) {
+                    continue;
+                }
+                let timezone = match schedule.locations.get(location.id.as_str()) {
+                    Some(x) => x.timezone,
+                    None => continue,
+                };
+                let flags = location.passenger_flags();
+                if !flags.public || flags.set_down_only {
+                    continue;
+                }
+                match location.local_times(date, timezone).public_dep {
+                    Some(x) if x >= now && x < until => departures.push(RegionDeparture {
+                        global_id: GlobalTrainId::new(&schedule.namespace, &train.id, date),
+                        namespace: schedule.namespace.clone(),
+                        id: train.id.clone(),
+                        date,
+                        public_id: train.variable_train.public_id.clone(),
+                        operator: train.variable_train.operator.clone(),
+                        location_id: location.id.clone(),
+                        destination_id: destination.id.clone(),
+                        departure: x,
+                        platform: location.scheduled_platform.clone(),
+                        source: train.source,
+                        modified,
+                        cancelled,
+                        runs_as_required: train.runs_as_required,
+                        branding: branding.for_train(&schedule.namespace, &train.variable_train),
+                    }),
+                    _ => (),
+                }
             }
         }
-        (trains, schedule.locations.clone())
-    };
+    }
+}
 
-    let mut actual_trains = vec![];
-    for train in trains {
-        // OK, this is somewhat hacky but I haven't yet thought of a better way.
-        if train.len() == 0 {
-            // deleted trains remain in map
-            continue;
+// Everything leaving anywhere in a country or region over the next day, soonest first, e.g. for
+// "departures from stations in Wales"
+#[get("/departures?<country>&<region>&<count>&<namespace>&<runs_as_required>&<source>")]
+fn region_departures(
+    country: Option<&str>,
+    region: Option<&str>,
+    count: Option<usize>,
+    namespace: Option<&str>,
+    runs_as_required: Option<bool>,
+    source: Option<TrainSources>,
+    schedule_manager: Schedules,
+    regions: &State<Regions>,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+) -> Result<Json<Vec<RegionDeparture>>, Status> {
+    if country.is_none() && region.is_none() {
+        return Err(Status::BadRequest);
+    }
+    let runs_as_required = runs_as_required.unwrap_or(conditional_paths.shown);
+    let now = Utc::now();
+    let mut departures = vec![];
+    {
+        let schedule_manager = schedule_manager.read();
+        for (schedule_namespace, schedule) in &*schedule_manager {
+            match namespace {
+                Some(x) if x != schedule_namespace => continue,
+                _ => (),
+            }
+            let location_ids = regions
+                .locations_in(schedule, country, region)
+                .into_iter()
+                .collect::<HashSet<_>>();
+            region_departures_in(
+                schedule,
+                &location_ids,
+                now,
+                runs_as_required,
+                &source,
+                branding,
+                &mut departures,
+            );
         }
-        let last_location = &train[0].route.last().unwrap();
-        let max_day_offset = if last_location.working_arr_day.is_none() {
-            last_location.public_arr_day.unwrap()
-        } else {
-            last_location.working_arr_day.unwrap()
-        } + 1;
+    }
+    departures.sort_by(|a, b| {
+        a.departure
+            .cmp(&b.departure)
+            .then_with(|| a.location_id.cmp(&b.location_id))
+    });
+    departures.truncate(count.unwrap_or(20));
+    Ok(Json(departures))
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct LocationSearchResult {
+    namespace: String,
+    id: String,
+    name: String,
+    public_id: Option<String>,
+    #[serde(flatten)]
+    region: Region,
+    score: f64,
+}
 
-        let first_date = start_datetime.date().sub(Days::new(max_day_offset.into()));
-        let end_date = end_datetime.date().add(Days::new(1)); // one past the end
-        let mut cur_date = first_date;
+fn score_location(query: &str, query_trigrams: &HashSet<String>, location: &Location) -> f64 {
+    // exact code matches always go first, so people who know the CRS/TIPLOC get what they asked
+    // for
+    match &location.public_id {
+        Some(x) if x.to_lowercase() == query => return 3.0,
+        _ => (),
+    }
+    if location.id.to_lowercase() == query {
+        return 2.5;
+    }
 
-        while cur_date != end_date {
-            let (train, cancelled, modified) = match get_train_instance(&train, cur_date) {
-                (Some(x), y, z) => (x, y, z),
-                _ => {
-                    cur_date = cur_date.add(Days::new(1));
-                    continue;
-                }
-            };
+    let name = location.name.to_lowercase();
+    let name_trigrams = get_trigrams(&name);
+    let shared = query_trigrams.intersection(&name_trigrams).count();
+    let mut score = if shared == 0 {
+        0.0
+    } else {
+        shared as f64 / (query_trigrams.len() + name_trigrams.len() - shared) as f64
+    };
 
-            let mut additions_for_this_train: Vec<BasicTrainForLocation> = vec![];
-            let mut origins_so_far = vec![];
-            let mut variable_train = &train.variable_train;
-            let mut found_from = match from_station {
-                Some(_) => false,
-                None => true,
-            };
-            let mut just_found_from = false;
-            let mut cur_found_tos = 0;
-            for (i, location) in train.route.iter().enumerate() {
-                if just_found_from {
-                    found_from = true;
-                    just_found_from = false;
-                }
+    if name == query {
+        score += 1.0;
+    } else if name.starts_with(query) {
+        score += 0.5;
+    } else if name.contains(query) {
+        score += 0.25;
+    }
+
+    score
+}
+
+#[get("/locations/search?<q>&<namespace>&<limit>&<country>&<region>")]
+fn location_search(
+    q: &str,
+    namespace: Option<&str>,
+    limit: Option<usize>,
+    country: Option<&str>,
+    region: Option<&str>,
+    schedule_manager: Schedules,
+    regions: &State<Regions>,
+) -> Json<Vec<LocationSearchResult>> {
+    let query = q.trim().to_lowercase();
+    let query_trigrams = get_trigrams(&query);
+
+    let mut results = vec![];
+    {
+        let schedule_manager = schedule_manager.read();
+        for (schedule_namespace, schedule) in &*schedule_manager {
+            match namespace {
+                Some(x) if x != schedule_namespace => continue,
+                _ => (),
+            }
 
-                if location.change_en_route.is_some() {
-                    variable_train = &location.change_en_route.as_ref().unwrap();
+            let mut candidates = HashSet::new();
+            for trigram in &query_trigrams {
+                match schedule.locations_indexed_by_trigram.get(trigram) {
+                    Some(x) => candidates.extend(x.iter()),
+                    None => (),
                 }
+            }
+            // in case the query was too short to produce any trigrams
+            match schedule
+                .locations_indexed_by_public_id
+                .get(&query.to_uppercase())
+            {
+                Some(x) => candidates.extend(x.iter()),
+                None => (),
+            }
 
-                if !found_from {
-                    just_found_from = from_station.as_ref().unwrap().contains(&location.id);
+            for location_id in candidates {
+                let location = match schedule.locations.get(location_id) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                let score = score_location(&query, &query_trigrams, location);
+                if score < 0.1 {
+                    continue;
                 }
-                if to_station.is_some() {
-                    if to_station.as_ref().unwrap().contains(&location.id) {
-                        cur_found_tos += 1;
-                    }
+                let location_region = regions.locate(schedule, location_id);
+                if !location_region.matches(country, region) {
+                    continue;
                 }
+                results.push(LocationSearchResult {
+                    namespace: schedule_namespace.clone(),
+                    id: location.id.clone(),
+                    name: location.name.clone(),
+                    public_id: location.public_id.clone(),
+                    region: location_region,
+                    score,
+                });
+            }
+        }
+    }
 
-                origins_so_far.append(&mut get_origins(
-                    i,
-                    &location,
-                    schedule_manager.clone(),
-                    cur_date,
-                    namespace,
-                ));
+    results.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap()
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    results.truncate(limit.unwrap_or(20));
 
-                let destinations = get_destinations(
-                    i,
-                    train.route.len(),
-                    &location,
-                    schedule_manager.clone(),
-                    cur_date,
-                    namespace,
-                );
+    Json(results)
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct BasicTrainForLocation {
+    id: String,
+    public_id: Option<String>,
+    origins: Vec<String>,
+    destinations: Vec<String>,
+    working_arr: Option<NaiveDateTime>,
+    working_dep: Option<NaiveDateTime>,
+    working_pass: Option<NaiveDateTime>,
+    public_arr: Option<NaiveDateTime>,
+    public_dep: Option<NaiveDateTime>,
+    platform: Option<IStr>,
+    live_platform: Option<IStr>,
+    platform_zone: Option<IStr>,
+    line: Option<IStr>,
+    path: Option<IStr>,
+    engineering_allowance_s: Option<u32>,
+    pathing_allowance_s: Option<u32>,
+    performance_allowance_s: Option<u32>,
+    modified: bool,
+    cancelled: bool,
+    cancellation_reason: Option<String>,
+    source: Option<TrainSource>,
+    runs_as_required: bool,
+    runs_to_locations_as_required: bool, // as of this location
+    branding: Option<Branding>,          // likewise
+    operator: Option<TrainOperator>,
+    name: Option<String>,
+    train_type: TrainType,
+    mode: TransportMode,
+    facilities: Facilities, // at this location
+    namespace: String,
+    date: NaiveDate,
+    is_first: bool,
+    is_last: bool,
+    cur_found_tos: usize,
+}
+
+fn get_origins(
+    i: usize,
+    location: &TrainLocation,
+    schedule_manager: Arc<ScheduleManager>,
+    date: NaiveDate,
+    namespace: &str,
+) -> Vec<String> {
+    let mut origins = vec![];
+
+    if i == 0 {
+        let mut found_origin = false;
+        for assoc in &location.divides_from {
+            let final_assoc = match get_association(assoc, date) {
+                Some(x) => x,
+                None => continue,
+            };
 
-                for addition in &mut additions_for_this_train {
-                    addition.destinations.append(&mut destinations.clone());
+            let trains = {
+                let schedule_manager = schedule_manager.read();
+                match schedule_manager
+                    .get(namespace)
+                    .unwrap()
+                    .trains
+                    .get(final_assoc.other_train_id.as_str())
+                {
+                    Some(x) => x.clone(),
+                    None => continue,
                 }
+            };
+
+            let other_date = if final_assoc.day_diff >= 0 {
+                date.add(Days::new(u64::try_from(final_assoc.day_diff).unwrap()))
+            } else {
+                date.sub(Days::new(u64::try_from(-final_assoc.day_diff).unwrap()))
+            };
+            let (train, cancelled, _modified) = get_train_instance(&trains, other_date);
+
+            if cancelled || train.is_none() {
+                continue;
+            }
+
+            found_origin = true;
+
+            let route = &train.as_ref().unwrap().route;
+            let end = call_index(route, &location.id, &assoc.other_train_location_id_suffix)
+                .unwrap_or(route.len());
+            for (i, other_location) in route[..end].iter().enumerate() {
+                origins.append(&mut get_origins(
+                    i,
+                    other_location,
+                    schedule_manager.clone(),
+                    other_date,
+                    namespace,
+                ));
+            }
+        }
+        if !found_origin {
+            origins.push(location.id.to_string());
+        }
+    }
+
+    for assoc in &location.joins_to {
+        let final_assoc = match get_association(&assoc, date) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        let trains = {
+            let schedule_manager = schedule_manager.read();
+            match schedule_manager
+                .get(namespace)
+                .unwrap()
+                .trains
+                .get(final_assoc.other_train_id.as_str())
+            {
+                Some(x) => x.clone(),
+                None => continue,
+            }
+        };
+
+        let other_date = if final_assoc.day_diff >= 0 {
+            date.add(Days::new(u64::try_from(final_assoc.day_diff).unwrap()))
+        } else {
+            date.sub(Days::new(u64::try_from(-final_assoc.day_diff).unwrap()))
+        };
+        let (train, cancelled, _modified) = get_train_instance(&trains, other_date);
+
+        if cancelled || train.is_none() {
+            continue;
+        }
+
+        let route = &train.as_ref().unwrap().route;
+        let end = call_index(route, &location.id, &assoc.other_train_location_id_suffix)
+            .unwrap_or(route.len());
+        for (i, other_location) in route[..end].iter().enumerate() {
+            origins.append(&mut get_origins(
+                i,
+                other_location,
+                schedule_manager.clone(),
+                other_date,
+                namespace,
+            ));
+        }
+    }
+
+    origins
+}
+
+fn get_destinations(
+    i: usize,
+    length: usize,
+    location: &TrainLocation,
+    schedule_manager: Arc<ScheduleManager>,
+    date: NaiveDate,
+    namespace: &str,
+) -> Vec<String> {
+    let mut destinations = vec![];
+
+    if i == length - 1 {
+        let mut found_destination = false;
+        for assoc in &location.is_joined_to_by {
+            let final_assoc = match get_association(assoc, date) {
+                Some(x) => x,
+                None => continue,
+            };
+
+            let trains = {
+                let schedule_manager = schedule_manager.read();
+                match schedule_manager
+                    .get(namespace)
+                    .unwrap()
+                    .trains
+                    .get(final_assoc.other_train_id.as_str())
+                {
+                    Some(x) => x.clone(),
+                    None => continue,
+                }
+            };
+
+            let other_date = if final_assoc.day_diff >= 0 {
+                date.add(Days::new(u64::try_from(final_assoc.day_diff).unwrap()))
+            } else {
+                date.sub(Days::new(u64::try_from(-final_assoc.day_diff).unwrap()))
+            };
+            let (train, cancelled, _modified) = get_train_instance(&trains, other_date);
+
+            if cancelled || train.is_none() {
+                continue;
+            }
+
+            found_destination = true;
+
+            let route = &train.as_ref().unwrap().route;
+            let start = match call_index(route, &location.id, &assoc.other_train_location_id_suffix)
+            {
+                Some(x) => x + 1,
+                None => route.len(),
+            };
+
+            for (i, other_location) in route.iter().enumerate().skip(start) {
+                destinations.append(&mut get_destinations(
+                    i,
+                    route.len(),
+                    other_location,
+                    schedule_manager.clone(),
+                    other_date,
+                    namespace,
+                ));
+            }
+        }
+        if !found_destination {
+            destinations.push(location.id.to_string());
+        }
+    }
+
+    for assoc in &location.divides_to_form {
+        let final_assoc = match get_association(assoc, date) {
+            Some(x) => x,
+            None => continue,
+        };
+
+        let trains = {
+            let schedule_manager = schedule_manager.read();
+            match schedule_manager
+                .get(namespace)
+                .unwrap()
+                .trains
+                .get(final_assoc.other_train_id.as_str())
+            {
+                Some(x) => x.clone(),
+                None => continue,
+            }
+        };
+
+        let other_date = if final_assoc.day_diff >= 0 {
+            date.add(Days::new(u64::try_from(final_assoc.day_diff).unwrap()))
+        } else {
+            date.sub(Days::new(u64::try_from(-final_assoc.day_diff).unwrap()))
+        };
+        let (train, cancelled, _modified) = get_train_instance(&trains, other_date);
+
+        if cancelled || train.is_none() {
+            continue;
+        }
+
+        let route = &train.as_ref().unwrap().route;
+        let start = match call_index(route, &location.id, &assoc.other_train_location_id_suffix) {
+            Some(x) => x + 1,
+            None => route.len(),
+        };
+        for (i, other_location) in route.iter().enumerate().skip(start) {
+            destinations.append(&mut get_destinations(
+                i,
+                route.len(),
+                other_location,
+                schedule_manager.clone(),
+                other_date,
+                namespace,
+            ));
+        }
+    }
+
+    destinations
+}
+
+type BoardCache = QueryCache<Option<serde_json::Value>>;
+
+// which schedules a cached answer is from, as other environments and the past share the cache
+fn cache_scope(schedule_manager: &ScheduleManager) -> String {
+    format!(
+        "{}|{}",
+        schedule_manager.environment().unwrap_or(MAIN_ENVIRONMENT),
+        schedule_manager
+            .get_as_of()
+            .map(|x| x.to_rfc3339())
+            .unwrap_or_default()
+    )
+}
+
+fn location_line_up(
+    namespace: &str,
+    location_ids: &HashSet<String>,
+    start_datetime: NaiveDateTime,
+    end_datetime: NaiveDateTime,
+    from_station: Option<HashSet<String>>,
+    to_station: Option<HashSet<String>>,
+    schedule_manager: Arc<ScheduleManager>,
+    query_cache: &BoardCache,
+    modes: Option<HashSet<TransportMode>>,
+    filter: BoardFilter,
+    branding: &OperatorBranding,
+    localiser: Localiser,
+    alerts: &Alerts,
+    redacted: Redacted,
+) -> Option<Template> {
+    let sorted = |x: &HashSet<String>| x.iter().sorted().join(",");
+    // times only to the minute, so boards for "now" can be shared for a bit
+    let key = format!(
+        "location|{}|{}|{}|{}|{}|{}|{}|{}|{}",
+        cache_scope(&schedule_manager),
+        namespace,
+        sorted(location_ids),
+        start_datetime.format("%Y-%m-%dT%H:%M"),
+        end_datetime.format("%Y-%m-%dT%H:%M"),
+        from_station.as_ref().map(sorted).unwrap_or_default(),
+        to_station.as_ref().map(sorted).unwrap_or_default(),
+        modes
+            .as_ref()
+            .map(|x| x.iter().map(|x| format!("{:?}", x)).sorted().join(","))
+            .unwrap_or_default(),
+        filter.key(),
+    );
+
+    let generation = schedule_manager.namespace_generation(namespace);
+    let mut context = query_cache.get_or_insert_with(key, generation, || {
+        location_line_up_context(
+            namespace,
+            location_ids,
+            start_datetime,
+            end_datetime,
+            from_station,
+            to_station,
+            schedule_manager.clone(),
+            modes,
+            &filter,
+            branding,
+        )
+    })?;
+    localiser.localise_board(namespace, &mut context);
+    // only changes what's shown, so it's not part of the key
+    context["advanced"] = serde_json::Value::Bool(filter.advanced.unwrap_or(false));
+    // nor are alerts, so they go up and come down straight away
+    let alerts = alerts.current(
+        Some(namespace),
+        &[],
+        location_ids.iter().map(|x| x.as_str()),
+    );
+    context["alerts"] = serde_json::to_value(alerts).unwrap();
+    // after the cache, as what's hidden depends on who's asking
+    match &redacted.0 {
+        Some(x) => {
+            x.apply_to_page(&mut context);
+        }
+        None => (),
+    }
+
+    Some(Template::render("location", &context))
+}
+
+fn location_line_up_context(
+    namespace: &str,
+    location_ids: &HashSet<String>,
+    start_datetime: NaiveDateTime,
+    end_datetime: NaiveDateTime,
+    from_station: Option<HashSet<String>>,
+    to_station: Option<HashSet<String>>,
+    schedule_manager: Arc<ScheduleManager>,
+    modes: Option<HashSet<TransportMode>>,
+    filter: &BoardFilter,
+    branding: &OperatorBranding,
+) -> Option<serde_json::Value> {
+    let (trains, instances, locations, restrictions, realtime, train_names) = {
+        let schedule_manager = schedule_manager.read();
+        let schedule = &schedule_manager.get(namespace)?;
+        let mut trains = HashMap::new();
+        let mut restrictions = vec![];
+        let mut realtime = HashMap::new();
+        for location_id in location_ids {
+            if !schedule.locations.contains_key(location_id) {
+                return None;
+            }
+            for restriction_id in schedule
+                .restrictions_indexed_by_location
+                .get(location_id)
+                .unwrap_or(&HashSet::new())
+            {
+                let restriction = match schedule.restrictions.get(restriction_id) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                if restriction.valid_begin.naive_local() <= end_datetime
+                    && restriction.valid_end.naive_local() >= start_datetime
+                    && !restrictions
+                        .iter()
+                        .any(|x: &Restriction| x.id == restriction.id)
+                {
+                    restrictions.push(restriction.clone());
+                }
+            }
+        }
+        // each train once a day, however many of the locations it calls at
+        let mut instances = vec![];
+        for call in schedule.calls_between(location_ids, start_datetime, end_datetime) {
+            if !trains.contains_key(&call.train_id) {
+                trains.insert(
+                    call.train_id.clone(),
+                    schedule.trains.get(&call.train_id)?.clone(),
+                );
+                match schedule.realtime.get(&call.train_id) {
+                    Some(x) => {
+                        realtime.insert(call.train_id.clone(), x.clone());
+                    }
+                    None => (),
+                }
+            }
+            let instance = (call.train_id, call.operating_date);
+            if !instances.contains(&instance) {
+                instances.push(instance);
+            }
+        }
+        (
+            trains,
+            instances,
+            schedule.locations.clone(),
+            restrictions,
+            realtime,
+            schedule.train_names.clone(),
+        )
+    };
+
+    let mut actual_trains = vec![];
+    for (train_id, cur_date) in instances {
+        let versions = &trains[&train_id];
+        let (mut train, mut cancelled, modified) = match get_train_instance(versions, cur_date) {
+            (Some(x), y, z) => (x, y, z),
+            _ => continue,
+        };
+        name_train(&train_names, &mut train, cur_date);
+        let train_realtime = realtime.get(&train.id).and_then(|x| x.get(&cur_date));
+        match train_realtime {
+            Some(x) => x.apply_platforms(&mut train.route),
+            None => (),
+        }
+        // en route cancellations still call at the stations before, so leave those be
+        match train_realtime.and_then(|x| x.cancellation.as_ref()) {
+            Some(x) if x.cancellation_type.as_deref() != Some("EN ROUTE") => cancelled = true,
+            _ => (),
+        }
+        let cancellation_reason = if cancelled {
+            cancellation_reason(versions, train_realtime, cur_date)
+        } else {
+            None
+        };
+
+        let mut additions_for_this_train: Vec<BasicTrainForLocation> = vec![];
+        let mut origins_so_far = vec![];
+        let mut variable_train = &train.variable_train;
+        let mut found_from = match from_station {
+            Some(_) => false,
+            None => true,
+        };
+        let mut just_found_from = false;
+        let mut cur_found_tos = 0;
+        for (i, location) in train.route.iter().enumerate() {
+            if just_found_from {
+                found_from = true;
+                just_found_from = false;
+            }
+
+            if location.change_en_route.is_some() {
+                variable_train = &location.change_en_route.as_ref().unwrap();
+            }
+
+            if !found_from {
+                just_found_from = from_station
+                    .as_ref()
+                    .unwrap()
+                    .contains(location.id.as_str());
+            }
+            if to_station.is_some() {
+                if to_station.as_ref().unwrap().contains(location.id.as_str()) {
+                    cur_found_tos += 1;
+                }
+            }
+
+            origins_so_far.append(&mut get_origins(
+                i,
+                &location,
+                schedule_manager.clone(),
+                cur_date,
+                namespace,
+            ));
+
+            let destinations = get_destinations(
+                i,
+                train.route.len(),
+                &location,
+                schedule_manager.clone(),
+                cur_date,
+                namespace,
+            );
+
+            for addition in &mut additions_for_this_train {
+                addition.destinations.append(&mut destinations.clone());
+            }
+
+            if !location_ids.contains(location.id.as_str()) {
+                continue;
+            }
+
+            if from_station.is_some() && !found_from {
+                continue;
+            }
+
+            match location.board_time(cur_date) {
+                Some(x) if x >= start_datetime && x <= end_datetime => (),
+                _ => continue,
+            }
+
+            // special case: add this station as destination if we are in the last iteration
+            let starting_destinations = if i == train.route.len() - 1 {
+                let mut dests = vec![];
+                dests.push(location.id.to_string());
+                dests
+            } else {
+                vec![]
+            };
+
+            additions_for_this_train.push(BasicTrainForLocation {
+                id: train.id.clone(),
+                public_id: variable_train.public_id.clone(),
+                origins: origins_so_far.clone(),
+                destinations: starting_destinations,
+                working_arr: match location.working_arr {
+                    None => None,
+                    Some(x) => Some(
+                        cur_date
+                            .add(Days::new(location.working_arr_day.unwrap().into()))
+                            .and_time(x),
+                    ),
+                },
+                working_dep: match location.working_dep {
+                    None => None,
+                    Some(x) => Some(
+                        cur_date
+                            .add(Days::new(location.working_dep_day.unwrap().into()))
+                            .and_time(x),
+                    ),
+                },
+                working_pass: match location.working_pass {
+                    None => None,
+                    Some(x) => Some(
+                        cur_date
+                            .add(Days::new(location.working_pass_day.unwrap().into()))
+                            .and_time(x),
+                    ),
+                },
+                public_arr: match location.public_arr {
+                    None => None,
+                    Some(x) => Some(
+                        cur_date
+                            .add(Days::new(location.public_arr_day.unwrap().into()))
+                            .and_time(x),
+                    ),
+                },
+                public_dep: match location.public_dep {
+                    None => None,
+                    Some(x) => Some(
+                        cur_date
+                            .add(Days::new(location.public_dep_day.unwrap().into()))
+                            .and_time(x),
+                    ),
+                },
+                platform: location.scheduled_platform.clone(),
+                live_platform: location.live_platform.clone(),
+                platform_zone: location.platform_zone.clone(),
+                line: location.line.clone(),
+                path: location.path.clone(),
+                engineering_allowance_s: location.engineering_allowance_s,
+                pathing_allowance_s: location.pathing_allowance_s,
+                performance_allowance_s: location.performance_allowance_s,
+                modified,
+                cancelled,
+                cancellation_reason: cancellation_reason.clone(),
+                source: train.source,
+                runs_as_required: train.runs_as_required,
+                runs_to_locations_as_required: variable_train.runs_to_locations_as_required(),
+                branding: branding.for_train(namespace, variable_train),
+                operator: variable_train.operator.clone(),
+                name: variable_train.name.clone(),
+                train_type: variable_train.train_type,
+                mode: variable_train.train_type.mode(),
+                facilities: variable_train.facilities(),
+                namespace: namespace.to_string(),
+                date: cur_date,
+                is_first: i == 0,
+                is_last: i == train.route.len() - 1,
+                cur_found_tos,
+            });
+        }
+
+        if to_station.is_some() {
+            for addition in additions_for_this_train {
+                if cur_found_tos > addition.cur_found_tos {
+                    actual_trains.push(addition.clone());
+                }
+            }
+        } else {
+            actual_trains.append(&mut additions_for_this_train);
+        }
+    }
+
+    if let Some(modes) = &modes {
+        actual_trains.retain(|train| modes.contains(&train.mode));
+    }
+    actual_trains.retain(|train| filter.includes(train));
+
+    actual_trains.sort_by_key(|train| {
+        if train.working_dep.is_some() {
+            train.working_dep
+        } else if train.public_dep.is_some() {
+            train.public_dep
+        } else if train.working_pass.is_some() {
+            train.working_pass
+        } else if train.working_arr.is_some() {
+            train.working_arr
+        } else if train.public_arr.is_some() {
+            train.public_arr
+        } else {
+            return None;
+        }
+    });
+
+    let context = context! {
+        actual_trains,
+        locations,
+        restrictions,
+        location_id: location_ids.iter().next().unwrap(),
+        namespace: namespace.to_string(),
+    };
+
+    serde_json::to_value(&context).ok()
+}
+
+// comma-separated, e.g. ?mode=bus,ship to see only replacement buses and ferries
+fn parse_modes(mode: Option<&str>) -> Result<Option<HashSet<TransportMode>>, WebUiError> {
+    let mode = match mode {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let mut modes = HashSet::new();
+    for part in mode.split(",") {
+        modes.insert(match part.trim().to_lowercase().as_str() {
+            "rail" | "train" => TransportMode::Rail,
+            "bus" | "coach" => TransportMode::Bus,
+            "ship" | "ferry" => TransportMode::Ship,
+            "tram" => TransportMode::Tram,
+            "lightrail" | "tramtrain" => TransportMode::LightRail,
+            "metro" => TransportMode::Metro,
+            "cablecar" => TransportMode::CableCar,
+            "funicular" => TransportMode::Funicular,
+            "trolleybus" => TransportMode::Trolleybus,
+            "monorail" => TransportMode::Monorail,
+            "taxi" => TransportMode::Taxi,
+            "air" => TransportMode::Air,
+            x => {
+                return Err(WebUiError {
+                    what: format!("Unknown transport mode {}", x),
+                })
+            }
+        });
+    }
+
+    Ok(Some(modes))
+}
+
+// Which of the long-term timetable (LTP), short-term overlays (STP) and very short-term plans
+// (VSTP) to show, comma-separated, e.g. ?source=stp,vstp for only what's changed at short notice.
+// Trains from feeds that don't say are left out whenever this is given.
+#[derive(Clone, Debug)]
+struct TrainSources(Vec<TrainSource>);
+
+#[rocket::async_trait]
+impl<'v> FromFormField<'v> for TrainSources {
+    fn from_value(field: ValueField<'v>) -> form::Result<'v, Self> {
+        let mut sources = vec![];
+        for part in field.value.split(",") {
+            sources.push(match part.trim().to_lowercase().as_str() {
+                "ltp" | "longterm" => TrainSource::LongTerm,
+                "stp" | "shortterm" => TrainSource::ShortTerm,
+                "vstp" | "veryshortterm" => TrainSource::VeryShortTerm,
+                x => Err(form::Error::validation(format!(
+                    "Unknown train source {}",
+                    x
+                )))?,
+            });
+        }
+        Ok(TrainSources(sources))
+    }
+}
+
+fn includes_source(sources: &Option<TrainSources>, source: Option<TrainSource>) -> bool {
+    match sources {
+        Some(x) => source.is_some_and(|y| x.0.contains(&y)),
+        None => true,
+    }
+}
+
+// Everything is shown unless left out, e.g. ?freight=false&passes=false for a passenger's view of
+// a busy junction, apart from runs-as-required paths, which need ?runs_as_required=true unless the
+// config shows them anyway. ?advanced=true adds lines, paths and allowances for the operationally
+// minded.
+#[derive(Clone, Debug, Default, FromForm)]
+struct BoardFilter {
+    empty_stock: Option<bool>,
+    freight: Option<bool>,
+    buses: Option<bool>, // including coaches and rail replacement
+    passes: Option<bool>,
+    runs_as_required: Option<bool>,
+    advanced: Option<bool>,
+    source: Option<TrainSources>,
+}
+
+impl BoardFilter {
+    fn with_defaults(mut self, conditional_paths: &ConditionalPaths) -> Self {
+        self.runs_as_required.get_or_insert(conditional_paths.shown);
+        self
+    }
+
+    fn includes(&self, train: &BasicTrainForLocation) -> bool {
+        (self.empty_stock.unwrap_or(true) || !train.train_type.is_empty_stock())
+            && (self.freight.unwrap_or(true) || !train.train_type.is_freight())
+            && (self.buses.unwrap_or(true) || train.mode != TransportMode::Bus)
+            && (self.passes.unwrap_or(true) || train.working_pass.is_none())
+            && (self.runs_as_required.unwrap_or(false) || !train.runs_as_required)
+            && includes_source(&self.source, train.source)
+    }
+
+    // for the board cache, leaving out `advanced` as that doesn't change what's on the board
+    fn key(&self) -> String {
+        let sources = match &self.source {
+            Some(x) => {
+                x.0.iter()
+                    .map(|y| match y {
+                        TrainSource::LongTerm => "l",
+                        TrainSource::ShortTerm => "s",
+                        TrainSource::VeryShortTerm => "v",
+                    })
+                    .sorted()
+                    .dedup()
+                    .join("")
+            }
+            None => "".to_string(),
+        };
+        let flags = [
+            self.empty_stock,
+            self.freight,
+            self.buses,
+            self.passes,
+            self.runs_as_required.or(Some(false)),
+        ]
+        .iter()
+        .map(|x| match x {
+            Some(false) => "n",
+            _ => "",
+        })
+        .join(",");
+        format!("{},{}", flags, sources)
+    }
+}
+
+struct Namespace {
+    namespace: String,
+    is_public_id: bool,
+}
+
+impl<'a> FromParam<'a> for Namespace {
+    type Error = WebUiError;
+
+    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
+        let parts = param.split("-").collect::<Vec<&str>>();
+        if parts.len() != 2 {
+            return Err(WebUiError {
+                what: "Invalid namespace string".to_string(),
+            });
+        }
+
+        match parts[1] {
+            "public" => Ok(Namespace {
+                namespace: parts[0].to_string(),
+                is_public_id: true,
+            }),
+            "internal" => Ok(Namespace {
+                namespace: parts[0].to_string(),
+                is_public_id: false,
+            }),
+            _ => {
+                return Err(WebUiError {
+                    what: "Invalid ID type".to_string(),
+                })
+            }
+        }
+    }
+}
+
+fn get_location_ids_and_first_tz(
+    location_id: &str,
+    namespace: &Namespace,
+    schedule_manager: Arc<ScheduleManager>,
+    station_groups: &StationGroups,
+) -> Option<(HashSet<String>, Tz)> {
+    let schedule_manager = schedule_manager.read();
+    let schedule = &schedule_manager.get(&namespace.namespace)?;
+    let mut location_ids = match namespace.is_public_id {
+        true => schedule
+            .locations_indexed_by_public_id
+            .get(location_id)
+            .cloned()
+            .unwrap_or_default(),
+        false => match schedule.locations.contains_key(location_id) {
+            true => HashSet::from([location_id.to_string()]),
+            false => HashSet::new(),
+        },
+    };
+    // otherwise it may be a station group, e.g. London Terminals
+    if location_ids.is_empty() {
+        location_ids = station_groups.members(schedule, location_id);
+    }
+    let timezone = schedule
+        .locations
+        .get(location_ids.iter().next()?)?
+        .timezone;
+    Some((location_ids, timezone))
+}
+
+#[get("/location/<namespace>/<location_id>?<mode>&<filter..>")]
+fn location(
+    namespace: Namespace,
+    location_id: &str,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    query_cache: &State<BoardCache>,
+    mode: Option<&str>,
+    filter: BoardFilter,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    localiser: Localiser,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
+) -> Result<Option<Template>, (Status, String)> {
+    let modes = parse_modes(mode).map_err(|x| (Status::BadRequest, x.to_string()))?;
+
+    let (location_ids, timezone) = match get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let now = timezone
+        .from_utc_datetime(&Utc::now().naive_utc())
+        .naive_local();
+
+    Ok(location_line_up(
+        &namespace.namespace,
+        &location_ids,
+        now - Duration::minutes(30),
+        now + Duration::minutes(120),
+        None,
+        None,
+        (*schedule_manager).clone(),
+        query_cache,
+        modes,
+        filter.with_defaults(conditional_paths),
+        branding,
+        localiser,
+        alerts,
+        redacted,
+    ))
+}
+
+#[get(
+    "/location/<namespace>/<location_id>/from/<from_id>?<mode>&<filter..>",
+    rank = 0
+)]
+fn location_from(
+    namespace: Namespace,
+    location_id: &str,
+    from_id: &str,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    query_cache: &State<BoardCache>,
+    mode: Option<&str>,
+    filter: BoardFilter,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    localiser: Localiser,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
+) -> Result<Option<Template>, (Status, String)> {
+    let modes = parse_modes(mode).map_err(|x| (Status::BadRequest, x.to_string()))?;
+
+    let (location_ids, timezone) = match get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let now = timezone
+        .from_utc_datetime(&Utc::now().naive_utc())
+        .naive_local();
+
+    let (from_ids, _timezone) = match get_location_ids_and_first_tz(
+        from_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    Ok(location_line_up(
+        &namespace.namespace,
+        &location_ids,
+        now - Duration::minutes(30),
+        now + Duration::minutes(120),
+        Some(from_ids),
+        None,
+        (*schedule_manager).clone(),
+        query_cache,
+        modes,
+        filter.with_defaults(conditional_paths),
+        branding,
+        localiser,
+        alerts,
+        redacted,
+    ))
+}
+
+#[get(
+    "/location/<namespace>/<location_id>/to/<to_id>?<mode>&<filter..>",
+    rank = 0
+)]
+fn location_to(
+    namespace: Namespace,
+    location_id: &str,
+    to_id: &str,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    query_cache: &State<BoardCache>,
+    mode: Option<&str>,
+    filter: BoardFilter,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    localiser: Localiser,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
+) -> Result<Option<Template>, (Status, String)> {
+    let modes = parse_modes(mode).map_err(|x| (Status::BadRequest, x.to_string()))?;
+
+    let (location_ids, timezone) = match get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let now = timezone
+        .from_utc_datetime(&Utc::now().naive_utc())
+        .naive_local();
+
+    let (to_ids, _timezone) = match get_location_ids_and_first_tz(
+        to_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    Ok(location_line_up(
+        &namespace.namespace,
+        &location_ids,
+        now - Duration::minutes(30),
+        now + Duration::minutes(120),
+        None,
+        Some(to_ids),
+        (*schedule_manager).clone(),
+        query_cache,
+        modes,
+        filter.with_defaults(conditional_paths),
+        branding,
+        localiser,
+        alerts,
+        redacted,
+    ))
+}
+
+#[get(
+    "/location/<namespace>/<location_id>/from/<from_id>/to/<to_id>?<mode>&<filter..>",
+    rank = 0
+)]
+fn location_from_to(
+    namespace: Namespace,
+    location_id: &str,
+    from_id: &str,
+    to_id: &str,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    query_cache: &State<BoardCache>,
+    mode: Option<&str>,
+    filter: BoardFilter,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    localiser: Localiser,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
+) -> Result<Option<Template>, (Status, String)> {
+    let modes = parse_modes(mode).map_err(|x| (Status::BadRequest, x.to_string()))?;
+
+    let (location_ids, timezone) = match get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let now = timezone
+        .from_utc_datetime(&Utc::now().naive_utc())
+        .naive_local();
+
+    let (from_ids, _timezone) = match get_location_ids_and_first_tz(
+        from_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let (to_ids, _timezone) = match get_location_ids_and_first_tz(
+        to_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    Ok(location_line_up(
+        &namespace.namespace,
+        &location_ids,
+        now - Duration::minutes(30),
+        now + Duration::minutes(120),
+        Some(from_ids),
+        Some(to_ids),
+        (*schedule_manager).clone(),
+        query_cache,
+        modes,
+        filter.with_defaults(conditional_paths),
+        branding,
+        localiser,
+        alerts,
+        redacted,
+    ))
+}
+
+#[get(
+    "/location/<namespace>/<location_id>/<date>/<time>?<mode>&<filter..>",
+    rank = 1
+)]
+fn location_time(
+    namespace: Namespace,
+    location_id: &str,
+    date: NaiveDateRocket,
+    time: NaiveTimeRocket,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    query_cache: &State<BoardCache>,
+    mode: Option<&str>,
+    filter: BoardFilter,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    localiser: Localiser,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
+) -> Result<Option<Template>, (Status, String)> {
+    let modes = parse_modes(mode).map_err(|x| (Status::BadRequest, x.to_string()))?;
+
+    let (location_ids, _timezone) = match get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    Ok(location_line_up(
+        &namespace.namespace,
+        &location_ids,
+        date.0.and_time(time.0) - Duration::minutes(30),
+        date.0.and_time(time.0) + Duration::minutes(120),
+        None,
+        None,
+        (*schedule_manager).clone(),
+        query_cache,
+        modes,
+        filter.with_defaults(conditional_paths),
+        branding,
+        localiser,
+        alerts,
+        redacted,
+    ))
+}
+
+#[get(
+    "/location/<namespace>/<location_id>/from/<from_id>/<date>/<time>?<mode>&<filter..>",
+    rank = 1
+)]
+fn location_from_time(
+    namespace: Namespace,
+    location_id: &str,
+    from_id: &str,
+    date: NaiveDateRocket,
+    time: NaiveTimeRocket,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    query_cache: &State<BoardCache>,
+    mode: Option<&str>,
+    filter: BoardFilter,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    localiser: Localiser,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
+) -> Result<Option<Template>, (Status, String)> {
+    let modes = parse_modes(mode).map_err(|x| (Status::BadRequest, x.to_string()))?;
+
+    let (location_ids, _timezone) = match get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let (from_ids, _timezone) = match get_location_ids_and_first_tz(
+        from_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    Ok(location_line_up(
+        &namespace.namespace,
+        &location_ids,
+        date.0.and_time(time.0) - Duration::minutes(30),
+        date.0.and_time(time.0) + Duration::minutes(120),
+        Some(from_ids),
+        None,
+        (*schedule_manager).clone(),
+        query_cache,
+        modes,
+        filter.with_defaults(conditional_paths),
+        branding,
+        localiser,
+        alerts,
+        redacted,
+    ))
+}
+
+#[get(
+    "/location/<namespace>/<location_id>/to/<to_id>/<date>/<time>?<mode>&<filter..>",
+    rank = 1
+)]
+fn location_to_time(
+    namespace: Namespace,
+    location_id: &str,
+    to_id: &str,
+    date: NaiveDateRocket,
+    time: NaiveTimeRocket,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    query_cache: &State<BoardCache>,
+    mode: Option<&str>,
+    filter: BoardFilter,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    localiser: Localiser,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
+) -> Result<Option<Template>, (Status, String)> {
+    let modes = parse_modes(mode).map_err(|x| (Status::BadRequest, x.to_string()))?;
+
+    let (location_ids, _timezone) = match get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let (to_ids, _timezone) = match get_location_ids_and_first_tz(
+        to_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    Ok(location_line_up(
+        &namespace.namespace,
+        &location_ids,
+        date.0.and_time(time.0) - Duration::minutes(30),
+        date.0.and_time(time.0) + Duration::minutes(120),
+        None,
+        Some(to_ids),
+        (*schedule_manager).clone(),
+        query_cache,
+        modes,
+        filter.with_defaults(conditional_paths),
+        branding,
+        localiser,
+        alerts,
+        redacted,
+    ))
+}
+
+#[get(
+    "/location/<namespace>/<location_id>/from/<from_id>/to/<to_id>/<date>/<time>?<mode>&<filter..>",
+    rank = 1
+)]
+fn location_from_to_time(
+    namespace: Namespace,
+    location_id: &str,
+    from_id: &str,
+    to_id: &str,
+    date: NaiveDateRocket,
+    time: NaiveTimeRocket,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    query_cache: &State<BoardCache>,
+    mode: Option<&str>,
+    filter: BoardFilter,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    localiser: Localiser,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
+) -> Result<Option<Template>, (Status, String)> {
+    let modes = parse_modes(mode).map_err(|x| (Status::BadRequest, x.to_string()))?;
+
+    let (location_ids, _timezone) = match get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let (from_ids, _timezone) = match get_location_ids_and_first_tz(
+        from_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let (to_ids, _timezone) = match get_location_ids_and_first_tz(
+        to_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    Ok(location_line_up(
+        &namespace.namespace,
+        &location_ids,
+        date.0.and_time(time.0) - Duration::minutes(30),
+        date.0.and_time(time.0) + Duration::minutes(120),
+        Some(from_ids),
+        Some(to_ids),
+        (*schedule_manager).clone(),
+        query_cache,
+        modes,
+        filter.with_defaults(conditional_paths),
+        branding,
+        localiser,
+        alerts,
+        redacted,
+    ))
+}
+
+#[get(
+    "/location/<namespace>/<location_id>/<date>/<from_time>/to/<to_time>?<mode>&<filter..>",
+    rank = 2
+)]
+fn location_time_to(
+    namespace: Namespace,
+    location_id: &str,
+    date: NaiveDateRocket,
+    from_time: NaiveTimeRocket,
+    to_time: NaiveTimeRocket,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    query_cache: &State<BoardCache>,
+    mode: Option<&str>,
+    filter: BoardFilter,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    localiser: Localiser,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
+) -> Result<Option<Template>, (Status, String)> {
+    let modes = parse_modes(mode).map_err(|x| (Status::BadRequest, x.to_string()))?;
+
+    let (location_ids, _timezone) = match get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let to_date = if to_time.0 < from_time.0 {
+        date.0 + Days::new(1)
+    } else {
+        date.0
+    };
+
+    Ok(location_line_up(
+        &namespace.namespace,
+        &location_ids,
+        date.0.and_time(from_time.0),
+        to_date.and_time(to_time.0),
+        None,
+        None,
+        (*schedule_manager).clone(),
+        query_cache,
+        modes,
+        filter.with_defaults(conditional_paths),
+        branding,
+        localiser,
+        alerts,
+        redacted,
+    ))
+}
+
+#[get(
+    "/location/<namespace>/<location_id>/from/<from_id>/<date>/<from_time>/to/<to_time>?<mode>&<filter..>",
+    rank = 2
+)]
+fn location_from_time_to(
+    namespace: Namespace,
+    location_id: &str,
+    from_id: &str,
+    date: NaiveDateRocket,
+    from_time: NaiveTimeRocket,
+    to_time: NaiveTimeRocket,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    query_cache: &State<BoardCache>,
+    mode: Option<&str>,
+    filter: BoardFilter,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    localiser: Localiser,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
+) -> Result<Option<Template>, (Status, String)> {
+    let modes = parse_modes(mode).map_err(|x| (Status::BadRequest, x.to_string()))?;
+
+    let (location_ids, _timezone) = match get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let to_date = if to_time.0 < from_time.0 {
+        date.0 + Days::new(1)
+    } else {
+        date.0
+    };
+
+    let (from_ids, _timezone) = match get_location_ids_and_first_tz(
+        from_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    Ok(location_line_up(
+        &namespace.namespace,
+        &location_ids,
+        date.0.and_time(from_time.0),
+        to_date.and_time(to_time.0),
+        Some(from_ids),
+        None,
+        (*schedule_manager).clone(),
+        query_cache,
+        modes,
+        filter.with_defaults(conditional_paths),
+        branding,
+        localiser,
+        alerts,
+        redacted,
+    ))
+}
+
+#[get(
+    "/location/<namespace>/<location_id>/to/<to_id>/<date>/<from_time>/to/<to_time>?<mode>&<filter..>",
+    rank = 2
+)]
+fn location_to_time_to(
+    namespace: Namespace,
+    location_id: &str,
+    to_id: &str,
+    date: NaiveDateRocket,
+    from_time: NaiveTimeRocket,
+    to_time: NaiveTimeRocket,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    query_cache: &State<BoardCache>,
+    mode: Option<&str>,
+    filter: BoardFilter,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    localiser: Localiser,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
+) -> Result<Option<Template>, (Status, String)> {
+    let modes = parse_modes(mode).map_err(|x| (Status::BadRequest, x.to_string()))?;
+
+    let (location_ids, _timezone) = match get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let to_date = if to_time.0 < from_time.0 {
+        date.0 + Days::new(1)
+    } else {
+        date.0
+    };
+
+    let (to_ids, _timezone) = match get_location_ids_and_first_tz(
+        to_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    Ok(location_line_up(
+        &namespace.namespace,
+        &location_ids,
+        date.0.and_time(from_time.0),
+        to_date.and_time(to_time.0),
+        None,
+        Some(to_ids),
+        (*schedule_manager).clone(),
+        query_cache,
+        modes,
+        filter.with_defaults(conditional_paths),
+        branding,
+        localiser,
+        alerts,
+        redacted,
+    ))
+}
+
+#[get(
+    "/location/<namespace>/<location_id>/from/<from_id>/to/<to_id>/<date>/<from_time>/to/<to_time>?<mode>&<filter..>",
+    rank = 2
+)]
+fn location_from_to_time_to(
+    namespace: Namespace,
+    location_id: &str,
+    from_id: &str,
+    to_id: &str,
+    date: NaiveDateRocket,
+    from_time: NaiveTimeRocket,
+    to_time: NaiveTimeRocket,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    query_cache: &State<BoardCache>,
+    mode: Option<&str>,
+    filter: BoardFilter,
+    conditional_paths: &State<ConditionalPaths>,
+    branding: &State<OperatorBranding>,
+    localiser: Localiser,
+    alerts: &State<Alerts>,
+    redacted: Redacted,
+) -> Result<Option<Template>, (Status, String)> {
+    let modes = parse_modes(mode).map_err(|x| (Status::BadRequest, x.to_string()))?;
+
+    let (location_ids, _timezone) = match get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    let to_date = if to_time.0 < from_time.0 {
+        date.0 + Days::new(1)
+    } else {
+        date.0
+    };
+
+    let (from_ids, _timezone) = match get_location_ids_and_first_tz(
+        from_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+    let (to_ids, _timezone) = match get_location_ids_and_first_tz(
+        to_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    ) {
+        Some(x) => x,
+        None => return Ok(None),
+    };
+
+    Ok(location_line_up(
+        &namespace.namespace,
+        &location_ids,
+        date.0.and_time(from_time.0),
+        to_date.and_time(to_time.0),
+        Some(from_ids),
+        Some(to_ids),
+        (*schedule_manager).clone(),
+        query_cache,
+        modes,
+        filter.with_defaults(conditional_paths),
+        branding,
+        localiser,
+        alerts,
+        redacted,
+    ))
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+struct ScheduleStats {
+    description: String,
+    trains: usize,        // distinct train IDs
+    schedules: usize,     // base schedules plus every overlay
+    cancellations: usize, // cancellation periods across all schedules
+    by_source: BTreeMap<String, usize>,
+    by_operator: BTreeMap<String, usize>,
+    by_type: BTreeMap<String, usize>,
+    locations: usize,
+    associations: usize, // counted once, from the side that divides/joins/becomes
+    restrictions: usize,
+    valid_begin: Option<DateTime<Tz>>,
+    valid_end: Option<DateTime<Tz>>,
+    last_updated: Option<DateTime<Tz>>, // as claimed by the feed
+    last_imported: Option<DateTime<Utc>>,
+    locations_by_country: BTreeMap<String, usize>,
+    trains_by_country: BTreeMap<String, usize>, // distinct train IDs calling there at all
+}
+
+fn count_schedule(stats: &mut ScheduleStats, train: &Train) {
+    stats.schedules += 1;
+    stats.cancellations += train.cancellations.len();
+    *stats
+        .by_source
+        .entry(match train.source {
+            Some(x) => format!("{:?}", x),
+            None => "Unknown".to_string(),
+        })
+        .or_default() += 1;
+    *stats
+        .by_operator
+        .entry(match &train.variable_train.operator {
+            Some(x) => x.id.to_string(),
+            None => "Unknown".to_string(),
+        })
+        .or_default() += 1;
+    *stats
+        .by_type
+        .entry(format!("{:?}", train.variable_train.train_type))
+        .or_default() += 1;
+    for location in &train.route {
+        stats.associations += location.divides_to_form.len()
+            + location.joins_to.len()
+            + location.becomes.iter().count();
+    }
+}
+
+fn schedule_stats(schedule: &Schedule, regions: &Regions) -> ScheduleStats {
+    let mut stats = ScheduleStats {
+        description: schedule.description.clone(),
+        locations: schedule.locations.len(),
+        restrictions: schedule.restrictions.len(),
+        valid_begin: schedule.valid_begin,
+        valid_end: schedule.valid_end,
+        last_updated: schedule.last_updated,
+        last_imported: schedule.last_imported,
+        ..Default::default()
+    };
+
+    let countries = schedule
+        .locations
+        .keys()
+        .map(|x| {
+            let country = regions.locate(schedule, x).country;
+            (x.as_str(), country.unwrap_or_else(|| "Unknown".to_string()))
+        })
+        .collect::<HashMap<_, _>>();
+    for country in countries.values() {
+        *stats
+            .locations_by_country
+            .entry(country.clone())
+            .or_default() += 1;
+    }
+
+    for trains in schedule.trains.values() {
+        if trains.is_empty() {
+            // deleted trains remain in map
+            continue;
+        }
+        stats.trains += 1;
+        let mut train_countries = HashSet::new();
+        for train in trains {
+            let train = peek_train(train);
+            count_schedule(&mut stats, &train);
+            for replacement in &train.replacements {
+                count_schedule(&mut stats, replacement);
+            }
+            for location in train
+                .route
+                .iter()
+                .chain(train.replacements.iter().flat_map(|x| x.route.iter()))
+            {
+                match countries.get(location.id.as_str()) {
+                    Some(x) => {
+                        train_countries.insert(x);
+                    }
+                    None => (),
+                }
+            }
+        }
+        for country in train_countries {
+            *stats.trains_by_country.entry(country.clone()).or_default() += 1;
+        }
+    }
+
+    stats
+}
+
+#[get("/stats")]
+fn stats(
+    schedule_manager: Schedules,
+    query_cache: &State<BoardCache>,
+    regions: &State<Regions>,
+) -> Option<Json<serde_json::Value>> {
+    // counting means walking every train, so only do it once per import
+    let key = format!("stats|{}", cache_scope(&schedule_manager));
+    let stats = query_cache.get_or_insert_with(key, schedule_manager.generation(), || {
+        let schedule_manager = schedule_manager.read();
+        let stats = schedule_manager
+            .iter()
+            .map(|(namespace, schedule)| (namespace.clone(), schedule_stats(schedule, regions)))
+            .collect::<BTreeMap<_, _>>();
+        serde_json::to_value(&stats).ok()
+    })?;
+
+    Some(Json(stats))
+}
+
+// How good each namespace's data has looked over the last few imports, oldest first
+#[get("/quality")]
+fn quality(
+    schedule_manager: &State<Arc<ScheduleManager>>,
+    quality: &State<Arc<Quality>>,
+) -> Json<BTreeMap<String, Vec<QualityReport>>> {
+    let schedule_manager = schedule_manager.read();
+    Json(quality.reports(schedule_manager.values().map(|x| x.as_ref())))
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct PendingOverlaySummary {
+    train_id: String,
+    valid_begin: NaiveDate,
+    valid_end: NaiveDate,
+    standalone: bool, // running as a train of its own meanwhile
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct PendingOverlays {
+    count: usize,
+    overlays: Vec<PendingOverlaySummary>,
+}
+
+// Overlays still waiting for the schedule they replace, which every import tries them against
+// again, by namespace
+#[get("/overlays/pending")]
+fn pending_overlays(schedule_manager: Schedules) -> Json<BTreeMap<String, PendingOverlays>> {
+    let schedule_manager = schedule_manager.read();
+    Json(
+        schedule_manager
+            .iter()
+            .map(|(namespace, schedule)| {
+                let overlays = schedule
+                    .pending_overlays
+                    .iter()
+                    .flat_map(|(train_id, pending)| {
+                        pending.iter().map(move |x| PendingOverlaySummary {
+                            train_id: train_id.clone(),
+                            valid_begin: x.overlay.validity[0].valid_begin.date_naive(),
+                            valid_end: x.overlay.validity[0].valid_end.date_naive(),
+                            standalone: x.standalone,
+                        })
+                    })
+                    .sorted_by(|x, y| {
+                        (&x.train_id, x.valid_begin).cmp(&(&y.train_id, y.valid_begin))
+                    })
+                    .collect::<Vec<_>>();
+                (
+                    namespace.clone(),
+                    PendingOverlays {
+                        count: overlays.len(),
+                        overlays,
+                    },
+                )
+            })
+            .collect(),
+    )
+}
 
-                if !location_ids.contains(&location.id) {
-                    continue;
-                }
+#[derive(Clone, Debug, Serialize)]
+struct ServiceStatus {
+    stale: bool, // if any source is
+    sources: Vec<SourceStatus>,
+    integrity_violations: usize, // in any namespace, as of the last check
+    integrity: Vec<IntegrityReport>,
+}
 
-                if from_station.is_some() && !found_from {
-                    continue;
-                }
+#[get("/status")]
+fn status(
+    staleness: &State<Arc<Staleness>>,
+    integrity: &State<Arc<Integrity>>,
+) -> Json<ServiceStatus> {
+    let sources = staleness.status();
+    let integrity = integrity.reports();
+    Json(ServiceStatus {
+        stale: sources.iter().any(|x| x.stale),
+        sources,
+        integrity_violations: integrity.iter().map(|x| x.violations).sum(),
+        integrity,
+    })
+}
 
-                let (best_time, best_offset) = {
-                    if location.working_dep.is_some() {
-                        (
-                            location.working_dep.unwrap(),
-                            location.working_dep_day.unwrap(),
-                        )
-                    } else if location.public_dep.is_some() {
-                        (
-                            location.public_dep.unwrap(),
-                            location.public_dep_day.unwrap(),
-                        )
-                    } else if location.working_pass.is_some() {
-                        (
-                            location.working_pass.unwrap(),
-                            location.working_pass_day.unwrap(),
-                        )
-                    } else if location.working_arr.is_some() {
-                        (
-                            location.working_arr.unwrap(),
-                            location.working_arr_day.unwrap(),
-                        )
-                    } else if location.public_arr.is_some() {
-                        (
-                            location.public_arr.unwrap(),
-                            location.public_arr_day.unwrap(),
-                        )
-                    } else {
-                        return None;
-                    }
-                };
-                let time_from_cur_date = cur_date
-                    .add(Days::new(best_offset.into()))
-                    .and_time(best_time);
-                if time_from_cur_date < start_datetime || time_from_cur_date > end_datetime {
-                    continue;
-                }
+#[derive(Clone, Debug, Serialize)]
+struct Liveness {
+    alive: bool,
+    namespaces: usize, // loaded so far, which is none until the first import or snapshot
+}
 
-                // special case: add this station as destination if we are in the last iteration
-                let starting_destinations = if i == train.route.len() - 1 {
-                    let mut dests = vec![];
-                    dests.push(location.id.clone());
-                    dests
-                } else {
-                    vec![]
-                };
+// For orchestrators to restart us if this stops answering. It only takes the schedules' lock, so
+// a long import or a feed being down doesn't count.
+#[get("/healthz")]
+fn healthz(schedule_manager: &State<Arc<ScheduleManager>>) -> Json<Liveness> {
+    Json(Liveness {
+        alive: true,
+        namespaces: schedule_manager.read().len(),
+    })
+}
 
-                additions_for_this_train.push(BasicTrainForLocation {
-                    id: train.id.clone(),
-                    public_id: variable_train.public_id.clone(),
-                    origins: origins_so_far.clone(),
-                    destinations: starting_destinations,
-                    working_arr: match location.working_arr {
-                        None => None,
-                        Some(x) => Some(
-                            cur_date
-                                .add(Days::new(location.working_arr_day.unwrap().into()))
-                                .and_time(x),
-                        ),
-                    },
-                    working_dep: match location.working_dep {
-                        None => None,
-                        Some(x) => Some(
-                            cur_date
-                                .add(Days::new(location.working_dep_day.unwrap().into()))
-                                .and_time(x),
-                        ),
-                    },
-                    working_pass: match location.working_pass {
-                        None => None,
-                        Some(x) => Some(
-                            cur_date
-                                .add(Days::new(location.working_pass_day.unwrap().into()))
-                                .and_time(x),
-                        ),
-                    },
-                    public_arr: match location.public_arr {
-                        None => None,
-                        Some(x) => Some(
-                            cur_date
-                                .add(Days::new(location.public_arr_day.unwrap().into()))
-                                .and_time(x),
-                        ),
-                    },
-                    public_dep: match location.public_dep {
-                        None => None,
-                        Some(x) => Some(
-                            cur_date
-                                .add(Days::new(location.public_dep_day.unwrap().into()))
-                                .and_time(x),
-                        ),
-                    },
-                    platform: location.platform.clone(),
-                    platform_zone: location.platform_zone.clone(),
-                    modified,
-                    cancelled,
-                    source: train.source,
-                    runs_as_required: train.runs_as_required,
-                    operator: variable_train.operator.clone(),
-                    name: variable_train.name.clone(),
-                    namespace: namespace.to_string(),
-                    date: cur_date,
-                    is_first: i == 0,
-                    is_last: i == train.route.len() - 1,
-                    cur_found_tos,
-                });
-            }
+#[derive(Clone, Debug, Serialize)]
+struct SourceReadiness {
+    #[serde(flatten)]
+    status: SourceStatus,
+    connected: Option<bool>, // for feeds we hold a connection open to
+    ready: bool,
+}
 
-            cur_date = cur_date.add(Days::new(1));
+#[derive(Clone, Debug, Serialize)]
+struct Readiness {
+    ready: bool,
+    imported: BTreeMap<String, Option<DateTime<Utc>>>, // namespaces loaded, and when last written to
+    sources: Vec<SourceReadiness>,
+}
 
-            if to_station.is_some() {
-                for addition in additions_for_this_train {
-                    if cur_found_tos > addition.cur_found_tos {
-                        actual_trains.push(addition.clone());
-                    }
-                }
-            } else {
-                actual_trains.append(&mut additions_for_this_train);
-            }
+// For load balancers to only send us requests once there's something to answer them with: at
+// least one complete import (or snapshot), and every feed we hold a connection to either connected
+// or not yet stale. Timetables that are only late still get served, so they don't count against
+// this; /status has those.
+#[get("/readyz")]
+fn readyz(
+    schedule_manager: &State<Arc<ScheduleManager>>,
+    staleness: &State<Arc<Staleness>>,
+    feed_telemetry: &State<Arc<FeedTelemetry>>,
+) -> (Status, Json<Readiness>) {
+    let imported = schedule_manager
+        .read()
+        .iter()
+        .map(|(namespace, schedule)| (namespace.clone(), schedule.last_imported))
+        .collect::<BTreeMap<_, _>>();
+    let telemetry = feed_telemetry.get();
+    let mut statuses = staleness.status();
+    for (source, x) in &telemetry {
+        if x.connected.is_some() && !statuses.iter().any(|y| &y.source == source) {
+            statuses.push(staleness.status_of(source));
         }
     }
+    let sources = statuses
+        .into_iter()
+        .map(|status| {
+            let connected = telemetry.get(&status.source).and_then(|x| x.connected);
+            SourceReadiness {
+                ready: connected.is_none() || connected == Some(true) || !status.stale,
+                status,
+                connected,
+            }
+        })
+        .sorted_by(|x, y| x.status.source.cmp(&y.status.source))
+        .collect::<Vec<_>>();
+    let ready = !imported.is_empty() && sources.iter().all(|x| x.ready);
+    let code = match ready {
+        true => Status::Ok,
+        false => Status::ServiceUnavailable,
+    };
+    (
+        code,
+        Json(Readiness {
+            ready,
+            imported,
+            sources,
+        }),
+    )
+}
 
-    actual_trains.sort_by_key(|train| {
-        if train.working_dep.is_some() {
-            train.working_dep
-        } else if train.public_dep.is_some() {
-            train.public_dep
-        } else if train.working_pass.is_some() {
-            train.working_pass
-        } else if train.working_arr.is_some() {
-            train.working_arr
-        } else if train.public_arr.is_some() {
-            train.public_arr
-        } else {
-            return None;
-        }
-    });
+#[derive(Clone, Debug, Serialize)]
+struct LocationInterchange {
+    namespace: String,
+    location_id: String,
+    #[serde(flatten)]
+    interchange: InterchangeInfo,
+}
 
-    let context = context! {
-        actual_trains,
-        locations,
-        location_id: location_ids.iter().next().unwrap(),
+// Styling for operators and brands, for frontends to colour boards with
+#[get("/operators?<namespace>")]
+fn operators(
+    namespace: Option<&str>,
+    branding: &State<OperatorBranding>,
+) -> Json<Vec<OperatorInfo>> {
+    Json(branding.operators(namespace))
+}
+
+#[get("/interchange/<namespace>/<location_id>")]
+fn interchange(
+    namespace: &str,
+    location_id: &str,
+    schedule_manager: Schedules,
+    interchange: &State<Interchange>,
+) -> Option<Json<LocationInterchange>> {
+    let location = schedule_manager
+        .get(namespace)?
+        .locations
+        .get(location_id)?
+        .clone();
+
+    let mut ids = vec![location.id.clone()];
+    match location.public_id {
+        Some(x) => ids.push(x),
+        None => (),
+    }
+
+    Some(Json(LocationInterchange {
         namespace: namespace.to_string(),
-    };
+        location_id: location.id,
+        interchange: interchange.get(namespace, &ids),
+    }))
+}
 
-    Some(Template::render("location", &context))
+// Everything timetabled on each platform at a station on a date, and where two trains are booked
+// onto the same one at once. A lot of these turn out to be data errors rather than real conflicts.
+#[get("/platforms/<namespace>/<location_id>/<date>?<turnback_margin_secs>")]
+fn platform_occupancy(
+    namespace: Namespace,
+    location_id: &str,
+    date: NaiveDateRocket,
+    turnback_margin_secs: Option<i64>,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    occupancy: &State<PlatformOccupancy>,
+) -> Option<Json<OccupancyReport>> {
+    let (location_ids, _) = get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    )?;
+    let schedule = schedule_manager.get(&namespace.namespace)?;
+    Some(Json(occupancy.report(
+        &schedule,
+        &location_ids,
+        date.0,
+        turnback_margin_secs,
+    )))
 }
 
-struct Namespace {
-    namespace: String,
-    is_public_id: bool,
+// How many direct services there are between two places each day and hour over a range of dates,
+// and who runs them, for comparing service levels
+#[get("/flows/<namespace>/<from_location_id>/<to_location_id>/<from>/<to>")]
+fn flows(
+    namespace: Namespace,
+    from_location_id: &str,
+    to_location_id: &str,
+    from: NaiveDateRocket,
+    to: NaiveDateRocket,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    flows: &State<Arc<Flows>>,
+) -> Option<Json<FlowReport>> {
+    let (from_location_ids, _) = get_location_ids_and_first_tz(
+        from_location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    )?;
+    let (to_location_ids, _) = get_location_ids_and_first_tz(
+        to_location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    )?;
+    let schedule = schedule_manager.get(&namespace.namespace)?;
+    Some(Json(flows.report(
+        &schedule,
+        &from_location_ids,
+        &to_location_ids,
+        from.0,
+        to.0,
+    )))
 }
 
-impl<'a> FromParam<'a> for Namespace {
-    type Error = WebUiError;
+// How long trains stand at a station and how long those terminating there take to turn round,
+// with the distribution of each, over one or more days
+#[get("/dwell/<namespace>/<location_id>/<date>?<days>")]
+fn dwell(
+    namespace: Namespace,
+    location_id: &str,
+    date: NaiveDateRocket,
+    days: Option<u64>,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    dwell: &State<Dwell>,
+) -> Option<Json<DwellReport>> {
+    let (location_ids, _) = get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    )?;
+    let schedule = schedule_manager.get(&namespace.namespace)?;
+    Some(Json(dwell.report(&schedule, &location_ids, date.0, days)))
+}
 
-    fn from_param(param: &'a str) -> Result<Self, Self::Error> {
-        let parts = param.split("-").collect::<Vec<&str>>();
-        if parts.len() != 2 {
-            return Err(WebUiError {
-                what: "Invalid namespace string".to_string(),
-            });
-        }
+// Every train through a location, passes and empty stock included, in working time order with
+// lines, paths, allowances and activities: the operational counterpart to a board. Times are
+// YYYY-MM-DDTHH:MM local to the location; by default, the next hour.
+#[get("/movements/<namespace>/<location_id>?<from>&<to>&<source>")]
+fn movements(
+    namespace: Namespace,
+    location_id: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+    source: Option<TrainSources>,
+    schedule_manager: Schedules,
+    station_groups: &State<StationGroups>,
+    movements: &State<Movements>,
+) -> Result<Json<MovementReport>, Status> {
+    let (location_ids, timezone) = get_location_ids_and_first_tz(
+        location_id,
+        &namespace,
+        (*schedule_manager).clone(),
+        station_groups,
+    )
+    .ok_or(Status::NotFound)?;
+    let parse = |x: &str| {
+        NaiveDateTime::parse_from_str(x, "%Y-%m-%dT%H:%M").map_err(|_| Status::BadRequest)
+    };
+    let from = match from {
+        Some(x) => parse(x)?,
+        None => timezone
+            .from_utc_datetime(&Utc::now().naive_utc())
+            .naive_local(),
+    };
+    let to = match to {
+        Some(x) => parse(x)?,
+        None => from + Duration::hours(1),
+    };
+    if to < from {
+        return Err(Status::BadRequest);
+    }
+    let schedule = schedule_manager
+        .get(&namespace.namespace)
+        .ok_or(Status::NotFound)?;
+    let mut report = movements.report(&schedule, &location_ids, from, to);
+    report
+        .movements
+        .retain(|x| includes_source(&source, x.source));
+    Ok(Json(report))
+}
 
-        match parts[1] {
-            "public" => Ok(Namespace {
-                namespace: parts[0].to_string(),
-                is_public_id: true,
-            }),
-            "internal" => Ok(Namespace {
-                namespace: parts[0].to_string(),
-                is_public_id: false,
-            }),
-            _ => {
-                return Err(WebUiError {
-                    what: "Invalid ID type".to_string(),
-                })
-            }
+// Upstream's answer as it is, but with what our own schedules say about each train and location in
+// it alongside, for apps still moving over from upstream
+#[get("/proxy/<name>/<path..>")]
+async fn proxy(
+    name: &str,
+    path: PathBuf,
+    origin: &Origin<'_>,
+    schedule_manager: Schedules,
+    proxy: &State<Proxy>,
+    http_client: &State<Arc<HttpClient>>,
+) -> Result<Json<serde_json::Value>, Status> {
+    let namespace = proxy.namespace(name).ok_or(Status::NotFound)?;
+    let query = origin.query().map(|x| x.as_str());
+    let mut value = match proxy
+        .fetch(http_client, name, &path.to_string_lossy(), query)
+        .await
+    {
+        Ok(Some(x)) => x,
+        Ok(None) => return Err(Status::NotFound),
+        Err(x) => {
+            println!("WARNING: Proxying {} failed: {}", name, x);
+            return Err(Status::BadGateway);
         }
+    };
+    // still worth having upstream's answer when we've nothing to add to it
+    match schedule_manager.get(namespace) {
+        Some(x) => proxy.enrich(name, &x, &mut value),
+        None => (),
     }
+    Ok(Json(value))
 }
 
-fn get_location_ids_and_first_tz(
-    location_id: &str,
-    namespace: &Namespace,
-    schedule_manager: Arc<ScheduleManager>,
-) -> Option<(HashSet<String>, Tz)> {
-    let schedule_manager = schedule_manager.read();
-    let schedule = &schedule_manager.get(&namespace.namespace)?;
-    match namespace.is_public_id {
-        true => {
-            let locations = schedule.locations_indexed_by_public_id.get(location_id)?;
-            if locations.len() == 0 {
-                return None;
+// Every train running on a date in one go, as JSON Lines, for loading into pandas or DuckDB. Only
+// one namespace if asked. It's streamed as it's made, and gzipped if asked.
+#[get("/dump/trains.jsonl?<date>&<namespace>&<gzip>")]
+fn dump_trains(
+    date: NaiveDateRocket,
+    namespace: Option<&str>,
+    gzip: Option<bool>,
+    schedule_manager: Schedules,
+    redacted: Redacted,
+) -> Result<(ContentType, ByteStream![Vec<u8>]), Status> {
+    let mut schedules = schedule_manager
+        .read()
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+    match namespace {
+        Some(namespace) => {
+            schedules.retain(|x| x.namespace == namespace);
+            if schedules.is_empty() {
+                return Err(Status::NotFound);
             }
-            Some((
-                locations.clone(),
-                schedule
-                    .locations
-                    .get(locations.iter().next().unwrap())
-                    .unwrap()
-                    .timezone
-                    .clone(),
-            ))
-        }
-        false => {
-            let mut location_ids = HashSet::new();
-            location_ids.insert(location_id.to_string());
-            let location = match schedule.locations.get(location_id) {
-                Some(x) => x,
-                None => return None,
-            };
-            Some((location_ids, location.timezone))
         }
+        None => (),
     }
+    let gzip = gzip.unwrap_or(false);
+    let content_type = match gzip {
+        true => ContentType::new("application", "gzip"),
+        false => ContentType::new("application", "x-ndjson"),
+    };
+    let mut dump = TrainDump::new(schedules, date.0, redacted.0, gzip);
+    Ok((
+        content_type,
+        ByteStream! {
+            // a chunk at a time, without holding up anything else
+            loop {
+                let (chunk, rest) = match tokio::task::spawn_blocking(move || {
+                    let chunk = dump.next();
+                    (chunk, dump)
+                })
+                .await
+                {
+                    Ok(x) => x,
+                    Err(_) => break,
+                };
+                dump = rest;
+                match chunk {
+                    Some(x) => yield x,
+                    None => break,
+                }
+            }
+        },
+    ))
 }
 
-#[get("/location/<namespace>/<location_id>")]
-fn location(
-    namespace: Namespace,
-    location_id: &str,
-    schedule_manager: &State<Arc<ScheduleManager>>,
-) -> Option<Template> {
-    let (location_ids, timezone) =
-        get_location_ids_and_first_tz(location_id, &namespace, (*schedule_manager).clone())?;
+// Realtime from the UK feeds as GTFS-RT, for apps that only take that: protobuf unless asked for
+// JSON, with trip updates and alerts together unless asked for one or the other, as some consumers
+// want them as separate feeds
+#[get("/gtfs-rt?<namespace>&<format>&<entities>")]
+fn gtfs_rt(
+    namespace: Option<&str>,
+    format: Option<&str>,
+    entities: Option<&str>,
+    schedule_manager: Schedules,
+    alerts: &State<Alerts>,
+    gtfs_rt: &State<GtfsRt>,
+) -> Result<(ContentType, Vec<u8>), Status> {
+    let schedule = schedule_manager
+        .get(namespace.unwrap_or("gbnr"))
+        .ok_or(Status::NotFound)?;
+    let mut feed = gtfs_rt.feed(&schedule, &alerts.all(), Utc::now());
+    match entities {
+        Some("trip_updates") => feed.entity.retain(|x| x.trip_update.is_some()),
+        Some("alerts") => feed.entity.retain(|x| x.alert.is_some()),
+        Some(_) => return Err(Status::BadRequest),
+        None => (),
+    }
+    match format {
+        Some("json") => Ok((ContentType::JSON, serde_json::to_vec(&feed).unwrap())),
+        Some("protobuf") | None => Ok((
+            ContentType::new("application", "x-protobuf"),
+            feed.to_protobuf(),
+        )),
+        Some(_) => Err(Status::BadRequest),
+    }
+}
 
-    let now = timezone
-        .from_utc_datetime(&Utc::now().naive_utc())
-        .naive_local();
+#[get("/openapi.json")]
+fn openapi() -> Json<serde_json::Value> {
+    Json(openapi_document())
+}
 
-    location_line_up(
-        &namespace.namespace,
-        &location_ids,
-        now - Duration::minutes(30),
-        now + Duration::minutes(120),
-        None,
-        None,
-        (*schedule_manager).clone(),
-    )
+#[get("/docs")]
+fn docs() -> RawHtml<&'static str> {
+    RawHtml(SWAGGER_UI)
 }
 
-#[get("/location/<namespace>/<location_id>/from/<from_id>", rank = 0)]
-fn location_from(
-    namespace: Namespace,
-    location_id: &str,
-    from_id: &str,
-    schedule_manager: &State<Arc<ScheduleManager>>,
-) -> Option<Template> {
-    let (location_ids, timezone) =
-        get_location_ids_and_first_tz(location_id, &namespace, (*schedule_manager).clone())?;
+fn bearer_token_matches(request: &Request<'_>, expected: &str) -> bool {
+    let token = request
+        .headers()
+        .get_one("Authorization")
+        .and_then(|x| x.strip_prefix("Bearer "))
+        .unwrap_or("");
+    // don't leak how much of the token was right through how long this takes
+    token.len() == expected.len()
+        && token
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
 
-    let now = timezone
-        .from_utc_datetime(&Utc::now().naive_utc())
-        .naive_local();
+// Only lets requests through with the configured admin token
+struct Admin<'r> {
+    config: &'r AdminConfig,
+}
 
-    let (from_ids, _timezone) =
-        get_location_ids_and_first_tz(from_id, &namespace, (*schedule_manager).clone())?;
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Admin<'r> {
+    type Error = WebUiError;
 
-    location_line_up(
-        &namespace.namespace,
-        &location_ids,
-        now - Duration::minutes(30),
-        now + Duration::minutes(120),
-        Some(from_ids),
-        None,
-        (*schedule_manager).clone(),
-    )
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = match request.rocket().state::<AdminConfig>() {
+            Some(x) => x,
+            None => {
+                return Outcome::Error((
+                    Status::NotFound,
+                    WebUiError {
+                        what: "Admin endpoints not enabled".to_string(),
+                    },
+                ))
+            }
+        };
+        if !bearer_token_matches(request, &config.token) {
+            return Outcome::Error((
+                Status::Unauthorized,
+                WebUiError {
+                    what: "Bad admin token".to_string(),
+                },
+            ));
+        }
+        Outcome::Success(Admin { config })
+    }
 }
 
-#[get("/location/<namespace>/<location_id>/to/<to_id>", rank = 0)]
-fn location_to(
-    namespace: Namespace,
-    location_id: &str,
-    to_id: &str,
-    schedule_manager: &State<Arc<ScheduleManager>>,
-) -> Option<Template> {
-    let (location_ids, timezone) =
-        get_location_ids_and_first_tz(location_id, &namespace, (*schedule_manager).clone())?;
+// Only lets requests through with the configured upstream token
+struct Upstream;
 
-    let now = timezone
-        .from_utc_datetime(&Utc::now().naive_utc())
-        .naive_local();
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Upstream {
+    type Error = WebUiError;
 
-    let (to_ids, _timezone) =
-        get_location_ids_and_first_tz(to_id, &namespace, (*schedule_manager).clone())?;
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let config = match request.rocket().state::<UpstreamConfig>() {
+            Some(x) => x,
+            None => {
+                return Outcome::Error((
+                    Status::NotFound,
+                    WebUiError {
+                        what: "Upstream notifications not enabled".to_string(),
+                    },
+                ))
+            }
+        };
+        if !bearer_token_matches(request, &config.token) {
+            return Outcome::Error((
+                Status::Unauthorized,
+                WebUiError {
+                    what: "Bad upstream token".to_string(),
+                },
+            ));
+        }
+        Outcome::Success(Upstream)
+    }
+}
 
-    location_line_up(
-        &namespace.namespace,
-        &location_ids,
-        now - Duration::minutes(30),
-        now + Duration::minutes(120),
-        None,
-        Some(to_ids),
-        (*schedule_manager).clone(),
-    )
+// A new extract or daily update for the source (e.g. gbnr-cif) has been published, so fetch it now
+// rather than at the usual time. It then counts as that day's run, so nothing is fetched twice.
+#[post("/upstream/<source>")]
+fn upstream_published(
+    source: &str,
+    _upstream: Upstream,
+    triggers: &State<Arc<Triggers>>,
+) -> Status {
+    match triggers.trigger(source) {
+        true => Status::Accepted,
+        false => Status::NotFound,
+    }
 }
 
-#[get(
-    "/location/<namespace>/<location_id>/from/<from_id>/to/<to_id>",
-    rank = 0
-)]
-fn location_from_to(
-    namespace: Namespace,
-    location_id: &str,
-    from_id: &str,
-    to_id: &str,
-    schedule_manager: &State<Arc<ScheduleManager>>,
-) -> Option<Template> {
-    let (location_ids, timezone) =
-        get_location_ids_and_first_tz(location_id, &namespace, (*schedule_manager).clone())?;
+// what can be notified about, for setting the webhooks up
+#[get("/upstream")]
+fn upstream_sources(_upstream: Upstream, triggers: &State<Arc<Triggers>>) -> Json<Vec<String>> {
+    Json(triggers.sources())
+}
 
-    let now = timezone
-        .from_utc_datetime(&Utc::now().naive_utc())
-        .naive_local();
+// When each source next fetches, and whether it's been paused
+#[get("/admin/sources")]
+async fn admin_sources(_admin: Admin<'_>, triggers: &State<Arc<Triggers>>) -> Json<Vec<RunState>> {
+    Json(triggers.states().await)
+}
 
-    let (from_ids, _timezone) =
-        get_location_ids_and_first_tz(from_id, &namespace, (*schedule_manager).clone())?;
-    let (to_ids, _timezone) =
-        get_location_ids_and_first_tz(to_id, &namespace, (*schedule_manager).clone())?;
+// How the realtime feeds' messages have been reading: fields we don't know yet and anything
+// quarantined
+#[get("/admin/feeds")]
+fn admin_feeds(
+    _admin: Admin<'_>,
+    feed_telemetry: &State<Arc<FeedTelemetry>>,
+) -> Json<BTreeMap<String, SourceTelemetry>> {
+    Json(feed_telemetry.get())
+}
 
-    location_line_up(
-        &namespace.namespace,
-        &location_ids,
-        now - Duration::minutes(30),
-        now + Duration::minutes(120),
-        Some(from_ids),
-        Some(to_ids),
-        (*schedule_manager).clone(),
-    )
+#[get("/admin/sources/<source>")]
+async fn admin_source(
+    _admin: Admin<'_>,
+    source: &str,
+    triggers: &State<Arc<Triggers>>,
+) -> Option<Json<RunState>> {
+    triggers.state(source).await.map(Json)
+}
+
+// Stops a source fetching, e.g. while upstream is known to be publishing rubbish. Anything missed
+// meanwhile is fetched as soon as it's resumed.
+#[post("/admin/sources/<source>/pause")]
+async fn admin_pause(_admin: Admin<'_>, source: &str, triggers: &State<Arc<Triggers>>) -> Status {
+    match triggers.send(source, ManagerCommand::Pause).await {
+        true => Status::Accepted,
+        false => Status::NotFound,
+    }
+}
+
+#[post("/admin/sources/<source>/resume")]
+async fn admin_resume(_admin: Admin<'_>, source: &str, triggers: &State<Arc<Triggers>>) -> Status {
+    match triggers.send(source, ManagerCommand::Resume).await {
+        true => Status::Accepted,
+        false => Status::NotFound,
+    }
+}
+
+// Moves the daily fetch, in the source's own local time, until the next restart
+#[post("/admin/sources/<source>/time/<time>")]
+async fn admin_set_time(
+    _admin: Admin<'_>,
+    source: &str,
+    time: NaiveTimeRocket,
+    triggers: &State<Arc<Triggers>>,
+) -> Status {
+    match triggers.send(source, ManagerCommand::SetTime(time.0)).await {
+        true => Status::Accepted,
+        false => Status::NotFound,
+    }
+}
+
+// Rebuilds the train indexes (or one namespace's) from the trains themselves, saying how far
+// they'd drifted. This isn't a new import, so nothing is archived or notified.
+#[post("/admin/reindex?<namespace>")]
+async fn admin_reindex(
+    _admin: Admin<'_>,
+    namespace: Option<&str>,
+    schedule_manager: Schedules,
+) -> Result<Json<Vec<IndexRebuild>>, Status> {
+    let mut schedule_manager = schedule_manager.transactional_write().await;
+    let mut namespaces = match namespace {
+        Some(x) if schedule_manager.contains_key(x) => vec![x.to_string()],
+        Some(_) => return Err(Status::NotFound),
+        None => schedule_manager.keys().cloned().collect::<Vec<_>>(),
+    };
+    namespaces.sort();
+    let mut rebuilds = vec![];
+    for namespace in namespaces {
+        let mut schedule = schedule_manager.take(&namespace).unwrap();
+        let rebuild = schedule.rebuild_train_indexes();
+        println!(
+            "Reindexed {}: {} stale entries removed, {} missing added",
+            namespace, rebuild.stale_removed, rebuild.missing_added
+        );
+        rebuilds.push(rebuild);
+        schedule_manager.insert(namespace, Arc::new(schedule));
+    }
+    schedule_manager.commit();
+    Ok(Json(rebuilds))
+}
+
+// How downloading from each source has been going since startup
+#[get("/admin/downloads")]
+fn admin_downloads(
+    _admin: Admin<'_>,
+    http_client: &State<Arc<HttpClient>>,
+) -> Json<Vec<DownloadMetrics>> {
+    Json(http_client.metrics())
 }
 
-#[get("/location/<namespace>/<location_id>/<date>/<time>", rank = 1)]
-fn location_time(
-    namespace: Namespace,
-    location_id: &str,
-    date: NaiveDateRocket,
-    time: NaiveTimeRocket,
-    schedule_manager: &State<Arc<ScheduleManager>>,
-) -> Option<Template> {
-    let (location_ids, _timezone) =
-        get_location_ids_and_first_tz(location_id, &namespace, (*schedule_manager).clone())?;
+// Everything we have (or one namespace), to restore somewhere else without fetching it all again
+#[get("/admin/snapshot?<namespace>")]
+async fn admin_snapshot(
+    _admin: Admin<'_>,
+    namespace: Option<&str>,
+    schedule_manager: Schedules,
+) -> Result<(ContentType, Vec<u8>), Status> {
+    let mut schedules = schedule_manager.read().clone(); // only clones the pointers
+    match namespace {
+        Some(namespace) => {
+            schedules.retain(|k, _| k == namespace);
+            if schedules.is_empty() {
+                return Err(Status::NotFound);
+            }
+        }
+        None => (),
+    }
+    match write_snapshot(schedules).await {
+        Ok(x) => Ok((ContentType::Binary, x)),
+        Err(x) => {
+            println!("Failed to write snapshot: {}", x);
+            Err(Status::InternalServerError)
+        }
+    }
+}
 
-    location_line_up(
-        &namespace.namespace,
-        &location_ids,
-        date.0.and_time(time.0) - Duration::minutes(30),
-        date.0.and_time(time.0) + Duration::minutes(120),
-        None,
-        None,
-        (*schedule_manager).clone(),
-    )
+// The replayer, if there's a download cache to replay from. Rocket won't launch with an
+// Option<&State<_>> of something that isn't managed, hence a guard.
+struct Replaying<'r> {
+    replayer: &'r Replayer,
 }
 
-#[get(
-    "/location/<namespace>/<location_id>/from/<from_id>/<date>/<time>",
-    rank = 1
-)]
-fn location_from_time(
-    namespace: Namespace,
-    location_id: &str,
-    from_id: &str,
-    date: NaiveDateRocket,
-    time: NaiveTimeRocket,
-    schedule_manager: &State<Arc<ScheduleManager>>,
-) -> Option<Template> {
-    let (location_ids, _timezone) =
-        get_location_ids_and_first_tz(location_id, &namespace, (*schedule_manager).clone())?;
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Replaying<'r> {
+    type Error = WebUiError;
 
-    let (from_ids, _timezone) =
-        get_location_ids_and_first_tz(from_id, &namespace, (*schedule_manager).clone())?;
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        match request.rocket().state::<Arc<Replayer>>() {
+            Some(x) => Outcome::Success(Replaying { replayer: x }),
+            None => Outcome::Error((
+                Status::NotFound,
+                WebUiError {
+                    what: "No download cache to replay from".to_string(),
+                },
+            )),
+        }
+    }
+}
 
-    location_line_up(
-        &namespace.namespace,
-        &location_ids,
-        date.0.and_time(time.0) - Duration::minutes(30),
-        date.0.and_time(time.0) + Duration::minutes(120),
-        Some(from_ids),
-        None,
-        (*schedule_manager).clone(),
-    )
+// The gbnr schedule as we had it at some point in the past, rebuilt from the download cache and
+// the VSTP log, as a snapshot for restoring somewhere to look into. This is a full CIF import, so
+// it takes a while; the replay subcommand does the same without a running server.
+#[get("/admin/replay?<at>")]
+async fn admin_replay(
+    _admin: Admin<'_>,
+    at: &str,
+    replaying: Replaying<'_>,
+) -> Result<(ContentType, Vec<u8>), Status> {
+    let replayer = replaying.replayer;
+    let at = match parse_as_of(at) {
+        Ok(x) => x,
+        Err(_) => return Err(Status::BadRequest),
+    };
+    let schedule = match replayer.replay(at).await {
+        Ok((schedule, summary)) => {
+            println!(
+                "Replayed gbnr to {}: full extract from {}, updates {:?}, {} VSTP messages",
+                summary.at, summary.full_extract_fetched, summary.updates, summary.vstp_applied
+            );
+            schedule
+        }
+        Err(x) => {
+            println!("Failed to replay to {}: {}", at, x);
+            return Err(Status::InternalServerError);
+        }
+    };
+    let schedules = HashMap::from([(schedule.namespace.clone(), Arc::new(schedule))]);
+    match write_snapshot(schedules).await {
+        Ok(x) => Ok((ContentType::Binary, x)),
+        Err(x) => {
+            println!("Failed to write snapshot: {}", x);
+            Err(Status::InternalServerError)
+        }
+    }
 }
 
-#[get(
-    "/location/<namespace>/<location_id>/to/<to_id>/<date>/<time>",
-    rank = 1
-)]
-fn location_to_time(
-    namespace: Namespace,
+// A departure poster for one location over a range of dates, as CSV, XLSX or PDF. For a lot of
+// stations at once, the export subcommand works from a snapshot.
+#[get("/admin/export/<namespace>/<location_id>/<from>/<to>?<format>")]
+fn admin_export(
+    _admin: Admin<'_>,
+    namespace: &str,
     location_id: &str,
-    to_id: &str,
-    date: NaiveDateRocket,
-    time: NaiveTimeRocket,
-    schedule_manager: &State<Arc<ScheduleManager>>,
-) -> Option<Template> {
-    let (location_ids, _timezone) =
-        get_location_ids_and_first_tz(location_id, &namespace, (*schedule_manager).clone())?;
-
-    let (to_ids, _timezone) =
-        get_location_ids_and_first_tz(to_id, &namespace, (*schedule_manager).clone())?;
+    from: NaiveDateRocket,
+    to: NaiveDateRocket,
+    format: Option<&str>,
+    schedule_manager: Schedules,
+) -> Result<(ContentType, Vec<u8>), Status> {
+    let format = match format {
+        Some(x) => ExportFormat::parse(x).ok_or(Status::BadRequest)?,
+        None => ExportFormat::Csv,
+    };
+    if to.0 < from.0 || (to.0 - from.0).num_days() > 400 {
+        return Err(Status::BadRequest);
+    }
+    let schedule = schedule_manager.get(namespace).ok_or(Status::NotFound)?;
+    let poster = departures_poster(&schedule, location_id, from.0, to.0).ok_or(Status::NotFound)?;
+    Ok((export_content_type(format), export(&poster, format)))
+}
 
-    location_line_up(
-        &namespace.namespace,
-        &location_ids,
-        date.0.and_time(time.0) - Duration::minutes(30),
-        date.0.and_time(time.0) + Duration::minutes(120),
-        None,
-        Some(to_ids),
-        (*schedule_manager).clone(),
-    )
+fn export_content_type(format: ExportFormat) -> ContentType {
+    match format {
+        ExportFormat::Csv => ContentType::CSV,
+        ExportFormat::Pdf => ContentType::PDF,
+        ExportFormat::Xlsx => ContentType::new(
+            "application",
+            "vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        ),
+    }
 }
 
-#[get(
-    "/location/<namespace>/<location_id>/from/<from_id>/to/<to_id>/<date>/<time>",
-    rank = 1
-)]
-fn location_from_to_time(
-    namespace: Namespace,
+// A week's timetable for one location, a row for each train and a column for each day, as CSV,
+// XLSX or PDF. The export-weekly subcommand does lots at once.
+#[get("/admin/weekly/<namespace>/<location_id>/<week_commencing>?<format>")]
+fn admin_weekly(
+    _admin: Admin<'_>,
+    namespace: &str,
     location_id: &str,
-    from_id: &str,
-    to_id: &str,
-    date: NaiveDateRocket,
-    time: NaiveTimeRocket,
-    schedule_manager: &State<Arc<ScheduleManager>>,
-) -> Option<Template> {
-    let (location_ids, _timezone) =
-        get_location_ids_and_first_tz(location_id, &namespace, (*schedule_manager).clone())?;
+    week_commencing: NaiveDateRocket,
+    format: Option<&str>,
+    schedule_manager: Schedules,
+) -> Result<(ContentType, Vec<u8>), Status> {
+    let format = match format {
+        Some(x) => ExportFormat::parse(x).ok_or(Status::BadRequest)?,
+        None => ExportFormat::Csv,
+    };
+    let schedule = schedule_manager.get(namespace).ok_or(Status::NotFound)?;
+    let weekly =
+        weekly_timetable(&schedule, location_id, week_commencing.0).ok_or(Status::NotFound)?;
+    Ok((export_content_type(format), export_weekly(&weekly, format)))
+}
 
-    let (from_ids, _timezone) =
-        get_location_ids_and_first_tz(from_id, &namespace, (*schedule_manager).clone())?;
-    let (to_ids, _timezone) =
-        get_location_ids_and_first_tz(to_id, &namespace, (*schedule_manager).clone())?;
+// Swaps in every namespace in the snapshot, leaving any others alone. Managers carry on updating
+// from there, so the snapshot should be from the same timetable period as the upstream data.
+#[post("/admin/restore", data = "<data>")]
+async fn admin_restore(
+    admin: Admin<'_>,
+    data: Data<'_>,
+    schedule_manager: Schedules,
+) -> Result<Json<Vec<String>>, Status> {
+    let limit = admin.config.max_restore_mb.unwrap_or(512).mebibytes();
+    let data = match data.open(limit).into_bytes().await {
+        Ok(x) if x.is_complete() => x.into_inner(),
+        Ok(_) => return Err(Status::PayloadTooLarge),
+        Err(x) => {
+            println!("Failed to receive snapshot: {}", x);
+            return Err(Status::BadRequest);
+        }
+    };
+    let unpacked_limit = admin
+        .config
+        .max_restore_unpacked_mb
+        .unwrap_or(4096)
+        .mebibytes();
+    let schedules = match read_snapshot(data, Some(unpacked_limit.as_u64())).await {
+        Ok(x) => x,
+        Err(Error::SnapshotError(x)) if x.too_large() => {
+            println!("Failed to read snapshot: {}", x);
+            return Err(Status::PayloadTooLarge);
+        }
+        Err(x) => {
+            println!("Failed to read snapshot: {}", x);
+            return Err(Status::BadRequest);
+        }
+    };
 
-    location_line_up(
-        &namespace.namespace,
-        &location_ids,
-        date.0.and_time(time.0) - Duration::minutes(30),
-        date.0.and_time(time.0) + Duration::minutes(120),
-        Some(from_ids),
-        Some(to_ids),
-        (*schedule_manager).clone(),
-    )
+    let mut namespaces = schedules.keys().cloned().collect::<Vec<_>>();
+    namespaces.sort();
+    println!("Restoring snapshot of {}", namespaces.join(", "));
+    let mut schedule_manager = schedule_manager.transactional_write().await;
+    for (namespace, schedule) in schedules {
+        // not put(), as this isn't a new import
+        schedule_manager.insert(namespace, Arc::new(schedule));
+    }
+    schedule_manager.archive();
+    schedule_manager.commit();
+
+    Ok(Json(namespaces))
 }
 
-#[get(
-    "/location/<namespace>/<location_id>/<date>/<from_time>/to/<to_time>",
-    rank = 2
-)]
-fn location_time_to(
-    namespace: Namespace,
-    location_id: &str,
-    date: NaiveDateRocket,
-    from_time: NaiveTimeRocket,
-    to_time: NaiveTimeRocket,
-    schedule_manager: &State<Arc<ScheduleManager>>,
-) -> Option<Template> {
-    let (location_ids, _timezone) =
-        get_location_ids_and_first_tz(location_id, &namespace, (*schedule_manager).clone())?;
+// Runs a file through an importer to see what it would make of it. Given a namespace, the file is
+// applied to a copy of that namespace's schedule, as an update would be.
+#[post("/admin/validate?<format>&<namespace>", data = "<data>")]
+async fn admin_validate(
+    admin: Admin<'_>,
+    format: Option<&str>,
+    namespace: Option<&str>,
+    data: Data<'_>,
+    schedule_manager: Schedules,
+) -> Result<Json<ValidationReport>, Status> {
+    let limit = admin.config.max_restore_mb.unwrap_or(512).mebibytes();
+    let data = match data.open(limit).into_bytes().await {
+        Ok(x) if x.is_complete() => x.into_inner(),
+        Ok(_) => return Err(Status::PayloadTooLarge),
+        Err(x) => {
+            println!("Failed to receive file to validate: {}", x);
+            return Err(Status::BadRequest);
+        }
+    };
+    let base = match namespace {
+        Some(x) => match schedule_manager.get(x) {
+            Some(x) => (*x).clone(),
+            None => return Err(Status::NotFound),
+        },
+        None => Schedule::new("validation".to_string(), "Validation".to_string()),
+    };
 
-    let to_date = if to_time.0 < from_time.0 {
-        date.0 + Days::new(1)
-    } else {
-        date.0
+    let result = match format.unwrap_or("cif") {
+        "cif" => {
+            let config = namespace
+                .and_then(|x| admin.config.validation.as_ref()?.get(x).cloned())
+                .unwrap_or_default();
+            validate_cif(data, config, base).await
+        }
+        "gtfs" => validate_gtfs(data, base).await,
+        _ => return Err(Status::BadRequest),
     };
+    match result {
+        Ok(x) => Ok(Json(x)),
+        Err(x) => {
+            println!("Failed to validate file: {}", x);
+            Err(Status::UnprocessableEntity)
+        }
+    }
+}
 
-    location_line_up(
-        &namespace.namespace,
-        &location_ids,
-        date.0.and_time(from_time.0),
-        to_date.and_time(to_time.0),
-        None,
-        None,
-        (*schedule_manager).clone(),
+// Whatever's up at the moment, optionally only those about a namespace, operator or location
+// (alerts that aren't scoped that way are left out)
+#[get("/alerts?<namespace>&<operator_id>&<location_id>")]
+fn current_alerts(
+    namespace: Option<&str>,
+    operator_id: Option<&str>,
+    location_id: Option<&str>,
+    alerts: &State<Alerts>,
+) -> Json<Vec<Alert>> {
+    let now = Utc::now();
+    Json(
+        alerts
+            .all()
+            .into_iter()
+            .filter(|x| {
+                x.is_current(now)
+                    && [
+                        (namespace, &x.alert.namespace),
+                        (operator_id, &x.alert.operator_id),
+                        (location_id, &x.alert.location_id),
+                    ]
+                    .iter()
+                    .all(|(wanted, scope)| wanted.is_none() || *wanted == scope.as_deref())
+            })
+            .collect(),
     )
 }
 
-#[get(
-    "/location/<namespace>/<location_id>/from/<from_id>/<date>/<from_time>/to/<to_time>",
-    rank = 2
-)]
-fn location_from_time_to(
-    namespace: Namespace,
-    location_id: &str,
-    from_id: &str,
-    date: NaiveDateRocket,
-    from_time: NaiveTimeRocket,
-    to_time: NaiveTimeRocket,
-    schedule_manager: &State<Arc<ScheduleManager>>,
-) -> Option<Template> {
-    let (location_ids, _timezone) =
-        get_location_ids_and_first_tz(location_id, &namespace, (*schedule_manager).clone())?;
+#[get("/admin/alerts")]
+fn admin_alerts(_admin: Admin<'_>, alerts: &State<Alerts>) -> Json<Vec<Alert>> {
+    Json(alerts.all())
+}
 
-    let to_date = if to_time.0 < from_time.0 {
-        date.0 + Days::new(1)
-    } else {
-        date.0
-    };
+#[post("/admin/alerts", data = "<alert>")]
+fn admin_add_alert(
+    _admin: Admin<'_>,
+    alert: Json<NewAlert>,
+    alerts: &State<Alerts>,
+) -> Result<Json<Alert>, Status> {
+    let alert = alert.into_inner();
+    if alert.message.trim().is_empty() {
+        return Err(Status::BadRequest);
+    }
+    match alerts.add(alert) {
+        Ok(x) => Ok(Json(x)),
+        Err(x) => {
+            println!("Failed to save alerts: {}", x);
+            Err(Status::InternalServerError)
+        }
+    }
+}
 
-    let (from_ids, _timezone) =
-        get_location_ids_and_first_tz(from_id, &namespace, (*schedule_manager).clone())?;
+#[post("/admin/alerts/<id>/expire")]
+fn admin_expire_alert(_admin: Admin<'_>, id: &str, alerts: &State<Alerts>) -> Status {
+    match alerts.expire(id) {
+        Ok(true) => Status::NoContent,
+        Ok(false) => Status::NotFound,
+        Err(x) => {
+            println!("Failed to save alerts: {}", x);
+            Status::InternalServerError
+        }
+    }
+}
 
-    location_line_up(
-        &namespace.namespace,
-        &location_ids,
-        date.0.and_time(from_time.0),
-        to_date.and_time(to_time.0),
-        Some(from_ids),
-        None,
-        (*schedule_manager).clone(),
-    )
+#[delete("/admin/alerts/<id>")]
+fn admin_delete_alert(_admin: Admin<'_>, id: &str, alerts: &State<Alerts>) -> Status {
+    match alerts.delete(id) {
+        Ok(true) => Status::NoContent,
+        Ok(false) => Status::NotFound,
+        Err(x) => {
+            println!("Failed to save alerts: {}", x);
+            Status::InternalServerError
+        }
+    }
 }
 
-#[get(
-    "/location/<namespace>/<location_id>/to/<to_id>/<date>/<from_time>/to/<to_time>",
-    rank = 2
-)]
-fn location_to_time_to(
-    namespace: Namespace,
-    location_id: &str,
-    to_id: &str,
-    date: NaiveDateRocket,
-    from_time: NaiveTimeRocket,
-    to_time: NaiveTimeRocket,
-    schedule_manager: &State<Arc<ScheduleManager>>,
-) -> Option<Template> {
-    let (location_ids, _timezone) =
-        get_location_ids_and_first_tz(location_id, &namespace, (*schedule_manager).clone())?;
+#[get("/cache/stats")]
+fn cache_stats(query_cache: &State<BoardCache>) -> Json<QueryCacheStats> {
+    Json(query_cache.stats())
+}
 
-    let to_date = if to_time.0 < from_time.0 {
-        date.0 + Days::new(1)
-    } else {
-        date.0
-    };
+#[derive(Deserialize)]
+struct SubscriptionRequest {
+    interest: Interest,
+    webhook: Option<String>, // notifications are POSTed here as well as kept for polling
+}
 
-    let (to_ids, _timezone) =
-        get_location_ids_and_first_tz(to_id, &namespace, (*schedule_manager).clone())?;
+#[derive(Serialize)]
+struct SubscriptionCreated {
+    id: String,
+}
 
-    location_line_up(
-        &namespace.namespace,
-        &location_ids,
-        date.0.and_time(from_time.0),
-        to_date.and_time(to_time.0),
-        None,
-        Some(to_ids),
-        (*schedule_manager).clone(),
-    )
+#[post("/subscriptions", data = "<request>")]
+fn subscribe(
+    request: Json<SubscriptionRequest>,
+    notifications: &State<Arc<Notifications>>,
+) -> Result<Json<SubscriptionCreated>, Status> {
+    let request = request.into_inner();
+    match &request.webhook {
+        Some(x) if !notifications.webhook_allowed(x) => return Err(Status::BadRequest),
+        _ => (),
+    }
+    match notifications.subscribe(request.interest, request.webhook) {
+        Some(id) => Ok(Json(SubscriptionCreated { id })),
+        None => Err(Status::ServiceUnavailable),
+    }
 }
 
-#[get(
-    "/location/<namespace>/<location_id>/from/<from_id>/to/<to_id>/<date>/<from_time>/to/<to_time>",
-    rank = 2
-)]
-fn location_from_to_time_to(
-    namespace: Namespace,
-    location_id: &str,
-    from_id: &str,
-    to_id: &str,
-    date: NaiveDateRocket,
-    from_time: NaiveTimeRocket,
-    to_time: NaiveTimeRocket,
-    schedule_manager: &State<Arc<ScheduleManager>>,
-) -> Option<Template> {
-    let (location_ids, _timezone) =
-        get_location_ids_and_first_tz(location_id, &namespace, (*schedule_manager).clone())?;
+#[get("/subscriptions/<id>/events?<after>")]
+fn subscription_events(
+    id: &str,
+    after: Option<u64>,
+    notifications: &State<Arc<Notifications>>,
+) -> Option<Json<Vec<Notification>>> {
+    Some(Json(notifications.poll(id, after)?))
+}
 
-    let to_date = if to_time.0 < from_time.0 {
-        date.0 + Days::new(1)
-    } else {
-        date.0
-    };
+// CORS preflights, for any path; Heading says what's allowed
+#[options("/<_..>")]
+fn preflight() -> Status {
+    Status::NoContent
+}
 
-    let (from_ids, _timezone) =
-        get_location_ids_and_first_tz(from_id, &namespace, (*schedule_manager).clone())?;
-    let (to_ids, _timezone) =
-        get_location_ids_and_first_tz(to_id, &namespace, (*schedule_manager).clone())?;
+#[delete("/subscriptions/<id>")]
+fn unsubscribe(id: &str, notifications: &State<Arc<Notifications>>) -> Status {
+    match notifications.unsubscribe(id) {
+        true => Status::NoContent,
+        false => Status::NotFound,
+    }
+}
 
-    location_line_up(
-        &namespace.namespace,
-        &location_ids,
-        date.0.and_time(from_time.0),
-        to_date.and_time(to_time.0),
-        Some(from_ids),
-        Some(to_ids),
-        (*schedule_manager).clone(),
-    )
+// Everything we answer, as mounted at the root and under each environment's prefix
+pub fn routes() -> Vec<rocket::Route> {
+    routes![
+        index,
+        train,
+        train_calendar,
+        service,
+        service_by_global_id,
+        train_geometry,
+        train_geometry_by_global_id,
+        train_history,
+        live_train_positions,
+        uic_trains,
+        next_trains,
+        region_departures,
+        location_search,
+        freight,
+        stats,
+        quality,
+        pending_overlays,
+        status,
+        healthz,
+        readyz,
+        operators,
+        interchange,
+        platform_occupancy,
+        flows,
+        dwell,
+        movements,
+        dump_trains,
+        gtfs_rt,
+        proxy,
+        cache_stats,
+        current_alerts,
+        admin_alerts,
+        admin_add_alert,
+        admin_expire_alert,
+        admin_delete_alert,
+        admin_snapshot,
+        admin_restore,
+        admin_validate,
+        admin_export,
+        admin_weekly,
+        admin_replay,
+        admin_reindex,
+        admin_sources,
+        admin_feeds,
+        admin_source,
+        admin_pause,
+        admin_resume,
+        admin_set_time,
+        admin_downloads,
+        upstream_published,
+        upstream_sources,
+        subscribe,
+        subscription_events,
+        unsubscribe,
+        preflight,
+        openapi,
+        docs,
+        location,
+        location_from,
+        location_to,
+        location_from_to,
+        location_time,
+        location_from_time,
+        location_to_time,
+        location_from_to_time,
+        location_time_to,
+        location_from_time_to,
+        location_to_time_to,
+        location_from_to_time_to
+    ]
 }
 
-pub async fn rocket(schedule_manager: Arc<ScheduleManager>) -> Result<(), Error> {
-    rocket::build()
-        .mount(
-            "/",
-            routes![
-                index,
-                train,
-                location,
-                location_from,
-                location_to,
-                location_from_to,
-                location_time,
-                location_from_time,
-                location_to_time,
-                location_from_to_time,
-                location_time_to,
-                location_from_time_to,
-                location_to_time_to,
-                location_from_to_time_to
-            ],
-        )
-        .attach(Template::fairing())
-        .manage(schedule_manager)
-        .launch()
-        .await?;
+pub async fn rocket(
+    environments: Environments,
+    notifications: Arc<Notifications>,
+    staleness: Arc<Staleness>,
+    integrity: Arc<Integrity>,
+    triggers: Arc<Triggers>,
+    replayer: Option<Arc<Replayer>>,
+    http_client: Arc<HttpClient>,
+    feed_telemetry: Arc<FeedTelemetry>,
+    config: WebUiConfig,
+) -> Result<(), Error> {
+    build(
+        environments,
+        notifications,
+        staleness,
+        integrity,
+        triggers,
+        replayer,
+        http_client,
+        feed_telemetry,
+        config,
+    )?
+    .launch()
+    .await?;
 
     Err(Error::WebUiError(WebUiError {
         what: "Shutdown requested".to_string(),
     }))
 }
+
+// Everything but the launch, so tests can ask it things without a socket
+pub fn build(
+    environments: Environments,
+    notifications: Arc<Notifications>,
+    staleness: Arc<Staleness>,
+    integrity: Arc<Integrity>,
+    triggers: Arc<Triggers>,
+    replayer: Option<Arc<Replayer>>,
+    http_client: Arc<HttpClient>,
+    feed_telemetry: Arc<FeedTelemetry>,
+    config: WebUiConfig,
+) -> Result<rocket::Rocket<rocket::Build>, Error> {
+    let schedule_manager = environments.main().clone();
+    let query_cache: BoardCache = QueryCache::new(std::time::Duration::from_secs(
+        config.query_cache_ttl_secs.unwrap_or(60),
+    ));
+
+    let mut localisation = Localisation::new(config.localisation.unwrap_or_default());
+    for filename in config.localisation_files.unwrap_or_default() {
+        localisation.load_file(&filename)?;
+    }
+
+    let interchange = Interchange::new(config.interchange.unwrap_or_default())?;
+    let route_geometry = RouteGeometry::new(config.route_geometry.unwrap_or_default())?;
+    let regions = Regions::new(config.regions.unwrap_or_default())?;
+    let station_groups = StationGroups::new(config.station_groups.unwrap_or_default())?;
+    let alerts = Alerts::new(config.alerts.unwrap_or_default())?;
+    let platform_occupancy = PlatformOccupancy::new(config.platform_occupancy.unwrap_or_default());
+
+    let dwell = Dwell::new(config.dwell.unwrap_or_default());
+    let movements = Movements::new(config.movements.unwrap_or_default());
+    let proxy = Proxy::new(config.proxy.unwrap_or_default());
+    let gtfs_rt = GtfsRt::new(config.gtfs_rt.unwrap_or_default());
+    let redaction = Redaction::new(config.redaction.unwrap_or_default());
+    let headers = ResponseHeaders::new(config.headers.unwrap_or_default());
+    let branding = OperatorBranding::new(config.branding.unwrap_or_default())?;
+    let flows = Arc::new(Flows::new(config.flows.unwrap_or_default()));
+    {
+        let flows = flows.clone();
+        schedule_manager.on_import_complete(move |_, schedule| {
+            tokio::spawn(flows.clone().refresh(schedule.clone()));
+        });
+    }
+    let duplicates = Arc::new(Duplicates::new(config.duplicates.unwrap_or_default()));
+    {
+        let duplicates = duplicates.clone();
+        schedule_manager.on_import_complete(move |_, schedule| {
+            tokio::spawn(duplicates.clone().refresh(schedule.clone()));
+        });
+    }
+    let quality = Arc::new(Quality::new(config.quality.unwrap_or_default()));
+    {
+        let quality = quality.clone();
+        schedule_manager.on_import_complete(move |_, schedule| {
+            let quality = quality.clone();
+            let schedule = schedule.clone();
+            tokio::task::spawn_blocking(move || quality.record(&schedule));
+        });
+    }
+
+    let mut rocket = rocket::build();
+    match config.admin {
+        Some(x) => rocket = rocket.manage(x),
+        None => (),
+    }
+    match config.upstream {
+        Some(x) => rocket = rocket.manage(x),
+        None => (),
+    }
+    match replayer {
+        Some(x) => rocket = rocket.manage(x),
+        None => (),
+    }
+
+    let routes = routes();
+    // when we're only here to stand in for upstream
+    let routes = match proxy.only() {
+        true => routes![proxy, status, healthz, preflight, openapi, docs],
+        false => routes,
+    };
+    // the same again for each other environment, under its own prefix
+    for name in environments.names() {
+        rocket = rocket.mount(format!("/env/{}", name), routes.clone());
+    }
+
+    Ok(rocket
+        .mount("/", routes)
+        .attach(Template::fairing())
+        .attach(Redacting)
+        .attach(Listing)
+        .attach(OutputFormatting)
+        .attach(Heading)
+        .manage(schedule_manager)
+        .manage(environments)
+        .manage(notifications)
+        .manage(staleness)
+        .manage(integrity)
+        .manage(triggers)
+        .manage(http_client)
+        .manage(feed_telemetry)
+        .manage(query_cache)
+        .manage(localisation)
+        .manage(interchange)
+        .manage(route_geometry)
+        .manage(regions)
+        .manage(station_groups)
+        .manage(alerts)
+        .manage(duplicates)
+        .manage(platform_occupancy)
+        .manage(flows)
+        .manage(quality)
+        .manage(dwell)
+        .manage(movements)
+        .manage(proxy)
+        .manage(gtfs_rt)
+        .manage(redaction)
+        .manage(headers)
+        .manage(branding)
+        .manage(ConditionalPaths {
+            shown: config.show_runs_as_required.unwrap_or(false),
+        }))
+}
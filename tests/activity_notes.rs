@@ -0,0 +1,52 @@
+// Activities at each call, in words rather than flags
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::redaction::RedactionPolicy;
+use worldrailtimetables::resolved_train::resolve_train;
+use worldrailtimetables::schedule::ActivityCategory;
+
+use chrono::NaiveDate;
+use serde_json::json;
+
+#[tokio::test]
+async fn activities_are_described() {
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let date = NaiveDate::from_ymd_opt(2026, 6, 2).unwrap();
+
+    let train = resolve_train(&schedule, "C10001", date).unwrap();
+    let notes = &train.route[0].activity_notes;
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0].activity, "train_begins");
+    assert_eq!(notes[0].category, ActivityCategory::Passenger);
+    assert_eq!(notes[0].note, "Train begins");
+    assert_eq!(
+        train.route[1].activity_notes[0].note,
+        "Picks up and sets down passengers"
+    );
+    // passing Bletchley, there's nothing to say
+    assert!(train.route[2].activity_notes.is_empty());
+
+    // a crew change at Watford isn't for the public
+    let versions = schedule.trains.get_mut("C10001").unwrap();
+    let location = &mut versions[0].route[1];
+    location.activities.crew_change = true;
+    let train = resolve_train(&schedule, "C10001", date).unwrap();
+    let categories = train.route[1]
+        .activity_notes
+        .iter()
+        .map(|x| x.category)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        categories,
+        vec![ActivityCategory::Passenger, ActivityCategory::Operational]
+    );
+
+    let policy = serde_json::from_value::<RedactionPolicy>(json!({ "activities": true })).unwrap();
+    let mut value = serde_json::to_value(train).unwrap();
+    assert!(policy.apply(&mut value));
+    let notes = value["route"][1]["activity_notes"].as_array().unwrap();
+    assert_eq!(notes.len(), 1);
+    assert_eq!(notes[0]["category"], "Passenger");
+}
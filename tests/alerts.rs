@@ -0,0 +1,94 @@
+// Alerts about the data, shown with what they're scoped to and kept across restarts
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::alerts::{Alert, AlertSeverity, Alerts, AlertsConfig, NewAlert};
+
+use chrono::{Duration, Utc};
+
+fn alert(message: &str) -> NewAlert {
+    NewAlert {
+        message: message.to_string(),
+        severity: AlertSeverity::Warning,
+        starts: None,
+        expires: None,
+        namespace: None,
+        operator_id: None,
+        location_id: None,
+    }
+}
+
+fn config(file: &std::path::Path) -> AlertsConfig {
+    serde_json::from_value(serde_json::json!({ "file": file })).unwrap()
+}
+
+#[tokio::test]
+async fn alerts_are_scoped_and_saved() {
+    let file = std::env::temp_dir().join(format!("alerts-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&file);
+    let alerts = Alerts::new(config(&file)).unwrap();
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let train = &schedule.trains["C10001"][0];
+
+    let everywhere = alerts.add(alert("Everything is late")).unwrap();
+    alerts
+        .add(NewAlert {
+            namespace: Some("gbnr".to_string()),
+            location_id: Some("NMPTN".to_string()),
+            ..alert("Northampton data missing")
+        })
+        .unwrap();
+    alerts
+        .add(NewAlert {
+            location_id: Some("WMBYICD".to_string()),
+            ..alert("Depot moves not shown")
+        })
+        .unwrap();
+    alerts
+        .add(NewAlert {
+            operator_id: Some("LM".to_string()),
+            ..alert("LM trains missing")
+        })
+        .unwrap();
+    alerts
+        .add(NewAlert {
+            starts: Some(Utc::now() + Duration::days(1)),
+            ..alert("Tomorrow")
+        })
+        .unwrap();
+
+    let messages = |x: Vec<Alert>| x.into_iter().map(|y| y.alert.message).collect::<Vec<_>>();
+    assert_eq!(
+        messages(alerts.for_train("gbnr", train)),
+        vec![
+            "Everything is late",
+            "Northampton data missing",
+            "LM trains missing"
+        ]
+    );
+    assert_eq!(
+        messages(alerts.for_train("gbni", train)),
+        vec!["Everything is late", "LM trains missing"]
+    );
+    assert_eq!(
+        messages(alerts.current(None, &[], [])),
+        vec!["Everything is late"]
+    );
+
+    assert!(alerts.expire(&everywhere.id).unwrap());
+    assert!(!alerts.expire("nonsense").unwrap());
+    assert!(alerts.current(None, &[], []).is_empty());
+
+    // back as they were after a restart, including the expired one
+    let reloaded = Alerts::new(config(&file)).unwrap();
+    assert_eq!(reloaded.all().len(), 5);
+    assert_eq!(
+        messages(reloaded.for_train("gbnr", train)),
+        vec!["Northampton data missing", "LM trains missing"]
+    );
+    assert!(reloaded.delete(&everywhere.id).unwrap());
+    assert_eq!(Alerts::new(config(&file)).unwrap().all().len(), 4);
+
+    std::fs::remove_file(&file).unwrap();
+}
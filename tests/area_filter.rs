@@ -0,0 +1,65 @@
+// Only keeping trains that call somewhere in the configured area
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::area_filter::{AreaFilter, AreaFilterConfig};
+use worldrailtimetables::consistency::check_schedule;
+use worldrailtimetables::schedule_manager::ScheduleManager;
+
+use serde_json::json;
+
+#[tokio::test]
+async fn drops_trains_outside_area() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let config: AreaFilterConfig =
+        serde_json::from_value(json!({ "locations": { "gbnr": ["BLTCHLY"] } })).unwrap();
+    let schedule_manager = ScheduleManager::new();
+    schedule_manager.set_area_filter(AreaFilter::new(config).unwrap());
+
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.put("gbnr", schedule);
+    transaction.archive();
+    transaction.commit();
+
+    let schedule = schedule_manager.get("gbnr").unwrap();
+    // C10001 passes Bletchley, and C10002 forms it
+    let mut train_ids = schedule.trains.keys().collect::<Vec<_>>();
+    train_ids.sort();
+    assert_eq!(train_ids, vec!["C10001", "C10002"]);
+    assert!(!schedule.trains_indexed_by_public_id.contains_key("2N99"));
+    assert_eq!(schedule.locations.len(), 6);
+    assert_eq!(check_schedule(&schedule), vec![]);
+
+    // other namespaces are left alone
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    schedule.namespace = "gbni".to_string();
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.put("gbni", schedule);
+    transaction.commit();
+    assert_eq!(schedule_manager.get("gbni").unwrap().trains.len(), 3);
+}
+
+#[tokio::test]
+async fn small_updates_only_check_the_trains_they_change() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let config: AreaFilterConfig =
+        serde_json::from_value(json!({ "locations": { "gbnr": ["BLTCHLY"] } })).unwrap();
+    let schedule_manager = ScheduleManager::new();
+    schedule_manager.set_area_filter(AreaFilter::new(config).unwrap());
+
+    // nothing changed, so nothing's checked, even though C10003 is outside the area
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.put("gbnr", schedule);
+    transaction.commit();
+    assert_eq!(schedule_manager.get("gbnr").unwrap().trains.len(), 3);
+
+    let mut transaction = schedule_manager.transactional_write().await;
+    let schedule = transaction.take("gbnr").unwrap();
+    transaction.train_changed("gbnr", "C10003");
+    transaction.put("gbnr", schedule);
+    transaction.commit();
+    let schedule = schedule_manager.get("gbnr").unwrap();
+    assert_eq!(schedule.trains.len(), 2);
+    assert!(!schedule.trains.contains_key("C10003"));
+}
@@ -0,0 +1,93 @@
+// Associations between trains, including ones that only ever come over VSTP
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use chrono::NaiveDate;
+
+use worldrailtimetables::consistency::check_schedule;
+use worldrailtimetables::importer::FastImporter;
+use worldrailtimetables::schedule::{AssociationNode, Schedule};
+use worldrailtimetables::uk_importer::{NrJsonImporter, NrJsonImporterConfig};
+
+// Y20001 (in vstp.json) forms C10002 at Wembley the morning after it sets off
+const ASSOCIATION: &str =
+    "AANY20001C100022606022606020100000NPNWMBYICD  TP                               P";
+
+#[tokio::test]
+async fn vstp_train_gets_cif_association() {
+    let cif = String::from_utf8(read_fixture("small.cif")).unwrap();
+    let cif = cif.replacen("\nZZ", &format!("\n{}\nZZ", ASSOCIATION), 1);
+    let (schedule, report) = import_cif(cif.as_bytes(), false).await.unwrap();
+    assert!(report.is_empty(), "{:?}", report);
+    assert_eq!(schedule.pending_associations["Y20001"].len(), 1);
+    let wembley = schedule.trains["C10002"][0]
+        .route
+        .iter()
+        .find(|x| x.id.as_str() == "WMBYICD")
+        .unwrap();
+    assert_eq!(
+        wembley.forms_from.as_ref().unwrap().other_train_id.as_str(),
+        "Y20001"
+    );
+
+    let importer = NrJsonImporter::new(serde_json::from_str::<NrJsonImporterConfig>("{}").unwrap())
+        .await
+        .unwrap();
+    let schedule = importer
+        .overlay(read_fixture("vstp.json"), schedule)
+        .unwrap();
+    assert_eq!(check_schedule(&schedule), vec![]);
+    let wembley = schedule.trains["Y20001"][0]
+        .route
+        .iter()
+        .find(|x| x.id.as_str() == "WMBYICD")
+        .unwrap();
+    assert_eq!(
+        wembley.becomes.as_ref().unwrap().other_train_id.as_str(),
+        "C10002"
+    );
+    // still there for if it's sent again
+    assert_eq!(schedule.pending_associations["Y20001"].len(), 1);
+}
+
+// a second insert for the same pair, e.g. for a summer timetable
+const SUMMER_ASSOCIATION: &str =
+    "AANC10002C100012606152606191111100NPSEUSTON   TP                               P";
+
+fn euston_becomes(schedule: &Schedule) -> &AssociationNode {
+    schedule.trains["C10002"][0]
+        .route
+        .iter()
+        .find(|x| x.id.as_str() == "EUSTON")
+        .unwrap()
+        .becomes
+        .as_ref()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn association_periods_are_merged_and_deleted_separately() {
+    let cif = String::from_utf8(read_fixture("small.cif")).unwrap();
+    let merged = cif.replacen("\nZZ", &format!("\n{}\nZZ", SUMMER_ASSOCIATION), 1);
+    let (schedule, report) = import_cif(merged.as_bytes(), false).await.unwrap();
+    assert!(report.is_empty(), "{:?}", report);
+    assert_eq!(euston_becomes(&schedule).validity.len(), 2);
+    let euston = schedule.trains["C10001"][0]
+        .route
+        .iter()
+        .find(|x| x.id.as_str() == "EUSTON")
+        .unwrap();
+    assert_eq!(euston.forms_from.as_ref().unwrap().validity.len(), 2);
+
+    let delete = format!("AADC10002C10001260615{:16}EUSTON {:35}P", "", "");
+    let deleted = merged.replacen("\nZZ", &format!("\n{}\nZZ", delete), 1);
+    let (schedule, report) = import_cif(deleted.as_bytes(), false).await.unwrap();
+    assert!(report.is_empty(), "{:?}", report);
+    let validity = &euston_becomes(&schedule).validity;
+    assert_eq!(validity.len(), 1);
+    assert_eq!(
+        validity[0].valid_begin.date_naive(),
+        NaiveDate::from_ymd_opt(2026, 5, 18).unwrap()
+    );
+}
@@ -0,0 +1,74 @@
+// Boards around midnight, where yesterday's trains are still calling
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::schedule::{max_day_offset, Schedule};
+
+use chrono::{NaiveDate, NaiveDateTime};
+
+fn at(date: u32, time: &str) -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2026, 6, date)
+        .unwrap()
+        .and_time(time.parse().unwrap())
+}
+
+fn board(
+    schedule: &Schedule,
+    location_ids: &[&str],
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+) -> Vec<(String, NaiveDate, String)> {
+    let location_ids = location_ids.iter().map(|x| x.to_string()).collect();
+    schedule
+        .calls_between(&location_ids, start, end)
+        .into_iter()
+        .map(|x| {
+            (
+                x.train_id,
+                x.operating_date,
+                x.time.format("%a %H:%M").to_string(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn previous_days_trains_are_on_boards_after_midnight() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let saturday = NaiveDate::from_ymd_opt(2026, 6, 6).unwrap();
+    let c10003 = |time: &str| ("C10003".to_string(), saturday, time.to_string());
+
+    assert_eq!(max_day_offset(&schedule.trains["C10003"]), 1);
+    assert_eq!(max_day_offset(&schedule.trains["C10001"]), 0);
+
+    // Saturday's 2N99 leaves Northampton at 23:45 and is at Watford at 00:32 on Sunday
+    assert_eq!(
+        board(&schedule, &["NMPTN"], at(6, "23:00:00"), at(6, "23:59:00")),
+        vec![c10003("Sat 23:45")]
+    );
+    assert_eq!(
+        board(&schedule, &["WATFDJ"], at(7, "00:00:00"), at(7, "02:00:00")),
+        vec![c10003("Sun 00:32")]
+    );
+    assert_eq!(
+        board(
+            &schedule,
+            &["NMPTN", "MKNSCEN", "WATFDJ"],
+            at(6, "23:50:00"),
+            at(7, "00:45:00")
+        ),
+        vec![c10003("Sun 00:04"), c10003("Sun 00:32")]
+    );
+    assert!(board(&schedule, &["WATFDJ"], at(7, "00:33:00"), at(7, "02:00:00")).is_empty());
+    // it only runs on Saturday nights
+    assert!(board(&schedule, &["WATFDJ"], at(6, "00:00:00"), at(6, "02:00:00")).is_empty());
+    assert!(board(&schedule, &["WATFDJ"], at(8, "00:00:00"), at(8, "02:00:00")).is_empty());
+
+    // and the day's own trains are still there
+    let monday = NaiveDate::from_ymd_opt(2026, 6, 1).unwrap();
+    assert_eq!(
+        board(&schedule, &["WATFDJ"], at(1, "00:00:00"), at(1, "23:59:00")),
+        vec![("C10001".to_string(), monday, "Mon 08:02".to_string())]
+    );
+}
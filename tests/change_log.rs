@@ -0,0 +1,67 @@
+// The change log of what VSTP has done to each train, as kept by the schedule manager
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::importer::FastImporter;
+use worldrailtimetables::schedule_manager::ScheduleManager;
+use worldrailtimetables::uk_importer::{NrJsonImporter, NrJsonImporterConfig};
+
+use chrono::NaiveDate;
+
+use serde_json::{json, Value};
+
+fn cancellation() -> Vec<u8> {
+    let mut message = serde_json::from_slice::<Value>(&read_fixture("vstp.json")).unwrap();
+    message["VSTPCIFMsgV1"]["schedule"]["CIF_stp_indicator"] = json!("C");
+    serde_json::to_vec(&message).unwrap()
+}
+
+#[tokio::test]
+async fn vstp_changes_are_logged_on_commit() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let importer = NrJsonImporter::new(serde_json::from_str::<NrJsonImporterConfig>("{}").unwrap())
+        .await
+        .unwrap();
+    let schedule_manager = ScheduleManager::new();
+
+    let schedule = importer
+        .overlay(read_fixture("vstp.json"), schedule)
+        .unwrap();
+    let schedule = importer.overlay(cancellation(), schedule).unwrap();
+    let mut transaction = schedule_manager.transactional_write().await;
+    for (train_id, change) in importer.take_changes() {
+        transaction.log_change("gbnr", &train_id, change);
+    }
+    assert!(importer.take_changes().is_empty());
+    let change_log = schedule_manager.change_log();
+    let tuesday = NaiveDate::from_ymd_opt(2026, 6, 2).unwrap();
+    // nothing until it's committed
+    assert!(change_log.history("gbnr", "Y20001", tuesday).is_empty());
+    transaction.put("gbnr", schedule);
+    transaction.commit();
+
+    let types = |date: NaiveDate| {
+        change_log
+            .history("gbnr", "Y20001", date)
+            .iter()
+            .map(|x| serde_json::to_value(&x.change).unwrap()["type"].clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        types(tuesday),
+        vec![json!("VstpCreated"), json!("VstpCancelled")]
+    );
+    assert!(types(tuesday.succ_opt().unwrap()).is_empty());
+
+    // only the latest are kept
+    change_log.set_max_per_train(1);
+    let schedule = schedule_manager.get("gbnr").unwrap().as_ref().clone();
+    importer
+        .overlay(read_fixture("vstp.json"), schedule)
+        .unwrap();
+    for (train_id, change) in importer.take_changes() {
+        change_log.record("gbnr", &train_id, change);
+    }
+    assert_eq!(types(tuesday), vec![json!("VstpCreated")]);
+}
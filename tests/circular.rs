@@ -0,0 +1,96 @@
+// Trains that call at the same location more than once, and associations at one of those calls
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::importer::FastImporter;
+use worldrailtimetables::schedule::{call_index, Train};
+use worldrailtimetables::uk_importer::{NrJsonImporter, NrJsonImporterConfig};
+
+use serde_json::{json, Value};
+
+// Euston round the Watford DC lines and back: formed from the Wembley ECS, and forms the morning
+// Northampton, both at Euston
+const CIRCULAR: [&str; 8] = [
+    "BSNC200012605182612111111100 POO2N02     22214000 EMU350 110      S            P",
+    "BX         LMYLM123400                                                          ",
+    "LOEUSTON  0650 06509  FL     TB                                                 ",
+    "LIWATFDJ            0702                                                        ",
+    "LTEUSTON 20712 07129     TF                                                     ",
+    "AANC10002C200012605182612111111100NPSEUSTON   TP                               P",
+    "AANC20001C100012605182612111111100NPSEUSTON 2 TP                               P",
+    "ZZ                                                                              ",
+];
+
+fn becomes(train: &Train, i: usize) -> Option<&str> {
+    train.route[i]
+        .becomes
+        .as_ref()
+        .map(|x| x.other_train_id.as_str())
+}
+
+fn forms_from(train: &Train, i: usize) -> Option<&str> {
+    train.route[i]
+        .forms_from
+        .as_ref()
+        .map(|x| x.other_train_id.as_str())
+}
+
+#[tokio::test]
+async fn cif_circular_associations_go_on_the_right_call() {
+    let cif = String::from_utf8(read_fixture("small.cif")).unwrap();
+    let end = cif.find("\nAANC10002").unwrap();
+    let cif = format!("{}\n{}\n", &cif[..end], CIRCULAR.join("\n"));
+    let (schedule, report) = import_cif(cif.as_bytes(), false).await.unwrap();
+    assert!(report.is_empty(), "{:?}", report);
+
+    let circular = &schedule.trains["C20001"][0];
+    assert_eq!(call_index(&circular.route, "EUSTON", &None), Some(0));
+    assert_eq!(
+        call_index(&circular.route, "EUSTON", &Some("2".to_string())),
+        Some(2)
+    );
+    assert_eq!(call_index(&circular.route, "WATFDJ", &None), Some(1));
+    assert_eq!(
+        call_index(&circular.route, "WATFDJ", &Some("2".to_string())),
+        None
+    );
+
+    assert_eq!(forms_from(circular, 0), Some("C10002"));
+    assert_eq!(becomes(circular, 0), None);
+    assert_eq!(forms_from(circular, 2), None);
+    assert_eq!(becomes(circular, 2), Some("C10001"));
+    assert_eq!(becomes(&schedule.trains["C10002"][0], 1), Some("C20001"));
+    assert_eq!(forms_from(&schedule.trains["C10001"][0], 0), Some("C20001"));
+}
+
+// VSTP doesn't have suffixes, so a reversal there has two calls at Wembley with nothing to tell
+// them apart
+fn reversal() -> Vec<u8> {
+    let mut message = serde_json::from_slice::<Value>(&read_fixture("vstp.json")).unwrap();
+    message["VSTPCIFMsgV1"]["schedule"]["schedule_segment"][0]["schedule_location"][0]
+        ["location"]["tiploc"]["tiploc_id"] = json!("WMBYICD");
+    serde_json::to_vec(&message).unwrap()
+}
+
+#[tokio::test]
+async fn vstp_reversal_associations_go_on_the_nth_call() {
+    let cif = String::from_utf8(read_fixture("small.cif")).unwrap();
+    let cif = cif.replacen(
+        "\nZZ",
+        "\nAANY20001C100022606022606020100000NPNWMBYICD2 TP                               P\nZZ",
+        1,
+    );
+    let (schedule, report) = import_cif(cif.as_bytes(), false).await.unwrap();
+    assert!(report.is_empty(), "{:?}", report);
+
+    let importer = NrJsonImporter::new(serde_json::from_str::<NrJsonImporterConfig>("{}").unwrap())
+        .await
+        .unwrap();
+    let schedule = importer.overlay(reversal(), schedule).unwrap();
+    let reversal = &schedule.trains["Y20001"][0];
+    assert_eq!(reversal.route[0].id.as_str(), "WMBYICD");
+    assert_eq!(reversal.route.last().unwrap().id.as_str(), "WMBYICD");
+    assert_eq!(becomes(reversal, 0), None);
+    assert_eq!(becomes(reversal, reversal.route.len() - 1), Some("C10002"));
+}
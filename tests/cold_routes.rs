@@ -0,0 +1,206 @@
+// Cold routes, which are kept compressed until something looks at them
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::cold_routes::{peek_train, ColdRoutes, ColdRoutesConfig};
+use worldrailtimetables::schedule::{Schedule, Train};
+use worldrailtimetables::schedule_manager::ScheduleManager;
+
+use chrono::NaiveDate;
+use serde_json::json;
+
+fn versions(schedule: &Schedule) -> impl Iterator<Item = &Train> {
+    schedule
+        .trains
+        .values()
+        .flatten()
+        .flat_map(|x| std::iter::once(x).chain(x.replacements.iter()))
+}
+
+fn decompressed(schedule: &Schedule) -> usize {
+    versions(schedule)
+        .filter(|x| x.route.is_decompressed())
+        .count()
+}
+
+#[tokio::test]
+async fn only_the_most_recently_used_routes_stay_decompressed() {
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let before = serde_json::to_value(&schedule.trains).unwrap();
+    let cold_routes = ColdRoutes::new(
+        serde_json::from_value::<ColdRoutesConfig>(json!({ "hot_routes": 1 })).unwrap(),
+    );
+
+    let stats = cold_routes.apply(&mut schedule);
+    assert_eq!(stats.compressed, versions(&schedule).count());
+    assert_eq!(stats.hot, 1);
+    assert!(versions(&schedule).all(|x| x.route.is_compressed()));
+    assert_eq!(decompressed(&schedule), 1);
+    assert!(!cold_routes.over_limit(&schedule));
+
+    // serialising doesn't decompress anything for good
+    assert_eq!(serde_json::to_value(&schedule.trains).unwrap(), before);
+    assert_eq!(decompressed(&schedule), 1);
+
+    // looking at one brings it back, and makes it the one that's kept
+    assert_eq!(schedule.trains["C10003"][0].route[0].id.as_str(), "NMPTN");
+    assert!(schedule.trains["C10003"][0].route.is_decompressed());
+    let stats = cold_routes.apply(&mut schedule);
+    assert_eq!(stats.compressed, 0);
+    assert!(schedule.trains["C10003"][0].route.is_decompressed());
+    assert_eq!(decompressed(&schedule), 1);
+
+    // changing one means it has to be compressed again
+    schedule.trains.get_mut("C10002").unwrap()[0].route[0].id = "WATFDJ".into();
+    assert!(!schedule.trains["C10002"][0].route.is_compressed());
+    assert_eq!(cold_routes.apply(&mut schedule).compressed, 1);
+    assert_eq!(decompressed(&schedule), 1);
+    let after = serde_json::to_value(&schedule.trains).unwrap();
+    assert_eq!(after["C10002"][0]["route"][0]["id"], "WATFDJ");
+    assert_eq!(after["C10001"], before["C10001"]);
+}
+
+#[tokio::test]
+async fn committed_schedules_are_compressed() {
+    let schedule_manager = ScheduleManager::new();
+    schedule_manager.set_cold_routes(ColdRoutes::new(
+        serde_json::from_value::<ColdRoutesConfig>(json!({ "hot_routes": 0 })).unwrap(),
+    ));
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.put("gbnr", schedule);
+    transaction.archive();
+    transaction.commit();
+
+    let generation = schedule_manager.generation();
+    let schedule = schedule_manager.get("gbnr").unwrap();
+    assert_eq!(decompressed(&schedule), 0);
+    assert_eq!(schedule.trains["C10001"][0].route.len(), 5);
+    assert_eq!(decompressed(&schedule), 1);
+    drop(schedule);
+
+    schedule_manager.compress_cold_routes().await;
+    assert_eq!(decompressed(&schedule_manager.get("gbnr").unwrap()), 0);
+    assert_eq!(schedule_manager.generation(), generation);
+}
+
+#[tokio::test]
+async fn decompressed_routes_share_strings_again() {
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let cold_routes = ColdRoutes::new(
+        serde_json::from_value::<ColdRoutesConfig>(json!({ "hot_routes": 0 })).unwrap(),
+    );
+    cold_routes.apply(&mut schedule);
+    assert_eq!(decompressed(&schedule), 0);
+
+    let origin = &schedule.trains["C10001"][0].route[0].id;
+    let destination = &schedule.trains["C10002"][0].route.last().unwrap().id;
+    assert_eq!(origin, "EUSTON");
+    assert_eq!(origin.as_str().as_ptr(), destination.as_str().as_ptr());
+}
+
+#[tokio::test]
+async fn peeking_at_trains_leaves_them_compressed() {
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let cold_routes = ColdRoutes::new(
+        serde_json::from_value::<ColdRoutesConfig>(json!({ "hot_routes": 0 })).unwrap(),
+    );
+    cold_routes.apply(&mut schedule);
+
+    let train = peek_train(&schedule.trains["C10001"][0]);
+    assert_eq!(train.route.len(), 5);
+    assert!(train.replacements.iter().all(|x| x.route.is_decompressed()));
+    assert_eq!(decompressed(&schedule), 0);
+}
+
+#[tokio::test]
+async fn compressing_a_committed_schedule_only_copies_what_changes() {
+    let schedule_manager = ScheduleManager::new();
+    schedule_manager.set_cold_routes(ColdRoutes::new(
+        serde_json::from_value::<ColdRoutesConfig>(json!({ "hot_routes": 0 })).unwrap(),
+    ));
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.put("gbnr", schedule);
+    transaction.archive();
+    transaction.commit();
+
+    let before = schedule_manager.get("gbnr").unwrap();
+    assert_eq!(before.trains["C10002"][0].route.len(), 2);
+    schedule_manager.compress_cold_routes().await;
+    let after = schedule_manager.get("gbnr").unwrap();
+    assert_eq!(decompressed(&after), 0);
+    // the one train's partition, and nothing else
+    assert_eq!(after.trains.shared_partitions(&before.trains), 255);
+    assert_eq!(after.locations.shared_partitions(&before.locations), 256);
+    // while whoever had the schedule before still has the route they looked at
+    assert!(before.trains["C10002"][0].route.is_decompressed());
+}
+
+#[tokio::test]
+async fn garbage_collection_leaves_routes_compressed() {
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let cold_routes = ColdRoutes::new(
+        serde_json::from_value::<ColdRoutesConfig>(json!({ "hot_routes": 0 })).unwrap(),
+    );
+    cold_routes.apply(&mut schedule);
+
+    // nothing to drop, so nothing's written to
+    schedule.garbage_collect(NaiveDate::from_ymd_opt(2000, 1, 1).unwrap());
+    assert!(versions(&schedule).all(|x| x.route.is_compressed()));
+    assert_eq!(decompressed(&schedule), 0);
+}
+
+#[tokio::test]
+async fn each_namespace_has_its_own_hot_routes() {
+    let (mut gbnr, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let mut other = gbnr.clone();
+    other.namespace = "other".to_string();
+    let cold_routes = ColdRoutes::new(
+        serde_json::from_value::<ColdRoutesConfig>(json!({ "hot_routes": 0 })).unwrap(),
+    );
+    cold_routes.apply(&mut gbnr);
+    cold_routes.apply(&mut other);
+    assert!(!cold_routes.due());
+
+    assert_eq!(gbnr.trains["C10001"][0].route.len(), 5);
+    assert!(cold_routes.due());
+    // a pass over another namespace doesn't count as one over this
+    cold_routes.apply(&mut other);
+    assert!(cold_routes.due());
+    assert_eq!(cold_routes.apply(&mut gbnr).evicted, 1);
+    assert!(!cold_routes.due());
+}
+
+#[tokio::test]
+async fn small_updates_only_compress_what_they_change() {
+    let schedule_manager = ScheduleManager::new();
+    schedule_manager.set_cold_routes(ColdRoutes::new(
+        serde_json::from_value::<ColdRoutesConfig>(json!({ "hot_routes": 1 })).unwrap(),
+    ));
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.put("gbnr", schedule);
+    transaction.archive();
+    transaction.commit();
+
+    for train_id in ["C10002", "C10003"] {
+        let mut transaction = schedule_manager.transactional_write().await;
+        let mut schedule = transaction.take("gbnr").unwrap();
+        schedule.trains.get_mut(train_id).unwrap()[0].route[0].id = "WATFDJ".into();
+        transaction.train_changed("gbnr", train_id);
+        transaction.put("gbnr", schedule);
+        transaction.commit();
+    }
+
+    let schedule = schedule_manager.get("gbnr").unwrap();
+    assert!(versions(&schedule).all(|x| x.route.is_compressed()));
+    // the last one changed stays decompressed, and pushes out the one before
+    assert!(schedule.trains["C10003"][0].route.is_decompressed());
+    assert!(!schedule.trains["C10002"][0].route.is_decompressed());
+    assert_eq!(
+        serde_json::to_value(&schedule.trains).unwrap()["C10002"][0]["route"][0]["id"],
+        "WATFDJ"
+    );
+}
@@ -0,0 +1,82 @@
+// Shared by the integration tests. Not every test uses everything here.
+#![allow(dead_code)]
+
+use worldrailtimetables::error::Error;
+use worldrailtimetables::importer::{ImportReport, SlowStreamingImporter};
+use worldrailtimetables::schedule::Schedule;
+use worldrailtimetables::uk_importer::{CifImporter, CifImporterConfig};
+
+use serde_json::{json, Value};
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("fixtures")
+        .join(name)
+}
+
+pub fn read_fixture(name: &str) -> Vec<u8> {
+    std::fs::read(fixture(name)).unwrap()
+}
+
+pub async fn import_cif(data: &[u8], lenient: bool) -> Result<(Schedule, ImportReport), Error> {
+    let mut importer = CifImporter::new(CifImporterConfig::default().with_lenient(lenient));
+    let schedule = importer
+        .overlay(data, Schedule::new("gbnr".to_string(), "Test".to_string()))
+        .await?;
+    Ok((schedule, importer.report().clone()))
+}
+
+// What a golden file holds: everything an import produced that doesn't depend on when it was run,
+// sorted so it comes out the same every time
+pub fn summary(schedule: &Schedule) -> Value {
+    json!({
+        "valid_begin": schedule.valid_begin,
+        "valid_end": schedule.valid_end,
+        "locations": schedule.locations.iter().collect::<BTreeMap<_, _>>(),
+        "trains": schedule.trains.iter().collect::<BTreeMap<_, _>>(),
+    })
+}
+
+// Compares against tests/golden/<name>.json. Run with UPDATE_GOLDEN=1 to write it instead, after
+// a change that's meant to alter the output (or for a new one); the diff then shows what it
+// altered. A missing file fails, so one that was never committed can't pass unnoticed.
+pub fn check_golden(name: &str, actual: &Value) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.json", name));
+    let actual = serde_json::to_string_pretty(actual).unwrap() + "\n";
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = match std::fs::read_to_string(&path) {
+        Ok(x) => x,
+        Err(x) => panic!(
+            "Can't read {}: {}; run with UPDATE_GOLDEN=1 to write it",
+            path.display(),
+            x
+        ),
+    };
+    if expected != actual {
+        let line = expected
+            .lines()
+            .zip(actual.lines())
+            .position(|(x, y)| x != y)
+            .unwrap_or(std::cmp::min(
+                expected.lines().count(),
+                actual.lines().count(),
+            ));
+        panic!(
+            "{} differs from {} from line {}; rerun with UPDATE_GOLDEN=1 if that's intended",
+            name,
+            path.display(),
+            line + 1
+        );
+    }
+}
@@ -0,0 +1,77 @@
+// Freight consists attached to the trains they make up
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::consist_importer::ConsistImporter;
+use worldrailtimetables::importer::SlowStreamingImporter;
+use worldrailtimetables::resolved_train::resolve_train;
+use worldrailtimetables::schedule::TrainType;
+
+use chrono::NaiveDate;
+use serde_json::json;
+
+#[tokio::test]
+async fn consists_are_summarised_by_wagon_type() {
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    // the fixture only has passenger trains, so make one of them freight
+    for version in schedule.trains.get_mut("C10002").unwrap() {
+        version.variable_train.train_type = TrainType::Freight;
+    }
+
+    let wagon = |wagon_type: &str, loaded: bool| {
+        json!({
+            "number": "31 70 4907 017-9",
+            "wagon_type": wagon_type,
+            "description": format!("{} container flat", wagon_type),
+            "length_m": 20.0,
+            "max_speed_mph": if loaded { 60 } else { 75 },
+            "loaded": loaded,
+            "gross_weight_t": if loaded { 60.0 } else { 20.0 },
+        })
+    };
+    let consists = json!([
+        {
+            "train_id": "C10002",
+            "date": "2026-06-02",
+            "id": "4L01",
+            "wagons": [wagon("FEA", true), wagon("FEA", false), wagon("FSA", true)],
+        },
+        // not freight, so not for here
+        { "train_id": "C10001", "date": "2026-06-02", "wagons": [wagon("FEA", true)] },
+    ])
+    .to_string();
+    let schedule = ConsistImporter::new()
+        .overlay(consists.as_bytes(), schedule)
+        .await
+        .unwrap();
+    assert!(!schedule.consists.contains_key("C10001"));
+
+    let date = NaiveDate::from_ymd_opt(2026, 6, 2).unwrap();
+    let consist = resolve_train(&schedule, "C10002", date)
+        .unwrap()
+        .variable_train
+        .actual_allocation
+        .unwrap();
+    assert_eq!(consist.id.as_str(), "4L01");
+    assert_eq!(
+        consist.description.as_str(),
+        "3 wagons (2 loaded), 60 m, 140 t"
+    );
+    let vehicles = consist.vehicles.unwrap();
+    assert_eq!(vehicles.len(), 2);
+    assert_eq!(vehicles[0].id, "FEA");
+    assert_eq!(vehicles[0].description, "FEA container flat");
+    assert_eq!(vehicles[0].cars, vec![2]);
+    assert_eq!(vehicles[0].max_speed_mph, Some(60));
+    assert_eq!(vehicles[0].car_length_m, Some(20.0));
+    assert_eq!(vehicles[1].cars, vec![1]);
+
+    // only on the day it was for
+    let date = NaiveDate::from_ymd_opt(2026, 6, 3).unwrap();
+    assert!(resolve_train(&schedule, "C10002", date)
+        .unwrap()
+        .variable_train
+        .actual_allocation
+        .is_none());
+}
@@ -0,0 +1,104 @@
+// Projecting a reported delay along the rest of the route
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::delay_propagation::{expected_times, ExpectedSource};
+use worldrailtimetables::schedule::{
+    get_train_instance, RealtimeEvent, RealtimeEventType, TrainRealtime,
+};
+
+use chrono::{NaiveDate, TimeZone};
+use chrono_tz::Europe::London;
+
+fn event(
+    location_id: &str,
+    event_type: RealtimeEventType,
+    planned: (u32, u32, u32),
+    delay_secs: i64,
+) -> RealtimeEvent {
+    let planned = London
+        .with_ymd_and_hms(2026, 5, 19, planned.0, planned.1, planned.2)
+        .unwrap();
+    RealtimeEvent {
+        location_ids: vec![location_id.to_string()],
+        event_type,
+        planned: Some(planned),
+        actual: planned + chrono::Duration::seconds(delay_secs),
+        platform: None,
+        delay_minutes: delay_secs / 60,
+        off_route: false,
+    }
+}
+
+fn realtime(events: Vec<RealtimeEvent>) -> TrainRealtime {
+    TrainRealtime {
+        realtime_id: "722N99MA19".to_string(),
+        activated: None,
+        cancellation: None,
+        events,
+        terminated: false,
+    }
+}
+
+#[tokio::test]
+async fn delay_carried_forward_less_allowances() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let date = NaiveDate::from_ymd_opt(2026, 5, 19).unwrap();
+    let train = get_train_instance(&schedule.trains["C10001"], date)
+        .0
+        .unwrap();
+    let planned = train
+        .route
+        .iter()
+        .map(|x| x.local_times(date, London))
+        .collect::<Vec<_>>();
+    let late = realtime(vec![event(
+        "EUSTON",
+        RealtimeEventType::Departure,
+        (7, 15, 30),
+        600,
+    )]);
+    let expected = expected_times(&late, &train.route, &planned);
+
+    let euston = expected[0].as_ref().unwrap();
+    assert_eq!(euston.departure.unwrap().source, ExpectedSource::Reported);
+    let watford = expected[1].as_ref().unwrap();
+    let arrival = watford.arrival.unwrap();
+    assert_eq!(arrival.source, ExpectedSource::Projected);
+    assert_eq!(
+        arrival.time,
+        London.with_ymd_and_hms(2026, 5, 19, 7, 41, 0).unwrap()
+    );
+    assert_eq!(watford.delay_minutes, Some(10));
+    // the allowances after Watford claw some of it back
+    let northampton = expected.last().unwrap().as_ref().unwrap();
+    assert_eq!(
+        northampton.arrival.unwrap().source,
+        ExpectedSource::Projected
+    );
+    assert!(northampton.delay_minutes.unwrap() < 10);
+
+    // running early, it waits for its booked departure
+    let early = realtime(vec![event(
+        "WATFDJ",
+        RealtimeEventType::Arrival,
+        (7, 31, 0),
+        -120,
+    )]);
+    let expected = expected_times(&early, &train.route, &planned);
+    assert!(expected[0].is_none());
+    let watford = expected[1].as_ref().unwrap();
+    assert_eq!(watford.arrival.unwrap().source, ExpectedSource::Reported);
+    assert_eq!(
+        watford.departure.unwrap().time,
+        planned[1].working_dep.unwrap()
+    );
+    assert_eq!(expected[2].as_ref().unwrap().delay_minutes, Some(0));
+
+    // and nothing is projected once it's finished
+    let mut finished = late.clone();
+    finished.terminated = true;
+    let expected = expected_times(&finished, &train.route, &planned);
+    assert!(expected[1].is_none());
+}
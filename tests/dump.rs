@@ -0,0 +1,76 @@
+// The bulk JSON Lines dump of every train running on a date
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::dump::TrainDump;
+use worldrailtimetables::redaction::RedactionPolicy;
+
+use chrono::NaiveDate;
+use flate2::read::GzDecoder;
+use serde_json::{json, Value};
+
+use std::io::Read;
+use std::sync::Arc;
+
+fn lines(dump: TrainDump) -> Vec<Value> {
+    let bytes = dump.flatten().collect::<Vec<_>>();
+    String::from_utf8(bytes)
+        .unwrap()
+        .lines()
+        .map(|x| serde_json::from_str(x).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn every_train_running_is_dumped_once() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let schedule = Arc::new(schedule);
+    let date = NaiveDate::from_ymd_opt(2026, 6, 2).unwrap();
+
+    let trains = lines(TrainDump::new(vec![schedule.clone()], date, None, false));
+    let ids = trains
+        .iter()
+        .map(|x| x["id"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    // C10003 only runs on Saturdays
+    assert_eq!(ids, vec!["C10001", "C10002"]);
+    assert_eq!(trains[0]["date"], "2026-06-02");
+    assert_eq!(trains[0]["route"][0]["id"], "EUSTON");
+    assert_eq!(
+        trains[0]["route"][0]["working_dep"],
+        "2026-06-02T07:15:30+01:00"
+    );
+    assert_eq!(trains[0]["route"].as_array().unwrap().len(), 5);
+
+    // the overlay on the 1st is what runs that day
+    let date = NaiveDate::from_ymd_opt(2026, 6, 1).unwrap();
+    let trains = lines(TrainDump::new(vec![schedule.clone()], date, None, false));
+    assert_eq!(trains[0]["modified"], true);
+    assert_eq!(trains[0]["route"].as_array().unwrap().len(), 4);
+
+    // gzipped, it's the same underneath
+    let mut gzipped = vec![];
+    GzDecoder::new(
+        &TrainDump::new(vec![schedule.clone()], date, None, true)
+            .flatten()
+            .collect::<Vec<_>>()[..],
+    )
+    .read_to_end(&mut gzipped)
+    .unwrap();
+    let plain = TrainDump::new(vec![schedule.clone()], date, None, false)
+        .flatten()
+        .collect::<Vec<_>>();
+    assert_eq!(gzipped, plain);
+
+    // and the public doesn't get to see what it wouldn't anywhere else
+    let policy = serde_json::from_value::<RedactionPolicy>(json!({
+        "working_times": true,
+        "staff_trains": true,
+    }))
+    .unwrap();
+    let trains = lines(TrainDump::new(vec![schedule], date, Some(policy), false));
+    assert_eq!(trains.len(), 1);
+    assert!(trains[0]["route"][0].get("working_dep").is_none());
+    assert!(trains[0]["route"][0].get("public_dep").is_some());
+}
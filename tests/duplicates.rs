@@ -0,0 +1,59 @@
+// The same train in more than one namespace
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::duplicates::{DuplicateConfig, Duplicates, TrainRef};
+use worldrailtimetables::schedule::{get_train_instance, Schedule, Train};
+
+use chrono::NaiveDate;
+use serde_json::json;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// the same extract imported twice, as if two feeds had the same trains
+async fn schedules() -> HashMap<String, Arc<Schedule>> {
+    let mut schedules = HashMap::new();
+    for namespace in ["gbnr", "other"] {
+        let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+        schedule.namespace = namespace.to_string();
+        schedules.insert(namespace.to_string(), Arc::new(schedule));
+    }
+    schedules
+}
+
+#[tokio::test]
+async fn copies_are_found_by_their_calls() {
+    let schedules = schedules().await;
+    let duplicates = Arc::new(Duplicates::new(
+        serde_json::from_value::<DuplicateConfig>(json!({ "priority": ["other"] })).unwrap(),
+    ));
+    duplicates.clone().refresh(schedules["other"].clone()).await;
+
+    let date = NaiveDate::from_ymd_opt(2026, 6, 2).unwrap();
+    let resolve = |x: &Vec<Train>| get_train_instance(x, date).0;
+    let train = resolve(&schedules["gbnr"].trains["C10001"]).unwrap();
+    let found = duplicates.find(&schedules, "gbnr", &train, date, resolve);
+    assert_eq!(
+        found,
+        vec![TrainRef {
+            namespace: "other".to_string(),
+            id: "C10001".to_string(),
+        }]
+    );
+    assert_eq!(duplicates.preferred("gbnr", &found), Some(&found[0]));
+
+    // the other way round, with the index built as it's first needed
+    let train = resolve(&schedules["other"].trains["C10001"]).unwrap();
+    let found = duplicates.find(&schedules, "other", &train, date, resolve);
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].namespace, "gbnr");
+    assert_eq!(duplicates.preferred("other", &found), None);
+
+    // nothing public, so nothing to go by
+    let train = resolve(&schedules["gbnr"].trains["C10002"]).unwrap();
+    assert!(duplicates
+        .find(&schedules, "gbnr", &train, date, resolve)
+        .is_empty());
+}
@@ -0,0 +1,48 @@
+// Other environments, under /env/<name>/ or asked for with X-Environment
+use worldrailtimetables::environments::{requested, MAIN_ENVIRONMENT};
+use worldrailtimetables::redaction::{Redaction, RedactionConfig};
+use worldrailtimetables::response_headers::{ResponseHeaders, ResponseHeadersConfig};
+
+use serde_json::json;
+
+#[test]
+fn environment_paths_keep_their_endpoints_policies() {
+    assert_eq!(requested(None, "/location/EUSTON"), Ok(MAIN_ENVIRONMENT));
+    assert_eq!(requested(None, "/env/test/location/EUSTON"), Ok("test"));
+    assert_eq!(requested(Some("test"), "/location/EUSTON"), Ok("test"));
+    assert_eq!(requested(Some("test"), "/env/test/freight"), Ok("test"));
+
+    // asking for production's data under another environment's prefix doesn't get around the
+    // policies, as it isn't answered at all
+    assert!(requested(Some("production"), "/env/test/freight").is_err());
+
+    let redaction = Redaction::new(
+        serde_json::from_value::<RedactionConfig>(json!({
+            "public_mode": true,
+            "endpoints": { "freight": { "freight": false } },
+        }))
+        .unwrap(),
+    );
+    let mut freight = json!([{ "id": "F", "train_type": "Freight", "working_pass": "09:00" }]);
+    assert!(redaction
+        .policy("/env/test/freight")
+        .unwrap()
+        .apply(&mut freight));
+    assert_eq!(freight.as_array().unwrap().len(), 1);
+
+    let headers = ResponseHeaders::new(
+        serde_json::from_value::<ResponseHeadersConfig>(json!({
+            "caching": { "max_age_secs": 30 },
+            "endpoints": { "location": { "caching": { "max_age_secs": 5 } } },
+        }))
+        .unwrap(),
+    );
+    assert_eq!(
+        headers.cache_control("/env/test/location/EUSTON", false),
+        Some("public, max-age=5".to_string())
+    );
+    assert_eq!(
+        headers.cache_control("/env/test/freight", false),
+        Some("public, max-age=30".to_string())
+    );
+}
@@ -0,0 +1,125 @@
+// Feeds the CIF importer mangled versions of a small extract: characters changed, records cut
+// short, dropped, repeated or out of order. A lenient import should get through any of them
+// without panicking, and whatever it does import should still make sense.
+//
+// FUZZ_SEED and FUZZ_CASES change what's tried and how much; a failure says which seed and case
+// to go back to.
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::consistency::check_schedule;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const DEFAULT_SEED: u64 = 4861;
+const DEFAULT_CASES: usize = 2000;
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(x) => x.parse().unwrap_or(default),
+        Err(_) => default,
+    }
+}
+
+fn mutate(lines: &mut Vec<String>, rng: &mut StdRng) {
+    if lines.is_empty() {
+        return;
+    }
+    let i = rng.gen_range(0..lines.len());
+    match rng.gen_range(0..8) {
+        // the usual: one character changed, mostly to something that could be in a CIF
+        0 | 1 => {
+            let mut chars = lines[i].chars().collect::<Vec<_>>();
+            if chars.is_empty() {
+                return;
+            }
+            let position = rng.gen_range(0..chars.len());
+            chars[position] = match rng.gen_range(0..10) {
+                0 => ' ',
+                1..=5 => rng.gen_range(b'0'..=b'9') as char,
+                _ => rng.gen_range(b' '..=b'~') as char,
+            };
+            lines[i] = chars.into_iter().collect();
+        }
+        // a field blanked out
+        2 => {
+            let len = lines[i].len();
+            if len < 4 || !lines[i].is_ascii() {
+                return;
+            }
+            let start = rng.gen_range(2..len);
+            let end = rng.gen_range(start..=std::cmp::min(len, start + 8));
+            lines[i].replace_range(start..end, &" ".repeat(end - start));
+        }
+        // something from outside ASCII in place of two characters, so it's still 80 bytes
+        7 => {
+            let len = lines[i].len();
+            if len < 4 || !lines[i].is_ascii() {
+                return;
+            }
+            let start = rng.gen_range(0..len - 1);
+            lines[i].replace_range(start..start + 2, "é");
+        }
+        // cut short
+        3 => {
+            let end = rng.gen_range(0..=lines[i].len());
+            if lines[i].is_char_boundary(end) {
+                lines[i].truncate(end);
+            }
+        }
+        4 => {
+            lines.remove(i);
+        }
+        5 => {
+            let line = lines[i].clone();
+            lines.insert(rng.gen_range(0..=lines.len()), line);
+        }
+        _ => {
+            let j = rng.gen_range(0..lines.len());
+            lines.swap(i, j);
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn mutated_cif() {
+    let seed = env_or("FUZZ_SEED", DEFAULT_SEED);
+    let cases = env_or("FUZZ_CASES", DEFAULT_CASES);
+    let original = String::from_utf8(read_fixture("small.cif")).unwrap();
+    let original = original.lines().map(|x| x.to_string()).collect::<Vec<_>>();
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    for case in 0..cases {
+        let mut lines = original.clone();
+        for _ in 0..rng.gen_range(1..=4) {
+            mutate(&mut lines, &mut rng);
+        }
+        let data = lines.join("\n") + "\n";
+
+        let input = data.clone();
+        let result = tokio::spawn(async move { import_cif(input.as_bytes(), true).await }).await;
+        let schedule = match result {
+            Ok(Ok((x, _))) => x,
+            // giving up is fine, just not falling over
+            Ok(Err(_)) => continue,
+            Err(x) if x.is_panic() => {
+                panic!(
+                    "Import panicked on seed {} case {}, input:\n{}",
+                    seed, case, data
+                )
+            }
+            Err(x) => panic!("{}", x),
+        };
+        let violations = check_schedule(&schedule);
+        assert!(
+            violations.is_empty(),
+            "Seed {} case {} imported as {:?}, input:\n{}",
+            seed,
+            case,
+            violations,
+            data
+        );
+    }
+}
@@ -0,0 +1,54 @@
+// Imports of small hand-written feeds, compared against what they imported as last time. A change
+// to an importer that alters these should be a deliberate one.
+mod common;
+
+use common::{check_golden, fixture, import_cif, read_fixture, summary};
+
+use worldrailtimetables::consistency::check_schedule;
+use worldrailtimetables::gtfs_importer::GtfsImporter;
+use worldrailtimetables::importer::{FastImporter, SlowGtfsImporter};
+use worldrailtimetables::schedule::Schedule;
+use worldrailtimetables::uk_importer::{NrJsonImporter, NrJsonImporterConfig};
+
+use gtfs_structures::GtfsReader;
+
+use serde_json::json;
+
+#[tokio::test]
+async fn cif() {
+    let (schedule, report) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    assert!(report.is_empty(), "{:?}", report);
+    assert_eq!(check_schedule(&schedule), vec![]);
+    check_golden("small_cif", &summary(&schedule));
+}
+
+// on top of the CIF, as VSTP always is
+#[tokio::test]
+async fn vstp() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let importer = NrJsonImporter::new(serde_json::from_str::<NrJsonImporterConfig>("{}").unwrap())
+        .await
+        .unwrap();
+    let schedule = importer
+        .overlay(read_fixture("vstp.json"), schedule)
+        .unwrap();
+    assert_eq!(importer.take_changed_trains(), vec!["Y20001".to_string()]);
+    assert_eq!(check_schedule(&schedule), vec![]);
+    check_golden("vstp", &json!(schedule.trains.get("Y20001")));
+}
+
+// the importer blocks in place, which needs the multi-threaded runtime
+#[tokio::test(flavor = "multi_thread")]
+async fn gtfs() {
+    let gtfs = GtfsReader::default()
+        .read_shapes(false)
+        .unkown_enum_as_default(false)
+        .read_from_path(fixture("gtfs").display().to_string())
+        .unwrap();
+    let schedule = GtfsImporter::new()
+        .overlay(gtfs, Schedule::new("ieir".to_string(), "Test".to_string()))
+        .await
+        .unwrap();
+    assert_eq!(check_schedule(&schedule), vec![]);
+    check_golden("gtfs", &summary(&schedule));
+}
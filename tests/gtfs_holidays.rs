@@ -0,0 +1,59 @@
+// Christmas and New Year as GTFS feeds usually have them: the weekday service taken off the bank
+// holidays in calendar_dates, and a holiday service with no days of its own added on them
+mod common;
+
+use common::fixture;
+
+use worldrailtimetables::gtfs_importer::GtfsImporter;
+use worldrailtimetables::importer::SlowGtfsImporter;
+use worldrailtimetables::resolved_train::resolve_train;
+use worldrailtimetables::running_days::running_days_text;
+use worldrailtimetables::schedule::Schedule;
+
+use chrono::NaiveDate;
+use gtfs_structures::GtfsReader;
+
+fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+// the importer blocks in place, which needs the multi-threaded runtime
+#[tokio::test(flavor = "multi_thread")]
+async fn holidays_are_left_out_rather_than_cancelled() {
+    let gtfs = GtfsReader::default()
+        .read_shapes(false)
+        .unkown_enum_as_default(false)
+        .read_from_path(fixture("gtfs_holidays").display().to_string())
+        .unwrap();
+    let schedule = GtfsImporter::new()
+        .overlay(gtfs, Schedule::new("ieir".to_string(), "Test".to_string()))
+        .await
+        .unwrap();
+
+    // Christmas Eve is an ordinary weekday
+    assert!(
+        !resolve_train(&schedule, "E801", date(2026, 12, 24))
+            .unwrap()
+            .cancelled
+    );
+    // and on the bank holidays it doesn't run at all, rather than showing as cancelled
+    for holiday in [date(2026, 12, 25), date(2026, 12, 28), date(2027, 1, 1)] {
+        assert!(resolve_train(&schedule, "E801", holiday).is_none());
+    }
+    let weekday = &schedule.trains["E801"][0];
+    assert_eq!(
+        running_days_text(&weekday.validity, &weekday.cancellations, date(2026, 12, 1)),
+        "Mondays to Fridays until 15 January 2027, not 25 to 28 December and 1 January 2027"
+    );
+
+    // the holiday service runs only on the days it's added on
+    assert!(resolve_train(&schedule, "E951", date(2026, 12, 28)).is_some());
+    assert!(resolve_train(&schedule, "E951", date(2027, 1, 1)).is_some());
+    assert!(resolve_train(&schedule, "E951", date(2026, 12, 25)).is_none());
+    assert!(resolve_train(&schedule, "E951", date(2026, 12, 29)).is_none());
+    let holiday = &schedule.trains["E951"][0];
+    assert_eq!(
+        running_days_text(&holiday.validity, &holiday.cancellations, date(2026, 12, 1)),
+        "28 December only; 1 January 2027 only"
+    );
+}
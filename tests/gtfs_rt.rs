@@ -0,0 +1,106 @@
+// GTFS-RT made from VSTP and TRUST
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::gtfs_rt::{GtfsRt, GtfsRtConfig, TripScheduleRelationship};
+use worldrailtimetables::schedule::{RealtimeEvent, RealtimeEventType, TrainRealtime, TrainSource};
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use chrono_tz::Europe::London;
+
+use std::collections::HashMap;
+
+#[tokio::test]
+async fn cancellations_and_delays_are_published() {
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+
+    // the fixture's cancellation of C10001 on the 25th, as if it had come by VSTP
+    let versions = schedule.trains.get_mut("C10001").unwrap();
+    for version in versions.iter_mut() {
+        for cancellation in version.cancellations.iter_mut() {
+            cancellation.source = TrainSource::VeryShortTerm;
+            cancellation.reason = Some("a shortage of train crew".to_string());
+        }
+    }
+    // and late away from Watford the day before
+    let planned = London.with_ymd_and_hms(2026, 8, 24, 7, 32, 30).unwrap();
+    let realtime = TrainRealtime {
+        realtime_id: "722N01MA24".to_string(),
+        activated: None,
+        cancellation: None,
+        events: vec![RealtimeEvent {
+            location_ids: vec!["WATFDJ".to_string()],
+            event_type: RealtimeEventType::Departure,
+            planned: Some(planned),
+            actual: planned + chrono::Duration::minutes(3),
+            platform: None,
+            delay_minutes: 3,
+            off_route: false,
+        }],
+        terminated: false,
+    };
+    schedule.realtime.insert(
+        "C10001".to_string(),
+        HashMap::from([(NaiveDate::from_ymd_opt(2026, 8, 24).unwrap(), realtime)]),
+    );
+
+    let gtfs_rt = GtfsRt::new(GtfsRtConfig::default());
+    let now = Utc.with_ymd_and_hms(2026, 8, 25, 7, 0, 0).unwrap();
+    let feed = gtfs_rt.feed(&schedule, &[], now);
+    let ids = feed
+        .entity
+        .iter()
+        .map(|x| x.id.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ids,
+        vec![
+            "C10001-20260824",
+            "C10001-20260825",
+            "C10001-20260825-cancelled"
+        ]
+    );
+
+    // only from where it was late, with what's projected from there on
+    let late = feed.entity[0].trip_update.as_ref().unwrap();
+    assert_eq!(
+        late.trip.schedule_relationship,
+        Some(TripScheduleRelationship::Scheduled)
+    );
+    assert_eq!(late.stop_time_update[0].stop_id, "WATFDJ");
+    let departure = late.stop_time_update[0].departure.as_ref().unwrap();
+    assert_eq!(departure.delay, Some(210));
+    assert_eq!(
+        departure.time,
+        (planned + chrono::Duration::minutes(3)).timestamp()
+    );
+    assert_eq!(late.stop_time_update.last().unwrap().stop_id, "NMPTN");
+
+    let cancelled = feed.entity[1].trip_update.as_ref().unwrap();
+    assert_eq!(cancelled.trip.start_date, "20260825");
+    assert_eq!(
+        cancelled.trip.schedule_relationship,
+        Some(TripScheduleRelationship::Canceled)
+    );
+    assert!(cancelled.stop_time_update.is_empty());
+    let alert = feed.entity[2].alert.as_ref().unwrap();
+    assert_eq!(
+        alert.header_text.translation[0].text,
+        "The 07:16 from LONDON EUSTON to NORTHAMPTON is cancelled"
+    );
+    assert_eq!(
+        alert.description_text.as_ref().unwrap().translation[0].text,
+        "This is because of a shortage of train crew."
+    );
+
+    // a FeedMessage starts with its header, which starts with the version
+    let protobuf = feed.to_protobuf();
+    assert_eq!(protobuf[0], 0x0a);
+    assert_eq!(&protobuf[2..7], &[0x0a, 0x03, b'2', b'.', b'0']);
+    assert!(protobuf.windows(15).any(|x| x == b"C10001-20260825"));
+
+    // STP cancellations are in the static timetable already
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    assert!(gtfs_rt.feed(&schedule, &[], now).entity.is_empty());
+}
@@ -0,0 +1,69 @@
+// Record types NR might add to the CIF, which can be configured to be skipped rather than fail
+// the import
+mod common;
+
+use common::{import_cif, read_fixture, summary};
+
+use worldrailtimetables::importer::SlowStreamingImporter;
+use worldrailtimetables::schedule::Schedule;
+use worldrailtimetables::uk_importer::{CifImporter, CifImporterConfig};
+
+use serde_json::json;
+
+fn with_new_records() -> String {
+    let cif = String::from_utf8(read_fixture("small.cif")).unwrap();
+    let record = |x: &str| format!("{:<80}\n", x);
+    // one between trains, and one in the middle of a train
+    cif.replacen("BSNC10001", &format!("{}BSNC10001", record("TR123456")), 1)
+        .replacen(
+            "LIBLTCHLY",
+            &format!("{}LIBLTCHLY", record("TSWATFDJ  X")),
+            1,
+        )
+}
+
+#[tokio::test]
+async fn configured_record_types_are_skipped() {
+    let cif = with_new_records();
+    assert!(import_cif(cif.as_bytes(), false).await.is_err());
+
+    let mut importer = CifImporter::new(
+        serde_json::from_value::<CifImporterConfig>(json!({ "ignore_record_types": ["TS", "TR"] }))
+            .unwrap(),
+    );
+    let schedule = importer
+        .overlay(
+            cif.as_bytes(),
+            Schedule::new("gbnr".to_string(), "Test".to_string()),
+        )
+        .await
+        .unwrap();
+    assert!(importer.report().is_empty());
+    assert!(importer.report().warnings.is_empty());
+
+    // exactly as if they'd never been there, so the train they were in the middle of is whole
+    let (expected, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    assert_eq!(summary(&schedule), summary(&expected));
+}
+
+#[tokio::test]
+async fn known_record_types_cant_be_skipped() {
+    let cif = String::from_utf8(read_fixture("small.cif")).unwrap();
+    let mut importer = CifImporter::new(
+        serde_json::from_value::<CifImporterConfig>(json!({ "ignore_record_types": ["AA"] }))
+            .unwrap(),
+    );
+    let schedule = importer
+        .overlay(
+            cif.as_bytes(),
+            Schedule::new("gbnr".to_string(), "Test".to_string()),
+        )
+        .await
+        .unwrap();
+    assert!(schedule.trains["C10002"][0]
+        .route
+        .last()
+        .unwrap()
+        .becomes
+        .is_some());
+}
@@ -0,0 +1,64 @@
+// The import scheduler, which runs full imports alongside each other but in dependency order
+use worldrailtimetables::manager::{ImportScheduler, ImportSchedulerConfig};
+
+use serde_json::json;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+fn scheduler(config: serde_json::Value) -> ImportScheduler {
+    ImportScheduler::new(serde_json::from_value::<ImportSchedulerConfig>(config).unwrap())
+}
+
+fn namespaces(x: &[&str]) -> Vec<String> {
+    x.iter().map(|x| x.to_string()).collect()
+}
+
+#[test]
+fn dependencies_have_to_make_sense() {
+    let all = namespaces(&["gbnr", "gbni", "ieir"]);
+    assert!(
+        scheduler(json!({ "after": { "gbni": ["gbnr"], "ieir": ["gbni"] } }))
+            .check(&all)
+            .is_ok()
+    );
+    assert!(scheduler(json!({ "after": { "gbni": ["dbde"] } }))
+        .check(&all)
+        .is_err());
+    assert!(scheduler(
+        json!({ "after": { "gbnr": ["ieir"], "gbni": ["gbnr"], "ieir": ["gbni"] } })
+    )
+    .check(&all)
+    .is_err());
+}
+
+#[tokio::test]
+async fn imports_wait_for_what_theyre_after() {
+    let scheduler = Arc::new(scheduler(
+        json!({ "max_concurrent": 2, "after": { "gbni": ["gbnr"] } }),
+    ));
+
+    let waiting = {
+        let scheduler = scheduler.clone();
+        tokio::spawn(async move {
+            scheduler.start("gbni").await.finished();
+        })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!waiting.is_finished());
+
+    // one that fails doesn't let anything waiting go
+    drop(scheduler.start("gbnr").await);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!waiting.is_finished());
+    assert!(!scheduler.is_imported("gbnr"));
+
+    scheduler.start("gbnr").await.finished();
+    assert!(scheduler.is_imported("gbnr"));
+    tokio::time::timeout(Duration::from_secs(1), waiting)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(scheduler.is_imported("gbni"));
+    scheduler.wait_imported("gbni").await;
+}
@@ -0,0 +1,45 @@
+// Keeping the train indexes in step with the trains as they're deleted and replaced
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::consistency::check_schedule;
+
+use std::collections::HashSet;
+
+#[tokio::test]
+async fn deleted_train_is_unindexed() {
+    let cif = String::from_utf8(read_fixture("small.cif")).unwrap();
+    let delete = format!("BSDC10003260523{:64}P", "");
+    let cif = cif.replacen("\nZZ", &format!("\n{}\nZZ", delete), 1);
+    let (mut schedule, report) = import_cif(cif.as_bytes(), false).await.unwrap();
+    assert!(report.is_empty(), "{:?}", report);
+    assert!(schedule.trains["C10003"].is_empty());
+    assert_eq!(check_schedule(&schedule), vec![]);
+    assert!(!schedule.trains_indexed_by_public_id.contains_key("2N99"));
+    // C10001 still goes there
+    assert_eq!(
+        schedule.trains_indexed_by_location["NMPTN"],
+        HashSet::from(["C10001".to_string()])
+    );
+
+    let rebuild = schedule.rebuild_train_indexes();
+    assert_eq!((rebuild.stale_removed, rebuild.missing_added), (0, 0));
+}
+
+#[tokio::test]
+async fn rebuild_fixes_drifted_indexes() {
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    schedule
+        .trains_indexed_by_location
+        .get_mut("NMPTN")
+        .unwrap()
+        .insert("C10002".to_string());
+    schedule.trains_indexed_by_public_id.remove("2N99");
+    assert_eq!(check_schedule(&schedule).len(), 1);
+
+    let rebuild = schedule.rebuild_train_indexes();
+    assert_eq!((rebuild.stale_removed, rebuild.missing_added), (1, 1));
+    assert_eq!(check_schedule(&schedule), vec![]);
+    assert!(schedule.trains_indexed_by_public_id["2N99"].contains("C10003"));
+}
@@ -0,0 +1,53 @@
+// Invariant checks on a loaded schedule, which only fail if an importer has got something wrong
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::integrity::integrity_report;
+
+use std::collections::HashSet;
+
+#[tokio::test]
+async fn imports_hold_together() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let report = integrity_report(&schedule, 10);
+    assert_eq!(report.namespace, "gbnr");
+    assert_eq!(report.violations, 0, "{:?}", report.examples);
+}
+
+#[tokio::test]
+async fn corruption_is_found() {
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+
+    schedule
+        .trains_indexed_by_location
+        .get_mut("WATFDJ")
+        .unwrap()
+        .remove("C10001");
+    schedule
+        .trains_indexed_by_public_id
+        .insert("9Z99".to_string(), HashSet::from(["C99999".to_string()]));
+    let mut association = schedule.trains["C10002"][0]
+        .route
+        .last()
+        .unwrap()
+        .becomes
+        .clone()
+        .unwrap();
+    schedule.trains.get_mut("C10002").unwrap()[0]
+        .route
+        .truncate(1);
+    association.other_train_id = "C99998".into();
+    schedule.trains.get_mut("C10001").unwrap()[0].route[0].becomes = Some(association);
+
+    let report = integrity_report(&schedule, 1);
+    assert_eq!(report.missing_index_entries, 1);
+    assert_eq!(report.dangling_index_entries, 1);
+    assert_eq!(report.short_routes, 1);
+    assert_eq!(report.missing_association_targets, 1);
+    assert_eq!(report.violations, 4);
+    assert_eq!(report.examples.len(), 4);
+    assert!(report
+        .examples
+        .contains(&"trains_indexed_by_location doesn't have C10001 under WATFDJ".to_string()));
+}
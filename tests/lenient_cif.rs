@@ -0,0 +1,43 @@
+// Lenient imports, which skip bad records and say what they were rather than fail the whole import
+mod common;
+
+use common::{import_cif, read_fixture, summary};
+
+fn small() -> String {
+    String::from_utf8(read_fixture("small.cif")).unwrap()
+}
+
+#[tokio::test]
+async fn bad_records_only_lose_their_train() {
+    // a departure time that isn't one
+    let cif = small().replacen("LOWMBYICD 0620", "LOWMBYICD 06X0", 1);
+    assert!(import_cif(cif.as_bytes(), false).await.is_err());
+
+    let (schedule, report) = import_cif(cif.as_bytes(), true).await.unwrap();
+    assert_eq!(report.error_count, 1);
+    assert_eq!(report.errors.len(), 1);
+    assert!(!schedule.trains.contains_key("C10002"));
+    assert!(schedule.trains.contains_key("C10001"));
+
+    // and nothing to report from a good one
+    let (_, report) = import_cif(&read_fixture("small.cif"), true).await.unwrap();
+    assert!(report.is_empty());
+}
+
+#[tokio::test]
+async fn reported_errors_are_capped_but_all_counted() {
+    // schedules starting on a day that isn't one, between the last train and the end
+    let rest = "26XX182612111111100 POO2N01     22214000 EMU350 110      S            P";
+    let bad = (0..150)
+        .map(|i| format!("BSNC2{:04}{}\n", i, rest))
+        .collect::<String>();
+    let cif = small().replacen("ZZ", &format!("{}ZZ", bad), 1);
+    assert!(import_cif(cif.as_bytes(), false).await.is_err());
+
+    let (schedule, report) = import_cif(cif.as_bytes(), true).await.unwrap();
+    assert_eq!(report.error_count, 150);
+    assert_eq!(report.errors.len(), 100);
+    // everything else is as if they'd never been there
+    let (expected, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    assert_eq!(summary(&schedule), summary(&expected));
+}
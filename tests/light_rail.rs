@@ -0,0 +1,68 @@
+// Tram-trains and light metro, which come in from CIF looking like any other EMU
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::schedule::{Schedule, TrainPower, TrainType, TransportMode};
+
+async fn import_as(operator: &str, timing_load: &str) -> Schedule {
+    let cif = String::from_utf8(read_fixture("small.cif"))
+        .unwrap()
+        .replace("BX         LM", &format!("BX         {}", operator))
+        .replace("EMU350 ", &format!("EMU{} ", timing_load));
+    import_cif(cif.as_bytes(), false).await.unwrap().0
+}
+
+fn type_and_power(schedule: &Schedule, train_id: &str) -> (TrainType, Option<TrainPower>) {
+    let variable_train = &schedule.trains[train_id][0].variable_train;
+    (variable_train.train_type, variable_train.power_type)
+}
+
+#[tokio::test]
+async fn light_rail_is_told_apart_from_heavy_rail() {
+    let schedule = import_as("LM", "350").await;
+    assert_eq!(
+        type_and_power(&schedule, "C10001"),
+        (
+            TrainType::OrdinaryPassenger,
+            Some(TrainPower::ElectricMultipleUnit)
+        )
+    );
+
+    let schedule = import_as("AW", "398").await;
+    assert_eq!(
+        type_and_power(&schedule, "C10001"),
+        (
+            TrainType::TramTrain,
+            Some(TrainPower::ElectricAndBatteryTram)
+        )
+    );
+    assert_eq!(TrainType::TramTrain.mode(), TransportMode::LightRail);
+    // the empty stock move stays off boards
+    assert_eq!(
+        type_and_power(&schedule, "C10002").0,
+        TrainType::EmptyPassenger
+    );
+
+    let schedule = import_as("SJ", "350").await;
+    assert_eq!(
+        type_and_power(&schedule, "C10001"),
+        (TrainType::TramTrain, Some(TrainPower::ElectricTram))
+    );
+
+    let schedule = import_as("TW", "350").await;
+    assert_eq!(
+        type_and_power(&schedule, "C10001"),
+        (TrainType::Metro, Some(TrainPower::ElectricTram))
+    );
+    assert_eq!(type_and_power(&schedule, "C10002").0, TrainType::EmptyMetro);
+
+    let schedule = import_as("ME", "777").await;
+    assert_eq!(
+        type_and_power(&schedule, "C10001"),
+        (
+            TrainType::OrdinaryPassenger,
+            Some(TrainPower::ElectricAndBatteryMultipleUnit)
+        )
+    );
+}
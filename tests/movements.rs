@@ -0,0 +1,94 @@
+// Movements at a location in working time order, as an operational user would want them
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::movements::{MovementType, Movements, MovementsConfig};
+
+use chrono::{NaiveDate, NaiveDateTime};
+use serde_json::json;
+
+use std::collections::HashSet;
+
+fn at(date: &str, time: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H:%M").unwrap()
+}
+
+fn ids(x: &[&str]) -> HashSet<String> {
+    x.iter().map(|x| x.to_string()).collect()
+}
+
+#[tokio::test]
+async fn movements_include_everything_in_working_time_order() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let movements = Movements::new(MovementsConfig::default());
+
+    // the empty stock in, then the first train out
+    let report = movements.report(
+        &schedule,
+        &ids(&["EUSTON"]),
+        at("2026-06-02", "06:00"),
+        at("2026-06-02", "08:00"),
+    );
+    let summary = report
+        .movements
+        .iter()
+        .map(|x| (x.train_id.as_str(), x.movement, x.working_time.to_rfc3339()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        summary,
+        vec![
+            (
+                "C10002",
+                MovementType::Terminates,
+                "2026-06-02T06:40:00+01:00".to_string()
+            ),
+            (
+                "C10001",
+                MovementType::Starts,
+                "2026-06-02T07:15:30+01:00".to_string()
+            ),
+        ]
+    );
+    assert_eq!(report.movements[1].origin, "EUSTON");
+    assert_eq!(report.movements[1].destination, "NMPTN");
+
+    // passes have nothing public at all
+    let report = movements.report(
+        &schedule,
+        &ids(&["BLTCHLY"]),
+        at("2026-06-02", "07:00"),
+        at("2026-06-02", "08:00"),
+    );
+    assert_eq!(report.movements.len(), 1);
+    assert_eq!(report.movements[0].movement, MovementType::Passes);
+    assert!(report.movements[0].times.public_dep.is_none());
+
+    // the Saturday night train is still the Saturday's after midnight
+    let report = movements.report(
+        &schedule,
+        &ids(&["WATFDJ"]),
+        at("2026-06-07", "00:00"),
+        at("2026-06-07", "01:00"),
+    );
+    assert_eq!(report.movements.len(), 1);
+    assert_eq!(report.movements[0].train_id, "C10003");
+    assert_eq!(
+        report.movements[0].date,
+        NaiveDate::from_ymd_opt(2026, 6, 6).unwrap()
+    );
+    assert_eq!(report.movements[0].movement, MovementType::Stops);
+
+    // spans are cut down to the configured maximum
+    let movements = Movements::new(
+        serde_json::from_value::<MovementsConfig>(json!({ "max_hours": 1 })).unwrap(),
+    );
+    let report = movements.report(
+        &schedule,
+        &ids(&["EUSTON"]),
+        at("2026-06-02", "06:00"),
+        at("2026-06-02", "08:00"),
+    );
+    assert_eq!(report.to, at("2026-06-02", "07:00"));
+    assert_eq!(report.movements.len(), 1);
+}
@@ -0,0 +1,54 @@
+// Names for trains from reference data, as the timetable has nowhere to put them
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::importer::SlowStreamingImporter;
+use worldrailtimetables::resolved_train::resolve_train;
+use worldrailtimetables::train_names_importer::TrainNamesImporter;
+
+use chrono::NaiveDate;
+
+const NAMES: &str = "name,train_id,headcode,from,to
+The Mercian,,2N01,,
+\"The \"\"Royal\"\" Scot, summer only\",C10001,,2026-07-01,2026-07-31
+,C10002,,,
+Sunday Special,C10003,,June,
+";
+
+#[tokio::test]
+async fn trains_are_named_by_id_or_headcode() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let schedule = TrainNamesImporter::new()
+        .overlay(NAMES.as_bytes(), schedule)
+        .await
+        .unwrap();
+    // one without a name, and one with a date that isn't one
+    assert_eq!(schedule.train_names.len(), 2);
+
+    let name = |train_id: &str, date: NaiveDate| {
+        resolve_train(&schedule, train_id, date)
+            .unwrap()
+            .variable_train
+            .name
+    };
+    let june = NaiveDate::from_ymd_opt(2026, 6, 2).unwrap();
+    let july = NaiveDate::from_ymd_opt(2026, 7, 7).unwrap();
+    assert_eq!(name("C10001", june).as_deref(), Some("The Mercian"));
+    // its own beats the headcode's, while it lasts
+    assert_eq!(
+        name("C10001", july).as_deref(),
+        Some("The \"Royal\" Scot, summer only")
+    );
+    assert_eq!(name("C10002", june), None);
+}
+
+#[tokio::test]
+async fn train_names_need_a_name_column() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let names = "train_id,headcode\nC10001,2N01\n";
+    assert!(TrainNamesImporter::new()
+        .overlay(names.as_bytes(), schedule)
+        .await
+        .is_err());
+}
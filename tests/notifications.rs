@@ -0,0 +1,24 @@
+// Webhooks only go to hosts the deployment has allowed
+use worldrailtimetables::notifications::{NotificationConfig, Notifications};
+
+use serde_json::json;
+
+fn notifications(config: serde_json::Value) -> Notifications {
+    Notifications::new(serde_json::from_value::<NotificationConfig>(config).unwrap()).0
+}
+
+#[test]
+fn webhooks_need_an_allowed_host() {
+    // none by default
+    let none = notifications(json!({}));
+    assert!(!none.webhook_allowed("https://hooks.example.com/x"));
+
+    let allowed = notifications(json!({ "webhook_hosts": ["hooks.example.com"] }));
+    assert!(allowed.webhook_allowed("https://hooks.example.com/x"));
+    assert!(allowed.webhook_allowed("http://HOOKS.example.com:8080/x"));
+    assert!(!allowed.webhook_allowed("ftp://hooks.example.com/x"));
+    assert!(!allowed.webhook_allowed("https://hooks.example.com.evil.test/x"));
+    assert!(!allowed.webhook_allowed("https://hooks.example.com@169.254.169.254/x"));
+    assert!(!allowed.webhook_allowed("http://localhost/admin"));
+    assert!(!allowed.webhook_allowed("not a url"));
+}
@@ -0,0 +1,693 @@
+// The OpenAPI document against what's actually served
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::environments::Environments;
+use worldrailtimetables::feed_telemetry::FeedTelemetry;
+use worldrailtimetables::fetch_core::{HttpClient, HttpConfig};
+use worldrailtimetables::importer::SlowStreamingImporter;
+use worldrailtimetables::integrity::{Integrity, IntegrityConfig};
+use worldrailtimetables::notifications::{Interest, NotificationConfig, Notifications, TrainEvent};
+use worldrailtimetables::openapi::openapi_document;
+use worldrailtimetables::schedule::{DaysOfWeek, RealtimeEvent, RealtimeEventType, TrainRealtime};
+use worldrailtimetables::schedule_manager::ScheduleManager;
+use worldrailtimetables::staleness::{Staleness, StalenessConfig};
+use worldrailtimetables::triggers::{DailyRun, Triggers};
+use worldrailtimetables::uk_importer::{CifImporter, CifImporterConfig};
+use worldrailtimetables::webui::{build, routes, WebUiConfig};
+
+use chrono::{DateTime, Days, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Europe::London;
+use rocket::http::{ContentType, Header, Method};
+use rocket::local::asynchronous::Client;
+use serde_json::{json, Value};
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+// Rocket's <name> and <name..> segments as OpenAPI's {name}, without the query
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(
+            |x| match x.strip_prefix('<').and_then(|x| x.strip_suffix('>')) {
+                Some(x) => format!("{{{}}}", x.trim_end_matches("..")),
+                None => x.to_string(),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// the names of a route's <name> segments, in the path or the query; <name..> in the query is a
+// form of several, so can't be checked this way
+fn dynamic(segments: &str, separator: char) -> BTreeSet<String> {
+    segments
+        .split(separator)
+        .filter_map(|x| x.strip_prefix('<').and_then(|x| x.strip_suffix('>')))
+        .filter(|x| separator == '/' || !x.ends_with(".."))
+        .map(|x| x.trim_end_matches("..").to_string())
+        .collect()
+}
+
+fn documented(operation: &Value, location: &str) -> BTreeSet<String> {
+    operation["parameters"]
+        .as_array()
+        .map(|x| x.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter(|x| x["in"] == location)
+        .map(|x| x["name"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[test]
+fn every_route_is_described() {
+    let document = openapi_document();
+    let paths = document["paths"].as_object().unwrap();
+    let mut missing = vec![];
+    for route in routes() {
+        // CORS preflights are for any path
+        if route.method == Method::Options {
+            continue;
+        }
+        let path = openapi_path(route.uri.unmounted_origin.path().as_str());
+        let method = route.method.as_str().to_lowercase();
+        if paths.get(&path).and_then(|x| x.get(&method)).is_none() {
+            missing.push(format!("{} {}", method, path));
+        }
+    }
+    assert!(missing.is_empty(), "Not in the document: {:?}", missing);
+}
+
+#[test]
+fn every_routes_parameters_are_described() {
+    let document = openapi_document();
+    let mut wrong = vec![];
+    for route in routes() {
+        if route.method == Method::Options {
+            continue;
+        }
+        let origin = &route.uri.unmounted_origin;
+        let path = openapi_path(origin.path().as_str());
+        let operation = &document["paths"][&path][route.method.as_str().to_lowercase()];
+
+        let taken = dynamic(origin.path().as_str(), '/');
+        let described = documented(operation, "path");
+        if taken != described {
+            wrong.push(format!(
+                "{}: path {:?}, described {:?}",
+                path, taken, described
+            ));
+        }
+
+        // every query parameter a route takes is described; schedule_as_of is taken by the
+        // Schedules guard rather than the route itself
+        let query = origin.query().map(|x| x.as_str()).unwrap_or("");
+        let taken = dynamic(query, '&');
+        let mut described = documented(operation, "query");
+        described.remove("schedule_as_of");
+        if !taken.is_subset(&described) {
+            wrong.push(format!(
+                "{}: query {:?}, described {:?}",
+                path, taken, described
+            ));
+        }
+        if !query.contains("..>") && !described.is_subset(&taken) {
+            wrong.push(format!(
+                "{}: query {:?}, described {:?}",
+                path, taken, described
+            ));
+        }
+    }
+    assert!(wrong.is_empty(), "{:#?}", wrong);
+}
+
+#[test]
+fn boards_are_described_in_every_combination() {
+    let document = openapi_document();
+    let paths = document["paths"].as_object().unwrap();
+    let boards = paths
+        .keys()
+        .filter(|x| x.starts_with("/location/"))
+        .collect::<Vec<_>>();
+    assert_eq!(boards.len(), 12);
+    let board = &paths["/location/{namespace}/{location_id}/from/{from_id}/{date}/{time}"];
+    let parameters = board["get"]["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["name"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        parameters[..6].join(","),
+        "namespace,location_id,from_id,date,time,mode"
+    );
+}
+
+// The documented path a request's answered under: the one matching it with the most fixed
+// segments, as Rocket ranks them
+fn documented_path<'a>(document: &'a Value, path: &str) -> &'a str {
+    let segments = path.split('/').collect::<Vec<_>>();
+    document["paths"]
+        .as_object()
+        .unwrap()
+        .keys()
+        .filter(|x| {
+            let template = x.split('/').collect::<Vec<_>>();
+            template.len() == segments.len()
+                && template
+                    .iter()
+                    .zip(&segments)
+                    .all(|(t, s)| t.starts_with('{') || t == s)
+        })
+        .max_by_key(|x| x.split('/').filter(|x| !x.starts_with('{')).count())
+        .unwrap_or_else(|| panic!("Nothing documented for {}", path))
+}
+
+// A schema with its $ref followed and any allOf merged into one, noting the named ones on the way
+fn resolve(document: &Value, schema: &Value, seen: &mut BTreeSet<String>) -> Value {
+    let mut schema = schema.clone();
+    match schema["$ref"].as_str() {
+        Some(x) => {
+            let name = x.rsplit('/').next().unwrap().to_string();
+            schema = resolve(document, &document["components"]["schemas"][&name], seen);
+            seen.insert(name);
+        }
+        None => (),
+    }
+    match schema.as_object_mut().unwrap().remove("allOf") {
+        Some(Value::Array(parts)) => {
+            for part in parts {
+                let part = resolve(document, &part, seen);
+                for (k, v) in part.as_object().unwrap() {
+                    match (k.as_str(), schema.get_mut(k)) {
+                        ("properties", Some(Value::Object(properties))) => {
+                            properties.extend(v.as_object().unwrap().clone())
+                        }
+                        (_, Some(_)) => (),
+                        (_, None) => {
+                            schema[k] = v.clone();
+                        }
+                    }
+                }
+            }
+        }
+        _ => (),
+    }
+    schema
+}
+
+// What about a value doesn't match its schema, by where it is in the value: the wrong type or
+// format, or an object's fields not the ones described
+fn check(
+    document: &Value,
+    schema: &Value,
+    value: &Value,
+    at: &str,
+    seen: &mut BTreeSet<String>,
+    wrong: &mut Vec<String>,
+) {
+    // only what's actually in a response counts as seen
+    let mut maybe_seen = BTreeSet::new();
+    let schema = resolve(document, schema, &mut maybe_seen);
+    if value.is_null() {
+        if schema["nullable"] != true {
+            wrong.push(format!("{}: null", at));
+        }
+        return;
+    }
+    seen.extend(maybe_seen);
+
+    match schema["oneOf"].as_array() {
+        Some(alternatives) => {
+            let matched = alternatives.iter().any(|x| {
+                let mut alternative_wrong = vec![];
+                check(
+                    document,
+                    x,
+                    value,
+                    at,
+                    &mut BTreeSet::new(),
+                    &mut alternative_wrong,
+                );
+                alternative_wrong.is_empty()
+            });
+            if !matched {
+                wrong.push(format!("{}: none of oneOf", at));
+            }
+            return;
+        }
+        None => (),
+    }
+    match schema["enum"].as_array() {
+        Some(x) if !x.contains(value) => wrong.push(format!("{}: {} not in enum", at, value)),
+        _ => (),
+    }
+
+    let right_type = match schema["type"].as_str() {
+        Some("string") => value.is_string(),
+        Some("integer") => value.is_i64() || value.is_u64(),
+        Some("number") => value.is_number(),
+        Some("boolean") => value.is_boolean(),
+        Some("array") => value.is_array(),
+        Some("object") => value.is_object(),
+        _ => true,
+    };
+    if !right_type {
+        wrong.push(format!("{}: {} isn't {}", at, value, schema["type"]));
+        return;
+    }
+
+    match (schema["format"].as_str(), value.as_str()) {
+        (Some("date-time"), Some(x)) if DateTime::parse_from_rfc3339(x).is_err() => {
+            wrong.push(format!("{}: {} isn't a date-time", at, x))
+        }
+        (Some("date"), Some(x)) if NaiveDate::parse_from_str(x, "%Y-%m-%d").is_err() => {
+            wrong.push(format!("{}: {} isn't a date", at, x))
+        }
+        _ => (),
+    }
+
+    match value {
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                check(
+                    document,
+                    &schema["items"],
+                    item,
+                    &format!("{}[{}]", at, i),
+                    seen,
+                    wrong,
+                );
+            }
+        }
+        Value::Object(fields) => {
+            let properties = schema["properties"].as_object();
+            for (k, v) in fields {
+                let field_schema = match properties.and_then(|x| x.get(k)) {
+                    Some(x) => x,
+                    None => match schema.get("additionalProperties") {
+                        Some(x) => x,
+                        None if properties.is_none() => continue, // e.g. GeoJSON properties
+                        None => {
+                            wrong.push(format!("{}.{}: not described", at, k));
+                            continue;
+                        }
+                    },
+                };
+                check(
+                    document,
+                    field_schema,
+                    v,
+                    &format!("{}.{}", at, k),
+                    seen,
+                    wrong,
+                );
+            }
+            for (k, v) in properties.into_iter().flatten() {
+                if !fields.contains_key(k)
+                    && resolve(document, v, &mut BTreeSet::new())["nullable"] != true
+                {
+                    wrong.push(format!("{}.{}: missing", at, k));
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+// status and body
+async fn sample(
+    client: &Client,
+    method: Method,
+    path: &str,
+    token: &str,
+    body: Option<Value>,
+) -> (String, String) {
+    let mut request = client
+        .req(method, path)
+        .header(Header::new("Authorization", format!("Bearer {}", token)));
+    match body {
+        // a string's sent as it is, e.g. a CIF file
+        Some(Value::String(x)) => request = request.header(ContentType::Binary).body(x),
+        Some(x) => {
+            request = request
+                .header(ContentType::JSON)
+                .body(serde_json::to_vec(&x).unwrap())
+        }
+        None => (),
+    }
+    let response = request.dispatch().await;
+    let status = response.status().code.to_string();
+    (status, response.into_string().await.unwrap_or_default())
+}
+
+// small.cif, with a UIC number on C10001
+fn gbnr_extract() -> String {
+    String::from_utf8(read_fixture("small.cif"))
+        .unwrap()
+        .replacen("BX         LM", "BX    12345LM", 1)
+}
+
+// An update with a freight copy of C10002's empty stock move, wanting the same platform at Euston
+// at the same time, and an overlay for C10004, which we haven't got
+fn gbnr_update(extract: &str) -> String {
+    let mut lines = extract.lines();
+    let mut header = lines.next().unwrap().to_string();
+    header.replace_range(46..47, "U");
+    let c10002 = lines
+        .skip_while(|x| !x.starts_with("BSNC10002"))
+        .take(4)
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>();
+    let mut freight = c10002.clone();
+    freight[0].replace_range(3..9, "C10005");
+    freight[0].replace_range(30..32, "DD");
+    let mut overlay = c10002;
+    overlay[0].replace_range(3..9, "C10004");
+    overlay[0].replace_range(79..80, "O");
+    format!(
+        "{}\n{}\n{}\n{:<80}\n",
+        header,
+        freight.join("\n"),
+        overlay.join("\n"),
+        "ZZ"
+    )
+}
+
+// Another feed's leg of C10001, carrying on from Northampton to Rugby under the same UIC number
+fn other_extract(extract: &str) -> String {
+    let lines = extract.lines().collect::<Vec<_>>();
+    let find = |prefix: &str| {
+        lines
+            .iter()
+            .find(|x| x.starts_with(prefix))
+            .unwrap()
+            .to_string()
+    };
+    let mut schedule = find("BSNC10001").replace("C10001", "C20001");
+    schedule.replace_range(21..28, "1111111");
+    [
+        find("HD"),
+        find("TINMPTN"),
+        find("TINMPTN")
+            .replace(
+                "TINMPTN  00000000 NORTHAMPTON",
+                "TIRUGBY  00000000 RUGBY      ",
+            )
+            .replace("NMPNORTHAMPTON", "RUGRUGBY      "),
+        schedule,
+        format!("{:<80}", "BX    12345LMYLM123400"),
+        find("LONMPTN").replace("2345 2345", "0830 0830"),
+        find("LTNMPTN").replace("LTNMPTN   0821 0821", "LTRUGBY   0900 0900"),
+        format!("{:<80}", "ZZ"),
+    ]
+    .join("\n")
+        + "\n"
+}
+
+#[tokio::test]
+async fn sample_responses_match_their_schemas() {
+    // enough going on that every schema turns up somewhere
+    let extract = gbnr_extract();
+    let (schedule, _) = import_cif(extract.as_bytes(), false).await.unwrap();
+    let mut schedule = CifImporter::new(CifImporterConfig::default())
+        .overlay(gbnr_update(&extract).as_bytes(), schedule)
+        .await
+        .unwrap();
+    let date = NaiveDate::from_ymd_opt(2026, 6, 2).unwrap();
+    schedule
+        .realtime
+        .entry("C10001".to_string())
+        .or_default()
+        .insert(
+            date,
+            TrainRealtime {
+                realtime_id: "721N01MU02".to_string(),
+                activated: Some(London.with_ymd_and_hms(2026, 6, 2, 7, 0, 0).unwrap()),
+                cancellation: None,
+                events: vec![RealtimeEvent {
+                    location_ids: vec!["WATFDJ".to_string()],
+                    event_type: RealtimeEventType::Departure,
+                    planned: Some(London.with_ymd_and_hms(2026, 6, 2, 7, 32, 0).unwrap()),
+                    actual: London.with_ymd_and_hms(2026, 6, 2, 7, 35, 0).unwrap(),
+                    platform: Some("9".to_string()),
+                    delay_minutes: 3,
+                    off_route: false,
+                }],
+                terminated: false,
+            },
+        );
+    // running every day around whenever this is run, for departures from now
+    let validity = &mut schedule.trains.get_mut("C10003").unwrap()[0].validity[0];
+    validity.valid_begin = London.from_utc_datetime(&Utc::now().naive_utc()) - Days::new(14);
+    validity.valid_end = validity.valid_begin + Days::new(28);
+    validity.days_of_week = DaysOfWeek {
+        monday: true,
+        tuesday: true,
+        wednesday: true,
+        thursday: true,
+        friday: true,
+        saturday: true,
+        sunday: true,
+    };
+    let (mut other, _) = import_cif(other_extract(&extract).as_bytes(), false)
+        .await
+        .unwrap();
+    other.namespace = "other".to_string();
+
+    let integrity = Arc::new(Integrity::new(
+        IntegrityConfig::default(),
+        Arc::new(ScheduleManager::new()),
+    ));
+    integrity.check(&schedule);
+    let schedule_manager = Arc::new(ScheduleManager::new());
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.put("gbnr", schedule);
+    transaction.put("other", other);
+    transaction.source_updated("gbnr-cif");
+    transaction.archive();
+    transaction.commit();
+
+    let (notifications, _) = Notifications::new(NotificationConfig::default());
+    let interest = Interest::Train {
+        namespace: "gbnr".to_string(),
+        train_id: "C10001".to_string(),
+        date,
+    };
+    let subscription = notifications.subscribe(interest, None).unwrap();
+    notifications.publish(
+        &schedule_manager.read()["gbnr"],
+        "C10001",
+        date,
+        TrainEvent::Retimed {
+            location_ids: vec!["WATFDJ".to_string()],
+            delay_minutes: 3,
+        },
+    );
+    let events = format!("/subscriptions/{}/events", subscription);
+
+    let triggers = Arc::new(Triggers::new());
+    let mut daily_run = DailyRun::new(
+        London,
+        NaiveTime::from_hms_opt(3, 0, 0).unwrap(),
+        &triggers,
+        "gbnr-cif",
+    );
+    tokio::spawn(async move { daily_run.wait().await });
+    let feed_telemetry = Arc::new(FeedTelemetry::new());
+    feed_telemetry.connection("gbnr-vstp", true);
+    feed_telemetry.message("gbnr-vstp", &["new_field".to_string()]);
+    let http_client = Arc::new(
+        HttpClient::new(serde_json::from_value::<HttpConfig>(json!({ "retries": 0 })).unwrap())
+            .unwrap(),
+    );
+    // nothing's listening there
+    assert!(http_client
+        .get("gbnr-cif", "http://127.0.0.1:9/", None)
+        .await
+        .is_err());
+
+    let config = serde_json::from_value::<WebUiConfig>(json!({
+        "admin": { "token": "admin" },
+        "upstream": { "token": "upstream" },
+        "branding": {
+            "namespaces": {
+                "gbnr": { "operators": { "LM": { "name": "West Midlands Trains", "colour": "#ff8300" } } },
+            },
+        },
+        "interchange": {
+            "links": [{
+                "from": { "namespace": "gbnr", "id": "EUSTON" },
+                "to": { "namespace": "gbnr", "id": "WATFDJ" },
+                "mode": "Bus",
+                "duration_secs": 3600,
+            }],
+        },
+        "regions": { "countries": { "gbnr": "GB" } },
+    }))
+    .unwrap();
+    let client = Client::untracked(
+        build(
+            Environments::new(schedule_manager.clone()),
+            Arc::new(notifications),
+            Arc::new(Staleness::new(
+                StalenessConfig::default(),
+                schedule_manager.clone(),
+            )),
+            integrity,
+            triggers,
+            None,
+            http_client,
+            feed_telemetry,
+            config,
+        )
+        .unwrap(),
+    )
+    .await
+    .unwrap();
+
+    let document = openapi_document();
+    let mut seen = BTreeSet::new();
+    let mut wrong = vec![];
+    let requests = [
+        (Method::Get, "/service/C10001/2026-06-01", "admin", None),
+        (Method::Get, "/service/gbnr:C10001:20260601", "admin", None),
+        (Method::Get, "/service/gbnr:C10001:20260602", "admin", None),
+        (Method::Get, "/uic/12345/2026-06-02", "admin", None),
+        (Method::Get, "/departures?country=GB", "admin", None),
+        (Method::Get, "/live/positions", "admin", None),
+        (Method::Get, "/freight/gbnr/2026-06-01", "admin", None),
+        (
+            Method::Get,
+            "/train/gbnr/C10001/calendar/2026-05-18/2026-06-30",
+            "admin",
+            None,
+        ),
+        (
+            Method::Get,
+            "/train/C10001/2026-06-01/history",
+            "admin",
+            None,
+        ),
+        (Method::Get, "/next?from=EUSTON&to=NMPTN", "admin", None),
+        (Method::Get, "/locations/search?q=watford", "admin", None),
+        (Method::Get, "/operators", "admin", None),
+        (Method::Get, "/interchange/gbnr/EUSTON", "admin", None),
+        (
+            Method::Get,
+            "/platforms/gbnr-internal/EUSTON/2026-06-02",
+            "admin",
+            None,
+        ),
+        (
+            Method::Get,
+            "/flows/gbnr-internal/EUSTON/NMPTN/2026-06-01/2026-06-07",
+            "admin",
+            None,
+        ),
+        (
+            Method::Get,
+            "/dwell/gbnr-internal/WATFDJ/2026-06-02",
+            "admin",
+            None,
+        ),
+        (
+            Method::Get,
+            "/dwell/gbnr-internal/EUSTON/2026-06-02",
+            "admin",
+            None,
+        ),
+        (
+            Method::Get,
+            "/movements/gbnr-internal/WATFDJ?from=2026-06-02T07:00&to=2026-06-02T10:00",
+            "admin",
+            None,
+        ),
+        (Method::Get, "/stats", "admin", None),
+        (Method::Get, "/quality", "admin", None),
+        (Method::Get, "/overlays/pending", "admin", None),
+        (Method::Get, "/status", "admin", None),
+        (Method::Get, "/healthz", "admin", None),
+        (Method::Get, "/readyz", "admin", None),
+        (Method::Get, "/cache/stats", "admin", None),
+        (Method::Get, "/admin/sources", "admin", None),
+        (Method::Get, "/admin/feeds", "admin", None),
+        (Method::Get, "/admin/downloads", "admin", None),
+        (Method::Post, "/admin/reindex", "admin", None),
+        (
+            Method::Post,
+            "/admin/validate",
+            "admin",
+            Some(Value::String(extract.clone())),
+        ),
+        (Method::Get, "/upstream", "upstream", None),
+        (
+            Method::Post,
+            "/admin/alerts",
+            "admin",
+            Some(json!({
+                "message": "Engineering works",
+                "severity": "Warning",
+                "location_id": "EUSTON",
+            })),
+        ),
+        (Method::Get, "/admin/alerts", "admin", None),
+        (Method::Get, "/alerts", "admin", None),
+        (
+            Method::Post,
+            "/subscriptions",
+            "admin",
+            Some(json!({
+                "interest": {
+                    "type": "Train",
+                    "namespace": "gbnr",
+                    "train_id": "C10001",
+                    "date": "2026-06-01",
+                },
+            })),
+        ),
+        (Method::Get, events.as_str(), "admin", None),
+    ];
+    for (method, path, token, body) in requests {
+        let template = documented_path(&document, path.split('?').next().unwrap());
+        let operation = &document["paths"][template][method.as_str().to_lowercase()];
+        let at = format!("{} {}", method, path);
+        match &body {
+            Some(Value::String(_)) | None => (),
+            Some(x) => check(
+                &document,
+                &operation["requestBody"]["content"]["application/json"]["schema"],
+                x,
+                &format!("{} request", at),
+                &mut seen,
+                &mut wrong,
+            ),
+        }
+
+        let (status, response) = sample(&client, method, path, token, body.clone()).await;
+        let schema = &operation["responses"][&status]["content"]["application/json"]["schema"];
+        match (status.as_str(), schema.is_null()) {
+            ("200", true) => continue, // not JSON
+            (_, true) => {
+                wrong.push(format!("{}: {} not described", at, status));
+                continue;
+            }
+            _ => (),
+        }
+        let response = serde_json::from_str::<Value>(&response).unwrap();
+        check(&document, schema, &response, &at, &mut seen, &mut wrong);
+    }
+    assert!(wrong.is_empty(), "{:#?}", wrong);
+
+    // live positions need track geometry and a train running as this is run
+    let unsampled = ["LivePositionProperties"];
+    let unseen = document["components"]["schemas"]
+        .as_object()
+        .unwrap()
+        .keys()
+        .filter(|x| !seen.contains(*x) && !unsampled.contains(&x.as_str()))
+        .collect::<Vec<_>>();
+    assert!(unseen.is_empty(), "No sample of {:?}", unseen);
+}
@@ -0,0 +1,44 @@
+// Trains timetabled past midnight as part of the day before
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use chrono::NaiveDate;
+
+use worldrailtimetables::schedule::{calendar_date, operating_date};
+
+fn date(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2026, 6, day).unwrap()
+}
+
+#[test]
+fn converts_between_operating_and_calendar_dates() {
+    // a Saturday-night train at 24:30 leaves on the Sunday
+    assert_eq!(calendar_date(date(6), Some(1)), Some(date(7)));
+    assert_eq!(operating_date(date(7), Some(1)), Some(date(6)));
+    assert_eq!(calendar_date(date(6), None), Some(date(6)));
+    assert_eq!(operating_date(date(6), Some(0)), Some(date(6)));
+}
+
+#[tokio::test]
+async fn train_after_midnight_keeps_its_operating_date() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let mut train = schedule.trains["C10002"][0].clone();
+    // CIF trains are always dated by the day they leave their origin
+    assert_eq!(train.departure_date(date(1)), Some(date(1)));
+
+    // as a GTFS feed would give it, with the whole journey on day 1
+    for location in train.route.iter_mut() {
+        for day in [
+            &mut location.working_arr_day,
+            &mut location.working_dep_day,
+            &mut location.working_pass_day,
+            &mut location.public_arr_day,
+            &mut location.public_dep_day,
+        ] {
+            *day = day.map(|x| x + 1);
+        }
+    }
+    assert_eq!(train.departure_date(date(6)), Some(date(7)));
+    assert_eq!(train.operating_date_for_departure(date(7)), Some(date(6)));
+}
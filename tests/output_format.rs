@@ -0,0 +1,50 @@
+// Rewriting responses' units and time formats on the way out
+use worldrailtimetables::output_format::{OutputFormat, SpeedUnit, TimeFormat};
+
+use serde_json::json;
+
+#[test]
+fn times_keep_their_offset() {
+    let mut value = json!({
+        "departure": "2026-06-01T07:15:00+01:00",
+        "working_arr": ["2026-06-01T23:45:30+01:00"],
+    });
+    OutputFormat {
+        speed: SpeedUnit::MetresPerSecond,
+        time: TimeFormat::TwelveHour,
+        seconds: false,
+    }
+    .apply(&mut value);
+    assert_eq!(
+        value,
+        json!({
+            "departure": "2026-06-01 7:15am +01:00",
+            "working_arr": ["2026-06-01 11:45pm +01:00"],
+        })
+    );
+}
+
+#[test]
+fn only_time_fields_are_rewritten() {
+    let mut value = json!([{
+        "time": "23:45:30",
+        "note": "23:45:30",
+        "id": "2026-06-01T07:15:00+01:00",
+        "top_speed_m_per_s": 10.0,
+    }]);
+    OutputFormat {
+        speed: SpeedUnit::KilometresPerHour,
+        time: TimeFormat::TwentyFourHour,
+        seconds: false,
+    }
+    .apply(&mut value);
+    assert_eq!(
+        value,
+        json!([{
+            "time": "23:45",
+            "note": "23:45:30",
+            "id": "2026-06-01T07:15:00+01:00",
+            "top_speed_km_per_h": 36.0,
+        }])
+    );
+}
@@ -0,0 +1,88 @@
+// Cutting responses down to a page and the fields asked for
+use worldrailtimetables::paging::Paging;
+
+use serde_json::json;
+
+fn trains() -> serde_json::Value {
+    json!([
+        { "id": "A", "date": "2026-06-01", "route": [{ "id": "EUSTON", "platform": "1" }] },
+        { "id": "B", "date": "2026-06-01", "route": [{ "id": "WATFDJ", "platform": "2" }] },
+        { "id": "C", "date": "2026-06-01", "route": [] },
+    ])
+}
+
+#[test]
+fn lists_are_paged_and_pruned() {
+    assert_eq!(Paging::parse(None, None, None), Ok(None));
+    assert!(Paging::parse(Some("x"), None, None).is_err());
+    assert!(Paging::parse(None, None, Some("route..id")).is_err());
+
+    let paging = Paging::parse(None, Some("2"), Some("id,route.id"))
+        .unwrap()
+        .unwrap();
+    let mut page = trains();
+    let cursor = paging.apply(&mut page, None, 7).unwrap().unwrap();
+    assert_eq!(
+        page,
+        json!([
+            { "id": "A", "route": [{ "id": "EUSTON" }] },
+            { "id": "B", "route": [{ "id": "WATFDJ" }] },
+        ])
+    );
+
+    let paging = Paging::parse(Some(&cursor), Some("2"), Some("-route,-date"))
+        .unwrap()
+        .unwrap();
+    let mut page = trains();
+    assert_eq!(paging.apply(&mut page, None, 7), Ok(None));
+    assert_eq!(page, json!([{ "id": "C" }]));
+
+    // not a list, so only the fields
+    let paging = Paging::parse(None, Some("1"), Some("-route.platform"))
+        .unwrap()
+        .unwrap();
+    let mut service = trains()[0].clone();
+    assert_eq!(paging.apply(&mut service, None, 7), Ok(None));
+    assert_eq!(
+        service,
+        json!({ "id": "A", "date": "2026-06-01", "route": [{ "id": "EUSTON" }] })
+    );
+}
+
+#[test]
+fn cursors_only_last_a_generation() {
+    let paging = Paging::parse(None, Some("1"), None).unwrap().unwrap();
+    let cursor = paging.apply(&mut trains(), None, 7).unwrap().unwrap();
+    let paging = Paging::parse(Some(&cursor), Some("1"), None)
+        .unwrap()
+        .unwrap();
+    let mut page = trains();
+    assert!(paging.apply(&mut page, None, 7).unwrap().is_some());
+    assert_eq!(page[0]["id"], "B");
+    assert!(paging.apply(&mut trains(), None, 8).is_err());
+
+    // nor for a list that's somehow changed regardless
+    let mut page = trains();
+    page.as_array_mut().unwrap().remove(0);
+    assert!(paging.apply(&mut page, None, 7).is_err());
+}
+
+#[test]
+fn objects_are_paged_through_their_list() {
+    let report = || json!({ "namespace": "gbnr", "movements": trains() });
+    let paging = Paging::parse(None, Some("2"), Some("namespace,movements.id"))
+        .unwrap()
+        .unwrap();
+    let mut page = report();
+    let cursor = paging.apply(&mut page, Some("/movements"), 7).unwrap();
+    assert!(cursor.is_some());
+    assert_eq!(
+        page,
+        json!({ "namespace": "gbnr", "movements": [{ "id": "A" }, { "id": "B" }] })
+    );
+
+    // unless the endpoint doesn't say which
+    let mut page = report();
+    assert_eq!(paging.apply(&mut page, None, 7), Ok(None));
+    assert_eq!(page["movements"].as_array().unwrap().len(), 3);
+}
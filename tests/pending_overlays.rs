@@ -0,0 +1,72 @@
+// STP overlays that come in a file before the one with the schedule they replace
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::importer::SlowStreamingImporter;
+use worldrailtimetables::resolved_train::resolve_train;
+use worldrailtimetables::schedule::Schedule;
+use worldrailtimetables::uk_importer::{CifImporter, CifImporterConfig};
+
+use chrono::NaiveDate;
+
+// an update with C10002's empty stock move as C10004, from the 18th of May or only in June
+fn update(begin_end: &str, stp_indicator: &str) -> String {
+    let fixture = String::from_utf8(read_fixture("small.cif")).unwrap();
+    let mut lines = fixture.lines();
+    let mut header = lines.next().unwrap().to_string();
+    header.replace_range(46..47, "U");
+    let mut schedule = lines
+        .skip_while(|x| !x.starts_with("BSNC10002"))
+        .take(4)
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>();
+    schedule[0].replace_range(3..9, "C10004");
+    schedule[0].replace_range(9..21, begin_end);
+    schedule[0].replace_range(79..80, stp_indicator);
+    format!("{}\n{}\n{:<80}\n", header, schedule.join("\n"), "ZZ")
+}
+
+async fn import(schedule: Schedule, cif: String) -> Schedule {
+    CifImporter::new(CifImporterConfig::default())
+        .overlay(cif.as_bytes(), schedule)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn overlays_wait_for_what_they_replace() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let date = NaiveDate::from_ymd_opt(2026, 6, 2).unwrap();
+
+    // with nothing else under its ID, it runs on its own meanwhile
+    let schedule = import(schedule, update("260601260630", "O")).await;
+    assert_eq!(schedule.pending_overlays["C10004"].len(), 1);
+    assert!(schedule.pending_overlays["C10004"][0].standalone);
+    assert_eq!(schedule.trains["C10004"].len(), 1);
+    assert!(!resolve_train(&schedule, "C10004", date).unwrap().modified);
+
+    // then replaces the schedule it was meant for once that turns up
+    let schedule = import(schedule, update("260518261211", "P")).await;
+    assert!(schedule.pending_overlays.is_empty());
+    let trains = &schedule.trains["C10004"];
+    assert_eq!(trains.len(), 1);
+    assert_eq!(trains[0].replacements.len(), 1);
+    assert!(resolve_train(&schedule, "C10004", date).unwrap().modified);
+    let date = NaiveDate::from_ymd_opt(2026, 7, 7).unwrap();
+    assert!(!resolve_train(&schedule, "C10004", date).unwrap().modified);
+}
+
+#[tokio::test]
+async fn pending_overlays_can_be_deleted() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let schedule = import(schedule, update("260601260630", "O")).await;
+
+    // a delete is the BS on its own
+    let overlay = update("260601260630", "O");
+    let lines = overlay.lines().collect::<Vec<_>>();
+    let delete = format!("{}\nBSD{}\n{:<80}\n", lines[0], &lines[1][3..], "ZZ");
+    let schedule = import(schedule, delete).await;
+    assert!(schedule.pending_overlays.is_empty());
+    assert!(!schedule.trains.contains_key("C10004"));
+}
@@ -0,0 +1,151 @@
+// What the proxy adds to upstream's answers from our own schedules
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::fetch_core::{HttpClient, HttpConfig};
+use worldrailtimetables::proxy::{Proxy, ProxyConfig};
+
+use serde_json::json;
+
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+
+#[tokio::test]
+async fn upstream_answers_get_local_trains_and_locations() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let proxy = Proxy::new(
+        serde_json::from_value::<ProxyConfig>(json!({
+            "endpoints": {
+                "darwin": {
+                    "url": "https://darwin.example/{path}",
+                    "train_id_fields": ["uid"],
+                    "public_id_fields": ["trainid"],
+                    "location_fields": ["crs"],
+                    "date_field": "sdd",
+                },
+            },
+        }))
+        .unwrap(),
+    );
+    assert_eq!(proxy.namespace("darwin"), Some("gbnr"));
+    assert_eq!(proxy.namespace("nope"), None);
+    assert!(!proxy.only());
+
+    let mut value = json!({
+        "locationName": "London Euston",
+        "crs": "EUS",
+        "trainServices": [
+            { "uid": "C10001", "sdd": "2026-06-02", "trainid": "2N01" },
+            // no UID, but only one 5N01 runs that day
+            { "sdd": "2026-06-02", "trainid": "5N01" },
+            // not running that day
+            { "uid": "C10003", "sdd": "2026-06-02" },
+            { "uid": "C10001", "sdd": "not a date" },
+        ],
+    });
+    proxy.enrich("darwin", &schedule, &mut value);
+
+    assert_eq!(value["local_location"]["id"], "EUSTON");
+    assert_eq!(value["local_location"]["name"], "LONDON EUSTON");
+    let services = value["trainServices"].as_array().unwrap();
+    assert_eq!(services[0]["local_train"]["id"], "C10001");
+    assert_eq!(services[0]["local_train"]["date"], "2026-06-02");
+    assert_eq!(services[0]["local_train"]["destination"], "NMPTN");
+    assert_eq!(services[0]["local_train"]["cancelled"], false);
+    assert_eq!(services[1]["local_train"]["id"], "C10002");
+    assert!(services[2].get("local_train").is_none());
+    assert!(services[3].get("local_train").is_none());
+    // upstream's own fields are left as they were
+    assert_eq!(services[0]["uid"], "C10001");
+    assert_eq!(value["locationName"], "London Euston");
+
+    // nothing's added for endpoints that aren't configured
+    let mut value = json!({ "uid": "C10001", "sdd": "2026-06-02" });
+    proxy.enrich("nope", &schedule, &mut value);
+    assert!(value.get("local_train").is_none());
+}
+
+// An upstream that answers anything with how many requests it's had, keeping each request's head
+fn upstream() -> (u16, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let requests = Arc::new(Mutex::new(vec![]));
+    let seen = requests.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut head = vec![];
+            let mut byte = [0];
+            while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+                head.push(byte[0]);
+            }
+            let mut seen = seen.lock().unwrap();
+            seen.push(String::from_utf8_lossy(&head).to_string());
+            let body = json!({ "n": seen.len() }).to_string();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        }
+    });
+    (port, requests)
+}
+
+#[tokio::test]
+async fn only_listed_query_parameters_go_upstream_and_the_cache_is_bounded() {
+    let (port, requests) = upstream();
+    let http_client = HttpClient::new(HttpConfig::default()).unwrap();
+    let proxy = Proxy::new(
+        serde_json::from_value::<ProxyConfig>(json!({
+            "endpoints": {
+                "up": {
+                    "url": format!("http://127.0.0.1:{}/{{path}}", port),
+                    "headers": { "X-Api-Key": "secret" },
+                    "query_params": ["crs", "rows"],
+                },
+            },
+            "max_cached": 2,
+        }))
+        .unwrap(),
+    );
+
+    let fetch = |path: &'static str, query: Option<&'static str>| {
+        let proxy = &proxy;
+        let http_client = &http_client;
+        async move {
+            proxy
+                .fetch(http_client, "up", path, query)
+                .await
+                .unwrap()
+                .unwrap()
+        }
+    };
+
+    assert_eq!(fetch("a", Some("rows=5&key=x&crs=EUS")).await["n"], 1);
+    {
+        let requests = requests.lock().unwrap();
+        assert!(requests[0].starts_with("GET /a?crs=EUS&rows=5 HTTP/1.1\r\n"));
+        assert!(requests[0].to_lowercase().contains("x-api-key: secret"));
+    }
+    // the same parameters in another order, or with others that are dropped, are the same answer
+    assert_eq!(fetch("a", Some("crs=EUS&rows=5")).await["n"], 1);
+    assert!(proxy
+        .fetch(&http_client, "nope", "a", None)
+        .await
+        .unwrap()
+        .is_none());
+
+    // the least recently used answer makes way once it's full
+    assert_eq!(fetch("b", None).await["n"], 2);
+    assert_eq!(fetch("a", Some("crs=EUS&rows=5")).await["n"], 1);
+    assert_eq!(fetch("c", None).await["n"], 3);
+    assert_eq!(proxy.cached_count(), 2);
+    assert_eq!(fetch("a", Some("crs=EUS&rows=5")).await["n"], 1);
+    assert_eq!(fetch("b", None).await["n"], 4);
+}
@@ -0,0 +1,88 @@
+// Public mode, which leaves out everything that isn't for passengers
+use worldrailtimetables::redaction::{Redaction, RedactionConfig};
+
+use serde_json::json;
+
+#[test]
+fn public_mode_hides_what_passengers_cant_use() {
+    let redaction = Redaction::new(
+        serde_json::from_value::<RedactionConfig>(json!({
+            "public_mode": true,
+            "endpoints": { "freight": { "freight": false } },
+        }))
+        .unwrap(),
+    );
+
+    let mut board = json!([
+        { "id": "A", "train_type": "OrdinaryPassenger", "public_dep": "08:00", "working_dep": "08:00:30", "working_pass": null },
+        { "id": "B", "train_type": "OrdinaryPassenger", "public_dep": null, "public_arr": null, "working_pass": "08:05" },
+        { "id": "C", "train_type": "Parcels", "public_dep": "08:10", "working_pass": null },
+        { "id": "D", "train_type": "EmptyPassenger", "public_dep": null, "working_pass": null },
+    ]);
+    assert!(redaction
+        .policy("/location/EUSTON")
+        .unwrap()
+        .apply(&mut board));
+    assert_eq!(
+        board,
+        json!([{ "id": "A", "train_type": "OrdinaryPassenger", "public_dep": "08:00" }])
+    );
+
+    let mut service = json!({
+        "variable_train": { "train_type": "ExpressPassenger" },
+        "route": [
+            { "id": "EUSTON", "flags": { "public": true }, "activities": { "train_begins": true, "crew_change": false } },
+            { "id": "WMBYICD", "flags": { "public": false }, "activities": { "train_begins": false, "crew_change": true } },
+        ],
+    });
+    assert!(redaction
+        .policy("/service/C10001/2026-06-01")
+        .unwrap()
+        .apply(&mut service));
+    assert_eq!(
+        service["route"],
+        json!([{ "id": "EUSTON", "flags": { "public": true }, "activities": { "train_begins": true } }])
+    );
+
+    // a train that isn't for passengers at all isn't there
+    let mut service = json!({ "variable_train": { "train_type": "Staff" }, "route": [] });
+    assert!(!redaction
+        .policy("/service/C10002/2026-06-01")
+        .unwrap()
+        .apply(&mut service));
+
+    // endpoints with their own policy keep it
+    let mut freight = json!([{ "id": "F", "train_type": "Freight", "working_pass": "09:00" }]);
+    assert!(redaction.policy("/freight").unwrap().apply(&mut freight));
+    assert_eq!(freight.as_array().unwrap().len(), 1);
+}
+
+// pages are rendered from the same data, but their templates want every field there
+#[test]
+fn public_mode_covers_pages_too() {
+    let redaction = Redaction::new(
+        serde_json::from_value::<RedactionConfig>(json!({ "public_mode": true })).unwrap(),
+    );
+    let policy = redaction.policy("/train/gbnr/C10001/2026-06-01").unwrap();
+
+    let mut page = json!({
+        "train": {
+            "variable_train": { "train_type": "ExpressPassenger" },
+            "route": [
+                { "id": "EUSTON", "public_dep": "07:15", "working_dep": "07:15:30", "working_pass": null },
+                { "id": "WMBYICD", "public_dep": null, "public_arr": null, "working_pass": "07:21" },
+            ],
+        },
+        "trains": [{ "id": "C10002", "train_type": "Locomotive", "working_dep": "09:00" }],
+    });
+    assert!(policy.apply_to_page(&mut page));
+    assert_eq!(
+        page["train"]["route"],
+        json!([{ "id": "EUSTON", "public_dep": "07:15", "working_dep": null, "working_pass": null }])
+    );
+    assert_eq!(page["trains"], json!([]));
+
+    // the train page goes when its train does
+    let mut page = json!({ "train": { "variable_train": { "train_type": "Freight" } } });
+    assert!(!policy.apply_to_page(&mut page["train"]));
+}
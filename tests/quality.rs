@@ -0,0 +1,43 @@
+// Data quality reports, and the history kept of them across imports
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::quality::{quality_report, Quality, QualityConfig};
+use worldrailtimetables::schedule::DaysOfWeek;
+
+use chrono::Weekday;
+
+#[tokio::test]
+async fn quality_is_scored_and_kept() {
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+
+    let report = quality_report(&schedule);
+    assert_eq!(report.schedules, 4);
+    // the empty stock move doesn't count
+    assert_eq!(
+        (report.passenger_schedules, report.with_public_times),
+        (3, 3)
+    );
+    // 2N01 becomes 5N01 at Euston, which is there
+    assert_eq!((report.associations, report.valid_associations), (1, 1));
+    assert_eq!((report.overlays, report.orphaned_overlays), (1, 0));
+    assert_eq!(report.public_times_pct, Some(100.0));
+
+    let quality = Quality::new(QualityConfig::default());
+    let before = quality.record(&schedule);
+
+    // an overlay on Mondays for a train that only runs on Saturdays
+    let c10003 = &mut schedule.trains.get_mut("C10003").unwrap()[0];
+    let mut overlay = c10003.clone();
+    overlay.validity[0].days_of_week = DaysOfWeek::from_single_weekday(Weekday::Mon);
+    c10003.replacements.push(overlay);
+    let after = quality.record(&schedule);
+    assert_eq!((after.overlays, after.orphaned_overlays), (2, 1));
+    assert_eq!(after.orphaned_overlays_pct, Some(50.0));
+    assert!(after.score.unwrap() < before.score.unwrap());
+
+    let reports = quality.reports([&schedule]);
+    assert_eq!(reports["gbnr"].len(), 2);
+    assert_eq!(reports["gbnr"][1].orphaned_overlays, 1);
+}
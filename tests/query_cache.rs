@@ -0,0 +1,21 @@
+// Caching boards and the like until what they were made from changes
+use worldrailtimetables::query_cache::QueryCache;
+
+use std::time::Duration;
+
+#[test]
+fn entries_last_until_their_own_generation_moves_on() {
+    let cache = QueryCache::new(Duration::from_secs(60));
+    assert_eq!(cache.get_or_insert_with("gbnr".to_string(), 1, || 1), 1);
+    assert_eq!(cache.get_or_insert_with("gbni".to_string(), 1, || 2), 2);
+
+    // gbnr changes, gbni doesn't
+    assert_eq!(cache.get_or_insert_with("gbnr".to_string(), 2, || 3), 3);
+    assert_eq!(cache.get_or_insert_with("gbni".to_string(), 1, || 4), 2);
+    assert_eq!(cache.get_or_insert_with("gbnr".to_string(), 2, || 5), 3);
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses), (2, 3));
+    assert_eq!(stats.entries, 2);
+    assert_eq!(stats.generation, 2);
+}
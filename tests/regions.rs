@@ -0,0 +1,68 @@
+// Placing locations in countries and regions
+mod common;
+
+use common::{fixture, import_cif, read_fixture};
+
+use worldrailtimetables::regions::{Region, Regions, RegionsConfig};
+use worldrailtimetables::schedule::Coordinate;
+
+use serde_json::json;
+
+fn region(country: &str, region: Option<&str>) -> Region {
+    Region {
+        country: Some(country.to_string()),
+        region: region.map(|x| x.to_string()),
+    }
+}
+
+#[tokio::test]
+async fn locate_by_reference_data_then_boundaries_then_namespace() {
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let config: RegionsConfig = serde_json::from_value(json!({
+        "boundary_files": [fixture("regions.geojson")],
+        "locations": { &schedule.namespace: { "EUSTON": { "country": "GB", "region": "London" } } },
+        "countries": { &schedule.namespace: "GB" },
+    }))
+    .unwrap();
+    let regions = Regions::new(config).unwrap();
+    for (id, latitude, longitude) in [
+        ("EUSTON", 51.53, -0.13),
+        ("NMPTN", 52.24, -0.91), // in the hole
+        ("WATFDJ", 51.66, -0.40),
+    ] {
+        schedule.locations.get_mut(id).unwrap().position = Some(Coordinate {
+            latitude,
+            longitude,
+        });
+    }
+
+    assert_eq!(
+        regions.locate(&schedule, "EUSTON"),
+        region("GB", Some("London"))
+    );
+    assert_eq!(
+        regions.locate(&schedule, "WATFDJ"),
+        region("GB", Some("England"))
+    );
+    assert_eq!(regions.locate(&schedule, "NMPTN"), region("GB", None));
+    assert_eq!(regions.locate(&schedule, "BLTCHLY"), region("GB", None));
+    assert!(regions
+        .locate(&schedule, "WATFDJ")
+        .matches(Some("gb"), Some("ENGLAND")));
+    assert!(!regions
+        .locate(&schedule, "NMPTN")
+        .matches(None, Some("England")));
+
+    // moving it means working it out again
+    schedule.locations.get_mut("NMPTN").unwrap().position = Some(Coordinate {
+        latitude: 54.0,
+        longitude: -1.5,
+    });
+    assert_eq!(
+        regions.locate(&schedule, "NMPTN"),
+        region("GB", Some("England"))
+    );
+    let mut in_england = regions.locations_in(&schedule, None, Some("england"));
+    in_england.sort();
+    assert_eq!(in_england, vec!["NMPTN", "WATFDJ"]);
+}
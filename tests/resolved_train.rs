@@ -0,0 +1,55 @@
+// Trains resolved once for every endpoint that shows them
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::resolved_train::resolve_train;
+use worldrailtimetables::schedule::AssociationCategory;
+
+use chrono::NaiveDate;
+
+#[tokio::test]
+async fn trains_are_resolved_on_a_date() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+
+    let date = NaiveDate::from_ymd_opt(2026, 6, 2).unwrap();
+    let train = resolve_train(&schedule, "C10001", date).unwrap();
+    assert_eq!(
+        train.global_id.to_string(),
+        format!("{}:C10001:20260602", schedule.namespace)
+    );
+    assert!(!train.modified);
+    assert!(!train.cancelled);
+    assert_eq!(train.route.len(), 5);
+    assert_eq!(train.route[0].id.as_str(), "EUSTON");
+    assert_eq!(
+        train.route[0].times.working_dep.unwrap().to_rfc3339(),
+        "2026-06-02T07:15:30+01:00"
+    );
+    assert!(train.route.iter().all(|x| x.segment == 0));
+    // Bletchley is only passed
+    assert!(!train.route[2].flags.public);
+
+    // the ECS into Euston becomes it, on the same day
+    let train = resolve_train(&schedule, "C10002", date).unwrap();
+    assert_eq!(train.associations.len(), 1);
+    let association = &train.associations[0];
+    assert_eq!(association.category, AssociationCategory::Next);
+    assert_eq!(association.location_id.as_str(), "EUSTON");
+    assert_eq!(association.train_id, "C10001");
+    assert_eq!(association.date, date);
+
+    // the overlay on the 1st, and the cancellation on the 25th of August
+    let date = NaiveDate::from_ymd_opt(2026, 6, 1).unwrap();
+    let train = resolve_train(&schedule, "C10001", date).unwrap();
+    assert!(train.modified);
+    assert_eq!(train.route.len(), 4);
+    let date = NaiveDate::from_ymd_opt(2026, 8, 25).unwrap();
+    let train = resolve_train(&schedule, "C10001", date).unwrap();
+    assert!(train.cancelled);
+    assert!(!train.cancelled_en_route);
+
+    // not on a date it doesn't run
+    let date = NaiveDate::from_ymd_opt(2026, 6, 6).unwrap();
+    assert!(resolve_train(&schedule, "C10001", date).is_none());
+}
@@ -0,0 +1,77 @@
+// CORS and caching headers, by endpoint
+use worldrailtimetables::response_headers::{etag_matches, ResponseHeaders, ResponseHeadersConfig};
+
+use serde_json::json;
+
+fn headers() -> ResponseHeaders {
+    ResponseHeaders::new(
+        serde_json::from_value::<ResponseHeadersConfig>(json!({
+            "cors": { "allowed_origins": ["https://example.com"] },
+            "caching": { "max_age_secs": 30 },
+            "endpoints": {
+                "location": { "cors": { "allowed_origins": ["*"] } },
+                "admin": { "caching": { "etag": false } },
+            },
+        }))
+        .unwrap(),
+    )
+}
+
+#[test]
+fn cors_is_by_origin_and_endpoint() {
+    let headers = headers();
+
+    assert!(headers
+        .cors("/service/C10001/2026-06-02", "https://elsewhere.com", false)
+        .is_empty());
+    let allowed = headers.cors("/service/C10001/2026-06-02", "https://example.com", false);
+    assert_eq!(
+        allowed[0],
+        (
+            "Access-Control-Allow-Origin",
+            "https://example.com".to_string()
+        )
+    );
+    assert_eq!(allowed[1].0, "Access-Control-Expose-Headers");
+
+    // boards are for anyone
+    let preflight = headers.cors("/location/EUSTON", "https://elsewhere.com", true);
+    assert_eq!(
+        preflight[0],
+        ("Access-Control-Allow-Origin", "*".to_string())
+    );
+    assert!(preflight
+        .iter()
+        .any(|x| x.0 == "Access-Control-Allow-Headers" && x.1.contains("If-None-Match")));
+
+    // and nobody else is let in without being configured
+    let headers = ResponseHeaders::new(ResponseHeadersConfig::default());
+    assert!(headers
+        .cors("/location/EUSTON", "https://example.com", true)
+        .is_empty());
+}
+
+#[test]
+fn caching_is_by_endpoint() {
+    let headers = headers();
+
+    assert_eq!(
+        headers.cache_control("/location/EUSTON", false).unwrap(),
+        "public, max-age=30"
+    );
+    assert_eq!(
+        headers.cache_control("/location/EUSTON", true).unwrap(),
+        "private, max-age=30"
+    );
+
+    let etag = headers.etag("/location/EUSTON", 7, b"{}").unwrap();
+    assert!(etag.starts_with("\"7-"));
+    assert_eq!(headers.etag("/location/EUSTON", 7, b"{}").unwrap(), etag);
+    assert_ne!(headers.etag("/location/EUSTON", 8, b"{}").unwrap(), etag);
+    assert_ne!(headers.etag("/location/EUSTON", 7, b"[]").unwrap(), etag);
+    assert!(headers.etag("/admin/alerts", 7, b"{}").is_none());
+
+    assert!(etag_matches(&format!("\"other\", W/{}", etag), &etag));
+    assert!(etag_matches("*", &etag));
+    assert!(!etag_matches("\"other\"", &etag));
+}
@@ -0,0 +1,201 @@
+// Writing to the schedule manager in transactions, and what it keeps of what they replace
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::schedule_manager::ScheduleManager;
+
+use chrono::{DateTime, Duration, Utc};
+
+use std::sync::Arc;
+
+#[tokio::test]
+async fn only_archived_imports_count_as_imported() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let schedule_manager = ScheduleManager::new();
+
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.put("gbnr", schedule);
+    transaction.archive();
+    transaction.commit();
+    let imported = schedule_manager.get("gbnr").unwrap().last_imported;
+    assert!(imported.is_some());
+
+    // e.g. a VSTP message
+    let mut transaction = schedule_manager.transactional_write().await;
+    let schedule = transaction.take("gbnr").unwrap();
+    transaction.put("gbnr", schedule);
+    transaction.commit();
+    assert_eq!(
+        schedule_manager.get("gbnr").unwrap().last_imported,
+        imported
+    );
+}
+
+#[tokio::test]
+async fn generations_are_kept_by_namespace() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let schedule_manager = ScheduleManager::new();
+
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.put("gbnr", schedule.clone());
+    transaction.put("gbni", schedule);
+    transaction.commit();
+    let gbni = schedule_manager.namespace_generation("gbni");
+    assert_eq!(schedule_manager.namespace_generation("gbnr"), gbni);
+    assert_eq!(schedule_manager.generation(), gbni);
+
+    let mut transaction = schedule_manager.transactional_write().await;
+    let schedule = transaction.take("gbnr").unwrap();
+    transaction.put("gbnr", schedule);
+    transaction.commit();
+    assert!(schedule_manager.namespace_generation("gbnr") > gbni);
+    assert_eq!(schedule_manager.namespace_generation("gbni"), gbni);
+
+    // and removing one counts as a change
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.remove("gbni");
+    transaction.commit();
+    assert!(schedule_manager.namespace_generation("gbni") > gbni);
+}
+
+#[tokio::test]
+async fn taking_a_schedule_only_copies_what_changes() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let schedule_manager = ScheduleManager::new();
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.put("gbnr", schedule);
+    transaction.commit();
+    let before = schedule_manager.get("gbnr").unwrap();
+    let all = before.trains.shared_partitions(&before.trains);
+
+    let mut transaction = schedule_manager.transactional_write().await;
+    let mut schedule = transaction.take("gbnr").unwrap();
+    assert_eq!(schedule.trains.shared_partitions(&before.trains), all);
+    schedule.trains.get_mut("C10001").unwrap()[0].id = "C10001".to_string();
+    schedule.reindex_train("C10001");
+    assert_eq!(schedule.trains.shared_partitions(&before.trains), all - 1);
+    assert_eq!(schedule.locations.shared_partitions(&before.locations), all);
+    assert_eq!(
+        schedule
+            .trains_indexed_by_location
+            .shared_partitions(&before.trains_indexed_by_location),
+        all
+    );
+
+    // and the one readers have is left alone
+    schedule.trains.remove("C10001");
+    assert!(before.trains.contains_key("C10001"));
+}
+
+#[tokio::test]
+async fn nothing_is_seen_until_a_transaction_commits() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let schedule_manager = ScheduleManager::new();
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.put("gbnr", schedule);
+    transaction.commit();
+    let before = schedule_manager.get("gbnr").unwrap();
+    let generation = schedule_manager.generation();
+
+    let mut transaction = schedule_manager.transactional_write().await;
+    let mut schedule = transaction.take("gbnr").unwrap();
+    schedule.trains.remove("C10001");
+    transaction.put("gbnr", schedule);
+    assert!(transaction.take("gbni").is_none());
+    // readers still have the old one while it's open
+    assert!(Arc::ptr_eq(&schedule_manager.get("gbnr").unwrap(), &before));
+    // and dropping it without committing throws the changes away
+    drop(transaction);
+    assert!(Arc::ptr_eq(&schedule_manager.get("gbnr").unwrap(), &before));
+    assert_eq!(schedule_manager.generation(), generation);
+
+    let mut transaction = schedule_manager.transactional_write().await;
+    let mut schedule = transaction.take("gbnr").unwrap();
+    schedule.trains.remove("C10001");
+    transaction.put("gbnr", schedule);
+    transaction.commit();
+    assert!(!schedule_manager
+        .get("gbnr")
+        .unwrap()
+        .trains
+        .contains_key("C10001"));
+    assert!(before.trains.contains_key("C10001"));
+    assert!(schedule_manager.generation() > generation);
+}
+
+// imports the fixture into a namespace, archiving what it replaces, and says when
+async fn import(schedule_manager: &ScheduleManager, namespace: &str) -> DateTime<Utc> {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let mut transaction = schedule_manager.transactional_write().await;
+    transaction.put(namespace, schedule);
+    transaction.archive();
+    transaction.commit();
+    schedule_manager
+        .get(namespace)
+        .unwrap()
+        .last_imported
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_past_is_found_by_when_each_import_was() {
+    let schedule_manager = ScheduleManager::new_with_history(2);
+    let first = import(&schedule_manager, "gbnr").await;
+    let first_schedule = schedule_manager.get("gbnr").unwrap();
+    let second = import(&schedule_manager, "gbnr").await;
+    let second_schedule = schedule_manager.get("gbnr").unwrap();
+    let third = import(&schedule_manager, "gbnr").await;
+    let third_schedule = schedule_manager.get("gbnr").unwrap();
+
+    let as_of = |x| schedule_manager.as_of(x).get("gbnr");
+    assert!(as_of(first - Duration::nanoseconds(1)).is_none());
+    assert!(Arc::ptr_eq(&as_of(first).unwrap(), &first_schedule));
+    assert!(Arc::ptr_eq(
+        &as_of(second - Duration::nanoseconds(1)).unwrap(),
+        &first_schedule
+    ));
+    assert!(Arc::ptr_eq(&as_of(second).unwrap(), &second_schedule));
+    assert!(Arc::ptr_eq(&as_of(third).unwrap(), &third_schedule));
+    assert!(Arc::ptr_eq(
+        &as_of(third + Duration::days(1)).unwrap(),
+        &third_schedule
+    ));
+}
+
+#[tokio::test]
+async fn namespaces_imported_since_are_left_out() {
+    let schedule_manager = ScheduleManager::new_with_history(2);
+    let gbnr = import(&schedule_manager, "gbnr").await;
+    let gbni = import(&schedule_manager, "gbni").await;
+
+    let past = schedule_manager.as_of(gbnr);
+    assert!(past.get("gbnr").is_some());
+    assert!(past.get("gbni").is_none());
+    let past = schedule_manager.as_of(gbni);
+    assert!(past.get("gbnr").is_some());
+    assert!(past.get("gbni").is_some());
+}
+
+#[tokio::test]
+async fn generations_past_the_limit_are_forgotten() {
+    let schedule_manager = ScheduleManager::new_with_history(1);
+    let first = import(&schedule_manager, "gbnr").await;
+    let second = import(&schedule_manager, "gbnr").await;
+    let second_schedule = schedule_manager.get("gbnr").unwrap();
+    import(&schedule_manager, "gbnr").await;
+
+    // the first import's been pushed out by the second, so there's nothing for then
+    assert!(schedule_manager.as_of(first).get("gbnr").is_none());
+    assert!(Arc::ptr_eq(
+        &schedule_manager.as_of(second).get("gbnr").unwrap(),
+        &second_schedule
+    ));
+
+    // and without any history, only the current one is there
+    let schedule_manager = ScheduleManager::new_with_history(0);
+    let first = import(&schedule_manager, "gbnr").await;
+    let second = import(&schedule_manager, "gbnr").await;
+    assert!(schedule_manager.as_of(first).get("gbnr").is_none());
+    assert!(schedule_manager.as_of(second).get("gbnr").is_some());
+}
@@ -0,0 +1,50 @@
+// Splitting a route up wherever the train's details change en route
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::schedule::TrainOperator;
+use worldrailtimetables::segments::route_segments;
+
+#[tokio::test]
+async fn segments_start_wherever_details_change() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let mut train = schedule.trains["C10001"][0].clone();
+
+    // nothing changes en route
+    let segments = route_segments(&train);
+    assert_eq!(segments.len(), 1);
+    assert_eq!(segments[0].from.as_str(), "EUSTON");
+    assert_eq!(segments[0].to.as_str(), "NMPTN");
+    assert!(segments[0].changed.is_empty());
+
+    // another operator takes over at Milton Keynes, after a change that changes nothing at Watford
+    let unchanged = train.variable_train.clone();
+    let mut changed = train.variable_train.clone();
+    changed.operator = Some(TrainOperator {
+        id: "XX".into(),
+        description: None,
+    });
+    changed.name = Some("The Northampton Flyer".to_string());
+    train.route[1].change_en_route = Some(unchanged);
+    train.route[3].change_en_route = Some(changed);
+
+    let segments = route_segments(&train);
+    assert_eq!(segments.len(), 2);
+    assert_eq!(segments[0].to.as_str(), "MKNSCEN");
+    assert!(!segments[0].operator_changed);
+    assert_eq!(segments[1].from.as_str(), "MKNSCEN");
+    assert_eq!(segments[1].to.as_str(), "NMPTN");
+    assert!(segments[1].operator_changed);
+    assert_eq!(segments[1].changed, vec!["name", "operator"]);
+    assert_eq!(
+        segments[1]
+            .variable_train
+            .operator
+            .as_ref()
+            .unwrap()
+            .id
+            .as_str(),
+        "XX"
+    );
+}
@@ -0,0 +1,31 @@
+// Snapshots of every schedule, as written for backups and read back by /admin/restore
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::error::Error;
+use worldrailtimetables::snapshot::{read_snapshot, write_snapshot};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[tokio::test]
+async fn snapshots_are_bounded_once_unpacked() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let snapshot = write_snapshot(HashMap::from([("gbnr".to_string(), Arc::new(schedule))]))
+        .await
+        .unwrap();
+
+    let schedules = read_snapshot(snapshot.clone(), None).await.unwrap();
+    assert!(!schedules["gbnr"].trains.is_empty());
+    assert!(read_snapshot(snapshot.clone(), Some(1 << 30)).await.is_ok());
+
+    match read_snapshot(snapshot, Some(100)).await {
+        Err(Error::SnapshotError(x)) => assert!(x.too_large()),
+        _ => panic!("A snapshot bigger than its limit was read"),
+    }
+    match read_snapshot(b"WRTSNAP".to_vec(), Some(100)).await {
+        Err(Error::SnapshotError(x)) => assert!(!x.too_large()),
+        _ => panic!("A truncated snapshot was read"),
+    }
+}
@@ -0,0 +1,55 @@
+// The SQL copy of each schedule
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::schedule::{Schedule, Train};
+use worldrailtimetables::sql_export::{SqlExport, SqlExportConfig};
+
+use serde_json::json;
+
+fn sql(dialect: &str, schedule: &Schedule) -> String {
+    let export = SqlExport::new(
+        serde_json::from_value::<SqlExportConfig>(json!({ "dialect": dialect })).unwrap(),
+    );
+    let mut out = vec![];
+    export.write_sql(schedule, &mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+// every version of a train, as in the trains table
+fn versions(train: &Train) -> usize {
+    1 + train.replacements.iter().map(versions).sum::<usize>()
+}
+
+#[tokio::test]
+async fn loads_are_all_or_nothing() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+
+    // the tables are made in the same transaction, and SQLite checks everything went in
+    let sqlite = sql("sqlite", &schedule);
+    assert!(sqlite.starts_with("BEGIN;\nCREATE TABLE IF NOT EXISTS locations"));
+    assert!(sqlite.trim_end().ends_with("COMMIT;"));
+    let check = sqlite
+        .lines()
+        .find(|x| x.starts_with("INSERT OR ROLLBACK INTO loaded"))
+        .unwrap();
+    assert!(check.contains(&format!(
+        "(SELECT count(*) FROM locations WHERE namespace = 'gbnr') = {}",
+        schedule.locations.len()
+    )));
+    assert!(check.contains(&format!(
+        "(SELECT count(*) FROM trains WHERE namespace = 'gbnr') = {}",
+        schedule
+            .trains
+            .values()
+            .flatten()
+            .map(versions)
+            .sum::<usize>()
+    )));
+
+    // Postgres rolls back on COMMIT by itself
+    let postgres = sql("postgres", &schedule);
+    assert!(postgres.starts_with("BEGIN;\n"));
+    assert!(!postgres.contains("OR ROLLBACK"));
+}
@@ -0,0 +1,54 @@
+// Station groups, which stand in for all their members wherever a location is asked for
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::station_groups::{StationGroups, StationGroupsConfig};
+
+use serde_json::json;
+
+use std::collections::HashSet;
+
+fn ids(x: &[&str]) -> HashSet<String> {
+    x.iter().map(|x| x.to_string()).collect()
+}
+
+#[tokio::test]
+async fn groups_stand_in_for_their_members() {
+    let file = std::env::temp_dir().join(format!("station-groups-{}.json", std::process::id()));
+    std::fs::write(
+        &file,
+        json!({
+            "gbnr": {
+                "G01": { "name": "London Terminals", "members": ["EUS", "WMBYICD", "KGX"] },
+                "G02": { "name": "Watford", "members": ["WFJ"] },
+            },
+        })
+        .to_string(),
+    )
+    .unwrap();
+    let config = serde_json::from_value::<StationGroupsConfig>(json!({
+        "groups": {
+            "gbnr": { "G02": { "name": "Watford or Bletchley", "members": ["WFJ", "BLTCHLY"] } },
+        },
+        "files": [file],
+    }))
+    .unwrap();
+    let groups = StationGroups::new(config).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+
+    // members by public ID or location ID, leaving out any we don't have
+    assert_eq!(
+        groups.location_ids(&schedule, "G01"),
+        ids(&["EUSTON", "WMBYICD"])
+    );
+    // files replace what's in the config
+    assert_eq!(groups.get("gbnr", "G02").unwrap().name, "Watford");
+    assert_eq!(groups.location_ids(&schedule, "G02"), ids(&["WATFDJ"]));
+    // locations themselves aren't affected
+    assert_eq!(groups.location_ids(&schedule, "EUS"), ids(&["EUSTON"]));
+    assert_eq!(groups.location_ids(&schedule, "NMPTN"), ids(&["NMPTN"]));
+    assert!(groups.location_ids(&schedule, "G03").is_empty());
+    assert!(groups.members(&schedule, "EUS").is_empty());
+}
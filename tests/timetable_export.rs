@@ -0,0 +1,80 @@
+// Weekly timetables for stations, with overlays and trains that run past midnight
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::timetable_export::{
+    export_weekly, weekly_timetable, weekly_to_csv, ExportFormat,
+};
+
+use chrono::NaiveDate;
+
+#[tokio::test]
+async fn weekly_timetable_has_a_column_per_day() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let week_commencing = NaiveDate::from_ymd_opt(2026, 6, 1).unwrap();
+    let weekly = weekly_timetable(&schedule, "WATFDJ", week_commencing).unwrap();
+
+    assert_eq!(
+        weekly
+            .rows
+            .iter()
+            .map(|x| x.train_id.as_str())
+            .collect::<Vec<_>>(),
+        vec!["C10003", "C10001"]
+    );
+    // Saturday's 2N99 gets there after midnight
+    assert_eq!(weekly.rows[0].days[6].len(), 1);
+    assert!(weekly.rows[0].days[..6].iter().all(|x| x.is_empty()));
+
+    let csv = weekly_to_csv(&weekly);
+    let lines = csv.split("\r\n").collect::<Vec<_>>();
+    assert_eq!(
+        lines[0],
+        "train,headcode,origin,destination,operator,Mon 1 Jun,Tue 2 Jun,Wed 3 Jun,Thu 4 Jun,\
+         Fri 5 Jun,Sat 6 Jun,Sun 7 Jun"
+    );
+    assert!(lines[1].ends_with(",,,,,,,00:32"), "{}", lines[1]);
+    // Monday's overlay leaves later
+    assert!(
+        lines[2].ends_with(",08:02,07:32,07:32,07:32,07:32,,"),
+        "{}",
+        lines[2]
+    );
+
+    let terminating = weekly_timetable(&schedule, "NMPTN", week_commencing).unwrap();
+    let c10001 = terminating
+        .rows
+        .iter()
+        .find(|x| x.train_id == "C10001")
+        .unwrap();
+    assert!(c10001.days[1][0].arrival_only);
+
+    assert!(weekly_timetable(&schedule, "NOWHERE", week_commencing).is_none());
+}
+
+#[tokio::test]
+async fn weekly_timetable_as_xlsx() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let week_commencing = NaiveDate::from_ymd_opt(2026, 6, 1).unwrap();
+    let weekly = weekly_timetable(&schedule, "WATFDJ", week_commencing).unwrap();
+
+    let xlsx = export_weekly(&weekly, ExportFormat::parse("xlsx").unwrap());
+    assert!(xlsx.starts_with(b"PK\x03\x04"));
+    // stored rather than deflated, so the sheet's there to see
+    let text = String::from_utf8_lossy(&xlsx);
+    assert!(text.contains("Mon 1 Jun"));
+    assert!(text.contains("08:02"));
+}
+
+#[tokio::test]
+async fn weekly_timetable_as_pdf() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let week_commencing = NaiveDate::from_ymd_opt(2026, 6, 1).unwrap();
+    let weekly = weekly_timetable(&schedule, "WATFDJ", week_commencing).unwrap();
+
+    let pdf = export_weekly(&weekly, ExportFormat::parse("pdf").unwrap());
+    assert!(pdf.starts_with(b"%PDF-"));
+    assert!(pdf.ends_with(b"%%EOF\n"));
+    assert!(String::from_utf8_lossy(&pdf).contains("08:02"));
+}
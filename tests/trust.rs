@@ -0,0 +1,170 @@
+// What trains actually did, from TRUST
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::importer::FastImporter;
+use worldrailtimetables::nr_trust_importer::NrTrustImporter;
+use worldrailtimetables::schedule::{DaysOfWeek, Schedule};
+
+use chrono::{Days, NaiveDate, TimeZone, Utc};
+use chrono_tz::Europe::London;
+
+use serde_json::json;
+
+fn activation(train_uid: &str, date: &str, extra: serde_json::Value) -> Vec<u8> {
+    let mut body = json!({
+        "train_id": "721N01MU02",
+        "train_uid": train_uid,
+        "tp_origin_timestamp": date,
+    });
+    body.as_object_mut()
+        .unwrap()
+        .extend(extra.as_object().unwrap().clone());
+    serde_json::to_vec(&json!([{ "header": { "msg_type": "0001" }, "body": body }])).unwrap()
+}
+
+#[tokio::test]
+async fn activations_are_only_taken_for_the_version_that_runs() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let activated = |schedule: &Schedule, train_uid: &str, date: &str| {
+        schedule
+            .realtime
+            .get(train_uid)
+            .is_some_and(|x| x.contains_key(&date.parse::<NaiveDate>().unwrap()))
+    };
+    let importer = NrTrustImporter::new();
+
+    // nothing we have, or not on a day it runs
+    let schedule = importer
+        .overlay(activation("C19999", "2026-06-02", json!({})), schedule)
+        .unwrap();
+    assert!(!activated(&schedule, "C19999", "2026-06-02"));
+    let schedule = importer
+        .overlay(activation("C10001", "2026-06-06", json!({})), schedule)
+        .unwrap();
+    assert!(!activated(&schedule, "C10001", "2026-06-06"));
+
+    // the overlay runs on the 1st, not the permanent schedule, which TRUST calls O
+    let permanent = json!({ "schedule_source": "C", "schedule_type": "O" });
+    let schedule = importer
+        .overlay(
+            activation("C10001", "2026-06-01", permanent.clone()),
+            schedule,
+        )
+        .unwrap();
+    assert!(!activated(&schedule, "C10001", "2026-06-01"));
+    let schedule = importer
+        .overlay(activation("C10001", "2026-06-02", permanent), schedule)
+        .unwrap();
+    assert!(activated(&schedule, "C10001", "2026-06-02"));
+    let overlay = json!({
+        "schedule_source": "C",
+        "schedule_type": "P",
+        "schedule_start_date": "2026-06-01",
+        "train_service_code": "22214000",
+    });
+    let schedule = importer
+        .overlay(activation("C10001", "2026-06-01", overlay), schedule)
+        .unwrap();
+    assert!(activated(&schedule, "C10001", "2026-06-01"));
+
+    // nor one for another start date or service
+    let schedule = importer
+        .overlay(
+            activation(
+                "C10002",
+                "2026-06-02",
+                json!({ "schedule_start_date": "2026-05-19" }),
+            ),
+            schedule,
+        )
+        .unwrap();
+    assert!(!activated(&schedule, "C10002", "2026-06-02"));
+    let schedule = importer
+        .overlay(
+            activation(
+                "C10002",
+                "2026-06-02",
+                json!({ "train_service_code": "11111111" }),
+            ),
+            schedule,
+        )
+        .unwrap();
+    assert!(!activated(&schedule, "C10002", "2026-06-02"));
+}
+
+#[tokio::test]
+async fn realtime_from_days_ago_is_dropped() {
+    let (mut schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let today = London
+        .from_utc_datetime(&Utc::now().naive_utc())
+        .date_naive();
+    // running every day around whenever this is run, so it's activated
+    let validity = &mut schedule.trains.get_mut("C10001").unwrap()[0].validity[0];
+    validity.valid_begin = London.from_utc_datetime(&Utc::now().naive_utc()) - Days::new(14);
+    validity.valid_end = validity.valid_begin + Days::new(28);
+    validity.days_of_week = DaysOfWeek {
+        monday: true,
+        tuesday: true,
+        wednesday: true,
+        thursday: true,
+        friday: true,
+        saturday: true,
+        sunday: true,
+    };
+    let importer = NrTrustImporter::new();
+    let mut schedule = importer
+        .overlay(
+            activation("C10001", &today.to_string(), json!({})),
+            schedule,
+        )
+        .unwrap();
+    let realtime = schedule.realtime["C10001"][&today].clone();
+    let last_week = today.checked_sub_days(Days::new(7)).unwrap();
+    schedule
+        .realtime
+        .get_mut("C10001")
+        .unwrap()
+        .insert(last_week, realtime);
+
+    // not until the day changes, or for a new importer
+    let schedule = importer.overlay(b"[]".to_vec(), schedule).unwrap();
+    assert!(schedule.realtime["C10001"].contains_key(&last_week));
+    let schedule = NrTrustImporter::new()
+        .overlay(b"[]".to_vec(), schedule)
+        .unwrap();
+    assert!(!schedule.realtime["C10001"].contains_key(&last_week));
+    assert!(schedule.realtime["C10001"].contains_key(&today));
+}
+
+#[tokio::test]
+async fn cancellations_without_a_time_are_kept() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let importer = NrTrustImporter::new();
+    let permanent = json!({ "schedule_source": "C", "schedule_type": "O" });
+    let schedule = importer
+        .overlay(activation("C10001", "2026-06-02", permanent), schedule)
+        .unwrap();
+
+    // when TRUST sent it on is near enough
+    let cancellation = json!([{
+        "header": { "msg_type": "0002", "msg_queue_timestamp": "1780383600000" },
+        "body": {
+            "train_id": "721N01MU02",
+            "loc_stanox": "00000",
+            "canx_reason_code": "MN",
+            "canx_timestamp": "",
+        },
+    }]);
+    let schedule = importer
+        .overlay(serde_json::to_vec(&cancellation).unwrap(), schedule)
+        .unwrap();
+    let realtime = &schedule.realtime["C10001"][&"2026-06-02".parse::<NaiveDate>().unwrap()];
+    let cancellation = realtime.cancellation.as_ref().unwrap();
+    assert_eq!(cancellation.reason.as_deref(), Some("a train fault (MN)"));
+    assert_eq!(
+        cancellation.timestamp,
+        London.with_ymd_and_hms(2026, 6, 2, 8, 0, 0).unwrap()
+    );
+}
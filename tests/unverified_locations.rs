@@ -0,0 +1,44 @@
+// TIPLOCs VSTP uses before any TI record has told us about them
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::importer::{FastImporter, SlowStreamingImporter};
+use worldrailtimetables::uk_importer::{
+    CifImporter, CifImporterConfig, NrJsonImporter, NrJsonImporterConfig,
+};
+
+#[tokio::test]
+async fn unknown_tiplocs_are_made_up_then_put_right() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let importer = NrJsonImporter::new(serde_json::from_str::<NrJsonImporterConfig>("{}").unwrap())
+        .await
+        .unwrap();
+    let vstp = String::from_utf8(read_fixture("vstp.json"))
+        .unwrap()
+        .replace("\"MKNSCEN\"", "\"MKNSDGS\"");
+
+    let schedule = importer.overlay(vstp.into_bytes(), schedule).unwrap();
+    assert!(schedule.trains.contains_key("Y20001"));
+    let location = &schedule.locations["MKNSDGS"];
+    assert!(location.unverified);
+    assert_eq!(location.name, "MKNSDGS");
+    assert!(!schedule.locations["MKNSCEN"].unverified);
+
+    // a later update's TI record says what it is
+    let update = String::from_utf8(read_fixture("small.cif")).unwrap();
+    let mut header = update.lines().next().unwrap().to_string();
+    header.replace_range(46..47, "U");
+    let update = format!(
+        "{}\n{:<80}\n{:<80}\n",
+        header, "TIMKNSDGS00000000 MILTON KEYNES SIDINGS     00000", "ZZ"
+    );
+    let mut cif_importer = CifImporter::new(CifImporterConfig::default());
+    let schedule = cif_importer
+        .overlay(update.as_bytes(), schedule)
+        .await
+        .unwrap();
+    let location = &schedule.locations["MKNSDGS"];
+    assert!(!location.unverified);
+    assert_eq!(location.name, "MILTON KEYNES SIDINGS");
+}
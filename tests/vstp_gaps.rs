@@ -0,0 +1,28 @@
+// Messages missed from a STOMP topic, going by the sequence numbers in their IDs
+use worldrailtimetables::nr_vstp_subscriber::MessageSequence;
+
+const PRODUCER: &str = "ID:some-host-12345-1691564234236-11:1:1:1";
+
+#[test]
+fn gaps_in_the_sequence_are_counted() {
+    let mut sequence = MessageSequence::default();
+    // nothing to go on for the first
+    assert_eq!(sequence.check(&format!("{}:5469", PRODUCER)), 0);
+    assert_eq!(sequence.check(&format!("{}:5470", PRODUCER)), 0);
+    assert_eq!(sequence.check(&format!("{}:5474", PRODUCER)), 3);
+    // redelivered, or out of order
+    assert_eq!(sequence.check(&format!("{}:5472", PRODUCER)), 0);
+    assert_eq!(sequence.check(&format!("{}:5473", PRODUCER)), 0);
+}
+
+#[test]
+fn reconnecting_to_another_producer_starts_again() {
+    let mut sequence = MessageSequence::default();
+    assert_eq!(sequence.check(&format!("{}:5469", PRODUCER)), 0);
+    // e.g. the broker restarted while we were away
+    assert_eq!(sequence.check("ID:other-host-1-2-3:1:1:1:12"), 0);
+    assert_eq!(sequence.check("ID:other-host-1-2-3:1:1:1:14"), 1);
+    // and IDs we can't make sense of don't count against anything
+    assert_eq!(sequence.check("not an ActiveMQ ID"), 0);
+    assert_eq!(sequence.check("ID:other-host-1-2-3:1:1:1:15"), 0);
+}
@@ -0,0 +1,104 @@
+// VSTP messages with fields we don't know yet, ones that can't be read at all, and whether we're
+// connected
+mod common;
+
+use common::{import_cif, read_fixture};
+
+use worldrailtimetables::feed_telemetry::FeedTelemetry;
+use worldrailtimetables::importer::FastImporter;
+use worldrailtimetables::schedule_manager::ScheduleManager;
+use worldrailtimetables::staleness::{Staleness, StalenessConfig};
+use worldrailtimetables::uk_importer::{NrJsonImporter, NrJsonImporterConfig};
+
+use serde_json::{json, Value};
+
+use std::sync::Arc;
+
+fn with_new_field() -> Vec<u8> {
+    let mut message = serde_json::from_slice::<Value>(&read_fixture("vstp.json")).unwrap();
+    message["VSTPCIFMsgV1"]["schedule"]["new_field"] = json!("surprise");
+    serde_json::to_vec(&message).unwrap()
+}
+
+async fn importer(config: Value, telemetry: &Arc<FeedTelemetry>) -> NrJsonImporter {
+    NrJsonImporter::new(serde_json::from_value::<NrJsonImporterConfig>(config).unwrap())
+        .await
+        .unwrap()
+        .with_telemetry(telemetry.clone())
+}
+
+#[tokio::test]
+async fn unknown_fields_are_counted() {
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let telemetry = Arc::new(FeedTelemetry::new());
+    let importer = importer(json!({}), &telemetry).await;
+
+    let schedule = importer.overlay(with_new_field(), schedule).unwrap();
+    let schedule = importer.overlay(with_new_field(), schedule).unwrap();
+    assert!(schedule.trains.contains_key("Y20001"));
+
+    let vstp = &telemetry.get()["gbnr-vstp"];
+    assert_eq!(vstp.messages, 2);
+    assert_eq!(
+        vstp.unknown_fields.get("VSTPCIFMsgV1.schedule.new_field"),
+        Some(&2)
+    );
+    assert_eq!(vstp.quarantined, 0);
+}
+
+#[tokio::test]
+async fn unreadable_messages_are_quarantined() {
+    let file = std::env::temp_dir().join(format!("vstp-quarantine-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&file);
+    let (schedule, _) = import_cif(&read_fixture("small.cif"), false).await.unwrap();
+    let telemetry = Arc::new(FeedTelemetry::new());
+    let importer = importer(
+        json!({ "strict": true, "quarantine_file": file }),
+        &telemetry,
+    )
+    .await;
+
+    let schedule = importer
+        .overlay(b"{\"VSTPCIFMsgV1\":".to_vec(), schedule)
+        .unwrap();
+    let schedule = importer.overlay(with_new_field(), schedule).unwrap();
+    assert!(!schedule.trains.contains_key("Y20001"));
+    let schedule = importer
+        .overlay(read_fixture("vstp.json"), schedule)
+        .unwrap();
+    assert!(schedule.trains.contains_key("Y20001"));
+
+    let vstp = &telemetry.get()["gbnr-vstp"];
+    assert_eq!((vstp.messages, vstp.quarantined), (3, 2));
+    assert!(vstp.last_error.as_ref().unwrap().contains("new_field"));
+
+    let quarantined = std::fs::read_to_string(&file)
+        .unwrap()
+        .lines()
+        .map(|x| serde_json::from_str::<Value>(x).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(quarantined.len(), 2);
+    assert_eq!(quarantined[0]["message"], "{\"VSTPCIFMsgV1\":");
+
+    std::fs::remove_file(&file).unwrap();
+}
+
+#[test]
+fn connections_are_tracked_for_readiness() {
+    let telemetry = FeedTelemetry::new();
+    telemetry.connection("gbnr-vstp", true);
+    let since = telemetry.get()["gbnr-vstp"].connection_changed;
+    // only a change moves when it changed
+    telemetry.connection("gbnr-vstp", true);
+    assert_eq!(telemetry.get()["gbnr-vstp"].connection_changed, since);
+    telemetry.connection("gbnr-vstp", false);
+    assert_eq!(telemetry.get()["gbnr-vstp"].connected, Some(false));
+
+    // connected but with nothing in yet, it still has the built-in budget from startup
+    let staleness = Staleness::new(StalenessConfig::default(), Arc::new(ScheduleManager::new()));
+    assert!(staleness.status().is_empty());
+    let status = staleness.status_of("gbnr-vstp");
+    assert_eq!(status.last_updated, None);
+    assert_eq!(status.max_age_secs, Some(3600));
+    assert!(!status.stale);
+}